@@ -0,0 +1,150 @@
+//! Time and randomness tapplets get from the host.
+//!
+//! Tapplets have no clock or entropy of their own: Lua scripts lose `os` to the
+//! sandbox and WASM modules can't reach the OS. They call `minotari_now()` and
+//! `minotari_random_bytes(n)` instead, which both hosts answer from their
+//! [`AmbientMode`]. In [`AmbientMode::Real`] those are the system clock and OS
+//! entropy. [`AmbientMode::Deterministic`] makes a tapplet behave the same in
+//! every host, e.g. when replaying a recorded call: the clock is fixed and the
+//! bytes come from a generator seeded with the mode's seed, so the same calls
+//! give the same values.
+
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::RngCore;
+use rand::rngs::OsRng;
+
+/// Most bytes one `minotari_random_bytes` call may ask for
+pub const MAX_RANDOM_BYTES: usize = 64 * 1024;
+
+/// Where a host's time and random bytes come from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AmbientMode {
+    /// The system clock and OS entropy
+    #[default]
+    Real,
+    /// Every read of the clock returns `now_ms`, and random bytes come from a
+    /// generator seeded with `seed`
+    Deterministic { seed: u64, now_ms: u64 },
+}
+
+impl AmbientMode {
+    /// A deterministic mode with its clock at the Unix epoch
+    pub fn deterministic(seed: u64) -> Self {
+        AmbientMode::Deterministic { seed, now_ms: 0 }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct State {
+    mode: AmbientMode,
+    /// State of the generator of a deterministic mode
+    rng: u64,
+}
+
+/// The time and randomness of one host, shared with its host functions
+#[derive(Clone)]
+pub(crate) struct Ambient {
+    state: Arc<Mutex<State>>,
+}
+
+impl Ambient {
+    pub fn new(mode: AmbientMode) -> Self {
+        let ambient = Self {
+            state: Arc::new(Mutex::new(State { mode, rng: 0 })),
+        };
+        ambient.set_mode(mode);
+        ambient
+    }
+
+    pub fn mode(&self) -> AmbientMode {
+        self.state.lock().unwrap().mode
+    }
+
+    /// Switch to `mode`, restarting a deterministic generator from its seed
+    pub fn set_mode(&self, mode: AmbientMode) {
+        let rng = match mode {
+            AmbientMode::Real => 0,
+            AmbientMode::Deterministic { seed, .. } => seed,
+        };
+        *self.state.lock().unwrap() = State { mode, rng };
+    }
+
+    /// Take over the mode and generator of `other`, e.g. of the host this one
+    /// replaces, so a deterministic sequence carries on where it was
+    pub fn inherit(&self, other: &Ambient) {
+        let state = *other.state.lock().unwrap();
+        *self.state.lock().unwrap() = state;
+    }
+
+    /// Milliseconds since the Unix epoch
+    pub fn now_ms(&self) -> u64 {
+        match self.mode() {
+            AmbientMode::Real => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as u64),
+            AmbientMode::Deterministic { now_ms, .. } => now_ms,
+        }
+    }
+
+    /// `len` random bytes, failing if that's over [`MAX_RANDOM_BYTES`]
+    pub fn random_bytes(&self, len: usize) -> Result<Vec<u8>, String> {
+        if len > MAX_RANDOM_BYTES {
+            return Err(format!(
+                "asked for {} random bytes, at most {} can be taken at once",
+                len, MAX_RANDOM_BYTES
+            ));
+        }
+        let mut bytes = vec![0; len];
+        let mut state = self.state.lock().unwrap();
+        match state.mode {
+            AmbientMode::Real => OsRng.fill_bytes(&mut bytes),
+            AmbientMode::Deterministic { .. } => {
+                for chunk in bytes.chunks_mut(8) {
+                    let random = splitmix64(&mut state.rng).to_le_bytes();
+                    chunk.copy_from_slice(&random[..chunk.len()]);
+                }
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+/// The next value of a splitmix64 generator
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic_mode_repeats() {
+        let mode = AmbientMode::Deterministic {
+            seed: 7,
+            now_ms: 1_700_000_000_000,
+        };
+        let first = Ambient::new(mode);
+        let second = Ambient::new(mode);
+        assert_eq!(first.now_ms(), 1_700_000_000_000);
+        let bytes = first.random_bytes(13).unwrap();
+        assert_eq!(bytes, second.random_bytes(13).unwrap());
+        // The sequence moves on, and a reloaded host carries it on
+        let next = first.random_bytes(13).unwrap();
+        assert_ne!(bytes, next);
+        let reloaded = Ambient::new(AmbientMode::Real);
+        reloaded.inherit(&second);
+        assert_eq!(reloaded.random_bytes(13).unwrap(), next);
+
+        let real = Ambient::new(AmbientMode::Real);
+        assert!(real.now_ms() > 1_700_000_000_000);
+        assert_eq!(real.random_bytes(32).unwrap().len(), 32);
+        assert!(real.random_bytes(MAX_RANDOM_BYTES + 1).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::Value;
+
+/// Default number of events kept by [`MemoryAuditSink`]
+pub const DEFAULT_AUDIT_CAPACITY: usize = 1024;
+
+/// What kind of call an [`AuditEvent`] records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditKind {
+    /// The host invoked a tapplet method through `run()`
+    MethodCall,
+    /// A tapplet called a host API function, e.g. `minotari_append_data`
+    HostCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditStatus {
+    Ok,
+    Error(String),
+}
+
+/// A single audited call.
+///
+/// Arguments are only summarized (names, types and sizes), never recorded
+/// verbatim, so the log doesn't leak keys or stored secrets.
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub tapplet: String,
+    pub kind: AuditKind,
+    pub method: String,
+    pub args_summary: String,
+    pub started_at: SystemTime,
+    pub duration: Duration,
+    pub status: AuditStatus,
+    /// Something about the call worth flagging even though it was allowed, e.g.
+    /// that the method is deprecated
+    pub warning: Option<String>,
+}
+
+/// Receives an event for every call made into or out of a tapplet
+pub trait AuditSink: Send + Sync {
+    fn record(&self, event: AuditEvent);
+}
+
+/// Keeps the most recent audit events in memory, dropping the oldest when full
+pub struct MemoryAuditSink {
+    capacity: usize,
+    events: Mutex<VecDeque<AuditEvent>>,
+}
+
+impl MemoryAuditSink {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Recorded events, oldest first
+    pub fn events(&self) -> Vec<AuditEvent> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+}
+
+impl Default for MemoryAuditSink {
+    fn default() -> Self {
+        Self::new(DEFAULT_AUDIT_CAPACITY)
+    }
+}
+
+impl AuditSink for MemoryAuditSink {
+    fn record(&self, event: AuditEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+}
+
+/// Records audit events for one tapplet, doing nothing if no sink is set
+#[derive(Clone, Default)]
+#[cfg_attr(not(feature = "host-core"), allow(dead_code))]
+pub(crate) struct Auditor {
+    sink: Option<Arc<dyn AuditSink>>,
+    tapplet: String,
+}
+
+#[cfg_attr(not(feature = "host-core"), allow(dead_code))]
+impl Auditor {
+    pub fn new(sink: Option<Arc<dyn AuditSink>>, tapplet: &str) -> Self {
+        Self {
+            sink,
+            tapplet: tapplet.to_string(),
+        }
+    }
+
+    pub fn record<T, E: Display>(
+        &self,
+        kind: AuditKind,
+        method: &str,
+        args_summary: impl FnOnce() -> String,
+        started: Instant,
+        result: &Result<T, E>,
+    ) {
+        self.record_with_warning(kind, method, args_summary, started, result, None);
+    }
+
+    /// [`Self::record`] with a [`AuditEvent::warning`]
+    pub fn record_with_warning<T, E: Display>(
+        &self,
+        kind: AuditKind,
+        method: &str,
+        args_summary: impl FnOnce() -> String,
+        started: Instant,
+        result: &Result<T, E>,
+        warning: Option<String>,
+    ) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        let duration = started.elapsed();
+        sink.record(AuditEvent {
+            tapplet: self.tapplet.clone(),
+            kind,
+            method: method.to_string(),
+            args_summary: args_summary(),
+            started_at: SystemTime::now() - duration,
+            duration,
+            status: match result {
+                Ok(_) => AuditStatus::Ok,
+                Err(e) => AuditStatus::Error(e.to_string()),
+            },
+            warning,
+        });
+    }
+}
+
+/// Describe the shape of JSON arguments without their values
+pub fn summarize_args(args: &Value) -> String {
+    match args {
+        Value::Null => "null".to_string(),
+        Value::Bool(_) => "bool".to_string(),
+        Value::Number(_) => "number".to_string(),
+        Value::String(s) => format!("string({})", s.len()),
+        Value::Array(items) => format!(
+            "[{}]",
+            items
+                .iter()
+                .map(summarize_args)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::Object(fields) => format!(
+            "{{{}}}",
+            fields
+                .iter()
+                .map(|(key, value)| format!("{}: {}", key, summarize_args(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_memory_sink_is_a_ring_buffer() {
+        let sink = Arc::new(MemoryAuditSink::new(2));
+        let auditor = Auditor::new(Some(sink.clone()), "wallet");
+        for method in ["a", "b", "c"] {
+            auditor.record(
+                AuditKind::HostCall,
+                method,
+                String::new,
+                Instant::now(),
+                &Ok::<_, String>(()),
+            );
+        }
+        auditor.record(
+            AuditKind::MethodCall,
+            "d",
+            String::new,
+            Instant::now(),
+            &Err::<(), _>("boom"),
+        );
+
+        let events = sink.events();
+        assert_eq!(
+            events.iter().map(|e| e.method.as_str()).collect::<Vec<_>>(),
+            vec!["c", "d"]
+        );
+        assert_eq!(events[1].status, AuditStatus::Error("boom".to_string()));
+        sink.clear();
+        assert!(sink.events().is_empty());
+    }
+
+    #[test]
+    fn test_summarize_args_hides_values() {
+        let summary = summarize_args(&json!({"viewkey": "secret", "amounts": [1, true]}));
+        assert_eq!(summary, "{amounts: [number, bool], viewkey: string(6)}");
+    }
+}
@@ -0,0 +1,20 @@
+//! Runs one tapplet for a parent process, see `tari_tapplet_lib::process`.
+//!
+//! Usage: `tapplet-runner <tapplet directory>`
+
+use std::process::ExitCode;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let Some(dir) = std::env::args_os().nth(1) else {
+        eprintln!("Usage: tapplet-runner <tapplet directory>");
+        return ExitCode::from(2);
+    };
+    match tari_tapplet_lib::process::serve_stdio(dir.as_ref()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("tapplet-runner: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
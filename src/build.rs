@@ -0,0 +1,644 @@
+//! Building WASM tapplets from their Rust crate with cargo
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::TappletManifest;
+use crate::error::TappletError;
+
+/// The target WASM tapplets are compiled for
+pub const WASM_TARGET: &str = "wasm32-unknown-unknown";
+
+/// Guest export allocating argument buffers, present in modules built with `tari-tapplet-guest`
+pub const GUEST_ALLOC_EXPORT: &str = "tapplet_alloc";
+/// Guest export freeing result buffers
+pub const GUEST_DEALLOC_EXPORT: &str = "tapplet_dealloc";
+/// Guest export returning the next chunk of a streamed result
+pub const GUEST_NEXT_CHUNK_EXPORT: &str = "tapplet_next_chunk";
+/// Guest export returning the message of the panic behind the last trap
+pub const GUEST_LAST_PANIC_EXPORT: &str = "tapplet_last_panic";
+/// Optional guest export run once after the module is instantiated
+pub const GUEST_INIT_EXPORT: &str = "tapplet_init";
+/// Optional guest export, or Lua function, reporting whether the tapplet still works
+pub const GUEST_HEALTH_EXPORT: &str = "tapplet_health";
+
+/// How cargo builds a tapplet's crate
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildOptions {
+    /// Cargo features to enable
+    pub features: Vec<String>,
+    /// Cargo profile to build with, `release` if not set
+    pub profile: Option<String>,
+    /// Added to the `RUSTFLAGS` cargo is run with
+    pub rustflags: Vec<String>,
+    /// Target directory, relative to the crate, instead of the one cargo would use
+    pub target_dir: Option<PathBuf>,
+    /// Fail instead of updating `Cargo.lock`
+    pub locked: bool,
+    /// Don't access the network
+    pub offline: bool,
+    /// Shrink the installed module, see [`optimize_module`]
+    pub optimize: bool,
+    /// The `wasm-opt` binary to optimize with, `wasm-opt` on the `PATH` if not set
+    pub wasm_opt: Option<PathBuf>,
+    /// Compile the installed module ahead of time for this machine's WASM engine,
+    /// so hosts start without compiling it. Needs the `host` or `engine-wasmtime`
+    /// feature.
+    pub precompile: bool,
+}
+
+impl BuildOptions {
+    fn profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or("release")
+    }
+
+    /// Directory of the profile's output below the target directory
+    fn profile_dir(&self) -> &str {
+        match self.profile() {
+            "dev" | "test" => "debug",
+            "bench" => "release",
+            profile => profile,
+        }
+    }
+
+    fn cargo(&self, crate_dir: &Path, subcommand: &str) -> Command {
+        let mut command = Command::new("cargo");
+        command.current_dir(crate_dir).arg(subcommand);
+        if self.locked {
+            command.arg("--locked");
+        }
+        if self.offline {
+            command.arg("--offline");
+        }
+        command
+    }
+}
+
+/// A message from the compiler, parsed from cargo's JSON output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BuildDiagnostic {
+    /// `error`, `warning`, ...
+    pub level: String,
+    pub message: String,
+    /// Error code like `E0425`, if any
+    pub code: Option<String>,
+    /// File, line and column of the primary span, if any
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// The message as rustc would print it
+    pub rendered: Option<String>,
+    #[serde(skip)]
+    notes: Vec<String>,
+}
+
+impl BuildDiagnostic {
+    pub fn is_error(&self) -> bool {
+        self.level == "error"
+    }
+
+    /// rustc couldn't find the standard library for the target
+    fn is_missing_target(&self) -> bool {
+        self.code.as_deref() == Some("E0463")
+            && self
+                .notes
+                .iter()
+                .any(|note| note.contains("target may not be installed"))
+    }
+}
+
+/// Sizes of a module before and after [`optimize_module`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleOptimization {
+    pub original_size: u64,
+    pub optimized_size: u64,
+    /// `wasm-opt` was found and run; custom sections are stripped either way
+    pub wasm_opt: bool,
+}
+
+/// Shrink the module at `path` in place: run `wasm-opt -Oz` on it, if the binary
+/// can be found, and strip its custom sections, like debug info and names.
+pub fn optimize_module(path: &Path, options: &BuildOptions) -> Result<ModuleOptimization> {
+    let original_size = file_size(path)?;
+    let wasm_opt = options
+        .wasm_opt
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("wasm-opt"));
+    let wasm_opt_ran = match Command::new(&wasm_opt)
+        .arg("-Oz")
+        .arg(path)
+        .arg("-o")
+        .arg(path)
+        .output()
+    {
+        Ok(output) if output.status.success() => true,
+        Ok(output) => bail!(TappletError::BuildFailed {
+            message: format!(
+                "wasm-opt failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ),
+            diagnostics: Vec::new(),
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to execute {}", wasm_opt.display()));
+        }
+    };
+
+    let module =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let stripped = strip_custom_sections(&module).ok_or_else(|| {
+        TappletError::ArtifactNotFound(format!("{} is not a valid WASM module", path.display()))
+    })?;
+    std::fs::write(path, stripped)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(ModuleOptimization {
+        original_size,
+        optimized_size: file_size(path)?,
+        wasm_opt: wasm_opt_ran,
+    })
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    Ok(std::fs::metadata(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .len())
+}
+
+/// Check that `module` exports a function for every method in `manifest`'s
+/// `api.methods`, and return warnings about exported functions that aren't listed.
+///
+/// Fails with [`TappletError::MissingExports`] if methods aren't exported, which
+/// would otherwise only show up as `METHOD_NOT_FOUND` when they are called.
+pub fn check_exports(manifest: &TappletManifest, module: &[u8]) -> Result<Vec<String>> {
+    let exports = function_exports(module).ok_or_else(|| {
+        TappletError::ArtifactNotFound(format!(
+            "the module of {} is not a valid WASM module",
+            manifest.name
+        ))
+    })?;
+    let missing: Vec<_> = manifest
+        .api
+        .methods
+        .iter()
+        .filter(|method| !exports.contains(method))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        bail!(TappletError::MissingExports { methods: missing });
+    }
+    Ok(exports
+        .iter()
+        .filter(|export| !manifest.api.methods.contains(export))
+        .filter(|export| !is_runtime_export(export))
+        .map(|export| {
+            format!(
+                "{} exports {}, which is not listed in api.methods and can't be called",
+                manifest.name, export
+            )
+        })
+        .collect())
+}
+
+/// Exports the host or the toolchain use, rather than tapplet methods, including
+/// the entry points of WASI modules
+pub(crate) fn is_runtime_export(name: &str) -> bool {
+    matches!(
+        name,
+        GUEST_ALLOC_EXPORT
+            | GUEST_DEALLOC_EXPORT
+            | GUEST_NEXT_CHUNK_EXPORT
+            | GUEST_LAST_PANIC_EXPORT
+            | GUEST_INIT_EXPORT
+            | GUEST_HEALTH_EXPORT
+            | "_initialize"
+            | "_start"
+    ) || name.starts_with("__")
+}
+
+/// Names of the functions `module` exports, or `None` if it isn't a WASM module
+fn function_exports(module: &[u8]) -> Option<Vec<String>> {
+    const EXPORT_SECTION: u8 = 7;
+    const FUNCTION_EXPORT: u8 = 0;
+    let mut exports = Vec::new();
+    for section in sections(module)? {
+        if section.id != EXPORT_SECTION {
+            continue;
+        }
+        let mut rest = section.payload;
+        let (count, len) = read_leb128_u32(rest)?;
+        rest = &rest[len..];
+        for _ in 0..count {
+            let (name_len, len) = read_leb128_u32(rest)?;
+            let name_end = len + usize::try_from(name_len).ok()?;
+            let name = std::str::from_utf8(rest.get(len..name_end)?).ok()?;
+            let kind = *rest.get(name_end)?;
+            let (_, index_len) = read_leb128_u32(rest.get(name_end + 1..)?)?;
+            if kind == FUNCTION_EXPORT {
+                exports.push(name.to_string());
+            }
+            rest = &rest[name_end + 1 + index_len..];
+        }
+    }
+    Some(exports)
+}
+
+/// The module without its custom sections, or `None` if it isn't a WASM module
+fn strip_custom_sections(module: &[u8]) -> Option<Vec<u8>> {
+    const CUSTOM_SECTION: u8 = 0;
+    let mut stripped = module.get(..8)?.to_vec();
+    for section in sections(module)? {
+        if section.id != CUSTOM_SECTION {
+            stripped.extend_from_slice(section.bytes);
+        }
+    }
+    Some(stripped)
+}
+
+struct Section<'a> {
+    id: u8,
+    /// The whole section, including its id and size
+    bytes: &'a [u8],
+    payload: &'a [u8],
+}
+
+/// The sections of `module`, or `None` if it isn't a WASM module
+fn sections(module: &[u8]) -> Option<Vec<Section<'_>>> {
+    const HEADER: &[u8] = b"\0asm";
+    if module.len() < 8 || &module[..4] != HEADER {
+        return None;
+    }
+    let mut sections = Vec::new();
+    let mut rest = &module[8..];
+    while let Some((&id, after_id)) = rest.split_first() {
+        let (size, size_len) = read_leb128_u32(after_id)?;
+        let end = 1 + size_len + usize::try_from(size).ok()?;
+        if end > rest.len() {
+            return None;
+        }
+        sections.push(Section {
+            id,
+            bytes: &rest[..end],
+            payload: &rest[1 + size_len..end],
+        });
+        rest = &rest[end..];
+    }
+    Some(sections)
+}
+
+/// An unsigned LEB128 number and how many bytes it took
+fn read_leb128_u32(bytes: &[u8]) -> Option<(u32, usize)> {
+    let mut value = 0u32;
+    for (i, byte) in bytes.iter().take(5).enumerate() {
+        value |= u32::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Run `cargo build` for [`WASM_TARGET`] in `crate_dir`
+pub(crate) fn cargo_build(crate_dir: &Path, options: &BuildOptions) -> Result<()> {
+    let mut command = options.cargo(crate_dir, "build");
+    command.args([
+        "--profile",
+        options.profile(),
+        "--target",
+        WASM_TARGET,
+        "--message-format",
+        "json",
+    ]);
+    if !options.features.is_empty() {
+        command.args(["--features", &options.features.join(",")]);
+    }
+    if let Some(target_dir) = &options.target_dir {
+        command.arg("--target-dir").arg(target_dir);
+    }
+    if !options.rustflags.is_empty() {
+        let mut rustflags = std::env::var("RUSTFLAGS").unwrap_or_default();
+        for flag in &options.rustflags {
+            if !rustflags.is_empty() {
+                rustflags.push(' ');
+            }
+            rustflags.push_str(flag);
+        }
+        command.env("RUSTFLAGS", rustflags);
+    }
+    let output = command
+        .output()
+        .context("Failed to execute cargo build. Is cargo installed?")?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let diagnostics = parse_diagnostics(&output.stdout);
+    if diagnostics.iter().any(BuildDiagnostic::is_missing_target) {
+        bail!(TappletError::MissingBuildTarget {
+            target: WASM_TARGET.to_string()
+        });
+    }
+    let errors: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.is_error())
+        .filter_map(|d| d.rendered.as_deref())
+        .collect();
+    // Cargo's own failures, e.g. an invalid Cargo.toml, are only on stderr
+    let message = if errors.is_empty() {
+        String::from_utf8_lossy(&output.stderr)
+            .trim_end()
+            .to_string()
+    } else {
+        errors.concat().trim_end().to_string()
+    };
+    bail!(TappletError::BuildFailed {
+        message,
+        diagnostics,
+    })
+}
+
+/// Where cargo puts the module built from `crate_dir`: named like `entrypoint`, or
+/// else after the crate's `cdylib` (or binary) target.
+///
+/// Workspaces and `CARGO_TARGET_DIR` move the target directory, so unless the
+/// options set one it is taken from `cargo metadata` rather than assumed to be
+/// `./target`.
+pub(crate) fn built_module(
+    crate_dir: &Path,
+    options: &BuildOptions,
+    entrypoint: Option<&Path>,
+) -> Result<PathBuf> {
+    let metadata = CargoMetadata::read(crate_dir, options)?;
+    let target_directory = match &options.target_dir {
+        Some(target_dir) => crate_dir.join(target_dir),
+        None => metadata.target_directory.clone(),
+    };
+    let wasm_target_dir = target_directory
+        .join(WASM_TARGET)
+        .join(options.profile_dir());
+    if let Some(file_name) = entrypoint.and_then(Path::file_name) {
+        return Ok(wasm_target_dir.join(file_name));
+    }
+
+    let manifest_path = crate_dir.join("Cargo.toml");
+    let package = metadata
+        .packages
+        .iter()
+        .find(|package| same_file(&package.manifest_path, &manifest_path))
+        .ok_or_else(|| {
+            TappletError::ArtifactNotFound(format!(
+                "{} is not a Cargo package",
+                manifest_path.display()
+            ))
+        })?;
+    let has_kind = |target: &&CargoTarget, kind: &str| target.kind.iter().any(|k| k == kind);
+    let file_name = if let Some(lib) = package.targets.iter().find(|t| has_kind(t, "cdylib")) {
+        // Cargo names library files after the crate name, with `_` for `-`
+        format!("{}.wasm", lib.name.replace('-', "_"))
+    } else if let Some(bin) = package.targets.iter().find(|t| has_kind(t, "bin")) {
+        format!("{}.wasm", bin.name)
+    } else {
+        bail!(TappletError::ArtifactNotFound(format!(
+            "package {} has no cdylib target to build a WASM module from",
+            package.name
+        )));
+    };
+    Ok(wasm_target_dir.join(file_name))
+}
+
+/// Compiler messages in cargo's `--message-format json` output
+fn parse_diagnostics(stdout: &[u8]) -> Vec<BuildDiagnostic> {
+    #[derive(Deserialize)]
+    struct Message {
+        reason: String,
+        message: Option<CompilerMessage>,
+    }
+    #[derive(Deserialize)]
+    struct CompilerMessage {
+        level: String,
+        message: String,
+        code: Option<Code>,
+        #[serde(default)]
+        spans: Vec<Span>,
+        #[serde(default)]
+        children: Vec<Child>,
+        rendered: Option<String>,
+    }
+    #[derive(Deserialize)]
+    struct Code {
+        code: String,
+    }
+    #[derive(Deserialize)]
+    struct Span {
+        file_name: PathBuf,
+        line_start: usize,
+        column_start: usize,
+        is_primary: bool,
+    }
+    #[derive(Deserialize)]
+    struct Child {
+        message: String,
+    }
+
+    String::from_utf8_lossy(stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Message>(line).ok())
+        .filter(|message| message.reason == "compiler-message")
+        .filter_map(|message| message.message)
+        .map(|message| {
+            let span = message.spans.into_iter().find(|span| span.is_primary);
+            BuildDiagnostic {
+                level: message.level,
+                message: message.message,
+                code: message.code.map(|code| code.code),
+                line: span.as_ref().map(|span| span.line_start),
+                column: span.as_ref().map(|span| span.column_start),
+                file: span.map(|span| span.file_name),
+                rendered: message.rendered,
+                notes: message.children.into_iter().map(|c| c.message).collect(),
+            }
+        })
+        .collect()
+}
+
+/// The parts of `cargo metadata` output used to find a built module
+#[derive(Deserialize)]
+struct CargoMetadata {
+    packages: Vec<CargoPackage>,
+    target_directory: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct CargoPackage {
+    name: String,
+    manifest_path: PathBuf,
+    targets: Vec<CargoTarget>,
+}
+
+#[derive(Deserialize)]
+struct CargoTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+impl CargoMetadata {
+    fn read(crate_dir: &Path, options: &BuildOptions) -> Result<Self> {
+        let output = options
+            .cargo(crate_dir, "metadata")
+            .args(["--format-version", "1", "--no-deps"])
+            .output()
+            .context("Failed to execute cargo metadata. Is cargo installed?")?;
+        if !output.status.success() {
+            bail!(TappletError::BuildFailed {
+                message: String::from_utf8_lossy(&output.stderr)
+                    .trim_end()
+                    .to_string(),
+                diagnostics: Vec::new(),
+            });
+        }
+        serde_json::from_slice(&output.stdout).context("Failed to parse cargo metadata output")
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_crate(dir: &Path, name: &str) {
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let manifest = format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[lib]\ncrate-type = [\"cdylib\"]\n",
+            name
+        );
+        std::fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "").unwrap();
+    }
+
+    #[test]
+    fn test_built_module_in_workspace() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        std::fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"tapplet\", \"helper\"]\n",
+        )
+        .unwrap();
+        write_crate(&root.join("tapplet"), "my-tapplet");
+        write_crate(&root.join("helper"), "helper");
+        let dir = root.join("tapplet");
+
+        let options = BuildOptions::default();
+        // The workspace's target directory, unless the tests run with another one
+        let target_dir = std::env::var_os("CARGO_TARGET_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| root.canonicalize().unwrap().join("target"));
+        let release = target_dir.join("wasm32-unknown-unknown/release");
+        assert_eq!(
+            built_module(&dir, &options, None).unwrap(),
+            release.join("my_tapplet.wasm")
+        );
+        assert_eq!(
+            built_module(&dir, &options, Some(Path::new("out/custom.wasm"))).unwrap(),
+            release.join("custom.wasm")
+        );
+
+        let options = BuildOptions {
+            profile: Some("dev".to_string()),
+            target_dir: Some(PathBuf::from("build")),
+            ..Default::default()
+        };
+        assert_eq!(
+            built_module(&dir, &options, None).unwrap(),
+            dir.join("build/wasm32-unknown-unknown/debug/my_tapplet.wasm")
+        );
+    }
+
+    #[test]
+    fn test_optimize_module_strips_custom_sections() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("module.wasm");
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        // A custom "name" section, an empty type section and a custom section of 200 bytes,
+        // whose size takes two LEB128 bytes
+        module.extend_from_slice(&[0, 5, 4, b'n', b'a', b'm', b'e']);
+        module.extend_from_slice(&[1, 1, 0]);
+        module.extend_from_slice(&[0, 0xc8, 0x01]);
+        module.extend_from_slice(&[0; 200]);
+        std::fs::write(&path, &module).unwrap();
+
+        let options = BuildOptions {
+            wasm_opt: Some(temp.path().join("missing-wasm-opt")),
+            ..Default::default()
+        };
+        let optimization = optimize_module(&path, &options).unwrap();
+        assert!(!optimization.wasm_opt);
+        assert_eq!(optimization.original_size, module.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), b"\0asm\x01\0\0\0\x01\x01\0");
+        assert_eq!(optimization.optimized_size, 11);
+
+        std::fs::write(&path, b"not wasm").unwrap();
+        assert!(optimize_module(&path, &options).is_err());
+    }
+
+    #[test]
+    fn test_check_exports() {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        // Exports of functions "greet", "debug" and "tapplet_alloc" and the memory
+        let mut exports = vec![4];
+        for (name, kind) in [
+            ("greet", 0),
+            ("debug", 0),
+            ("tapplet_alloc", 0),
+            ("memory", 2),
+        ] {
+            exports.push(name.len() as u8);
+            exports.extend_from_slice(name.as_bytes());
+            exports.extend_from_slice(&[kind, 0]);
+        }
+        module.extend_from_slice(&[7, exports.len() as u8]);
+        module.extend_from_slice(&exports);
+
+        let mut manifest =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("greeter", "0.1.0"))
+                .unwrap();
+        manifest.api.methods = vec!["greet".to_string()];
+        let warnings = check_exports(&manifest, &module).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("exports debug"));
+
+        manifest.api.methods.push("memory".to_string());
+        let err = check_exports(&manifest, &module).unwrap_err();
+        assert_eq!(crate::error_code(&err), "MISSING_EXPORTS");
+        assert!(err.to_string().ends_with("memory"));
+    }
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let stdout = r#"{"reason":"compiler-artifact","package_id":"x"}
+{"reason":"compiler-message","message":{"rendered":"error[E0425]: cannot find value `x`\n","level":"error","message":"cannot find value `x` in this scope","code":{"code":"E0425"},"children":[],"spans":[{"file_name":"src/lib.rs","line_start":3,"column_start":5,"is_primary":true}]}}
+{"reason":"compiler-message","message":{"rendered":"error[E0463]: can't find crate for `std`\n","level":"error","message":"can't find crate for `std`","code":{"code":"E0463"},"children":[{"level":"note","message":"the `wasm32-unknown-unknown` target may not be installed"}],"spans":[]}}
+{"reason":"build-finished","success":false}"#;
+        let diagnostics = parse_diagnostics(stdout.as_bytes());
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[0].is_error());
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E0425"));
+        assert_eq!(diagnostics[0].file, Some(PathBuf::from("src/lib.rs")));
+        assert_eq!(
+            (diagnostics[0].line, diagnostics[0].column),
+            (Some(3), Some(5))
+        );
+        assert!(!diagnostics[0].is_missing_target());
+        assert!(diagnostics[1].is_missing_target());
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::host::HostError;
+use crate::model::TappletManifest;
+
+/// Who initiated a call into a tapplet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Caller {
+    /// The user, e.g. through a button in the wallet UI
+    User,
+    /// Another tapplet, identified by name
+    Tapplet(String),
+    /// The host application itself, e.g. a background job
+    System,
+    /// A client of the JSON-RPC server, whoever can reach it
+    Rpc,
+}
+
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Caller::User => write!(f, "user"),
+            Caller::Tapplet(name) => write!(f, "tapplet {}", name),
+            Caller::System => write!(f, "system"),
+            Caller::Rpc => write!(f, "rpc client"),
+        }
+    }
+}
+
+/// Who is calling a tapplet method and what they are allowed to do
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallContext {
+    pub caller: Caller,
+    pub session_id: Option<String>,
+    pub permissions: HashSet<String>,
+}
+
+impl CallContext {
+    pub fn new(caller: Caller) -> Self {
+        Self {
+            caller,
+            session_id: None,
+            permissions: HashSet::new(),
+        }
+    }
+
+    /// A call initiated by the user
+    pub fn user() -> Self {
+        Self::new(Caller::User)
+    }
+
+    /// A call initiated by another tapplet
+    pub fn tapplet<S: Into<String>>(name: S) -> Self {
+        Self::new(Caller::Tapplet(name.into()))
+    }
+
+    pub fn system() -> Self {
+        Self::new(Caller::System)
+    }
+
+    /// A call from a JSON-RPC client
+    pub fn rpc() -> Self {
+        Self::new(Caller::Rpc)
+    }
+
+    pub fn with_session_id<S: Into<String>>(mut self, session_id: S) -> Self {
+        self.session_id = Some(session_id.into());
+        self
+    }
+
+    pub fn with_permission<S: Into<String>>(mut self, permission: S) -> Self {
+        self.permissions.insert(permission.into());
+        self
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.contains(permission)
+    }
+
+    /// Fail unless the caller holds a permission
+    pub fn require_permission(&self, permission: &str) -> Result<(), HostError> {
+        if self.has_permission(permission) {
+            return Ok(());
+        }
+        Err(HostError::PermissionDenied(format!(
+            "{} requires permission {}",
+            self.caller, permission
+        )))
+    }
+
+    /// Check that this context may call a method declared in the manifest
+    pub fn ensure_allowed(
+        &self,
+        manifest: &TappletManifest,
+        method: &str,
+    ) -> Result<(), HostError> {
+        if !manifest.api.methods.iter().any(|m| m == method) {
+            return Err(HostError::MethodNotFound(method.to_string()));
+        }
+        let Some(definition) = manifest.api.method(method) else {
+            return Ok(());
+        };
+
+        if definition.user_only && self.caller != Caller::User {
+            return Err(HostError::PermissionDenied(format!(
+                "{} can only be called by the user, not by {}",
+                method, self.caller
+            )));
+        }
+        let missing: Vec<_> = definition
+            .permissions
+            .iter()
+            .filter(|permission| !self.has_permission(permission))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            return Err(HostError::PermissionDenied(format!(
+                "{} requires permissions: {}",
+                method,
+                missing.join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> TappletManifest {
+        let transfer = r#"methods = ["greet", "transfer"]
+
+[api.transfer]
+description = "Sends funds."
+permissions = ["wallet:send"]
+user_only = true
+
+[api.transfer.returns]
+type = "string"
+description = "The transaction id."
+"#;
+        let toml = crate::test_utils::manifest_toml("wallet", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, transfer);
+        TappletManifest::from_toml_str(&toml).unwrap()
+    }
+
+    #[test]
+    fn test_method_permissions() {
+        let manifest = manifest();
+        assert!(
+            CallContext::tapplet("other")
+                .ensure_allowed(&manifest, "greet")
+                .is_ok()
+        );
+        assert!(matches!(
+            CallContext::user().ensure_allowed(&manifest, "missing"),
+            Err(HostError::MethodNotFound(_))
+        ));
+        assert!(matches!(
+            CallContext::user().ensure_allowed(&manifest, "transfer"),
+            Err(HostError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            CallContext::tapplet("other")
+                .with_permission("wallet:send")
+                .ensure_allowed(&manifest, "transfer"),
+            Err(HostError::PermissionDenied(_))
+        ));
+        assert!(
+            CallContext::user()
+                .with_session_id("session")
+                .with_permission("wallet:send")
+                .ensure_allowed(&manifest, "transfer")
+                .is_ok()
+        );
+    }
+}
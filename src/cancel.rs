@@ -0,0 +1,180 @@
+//! Cancelling tapplet calls that are in flight.
+//!
+//! Pass a [`CancellationToken`] to `run_with_cancel` on either host and call
+//! [`CancellationToken::cancel`] from another thread or task, e.g. when the user
+//! navigates away, to stop the call with [`crate::host::HostError::Cancelled`].
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type Listener = Box<dyn Fn() + Send + Sync>;
+
+/// Cancels the calls it was passed to. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    listeners: Mutex<Listeners>,
+}
+
+#[derive(Default)]
+struct Listeners {
+    next_id: u64,
+    listeners: BTreeMap<u64, Listener>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop calls running with this token. Calls started with it afterwards fail
+    /// straight away.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let listeners = self.inner.listeners.lock().unwrap();
+        for listener in listeners.listeners.values() {
+            listener();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Run `listener` when the token is cancelled, until the returned guard is
+    /// dropped. Check [`Self::is_cancelled`] afterwards, as it isn't run if the
+    /// token already was.
+    pub(crate) fn on_cancel(&self, listener: impl Fn() + Send + Sync + 'static) -> OnCancel {
+        let mut listeners = self.inner.listeners.lock().unwrap();
+        let id = listeners.next_id;
+        listeners.next_id += 1;
+        listeners.listeners.insert(id, Box::new(listener));
+        OnCancel {
+            token: self.clone(),
+            id,
+        }
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Removes a listener added with [`CancellationToken::on_cancel`] when dropped
+pub(crate) struct OnCancel {
+    token: CancellationToken,
+    id: u64,
+}
+
+impl Drop for OnCancel {
+    fn drop(&mut self) {
+        let mut listeners = self.token.inner.listeners.lock().unwrap();
+        listeners.listeners.remove(&self.id);
+    }
+}
+
+/// A token that is cancelled once a timeout passes, or when the token it was
+/// started from is. Dropping the timer stops it.
+pub(crate) struct Timer {
+    token: CancellationToken,
+    expired: Arc<AtomicBool>,
+    _stop: mpsc::Sender<()>,
+    _parent: Option<OnCancel>,
+}
+
+impl Timer {
+    pub(crate) fn start(timeout: Duration, parent: Option<&CancellationToken>) -> Self {
+        let token = CancellationToken::new();
+        let expired = Arc::new(AtomicBool::new(false));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let (timer_token, timer_expired) = (token.clone(), expired.clone());
+        std::thread::spawn(move || {
+            // The sender is only ever dropped, which disconnects the channel
+            if stopped.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                timer_expired.store(true, Ordering::SeqCst);
+                timer_token.cancel();
+            }
+        });
+        let parent = parent.map(|parent| {
+            let child = token.clone();
+            let listener = parent.on_cancel(move || child.cancel());
+            if parent.is_cancelled() {
+                token.cancel();
+            }
+            listener
+        });
+        Self {
+            token,
+            expired,
+            _stop: stop,
+            _parent: parent,
+        }
+    }
+
+    pub(crate) fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Whether the timeout passed, rather than the parent token being cancelled
+    pub(crate) fn expired(&self) -> bool {
+        self.expired.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_cancel_runs_listeners_once() {
+        let token = CancellationToken::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls2 = calls.clone();
+        let listener = token.on_cancel(move || {
+            calls2.fetch_add(1, Ordering::SeqCst);
+        });
+        let calls3 = calls.clone();
+        drop(token.on_cancel(move || {
+            calls3.fetch_add(10, Ordering::SeqCst);
+        }));
+
+        assert!(!token.is_cancelled());
+        token.clone().cancel();
+        token.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        drop(listener);
+        assert!(token.inner.listeners.lock().unwrap().listeners.is_empty());
+    }
+
+    #[test]
+    fn test_timer() {
+        let timer = Timer::start(Duration::from_millis(10), None);
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(timer.token().is_cancelled() && timer.expired());
+
+        // Cancelling the parent cancels the timer's token, without it expiring
+        let parent = CancellationToken::new();
+        let timer = Timer::start(Duration::from_secs(60), Some(&parent));
+        parent.cancel();
+        assert!(timer.token().is_cancelled() && !timer.expired());
+        drop(timer);
+        assert!(parent.inner.listeners.lock().unwrap().listeners.is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+//! Read-only chain queries of tapplets.
+//!
+//! The embedder answers them through [`MinotariChainApi`], usually by asking its
+//! base node, and gives the API to a host with `with_chain_api`. Tapplets must
+//! also ask for it in their manifest:
+//!
+//! ```toml
+//! [permissions.chain]
+//! read = true
+//! ```
+//!
+//! Tapplets then call `minotari_get_tip_height()`, `minotari_get_block_header(height)`
+//! and `minotari_get_kernel(excess)`. Queries of tapplets that don't declare the
+//! permission, or of hosts without a chain API, fail with `PERMISSION_DENIED`.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::task;
+
+use crate::host::HostError;
+use crate::model::PermissionsConfig;
+
+/// Header of a block on the main chain. Hashes and Merkle roots are hex.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub height: u64,
+    pub hash: String,
+    pub prev_hash: String,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub output_mr: String,
+    pub kernel_mr: String,
+}
+
+/// A transaction kernel mined on the main chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KernelInfo {
+    /// Public excess of the kernel, hex
+    pub excess: String,
+    /// Fee in microMinotari
+    pub fee: u64,
+    pub lock_height: u64,
+    /// Height of the block the kernel was mined in
+    pub block_height: u64,
+}
+
+/// Chain surface the embedder provides, on top of whatever base node it talks to.
+///
+/// Exposed to tapplets whose manifest declares `[permissions.chain] read = true`
+/// once given to a host with `with_chain_api`.
+#[async_trait]
+pub trait MinotariChainApi: Send + Sync {
+    async fn get_tip_height(&self) -> Result<u64, anyhow::Error>;
+    /// The header at `height`, `None` if the chain isn't that long
+    async fn get_block_header(&self, height: u64) -> Result<Option<BlockHeader>, anyhow::Error>;
+    /// The kernel with the hex `excess`, `None` if it hasn't been mined
+    async fn get_kernel(&self, excess: &str) -> Result<Option<KernelInfo>, anyhow::Error>;
+}
+
+#[async_trait]
+impl<T: MinotariChainApi + ?Sized> MinotariChainApi for Arc<T> {
+    async fn get_tip_height(&self) -> Result<u64, anyhow::Error> {
+        (**self).get_tip_height().await
+    }
+
+    async fn get_block_header(&self, height: u64) -> Result<Option<BlockHeader>, anyhow::Error> {
+        (**self).get_block_header(height).await
+    }
+
+    async fn get_kernel(&self, excess: &str) -> Result<Option<KernelInfo>, anyhow::Error> {
+        (**self).get_kernel(excess).await
+    }
+}
+
+/// The chain API of one host, shared with its host functions
+#[derive(Clone)]
+pub(crate) struct GuestChain {
+    /// Whether the manifest declares `permissions.chain.read`
+    read: bool,
+    tapplet: String,
+    api: Arc<Mutex<Option<Arc<dyn MinotariChainApi>>>>,
+}
+
+impl GuestChain {
+    pub fn new(tapplet: &str, permissions: &PermissionsConfig) -> Self {
+        Self {
+            read: permissions.allows_chain_read(),
+            tapplet: tapplet.to_string(),
+            api: Arc::default(),
+        }
+    }
+
+    pub fn set_api(&self, api: Option<Arc<dyn MinotariChainApi>>) {
+        *self.api.lock().unwrap() = api;
+    }
+
+    pub fn tip_height(&self) -> Result<u64, HostError> {
+        let api = self.api()?;
+        block_on(async move { api.get_tip_height().await })
+    }
+
+    pub fn block_header(&self, height: u64) -> Result<Option<BlockHeader>, HostError> {
+        let api = self.api()?;
+        block_on(async move { api.get_block_header(height).await })
+    }
+
+    pub fn kernel(&self, excess: &str) -> Result<Option<KernelInfo>, HostError> {
+        if hex::decode(excess).is_err() {
+            return Err(HostError::InvalidArguments(format!(
+                "kernel excess '{}' is not hex",
+                excess
+            )));
+        }
+        let api = self.api()?;
+        let excess = excess.to_string();
+        block_on(async move { api.get_kernel(&excess).await })
+    }
+
+    /// The API, if the tapplet may use it
+    fn api(&self) -> Result<Arc<dyn MinotariChainApi>, HostError> {
+        if !self.read {
+            return Err(HostError::PermissionDenied(format!(
+                "{} doesn't declare permissions.chain.read in its manifest",
+                self.tapplet
+            )));
+        }
+        self.api.lock().unwrap().clone().ok_or_else(|| {
+            HostError::PermissionDenied("chain queries are not enabled in this host".to_string())
+        })
+    }
+}
+
+/// Wait for a query from a host function. Lua calls run on a multi-threaded
+/// runtime; WASM calls may run outside of any runtime, or on one that can't be
+/// blocked, so they get a runtime of their own on another thread.
+pub(crate) fn block_on<T: Send>(
+    query: impl Future<Output = Result<T, anyhow::Error>> + Send,
+) -> Result<T, HostError> {
+    let result = match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            task::block_in_place(|| handle.block_on(query))
+        }
+        _ => std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()?
+                        .block_on(query)
+                })
+                .join()
+                .map_err(|_| anyhow::anyhow!("host query panicked"))?
+        }),
+    };
+    result.map_err(|e| HostError::ExecutionError(format!("{:#}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TwoBlocks;
+
+    #[async_trait]
+    impl MinotariChainApi for TwoBlocks {
+        async fn get_tip_height(&self) -> Result<u64, anyhow::Error> {
+            Ok(1)
+        }
+
+        async fn get_block_header(
+            &self,
+            height: u64,
+        ) -> Result<Option<BlockHeader>, anyhow::Error> {
+            Ok((height <= 1).then(|| BlockHeader {
+                height,
+                hash: format!("{:064x}", height + 1),
+                prev_hash: format!("{:064x}", height),
+                timestamp: 1_700_000_000 + height * 120,
+                output_mr: "00".repeat(32),
+                kernel_mr: "00".repeat(32),
+            }))
+        }
+
+        async fn get_kernel(&self, _: &str) -> Result<Option<KernelInfo>, anyhow::Error> {
+            anyhow::bail!("base node unreachable")
+        }
+    }
+
+    #[test]
+    fn test_queries_need_permission_and_api() {
+        let toml = crate::test_utils::manifest_toml("explorer", "0.1.0");
+        let manifest = crate::TappletManifest::from_toml_str(&toml).unwrap();
+        let chain = GuestChain::new("explorer", &manifest.permissions);
+        chain.set_api(Some(Arc::new(TwoBlocks)));
+        let err = chain.tip_height().unwrap_err();
+        assert!(
+            err.to_string().contains("permissions.chain.read"),
+            "{}",
+            err
+        );
+
+        let manifest =
+            crate::TappletManifest::from_toml_str(&(toml + "[permissions.chain]\nread = true\n"))
+                .unwrap();
+        let chain = GuestChain::new("explorer", &manifest.permissions);
+        assert_eq!(chain.tip_height().unwrap_err().code(), "PERMISSION_DENIED");
+
+        // Outside of a runtime the queries get one of their own
+        chain.set_api(Some(Arc::new(TwoBlocks)));
+        assert_eq!(chain.tip_height().unwrap(), 1);
+        assert_eq!(
+            chain.block_header(1).unwrap().unwrap().timestamp,
+            1_700_000_120
+        );
+        assert_eq!(chain.block_header(2).unwrap(), None);
+        assert_eq!(chain.kernel("xyz").unwrap_err().code(), "INVALID_ARGUMENTS");
+        let err = chain.kernel("abcd").unwrap_err();
+        assert_eq!(err.code(), "EXECUTION_ERROR");
+        assert!(err.to_string().contains("base node unreachable"), "{}", err);
+    }
+}
@@ -0,0 +1,386 @@
+//! Client bindings generated from a tapplet's [`ApiConfig`].
+//!
+//! [`typescript`] emits one params interface and one result type per method plus
+//! a client class, so wallet web UIs calling a tapplet get compile-time checked
+//! method names and arguments:
+//!
+//! ```ts
+//! const greeter = new GreeterClient((tapplet, method, params) =>
+//!   rpc.request("tapplet.call", [tapplet, method, params]));
+//! const greeting = await greeter.greet({ name: "Alice" });
+//! ```
+//!
+//! [`ApiConfig`]: crate::model::ApiConfig
+
+use std::fmt::Write;
+
+use crate::model::{MethodDefinition, ParamType, TappletManifest};
+
+/// Generate a TypeScript module with types and a typed client for a tapplet's API
+pub fn typescript(manifest: &TappletManifest) -> String {
+    let mut out = String::new();
+    let client = format!("{}Client", pascal_case(&manifest.name));
+    writeln!(
+        out,
+        "// Generated by tari-tapplet-lib from {} {}. Do not edit.",
+        manifest.name, manifest.version
+    )
+    .unwrap();
+    out.push('\n');
+    out.push_str(
+        "/** Sends a call to a tapplet, e.g. with the `tapplet.call` JSON-RPC method */\n\
+         export type TappletTransport = (tapplet: string, method: string, params: unknown) => Promise<unknown>;\n",
+    );
+
+    for method in &manifest.api.methods {
+        let definition = manifest.api.method_definitions.get(method);
+        let type_name = pascal_case(method);
+        out.push('\n');
+        write_params(&mut out, &type_name, definition);
+        out.push('\n');
+        match definition {
+            Some(definition) => {
+                let return_type = &definition.returns.return_type;
+                let mut doc = definition.returns.description.trim().to_string();
+                if contains_int64(return_type) {
+                    doc.push_str(INT64_RESULT_NOTE);
+                }
+                write_doc(&mut out, "", &doc);
+                writeln!(
+                    out,
+                    "export type {}Result = {};",
+                    type_name,
+                    typescript_result_type(return_type)
+                )
+                .unwrap();
+            }
+            None => writeln!(out, "export type {}Result = unknown;", type_name).unwrap(),
+        }
+    }
+
+    out.push('\n');
+    write_doc(
+        &mut out,
+        "",
+        manifest
+            .description
+            .as_deref()
+            .unwrap_or(&manifest.friendly_name),
+    );
+    writeln!(out, "export class {} {{", client).unwrap();
+    writeln!(
+        out,
+        "  static readonly tapplet = {};",
+        string_literal(&manifest.name)
+    )
+    .unwrap();
+    out.push('\n');
+    out.push_str("  constructor(private readonly transport: TappletTransport) {}\n");
+    for method in &manifest.api.methods {
+        let definition = manifest.api.method_definitions.get(method);
+        let type_name = pascal_case(method);
+        out.push('\n');
+        if let Some(definition) = definition {
+            write_method_doc(&mut out, definition);
+        }
+        writeln!(
+            out,
+            "  {}(params: {}Params): Promise<{}Result> {{",
+            property_name(method),
+            type_name,
+            type_name
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "    return this.transport({}.tapplet, {}, params) as Promise<{}Result>;",
+            client,
+            string_literal(method),
+            type_name
+        )
+        .unwrap();
+        out.push_str("  }\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn write_params(out: &mut String, type_name: &str, definition: Option<&MethodDefinition>) {
+    let Some(definition) = definition else {
+        writeln!(
+            out,
+            "export type {}Params = Record<string, unknown>;",
+            type_name
+        )
+        .unwrap();
+        return;
+    };
+    if definition.params.is_empty() {
+        writeln!(
+            out,
+            "export type {}Params = Record<string, never>;",
+            type_name
+        )
+        .unwrap();
+        return;
+    }
+    writeln!(out, "export interface {}Params {{", type_name).unwrap();
+    let mut params: Vec<_> = definition.params.iter().collect();
+    params.sort_by_key(|(name, _)| *name);
+    for (name, param) in params {
+        write_doc(out, "  ", &param.description);
+        writeln!(
+            out,
+            "  {}{}: {};",
+            property_name(name),
+            if param.is_optional() { "?" } else { "" },
+            typescript_type(&param.param_type)
+        )
+        .unwrap();
+    }
+    out.push_str("}\n");
+}
+
+/// Added to the doc comment of results with 64-bit integers, which hosts return
+/// as JSON numbers
+const INT64_RESULT_NOTE: &str = " 64-bit integers above Number.MAX_SAFE_INTEGER lose precision \
+                                 unless the transport parses JSON with big integer support.";
+
+/// The TypeScript type for a manifest param.
+///
+/// 64-bit integers are decimal strings, which hosts accept for every integer
+/// type, since a `number` loses precision above 2^53.
+pub fn typescript_type(param_type: &ParamType) -> String {
+    typescript_type_of(param_type, false)
+}
+
+/// The TypeScript type for a manifest return type. Hosts return `bytes` as
+/// base64 strings, and 64-bit integers as JSON numbers.
+pub fn typescript_result_type(param_type: &ParamType) -> String {
+    typescript_type_of(param_type, true)
+}
+
+fn typescript_type_of(param_type: &ParamType, result: bool) -> String {
+    let nested = |param_type| typescript_type_of(param_type, result);
+    match param_type {
+        ParamType::String => "string".to_string(),
+        ParamType::U64 | ParamType::I64 if !result => "string".to_string(),
+        ParamType::U8
+        | ParamType::U16
+        | ParamType::U32
+        | ParamType::U64
+        | ParamType::I8
+        | ParamType::I16
+        | ParamType::I32
+        | ParamType::I64
+        | ParamType::F64 => "number".to_string(),
+        ParamType::Bool => "boolean".to_string(),
+        ParamType::Bytes if result => "string".to_string(),
+        ParamType::Bytes => "string | number[]".to_string(),
+        ParamType::Null => "null".to_string(),
+        ParamType::Any | ParamType::Other(_) => "unknown".to_string(),
+        ParamType::Array(item) => array_of(&nested(item)),
+        ParamType::Object(fields) if fields.is_empty() => "Record<string, unknown>".to_string(),
+        ParamType::Object(fields) => {
+            let fields: Vec<_> = fields
+                .iter()
+                .map(|(name, field_type)| {
+                    format!(
+                        "{}{}: {}",
+                        property_name(name),
+                        if field_type.is_optional() { "?" } else { "" },
+                        nested(field_type)
+                    )
+                })
+                .collect();
+            format!("{{ {} }}", fields.join("; "))
+        }
+        ParamType::Optional(inner) => format!("{} | null", nested(inner)),
+    }
+}
+
+fn contains_int64(param_type: &ParamType) -> bool {
+    match param_type {
+        ParamType::U64 | ParamType::I64 => true,
+        ParamType::Array(inner) | ParamType::Optional(inner) => contains_int64(inner),
+        ParamType::Object(fields) => fields.values().any(contains_int64),
+        _ => false,
+    }
+}
+
+fn array_of(item: &str) -> String {
+    if item.chars().all(|c| c.is_ascii_alphanumeric()) {
+        format!("{}[]", item)
+    } else {
+        format!("Array<{}>", item)
+    }
+}
+
+/// `password_manager` or `get-balance` to `PasswordManager` and `GetBalance`
+fn pascal_case(name: &str) -> String {
+    let pascal: String = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect();
+    if pascal.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("T{}", pascal)
+    } else {
+        pascal
+    }
+}
+
+/// A property or method name, quoted unless it is a plain identifier
+fn property_name(name: &str) -> String {
+    let is_identifier = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if is_identifier {
+        name.to_string()
+    } else {
+        string_literal(name)
+    }
+}
+
+fn string_literal(value: &str) -> String {
+    serde_json::Value::String(value.to_string()).to_string()
+}
+
+fn write_doc(out: &mut String, indent: &str, text: &str) {
+    let text = text.trim();
+    if !text.is_empty() {
+        writeln!(out, "{}/** {} */", indent, text.replace("*/", "*\\/")).unwrap();
+    }
+}
+
+/// The method's description, with a `@deprecated` tag for deprecated methods so
+/// editors strike calls to it through
+fn write_method_doc(out: &mut String, definition: &MethodDefinition) {
+    let Some(deprecation) = &definition.deprecated else {
+        write_doc(out, "  ", &definition.description);
+        return;
+    };
+    let mut tag = String::new();
+    if let Some(since) = &deprecation.since {
+        write!(tag, " Since {}.", since).unwrap();
+    }
+    if let Some(message) = &deprecation.message {
+        write!(tag, " {}", message.trim()).unwrap();
+    }
+    if let Some(use_instead) = &deprecation.use_instead {
+        write!(tag, " Use `{}` instead.", use_instead).unwrap();
+    }
+    out.push_str("  /**\n");
+    for line in [
+        definition.description.trim(),
+        &format!("@deprecated{}", tag),
+    ] {
+        if !line.is_empty() {
+            writeln!(out, "   * {}", line.replace("*/", "*\\/")).unwrap();
+        }
+    }
+    out.push_str("   */\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typescript_bindings() {
+        let toml = crate::test_utils::manifest_toml("password-manager", "0.1.0").replace(
+            "[api.greet.returns]",
+            r#"[api.greet.params]
+name = { type = "string", description = "The name to greet." }
+times = { type = "u32", description = "How often." }
+tags = { type = "array<string>", description = "Tags." }
+
+[api.greet.returns]"#,
+        );
+        let manifest = TappletManifest::from_toml_str(&toml).unwrap();
+        let ts = typescript(&manifest);
+
+        assert!(ts.contains(
+            "export interface GreetParams {\n  \
+             /** The name to greet. */\n  name: string;\n  \
+             /** Tags. */\n  tags: string[];\n  \
+             /** How often. */\n  times: number;\n}\n"
+        ));
+        assert!(ts.contains("export type GreetResult = string;"));
+        assert!(ts.contains("export class PasswordManagerClient {"));
+        assert!(ts.contains("static readonly tapplet = \"password-manager\";"));
+        assert!(ts.contains("  greet(params: GreetParams): Promise<GreetResult> {\n"));
+
+        let mut manifest = manifest;
+        manifest
+            .api
+            .method_definitions
+            .get_mut("greet")
+            .unwrap()
+            .deprecated = Some(crate::model::MethodDeprecation {
+            since: Some("0.3.0".to_string()),
+            message: Some("Greetings are now localized.".to_string()),
+            use_instead: Some("greet_localized".to_string()),
+        });
+        assert!(typescript(&manifest).contains(
+            "  /**\n   * Returns a greeting message.\n   \
+             * @deprecated Since 0.3.0. Greetings are now localized. Use `greet_localized` instead.\n   \
+             */\n  greet(params"
+        ));
+    }
+
+    #[test]
+    fn test_typescript_type() {
+        let ts = |manifest_type: &str| typescript_type(&manifest_type.parse().unwrap());
+        assert_eq!(ts("u64"), "string");
+        assert_eq!(ts("u32"), "number");
+        assert_eq!(ts("bytes"), "string | number[]");
+        assert_eq!(ts("transaction"), "unknown");
+        assert_eq!(ts("object[]"), "Array<Record<string, unknown>>");
+        assert_eq!(
+            ts("object{name: string, age: optional<u64>}"),
+            "{ age?: string | null; name: string }"
+        );
+    }
+
+    #[test]
+    fn test_typescript_result_type() {
+        let ts = |manifest_type: &str| typescript_result_type(&manifest_type.parse().unwrap());
+        assert_eq!(ts("u64"), "number");
+        assert_eq!(ts("bytes"), "string");
+        assert_eq!(
+            ts("object{key: bytes, keys: bytes[], amount: optional<i64>}"),
+            "{ amount?: number | null; key: string; keys: string[] }"
+        );
+    }
+
+    #[test]
+    fn test_typescript_bindings_of_64_bit_integers() {
+        let toml = crate::test_utils::manifest_toml("wallet", "0.1.0").replace(
+            "[api.greet.returns]\ntype = \"string\"",
+            r#"[api.greet.params]
+amount = { type = "u64", description = "Amount." }
+
+[api.greet.returns]
+type = "u64""#,
+        );
+        let manifest = TappletManifest::from_toml_str(&toml).unwrap();
+        let ts = typescript(&manifest);
+
+        assert!(ts.contains("  /** Amount. */\n  amount: string;\n"));
+        assert!(ts.contains(&format!(
+            "/** A greeting message.{} */\nexport type GreetResult = number;",
+            INT64_RESULT_NOTE
+        )));
+    }
+}
@@ -0,0 +1,627 @@
+//! Fuel metering for engines that can't stop running code from outside.
+//!
+//! wasmer has no way to interrupt a call, so [`instrument`] rewrites a module
+//! before it is compiled: each block charges the instructions it runs against a
+//! fuel counter, a global the host resets before every call, and once the
+//! counter runs out the module asks the host for more through an import from
+//! [`METER_MODULE`]. That is where the host stops a call that was cancelled,
+//! timed out or used up its budget, within [`FUEL_SLICE`] instructions.
+//! `memory.grow` asks the host first too, which refuses to grow the memory
+//! past the call's limit. A second global counts the frames a call may still
+//! enter; a function entered when it is 0 traps.
+
+use wasm_encoder::reencode::{self, Reencode};
+use wasm_encoder::{
+    BlockType, CodeSection, ConstExpr, EntityType, ExportKind, ExportSection, Function,
+    GlobalSection, GlobalType, ImportSection, Instruction, SectionId, TypeSection, ValType,
+};
+use wasmparser::{FunctionBody, Operator, Parser, Payload, TypeRef};
+
+use super::CallLimits;
+use crate::cancel::CancellationToken;
+use crate::host::HostError;
+
+/// Module the functions of the instrumentation are imported from
+pub(super) const METER_MODULE: &str = "minotari:meter";
+
+/// Export of the fuel left in the current slice
+pub(super) const FUEL_EXPORT: &str = "minotari:fuel";
+
+/// Export of the frames the call may still enter
+pub(super) const DEPTH_EXPORT: &str = "minotari:depth";
+
+/// Instructions a module runs between two checks of the host
+const FUEL_SLICE: u64 = 100_000;
+
+/// Changes whenever [`instrument`] does, so modules compiled before aren't loaded
+pub(super) const VERSION: u32 = 3;
+
+/// Size of a page of WASM memory, in bytes
+const PAGE_SIZE: u64 = 64 * 1024;
+
+/// A function the instrumentation imports from [`METER_MODULE`]
+struct MeterImport {
+    name: &'static str,
+    params: &'static [ValType],
+    results: &'static [ValType],
+}
+
+const IMPORTS: &[MeterImport] = &[
+    MeterImport {
+        name: "refill",
+        params: &[],
+        results: &[ValType::I64],
+    },
+    MeterImport {
+        name: "grow",
+        params: &[ValType::I32, ValType::I32],
+        results: &[ValType::I32],
+    },
+    MeterImport {
+        name: "stack_overflow",
+        params: &[],
+        results: &[],
+    },
+];
+
+/// Position of the `refill` import in [`IMPORTS`]
+const REFILL: u32 = 0;
+/// Position of the `grow` import in [`IMPORTS`]
+const GROW: u32 = 1;
+/// Position of the `stack_overflow` import in [`IMPORTS`]
+const STACK_OVERFLOW: u32 = 2;
+
+/// Functions the instrumentation imports; the module's own functions come
+/// after them, so their indices grow by this much
+pub(super) const ADDED_FUNCTIONS: u32 = IMPORTS.len() as u32;
+
+/// Rewrite `wasm`, a binary module, to charge fuel as it runs and to ask before
+/// it grows its memory
+pub(super) fn instrument(wasm: &[u8]) -> Result<Vec<u8>, HostError> {
+    let mut instrumenter = Instrumenter::new(wasm)?;
+    let mut module = wasm_encoder::Module::new();
+    instrumenter
+        .parse_core_module(&mut module, Parser::new(0), wasm)
+        .map_err(|e| HostError::WasmCompileError(e.to_string()))?;
+    Ok(module.finish())
+}
+
+/// What the functions of the instrumentation answer with, kept by the instance
+pub(super) struct Meter {
+    cancel: Option<CancellationToken>,
+    limits: CallLimits,
+    /// Fuel the calls may still be given
+    remaining: u64,
+    /// Fuel the calls were given, less what they left
+    granted: u64,
+    /// Whether the call used up its budget
+    exhausted: bool,
+    /// Whether the call's memory would have grown past its limit
+    memory_denied: bool,
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self {
+            cancel: None,
+            limits: CallLimits::default(),
+            remaining: u64::MAX,
+            granted: 0,
+            exhausted: false,
+            memory_denied: false,
+        }
+    }
+}
+
+impl Meter {
+    /// Limit the calls from now on, which start with a full budget
+    pub fn set_limits(&mut self, limits: CallLimits) {
+        self.limits = limits;
+        self.remaining = limits.fuel.unwrap_or(u64::MAX);
+        self.granted = 0;
+    }
+
+    pub fn start_call(&mut self) {
+        self.exhausted = false;
+        self.memory_denied = false;
+    }
+
+    /// Take back the fuel `left` in the module's counter after a call
+    pub fn end_call(&mut self, left: i64) {
+        let left = left.max(0) as u64;
+        self.remaining = self.remaining.saturating_add(left);
+        self.granted -= left.min(self.granted);
+    }
+
+    /// Fuel the calls used since the limits were set
+    pub fn fuel_used(&self) -> u64 {
+        self.granted
+    }
+
+    /// Frames a call may enter, what a call starts the depth counter at
+    pub fn depth(&self) -> i32 {
+        self.limits
+            .stack
+            .map_or(i32::MAX, |stack| stack.min(i32::MAX as usize) as i32)
+    }
+
+    /// Why a failed call of `method` failed, if the meter stopped it
+    pub fn call_error(&self, method: &str) -> Option<HostError> {
+        if self.is_cancelled() {
+            Some(HostError::Cancelled(method.to_string()))
+        } else if self.exhausted {
+            Some(HostError::ExecutionBudgetExceeded(method.to_string()))
+        } else if self.memory_denied {
+            Some(HostError::MemoryLimitExceeded(method.to_string()))
+        } else {
+            None
+        }
+    }
+
+    pub fn set_cancellation(&mut self, token: Option<CancellationToken>) {
+        self.cancel = token;
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// The next slice of fuel, or why the call has to stop
+    pub fn refill(&mut self) -> Result<i64, &'static str> {
+        if self.is_cancelled() {
+            return Err("call cancelled");
+        }
+        if self.remaining == 0 {
+            self.exhausted = true;
+            return Err("execution budget exceeded");
+        }
+        let slice = self.remaining.min(FUEL_SLICE);
+        self.remaining -= slice;
+        self.granted += slice;
+        Ok(slice as i64)
+    }
+
+    /// The pages to grow a memory of `pages` by: `delta`, or, past the limit,
+    /// more than any memory has, so `memory.grow` fails
+    pub fn grow(&mut self, delta: u32, pages: u32) -> u32 {
+        if let Some(limit) = self.limits.memory
+            && (u64::from(pages) + u64::from(delta)) * PAGE_SIZE > limit
+        {
+            self.memory_denied = true;
+            return u32::MAX;
+        }
+        delta
+    }
+}
+
+/// Rewrites a module, see the [module docs](self)
+struct Instrumenter {
+    /// Types of the module before it was instrumented
+    types: u32,
+    /// Functions the module imports before it was instrumented
+    imported_functions: u32,
+    /// Globals, imported or not, of the module before it was instrumented
+    globals: u32,
+    /// Sections the module doesn't have, added when their place comes up
+    missing: Vec<SectionId>,
+}
+
+impl Instrumenter {
+    fn new(wasm: &[u8]) -> Result<Self, HostError> {
+        let compile_error =
+            |e: wasmparser::BinaryReaderError| HostError::WasmCompileError(e.to_string());
+        let mut instrumenter = Self {
+            types: 0,
+            imported_functions: 0,
+            globals: 0,
+            missing: vec![
+                SectionId::Type,
+                SectionId::Import,
+                SectionId::Global,
+                SectionId::Export,
+            ],
+        };
+        for payload in Parser::new(0).parse_all(wasm) {
+            match payload.map_err(compile_error)? {
+                Payload::TypeSection(section) => {
+                    instrumenter.missing.retain(|id| *id != SectionId::Type);
+                    for group in section {
+                        instrumenter.types += group.map_err(compile_error)?.types().len() as u32;
+                    }
+                }
+                Payload::ImportSection(section) => {
+                    instrumenter.missing.retain(|id| *id != SectionId::Import);
+                    for import in section {
+                        let import = import.map_err(compile_error)?;
+                        if import.module == METER_MODULE {
+                            return Err(reserved(import.module));
+                        }
+                        match import.ty {
+                            TypeRef::Func(_) => instrumenter.imported_functions += 1,
+                            TypeRef::Global(_) => instrumenter.globals += 1,
+                            TypeRef::Memory(memory) if memory.memory64 => {
+                                return Err(memory64());
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Payload::MemorySection(section) => {
+                    for memory in section {
+                        if memory.map_err(compile_error)?.memory64 {
+                            return Err(memory64());
+                        }
+                    }
+                }
+                Payload::GlobalSection(section) => {
+                    instrumenter.missing.retain(|id| *id != SectionId::Global);
+                    instrumenter.globals += section.count();
+                }
+                Payload::ExportSection(section) => {
+                    instrumenter.missing.retain(|id| *id != SectionId::Export);
+                    for export in section {
+                        let export = export.map_err(compile_error)?;
+                        if export.name.starts_with("minotari:") {
+                            return Err(reserved(export.name));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(instrumenter)
+    }
+
+    /// Index of the fuel global
+    fn fuel(&self) -> u32 {
+        self.globals
+    }
+
+    /// Index of the depth global
+    fn depth(&self) -> u32 {
+        self.globals + 1
+    }
+
+    fn add_types(&self, types: &mut TypeSection) {
+        for import in IMPORTS {
+            types.ty().function(
+                import.params.iter().copied(),
+                import.results.iter().copied(),
+            );
+        }
+    }
+
+    fn add_imports(&self, imports: &mut ImportSection) {
+        for (i, import) in (0..).zip(IMPORTS) {
+            imports.import(
+                METER_MODULE,
+                import.name,
+                EntityType::Function(self.types + i),
+            );
+        }
+    }
+
+    fn add_globals(&self, globals: &mut GlobalSection) {
+        let global = |val_type| GlobalType {
+            val_type,
+            mutable: true,
+            shared: false,
+        };
+        globals.global(global(ValType::I64), &ConstExpr::i64_const(0));
+        globals.global(global(ValType::I32), &ConstExpr::i32_const(i32::MAX));
+    }
+
+    fn add_exports(&self, exports: &mut ExportSection) {
+        exports.export(FUEL_EXPORT, ExportKind::Global, self.fuel());
+        exports.export(DEPTH_EXPORT, ExportKind::Global, self.depth());
+    }
+
+    /// Enter a frame, trapping if the call may enter no more
+    fn enter(&self, function: &mut Function) {
+        let depth = self.depth();
+        function
+            .instruction(&Instruction::GlobalGet(depth))
+            .instruction(&Instruction::I32Eqz)
+            .instruction(&Instruction::If(BlockType::Empty))
+            .instruction(&Instruction::Call(self.imported_functions + STACK_OVERFLOW))
+            .instruction(&Instruction::End)
+            .instruction(&Instruction::GlobalGet(depth))
+            .instruction(&Instruction::I32Const(1))
+            .instruction(&Instruction::I32Sub)
+            .instruction(&Instruction::GlobalSet(depth));
+    }
+
+    /// Leave a frame
+    fn leave(&self, function: &mut Function) {
+        let depth = self.depth();
+        function
+            .instruction(&Instruction::GlobalGet(depth))
+            .instruction(&Instruction::I32Const(1))
+            .instruction(&Instruction::I32Add)
+            .instruction(&Instruction::GlobalSet(depth));
+    }
+
+    /// Charge `cost` instructions, asking the host for more fuel once it runs out
+    fn charge(&self, function: &mut Function, cost: u64) {
+        let fuel = self.fuel();
+        function
+            .instruction(&Instruction::GlobalGet(fuel))
+            .instruction(&Instruction::I64Const(cost as i64))
+            .instruction(&Instruction::I64Sub)
+            .instruction(&Instruction::GlobalSet(fuel))
+            .instruction(&Instruction::GlobalGet(fuel))
+            .instruction(&Instruction::I64Const(0))
+            .instruction(&Instruction::I64LtS)
+            .instruction(&Instruction::If(BlockType::Empty))
+            .instruction(&Instruction::Call(self.imported_functions + REFILL))
+            .instruction(&Instruction::GlobalGet(fuel))
+            .instruction(&Instruction::I64Add)
+            .instruction(&Instruction::GlobalSet(fuel))
+            .instruction(&Instruction::End);
+    }
+}
+
+fn reserved(name: &str) -> HostError {
+    HostError::WasmCompileError(format!("{} is reserved for the host", name))
+}
+
+fn memory64() -> HostError {
+    HostError::WasmCompileError("64-bit memories aren't supported".to_string())
+}
+
+/// Whether `operator` may leave the straight line of code before it, so the
+/// instructions up to it are charged first
+fn ends_block(operator: &Operator) -> bool {
+    matches!(
+        operator,
+        Operator::Loop { .. }
+            | Operator::End
+            | Operator::If { .. }
+            | Operator::Else
+            | Operator::Br { .. }
+            | Operator::BrIf { .. }
+            | Operator::BrTable { .. }
+            | Operator::BrOnNull { .. }
+            | Operator::BrOnNonNull { .. }
+            | Operator::BrOnCast { .. }
+            | Operator::BrOnCastFail { .. }
+            | Operator::Unreachable
+            | Operator::Return
+            | Operator::Call { .. }
+            | Operator::CallIndirect { .. }
+            | Operator::CallRef { .. }
+            | Operator::ReturnCall { .. }
+            | Operator::ReturnCallIndirect { .. }
+            | Operator::ReturnCallRef { .. }
+            | Operator::Try { .. }
+            | Operator::TryTable { .. }
+            | Operator::Catch { .. }
+            | Operator::CatchAll
+            | Operator::Delegate { .. }
+            | Operator::Throw { .. }
+            | Operator::ThrowRef
+            | Operator::Rethrow { .. }
+    )
+}
+
+/// Where a section goes in a module, which isn't the order of the ids
+fn position(id: SectionId) -> u8 {
+    match id {
+        SectionId::Custom => 0,
+        SectionId::Type => 1,
+        SectionId::Import => 2,
+        SectionId::Function => 3,
+        SectionId::Table => 4,
+        SectionId::Memory => 5,
+        SectionId::Tag => 6,
+        SectionId::Global => 7,
+        SectionId::Export => 8,
+        SectionId::Start => 9,
+        SectionId::Element => 10,
+        SectionId::DataCount => 11,
+        SectionId::Code => 12,
+        SectionId::Data => 13,
+    }
+}
+
+impl Reencode for Instrumenter {
+    type Error = std::convert::Infallible;
+
+    fn function_index(&mut self, func: u32) -> u32 {
+        if func < self.imported_functions {
+            func
+        } else {
+            func + ADDED_FUNCTIONS
+        }
+    }
+
+    fn parse_type_section(
+        &mut self,
+        types: &mut TypeSection,
+        section: wasmparser::TypeSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_type_section(self, types, section)?;
+        self.add_types(types);
+        Ok(())
+    }
+
+    fn parse_import_section(
+        &mut self,
+        imports: &mut ImportSection,
+        section: wasmparser::ImportSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_import_section(self, imports, section)?;
+        self.add_imports(imports);
+        Ok(())
+    }
+
+    fn parse_global_section(
+        &mut self,
+        globals: &mut GlobalSection,
+        section: wasmparser::GlobalSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_global_section(self, globals, section)?;
+        self.add_globals(globals);
+        Ok(())
+    }
+
+    fn parse_export_section(
+        &mut self,
+        exports: &mut ExportSection,
+        section: wasmparser::ExportSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_export_section(self, exports, section)?;
+        self.add_exports(exports);
+        Ok(())
+    }
+
+    fn intersperse_section_hook(
+        &mut self,
+        module: &mut wasm_encoder::Module,
+        _after: Option<SectionId>,
+        before: Option<SectionId>,
+    ) -> Result<(), reencode::Error> {
+        while let Some(&id) = self.missing.first()
+            && before.is_none_or(|before| position(before) > position(id))
+        {
+            self.missing.remove(0);
+            match id {
+                SectionId::Type => {
+                    let mut types = TypeSection::new();
+                    self.add_types(&mut types);
+                    module.section(&types);
+                }
+                SectionId::Import => {
+                    let mut imports = ImportSection::new();
+                    self.add_imports(&mut imports);
+                    module.section(&imports);
+                }
+                SectionId::Global => {
+                    let mut globals = GlobalSection::new();
+                    self.add_globals(&mut globals);
+                    module.section(&globals);
+                }
+                _ => {
+                    let mut exports = ExportSection::new();
+                    self.add_exports(&mut exports);
+                    module.section(&exports);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_function_body(
+        &mut self,
+        code: &mut CodeSection,
+        body: FunctionBody<'_>,
+    ) -> Result<(), reencode::Error> {
+        let mut function = self.new_function_with_parsed_locals(&body)?;
+        self.enter(&mut function);
+        let mut reader = body.get_operators_reader()?;
+        let mut cost = 0;
+        // Blocks open at the operator, the function's own closes with its last `end`
+        let mut open = 0;
+        while !reader.eof() {
+            let operator = reader.read()?;
+            cost += 1;
+            if ends_block(&operator) {
+                self.charge(&mut function, cost);
+                cost = 0;
+            }
+            match operator {
+                Operator::Block { .. }
+                | Operator::Loop { .. }
+                | Operator::If { .. }
+                | Operator::Try { .. }
+                | Operator::TryTable { .. } => open += 1,
+                Operator::Delegate { .. } => open -= 1,
+                Operator::End if open > 0 => open -= 1,
+                Operator::End
+                | Operator::Return
+                | Operator::ReturnCall { .. }
+                | Operator::ReturnCallIndirect { .. }
+                | Operator::ReturnCallRef { .. } => self.leave(&mut function),
+                _ => {}
+            }
+            if let Operator::MemoryGrow { mem } = operator {
+                // The host turns the delta into one that fails past the limit
+                function
+                    .instruction(&Instruction::MemorySize(mem))
+                    .instruction(&Instruction::Call(self.imported_functions + GROW))
+                    .instruction(&Instruction::MemoryGrow(mem));
+                continue;
+            }
+            function.instruction(&self.instruction(operator)?);
+        }
+        code.function(&function);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instrument() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "minotari" "minotari_log" (func $log (param i32 i32)))
+                (func $inner (result i32) (i32.const 1))
+                (func (export "outer") (result i32) (call $inner))
+                (start $inner_start)
+                (func $inner_start))"#,
+        )
+        .unwrap();
+        let instrumented = instrument(&wasm).unwrap();
+        wasmparser::Validator::new()
+            .validate_all(&instrumented)
+            .unwrap();
+
+        // The module's own functions come after the imports of the instrumentation
+        let mut exports = Vec::new();
+        let mut imports = Vec::new();
+        for payload in Parser::new(0).parse_all(&instrumented) {
+            match payload.unwrap() {
+                Payload::ImportSection(section) => {
+                    for import in section {
+                        let import = import.unwrap();
+                        imports.push(format!("{}.{}", import.module, import.name));
+                    }
+                }
+                Payload::ExportSection(section) => {
+                    for export in section {
+                        let export = export.unwrap();
+                        exports.push((export.name.to_string(), export.index));
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(
+            imports,
+            [
+                "minotari.minotari_log",
+                "minotari:meter.refill",
+                "minotari:meter.grow",
+                "minotari:meter.stack_overflow"
+            ]
+        );
+        assert_eq!(
+            exports,
+            [
+                ("outer".to_string(), 5),
+                (FUEL_EXPORT.to_string(), 0),
+                (DEPTH_EXPORT.to_string(), 1)
+            ]
+        );
+
+        let reserved =
+            wat::parse_str(r#"(module (import "minotari:meter" "refill" (func)))"#).unwrap();
+        let err = instrument(&reserved).unwrap_err();
+        assert_eq!(err.code(), "WASM_COMPILE_ERROR");
+    }
+}
@@ -0,0 +1,581 @@
+//! The WASM runtime behind [`crate::host::WasmTappletHost`].
+//!
+//! The crate can be built with [wasmer](https://wasmer.io) (the `engine-wasmer`
+//! feature, which `host` enables) or [wasmtime](https://wasmtime.dev) (the
+//! `engine-wasmtime` feature), so embedders that already link one runtime don't
+//! need a second. Hosts use [`default_engine`] unless given one explicitly with
+//! `WasmTappletHost::from_bytes_with_engine`.
+//!
+//! Compiling a module is the slowest part of starting a WASM tapplet.
+//! [`precompile_file`] compiles it ahead of time into an artifact that
+//! `WasmTappletHost::from_precompiled` loads without compiling. Artifacts only
+//! work with the engine, engine version and platform in their
+//! [`WasmEngine::artifact_key`].
+//!
+//! Engines also compile modules in the WebAssembly text format, so test fixtures
+//! and host ABI experiments can be written as readable `.wat` files.
+//!
+//! Modules call back into the host through functions they import from
+//! [`HOST_MODULE`], which hosts provide as [`HostImports`].
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use crate::cancel::CancellationToken;
+use crate::checksum::sha256_hex;
+use crate::host::HostError;
+use crate::rate_limit::RateLimiter;
+use crate::wasi::{GuestMemory, WasiOptions};
+
+#[cfg(feature = "engine-wasmer")]
+mod metering;
+#[cfg(feature = "engine-wasmer")]
+mod wasmer_engine;
+#[cfg(feature = "engine-wasmtime")]
+mod wasmtime_engine;
+
+#[cfg(feature = "engine-wasmer")]
+pub use wasmer_engine::WasmerEngine;
+#[cfg(feature = "engine-wasmtime")]
+pub use wasmtime_engine::WasmtimeEngine;
+
+#[cfg(not(any(feature = "engine-wasmer", feature = "engine-wasmtime")))]
+compile_error!("enable the `host` (wasmer) or `engine-wasmtime` feature to get a WASM engine");
+
+/// A value passed to or returned from a WASM function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WasmValue {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+/// Type of a param or result of a WASM function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WasmType {
+    I32,
+    I64,
+    F32,
+    F64,
+    V128,
+    /// `funcref`, `externref` and the other reference types
+    Ref,
+}
+
+impl WasmType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WasmType::I32 => "i32",
+            WasmType::I64 => "i64",
+            WasmType::F32 => "f32",
+            WasmType::F64 => "f64",
+            WasmType::V128 => "v128",
+            WasmType::Ref => "ref",
+        }
+    }
+
+    /// Whether values of the type can be passed as a [`WasmValue`]
+    pub fn is_number(&self) -> bool {
+        matches!(
+            self,
+            WasmType::I32 | WasmType::I64 | WasmType::F32 | WasmType::F64
+        )
+    }
+}
+
+impl std::fmt::Display for WasmType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A function a module exports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionExport {
+    pub name: String,
+    pub params: Vec<WasmType>,
+    pub results: Vec<WasmType>,
+}
+
+impl std::fmt::Display for FunctionExport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |types: &[WasmType]| {
+            types
+                .iter()
+                .map(WasmType::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(
+            f,
+            "{}({}) -> ({})",
+            self.name,
+            join(&self.params),
+            join(&self.results)
+        )
+    }
+}
+
+/// Module that WASM tapplets import host functions like `minotari_log` from
+pub const HOST_MODULE: &str = "minotari";
+
+/// Body of a [`HostFunction`], called with the memory of the calling instance and
+/// the arguments. An error traps the call with its message.
+pub type HostFunctionBody =
+    dyn Fn(&mut dyn GuestMemory, &[WasmValue]) -> Result<Vec<WasmValue>, String> + Send + Sync;
+
+/// A function modules can import from [`HOST_MODULE`]
+#[derive(Clone)]
+pub struct HostFunction {
+    pub name: &'static str,
+    pub params: Vec<WasmType>,
+    pub results: Vec<WasmType>,
+    pub body: Arc<HostFunctionBody>,
+}
+
+/// The host functions an instance may import. A module importing a function from
+/// [`HOST_MODULE`] that isn't provided, or with another signature, fails to
+/// instantiate.
+#[derive(Clone, Default)]
+pub struct HostImports {
+    functions: Vec<HostFunction>,
+}
+
+impl HostImports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Provide `name`, replacing a function of that name provided before
+    pub fn with_function(
+        mut self,
+        name: &'static str,
+        params: &[WasmType],
+        results: &[WasmType],
+        body: impl Fn(&mut dyn GuestMemory, &[WasmValue]) -> Result<Vec<WasmValue>, String>
+        + Send
+        + Sync
+        + 'static,
+    ) -> Self {
+        self.functions.retain(|function| function.name != name);
+        self.functions.push(HostFunction {
+            name,
+            params: params.to_vec(),
+            results: results.to_vec(),
+            body: Arc::new(body),
+        });
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&HostFunction> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &HostFunction> {
+        self.functions.iter()
+    }
+
+    /// The function a module imports as `name` with `params` and `results`
+    fn resolve(
+        &self,
+        name: &str,
+        params: &[WasmType],
+        results: &[WasmType],
+    ) -> Result<&HostFunction, HostError> {
+        let signature = |params: &[WasmType], results: &[WasmType]| FunctionExport {
+            name: name.to_string(),
+            params: params.to_vec(),
+            results: results.to_vec(),
+        };
+        let Some(function) = self.get(name) else {
+            return Err(HostError::WasmInstantiationError(format!(
+                "module imports unknown host function {}.{}",
+                HOST_MODULE, name
+            )));
+        };
+        if function.params != params || function.results != results {
+            return Err(HostError::WasmInstantiationError(format!(
+                "module imports {}.{}, but the host provides {}",
+                HOST_MODULE,
+                signature(params, results),
+                signature(&function.params, &function.results)
+            )));
+        }
+        Ok(function)
+    }
+}
+
+/// Limits of the calls of a [`WasmInstance`], see [`WasmInstance::set_limits`].
+/// `None` doesn't limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallLimits {
+    /// Instructions the calls may run together
+    pub fuel: Option<u64>,
+    /// Bytes the module's memory may grow to. Growing it further fails, and so
+    /// does the call if the module then traps.
+    pub memory: Option<u64>,
+    /// Functions of the module that may be running at once. Engines that can't
+    /// count them say so in [`WasmEngine::limits_stack`].
+    pub stack: Option<usize>,
+}
+
+/// Compiles WASM modules
+pub trait WasmEngine: Send + Sync {
+    /// Short name of the engine, e.g. `wasmer`
+    fn name(&self) -> &'static str;
+
+    /// Version of the engine. Modules serialized by one version can't be loaded by another.
+    fn version(&self) -> String;
+
+    /// File extension of modules serialized by this engine
+    fn artifact_extension(&self) -> &'static str;
+
+    /// Whether instances enforce [`CallLimits::stack`]
+    fn limits_stack(&self) -> bool;
+
+    /// What serialized modules are tied to: the engine, its version and the
+    /// architecture and OS they were compiled for
+    fn artifact_key(&self) -> String {
+        format!(
+            "{}-{}-{}-{}",
+            self.name(),
+            self.version(),
+            std::env::consts::ARCH,
+            std::env::consts::OS
+        )
+    }
+
+    /// Compile a WASM binary, or a module in the WebAssembly text format
+    fn compile(&self, wasm_bytes: &[u8]) -> Result<Arc<dyn CompiledModule>, HostError>;
+
+    /// Load a module from the bytes [`CompiledModule::serialize`] returned.
+    ///
+    /// # Safety
+    ///
+    /// Serialized modules contain native code that runs without further checks, so
+    /// `bytes` must come from `serialize` on a trusted machine. Bytes from another
+    /// engine or engine version are rejected.
+    unsafe fn deserialize(&self, bytes: &[u8]) -> Result<Arc<dyn CompiledModule>, HostError>;
+}
+
+/// A compiled module, which can be instantiated any number of times
+pub trait CompiledModule: Send + Sync {
+    /// Native code for the module, to load again with [`WasmEngine::deserialize`]
+    fn serialize(&self) -> Result<Vec<u8>, HostError>;
+
+    /// Whether the module imports any WASI functions
+    fn imports_wasi(&self) -> bool;
+
+    /// Whether the module imports `name` from `module`
+    fn imports(&self, module: &str, name: &str) -> bool;
+
+    /// Create an instance with a fresh memory. WASI imports are provided from
+    /// `wasi` if it is set, and imports from [`HOST_MODULE`] from `host`; the
+    /// module may not import anything else.
+    fn instantiate(
+        &self,
+        wasi: Option<&WasiOptions>,
+        host: &HostImports,
+        tapplet: &str,
+    ) -> Result<Box<dyn WasmInstance>, HostError>;
+}
+
+/// A running module and its store
+pub trait WasmInstance: Send {
+    /// Whether the module exports a function called `name`
+    fn has_function(&mut self, name: &str) -> bool;
+
+    /// The functions the module exports, in the order it lists them
+    fn function_exports(&mut self) -> Vec<FunctionExport>;
+
+    /// Call the exported function `name`, failing with
+    /// [`HostError::MethodNotFound`] if there is none
+    fn call(&mut self, name: &str, args: &[WasmValue]) -> Result<Vec<WasmValue>, HostError>;
+
+    /// Read from the exported `memory`
+    fn read_memory(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), HostError>;
+
+    /// Write to the exported `memory`
+    fn write_memory(&mut self, offset: u64, data: &[u8]) -> Result<(), HostError>;
+
+    /// Size of the exported `memory` in bytes, 0 if there is none
+    fn memory_size(&mut self) -> u64;
+
+    /// Limit the calls made from now on, with a fresh budget of fuel. A call
+    /// that runs out of fuel fails with [`HostError::ExecutionBudgetExceeded`],
+    /// one that traps after its memory couldn't grow with
+    /// [`HostError::MemoryLimitExceeded`].
+    fn set_limits(&mut self, limits: CallLimits);
+
+    /// Fuel the calls used since the limits were last set. An instruction is
+    /// one unit, though engines count some, like calls, differently.
+    fn fuel_used(&mut self) -> u64;
+
+    /// Limit how often the module may call WASI functions, which then return
+    /// `EAGAIN`. Modules without WASI imports don't call the host.
+    fn set_rate_limiter(&mut self, limiter: RateLimiter);
+
+    /// Fail calls with [`HostError::Cancelled`] once `token` is cancelled, until
+    /// it is unset. Running code is interrupted, as are host functions it calls
+    /// once they return.
+    fn set_cancellation(&mut self, token: Option<CancellationToken>);
+}
+
+/// The engine hosts use by default: wasmtime if the `engine-wasmtime` feature is
+/// enabled, wasmer otherwise. It is created once and shared.
+pub fn default_engine() -> Arc<dyn WasmEngine> {
+    static ENGINE: OnceLock<Arc<dyn WasmEngine>> = OnceLock::new();
+    ENGINE
+        .get_or_init(|| {
+            #[cfg(feature = "engine-wasmtime")]
+            let engine: Arc<dyn WasmEngine> = Arc::new(WasmtimeEngine::new());
+            #[cfg(not(feature = "engine-wasmtime"))]
+            let engine: Arc<dyn WasmEngine> = Arc::new(WasmerEngine::new());
+            engine
+        })
+        .clone()
+}
+
+/// Start of every artifact written by [`precompile_file`]
+const PRECOMPILED_MAGIC: &[u8] = b"tapplet-precompiled\n";
+
+/// Where [`precompile_file`] writes the artifact of the module whose SHA-256 is
+/// `wasm_sha256`: in `directory`, named after the hash and `engine`'s artifact
+/// key, e.g. `<sha256>.wasmer-4.4.0+meter.3-x86_64-linux.wasmu`
+pub fn precompiled_path(directory: &Path, wasm_sha256: &str, engine: &dyn WasmEngine) -> PathBuf {
+    directory.join(format!(
+        "{}.{}.{}",
+        wasm_sha256,
+        engine.artifact_key(),
+        engine.artifact_extension()
+    ))
+}
+
+/// Compile the module at `wasm_path` with `engine` and write it to its
+/// [`precompiled_path`] in `directory`, returning that path.
+///
+/// Artifacts are native code, so `directory` must only be written by the host,
+/// like the module cache; never next to files that come from a package.
+pub fn precompile_file(
+    wasm_path: &Path,
+    directory: &Path,
+    engine: &dyn WasmEngine,
+) -> Result<PathBuf, HostError> {
+    let wasm_bytes = std::fs::read(wasm_path)?;
+    let module = engine.compile(&wasm_bytes)?;
+    let mut artifact = PRECOMPILED_MAGIC.to_vec();
+    artifact.extend_from_slice(engine.artifact_key().as_bytes());
+    artifact.push(b'\n');
+    artifact.extend_from_slice(sha256_hex(&wasm_bytes).as_bytes());
+    artifact.push(b'\n');
+    artifact.extend(module.serialize()?);
+    let path = precompiled_path(directory, &sha256_hex(&wasm_bytes), engine);
+    std::fs::create_dir_all(directory)?;
+    std::fs::write(&path, artifact)?;
+    Ok(path)
+}
+
+/// An artifact written by [`precompile_file`]
+pub(crate) struct Precompiled<'a> {
+    /// [`WasmEngine::artifact_key`] of the engine that compiled it
+    pub key: &'a str,
+    /// SHA-256 of the WASM module it was compiled from
+    pub wasm_sha256: &'a str,
+    pub module: &'a [u8],
+}
+
+impl<'a> Precompiled<'a> {
+    pub fn parse(artifact: &'a [u8]) -> Result<Self, HostError> {
+        let invalid = || HostError::WasmLoadError("not a precompiled tapplet module".to_string());
+        let rest = artifact
+            .strip_prefix(PRECOMPILED_MAGIC)
+            .ok_or_else(invalid)?;
+        let mut parts = rest.splitn(3, |byte| *byte == b'\n');
+        let mut text = || {
+            parts
+                .next()
+                .and_then(|part| std::str::from_utf8(part).ok())
+                .ok_or_else(invalid)
+        };
+        let key = text()?;
+        let wasm_sha256 = text()?;
+        let module = parts.next().ok_or_else(invalid)?;
+        Ok(Self {
+            key,
+            wasm_sha256,
+            module,
+        })
+    }
+
+    /// Load the module with `engine`, which must be the engine that compiled it.
+    ///
+    /// # Safety
+    ///
+    /// See [`WasmEngine::deserialize`].
+    pub unsafe fn load(
+        &self,
+        engine: &dyn WasmEngine,
+    ) -> Result<Arc<dyn CompiledModule>, HostError> {
+        let key = engine.artifact_key();
+        if self.key != key {
+            return Err(HostError::WasmLoadError(format!(
+                "module was precompiled for {}, but this host runs {}",
+                self.key, key
+            )));
+        }
+        // SAFETY: upheld by the caller
+        unsafe { engine.deserialize(self.module) }
+    }
+}
+
+/// The binary module in `bytes`, converted first if they are WebAssembly text
+fn module_binary(bytes: &[u8]) -> Result<Cow<'_, [u8]>, HostError> {
+    wat::parse_bytes(bytes).map_err(|e| HostError::WasmCompileError(e.to_string()))
+}
+
+fn missing_memory() -> HostError {
+    HostError::WasmLoadError("module does not export its memory".to_string())
+}
+
+fn memory_error(e: impl std::fmt::Display) -> HostError {
+    HostError::ExecutionError(format!("Invalid guest memory access: {}", e))
+}
+
+fn unsupported_value(value: impl std::fmt::Debug) -> HostError {
+    HostError::ExecutionError(format!("Unsupported WASM value type: {:?}", value))
+}
+
+/// A function on the guest's call stack when a call trapped
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct GuestFrame {
+    /// Demangled name from the module's `name` section
+    function: Option<String>,
+    func_index: u32,
+    /// Offset of the instruction in the module. wasmer runs a metered copy of
+    /// the module, see [`metering`], so only wasmtime knows it.
+    offset: Option<usize>,
+    /// `file:line` from the module's DWARF debug info, only read by wasmtime
+    location: Option<String>,
+}
+
+impl std::fmt::Display for GuestFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.function {
+            Some(function) => write!(f, "{} (func {}", function, self.func_index)?,
+            None => write!(f, "<unnamed> (func {}", self.func_index)?,
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " @ 0x{:x}", offset)?;
+        }
+        write!(f, ")")?;
+        if let Some(location) = &self.location {
+            write!(f, " at {}", location)?;
+        }
+        Ok(())
+    }
+}
+
+/// The error of a call that trapped, with the guest's stack trace, innermost
+/// frame first
+fn trap_error(message: &str, frames: &[GuestFrame]) -> HostError {
+    let mut detail = message.to_string();
+    if !frames.is_empty() {
+        detail.push_str("\nguest stack trace:");
+        for (i, frame) in frames.iter().enumerate() {
+            detail.push_str(&format!("\n  {}: {}", i, frame));
+        }
+    }
+    HostError::ExecutionError(detail)
+}
+
+/// Rust symbols in the name section are mangled; other names are kept as they are
+fn demangle(name: &str) -> String {
+    format!("{:#}", rustc_demangle::demangle(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trap_stack_trace() {
+        let wat = r#"(module
+            (func $validate_amount unreachable)
+            (func (export "transfer") (call $validate_amount)))"#;
+        for engine in engines() {
+            let module = engine.compile(wat.as_bytes()).unwrap();
+            let mut instance = module
+                .instantiate(None, &HostImports::new(), "ledger")
+                .unwrap();
+            let err = instance.call("transfer", &[]).unwrap_err();
+            let HostError::ExecutionError(detail) = err else {
+                panic!("{}: {:?}", engine.name(), err);
+            };
+            let (message, trace) = detail.split_once("\nguest stack trace:").unwrap();
+            assert!(message.contains("unreachable"), "{}", detail);
+            let frames: Vec<_> = trace.lines().skip(1).collect();
+            assert!(
+                frames[0].starts_with("  0: validate_amount (func 0"),
+                "{}",
+                detail
+            );
+            assert!(
+                frames[1].starts_with("  1: <unnamed> (func 1"),
+                "{}",
+                detail
+            );
+        }
+    }
+
+    fn engines() -> Vec<Arc<dyn WasmEngine>> {
+        vec![
+            #[cfg(feature = "engine-wasmer")]
+            Arc::new(WasmerEngine::new()),
+            #[cfg(feature = "engine-wasmtime")]
+            Arc::new(WasmtimeEngine::new()),
+        ]
+    }
+
+    #[test]
+    fn test_engines_compile_serialize_and_call() {
+        let wat = r#"(module
+            (memory (export "memory") 1)
+            (func (export "add") (param i64 i64) (result i64)
+                (i64.add (local.get 0) (local.get 1)))
+            (func (export "first_byte") (result i32)
+                (i32.load8_u (i32.const 64))))"#;
+        for engine in engines() {
+            let module = engine.compile(wat.as_bytes()).unwrap();
+            assert!(!module.imports_wasi());
+            let serialized = module.serialize().unwrap();
+            let module = unsafe { engine.deserialize(&serialized) }.unwrap();
+            let mut instance = module
+                .instantiate(None, &HostImports::new(), "adder")
+                .unwrap();
+
+            assert!(instance.has_function("add"));
+            assert!(!instance.has_function("sub"));
+            let sum = instance
+                .call("add", &[WasmValue::I64(40), WasmValue::I64(2)])
+                .unwrap();
+            assert_eq!(sum, vec![WasmValue::I64(42)], "{}", engine.name());
+            assert_eq!(
+                instance.call("sub", &[]).unwrap_err().code(),
+                "METHOD_NOT_FOUND"
+            );
+
+            instance.write_memory(64, &[7]).unwrap();
+            let mut byte = [0];
+            instance.read_memory(64, &mut byte).unwrap();
+            assert_eq!(byte, [7]);
+            assert_eq!(
+                instance.call("first_byte", &[]).unwrap(),
+                vec![WasmValue::I32(7)]
+            );
+            assert!(instance.read_memory(1 << 20, &mut byte).is_err());
+            assert!(unsafe { engine.deserialize(b"garbage") }.is_err());
+        }
+    }
+}
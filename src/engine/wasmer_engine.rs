@@ -0,0 +1,521 @@
+use std::sync::Arc;
+
+use wasmer::{
+    Engine, Function, FunctionEnv, FunctionEnvMut, Imports, Instance, Memory, MemoryView, Module,
+    RuntimeError, Store, Type, Value,
+};
+
+use super::metering::{self, ADDED_FUNCTIONS, DEPTH_EXPORT, FUEL_EXPORT, METER_MODULE, Meter};
+use super::{
+    CallLimits, CompiledModule, FunctionExport, GuestFrame, HOST_MODULE, HostImports, WasmEngine,
+    WasmInstance, WasmType, WasmValue, demangle, memory_error, missing_memory, module_binary,
+    trap_error, unsupported_value,
+};
+use crate::cancel::CancellationToken;
+use crate::host::HostError;
+use crate::rate_limit::RateLimiter;
+use crate::wasi::{self, Guest, GuestMemory, WASI_MODULE, WasiEnv, WasiOptions};
+
+impl From<wasmer::CompileError> for HostError {
+    fn from(err: wasmer::CompileError) -> Self {
+        HostError::WasmCompileError(err.to_string())
+    }
+}
+
+impl From<wasmer::InstantiationError> for HostError {
+    fn from(err: wasmer::InstantiationError) -> Self {
+        HostError::WasmInstantiationError(err.to_string())
+    }
+}
+
+impl From<wasmer::RuntimeError> for HostError {
+    fn from(err: wasmer::RuntimeError) -> Self {
+        let frames: Vec<_> = err
+            .trace()
+            .iter()
+            .map(|frame| GuestFrame {
+                function: frame.function_name().map(demangle),
+                // Index in the module as it was before it was metered
+                func_index: frame.func_index().saturating_sub(ADDED_FUNCTIONS),
+                offset: None,
+                location: None,
+            })
+            .collect();
+        trap_error(&err.message(), &frames)
+    }
+}
+
+/// Runs modules with wasmer and its Cranelift compiler. wasmer can't interrupt
+/// running code, so modules are [`metering::instrument`]ed to check on the host
+/// as they run and to count their frames.
+#[derive(Clone, Default)]
+pub struct WasmerEngine {
+    engine: Engine,
+}
+
+impl WasmerEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl WasmEngine for WasmerEngine {
+    fn name(&self) -> &'static str {
+        "wasmer"
+    }
+
+    /// The version of wasmer and of the metering compiled into modules
+    fn version(&self) -> String {
+        format!("{}+meter.{}", wasmer::VERSION, metering::VERSION)
+    }
+
+    fn artifact_extension(&self) -> &'static str {
+        "wasmu"
+    }
+
+    fn limits_stack(&self) -> bool {
+        true
+    }
+
+    fn compile(&self, wasm_bytes: &[u8]) -> Result<Arc<dyn CompiledModule>, HostError> {
+        let wasm_bytes = metering::instrument(&module_binary(wasm_bytes)?)?;
+        let module = Module::new(&self.engine, wasm_bytes)?;
+        Ok(Arc::new(WasmerModule {
+            engine: self.engine.clone(),
+            module,
+        }))
+    }
+
+    unsafe fn deserialize(&self, bytes: &[u8]) -> Result<Arc<dyn CompiledModule>, HostError> {
+        // SAFETY: upheld by the caller
+        let module = unsafe { Module::deserialize(&self.engine, bytes) }
+            .map_err(|e| HostError::WasmLoadError(e.to_string()))?;
+        Ok(Arc::new(WasmerModule {
+            engine: self.engine.clone(),
+            module,
+        }))
+    }
+}
+
+struct WasmerModule {
+    engine: Engine,
+    module: Module,
+}
+
+impl CompiledModule for WasmerModule {
+    fn serialize(&self) -> Result<Vec<u8>, HostError> {
+        let bytes = self
+            .module
+            .serialize()
+            .map_err(|e| HostError::WasmLoadError(e.to_string()))?;
+        Ok(bytes.to_vec())
+    }
+
+    fn imports_wasi(&self) -> bool {
+        self.module
+            .imports()
+            .any(|import| import.module() == WASI_MODULE)
+    }
+
+    fn imports(&self, module: &str, name: &str) -> bool {
+        self.module
+            .imports()
+            .any(|import| import.module() == module && import.name() == name)
+    }
+
+    fn instantiate(
+        &self,
+        wasi: Option<&WasiOptions>,
+        host: &HostImports,
+        tapplet: &str,
+    ) -> Result<Box<dyn WasmInstance>, HostError> {
+        let mut store = Store::new(self.engine.clone());
+        let (mut imports, wasi_env) = match wasi {
+            Some(wasi) => {
+                let (imports, env) = wasi_imports(&mut store, &self.module, wasi, tapplet);
+                (imports, Some(env))
+            }
+            None => (Imports::new(), None),
+        };
+        let meter = define_meter_functions(&mut store, &mut imports);
+        let host_env = define_host_functions(&mut store, &self.module, host, &meter, &mut imports)?;
+        let instance = Instance::new(&mut store, &self.module, &imports)?;
+        let memory = instance.exports.get_memory("memory").ok().cloned();
+        host_env.as_mut(&mut store).memory = memory.clone();
+        if let Some(env) = &wasi_env {
+            let memory = memory.ok_or_else(|| {
+                HostError::WasmInstantiationError(
+                    "WASI module does not export its memory".to_string(),
+                )
+            })?;
+            env.as_mut(&mut store).memory = Some(memory);
+        }
+        Ok(Box::new(WasmerInstance {
+            store,
+            instance,
+            wasi: wasi_env,
+            meter,
+        }))
+    }
+}
+
+struct WasmerInstance {
+    store: Store,
+    instance: Instance,
+    /// State of the WASI functions, if the module has them
+    wasi: Option<FunctionEnv<WasiState>>,
+    meter: FunctionEnv<Meter>,
+}
+
+impl WasmerInstance {
+    fn memory(&self) -> Result<Memory, HostError> {
+        self.instance
+            .exports
+            .get_memory("memory")
+            .cloned()
+            .map_err(|_| missing_memory())
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.meter.as_ref(&self.store).is_cancelled()
+    }
+
+    /// Let the next call enter as many frames as its limit allows
+    fn reset_depth(&mut self) -> Result<(), HostError> {
+        let depth = self.meter.as_ref(&self.store).depth();
+        self.instance
+            .exports
+            .get_global(DEPTH_EXPORT)
+            .map_err(|e| HostError::ExecutionError(e.to_string()))?
+            .set(&mut self.store, Value::I32(depth))
+            .map_err(|e| HostError::ExecutionError(e.to_string()))
+    }
+
+    /// Empty the module's fuel counter, so the next call's first block asks the
+    /// host for fuel, and return what was left in it
+    fn take_fuel(&mut self) -> Result<i64, HostError> {
+        let global = self
+            .instance
+            .exports
+            .get_global(FUEL_EXPORT)
+            .map_err(|e| HostError::ExecutionError(e.to_string()))?;
+        let left = match global.get(&mut self.store) {
+            Value::I64(left) => left,
+            _ => 0,
+        };
+        global
+            .set(&mut self.store, Value::I64(0))
+            .map_err(|e| HostError::ExecutionError(e.to_string()))?;
+        Ok(left)
+    }
+}
+
+fn wasm_type(ty: &Type) -> WasmType {
+    match ty {
+        Type::I32 => WasmType::I32,
+        Type::I64 => WasmType::I64,
+        Type::F32 => WasmType::F32,
+        Type::F64 => WasmType::F64,
+        Type::V128 => WasmType::V128,
+        Type::ExternRef | Type::FuncRef => WasmType::Ref,
+    }
+}
+
+fn value(value: WasmValue) -> Value {
+    match value {
+        WasmValue::I32(value) => Value::I32(value),
+        WasmValue::I64(value) => Value::I64(value),
+        WasmValue::F32(value) => Value::F32(value),
+        WasmValue::F64(value) => Value::F64(value),
+    }
+}
+
+fn wasm_value(value: &Value) -> Result<WasmValue, HostError> {
+    match value {
+        Value::I32(value) => Ok(WasmValue::I32(*value)),
+        Value::I64(value) => Ok(WasmValue::I64(*value)),
+        Value::F32(value) => Ok(WasmValue::F32(*value)),
+        Value::F64(value) => Ok(WasmValue::F64(*value)),
+        other => Err(unsupported_value(other)),
+    }
+}
+
+impl WasmInstance for WasmerInstance {
+    fn has_function(&mut self, name: &str) -> bool {
+        self.instance.exports.get_function(name).is_ok()
+    }
+
+    fn function_exports(&mut self) -> Vec<FunctionExport> {
+        self.instance
+            .module()
+            .exports()
+            .functions()
+            .map(|export| FunctionExport {
+                name: export.name().to_string(),
+                params: export.ty().params().iter().map(wasm_type).collect(),
+                results: export.ty().results().iter().map(wasm_type).collect(),
+            })
+            .collect()
+    }
+
+    fn call(&mut self, name: &str, args: &[WasmValue]) -> Result<Vec<WasmValue>, HostError> {
+        let func = self
+            .instance
+            .exports
+            .get_function(name)
+            .cloned()
+            .map_err(|_| HostError::MethodNotFound(name.to_string()))?;
+        let args: Vec<_> = args.iter().copied().map(value).collect();
+        if self.is_cancelled() {
+            return Err(HostError::Cancelled(name.to_string()));
+        }
+        self.meter.as_mut(&mut self.store).start_call();
+        self.reset_depth()?;
+        let result = func.call(&mut self.store, &args);
+        let left = self.take_fuel()?;
+        self.meter.as_mut(&mut self.store).end_call(left);
+        let results = result.map_err(|e| {
+            self.meter
+                .as_ref(&self.store)
+                .call_error(name)
+                .unwrap_or_else(|| e.into())
+        })?;
+        results.iter().map(wasm_value).collect()
+    }
+
+    fn read_memory(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), HostError> {
+        let memory = self.memory()?;
+        memory
+            .view(&self.store)
+            .read(offset, buf)
+            .map_err(memory_error)
+    }
+
+    fn write_memory(&mut self, offset: u64, data: &[u8]) -> Result<(), HostError> {
+        let memory = self.memory()?;
+        memory
+            .view(&self.store)
+            .write(offset, data)
+            .map_err(memory_error)
+    }
+
+    fn memory_size(&mut self) -> u64 {
+        self.memory()
+            .map(|memory| memory.view(&self.store).data_size())
+            .unwrap_or(0)
+    }
+
+    fn set_limits(&mut self, limits: CallLimits) {
+        self.meter.as_mut(&mut self.store).set_limits(limits);
+    }
+
+    fn fuel_used(&mut self) -> u64 {
+        self.meter.as_ref(&self.store).fuel_used()
+    }
+
+    fn set_rate_limiter(&mut self, limiter: RateLimiter) {
+        if let Some(env) = &self.wasi {
+            env.as_mut(&mut self.store).wasi.set_rate_limiter(limiter);
+        }
+    }
+
+    fn set_cancellation(&mut self, token: Option<CancellationToken>) {
+        self.meter.as_mut(&mut self.store).set_cancellation(token);
+    }
+}
+
+/// WASI state and the memory of the instance it belongs to, which is only known
+/// once the instance has been created
+struct WasiState {
+    memory: Option<Memory>,
+    wasi: WasiEnv,
+}
+
+impl GuestMemory for MemoryView<'_> {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> bool {
+        MemoryView::read(self, offset, buf).is_ok()
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> bool {
+        MemoryView::write(self, offset, data).is_ok()
+    }
+
+    fn size(&self) -> u64 {
+        self.data_size()
+    }
+}
+
+/// Run a WASI function with the instance's state and memory
+fn with_guest(
+    mut env: FunctionEnvMut<WasiState>,
+    function: impl FnOnce(&mut WasiEnv, &mut Guest) -> Result<(), i32>,
+) -> i32 {
+    let (state, store) = env.data_and_store_mut();
+    let Some(memory) = state.memory.clone() else {
+        return wasi::EFAULT;
+    };
+    let mut view = memory.view(&store);
+    wasi::errno(function(&mut state.wasi, &mut Guest::new(&mut view)))
+}
+
+fn wasi_imports(
+    store: &mut Store,
+    module: &Module,
+    options: &WasiOptions,
+    tapplet: &str,
+) -> (Imports, FunctionEnv<WasiState>) {
+    let env = FunctionEnv::new(
+        store,
+        WasiState {
+            memory: None,
+            wasi: WasiEnv::new(options, tapplet),
+        },
+    );
+    let mut imports = Imports::new();
+    macro_rules! define {
+        ($function:ident($($arg:ident: $ty:ty),*)) => {
+            imports.define(
+                WASI_MODULE,
+                stringify!($function),
+                Function::new_typed_with_env(
+                    store,
+                    &env,
+                    |env: FunctionEnvMut<WasiState>, $($arg: $ty),*| -> i32 {
+                        with_guest(env, |state, guest| {
+                            wasi::limit(state, stringify!($function))?;
+                            wasi::$function(state, guest, $($arg),*)
+                        })
+                    },
+                ),
+            );
+        };
+    }
+    wasi::wasi_functions!(define);
+    imports.define(
+        WASI_MODULE,
+        "proc_exit",
+        Function::new_typed_with_env(
+            store,
+            &env,
+            |_env: FunctionEnvMut<WasiState>, code: i32| -> Result<(), RuntimeError> {
+                Err(RuntimeError::new(wasi::exit_message(code)))
+            },
+        ),
+    );
+    for import in module.imports().functions() {
+        if import.module() != WASI_MODULE || wasi::is_provided(import.name()) {
+            continue;
+        }
+        let results: Vec<_> = import.ty().results().iter().map(unsupported).collect();
+        let stub = Function::new(store, import.ty().clone(), move |_| Ok(results.clone()));
+        imports.define(WASI_MODULE, import.name(), stub);
+    }
+    (imports, env)
+}
+
+/// Define the functions [`metering::instrument`] makes the module import
+fn define_meter_functions(store: &mut Store, imports: &mut Imports) -> FunctionEnv<Meter> {
+    let env = FunctionEnv::new(store, Meter::default());
+    imports.define(
+        METER_MODULE,
+        "refill",
+        Function::new_typed_with_env(
+            store,
+            &env,
+            |mut env: FunctionEnvMut<Meter>| -> Result<i64, RuntimeError> {
+                env.data_mut().refill().map_err(RuntimeError::new)
+            },
+        ),
+    );
+    imports.define(
+        METER_MODULE,
+        "stack_overflow",
+        Function::new_typed(store, || -> Result<(), RuntimeError> {
+            Err(RuntimeError::new("stack limit exceeded"))
+        }),
+    );
+    imports.define(
+        METER_MODULE,
+        "grow",
+        Function::new_typed_with_env(
+            store,
+            &env,
+            |mut env: FunctionEnvMut<Meter>, delta: i32, pages: i32| -> i32 {
+                env.data_mut().grow(delta as u32, pages as u32) as i32
+            },
+        ),
+    );
+    env
+}
+
+/// The memory of the instance host functions are called from, which is only known
+/// once the instance has been created, and its meter
+struct HostState {
+    memory: Option<Memory>,
+    meter: FunctionEnv<Meter>,
+}
+
+/// Define the functions the module imports from [`HOST_MODULE`] in `imports`
+fn define_host_functions(
+    store: &mut Store,
+    module: &Module,
+    host: &HostImports,
+    meter: &FunctionEnv<Meter>,
+    imports: &mut Imports,
+) -> Result<FunctionEnv<HostState>, HostError> {
+    let env = FunctionEnv::new(
+        store,
+        HostState {
+            memory: None,
+            meter: meter.clone(),
+        },
+    );
+    for import in module.imports().functions() {
+        if import.module() != HOST_MODULE {
+            continue;
+        }
+        let ty = import.ty();
+        let params: Vec<_> = ty.params().iter().map(wasm_type).collect();
+        let results: Vec<_> = ty.results().iter().map(wasm_type).collect();
+        let body = host.resolve(import.name(), &params, &results)?.body.clone();
+        let function = Function::new_with_env(
+            store,
+            &env,
+            ty.clone(),
+            move |mut env: FunctionEnvMut<HostState>, args: &[Value]| {
+                let (state, store) = env.data_and_store_mut();
+                if state.meter.as_ref(&store).is_cancelled() {
+                    return Err(RuntimeError::new("call cancelled"));
+                }
+                let memory = state
+                    .memory
+                    .clone()
+                    .ok_or_else(|| RuntimeError::new("module does not export its memory"))?;
+                let args = args
+                    .iter()
+                    .map(wasm_value)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| RuntimeError::new(e.to_string()))?;
+                let mut view = memory.view(&store);
+                let results = body(&mut view, &args).map_err(RuntimeError::new)?;
+                Ok(results.into_iter().map(value).collect())
+            },
+        );
+        imports.define(HOST_MODULE, import.name(), function);
+    }
+    Ok(env)
+}
+
+/// What a WASI function without a virtual implementation returns: `ENOSYS`, or
+/// zero for results that aren't error numbers
+fn unsupported(ty: &Type) -> Value {
+    match ty {
+        Type::I32 => Value::I32(wasi::UNSUPPORTED),
+        Type::I64 => Value::I64(0),
+        Type::F32 => Value::F32(0.0),
+        Type::F64 => Value::F64(0.0),
+        Type::V128 => Value::V128(0),
+        Type::ExternRef => Value::ExternRef(None),
+        Type::FuncRef => Value::FuncRef(None),
+    }
+}
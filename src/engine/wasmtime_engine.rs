@@ -0,0 +1,480 @@
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use wasmtime::{
+    Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, ResourceLimiter, Store, Trap,
+    UpdateDeadline, Val, ValType, WasmBacktrace, WasmBacktraceDetails,
+};
+
+use super::{
+    CallLimits, CompiledModule, FunctionExport, GuestFrame, HOST_MODULE, HostImports, WasmEngine,
+    WasmInstance, WasmType, WasmValue, demangle, memory_error, missing_memory, module_binary,
+    trap_error, unsupported_value,
+};
+use crate::cancel::{CancellationToken, OnCancel};
+use crate::host::HostError;
+use crate::rate_limit::RateLimiter;
+use crate::wasi::{self, Guest, WASI_MODULE, WasiEnv, WasiOptions};
+
+/// Runs modules with wasmtime and its Cranelift compiler
+#[derive(Clone)]
+pub struct WasmtimeEngine {
+    engine: Engine,
+}
+
+impl WasmtimeEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for WasmtimeEngine {
+    fn default() -> Self {
+        // Epoch checks let a cancelled call be interrupted, see `set_cancellation`
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        // Fuel enforces execution budgets, see `set_limits`
+        config.consume_fuel(true);
+        // Name trap frames after the source lines in the module's debug info, if any
+        config.wasm_backtrace_details(WasmBacktraceDetails::Enable);
+        Self {
+            engine: Engine::new(&config).expect("default wasmtime configuration is valid"),
+        }
+    }
+}
+
+impl WasmEngine for WasmtimeEngine {
+    fn name(&self) -> &'static str {
+        "wasmtime"
+    }
+
+    /// wasmtime doesn't export its version, so this is a digest of what it checks
+    /// before loading a serialized module: its version and compiler settings
+    fn version(&self) -> String {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.engine
+            .precompile_compatibility_hash()
+            .hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    fn artifact_extension(&self) -> &'static str {
+        "cwasm"
+    }
+
+    /// wasmtime only bounds the native stack, for all instances of the engine
+    fn limits_stack(&self) -> bool {
+        false
+    }
+
+    fn compile(&self, wasm_bytes: &[u8]) -> Result<Arc<dyn CompiledModule>, HostError> {
+        let wasm_bytes = module_binary(wasm_bytes)?;
+        let module = Module::new(&self.engine, &wasm_bytes)
+            .map_err(|e| HostError::WasmCompileError(format!("{:#}", e)))?;
+        Ok(Arc::new(WasmtimeModule {
+            engine: self.engine.clone(),
+            module,
+        }))
+    }
+
+    unsafe fn deserialize(&self, bytes: &[u8]) -> Result<Arc<dyn CompiledModule>, HostError> {
+        // SAFETY: upheld by the caller
+        let module = unsafe { Module::deserialize(&self.engine, bytes) }
+            .map_err(|e| HostError::WasmLoadError(format!("{:#}", e)))?;
+        Ok(Arc::new(WasmtimeModule {
+            engine: self.engine.clone(),
+            module,
+        }))
+    }
+}
+
+struct WasmtimeModule {
+    engine: Engine,
+    module: Module,
+}
+
+impl CompiledModule for WasmtimeModule {
+    fn serialize(&self) -> Result<Vec<u8>, HostError> {
+        self.module
+            .serialize()
+            .map_err(|e| HostError::WasmLoadError(format!("{:#}", e)))
+    }
+
+    fn imports_wasi(&self) -> bool {
+        self.module
+            .imports()
+            .any(|import| import.module() == WASI_MODULE)
+    }
+
+    fn imports(&self, module: &str, name: &str) -> bool {
+        self.module
+            .imports()
+            .any(|import| import.module() == module && import.name() == name)
+    }
+
+    fn instantiate(
+        &self,
+        wasi: Option<&WasiOptions>,
+        host: &HostImports,
+        tapplet: &str,
+    ) -> Result<Box<dyn WasmInstance>, HostError> {
+        let instantiation_error =
+            |e: wasmtime::Error| HostError::WasmInstantiationError(format!("{:#}", e));
+        let mut store = Store::new(
+            &self.engine,
+            StoreState {
+                wasi: wasi.map(|options| WasiEnv::new(options, tapplet)),
+                limiter: MemoryLimiter::default(),
+            },
+        );
+        store.limiter(|state| &mut state.limiter);
+        // Start functions run without a budget
+        store.set_fuel(u64::MAX).map_err(instantiation_error)?;
+        let mut linker = Linker::new(&self.engine);
+        if wasi.is_some() {
+            define_wasi(&mut linker, &self.module).map_err(instantiation_error)?;
+        }
+        define_host_functions(&mut linker, &self.module, host)?;
+        let instance = linker
+            .instantiate(&mut store, &self.module)
+            .map_err(instantiation_error)?;
+        if wasi.is_some() && instance.get_memory(&mut store, "memory").is_none() {
+            return Err(HostError::WasmInstantiationError(
+                "WASI module does not export its memory".to_string(),
+            ));
+        }
+        // Epochs only advance when a token is cancelled, so by default every
+        // epoch check passes
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(|_| Ok(UpdateDeadline::Continue(1)));
+        Ok(Box::new(WasmtimeInstance {
+            store,
+            instance,
+            cancel: None,
+            fuel: None,
+        }))
+    }
+}
+
+/// The state of an instance's WASI functions if it has them, and what limits
+/// its memory
+struct StoreState {
+    wasi: Option<WasiEnv>,
+    limiter: MemoryLimiter,
+}
+
+/// Refuses to grow memories past a limit, and remembers it did
+#[derive(Default)]
+struct MemoryLimiter {
+    limit: Option<u64>,
+    denied: bool,
+}
+
+impl ResourceLimiter for MemoryLimiter {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        if self.limit.is_some_and(|limit| desired as u64 > limit) {
+            self.denied = true;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        _desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// An instance and its store
+struct WasmtimeInstance {
+    store: Store<StoreState>,
+    instance: Instance,
+    /// The token calls are cancelled with, and the listener that interrupts them
+    cancel: Option<(CancellationToken, OnCancel)>,
+    /// Fuel the calls may use together since the limits were set
+    fuel: Option<u64>,
+}
+
+impl WasmtimeInstance {
+    fn memory(&mut self) -> Result<Memory, HostError> {
+        self.instance
+            .get_memory(&mut self.store, "memory")
+            .ok_or_else(missing_memory)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|(token, _)| token.is_cancelled())
+    }
+}
+
+/// The error of a call that failed, with the guest's stack trace if it trapped
+fn execution_error(e: &wasmtime::Error) -> HostError {
+    let frames: Vec<_> =
+        e.downcast_ref::<WasmBacktrace>()
+            .map(|backtrace| {
+                backtrace
+                    .frames()
+                    .iter()
+                    .map(|frame| GuestFrame {
+                        function: frame.func_name().map(demangle),
+                        func_index: frame.func_index(),
+                        offset: frame.module_offset(),
+                        location: frame.symbols().first().and_then(|symbol| {
+                            Some(format!("{}:{}", symbol.file()?, symbol.line()?))
+                        }),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+    trap_error(&e.root_cause().to_string(), &frames)
+}
+
+fn wasm_type(ty: &ValType) -> WasmType {
+    match ty {
+        ValType::I32 => WasmType::I32,
+        ValType::I64 => WasmType::I64,
+        ValType::F32 => WasmType::F32,
+        ValType::F64 => WasmType::F64,
+        ValType::V128 => WasmType::V128,
+        ValType::Ref(_) => WasmType::Ref,
+    }
+}
+
+fn val(value: WasmValue) -> Val {
+    match value {
+        WasmValue::I32(value) => Val::I32(value),
+        WasmValue::I64(value) => Val::I64(value),
+        WasmValue::F32(value) => Val::F32(value.to_bits()),
+        WasmValue::F64(value) => Val::F64(value.to_bits()),
+    }
+}
+
+fn wasm_value(value: &Val) -> Result<WasmValue, HostError> {
+    match value {
+        Val::I32(value) => Ok(WasmValue::I32(*value)),
+        Val::I64(value) => Ok(WasmValue::I64(*value)),
+        Val::F32(bits) => Ok(WasmValue::F32(f32::from_bits(*bits))),
+        Val::F64(bits) => Ok(WasmValue::F64(f64::from_bits(*bits))),
+        other => Err(unsupported_value(other)),
+    }
+}
+
+impl WasmInstance for WasmtimeInstance {
+    fn has_function(&mut self, name: &str) -> bool {
+        self.instance.get_func(&mut self.store, name).is_some()
+    }
+
+    fn function_exports(&mut self) -> Vec<FunctionExport> {
+        self.instance
+            .module(&self.store)
+            .exports()
+            .filter_map(|export| {
+                let ty = export.ty().func()?.clone();
+                Some(FunctionExport {
+                    name: export.name().to_string(),
+                    params: ty.params().map(|ty| wasm_type(&ty)).collect(),
+                    results: ty.results().map(|ty| wasm_type(&ty)).collect(),
+                })
+            })
+            .collect()
+    }
+
+    fn call(&mut self, name: &str, args: &[WasmValue]) -> Result<Vec<WasmValue>, HostError> {
+        let func = self
+            .instance
+            .get_func(&mut self.store, name)
+            .ok_or_else(|| HostError::MethodNotFound(name.to_string()))?;
+        let args: Vec<_> = args.iter().copied().map(val).collect();
+        if self.is_cancelled() {
+            return Err(HostError::Cancelled(name.to_string()));
+        }
+        self.store.data_mut().limiter.denied = false;
+        let mut results = vec![Val::I32(0); func.ty(&self.store).results().len()];
+        func.call(&mut self.store, &args, &mut results)
+            .map_err(|e| {
+                if self.is_cancelled() {
+                    HostError::Cancelled(name.to_string())
+                } else if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+                    HostError::ExecutionBudgetExceeded(name.to_string())
+                } else if self.store.data().limiter.denied {
+                    HostError::MemoryLimitExceeded(name.to_string())
+                } else {
+                    execution_error(&e)
+                }
+            })?;
+        results.iter().map(wasm_value).collect()
+    }
+
+    fn read_memory(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), HostError> {
+        let memory = self.memory()?;
+        let offset = usize::try_from(offset).map_err(memory_error)?;
+        memory.read(&self.store, offset, buf).map_err(memory_error)
+    }
+
+    fn write_memory(&mut self, offset: u64, data: &[u8]) -> Result<(), HostError> {
+        let memory = self.memory()?;
+        let offset = usize::try_from(offset).map_err(memory_error)?;
+        memory
+            .write(&mut self.store, offset, data)
+            .map_err(memory_error)
+    }
+
+    fn memory_size(&mut self) -> u64 {
+        self.memory()
+            .map(|memory| memory.data_size(&self.store) as u64)
+            .unwrap_or(0)
+    }
+
+    fn set_limits(&mut self, limits: CallLimits) {
+        self.fuel = limits.fuel;
+        // Only fails if fuel isn't enabled, which the engine always does
+        let _ = self.store.set_fuel(limits.fuel.unwrap_or(u64::MAX));
+        self.store.data_mut().limiter.limit = limits.memory;
+    }
+
+    fn fuel_used(&mut self) -> u64 {
+        let left = self.store.get_fuel().unwrap_or(0);
+        self.fuel.unwrap_or(u64::MAX).saturating_sub(left)
+    }
+
+    fn set_rate_limiter(&mut self, limiter: RateLimiter) {
+        if let Some(wasi) = &mut self.store.data_mut().wasi {
+            wasi.set_rate_limiter(limiter);
+        }
+    }
+
+    fn set_cancellation(&mut self, token: Option<CancellationToken>) {
+        self.cancel = token.map(|token| {
+            // Advancing the engine's epoch makes every instance running on it check
+            // its deadline; only those whose token is cancelled stop
+            let engine = self.store.engine().clone();
+            let listener = token.on_cancel(move || engine.increment_epoch());
+            let cancelled = token.clone();
+            self.store.epoch_deadline_callback(move |_| {
+                if cancelled.is_cancelled() {
+                    Err(wasmtime::Error::msg("call cancelled"))
+                } else {
+                    Ok(UpdateDeadline::Continue(1))
+                }
+            });
+            (token, listener)
+        });
+        if self.cancel.is_none() {
+            self.store
+                .epoch_deadline_callback(|_| Ok(UpdateDeadline::Continue(1)));
+        }
+    }
+}
+
+/// Run a WASI function with the calling instance's state and memory
+fn with_guest(
+    mut caller: Caller<'_, StoreState>,
+    function: impl FnOnce(&mut WasiEnv, &mut Guest) -> Result<(), i32>,
+) -> i32 {
+    let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+        return wasi::EFAULT;
+    };
+    let (mut data, state) = memory.data_and_store_mut(&mut caller);
+    let Some(state) = &mut state.wasi else {
+        return wasi::EFAULT;
+    };
+    wasi::errno(function(state, &mut Guest::new(&mut data)))
+}
+
+fn define_wasi(linker: &mut Linker<StoreState>, module: &Module) -> wasmtime::Result<()> {
+    macro_rules! define {
+        ($function:ident($($arg:ident: $ty:ty),*)) => {
+            linker.func_wrap(
+                WASI_MODULE,
+                stringify!($function),
+                |caller: Caller<'_, StoreState>, $($arg: $ty),*| -> i32 {
+                    with_guest(caller, |state, guest| {
+                        wasi::limit(state, stringify!($function))?;
+                        wasi::$function(state, guest, $($arg),*)
+                    })
+                },
+            )?;
+        };
+    }
+    wasi::wasi_functions!(define);
+    linker.func_wrap(
+        WASI_MODULE,
+        "proc_exit",
+        |_caller: Caller<'_, StoreState>, code: i32| -> wasmtime::Result<()> {
+            Err(wasmtime::Error::msg(wasi::exit_message(code)))
+        },
+    )?;
+    for import in module.imports() {
+        if import.module() != WASI_MODULE || wasi::is_provided(import.name()) {
+            continue;
+        }
+        let Some(ty) = import.ty().func().cloned() else {
+            continue;
+        };
+        let results: Vec<_> = ty.results().map(|ty| unsupported(&ty)).collect();
+        linker.func_new(WASI_MODULE, import.name(), ty, move |_, _, out| {
+            out.clone_from_slice(&results);
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Define the functions the module imports from [`HOST_MODULE`] in `linker`
+fn define_host_functions(
+    linker: &mut Linker<StoreState>,
+    module: &Module,
+    host: &HostImports,
+) -> Result<(), HostError> {
+    for import in module.imports() {
+        if import.module() != HOST_MODULE {
+            continue;
+        }
+        let Some(ty) = import.ty().func().cloned() else {
+            continue;
+        };
+        let params: Vec<_> = ty.params().map(|ty| wasm_type(&ty)).collect();
+        let results: Vec<_> = ty.results().map(|ty| wasm_type(&ty)).collect();
+        let body = host.resolve(import.name(), &params, &results)?.body.clone();
+        linker
+            .func_new(
+                HOST_MODULE,
+                import.name(),
+                ty,
+                move |mut caller, args, out| {
+                    let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+                        return Err(wasmtime::Error::msg("module does not export its memory"));
+                    };
+                    let args = args.iter().map(wasm_value).collect::<Result<Vec<_>, _>>()?;
+                    let mut data = memory.data_mut(&mut caller);
+                    let results = body(&mut data, &args).map_err(wasmtime::Error::msg)?;
+                    for (slot, result) in out.iter_mut().zip(results) {
+                        *slot = val(result);
+                    }
+                    Ok(())
+                },
+            )
+            .map_err(|e| HostError::WasmInstantiationError(format!("{:#}", e)))?;
+    }
+    Ok(())
+}
+
+/// What a WASI function without a virtual implementation returns: `ENOSYS`, or
+/// zero for results that aren't error numbers
+fn unsupported(ty: &ValType) -> Val {
+    match ty {
+        ValType::I32 => Val::I32(wasi::UNSUPPORTED),
+        other => Val::default_for_ty(other).unwrap_or(Val::I64(0)),
+    }
+}
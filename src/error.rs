@@ -0,0 +1,210 @@
+use std::path::PathBuf;
+
+use serde_json::{Value, json};
+
+use crate::build::BuildDiagnostic;
+use crate::model::ManifestIssue;
+use crate::registry::TappletConflict;
+use crate::trust::TrustViolation;
+
+/// Failures of the registry, installers and manager that frontends may want to act on.
+///
+/// These are returned inside `anyhow::Error`, so the registry and installer APIs
+/// keep their context chains. Use [`error_code`] and [`error_to_json`] to map any
+/// error returned by this crate to a stable machine-readable code.
+#[derive(Debug, thiserror::Error)]
+pub enum TappletError {
+    #[error("No manifest.toml found in {}", .path.display())]
+    ManifestNotFound { path: PathBuf },
+    #[error("Invalid manifest: {0}")]
+    InvalidManifest(String),
+    #[error("Invalid manifest: {}", join_display(.issues))]
+    ManifestIssues { issues: Vec<ManifestIssue> },
+    #[error("Manifest {field} '{actual}' does not match expected '{expected}'")]
+    ManifestMismatch {
+        field: &'static str,
+        expected: String,
+        actual: String,
+    },
+    #[error("Invalid version: {0}")]
+    InvalidVersion(String),
+    #[error("Tapplet '{name}' not found")]
+    TappletNotFound { name: String },
+    #[error("Tapplets of several publishers are called '{name}': {}, use publisher/name", .publishers.join(", "))]
+    AmbiguousName {
+        name: String,
+        publishers: Vec<String>,
+    },
+    #[error("Tapplet {name}@{version} has been yanked by its publisher")]
+    Yanked { name: String, version: String },
+    #[error("Tapplet {name} requires host API {required}, but this host provides {supported}")]
+    UnsupportedHostApi {
+        name: String,
+        required: String,
+        supported: String,
+    },
+    #[error("Tapplet '{name}' is not installed")]
+    NotInstalled { name: String },
+    #[error("Tapplet '{name}' is already installed at {}", .path.display())]
+    AlreadyInstalled { name: String, path: PathBuf },
+    #[error("Tapplet {name} is not trusted: {}", join_display(.violations))]
+    Untrusted {
+        name: String,
+        violations: Vec<TrustViolation>,
+    },
+    #[error("Registry not loaded. Please call fetch() or load() first.")]
+    RegistryNotLoaded,
+    #[error("Repository not found at {}. Please fetch it first using fetch().", .path.display())]
+    RepositoryNotFound { path: PathBuf },
+    #[error("Duplicate tapplet: {}", join_display(.conflicts))]
+    DuplicateTapplet { conflicts: Vec<TappletConflict> },
+    #[error("Registry has no {0}")]
+    RegistryRefNotFound(String),
+    #[error("No artifact found: {0}")]
+    ArtifactNotFound(String),
+    #[error("Integrity check failed for {file}: expected SHA-256 {expected}, got {actual}")]
+    IntegrityMismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("Invalid asset: {0}")]
+    InvalidAsset(String),
+    #[error("Invalid tapplet package: {0}")]
+    InvalidPackage(String),
+    #[error("Registry index signature check failed: {0}")]
+    InvalidIndexSignature(String),
+    #[error("Failed to compile tapplet:\n{message}")]
+    BuildFailed {
+        message: String,
+        diagnostics: Vec<BuildDiagnostic>,
+    },
+    #[error("The {target} target is not installed, add it with `rustup target add {target}`")]
+    MissingBuildTarget { target: String },
+    #[error("The WASM module doesn't export methods listed in api.methods: {}", .methods.join(", "))]
+    MissingExports { methods: Vec<String> },
+    #[error("Lua script {} failed the sandbox check: {}", .path.display(), .issues.join("; "))]
+    InvalidScript { path: PathBuf, issues: Vec<String> },
+    #[error("Lock file mismatch: {0}")]
+    LockMismatch(String),
+    #[error("Dependency cycle: {}", .cycle.join(" -> "))]
+    DependencyCycle { cycle: Vec<String> },
+    #[error("No version of '{name}' satisfies all requirements: {}", .requirements.join(", "))]
+    DependencyConflict {
+        name: String,
+        requirements: Vec<String>,
+    },
+}
+
+impl TappletError {
+    /// Stable machine-readable code for this error
+    pub fn code(&self) -> &'static str {
+        match self {
+            TappletError::ManifestNotFound { .. } => "MANIFEST_NOT_FOUND",
+            TappletError::InvalidManifest(_) | TappletError::ManifestIssues { .. } => {
+                "INVALID_MANIFEST"
+            }
+            TappletError::ManifestMismatch { .. } => "MANIFEST_MISMATCH",
+            TappletError::InvalidVersion(_) => "INVALID_VERSION",
+            TappletError::TappletNotFound { .. } => "TAPPLET_NOT_FOUND",
+            TappletError::AmbiguousName { .. } => "AMBIGUOUS_NAME",
+            TappletError::Yanked { .. } => "TAPPLET_YANKED",
+            TappletError::UnsupportedHostApi { .. } => "UNSUPPORTED_HOST_API",
+            TappletError::NotInstalled { .. } => "NOT_INSTALLED",
+            TappletError::AlreadyInstalled { .. } => "ALREADY_INSTALLED",
+            TappletError::Untrusted { violations, .. }
+                if violations
+                    .iter()
+                    .any(|v| matches!(v, TrustViolation::InvalidSignature(_))) =>
+            {
+                "SIGNATURE_INVALID"
+            }
+            TappletError::Untrusted { .. } => "UNTRUSTED_TAPPLET",
+            TappletError::RegistryNotLoaded => "REGISTRY_NOT_LOADED",
+            TappletError::RepositoryNotFound { .. } => "REPOSITORY_NOT_FOUND",
+            TappletError::DuplicateTapplet { .. } => "DUPLICATE_TAPPLET",
+            TappletError::RegistryRefNotFound(_) => "REGISTRY_REF_NOT_FOUND",
+            TappletError::ArtifactNotFound(_) => "ARTIFACT_NOT_FOUND",
+            TappletError::IntegrityMismatch { .. } => "INTEGRITY_MISMATCH",
+            TappletError::InvalidAsset(_) => "INVALID_ASSET",
+            TappletError::InvalidPackage(_) => "INVALID_PACKAGE",
+            TappletError::InvalidIndexSignature(_) => "INVALID_INDEX_SIGNATURE",
+            TappletError::BuildFailed { .. } => "BUILD_FAILED",
+            TappletError::MissingBuildTarget { .. } => "MISSING_BUILD_TARGET",
+            TappletError::MissingExports { .. } => "MISSING_EXPORTS",
+            TappletError::InvalidScript { .. } => "INVALID_SCRIPT",
+            TappletError::LockMismatch(_) => "LOCK_MISMATCH",
+            TappletError::DependencyCycle { .. } => "DEPENDENCY_CYCLE",
+            TappletError::DependencyConflict { .. } => "DEPENDENCY_CONFLICT",
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut value = json!({ "code": self.code(), "message": self.to_string() });
+        match self {
+            TappletError::ManifestIssues { issues } => value["issues"] = json!(issues),
+            TappletError::BuildFailed { diagnostics, .. } => {
+                value["diagnostics"] = json!(diagnostics)
+            }
+            TappletError::InvalidScript { issues, .. } => value["issues"] = json!(issues),
+            TappletError::DuplicateTapplet { conflicts } => value["conflicts"] = json!(conflicts),
+            _ => {}
+        }
+        value
+    }
+}
+
+fn join_display(items: &[impl std::fmt::Display]) -> String {
+    items
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Code used for errors that don't carry a more specific code
+pub const INTERNAL_ERROR_CODE: &str = "INTERNAL_ERROR";
+
+/// Stable machine-readable code for an error returned by this crate
+pub fn error_code(err: &anyhow::Error) -> &'static str {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<TappletError>() {
+            return err.code();
+        }
+        #[cfg(feature = "host-core")]
+        if let Some(err) = cause.downcast_ref::<crate::host::HostError>() {
+            return err.code();
+        }
+    }
+    INTERNAL_ERROR_CODE
+}
+
+/// JSON representation of an error with its code and full context chain
+pub fn error_to_json(err: &anyhow::Error) -> Value {
+    json!({ "code": error_code(err), "message": format!("{:#}", err) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_error_codes_through_context() {
+        let err = Err::<(), _>(TappletError::NotInstalled {
+            name: "wallet".to_string(),
+        })
+        .context("Failed to update tapplet")
+        .unwrap_err();
+        assert_eq!(error_code(&err), "NOT_INSTALLED");
+        assert_eq!(
+            error_to_json(&err),
+            json!({
+                "code": "NOT_INSTALLED",
+                "message": "Failed to update tapplet: Tapplet 'wallet' is not installed"
+            })
+        );
+
+        assert_eq!(error_code(&anyhow::anyhow!("boom")), INTERNAL_ERROR_CODE);
+    }
+}
@@ -0,0 +1,251 @@
+//! Host events delivered to subscribed tapplets.
+//!
+//! Tapplets subscribe in their manifest:
+//!
+//! ```toml
+//! [events]
+//! subscribe = ["new_block", "transaction_received"]
+//! handler = "on_event"
+//! ```
+//!
+//! The host publishes events on a [`TappletEventBus`], which calls the handler of
+//! every subscribed tapplet with the event as JSON, e.g.
+//! `{"type": "new_block", "height": 1000, "hash": "..."}`. Events raised while
+//! tapplets run, like `slot_changed`, are queued through the bus's
+//! [`EventSender`] and published with [`TappletEventBus::publish_queued`].
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::call_context::CallContext;
+use crate::host::{HostError, TappletRunner};
+use crate::model::{EventKind, TappletManifest};
+use crate::wallet::TransactionInfo;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TappletEvent {
+    NewBlock {
+        height: u64,
+        hash: String,
+    },
+    TransactionReceived {
+        transaction: TransactionInfo,
+    },
+    /// Data was appended to one of a tapplet's slots. Only delivered to that tapplet.
+    SlotChanged {
+        /// Qualified name of the tapplet, see [`TappletManifest::qualified_name`]
+        tapplet: String,
+        slot: String,
+    },
+}
+
+impl TappletEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            TappletEvent::NewBlock { .. } => EventKind::NewBlock,
+            TappletEvent::TransactionReceived { .. } => EventKind::TransactionReceived,
+            TappletEvent::SlotChanged { .. } => EventKind::SlotChanged,
+        }
+    }
+
+    /// Whether a tapplet should receive this event
+    pub fn is_for(&self, manifest: &TappletManifest) -> bool {
+        if !manifest.events.is_subscribed(self.kind()) {
+            return false;
+        }
+        match self {
+            TappletEvent::SlotChanged { tapplet, .. } => *tapplet == manifest.qualified_name(),
+            _ => true,
+        }
+    }
+}
+
+/// The outcome of delivering an event to one tapplet
+#[derive(Debug)]
+pub struct EventDelivery {
+    pub tapplet: String,
+    pub result: Result<Value, HostError>,
+}
+
+/// Queues events on a [`TappletEventBus`], see [`TappletEventBus::sender`]
+pub type EventSender = mpsc::UnboundedSender<TappletEvent>;
+
+/// Delivers host events to the tapplets subscribed to them
+pub struct TappletEventBus {
+    targets: Vec<Box<dyn TappletRunner>>,
+    sender: EventSender,
+    queued: mpsc::UnboundedReceiver<TappletEvent>,
+}
+
+impl Default for TappletEventBus {
+    fn default() -> Self {
+        let (sender, queued) = mpsc::unbounded_channel();
+        Self {
+            targets: Vec::new(),
+            sender,
+            queued,
+        }
+    }
+}
+
+impl TappletEventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Where hosts queue the events their tapplets raise, e.g. for
+    /// `LuaTappletHost::with_event_sender`, whose appends raise `slot_changed`
+    pub fn sender(&self) -> EventSender {
+        self.sender.clone()
+    }
+
+    /// Add a tapplet to the bus. Tapplets without subscriptions are not added,
+    /// in which case this returns false.
+    pub fn subscribe(&mut self, target: impl TappletRunner + 'static) -> bool {
+        if target.manifest().events.subscribe.is_empty() {
+            return false;
+        }
+        self.targets.push(Box::new(target));
+        true
+    }
+
+    /// Names of the tapplets that would receive an event
+    pub fn subscribers(&self, event: &TappletEvent) -> Vec<&str> {
+        self.targets
+            .iter()
+            .map(|target| target.manifest())
+            .filter(|manifest| event.is_for(manifest))
+            .map(|manifest| manifest.name.as_str())
+            .collect()
+    }
+
+    /// Call the handler of every subscribed tapplet, in subscription order.
+    ///
+    /// Handlers run as [`crate::call_context::Caller::System`]. A failing handler
+    /// doesn't stop delivery to the others.
+    pub async fn publish(&mut self, event: &TappletEvent) -> Vec<EventDelivery> {
+        let payload = serde_json::to_value(event).expect("events serialize to JSON");
+        let context = CallContext::system();
+        let mut deliveries = Vec::new();
+        for target in &mut self.targets {
+            let manifest = target.manifest();
+            if !event.is_for(manifest) {
+                continue;
+            }
+            let tapplet = manifest.name.clone();
+            let handler = manifest.events.handler().to_string();
+            let result = target.call(&handler, payload.clone(), &context).await;
+            deliveries.push(EventDelivery { tapplet, result });
+        }
+        deliveries
+    }
+
+    /// Publish the events queued through [`Self::sender`] so far, in order.
+    /// Events raised by the handlers, e.g. by appending to a slot, are left for
+    /// the next call.
+    pub async fn publish_queued(&mut self) -> Vec<EventDelivery> {
+        let mut events = Vec::new();
+        while let Ok(event) = self.queued.try_recv() {
+            events.push(event);
+        }
+        let mut deliveries = Vec::new();
+        for event in &events {
+            deliveries.extend(self.publish(event).await);
+        }
+        deliveries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::host::LuaTappletHost;
+    use crate::reference_api::MemoryTappletApi;
+
+    fn host(
+        name: &str,
+        events: &str,
+        api: Arc<MemoryTappletApi>,
+    ) -> LuaTappletHost<MemoryTappletApi> {
+        let toml = crate::test_utils::manifest_toml(name, "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["on_event"]"#)
+            + events;
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function on_event(event)
+                minotari_append_data("events", event.type)
+            end
+        "#;
+        LuaTappletHost::from_string_shared(config, code, api).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_publish_to_subscribers() {
+        let api = Arc::new(MemoryTappletApi::new());
+        let mut bus = TappletEventBus::new();
+        assert!(bus.subscribe(host(
+            "blocks",
+            "[events]\nsubscribe = [\"new_block\", \"slot_changed\"]\n",
+            api.clone()
+        )));
+        assert!(bus.subscribe(host(
+            "payments",
+            "[events]\nsubscribe = [\"transaction_received\"]\n",
+            api.clone()
+        )));
+        assert!(!bus.subscribe(host("quiet", "", api.clone())));
+
+        let block = TappletEvent::NewBlock {
+            height: 1000,
+            hash: "abcd".to_string(),
+        };
+        assert_eq!(bus.subscribers(&block), vec!["blocks"]);
+        let deliveries = bus.publish(&block).await;
+        assert_eq!(deliveries.len(), 1);
+        assert!(deliveries[0].result.is_ok());
+
+        // Slot changes only go to the tapplet owning the slot
+        let slot_changed = TappletEvent::SlotChanged {
+            tapplet: "test_publisher/payments".to_string(),
+            slot: "events".to_string(),
+        };
+        assert!(bus.publish(&slot_changed).await.is_empty());
+
+        assert_eq!(
+            api.state().slots["test_publisher/blocks/events"],
+            vec!["new_block"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_appends_raise_slot_changed() {
+        let api = Arc::new(MemoryTappletApi::new());
+        let mut bus = TappletEventBus::new();
+        let subscribe = "[events]\nsubscribe = [\"new_block\", \"slot_changed\"]\n";
+        let sender = bus.sender();
+        assert!(bus.subscribe(host("blocks", subscribe, api.clone()).with_event_sender(sender)));
+        assert!(bus.subscribe(host("other", subscribe, api.clone())));
+
+        let block = TappletEvent::NewBlock {
+            height: 1000,
+            hash: "abcd".to_string(),
+        };
+        assert_eq!(bus.publish(&block).await.len(), 2);
+        // Only the host with the sender raised an event, for its own slot
+        let deliveries = bus.publish_queued().await;
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].tapplet, "blocks");
+        assert!(deliveries[0].result.is_ok());
+        assert_eq!(
+            api.state().slots["test_publisher/blocks/events"],
+            vec!["new_block", "slot_changed"]
+        );
+
+        // The handler's own append waits for the next call
+        assert_eq!(bus.publish_queued().await.len(), 1);
+    }
+}
@@ -1,68 +1,362 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use git2::Repository;
 
 use crate::TappletManifest;
+use crate::error::TappletError;
+use crate::install::{
+    ExistingInstall, InstallOptions, InstallProgress, InstallReport, InstallReporter,
+};
+use crate::local_folder_lua_tapplet::{LocalFolderLuaTapplet, tapplet_dir_runtime};
+use crate::local_folder_tapplet::{LocalFolderTapplet, skipped_install};
+use crate::model::{GitConfig, HOST_API_VERSION, RuntimeKind, find_manifest_file};
+use crate::registry::{
+    FetchOptions, NoProgress, clone_repository, fetch_updates, sanitize_repo_name,
+};
+use crate::trace;
 
+/// Directory inside the cache where git checkouts of tapplet sources are kept
+const GIT_SOURCES_DIR: &str = ".git_sources";
+
+#[derive(Clone)]
 pub struct GitTapplet {
     config: TappletManifest,
+    git: GitConfig,
+    options: InstallOptions,
 }
 
 impl GitTapplet {
-    pub fn new(_config: TappletManifest) -> Self {
-        todo!("Need to find a way to validate safely that this repo can be used");
+    /// Create a git tapplet from a manifest that declares a `git` source.
+    ///
+    /// The repository itself is only trusted once it has been cloned and its
+    /// `manifest.toml` matches this manifest, see [`GitTapplet::install`].
+    pub fn new(config: TappletManifest) -> Result<Self> {
+        let Some(git) = config.git.clone() else {
+            bail!(TappletError::InvalidManifest(format!(
+                "tapplet '{}' does not declare a git source",
+                config.name
+            )));
+        };
+        if git.url.trim().is_empty() {
+            bail!(TappletError::InvalidManifest(format!(
+                "tapplet '{}' has an empty git url",
+                config.name
+            )));
+        }
+        Ok(Self {
+            config,
+            git,
+            options: InstallOptions::default(),
+        })
+    }
+
+    /// Lay out the install directory and treat an existing install according to
+    /// `options`
+    pub fn with_install_options(mut self, options: InstallOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn config(&self) -> &TappletManifest {
+        &self.config
+    }
+
+    /// Clone the tapplet's repository and install it into `cache_directory/<name>`,
+    /// or wherever the install options put it
+    pub async fn install(&self, cache_directory: PathBuf) -> Result<InstallReport> {
+        self.install_with_progress(cache_directory, Arc::new(NoProgress))
+            .await
+    }
+
+    /// Install like [`GitTapplet::install`], reporting each step to `reporter`
+    pub async fn install_with_progress(
+        &self,
+        cache_directory: PathBuf,
+        reporter: Arc<dyn InstallReporter>,
+    ) -> Result<InstallReport> {
+        // Use tokio to run the blocking git operations in a separate thread
+        let tapplet = self.clone();
+        tokio::task::spawn_blocking(move || tapplet.install_blocking(&cache_directory, &*reporter))
+            .await
+            .context("Failed to spawn blocking task")?
+    }
+
+    pub(crate) fn install_blocking(
+        &self,
+        cache_directory: &Path,
+        reporter: &dyn InstallReporter,
+    ) -> Result<InstallReport> {
+        let _span = trace::span!("git_install", url = %self.git.url);
+        let started = Instant::now();
+        reporter.report(InstallProgress::Started {
+            name: self.config.name.clone(),
+        });
+
+        self.config.ensure_host_api(&HOST_API_VERSION)?;
+
+        let target_path = self.options.install_dir(cache_directory, &self.config)?;
+
+        // Skip the clone if the install is kept anyway
+        let existing = self
+            .options
+            .existing_install(&target_path, &self.repository_manifest())?;
+        if existing == ExistingInstall::Keep {
+            return Ok(skipped_install(target_path, started, reporter));
+        }
+
+        let source_path = self.checkout(cache_directory, reporter)?;
+        self.validate_checkout(&source_path)?;
+
+        // The checked out repository is a regular tapplet folder from here on
+        let folder_reporter = |progress: InstallProgress| {
+            if !matches!(progress, InstallProgress::Started { .. }) {
+                reporter.report(progress);
+            }
+        };
+        let mut report = if tapplet_dir_runtime(&source_path)? == RuntimeKind::Lua {
+            LocalFolderLuaTapplet::load(source_path)?
+                .with_install_options(self.options)
+                .install_blocking(cache_directory, &folder_reporter)?
+        } else {
+            LocalFolderTapplet::load(source_path)?
+                .with_install_options(self.options)
+                .install_blocking(cache_directory, &folder_reporter)?
+        };
+        report.duration = started.elapsed();
+        Ok(report)
     }
 
-    pub fn install(&self, cache_directory: PathBuf) -> Result<()> {
-        println!("Installing tapplet: {}", self.config.name);
+    /// Clone (or update) the repository and check out the pinned revision
+    fn checkout(&self, cache_directory: &Path, reporter: &dyn InstallReporter) -> Result<PathBuf> {
+        let source_path = cache_directory
+            .join(GIT_SOURCES_DIR)
+            .join(sanitize_repo_name(&self.git.url));
+
+        let repo = if source_path.exists() {
+            let repo = Repository::open(&source_path).with_context(|| {
+                format!(
+                    "Failed to open existing checkout: {}",
+                    source_path.display()
+                )
+            })?;
+            fetch_updates(&repo, &FetchOptions::default(), &NoProgress)
+                .context("Failed to fetch updates")?;
+            repo
+        } else {
+            reporter.report(InstallProgress::Cloning {
+                url: self.git.url.clone(),
+            });
+            clone_repository(
+                &self.git.url,
+                &source_path,
+                &FetchOptions::default(),
+                &NoProgress,
+            )
+            .with_context(|| format!("Failed to clone repository from {}", self.git.url))?
+        };
+
+        // Checkout the specific revision if specified
+        if !self.git.rev.is_empty() {
+            reporter.report(InstallProgress::CheckingOut {
+                rev: self.git.rev.clone(),
+            });
+
+            // Prefer the remote branch so an updated checkout sees new commits
+            let object = repo
+                .revparse_single(&format!("origin/{}", self.git.rev))
+                .or_else(|_| repo.revparse_single(&self.git.rev))
+                .with_context(|| format!("Failed to find revision: {}", self.git.rev))?;
+            let commit = object
+                .peel_to_commit()
+                .with_context(|| format!("Revision is not a commit: {}", self.git.rev))?;
 
-        // Create the target directory path: cache_directory/tapplet_name
-        let target_path = cache_directory.join(&self.config.name);
+            repo.checkout_tree(
+                commit.as_object(),
+                Some(git2::build::CheckoutBuilder::default().force()),
+            )
+            .with_context(|| format!("Failed to checkout revision: {}", self.git.rev))?;
 
-        // Check if the directory already exists
-        if target_path.exists() {
-            println!("Tapplet already installed at: {}", target_path.display());
-            return Ok(());
+            // Set HEAD to the detached state at this revision
+            repo.set_head_detached(commit.id())
+                .with_context(|| format!("Failed to set HEAD to revision: {}", self.git.rev))?;
         }
 
-        todo!("Implement git clone functionality here");
-        // Clone the repository
-        // println!("Cloning from: {}", self.config.git.url);
-        // let repo = Repository::clone(&self.config.git.url, &target_path)
-        //     .with_context(|| format!("Failed to clone repository from {}", self.config.git.url))?;
-
-        // // Checkout the specific revision if specified
-        // if !self.config.git.rev.is_empty() {
-        //     println!("Checking out revision: {}", self.config.git.rev);
-
-        //     // Find the object for the revision
-        //     let oid = repo
-        //         .revparse_single(&self.config.git.rev)
-        //         .with_context(|| format!("Failed to find revision: {}", self.config.git.rev))?
-        //         .id();
-
-        //     // Get the object and peel it to a commit
-        //     let object = repo.find_object(oid, None).with_context(|| {
-        //         format!(
-        //             "Failed to find object for revision: {}",
-        //             self.config.git.rev
-        //         )
-        //     })?;
-
-        //     // Checkout the specific revision
-        //     repo.checkout_tree(&object, None)
-        //         .with_context(|| format!("Failed to checkout revision: {}", self.config.git.rev))?;
-
-        //     // Set HEAD to the detached state at this revision
-        //     repo.set_head_detached(oid).with_context(|| {
-        //         format!("Failed to set HEAD to revision: {}", self.config.git.rev)
-        //     })?;
-        // }
-
-        // println!(
-        // "Successfully installed tapplet to: {}",
-        // target_path.display()
-        // );
-        // Ok(())
+        Ok(source_path)
+    }
+
+    /// The manifest the repository should contain: this one, except that it
+    /// needn't point at the repository itself
+    fn repository_manifest(&self) -> TappletManifest {
+        TappletManifest {
+            git: None,
+            ..self.config.clone()
+        }
+    }
+
+    /// Make sure the checked out repository is the tapplet we were asked to install
+    fn validate_checkout(&self, source_path: &Path) -> Result<()> {
+        let Some(manifest_file) = find_manifest_file(source_path) else {
+            bail!(TappletError::ManifestNotFound {
+                path: source_path.to_path_buf()
+            });
+        };
+        let manifest = TappletManifest::from_file(&manifest_file)?;
+        if !manifest.name_matches(&self.config.name) {
+            bail!(TappletError::ManifestMismatch {
+                field: "name",
+                expected: self.config.name.clone(),
+                actual: manifest.name,
+            });
+        }
+        if manifest.version != self.config.version {
+            bail!(TappletError::ManifestMismatch {
+                field: "version",
+                expected: self.config.version.clone(),
+                actual: manifest.version,
+            });
+        }
+        if manifest.publisher != self.config.publisher {
+            bail!(TappletError::ManifestMismatch {
+                field: "publisher",
+                expected: self.config.publisher.clone(),
+                actual: manifest.publisher,
+            });
+        }
+        if manifest.public_key != self.config.public_key {
+            bail!(TappletError::ManifestMismatch {
+                field: "public key",
+                expected: self.config.public_key.clone(),
+                actual: manifest.public_key,
+            });
+        }
+        // Anything else, e.g. permissions or the runtime, must match as well
+        let expected = self.repository_manifest().digest()?;
+        let actual = TappletManifest {
+            git: None,
+            ..manifest
+        }
+        .digest()?;
+        if actual != expected {
+            bail!(TappletError::ManifestMismatch {
+                field: "digest",
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    fn manifest_with_git(url: &str, rev: &str) -> TappletManifest {
+        let mut manifest =
+            TappletManifest::from_toml_str(&test_utils::manifest_toml("git-lua", "0.1.0")).unwrap();
+        manifest.git = Some(GitConfig {
+            url: url.to_string(),
+            rev: rev.to_string(),
+        });
+        manifest
+    }
+
+    /// Create a repository with a single commit containing a Lua tapplet
+    fn init_repo(dir: &Path) -> String {
+        test_utils::write_lua_tapplet(dir, "git-lua", "0.1.0");
+        test_utils::commit_all(dir)
+    }
+
+    #[test]
+    fn test_new_requires_git_source() {
+        let manifest =
+            TappletManifest::from_toml_str(&test_utils::manifest_toml("git-lua", "0.1.0")).unwrap();
+        assert!(GitTapplet::new(manifest).is_err());
+    }
+    #[tokio::test]
+    async fn test_install_from_git_at_rev() {
+        let temp = tempfile::tempdir().unwrap();
+        let repo_dir = temp.path().join("repo");
+        let commit = init_repo(&repo_dir);
+        let cache = temp.path().join("cache");
+
+        let tapplet =
+            GitTapplet::new(manifest_with_git(repo_dir.to_str().unwrap(), &commit)).unwrap();
+        tapplet.install(cache.clone()).await.unwrap();
+
+        assert!(cache.join("git-lua").join("git-lua.lua").exists());
+        assert!(cache.join("git-lua").join("manifest.toml").exists());
+    }
+
+    #[tokio::test]
+    async fn test_reinstall_follows_install_options() {
+        let temp = tempfile::tempdir().unwrap();
+        let repo_dir = temp.path().join("repo");
+        let commit = init_repo(&repo_dir);
+        let cache = temp.path().join("cache");
+        let manifest = manifest_with_git(repo_dir.to_str().unwrap(), &commit);
+
+        let tapplet = GitTapplet::new(manifest.clone()).unwrap();
+        assert!(!tapplet.install(cache.clone()).await.unwrap().skipped);
+        assert!(tapplet.install(cache.clone()).await.unwrap().skipped);
+
+        let forced = tapplet.clone().with_install_options(InstallOptions {
+            force: true,
+            ..InstallOptions::default()
+        });
+        std::fs::write(cache.join("git-lua/stale.txt"), "").unwrap();
+        assert!(!forced.install(cache.clone()).await.unwrap().skipped);
+        assert!(!cache.join("git-lua/stale.txt").exists());
+
+        std::fs::write(
+            cache.join("git-lua/manifest.toml"),
+            test_utils::manifest_toml("git-lua", "0.0.1"),
+        )
+        .unwrap();
+        let strict = tapplet.with_install_options(InstallOptions {
+            on_conflict: crate::install::OnConflict::Error,
+            ..InstallOptions::default()
+        });
+        let err = strict.install(cache.clone()).await.unwrap_err();
+        assert_eq!(crate::error_code(&err), "ALREADY_INSTALLED");
+    }
+
+    #[tokio::test]
+    async fn test_install_rejects_mismatched_manifest() {
+        let temp = tempfile::tempdir().unwrap();
+        let repo_dir = temp.path().join("repo");
+        let commit = init_repo(&repo_dir);
+
+        let mut manifest = manifest_with_git(repo_dir.to_str().unwrap(), &commit);
+        manifest.public_key = "another_key".to_string();
+        let tapplet = GitTapplet::new(manifest).unwrap();
+        assert!(tapplet.install(temp.path().join("cache")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_install_rejects_manifest_with_other_content() {
+        let temp = tempfile::tempdir().unwrap();
+        let repo_dir = temp.path().join("repo");
+        let commit = init_repo(&repo_dir);
+        let cache = temp.path().join("cache");
+
+        let mut other_publisher = manifest_with_git(repo_dir.to_str().unwrap(), &commit);
+        other_publisher.publisher = "other_publisher".to_string();
+        let mut other_description = manifest_with_git(repo_dir.to_str().unwrap(), &commit);
+        other_description.description = Some("Something else".to_string());
+        for (manifest, field) in [
+            (other_publisher, "publisher"),
+            (other_description, "digest"),
+        ] {
+            let tapplet = GitTapplet::new(manifest).unwrap();
+            let err = tapplet.install(cache.clone()).await.unwrap_err();
+            assert_eq!(crate::error_code(&err), "MANIFEST_MISMATCH");
+            assert!(err.to_string().contains(field), "{}", err);
+            assert!(!cache.join("git-lua").exists());
+        }
     }
 }
@@ -0,0 +1,425 @@
+//! Budgets shared by every running tapplet.
+//!
+//! Per-host limits like [`crate::rate_limit`] and execution budgets stop one
+//! tapplet from running away, but not twenty well-behaved ones from adding up. A
+//! [`ResourceGovernor`] is shared by all hosts, set with `with_governor`, and
+//! enforces global budgets:
+//!
+//! - at most `max_concurrent_calls` calls run at once; calls over it are throttled
+//! - a tapplet may spend `call_time_per_minute` in calls each minute; its calls
+//!   are throttled until the minute is over
+//! - hosts report their memory after each call, and once all of them
+//!   together use more than `total_memory` the one using the most is evicted. Its
+//!   calls are throttled until its host is reloaded or
+//!   [`ResourceGovernor::release`] is called.
+//!
+//! Throttled calls fail with [`HostError::Throttled`].
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::host::HostError;
+#[cfg(feature = "metrics")]
+use crate::metrics::{self, MetricsSink};
+
+/// Why a call was throttled, the `reason` label of throttling metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ThrottleReason {
+    /// Too many calls were already running
+    Concurrency,
+    /// The tapplet used up its call time for the minute
+    CallTime,
+    /// The tapplet was evicted for using the most memory
+    Evicted,
+}
+
+impl ThrottleReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThrottleReason::Concurrency => "concurrency",
+            ThrottleReason::CallTime => "call_time",
+            ThrottleReason::Evicted => "evicted",
+        }
+    }
+}
+
+/// Limits across all hosts sharing a [`ResourceGovernor`]. `None` doesn't limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceBudgets {
+    /// Bytes all tapplets may use together: the Lua states and WASM memories
+    pub total_memory: Option<usize>,
+    /// Calls that may run at once
+    pub max_concurrent_calls: Option<usize>,
+    /// Time each tapplet may spend in calls per minute
+    pub call_time_per_minute: Option<Duration>,
+}
+
+impl ResourceBudgets {
+    pub fn with_total_memory(mut self, bytes: usize) -> Self {
+        self.total_memory = Some(bytes);
+        self
+    }
+
+    pub fn with_max_concurrent_calls(mut self, calls: usize) -> Self {
+        self.max_concurrent_calls = Some(calls);
+        self
+    }
+
+    pub fn with_call_time_per_minute(mut self, time: Duration) -> Self {
+        self.call_time_per_minute = Some(time);
+        self
+    }
+}
+
+/// What the governor knows about one tapplet
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TappletUsage {
+    /// Memory reported after its last call, in bytes
+    pub memory: usize,
+    /// Time spent in calls this minute
+    pub call_time: Duration,
+    /// Calls throttled since the governor was created, by reason
+    pub throttled: BTreeMap<ThrottleReason, u64>,
+    pub evicted: bool,
+}
+
+/// Snapshot of a [`ResourceGovernor`], see [`ResourceGovernor::usage`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GovernorUsage {
+    pub running_calls: usize,
+    /// Memory reported by all tapplets together, in bytes
+    pub total_memory: usize,
+    pub tapplets: BTreeMap<String, TappletUsage>,
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    tapplets: HashMap<String, Usage>,
+}
+
+struct Usage {
+    memory: usize,
+    /// Start of the minute `call_time` was spent in
+    minute: Instant,
+    call_time: Duration,
+    throttled: BTreeMap<ThrottleReason, u64>,
+    evicted: bool,
+}
+
+impl Usage {
+    fn new(now: Instant) -> Self {
+        Self {
+            memory: 0,
+            minute: now,
+            call_time: Duration::ZERO,
+            throttled: BTreeMap::new(),
+            evicted: false,
+        }
+    }
+
+    /// Call time spent in the minute `now` falls in
+    fn call_time_at(&mut self, now: Instant) -> Duration {
+        if now.saturating_duration_since(self.minute) >= Duration::from_secs(60) {
+            self.minute = now;
+            self.call_time = Duration::ZERO;
+        }
+        self.call_time
+    }
+}
+
+struct Inner {
+    budgets: ResourceBudgets,
+    state: Mutex<State>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<dyn MetricsSink>>,
+}
+
+/// Global budgets shared by every host it is set on, see the [module docs](self).
+/// Clones share the budgets and usage.
+#[derive(Clone)]
+pub struct ResourceGovernor {
+    inner: Arc<Inner>,
+}
+
+impl ResourceGovernor {
+    pub fn new(budgets: ResourceBudgets) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                budgets,
+                state: Mutex::default(),
+                #[cfg(feature = "metrics")]
+                metrics: None,
+            }),
+        }
+    }
+
+    /// Count throttled calls and evictions in `sink`. Only takes effect before
+    /// the governor is cloned or set on a host.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics_sink(mut self, sink: Arc<dyn MetricsSink>) -> Self {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            inner.metrics = Some(sink);
+        }
+        self
+    }
+
+    pub fn budgets(&self) -> ResourceBudgets {
+        self.inner.budgets
+    }
+
+    /// Start a call of `tapplet`, or fail with [`HostError::Throttled`] if a
+    /// budget doesn't allow it. The call counts as running until the permit is
+    /// dropped.
+    pub fn admit(&self, tapplet: &str) -> Result<CallPermit, HostError> {
+        self.admit_at(tapplet, Instant::now())
+    }
+
+    fn admit_at(&self, tapplet: &str, now: Instant) -> Result<CallPermit, HostError> {
+        let budgets = self.inner.budgets;
+        let mut state = self.inner.state.lock().unwrap();
+        let running = state.running;
+        let usage = state
+            .tapplets
+            .entry(tapplet.to_string())
+            .or_insert_with(|| Usage::new(now));
+        let throttled = if usage.evicted {
+            Some((
+                ThrottleReason::Evicted,
+                format!("{} was evicted for using too much memory", tapplet),
+            ))
+        } else if let Some(budget) = budgets.call_time_per_minute
+            && usage.call_time_at(now) >= budget
+        {
+            Some((
+                ThrottleReason::CallTime,
+                format!(
+                    "{} used its {:?} of call time for this minute",
+                    tapplet, budget
+                ),
+            ))
+        } else if let Some(max) = budgets.max_concurrent_calls
+            && running >= max
+        {
+            Some((
+                ThrottleReason::Concurrency,
+                format!("{} calls are already running", running),
+            ))
+        } else {
+            None
+        };
+        if let Some((reason, message)) = throttled {
+            *usage.throttled.entry(reason).or_default() += 1;
+            drop(state);
+            #[cfg(feature = "metrics")]
+            self.count(
+                metrics::CALLS_THROTTLED_TOTAL,
+                &[("tapplet", tapplet), ("reason", reason.as_str())],
+            );
+            return Err(HostError::Throttled(message));
+        }
+        state.running += 1;
+        Ok(CallPermit {
+            governor: self.clone(),
+            tapplet: tapplet.to_string(),
+            started: now,
+            memory: None,
+        })
+    }
+
+    /// Forget a tapplet's memory and lift its eviction, e.g. because its host
+    /// was reloaded or dropped. Hosts call this on reload.
+    pub fn release(&self, tapplet: &str) {
+        let mut state = self.inner.state.lock().unwrap();
+        if let Some(usage) = state.tapplets.get_mut(tapplet) {
+            usage.memory = 0;
+            usage.evicted = false;
+        }
+    }
+
+    /// What every tapplet that was admitted or throttled has used
+    pub fn usage(&self) -> GovernorUsage {
+        let now = Instant::now();
+        let mut state = self.inner.state.lock().unwrap();
+        let running_calls = state.running;
+        let tapplets: BTreeMap<_, _> = state
+            .tapplets
+            .iter_mut()
+            .map(|(name, usage)| {
+                (
+                    name.clone(),
+                    TappletUsage {
+                        memory: usage.memory,
+                        call_time: usage.call_time_at(now),
+                        throttled: usage.throttled.clone(),
+                        evicted: usage.evicted,
+                    },
+                )
+            })
+            .collect();
+        GovernorUsage {
+            running_calls,
+            total_memory: tapplets.values().map(|usage| usage.memory).sum(),
+            tapplets,
+        }
+    }
+
+    fn finish(&self, permit: &CallPermit, now: Instant) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.running -= 1;
+        let Some(usage) = state.tapplets.get_mut(&permit.tapplet) else {
+            return;
+        };
+        usage.call_time_at(now);
+        usage.call_time += now.saturating_duration_since(permit.started);
+        if let Some(memory) = permit.memory {
+            usage.memory = memory;
+        }
+
+        let Some(budget) = self.inner.budgets.total_memory else {
+            return;
+        };
+        let total: usize = state.tapplets.values().map(|usage| usage.memory).sum();
+        if total <= budget {
+            return;
+        }
+        let Some((worst, usage)) = state
+            .tapplets
+            .iter_mut()
+            .filter(|(_, usage)| !usage.evicted)
+            .max_by_key(|(_, usage)| usage.memory)
+        else {
+            return;
+        };
+        usage.evicted = true;
+        let worst = worst.clone();
+        drop(state);
+        crate::trace::warning!(
+            "Evicted tapplet {}: tapplets use {} bytes of memory, more than the {} allowed",
+            worst,
+            total,
+            budget
+        );
+        #[cfg(feature = "metrics")]
+        self.count(metrics::TAPPLETS_EVICTED_TOTAL, &[("tapplet", &worst)]);
+    }
+
+    #[cfg(feature = "metrics")]
+    fn count(&self, name: &'static str, labels: metrics::Labels<'_>) {
+        if let Some(sink) = &self.inner.metrics {
+            sink.increment_counter(name, labels, 1);
+        }
+    }
+}
+
+impl std::fmt::Debug for ResourceGovernor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResourceGovernor")
+            .field("budgets", &self.inner.budgets)
+            .finish()
+    }
+}
+
+/// A call admitted by a [`ResourceGovernor`]. Dropping it ends the call.
+#[derive(Debug)]
+pub struct CallPermit {
+    governor: ResourceGovernor,
+    tapplet: String,
+    started: Instant,
+    memory: Option<usize>,
+}
+
+impl CallPermit {
+    /// Report the memory the tapplet uses now the call is over
+    pub fn report_memory(&mut self, bytes: usize) {
+        self.memory = Some(bytes);
+    }
+}
+
+impl Drop for CallPermit {
+    fn drop(&mut self) {
+        self.governor.finish(self, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_governor_budgets() {
+        let governor = ResourceGovernor::new(
+            ResourceBudgets::default()
+                .with_max_concurrent_calls(2)
+                .with_call_time_per_minute(Duration::from_secs(1))
+                .with_total_memory(1000),
+        );
+        #[cfg(feature = "metrics")]
+        let sink = Arc::new(crate::metrics::MemoryMetricsSink::new());
+        #[cfg(feature = "metrics")]
+        let governor = governor.with_metrics_sink(sink.clone());
+        let start = Instant::now();
+
+        // A third call at once is throttled
+        let first = governor.admit_at("wallet", start).unwrap();
+        let second = governor.admit_at("notes", start).unwrap();
+        let err = governor.admit_at("chat", start).unwrap_err();
+        assert_eq!(err.code(), "THROTTLED");
+        drop(first);
+        drop(second);
+        governor.admit_at("chat", start).unwrap();
+
+        // A tapplet that spent its call time waits for the next minute
+        let mut permit = governor.admit_at("wallet", start).unwrap();
+        permit.started = start - Duration::from_secs(2);
+        drop(permit);
+        let err = governor.admit_at("wallet", start).unwrap_err();
+        assert!(err.to_string().contains("call time"), "{}", err);
+        governor
+            .admit_at("wallet", start + Duration::from_secs(61))
+            .unwrap();
+
+        // Going over the memory budget evicts the biggest tapplet until released
+        let mut notes = governor.admit_at("notes", start).unwrap();
+        notes.report_memory(300);
+        drop(notes);
+        let mut chat = governor.admit_at("chat", start).unwrap();
+        chat.report_memory(800);
+        drop(chat);
+        assert!(governor.admit_at("notes", start).is_ok());
+        let err = governor.admit_at("chat", start).unwrap_err();
+        assert!(err.to_string().contains("evicted"), "{}", err);
+
+        let usage = governor.usage();
+        assert_eq!(usage.running_calls, 0);
+        assert_eq!(usage.total_memory, 1100);
+        let chat = &usage.tapplets["chat"];
+        assert!(chat.evicted);
+        assert_eq!(chat.throttled[&ThrottleReason::Concurrency], 1);
+        assert_eq!(chat.throttled[&ThrottleReason::Evicted], 1);
+        assert_eq!(
+            usage.tapplets["wallet"].throttled[&ThrottleReason::CallTime],
+            1
+        );
+
+        #[cfg(feature = "metrics")]
+        {
+            let throttled = |reason| {
+                sink.counter(
+                    metrics::CALLS_THROTTLED_TOTAL,
+                    &[("tapplet", "chat"), ("reason", reason)],
+                )
+            };
+            assert_eq!(throttled("concurrency"), 1);
+            assert_eq!(throttled("evicted"), 1);
+            assert_eq!(
+                sink.counter(metrics::TAPPLETS_EVICTED_TOTAL, &[("tapplet", "chat")]),
+                1
+            );
+        }
+
+        governor.release("chat");
+        assert!(governor.admit_at("chat", start).is_ok());
+    }
+}
@@ -1,151 +1,1464 @@
-use crate::model::TappletManifest;
+use crate::ambient::{Ambient, AmbientMode};
+use crate::audit::{AuditKind, AuditSink, Auditor, summarize_args};
+use crate::call_context::CallContext;
+use crate::cancel::{CancellationToken, Timer};
+use crate::chain::{GuestChain, MinotariChainApi};
+use crate::engine::{
+    self, CallLimits, CompiledModule, FunctionExport, HostImports, WasmEngine, WasmInstance,
+    WasmType, WasmValue,
+};
+use crate::events::EventSender;
+use crate::governor::{CallPermit, ResourceGovernor};
+use crate::host_options::HostOptions;
+use crate::intercept::{CallInterceptor, InterceptedCall, Interceptors};
+use crate::log_sink::{GuestLog, LogLevel, LogRecord, LogSink, MAX_LOG_MESSAGE_BYTES};
+use crate::lua_json::{self, BoxedInteger, TableConversion};
+#[cfg(feature = "metrics")]
+use crate::metrics::{Meter, MetricsSink};
+use crate::model::{HOST_API_VERSION, MethodDefinition, ParamType, RuntimeKind, TappletManifest};
+use crate::module_cache::ModuleCache;
+use crate::network::{GuestNetwork, HttpMethod, HttpRequest, MAX_URL_BYTES, NetworkAccess};
+use crate::rate_limit::{RateLimit, RateLimiter};
+use crate::result_cache::ResultCache;
+use crate::router::{RouterHandle, TappletRouter};
+use crate::sandbox::SandboxOptions;
+use crate::secure_storage::StorageKey;
+use crate::storage::{StagedWrites, StorageQuota, TappletStorage};
+use crate::trace;
+use crate::wallet::{
+    Balance, GuestWallet, MinotariTappletApiV2, PERMISSION_READ_BALANCE,
+    PERMISSION_READ_TRANSACTIONS, PERMISSION_SEND_TRANSACTION, TransactionFilter, TransactionInfo,
+};
+use crate::wasi::{GuestMemory, WasiOptions};
 use async_trait::async_trait;
+use futures_core::Stream;
+use serde::Serialize;
 use serde_json::Value;
 use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant};
 use tokio::{runtime::Handle, task};
-use wasmer::{Instance, Module, Store, Value as WasmValue};
 
-#[cfg(feature = "host")]
+#[cfg(feature = "host-core")]
 use mlua::Lua;
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum HostError {
+    #[error("WASM load error: {0}")]
     WasmLoadError(String),
+    #[error("WASM compile error: {0}")]
     WasmCompileError(String),
+    #[error("WASM instantiation error: {0}")]
     WasmInstantiationError(String),
+    #[error("Lua load error: {0}")]
     LuaLoadError(String),
-    LuaExecutionError(String),
+    #[error("Lua execution error: {0}")]
+    LuaExecutionError(LuaErrorDetails),
+    #[error("Method not found: {0}")]
     MethodNotFound(String),
+    #[error("Execution error: {0}")]
     ExecutionError(String),
+    #[error("Invalid arguments: {0}")]
     InvalidArguments(String),
-    IoError(std::io::Error),
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("Execution budget exceeded: {0}")]
+    ExecutionBudgetExceeded(String),
+    #[error("Call cancelled: {0}")]
+    Cancelled(String),
+    #[error("Call timed out: {0}")]
+    Timeout(String),
+    #[error("Memory limit exceeded: {0}")]
+    MemoryLimitExceeded(String),
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+    #[error("Batch aborted: {0}")]
+    BatchAborted(String),
+    #[error("Throttled: {0}")]
+    Throttled(String),
+    #[error("Storage quota exceeded: {0}")]
+    StorageQuotaExceeded(String),
+    #[error("Tapplet not found: {0}")]
+    TappletNotFound(String),
+    #[error("Re-entrant tapplet call: {0}")]
+    ReentrantCall(String),
+    #[error("Call depth exceeded: {0}")]
+    CallDepthExceeded(String),
+    #[error("{0}")]
+    IntegrityMismatch(String),
+    #[error("{0}")]
+    UnsupportedHostApi(String),
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+    #[error("Tapplet process crashed: {0}")]
+    ProcessCrashed(String),
+    #[error("Network error: {0}")]
+    NetworkError(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
 }
 
-impl std::fmt::Display for HostError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl HostError {
+    /// Stable machine-readable code for this error
+    pub fn code(&self) -> &'static str {
         match self {
-            HostError::WasmLoadError(msg) => write!(f, "WASM load error: {}", msg),
-            HostError::WasmCompileError(msg) => write!(f, "WASM compile error: {}", msg),
-            HostError::WasmInstantiationError(msg) => {
-                write!(f, "WASM instantiation error: {}", msg)
-            }
-            HostError::LuaLoadError(msg) => write!(f, "Lua load error: {}", msg),
-            HostError::LuaExecutionError(msg) => write!(f, "Lua execution error: {}", msg),
-            HostError::MethodNotFound(method) => write!(f, "Method not found: {}", method),
-            HostError::ExecutionError(msg) => write!(f, "Execution error: {}", msg),
-            HostError::InvalidArguments(msg) => write!(f, "Invalid arguments: {}", msg),
-            HostError::IoError(err) => write!(f, "IO error: {}", err),
+            HostError::WasmLoadError(_) => "WASM_LOAD_ERROR",
+            HostError::WasmCompileError(_) => "WASM_COMPILE_ERROR",
+            HostError::WasmInstantiationError(_) => "WASM_INSTANTIATION_ERROR",
+            HostError::LuaLoadError(_) => "LUA_LOAD_ERROR",
+            HostError::LuaExecutionError(_) => "LUA_EXECUTION_ERROR",
+            HostError::MethodNotFound(_) => "METHOD_NOT_FOUND",
+            HostError::ExecutionError(_) => "EXECUTION_ERROR",
+            HostError::InvalidArguments(_) => "INVALID_ARGUMENTS",
+            HostError::PermissionDenied(_) => "PERMISSION_DENIED",
+            HostError::ExecutionBudgetExceeded(_) => "EXECUTION_BUDGET_EXCEEDED",
+            HostError::Cancelled(_) => "CANCELLED",
+            HostError::Timeout(_) => "TIMEOUT",
+            HostError::MemoryLimitExceeded(_) => "MEMORY_LIMIT_EXCEEDED",
+            HostError::RateLimited(_) => "RATE_LIMITED",
+            HostError::Throttled(_) => "THROTTLED",
+            HostError::BatchAborted(_) => "BATCH_ABORTED",
+            HostError::StorageQuotaExceeded(_) => "STORAGE_QUOTA_EXCEEDED",
+            HostError::TappletNotFound(_) => "TAPPLET_NOT_FOUND",
+            HostError::ReentrantCall(_) => "REENTRANT_CALL",
+            HostError::CallDepthExceeded(_) => "CALL_DEPTH_EXCEEDED",
+            HostError::IntegrityMismatch(_) => "INTEGRITY_MISMATCH",
+            HostError::UnsupportedHostApi(_) => "UNSUPPORTED_HOST_API",
+            HostError::Unsupported(_) => "UNSUPPORTED",
+            HostError::ProcessCrashed(_) => "PROCESS_CRASHED",
+            HostError::NetworkError(_) => "NETWORK_ERROR",
+            HostError::IoError(_) => "IO_ERROR",
+        }
+    }
+
+    /// The message without the prefix naming the kind of error, so it can be
+    /// sent elsewhere and rebuilt with [`HostError::from_code`]
+    pub(crate) fn detail(&self) -> String {
+        match self {
+            HostError::WasmLoadError(detail)
+            | HostError::WasmCompileError(detail)
+            | HostError::WasmInstantiationError(detail)
+            | HostError::LuaLoadError(detail)
+            | HostError::MethodNotFound(detail)
+            | HostError::ExecutionError(detail)
+            | HostError::InvalidArguments(detail)
+            | HostError::PermissionDenied(detail)
+            | HostError::ExecutionBudgetExceeded(detail)
+            | HostError::Cancelled(detail)
+            | HostError::Timeout(detail)
+            | HostError::MemoryLimitExceeded(detail)
+            | HostError::RateLimited(detail)
+            | HostError::Throttled(detail)
+            | HostError::BatchAborted(detail)
+            | HostError::StorageQuotaExceeded(detail)
+            | HostError::TappletNotFound(detail)
+            | HostError::ReentrantCall(detail)
+            | HostError::CallDepthExceeded(detail)
+            | HostError::IntegrityMismatch(detail)
+            | HostError::UnsupportedHostApi(detail)
+            | HostError::Unsupported(detail)
+            | HostError::ProcessCrashed(detail)
+            | HostError::NetworkError(detail) => detail.clone(),
+            HostError::LuaExecutionError(details) => details.message.clone(),
+            HostError::IoError(e) => e.to_string(),
         }
     }
-}
 
-impl std::error::Error for HostError {}
+    /// The error with the given [`HostError::code`] and [`HostError::detail`].
+    /// Unknown codes become [`HostError::ExecutionError`].
+    pub(crate) fn from_code(code: &str, detail: String) -> HostError {
+        match code {
+            "WASM_LOAD_ERROR" => HostError::WasmLoadError(detail),
+            "WASM_COMPILE_ERROR" => HostError::WasmCompileError(detail),
+            "WASM_INSTANTIATION_ERROR" => HostError::WasmInstantiationError(detail),
+            "LUA_LOAD_ERROR" => HostError::LuaLoadError(detail),
+            "LUA_EXECUTION_ERROR" => HostError::LuaExecutionError(LuaErrorDetails::new(detail)),
+            "METHOD_NOT_FOUND" => HostError::MethodNotFound(detail),
+            "INVALID_ARGUMENTS" => HostError::InvalidArguments(detail),
+            "PERMISSION_DENIED" => HostError::PermissionDenied(detail),
+            "EXECUTION_BUDGET_EXCEEDED" => HostError::ExecutionBudgetExceeded(detail),
+            "CANCELLED" => HostError::Cancelled(detail),
+            "TIMEOUT" => HostError::Timeout(detail),
+            "MEMORY_LIMIT_EXCEEDED" => HostError::MemoryLimitExceeded(detail),
+            "RATE_LIMITED" => HostError::RateLimited(detail),
+            "THROTTLED" => HostError::Throttled(detail),
+            "BATCH_ABORTED" => HostError::BatchAborted(detail),
+            "STORAGE_QUOTA_EXCEEDED" => HostError::StorageQuotaExceeded(detail),
+            "TAPPLET_NOT_FOUND" => HostError::TappletNotFound(detail),
+            "REENTRANT_CALL" => HostError::ReentrantCall(detail),
+            "CALL_DEPTH_EXCEEDED" => HostError::CallDepthExceeded(detail),
+            "INTEGRITY_MISMATCH" => HostError::IntegrityMismatch(detail),
+            "UNSUPPORTED_HOST_API" => HostError::UnsupportedHostApi(detail),
+            "UNSUPPORTED" => HostError::Unsupported(detail),
+            "PROCESS_CRASHED" => HostError::ProcessCrashed(detail),
+            "NETWORK_ERROR" => HostError::NetworkError(detail),
+            "IO_ERROR" => HostError::IoError(std::io::Error::other(detail)),
+            _ => HostError::ExecutionError(detail),
+        }
+    }
 
-impl From<std::io::Error> for HostError {
-    fn from(err: std::io::Error) -> Self {
-        HostError::IoError(err)
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({ "code": self.code(), "message": self.to_string() })
     }
 }
 
-impl From<wasmer::CompileError> for HostError {
-    fn from(err: wasmer::CompileError) -> Self {
-        HostError::WasmCompileError(err.to_string())
+#[cfg(feature = "host-core")]
+impl From<mlua::Error> for HostError {
+    fn from(err: mlua::Error) -> Self {
+        HostError::LuaExecutionError(LuaErrorDetails::from_lua_error(&err))
     }
 }
 
-impl From<wasmer::InstantiationError> for HostError {
-    fn from(err: wasmer::InstantiationError) -> Self {
-        HostError::WasmInstantiationError(err.to_string())
+/// Admit a call with the host's governor, if it has one
+fn admit(
+    governor: Option<&ResourceGovernor>,
+    tapplet: &str,
+) -> Result<Option<CallPermit>, HostError> {
+    governor.map(|governor| governor.admit(tapplet)).transpose()
+}
+
+/// Raise an error from a host function, keeping a [`HostError`] recognisable by
+/// [`find_host_error`] once it has passed through the script
+fn to_lua_error(err: anyhow::Error) -> mlua::Error {
+    match err.downcast::<HostError>() {
+        Ok(err) => mlua::Error::external(err),
+        Err(err) => err.into(),
     }
 }
 
-impl From<wasmer::RuntimeError> for HostError {
-    fn from(err: wasmer::RuntimeError) -> Self {
-        HostError::ExecutionError(err.to_string())
+/// The host error a host function raised, if that is what made a call fail
+fn find_host_error(err: &mlua::Error) -> Option<&HostError> {
+    match err {
+        mlua::Error::CallbackError { cause, .. } => find_host_error(cause),
+        mlua::Error::ExternalError(err) => err.downcast_ref::<HostError>(),
+        _ => None,
     }
 }
 
-#[cfg(feature = "host")]
-impl From<mlua::Error> for HostError {
-    fn from(err: mlua::Error) -> Self {
-        HostError::LuaExecutionError(err.to_string())
+/// Errors raised by host functions that fail the whole call with their own code
+/// rather than as a Lua execution error
+fn propagated(err: &HostError) -> Option<HostError> {
+    Some(match err {
+        HostError::StorageQuotaExceeded(message) => {
+            HostError::StorageQuotaExceeded(message.clone())
+        }
+        HostError::PermissionDenied(message) => HostError::PermissionDenied(message.clone()),
+        HostError::RateLimited(message) => HostError::RateLimited(message.clone()),
+        HostError::TappletNotFound(name) => HostError::TappletNotFound(name.clone()),
+        HostError::ReentrantCall(message) => HostError::ReentrantCall(message.clone()),
+        HostError::CallDepthExceeded(message) => HostError::CallDepthExceeded(message.clone()),
+        _ => return None,
+    })
+}
+
+/// A Lua runtime error with where it was raised and the Lua stack trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LuaErrorDetails {
+    /// The error message, usually prefixed with its location, e.g. `main.lua:3: boom`
+    pub message: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub traceback: Option<String>,
+}
+
+impl LuaErrorDetails {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        let message = message.into();
+        let (file, line) = parse_location(&message).unzip();
+        Self {
+            message,
+            file,
+            line,
+            traceback: None,
+        }
+    }
+
+    pub fn from_lua_error(err: &mlua::Error) -> Self {
+        match err {
+            mlua::Error::CallbackError { traceback, cause } => {
+                // Raised by a host function, so locate it at the Lua code that called it
+                let mut details = Self::from_lua_error(cause);
+                if details.line.is_none() {
+                    (details.file, details.line) = traceback
+                        .lines()
+                        .find_map(|frame| parse_location(frame.trim()))
+                        .unzip();
+                }
+                details.traceback = Some(traceback.clone());
+                details
+            }
+            mlua::Error::RuntimeError(message) => match message.split_once("\nstack traceback:") {
+                Some((message, traceback)) => Self {
+                    traceback: Some(format!("stack traceback:{}", traceback)),
+                    ..Self::new(message)
+                },
+                None => Self::new(message.as_str()),
+            },
+            mlua::Error::SyntaxError { message, .. } => Self::new(message.as_str()),
+            _ => Self::new(err.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for LuaErrorDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(traceback) = &self.traceback {
+            write!(f, "\n{}", traceback)?;
+        }
+        Ok(())
     }
 }
 
+/// Parse the `file:line:` prefix Lua puts in front of error messages and stack frames
+fn parse_location(text: &str) -> Option<(String, u32)> {
+    let mut parts = text.splitn(3, ':');
+    let file = parts.next()?;
+    let line = parts.next()?.parse().ok()?;
+    parts.next()?;
+    (!file.is_empty() && !file.starts_with('[')).then(|| (file.to_string(), line))
+}
+
+pub use crate::build::{
+    GUEST_ALLOC_EXPORT, GUEST_DEALLOC_EXPORT, GUEST_HEALTH_EXPORT, GUEST_INIT_EXPORT,
+    GUEST_LAST_PANIC_EXPORT, GUEST_NEXT_CHUNK_EXPORT,
+};
+
+/// Largest response a WASM tapplet may return from a call, in bytes
+pub const MAX_RESPONSE_BYTES: u64 = 16 * 1024 * 1024;
+
 pub struct WasmTappletHost {
     config: TappletManifest,
-    store: Store,
-    instance: Instance,
+    engine: Arc<dyn WasmEngine>,
+    instance: Box<dyn WasmInstance>,
+    audit: Auditor,
+    #[cfg(feature = "metrics")]
+    metrics: Meter,
+    /// Kept to give the module a fresh WASI environment on [`WasmTappletHost::reload`]
+    wasi: Option<WasiOptions>,
+    rate_limiter: RateLimiter,
+    governor: Option<ResourceGovernor>,
+    interceptors: Interceptors,
+    result_cache: Option<ResultCache>,
+    timeout: Option<Duration>,
+    execution_budget: Option<u64>,
+    memory_limit: Option<usize>,
+    stack_limit: Option<usize>,
+    /// Token of the call in progress, see [`WasmTappletHost::run_with_cancel`]
+    cancel: Option<CancellationToken>,
+    /// Whether [`WasmTappletHost::init`] ran, so reloading runs it again
+    initialized: bool,
+    /// Where the module's `minotari_log` output goes
+    log: GuestLog,
+    /// Time and randomness of `minotari_now` and `minotari_random_bytes`
+    ambient: Ambient,
+    /// Where `minotari_http_get` and `minotari_http_post` requests go, if anywhere
+    network: GuestNetwork,
+    /// What answers `minotari_get_tip_height` and the other chain queries
+    chain: GuestChain,
+    /// What answers `minotari_get_balance` and the other wallet functions
+    wallet: GuestWallet,
+}
+
+/// Chunks of a method's result, from [`WasmTappletHost::run_stream`].
+///
+/// Modules built with `tari-tapplet-guest` stream a result by calling its `stream`
+/// function; the host pulls the chunks through the guest's
+/// [`GUEST_NEXT_CHUNK_EXPORT`]. A result the method returns itself, such as the
+/// whole result of a method that doesn't stream, is the first chunk. Each pull
+/// counts as a call of the method for timeouts, the governor, the audit log and
+/// metrics. When the return type is `array<T>` chunks are `T`s.
+pub struct WasmChunkStream<'a> {
+    host: &'a mut WasmTappletHost,
+    method: String,
+    /// Recorded in the audit log for every pull
+    args: Value,
+    first: Option<Value>,
+    done: bool,
+}
+
+impl Iterator for WasmChunkStream<'_> {
+    type Item = Result<Value, HostError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(first) = self.first.take() {
+            return Some(Ok(first));
+        }
+        if self.done {
+            return None;
+        }
+        let method = &self.method;
+        let chunk = self.host.instrumented(method, &self.args, |host| {
+            host.with_call_timeout(method, |host| host.next_json_chunk(method))
+        });
+        match chunk {
+            Ok(Some(chunk)) => Some(Ok(chunk)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl Stream for WasmChunkStream<'_> {
+    type Item = Result<Value, HostError>;
+
+    fn poll_next(self: Pin<&mut Self>, _: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.get_mut().next())
+    }
+}
+
+/// Type of the chunks of a streamed result of type `return_type`, `None` if
+/// they can be anything
+fn chunk_type(return_type: &ParamType) -> Option<&ParamType> {
+    match return_type {
+        ParamType::Array(item_type) => Some(item_type),
+        _ => None,
+    }
+}
+
+/// The cache to answer calls of `method` from, if the host has one and the
+/// method is pure
+fn pure_method_cache<'a>(
+    cache: Option<&'a ResultCache>,
+    config: &TappletManifest,
+    method: &str,
+) -> Option<&'a ResultCache> {
+    cache.filter(|_| {
+        config
+            .api
+            .method(method)
+            .is_some_and(|definition| definition.pure)
+    })
+}
+
+/// Host functions WASM tapplets can import from [`engine::HOST_MODULE`]:
+///
+/// - `minotari_log(level: i32, ptr: i32, len: i32)` logs the UTF-8 message at
+///   `ptr` at level 0 (debug), 1 (info), 2 (warn) or 3 (error)
+/// - `minotari_now() -> i64` returns milliseconds since the Unix epoch
+/// - `minotari_random_bytes(ptr: i32, len: i32)` fills `len` bytes at `ptr`
+/// - `minotari_http_get(url_ptr: i32, url_len: i32) -> i32` and
+///   `minotari_http_post(url_ptr: i32, url_len: i32, body_ptr: i32, body_len: i32,
+///   type_ptr: i32, type_len: i32) -> i32` send a request and return the length
+///   of its result, `{"ok": {"status": 200, "body": "..."}}` or
+///   `{"err": {"code": "...", "message": "..."}}`. An empty content type means
+///   [`crate::network::DEFAULT_CONTENT_TYPE`].
+/// - `minotari_get_tip_height() -> i32`, `minotari_get_block_header(height: i64) -> i32`
+///   and `minotari_get_kernel(excess_ptr: i32, excess_len: i32) -> i32` query the
+///   chain and return the length of their result like the HTTP functions
+/// - `minotari_get_balance() -> i32`, `minotari_send_transaction(dest_ptr: i32,
+///   dest_len: i32, amount: i64, fee: i64) -> i32` and
+///   `minotari_get_transactions(filter_ptr: i32, filter_len: i32) -> i32` call the
+///   wallet with the permissions of the running call. The filter is the JSON of a
+///   [`TransactionFilter`], empty for all transactions.
+/// - `minotari_take_result(ptr: i32, len: i32)` copies that result to `ptr`
+fn host_imports(
+    log: &GuestLog,
+    ambient: &Ambient,
+    network: &GuestNetwork,
+    chain: &GuestChain,
+    wallet: &GuestWallet,
+) -> HostImports {
+    let log = log.clone();
+    let now_ambient = ambient.clone();
+    let random_ambient = ambient.clone();
+    // Result of the last request, until the module takes it
+    let pending = Arc::new(Mutex::new(Vec::new()));
+    let get_network = network.clone();
+    let get_pending = pending.clone();
+    let post_network = network.clone();
+    let post_pending = pending.clone();
+    let tip_chain = chain.clone();
+    let tip_pending = pending.clone();
+    let header_chain = chain.clone();
+    let header_pending = pending.clone();
+    let kernel_chain = chain.clone();
+    let kernel_pending = pending.clone();
+    let balance_wallet = wallet.clone();
+    let balance_pending = pending.clone();
+    let send_wallet = wallet.clone();
+    let send_pending = pending.clone();
+    let transactions_wallet = wallet.clone();
+    let transactions_pending = pending.clone();
+    HostImports::new()
+        .with_function(
+            "minotari_log",
+            &[WasmType::I32, WasmType::I32, WasmType::I32],
+            &[],
+            move |memory, args| {
+                let [
+                    WasmValue::I32(level),
+                    WasmValue::I32(ptr),
+                    WasmValue::I32(len),
+                ] = *args
+                else {
+                    return Err("minotari_log takes a level, pointer and length".to_string());
+                };
+                let level = match level {
+                    0 => LogLevel::Debug,
+                    1 => LogLevel::Info,
+                    2 => LogLevel::Warn,
+                    3 => LogLevel::Error,
+                    other => return Err(format!("unknown log level {}", other)),
+                };
+                let (ptr, len) = (ptr as u32 as u64, len as u32 as u64);
+                if ptr + len > memory.size() {
+                    return Err("log message is outside of the module's memory".to_string());
+                }
+                // Longer messages are truncated anyway, so don't read the rest
+                let mut message = vec![0; len.min(MAX_LOG_MESSAGE_BYTES as u64) as usize];
+                if !memory.read(ptr, &mut message) {
+                    return Err("log message is outside of the module's memory".to_string());
+                }
+                log.log(level, String::from_utf8_lossy(&message).into_owned());
+                Ok(Vec::new())
+            },
+        )
+        .with_function("minotari_now", &[], &[WasmType::I64], move |_, _| {
+            Ok(vec![WasmValue::I64(now_ambient.now_ms() as i64)])
+        })
+        .with_function(
+            "minotari_random_bytes",
+            &[WasmType::I32, WasmType::I32],
+            &[],
+            move |memory, args| {
+                let [WasmValue::I32(ptr), WasmValue::I32(len)] = *args else {
+                    return Err("minotari_random_bytes takes a pointer and length".to_string());
+                };
+                let bytes = random_ambient.random_bytes(len as u32 as usize)?;
+                if !memory.write(ptr as u32 as u64, &bytes) {
+                    return Err("random buffer is outside of the module's memory".to_string());
+                }
+                Ok(Vec::new())
+            },
+        )
+        .with_function(
+            "minotari_http_get",
+            &[WasmType::I32, WasmType::I32],
+            &[WasmType::I32],
+            move |memory, args| {
+                let [WasmValue::I32(ptr), WasmValue::I32(len)] = *args else {
+                    return Err("minotari_http_get takes a pointer and length".to_string());
+                };
+                let url = read_guest_string(memory, ptr, len, "URL", MAX_URL_BYTES)?;
+                let result = get_network.fetch(&HttpRequest::get(&url));
+                Ok(vec![store_pending_result(&get_pending, result)])
+            },
+        )
+        .with_function(
+            "minotari_http_post",
+            &[WasmType::I32; 6],
+            &[WasmType::I32],
+            move |memory, args| {
+                let [
+                    WasmValue::I32(url_ptr),
+                    WasmValue::I32(url_len),
+                    WasmValue::I32(body_ptr),
+                    WasmValue::I32(body_len),
+                    WasmValue::I32(type_ptr),
+                    WasmValue::I32(type_len),
+                ] = *args
+                else {
+                    return Err(
+                        "minotari_http_post takes pointers and lengths of a URL, body and content type"
+                            .to_string(),
+                    );
+                };
+                let url = read_guest_string(memory, url_ptr, url_len, "URL", MAX_URL_BYTES)?;
+                // Too large a body is the tapplet's error to handle, not a trap
+                let max_body = post_network.limits().max_request_bytes;
+                if body_len as u32 as usize > max_body {
+                    let result: Result<(), _> = Err(HostError::InvalidArguments(format!(
+                        "request body of {} bytes is larger than the limit of {} bytes",
+                        body_len as u32, max_body
+                    )));
+                    return Ok(vec![store_pending_result(&post_pending, result)]);
+                }
+                let body = read_guest_string(memory, body_ptr, body_len, "body", max_body)?;
+                let content_type = read_guest_string(
+                    memory,
+                    type_ptr,
+                    type_len,
+                    "content type",
+                    MAX_GUEST_FIELD_BYTES,
+                )?;
+                let request = HttpRequest::post(
+                    &url,
+                    body,
+                    (!content_type.is_empty()).then_some(content_type),
+                );
+                let result = post_network.fetch(&request);
+                Ok(vec![store_pending_result(&post_pending, result)])
+            },
+        )
+        .with_function(
+            "minotari_get_tip_height",
+            &[],
+            &[WasmType::I32],
+            move |_, _| {
+                let result = tip_chain.tip_height();
+                Ok(vec![store_pending_result(&tip_pending, result)])
+            },
+        )
+        .with_function(
+            "minotari_get_block_header",
+            &[WasmType::I64],
+            &[WasmType::I32],
+            move |_, args| {
+                let [WasmValue::I64(height)] = *args else {
+                    return Err("minotari_get_block_header takes a height".to_string());
+                };
+                let result = header_chain.block_header(height as u64);
+                Ok(vec![store_pending_result(&header_pending, result)])
+            },
+        )
+        .with_function(
+            "minotari_get_kernel",
+            &[WasmType::I32, WasmType::I32],
+            &[WasmType::I32],
+            move |memory, args| {
+                let [WasmValue::I32(ptr), WasmValue::I32(len)] = *args else {
+                    return Err("minotari_get_kernel takes a pointer and length".to_string());
+                };
+                let excess =
+                    read_guest_string(memory, ptr, len, "kernel excess", MAX_GUEST_FIELD_BYTES)?;
+                let result = kernel_chain.kernel(&excess);
+                Ok(vec![store_pending_result(&kernel_pending, result)])
+            },
+        )
+        .with_function(
+            "minotari_get_balance",
+            &[],
+            &[WasmType::I32],
+            move |_, _| {
+                let result = balance_wallet.balance();
+                Ok(vec![store_pending_result(&balance_pending, result)])
+            },
+        )
+        .with_function(
+            "minotari_send_transaction",
+            &[WasmType::I32, WasmType::I32, WasmType::I64, WasmType::I64],
+            &[WasmType::I32],
+            move |memory, args| {
+                let [
+                    WasmValue::I32(ptr),
+                    WasmValue::I32(len),
+                    WasmValue::I64(amount),
+                    WasmValue::I64(fee),
+                ] = *args
+                else {
+                    return Err(
+                        "minotari_send_transaction takes a destination pointer and length, an amount and a fee"
+                            .to_string(),
+                    );
+                };
+                let destination =
+                    read_guest_string(memory, ptr, len, "destination", MAX_GUEST_FIELD_BYTES)?;
+                let result = send_wallet.send_transaction(&destination, amount as u64, fee as u64);
+                Ok(vec![store_pending_result(&send_pending, result)])
+            },
+        )
+        .with_function(
+            "minotari_get_transactions",
+            &[WasmType::I32, WasmType::I32],
+            &[WasmType::I32],
+            move |memory, args| {
+                let [WasmValue::I32(ptr), WasmValue::I32(len)] = *args else {
+                    return Err("minotari_get_transactions takes a pointer and length".to_string());
+                };
+                let filter =
+                    read_guest_string(memory, ptr, len, "transaction filter", MAX_GUEST_FIELD_BYTES)?;
+                let result = if filter.is_empty() {
+                    Ok(TransactionFilter::default())
+                } else {
+                    serde_json::from_str(&filter).map_err(|e| {
+                        HostError::InvalidArguments(format!("invalid transaction filter: {}", e))
+                    })
+                }
+                .and_then(|filter| transactions_wallet.transactions(&filter));
+                Ok(vec![store_pending_result(&transactions_pending, result)])
+            },
+        )
+        .with_function(
+            "minotari_take_result",
+            &[WasmType::I32, WasmType::I32],
+            &[],
+            move |memory, args| {
+                let [WasmValue::I32(ptr), WasmValue::I32(len)] = *args else {
+                    return Err("minotari_take_result takes a pointer and length".to_string());
+                };
+                let result = std::mem::take(&mut *pending.lock().unwrap());
+                let len = (len as u32 as usize).min(result.len());
+                if !memory.write(ptr as u32 as u64, &result[..len]) {
+                    return Err("result buffer is outside of the module's memory".to_string());
+                }
+                Ok(Vec::new())
+            },
+        )
+}
+
+/// Longest content type, kernel excess, destination or transaction filter a
+/// module may pass to a host function
+const MAX_GUEST_FIELD_BYTES: usize = 256;
+
+/// The UTF-8 string of `len` bytes at `ptr` in a module's memory, refused without
+/// reading it if it is longer than `max_len` or outside of the memory
+fn read_guest_string(
+    memory: &mut dyn GuestMemory,
+    ptr: i32,
+    len: i32,
+    what: &str,
+    max_len: usize,
+) -> Result<String, String> {
+    let (ptr, len) = (ptr as u32 as u64, len as u32 as u64);
+    if len > max_len as u64 {
+        return Err(format!(
+            "{} of {} bytes is longer than the limit of {} bytes",
+            what, len, max_len
+        ));
+    }
+    if ptr + len > memory.size() {
+        return Err(format!("{} is outside of the module's memory", what));
+    }
+    let mut bytes = vec![0; len as usize];
+    if !memory.read(ptr, &mut bytes) {
+        return Err(format!("{} is outside of the module's memory", what));
+    }
+    String::from_utf8(bytes).map_err(|_| format!("{} is not UTF-8", what))
+}
+
+/// Keep the JSON of `result` for `minotari_take_result`, returning its length
+fn store_pending_result<T: Serialize>(
+    pending: &Mutex<Vec<u8>>,
+    result: Result<T, HostError>,
+) -> WasmValue {
+    let value = match result {
+        Ok(value) => serde_json::json!({ "ok": value }),
+        Err(e) => serde_json::json!({ "err": e.to_json() }),
+    };
+    let bytes = value.to_string().into_bytes();
+    let len = bytes.len() as i32;
+    *pending.lock().unwrap() = bytes;
+    WasmValue::I32(len)
+}
+
+/// Instantiate `module`, with virtual WASI imports if `wasi` is set
+fn instantiate(
+    module: &dyn CompiledModule,
+    wasi: Option<&WasiOptions>,
+    host: &HostImports,
+    tapplet: &str,
+) -> Result<Box<dyn WasmInstance>, HostError> {
+    if wasi.is_none() && module.imports_wasi() {
+        return Err(HostError::WasmInstantiationError(format!(
+            "{} imports WASI functions; create its host with WASI options",
+            tapplet
+        )));
+    }
+    if module.imports(engine::HOST_MODULE, "minotari_call_tapplet") {
+        return Err(HostError::Unsupported(format!(
+            "{} imports minotari_call_tapplet, but WASM tapplets can't call other tapplets",
+            tapplet
+        )));
+    }
+    let mut instance = module.instantiate(wasi, host, tapplet)?;
+    // WASI reactors need `_initialize` before any other call
+    if wasi.is_some() && instance.has_function("_initialize") {
+        instance
+            .call("_initialize", &[])
+            .map_err(|e| HostError::WasmInstantiationError(format!("_initialize: {}", e)))?;
+    }
+    Ok(instance)
+}
+
+/// Warn about a call to a deprecated method, returning the warning for the audit log
+fn deprecation_warning(config: &TappletManifest, method: &str) -> Option<String> {
+    let deprecation = config.api.method(method)?.deprecated.as_ref()?;
+    trace::deprecated_call(&config.name, method, deprecation);
+    Some(format!("{}.{} is {}", config.name, method, deprecation))
+}
+
+/// Refuse tapplets that need a host API this build doesn't provide, before
+/// their calls fail with `METHOD_NOT_FOUND`
+fn check_host_api(config: &TappletManifest) -> Result<(), HostError> {
+    config
+        .ensure_host_api(&HOST_API_VERSION)
+        .map_err(|e| HostError::UnsupportedHostApi(format!("{:#}", e)))
+}
+
+/// Apply the settings of [`HostOptions`] both hosts have the same builders for
+macro_rules! apply_common_options {
+    ($host:ident, $options:expr) => {{
+        let options: &HostOptions = $options;
+        for interceptor in &options.interceptors {
+            $host = $host.with_interceptor(interceptor.clone());
+        }
+        if let Some(limit) = options.rate_limit {
+            $host = $host.with_rate_limit(limit);
+        }
+        if let Some(governor) = &options.governor {
+            $host = $host.with_governor(governor);
+        }
+        if let Some(cache) = &options.result_cache {
+            $host = $host.with_result_cache(cache);
+        }
+        if let Some(sink) = &options.audit_sink {
+            $host = $host.with_audit_sink(sink.clone());
+        }
+        if let Some(sink) = &options.log_sink {
+            $host = $host.with_log_sink(sink.clone());
+        }
+        if let Some(level) = options.log_level_for(&$host.config.name) {
+            $host = $host.with_log_level(level);
+        }
+        $host = $host.with_ambient_mode(options.ambient_mode());
+        if let Some(access) = &options.network {
+            $host = $host.with_network_access(access.clone());
+        }
+        if let Some(api) = &options.chain_api {
+            $host = $host.with_chain_api(api.clone());
+        }
+        $host
+    }};
 }
 
 impl WasmTappletHost {
-    /// Create a new TappletHost by loading a WASM module from a file
+    /// Create a new TappletHost by loading a WASM module from a file, which may
+    /// also be a `.wat` file in the WebAssembly text format
     pub fn new(config: TappletManifest, wasm_path: impl AsRef<Path>) -> Result<Self, HostError> {
         // Read the WASM file
-        let wasm_bytes = std::fs::read(wasm_path)?;
+        let wasm_bytes = std::fs::read(wasm_path.as_ref())?;
+        config
+            .verify_entrypoint(RuntimeKind::Wasm, wasm_path.as_ref(), &wasm_bytes)
+            .map_err(|e| HostError::IntegrityMismatch(e.to_string()))?;
+
+        Self::from_bytes_with_engine(engine::default_engine(), config, &wasm_bytes, None)
+    }
 
-        // Create a new store
-        let mut store = Store::default();
+    /// Create a new TappletHost from a WASM file with the limits and hooks of
+    /// `options`. Fails with [`HostError::Unsupported`] if the engine can't
+    /// enforce one of the limits.
+    pub fn new_with_options(
+        config: TappletManifest,
+        wasm_path: impl AsRef<Path>,
+        options: &HostOptions,
+    ) -> Result<Self, HostError> {
+        Self::new(config, wasm_path)?.apply_options(options)
+    }
 
-        // Compile the WASM module
-        let module = Module::new(&store, wasm_bytes)?;
+    /// Create a new TappletHost from a WASM file built for WASI, e.g. with the
+    /// `wasm32-wasip1` target. The module sees only the virtual environment in `wasi`.
+    pub fn new_with_wasi(
+        config: TappletManifest,
+        wasm_path: impl AsRef<Path>,
+        wasi: WasiOptions,
+    ) -> Result<Self, HostError> {
+        let wasm_bytes = std::fs::read(wasm_path.as_ref())?;
+        config
+            .verify_entrypoint(RuntimeKind::Wasm, wasm_path.as_ref(), &wasm_bytes)
+            .map_err(|e| HostError::IntegrityMismatch(e.to_string()))?;
+        Self::from_bytes_with_engine(engine::default_engine(), config, &wasm_bytes, Some(wasi))
+    }
 
-        // Instantiate the module
-        let instance = Instance::new(&mut store, &module, &wasmer::imports! {})?;
+    /// Create a new TappletHost from WASM bytes run by `engine` rather than the
+    /// default one, with WASI if `wasi` is set
+    pub fn from_bytes_with_engine(
+        engine: Arc<dyn WasmEngine>,
+        config: TappletManifest,
+        wasm_bytes: &[u8],
+        wasi: Option<WasiOptions>,
+    ) -> Result<Self, HostError> {
+        check_host_api(&config)?;
+        let module = engine.compile(wasm_bytes)?;
+        let log = GuestLog::new(&config.name);
+        let ambient = Ambient::new(AmbientMode::Real);
+        let network = GuestNetwork::new(&config.permissions);
+        let chain = GuestChain::new(&config.name, &config.permissions);
+        let wallet = GuestWallet::default();
+        let instance = instantiate(
+            module.as_ref(),
+            wasi.as_ref(),
+            &host_imports(&log, &ambient, &network, &chain, &wallet),
+            &config.name,
+        )?;
 
         Ok(Self {
             config,
-            store,
+            engine,
             instance,
+            audit: Auditor::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Meter::default(),
+            wasi,
+            rate_limiter: RateLimiter::default(),
+            governor: None,
+            interceptors: Interceptors::default(),
+            result_cache: None,
+            timeout: None,
+            execution_budget: None,
+            memory_limit: None,
+            stack_limit: None,
+            cancel: None,
+            initialized: false,
+            log,
+            ambient,
+            network,
+            chain,
+            wallet,
         })
     }
 
-    /// Create a new TappletHost from WASM bytes
-    pub fn from_bytes(config: TappletManifest, wasm_bytes: &[u8]) -> Result<Self, HostError> {
-        // Create a new store
-        let mut store = Store::default();
+    /// Create a new TappletHost from a WASM file, reusing a compiled module from the cache
+    pub fn with_module_cache(
+        config: TappletManifest,
+        wasm_path: impl AsRef<Path>,
+        module_cache: &ModuleCache,
+    ) -> Result<Self, HostError> {
+        check_host_api(&config)?;
+        let wasm_bytes = std::fs::read(wasm_path.as_ref())?;
+        config
+            .verify_entrypoint(RuntimeKind::Wasm, wasm_path.as_ref(), &wasm_bytes)
+            .map_err(|e| HostError::IntegrityMismatch(e.to_string()))?;
+        let module = module_cache.load(&wasm_bytes)?;
+        let log = GuestLog::new(&config.name);
+        let ambient = Ambient::new(AmbientMode::Real);
+        let network = GuestNetwork::new(&config.permissions);
+        let chain = GuestChain::new(&config.name, &config.permissions);
+        let wallet = GuestWallet::default();
+        let instance = instantiate(
+            module.as_ref(),
+            None,
+            &host_imports(&log, &ambient, &network, &chain, &wallet),
+            &config.name,
+        )?;
 
-        // Compile the WASM module
-        let module = Module::new(&store, wasm_bytes)?;
+        Ok(Self {
+            config,
+            engine: module_cache.engine().clone(),
+            instance,
+            audit: Auditor::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Meter::default(),
+            wasi: None,
+            rate_limiter: RateLimiter::default(),
+            governor: None,
+            interceptors: Interceptors::default(),
+            result_cache: None,
+            timeout: None,
+            execution_budget: None,
+            memory_limit: None,
+            stack_limit: None,
+            cancel: None,
+            initialized: false,
+            log,
+            ambient,
+            network,
+            chain,
+            wallet,
+        })
+    }
 
-        // Instantiate the module
-        let instance = Instance::new(&mut store, &module, &wasmer::imports! {})?;
+    /// Create a new TappletHost from a module compiled ahead of time with
+    /// [`engine::precompile_file`], skipping compilation. Fails if the default
+    /// engine isn't the engine, version and platform the module was compiled for.
+    ///
+    /// # Safety
+    ///
+    /// The artifact contains native code that is run as is, so it must have been
+    /// written by `precompile_file` into a directory only the host writes, e.g.
+    /// when the tapplet was installed with
+    /// [`crate::build::BuildOptions::precompile`], and not modified since. Never
+    /// load artifacts that came with a package or from a registry. The
+    /// hash of the WASM module it was compiled from is still checked against the
+    /// manifest's `[artifacts]`.
+    pub unsafe fn from_precompiled(
+        config: TappletManifest,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, HostError> {
+        check_host_api(&config)?;
+        let artifact = std::fs::read(path.as_ref())?;
+        let precompiled = engine::Precompiled::parse(&artifact)?;
+        config
+            .verify_entrypoint_hash(RuntimeKind::Wasm, path.as_ref(), precompiled.wasm_sha256)
+            .map_err(|e| HostError::IntegrityMismatch(e.to_string()))?;
+        let engine = engine::default_engine();
+        // SAFETY: upheld by the caller
+        let module = unsafe { precompiled.load(engine.as_ref()) }?;
+        let log = GuestLog::new(&config.name);
+        let ambient = Ambient::new(AmbientMode::Real);
+        let network = GuestNetwork::new(&config.permissions);
+        let chain = GuestChain::new(&config.name, &config.permissions);
+        let wallet = GuestWallet::default();
+        let instance = instantiate(
+            module.as_ref(),
+            None,
+            &host_imports(&log, &ambient, &network, &chain, &wallet),
+            &config.name,
+        )?;
 
         Ok(Self {
             config,
-            store,
+            engine,
             instance,
+            audit: Auditor::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Meter::default(),
+            wasi: None,
+            rate_limiter: RateLimiter::default(),
+            governor: None,
+            interceptors: Interceptors::default(),
+            result_cache: None,
+            timeout: None,
+            execution_budget: None,
+            memory_limit: None,
+            stack_limit: None,
+            cancel: None,
+            initialized: false,
+            log,
+            ambient,
+            network,
+            chain,
+            wallet,
         })
     }
 
+    /// Replace the module with the one at `wasm_path`, e.g. after rebuilding it,
+    /// keeping the manifest, audit sink and WASI options
+    pub fn reload(&mut self, wasm_path: impl AsRef<Path>) -> Result<(), HostError> {
+        let wasm_bytes = std::fs::read(wasm_path.as_ref())?;
+        self.config
+            .verify_entrypoint(RuntimeKind::Wasm, wasm_path.as_ref(), &wasm_bytes)
+            .map_err(|e| HostError::IntegrityMismatch(e.to_string()))?;
+        let module = self.engine.compile(&wasm_bytes)?;
+        self.instance = instantiate(
+            module.as_ref(),
+            self.wasi.as_ref(),
+            &host_imports(
+                &self.log,
+                &self.ambient,
+                &self.network,
+                &self.chain,
+                &self.wallet,
+            ),
+            &self.config.name,
+        )?;
+        self.instance.set_rate_limiter(self.rate_limiter.clone());
+        self.instance.set_limits(self.host_limits());
+        if let Some(governor) = &self.governor {
+            governor.release(&self.config.name);
+        }
+        if let Some(cache) = &self.result_cache {
+            cache.invalidate(&self.config.qualified_name());
+        }
+        if self.initialized {
+            self.init()?;
+        }
+        Ok(())
+    }
+
+    /// Run the guest's [`GUEST_INIT_EXPORT`], if the module has one, returning
+    /// whether it did. Modules use it to set up state before the first call.
+    /// Hosts from [`crate::TappletManager`] have run it already; after this,
+    /// [`WasmTappletHost::reload`] runs it again for the new module.
+    pub fn init(&mut self) -> Result<bool, HostError> {
+        self.initialized = true;
+        if !self.instance.has_function(GUEST_INIT_EXPORT) {
+            return Ok(false);
+        }
+        let output_ptr = self.call_i32(GUEST_INIT_EXPORT, &[])?;
+        let response = self.take_response(GUEST_INIT_EXPORT, output_ptr)?;
+        self.response_value(GUEST_INIT_EXPORT, response, None)?;
+        Ok(true)
+    }
+
+    /// Run the guest's [`GUEST_HEALTH_EXPORT`], if the module has one, returning
+    /// whether it did. Fails if the guest reports an error, traps or doesn't
+    /// return within `timeout`.
+    pub fn check_health(&mut self, timeout: Duration) -> Result<bool, HostError> {
+        if !self.instance.has_function(GUEST_HEALTH_EXPORT) {
+            return Ok(false);
+        }
+        let timer = Timer::start(timeout, None);
+        self.instance.set_cancellation(Some(timer.token().clone()));
+        // Only the timeout limits how long the check runs
+        self.instance.set_limits(CallLimits {
+            fuel: None,
+            ..self.host_limits()
+        });
+        let result = self
+            .call_i32(GUEST_HEALTH_EXPORT, &[])
+            .and_then(|output_ptr| self.take_response(GUEST_HEALTH_EXPORT, output_ptr))
+            .and_then(|response| self.response_value(GUEST_HEALTH_EXPORT, response, None));
+        self.instance.set_cancellation(None);
+        self.instance.set_limits(self.host_limits());
+        match result {
+            Ok(_) => Ok(true),
+            Err(HostError::Cancelled(_)) if timer.expired() => {
+                Err(HostError::Timeout(GUEST_HEALTH_EXPORT.to_string()))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Size of the module's linear memory in bytes
+    pub fn memory_usage(&mut self) -> u64 {
+        self.instance.memory_size()
+    }
+
+    /// Create a new TappletHost from WASM bytes, either a binary module or one in
+    /// the WebAssembly text format
+    pub fn from_bytes(config: TappletManifest, wasm_bytes: &[u8]) -> Result<Self, HostError> {
+        Self::from_bytes_with_engine(engine::default_engine(), config, wasm_bytes, None)
+    }
+
+    /// Create a new TappletHost from a module in the WebAssembly text format, e.g.
+    /// a test fixture. Unlike [`WasmTappletHost::from_bytes`], binary modules are
+    /// rejected.
+    pub fn from_wat(config: TappletManifest, wat: &str) -> Result<Self, HostError> {
+        let wasm_bytes =
+            wat::parse_str(wat).map_err(|e| HostError::WasmCompileError(e.to_string()))?;
+        Self::from_bytes(config, &wasm_bytes)
+    }
+
+    /// Create a new TappletHost from WASM bytes with the limits and hooks of
+    /// `options`, see [`Self::new_with_options`]
+    pub fn from_bytes_with_options(
+        config: TappletManifest,
+        wasm_bytes: &[u8],
+        options: &HostOptions,
+    ) -> Result<Self, HostError> {
+        Self::from_bytes(config, wasm_bytes)?.apply_options(options)
+    }
+
+    /// Create a new TappletHost from the bytes of a WASM module built for WASI
+    pub fn from_bytes_with_wasi(
+        config: TappletManifest,
+        wasm_bytes: &[u8],
+        wasi: WasiOptions,
+    ) -> Result<Self, HostError> {
+        Self::from_bytes_with_engine(engine::default_engine(), config, wasm_bytes, Some(wasi))
+    }
+
     /// Run a method with the given arguments
     ///
     /// # Arguments
     /// * `method` - The name of the method to call
     /// * `args` - JSON value representing the arguments
+    /// * `context` - Who is calling, checked against the method's declared permissions
     ///
     /// # Returns
     /// A JSON value containing the result of the method call
-    pub fn run(&mut self, method: &str, args: Value) -> Result<Value, HostError> {
-        // Verify the method exists in the API config
-        if !self.config.api.methods.contains(&method.to_string()) {
-            return Err(HostError::MethodNotFound(method.to_string()));
+    pub fn run(
+        &mut self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError> {
+        let interceptors = self.interceptors.clone();
+        let tapplet = self.config.name.clone();
+        self.instrumented(method, &args, |host| {
+            let call = InterceptedCall {
+                tapplet: &tapplet,
+                method,
+                args: &args,
+                context,
+            };
+            interceptors.around(&call, || {
+                host.with_call_timeout(method, |host| host.call_method(method, &args, context))
+            })
+        })
+    }
+
+    /// Limits of calls outside of methods with their own
+    fn host_limits(&self) -> CallLimits {
+        CallLimits {
+            fuel: self.execution_budget,
+            memory: self.memory_limit.map(|bytes| bytes as u64),
+            stack: self.stack_limit,
         }
+    }
 
-        // Get the exported function from the WASM instance
-        let func = self
-            .instance
-            .exports
-            .get_function(method)
-            .map_err(|_| HostError::MethodNotFound(method.to_string()))?;
+    /// Run `call` under the limits of `method`, admitted by the governor, and
+    /// record it in the audit log and metrics
+    fn instrumented<R>(
+        &mut self,
+        method: &str,
+        args: &Value,
+        call: impl FnOnce(&mut Self) -> Result<R, HostError>,
+    ) -> Result<R, HostError> {
+        let started = Instant::now();
+        // The method's own limits take the place of the host's
+        let definition = self.config.api.method(method);
+        let limits = CallLimits {
+            fuel: definition
+                .and_then(|definition| definition.max_fuel)
+                .or(self.execution_budget),
+            memory: definition
+                .and_then(|definition| definition.max_memory)
+                .or(self.memory_limit.map(|bytes| bytes as u64)),
+            stack: self.stack_limit,
+        };
+        self.instance.set_limits(limits);
+        let warning = deprecation_warning(&self.config, method);
+        let tapplet = self.config.name.clone();
+        let result = trace::call(&tapplet, method, || {
+            let mut permit = admit(self.governor.as_ref(), &tapplet)?;
+            let result = call(self);
+            if let Some(permit) = &mut permit {
+                permit.report_memory(self.instance.memory_size() as usize);
+            }
+            result
+        });
+        #[cfg(feature = "metrics")]
+        let fuel = limits.fuel.map(|_| self.instance.fuel_used());
+        self.instance.set_limits(self.host_limits());
+        self.audit.record_with_warning(
+            AuditKind::MethodCall,
+            method,
+            || summarize_args(args),
+            started,
+            &result,
+            warning,
+        );
+        #[cfg(feature = "metrics")]
+        self.metrics.record_call(
+            method,
+            started.elapsed(),
+            fuel,
+            result.as_ref().err().map(HostError::code),
+        );
+        result
+    }
+
+    /// Run several methods one after the other on the same instance, e.g. the
+    /// reads a UI needs to render one screen. The host functions the module
+    /// imports were set up once when it was instantiated and serve the whole
+    /// batch. Each call succeeds or fails on its own, like [`Self::run`].
+    pub fn run_batch(
+        &mut self,
+        calls: Vec<(String, Value)>,
+        context: &CallContext,
+    ) -> Vec<Result<Value, HostError>> {
+        calls
+            .into_iter()
+            .map(|(method, args)| self.run(&method, args, context))
+            .collect()
+    }
+
+    /// Run a method whose result is handed over in chunks, see
+    /// [`WasmChunkStream`]. Fails if the call starting the stream does.
+    pub fn run_stream(
+        &mut self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<WasmChunkStream<'_>, HostError> {
+        let result = self.run(method, args.clone(), context)?;
+        let streams = self.instance.has_function(GUEST_NEXT_CHUNK_EXPORT);
+        Ok(WasmChunkStream {
+            host: self,
+            method: method.to_string(),
+            args,
+            first: (!streams || !result.is_null()).then_some(result),
+            done: !streams,
+        })
+    }
+
+    /// [`Self::run`], failing with [`HostError::Cancelled`] once `token` is
+    /// cancelled. The running code is interrupted; a host function it is waiting
+    /// on finishes first.
+    pub fn run_with_cancel(
+        &mut self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+        token: &CancellationToken,
+    ) -> Result<Value, HostError> {
+        self.cancel = Some(token.clone());
+        let result = self.run(method, args, context);
+        self.cancel = None;
+        result
+    }
+
+    /// Fail calls with [`HostError::Timeout`] once they have run for `timeout`.
+    /// Methods may set their own `timeout_ms`. Calls are stopped the same way as
+    /// cancelled ones.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Abort a call with [`HostError::ExecutionBudgetExceeded`] once the module
+    /// has run `budget` instructions. The budget is reset at the start of each
+    /// `run()`; methods may set their own `max_fuel`.
+    pub fn with_execution_budget(mut self, budget: u64) -> Self {
+        self.execution_budget = Some(budget);
+        self.instance.set_limits(self.host_limits());
+        self
+    }
+
+    /// Keep the module's memory from growing past `bytes`. `memory.grow` fails
+    /// instead, and a call that then traps fails with
+    /// [`HostError::MemoryLimitExceeded`]. Methods may set their own `max_memory`.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self.instance.set_limits(self.host_limits());
+        self
+    }
+
+    /// Trap a call once more than `depth` of the module's functions are running
+    /// at once, e.g. in a runaway recursion. The call fails with a `stack limit
+    /// exceeded` error. Fails with [`HostError::Unsupported`] on engines that
+    /// can't count frames, see [`WasmEngine::limits_stack`].
+    pub fn with_stack_limit(mut self, depth: usize) -> Result<Self, HostError> {
+        if !self.engine.limits_stack() {
+            return Err(HostError::Unsupported(format!(
+                "{} can't limit the stack depth",
+                self.engine.name()
+            )));
+        }
+        self.stack_limit = Some(depth);
+        self.instance.set_limits(self.host_limits());
+        Ok(self)
+    }
+
+    /// Apply the settings of `options` that WASM hosts support, failing if the
+    /// engine can't enforce one of its limits
+    pub(crate) fn apply_options(mut self, options: &HostOptions) -> Result<Self, HostError> {
+        if let Some(fuel) = options.fuel {
+            self = self.with_execution_budget(fuel);
+        }
+        if let Some(timeout) = options.timeout {
+            self = self.with_timeout(timeout);
+        }
+        if let Some(bytes) = options.memory_limit {
+            self = self.with_memory_limit(bytes);
+        }
+        if let Some(depth) = options.stack_limit {
+            self = self.with_stack_limit(depth)?;
+        }
+        Ok(apply_common_options!(self, options))
+    }
+
+    /// Send the module's `minotari_log` output to `sink`
+    pub fn with_log_sink(self, sink: Arc<dyn LogSink>) -> Self {
+        self.log.set_sink(sink);
+        self
+    }
+
+    /// Drop `minotari_log` output below `level`, [`LogLevel::Info`] by default
+    pub fn with_log_level(self, level: LogLevel) -> Self {
+        self.log.set_level(level);
+        self
+    }
+
+    /// Where `minotari_now` and `minotari_random_bytes` get their values from,
+    /// [`AmbientMode::Real`] by default
+    pub fn with_ambient_mode(self, mode: AmbientMode) -> Self {
+        self.ambient.set_mode(mode);
+        self
+    }
+
+    /// Let the module send HTTP requests through `access` to the hosts its
+    /// manifest allows, see [`crate::network`]. Without it every request fails.
+    pub fn with_network_access(self, access: NetworkAccess) -> Self {
+        self.network.set_access(Some(access));
+        self
+    }
+
+    /// Answer the module's chain queries with `api`, if its manifest asks for
+    /// `permissions.chain.read`, see [`crate::chain`]
+    pub fn with_chain_api(self, api: Arc<dyn MinotariChainApi>) -> Self {
+        self.chain.set_api(Some(api));
+        self
+    }
+
+    /// Answer the module's wallet functions with `api`. Like with
+    /// [`LuaTappletHost::with_api_v2`], each function needs its `PERMISSION_*`
+    /// permission in the call's context, see [`crate::wallet`].
+    pub fn with_wallet_api(self, api: Arc<dyn MinotariTappletApiV2>) -> Self {
+        self.wallet.set_api(Some(api));
+        self
+    }
+
+    /// Make a call of `method`, stopping it once it is cancelled or times out
+    fn with_call_timeout<R>(
+        &mut self,
+        method: &str,
+        call: impl FnOnce(&mut Self) -> Result<R, HostError>,
+    ) -> Result<R, HostError> {
+        let timeout = self
+            .config
+            .api
+            .method(method)
+            .and_then(MethodDefinition::timeout)
+            .or(self.timeout);
+        let Some(timeout) = timeout else {
+            self.instance.set_cancellation(self.cancel.clone());
+            let result = call(self);
+            self.instance.set_cancellation(None);
+            return result;
+        };
+        let timer = Timer::start(timeout, self.cancel.as_ref());
+        self.instance.set_cancellation(Some(timer.token().clone()));
+        let result = call(self);
+        self.instance.set_cancellation(None);
+        match result {
+            Err(HostError::Cancelled(method)) if timer.expired() => Err(HostError::Timeout(method)),
+            result => result,
+        }
+    }
+
+    fn call_method(
+        &mut self,
+        method: &str,
+        args: &Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError> {
+        // Verify the method exists in the API config and the caller may call it
+        context.ensure_allowed(&self.config, method)?;
+        let args = &self
+            .config
+            .api
+            .coerce_args(method, args.clone())
+            .map_err(HostError::InvalidArguments)?;
+
+        let cache = pure_method_cache(self.result_cache.as_ref(), &self.config, method).cloned();
+        if let Some(cache) = &cache
+            && let Some(result) = cache.get(&self.config, method, args)
+        {
+            return Ok(result);
+        }
+        // Wallet functions check the permissions of this call
+        self.wallet.set_context(Some(context.clone()));
+        let result = self.execute(method, args);
+        self.wallet.set_context(None);
+        let result = result?;
+        if let Some(cache) = &cache {
+            cache.insert(&self.config, method, args, &result);
+        }
+        Ok(result)
+    }
+
+    fn execute(&mut self, method: &str, args: &Value) -> Result<Value, HostError> {
+        if self.instance.has_function(GUEST_ALLOC_EXPORT) {
+            return self.call_json_method(method, args);
+        }
 
         // Convert JSON args to WASM values
-        let wasm_args = self.json_to_wasm_args(&args)?;
+        let wasm_args = self.json_to_wasm_args(args)?;
 
-        // Call the function
-        let results = func
-            .call(&mut self.store, &wasm_args)
-            .map_err(|e| HostError::ExecutionError(e.to_string()))?;
+        // Call the exported function
+        let results = self.instance.call(method, &wasm_args)?;
 
         // Convert results back to JSON
         let result = self.wasm_results_to_json(&results)?;
@@ -153,6 +1466,140 @@ impl WasmTappletHost {
         Ok(result)
     }
 
+    /// Call a method of a module built with `tari-tapplet-guest`, passing JSON in and
+    /// out through the guest's memory. See that crate for the calling convention.
+    fn call_json_method(&mut self, method: &str, args: &Value) -> Result<Value, HostError> {
+        if !self.instance.has_function(method) {
+            return Err(HostError::MethodNotFound(method.to_string()));
+        }
+        let input =
+            serde_json::to_vec(args).map_err(|e| HostError::InvalidArguments(e.to_string()))?;
+        let input_len = i32::try_from(input.len())
+            .map_err(|_| HostError::InvalidArguments("arguments are too large".to_string()))?;
+        let input_ptr = self.call_i32(GUEST_ALLOC_EXPORT, &[WasmValue::I32(input_len)])?;
+        self.instance
+            .write_memory(input_ptr as u32 as u64, &input)?;
+
+        let output_ptr = self.call_i32(
+            method,
+            &[WasmValue::I32(input_ptr), WasmValue::I32(input_len)],
+        )?;
+        let response = self.take_response(method, output_ptr)?;
+        self.response_value(method, response, self.return_type(method))
+    }
+
+    /// Pull the next chunk of a streamed result of `method`, `None` once there
+    /// are no more
+    fn next_json_chunk(&mut self, method: &str) -> Result<Option<Value>, HostError> {
+        let output_ptr = self.call_i32(GUEST_NEXT_CHUNK_EXPORT, &[])?;
+        let response = self.take_response(GUEST_NEXT_CHUNK_EXPORT, output_ptr)?;
+        if response.get("done").and_then(Value::as_bool) == Some(true) {
+            return Ok(None);
+        }
+        let chunk_type = self.return_type(method).and_then(chunk_type);
+        self.response_value(method, response, chunk_type).map(Some)
+    }
+
+    fn return_type(&self, method: &str) -> Option<&ParamType> {
+        self.config
+            .api
+            .method(method)
+            .map(|definition| &definition.returns.return_type)
+    }
+
+    /// Copy a result buffer of the JSON calling convention out of the guest's
+    /// memory and free it
+    fn take_response(&mut self, export: &str, output_ptr: i32) -> Result<Value, HostError> {
+        let output_ptr = output_ptr as u32 as u64;
+        let mut prefix = [0u8; 4];
+        self.instance.read_memory(output_ptr, &mut prefix)?;
+        let output_len = u32::from_le_bytes(prefix);
+        // The length comes from the guest, so check it before allocating
+        if u64::from(output_len) > MAX_RESPONSE_BYTES {
+            return Err(HostError::ExecutionError(format!(
+                "{} returned a response of {} bytes, more than the limit of {} bytes",
+                export, output_len, MAX_RESPONSE_BYTES
+            )));
+        }
+        if output_ptr + 4 + u64::from(output_len) > self.instance.memory_size() {
+            return Err(HostError::ExecutionError(format!(
+                "{} returned a response of {} bytes at {}, outside of its memory",
+                export, output_len, output_ptr
+            )));
+        }
+        let mut output = vec![0u8; output_len as usize];
+        self.instance.read_memory(output_ptr + 4, &mut output)?;
+        self.instance.call(
+            GUEST_DEALLOC_EXPORT,
+            &[
+                WasmValue::I32(output_ptr as i32),
+                WasmValue::I32(output_len.wrapping_add(4) as i32),
+            ],
+        )?;
+
+        serde_json::from_slice(&output).map_err(|e| {
+            HostError::ExecutionError(format!("Invalid JSON returned by {}: {}", export, e))
+        })
+    }
+
+    /// The value of an `ok` response of `method`, with `bytes` encoded as
+    /// `value_type` says, or the error of an `err` response
+    fn response_value(
+        &self,
+        method: &str,
+        mut response: Value,
+        value_type: Option<&ParamType>,
+    ) -> Result<Value, HostError> {
+        if let Some(message) = response.get("err") {
+            let message = message
+                .as_str()
+                .map(str::to_string)
+                .unwrap_or_else(|| message.to_string());
+            return Err(HostError::ExecutionError(message));
+        }
+        match response.get_mut("ok") {
+            // Guests may return `bytes` as arrays of byte values, e.g. a serialized `Vec<u8>`
+            Some(value) => Ok(match value_type {
+                Some(value_type) => value_type.encode_bytes(value.take()),
+                None => value.take(),
+            }),
+            None => Err(HostError::ExecutionError(format!(
+                "{} returned neither ok nor err",
+                method
+            ))),
+        }
+    }
+
+    /// Call an export of the JSON calling convention, which returns a single `i32`
+    fn call_i32(&mut self, name: &str, args: &[WasmValue]) -> Result<i32, HostError> {
+        let results = match self.instance.call(name, args) {
+            Err(HostError::ExecutionError(trap)) if name != GUEST_LAST_PANIC_EXPORT => {
+                return Err(self.trap_error(trap));
+            }
+            results => results?,
+        };
+        match results.as_slice() {
+            [WasmValue::I32(value)] => Ok(*value),
+            _ => Err(HostError::WasmLoadError(format!(
+                "{} must return a single i32",
+                name
+            ))),
+        }
+    }
+
+    /// The error of a call of a module built with `tari-tapplet-guest` that
+    /// trapped, starting with the message of the panic that caused it, if any
+    fn trap_error(&mut self, trap: String) -> HostError {
+        if self.instance.has_function(GUEST_LAST_PANIC_EXPORT)
+            && let Ok(output_ptr) = self.call_i32(GUEST_LAST_PANIC_EXPORT, &[])
+            && let Ok(response) = self.take_response(GUEST_LAST_PANIC_EXPORT, output_ptr)
+            && let Some(message) = response.get("ok").and_then(Value::as_str)
+        {
+            return HostError::ExecutionError(format!("tapplet {}\n{}", message, trap));
+        }
+        HostError::ExecutionError(trap)
+    }
+
     /// Convert JSON arguments to WASM values
     fn json_to_wasm_args(&self, args: &Value) -> Result<Vec<WasmValue>, HostError> {
         let mut wasm_args = Vec::new();
@@ -250,26 +1697,153 @@ impl WasmTappletHost {
                     ))
                 }
             }
-            _ => Err(HostError::ExecutionError(format!(
-                "Unsupported WASM value type: {:?}",
-                value
-            ))),
         }
     }
 
-    /// Get the tapplet configuration
-    pub fn config(&self) -> &TappletManifest {
-        &self.config
+    /// Record every call into this tapplet in the given audit sink
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.audit = Auditor::new(Some(sink), &self.config.name);
+        self
     }
-}
 
-/// Convenience function to run a method on a tapplet
-///
-/// # Arguments
-/// * `config` - The tapplet configuration
+    /// Report call counts, durations and errors to the given metrics sink
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics_sink(mut self, sink: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = Meter::new(sink, &self.config.name);
+        self
+    }
+
+    /// Limit how often the module may call WASI functions; calls over the limit
+    /// return `EAGAIN`, which the module can retry later. Modules that don't import
+    /// WASI don't call the host, so there is nothing to limit.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limiter = RateLimiter::new(limit);
+        self.instance.set_rate_limiter(self.rate_limiter.clone());
+        self
+    }
+
+    /// Count calls against the global budgets of `governor`, which other hosts
+    /// may share. The size of the module's memory is reported after every call,
+    /// so the host may be evicted when tapplets use too much together.
+    pub fn with_governor(mut self, governor: &ResourceGovernor) -> Self {
+        self.governor = Some(governor.clone());
+        self
+    }
+
+    /// Run `interceptor` around every call, after the ones already registered
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn CallInterceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Answer repeated calls of methods marked `pure` from `cache`, which other
+    /// hosts may share
+    pub fn with_result_cache(mut self, cache: &ResultCache) -> Self {
+        self.result_cache = Some(cache.clone());
+        self
+    }
+
+    /// The functions the module exports, with their param and result types
+    pub fn list_exports(&mut self) -> Vec<FunctionExport> {
+        self.instance.function_exports()
+    }
+
+    /// Compare the module's exports to the manifest's `api.methods`: methods the
+    /// module doesn't export, or with a signature the host can't call, and
+    /// exported functions that aren't methods
+    pub fn validate_against_manifest(&mut self) -> ExportReport {
+        let exports = self.list_exports();
+        let json_abi = exports
+            .iter()
+            .any(|export| export.name == GUEST_ALLOC_EXPORT);
+        let mut report = ExportReport::default();
+        for method in &self.config.api.methods {
+            match exports.iter().find(|export| &export.name == method) {
+                Some(export) => {
+                    if let Some(problem) = self.signature_problem(export, json_abi) {
+                        report.mismatched.push((method.clone(), problem));
+                    }
+                }
+                None => report.missing.push(method.clone()),
+            }
+        }
+        report.unlisted = exports
+            .into_iter()
+            .map(|export| export.name)
+            .filter(|name| !self.config.api.methods.contains(name))
+            .filter(|name| !crate::build::is_runtime_export(name))
+            .collect();
+        report
+    }
+
+    /// Why the host can't call `export` as a method, if it can't
+    fn signature_problem(&self, export: &FunctionExport, json_abi: bool) -> Option<String> {
+        if json_abi {
+            let matches = export.params == [WasmType::I32, WasmType::I32]
+                && export.results == [WasmType::I32];
+            return (!matches).then(|| {
+                format!(
+                    "{} doesn't follow the JSON calling convention, (i32, i32) -> (i32)",
+                    export
+                )
+            });
+        }
+        if let Some(ty) = export
+            .params
+            .iter()
+            .chain(&export.results)
+            .find(|ty| !ty.is_number())
+        {
+            return Some(format!(
+                "{} uses {}, which can't be passed as JSON",
+                export, ty
+            ));
+        }
+        let definition = self.config.api.method(&export.name)?;
+        (definition.params.len() != export.params.len()).then(|| {
+            format!(
+                "{} takes {} params, but the manifest declares {}",
+                export,
+                export.params.len(),
+                definition.params.len()
+            )
+        })
+    }
+
+    /// Get the tapplet configuration
+    pub fn config(&self) -> &TappletManifest {
+        &self.config
+    }
+}
+
+/// How a module's exports compare to its manifest, from
+/// [`WasmTappletHost::validate_against_manifest`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportReport {
+    /// Methods in `api.methods` the module doesn't export
+    pub missing: Vec<String>,
+    /// Methods the host can't call, with the reason
+    pub mismatched: Vec<(String, String)>,
+    /// Exported functions that aren't in `api.methods`, leaving out the ones the
+    /// host and toolchains use
+    pub unlisted: Vec<String>,
+}
+
+impl ExportReport {
+    /// Whether every method can be called
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+/// Convenience function to run a method on a tapplet
+///
+/// # Arguments
+/// * `config` - The tapplet configuration
 /// * `wasm_path` - Path to the WASM file
 /// * `method` - The name of the method to call
 /// * `args` - JSON value representing the arguments
+/// * `context` - Who is calling, checked against the method's declared permissions
 ///
 /// # Returns
 /// A JSON value containing the result of the method call
@@ -278,101 +1852,471 @@ pub fn run(
     wasm_path: impl AsRef<Path>,
     method: &str,
     args: Value,
+    context: &CallContext,
 ) -> Result<Value, HostError> {
     let mut host = WasmTappletHost::new(config, wasm_path)?;
-    host.run(method, args)
+    host.run(method, args, context)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// A loaded tapplet of either kind, for code that drives tapplets without caring
+/// how they are executed, like [`crate::events::TappletEventBus`]
+#[async_trait(?Send)]
+pub trait TappletRunner {
+    fn manifest(&self) -> &TappletManifest;
 
-    #[test]
-    fn test_host_error_display() {
-        let err = HostError::MethodNotFound("test_method".to_string());
-        assert_eq!(err.to_string(), "Method not found: test_method");
+    /// Call one of the tapplet's methods, see [`WasmTappletHost::run`] and [`LuaTappletHost::run`]
+    async fn call(
+        &mut self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError>;
+}
+
+#[async_trait(?Send)]
+impl TappletRunner for WasmTappletHost {
+    fn manifest(&self) -> &TappletManifest {
+        self.config()
     }
 
-    #[test]
-    fn test_invalid_wasm_error() {
-        let config = TappletManifest {
-            name: "test".to_string(),
-            version: "0.1.0".to_string(),
-            friendly_name: "Test".to_string(),
-            description: "Test tapplet".to_string(),
-            publisher: "test_publisher".to_string(),
-            git: crate::model::GitConfig {
-                url: "https://example.com".to_string(),
-                rev: "main".to_string(),
-            },
-            api: crate::model::ApiConfig {
-                methods: vec!["test".to_string()],
-                method_definitions: std::collections::HashMap::new(),
-            },
-            sigs: crate::model::SigsConfig {
-                todo: "test".to_string(),
-            },
-        };
+    async fn call(
+        &mut self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError> {
+        self.run(method, args, context)
+    }
+}
 
-        // Create an invalid WASM module for testing error handling
-        let wasm_bytes = vec![0x00, 0x61, 0x73, 0x6d];
+#[async_trait(?Send)]
+impl<T: MinotariTappletApiV1 + ?Sized + 'static> TappletRunner for LuaTappletHost<T> {
+    fn manifest(&self) -> &TappletManifest {
+        self.config()
+    }
 
-        let result = TappletHost::from_bytes(config, &wasm_bytes);
-        // This should fail because it's not a complete valid WASM module
-        assert!(result.is_err());
-        if let Err(e) = result {
-            // Verify we get a proper error message
-            assert!(!e.to_string().is_empty());
-        }
+    async fn call(
+        &mut self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError> {
+        self.run(method, args, context).await
     }
 }
 
+/// Host API available to tapplets.
+///
+/// The trait is dyn-compatible, so hosts for different API implementations can
+/// share a type by using `Arc<dyn MinotariTappletApiV1>`, see [`DynLuaTappletHost`].
 #[async_trait]
-pub trait MinotariTappletApiV1: Clone {
+pub trait MinotariTappletApiV1: Send + Sync {
     async fn append_data(&self, slot: &str, value: &str) -> Result<(), anyhow::Error>;
     async fn load_data_entries(&self, slot: &str) -> Result<Vec<String>, anyhow::Error>;
     async fn add_watched_viewkey(&self, viewkey: &str, birthday: u64) -> Result<(), anyhow::Error>;
 }
 
-pub struct LuaTappletHost<T> {
+#[async_trait]
+impl<T: MinotariTappletApiV1 + ?Sized> MinotariTappletApiV1 for Arc<T> {
+    async fn append_data(&self, slot: &str, value: &str) -> Result<(), anyhow::Error> {
+        (**self).append_data(slot, value).await
+    }
+
+    async fn load_data_entries(&self, slot: &str) -> Result<Vec<String>, anyhow::Error> {
+        (**self).load_data_entries(slot).await
+    }
+
+    async fn add_watched_viewkey(&self, viewkey: &str, birthday: u64) -> Result<(), anyhow::Error> {
+        (**self).add_watched_viewkey(viewkey, birthday).await
+    }
+}
+
+/// Chunks of a method's result, from [`LuaTappletHost::run_stream`].
+///
+/// The method runs as a coroutine and hands over a chunk with every
+/// `coroutine.yield(chunk)`. What it returns in the end is the last chunk unless
+/// it is `nil`, so a method that doesn't yield streams its whole result as one
+/// chunk. Each pull resumes the coroutine and counts as a call of the method for
+/// limits, the governor, the audit log and metrics. When the return type is
+/// `array<T>` chunks are `T`s.
+pub struct LuaChunkStream<'a, T: ?Sized> {
+    host: &'a LuaTappletHost<T>,
+    method: String,
+    args: Value,
+    context: CallContext,
+    /// The method's coroutine, once the first chunk was pulled
+    thread: Option<mlua::Thread>,
+    chunks: usize,
+    done: bool,
+}
+
+impl<T: MinotariTappletApiV1 + ?Sized + 'static> LuaChunkStream<'_, T> {
+    fn next_chunk(&mut self) -> Option<Result<Value, HostError>> {
+        if self.done {
+            return None;
+        }
+        let host = self.host;
+        let chunk = host.instrumented(&self.method, &self.args, || {
+            let call = InterceptedCall {
+                tapplet: &host.config.name,
+                method: &self.method,
+                args: &self.args,
+                context: &self.context,
+            };
+            if self.thread.is_none()
+                && let Some(answer) = host.interceptors.before(&call)
+            {
+                self.done = true;
+                return answer.map(Some);
+            }
+            let (thread, resumed) = match &self.thread {
+                Some(thread) => (thread, thread.resume::<mlua::Value>(())),
+                None => {
+                    let args = host.check_call(&self.method, &self.args, &self.context)?;
+                    let (func, lua_args) =
+                        host.prepare_call(&self.method, &args, &self.context, None)?;
+                    let thread = self.thread.insert(host.lua.create_thread(func)?);
+                    (&*thread, thread.resume(lua_args))
+                }
+            };
+            let value = resumed.map_err(|e| host.call_error(&self.method, e))?;
+            self.done = thread.status() != mlua::ThreadStatus::Resumable;
+            if self.done && value.is_nil() && self.chunks > 0 {
+                return Ok(None);
+            }
+            let return_type = host.return_type(&self.method);
+            // The returned result of a method that didn't yield is the whole result
+            let value_type = if self.done && self.chunks == 0 {
+                return_type
+            } else {
+                return_type.and_then(chunk_type)
+            };
+            host.result_to_json(&value, value_type).map(Some)
+        });
+        match chunk {
+            Ok(Some(chunk)) => {
+                self.chunks += 1;
+                Some(Ok(chunk))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<T: MinotariTappletApiV1 + ?Sized + 'static> Stream for LuaChunkStream<'_, T> {
+    type Item = Result<Value, HostError>;
+
+    fn poll_next(self: Pin<&mut Self>, _: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.get_mut().next_chunk())
+    }
+}
+
+/// Calls run together by [`LuaTappletHost::run_batch`]. Calls outside a batch
+/// run in an empty one.
+#[derive(Default)]
+struct Batch {
+    /// Appends held back until an atomic batch commits
+    staged: Option<Arc<StagedWrites>>,
+    /// Whether the host functions were registered for the whole batch
+    registered: bool,
+}
+
+/// Registers additional host functions before each call, see [`LuaTappletHost::with_api_v2`]
+type RegisterFn<T> = fn(&LuaTappletHost<T>, &CallContext) -> Result<(), HostError>;
+
+pub struct LuaTappletHost<T: ?Sized> {
     config: TappletManifest,
     lua: Lua,
-    api: T,
+    api: Arc<T>,
+    audit: Auditor,
+    #[cfg(feature = "metrics")]
+    metrics: Meter,
+    register_api_v2: Option<RegisterFn<T>>,
+    execution_budget: Option<u64>,
+    budget_remaining: Arc<AtomicU64>,
+    timeout: Option<Duration>,
+    /// When the call in progress times out
+    deadline: Arc<RwLock<Option<Instant>>>,
+    memory_limit: Option<usize>,
+    stack_limit: Option<usize>,
+    /// Token of the call in progress, see [`LuaTappletHost::run_with_cancel`]
+    cancel: Arc<RwLock<Option<CancellationToken>>>,
+    table_conversion: TableConversion,
+    log: GuestLog,
+    ambient: Ambient,
+    /// Where `minotari_http_get` and `minotari_http_post` requests go, if anywhere
+    network: GuestNetwork,
+    /// What answers `minotari_get_tip_height` and the other chain queries
+    chain: GuestChain,
+    storage_key: Option<StorageKey>,
+    storage_quota: StorageQuota,
+    /// Where appends raise `slot_changed` events, if anywhere
+    events: Option<EventSender>,
+    rate_limiter: RateLimiter,
+    governor: Option<ResourceGovernor>,
+    interceptors: Interceptors,
+    result_cache: Option<ResultCache>,
+    router: Option<RouterHandle>,
+    /// Kept to load the script again on [`LuaTappletHost::reload`]
+    sandbox: SandboxOptions,
+}
+
+/// What error messages and tracebacks call the script at `lua_path`
+fn chunk_name(config: &TappletManifest, lua_path: &Path) -> String {
+    match lua_path.file_name() {
+        Some(file_name) => format!("@{}", file_name.to_string_lossy()),
+        None => format!("={}", config.name),
+    }
 }
 
-impl<T: MinotariTappletApiV1 + 'static> LuaTappletHost<T> {
+/// A Lua host over a type-erased API, so hosts for different APIs can be stored together
+pub type DynLuaTappletHost = LuaTappletHost<dyn MinotariTappletApiV1>;
+
+impl<T: MinotariTappletApiV1 + ?Sized + 'static> LuaTappletHost<T> {
     /// Create a new LuaTappletHost by loading a Lua script from a file
     pub fn new(
         config: TappletManifest,
         lua_path: impl AsRef<Path>,
         api: T,
+    ) -> Result<Self, HostError>
+    where
+        T: Sized,
+    {
+        Self::new_shared(config, lua_path, Arc::new(api))
+    }
+
+    /// Create a new LuaTappletHost from a Lua code string
+    pub fn from_string(config: TappletManifest, lua_code: &str, api: T) -> Result<Self, HostError>
+    where
+        T: Sized,
+    {
+        Self::from_string_shared(config, lua_code, Arc::new(api))
+    }
+
+    /// Create a new LuaTappletHost from a Lua script file with a shared, possibly `dyn`, API.
+    ///
+    /// The script can `require` modules from its own directory.
+    pub fn new_shared(
+        config: TappletManifest,
+        lua_path: impl AsRef<Path>,
+        api: Arc<T>,
+    ) -> Result<Self, HostError> {
+        Self::new_sandboxed(config, lua_path, api, SandboxOptions::default())
+    }
+
+    /// Create a new LuaTappletHost from a Lua script file with custom sandbox options.
+    ///
+    /// Unless the options set a module root, the script's directory is used.
+    pub fn new_sandboxed(
+        config: TappletManifest,
+        lua_path: impl AsRef<Path>,
+        api: Arc<T>,
+        mut sandbox: SandboxOptions,
     ) -> Result<Self, HostError> {
+        let lua_path = lua_path.as_ref();
+        let chunk_name = chunk_name(&config, lua_path);
         // Read the Lua file
         let lua_code = std::fs::read_to_string(lua_path)?;
+        config
+            .verify_entrypoint(RuntimeKind::Lua, lua_path, lua_code.as_bytes())
+            .map_err(|e| HostError::IntegrityMismatch(e.to_string()))?;
+        if sandbox.module_root().is_none() {
+            let dir = lua_path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            sandbox = sandbox.with_module_root(dir);
+        }
+        Self::load(config, &lua_code, &chunk_name, api, &sandbox)
+    }
 
-        // Create a new Lua instance
-        let lua = Lua::new();
-        lua.sandbox(true)?;
+    /// Create a new LuaTappletHost from a Lua script file with the limits, sandbox
+    /// and hooks of `options`
+    pub fn new_with_options(
+        config: TappletManifest,
+        lua_path: impl AsRef<Path>,
+        api: Arc<T>,
+        options: &HostOptions,
+    ) -> Result<Self, HostError> {
+        Ok(
+            Self::new_sandboxed(config, lua_path, api, options.lua_sandbox())?
+                .apply_options(options),
+        )
+    }
 
-        // Load and execute the Lua code to define functions
-        lua.load(&lua_code)
-            .exec()
-            .map_err(|e| HostError::LuaLoadError(e.to_string()))?;
+    /// Create a new LuaTappletHost from a Lua code string with the limits, sandbox
+    /// and hooks of `options`
+    pub fn from_string_with_options(
+        config: TappletManifest,
+        lua_code: &str,
+        api: Arc<T>,
+        options: &HostOptions,
+    ) -> Result<Self, HostError> {
+        let sandbox = options.lua_sandbox();
+        Ok(Self::from_string_sandboxed(config, lua_code, api, &sandbox)?.apply_options(options))
+    }
 
-        Ok(Self { config, lua, api })
+    /// Create a new LuaTappletHost from a Lua code string with a shared, possibly `dyn`, API
+    pub fn from_string_shared(
+        config: TappletManifest,
+        lua_code: &str,
+        api: Arc<T>,
+    ) -> Result<Self, HostError> {
+        Self::from_string_sandboxed(config, lua_code, api, &SandboxOptions::default())
     }
 
-    /// Create a new LuaTappletHost from a Lua code string
-    pub fn from_string(config: TappletManifest, lua_code: &str, api: T) -> Result<Self, HostError> {
-        // Create a new Lua instance
+    /// Create a new LuaTappletHost from a Lua code string with custom sandbox options
+    pub fn from_string_sandboxed(
+        config: TappletManifest,
+        lua_code: &str,
+        api: Arc<T>,
+        sandbox: &SandboxOptions,
+    ) -> Result<Self, HostError> {
+        let chunk_name = format!("={}", config.name);
+        Self::load(config, lua_code, &chunk_name, api, sandbox)
+    }
+
+    /// `chunk_name` is what error messages and tracebacks call the script
+    fn load(
+        config: TappletManifest,
+        lua_code: &str,
+        chunk_name: &str,
+        api: Arc<T>,
+        sandbox: &SandboxOptions,
+    ) -> Result<Self, HostError> {
+        check_host_api(&config)?;
+        // Create a new Lua instance with the denied globals removed
         let lua = Lua::new();
+        sandbox.apply(&lua)?;
+        lua_json::register_tags(&lua)?;
+
+        // Luau resolves built-in globals like `print` when the script is loaded, so
+        // replace it now and let `with_log_sink` pick the destination later
+        let log = GuestLog::new(&config.name);
+        let print_log = log.clone();
+        let print = lua.create_function(move |_, args: mlua::Variadic<mlua::Value>| {
+            let message = args
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect::<mlua::Result<Vec<_>>>()?
+                .join("\t");
+            print_log.log(LogLevel::Info, message);
+            Ok(())
+        })?;
+        lua.globals().set("print", print)?;
+        let level_log = log.clone();
+        let minotari_log =
+            lua.create_function(move |_, (level, message): (String, mlua::Value)| {
+                let level = level.parse::<LogLevel>().map_err(mlua::Error::runtime)?;
+                level_log.log(level, message.to_string()?);
+                Ok(())
+            })?;
+        lua.globals().set("minotari_log", minotari_log)?;
+        // The sandbox takes `os` away from deterministic scripts, so their clock
+        // and randomness come from a seeded source instead
+        let ambient = Ambient::new(match sandbox.is_deterministic() {
+            true => AmbientMode::deterministic(0),
+            false => AmbientMode::Real,
+        });
+        let now_ambient = ambient.clone();
+        let now = lua.create_function(move |_, ()| Ok(now_ambient.now_ms()))?;
+        lua.globals().set("minotari_now", now)?;
+        let random_ambient = ambient.clone();
+        let random_bytes = lua.create_function(move |lua, len: usize| {
+            let bytes = random_ambient
+                .random_bytes(len)
+                .map_err(mlua::Error::runtime)?;
+            lua.create_string(&bytes)
+        })?;
+        lua.globals().set("minotari_random_bytes", random_bytes)?;
 
         // Load and execute the Lua code to define functions
         lua.load(lua_code)
+            .set_name(chunk_name)
             .exec()
-            .map_err(|e| HostError::LuaLoadError(e.to_string()))?;
+            .map_err(|e| {
+                HostError::LuaLoadError(LuaErrorDetails::from_lua_error(&e).to_string())
+            })?;
+
+        let network = GuestNetwork::new(&config.permissions);
+        let chain = GuestChain::new(&config.name, &config.permissions);
+        let host = Self {
+            config,
+            lua,
+            api,
+            audit: Auditor::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Meter::default(),
+            register_api_v2: None,
+            execution_budget: None,
+            budget_remaining: Arc::new(AtomicU64::new(u64::MAX)),
+            timeout: None,
+            deadline: Arc::default(),
+            memory_limit: None,
+            stack_limit: None,
+            cancel: Arc::default(),
+            table_conversion: TableConversion::default(),
+            log,
+            ambient,
+            network,
+            chain,
+            storage_key: None,
+            storage_quota: StorageQuota::default(),
+            events: None,
+            rate_limiter: RateLimiter::default(),
+            governor: None,
+            interceptors: Interceptors::default(),
+            result_cache: None,
+            router: None,
+            sandbox: sandbox.clone(),
+        };
+        if host.needs_interrupt() {
+            host.set_interrupt();
+        }
+        Ok(host)
+    }
 
-        Ok(Self { config, lua, api })
+    /// Replace the tapplet's script with the one at `lua_path`, e.g. after editing it.
+    ///
+    /// The script runs in a fresh Lua state, so globals set by the old script are
+    /// gone and modules are `require`d again, while the manifest, API, sinks and
+    /// limits of the host are kept.
+    pub fn reload(&mut self, lua_path: impl AsRef<Path>) -> Result<(), HostError> {
+        let lua_path = lua_path.as_ref();
+        let lua_code = std::fs::read_to_string(lua_path)?;
+        self.config
+            .verify_entrypoint(RuntimeKind::Lua, lua_path, lua_code.as_bytes())
+            .map_err(|e| HostError::IntegrityMismatch(e.to_string()))?;
+        let chunk_name = chunk_name(&self.config, lua_path);
+        let reloaded = Self::load(
+            self.config.clone(),
+            &lua_code,
+            &chunk_name,
+            self.api.clone(),
+            &self.sandbox,
+        )?;
+        reloaded.log.inherit(&self.log);
+        reloaded.ambient.inherit(&self.ambient);
+        self.lua = reloaded.lua;
+        self.log = reloaded.log;
+        self.ambient = reloaded.ambient;
+        if self.needs_interrupt() {
+            self.set_interrupt();
+        }
+        if let Some(limit) = self.memory_limit {
+            self.lua.set_memory_limit(limit)?;
+        }
+        if let Some(governor) = &self.governor {
+            governor.release(&self.config.name);
+        }
+        if let Some(cache) = &self.result_cache {
+            cache.invalidate(&self.config.qualified_name());
+        }
+        Ok(())
     }
 
     /// Run a method with the given arguments
@@ -380,15 +2324,318 @@ impl<T: MinotariTappletApiV1 + 'static> LuaTappletHost<T> {
     /// # Arguments
     /// * `method` - The name of the method to call
     /// * `args` - JSON value representing the arguments
+    /// * `context` - Who is calling, checked against the method's declared permissions
     ///
     /// # Returns
     /// A JSON value containing the result of the method call
-    pub async fn run(&self, method: &str, args: Value) -> Result<Value, HostError> {
-        // Verify the method exists in the API config
-        if !self.config.api.methods.contains(&method.to_string()) {
-            return Err(HostError::MethodNotFound(method.to_string()));
+    pub async fn run(
+        &self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError> {
+        self.run_in_batch(method, args, context, None).await
+    }
+
+    /// Run several methods one after the other, e.g. the reads a UI needs to
+    /// render one screen. The host API is set up once for the whole batch rather
+    /// than for every call. Each call succeeds or fails on its own, like
+    /// [`Self::run`].
+    pub async fn run_batch(
+        &mut self,
+        calls: Vec<(String, Value)>,
+        context: &CallContext,
+    ) -> Vec<Result<Value, HostError>> {
+        let batch = self.start_batch(None, context);
+        let mut results = Vec::with_capacity(calls.len());
+        for (method, args) in calls {
+            results.push(
+                self.run_in_batch(&method, args, context, Some(&batch))
+                    .await,
+            );
+        }
+        results
+    }
+
+    /// [`Self::run_batch`], all or nothing.
+    ///
+    /// Appends to data slots are held back, seen by later calls of the batch, and
+    /// only made once every call has succeeded. The batch stops at the first call
+    /// that fails, which returns its error while every other call returns
+    /// [`HostError::BatchAborted`]. Only data slots are rolled back: other host
+    /// functions, like `minotari_add_watched_viewkey`, take effect right away.
+    pub async fn run_batch_atomic(
+        &mut self,
+        calls: Vec<(String, Value)>,
+        context: &CallContext,
+    ) -> Vec<Result<Value, HostError>> {
+        let staged = Arc::new(StagedWrites::default());
+        let batch = self.start_batch(Some(staged.clone()), context);
+        let count = calls.len();
+        let mut results = Vec::with_capacity(count);
+        let mut failure = None;
+        for (index, (method, args)) in calls.into_iter().enumerate() {
+            match self
+                .run_in_batch(&method, args, context, Some(&batch))
+                .await
+            {
+                Ok(value) => results.push(Ok(value)),
+                Err(err) => {
+                    let reason =
+                        format!("call {} ({}) of the batch failed: {}", index, method, err);
+                    failure = Some((index, err, reason));
+                    break;
+                }
+            }
+        }
+        let (mut failed, reason) = match failure {
+            Some((index, err, reason)) => (Some((index, err)), reason),
+            None => match staged.commit(self.api.as_ref()).await {
+                Ok(_) => return results,
+                Err(e) => (None, format!("the batch's appends failed: {}", e)),
+            },
+        };
+        (0..count)
+            .map(
+                |index| match failed.take_if(|(failed, _)| *failed == index) {
+                    Some((_, err)) => Err(err),
+                    None => Err(HostError::BatchAborted(reason.clone())),
+                },
+            )
+            .collect()
+    }
+
+    /// Run a method whose result is handed over in chunks, see
+    /// [`LuaChunkStream`]. The method only starts once the first chunk is pulled.
+    pub fn run_stream(
+        &mut self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> LuaChunkStream<'_, T> {
+        LuaChunkStream {
+            host: self,
+            method: method.to_string(),
+            args,
+            context: context.clone(),
+            thread: None,
+            chunks: 0,
+            done: false,
+        }
+    }
+
+    /// Register the host functions for every call of a batch. If that fails the
+    /// calls register them themselves, and report why.
+    fn start_batch(&self, staged: Option<Arc<StagedWrites>>, context: &CallContext) -> Batch {
+        let mut batch = Batch {
+            staged,
+            registered: false,
+        };
+        batch.registered = self.register_host_functions(context, &batch).is_ok();
+        batch
+    }
+
+    async fn run_in_batch(
+        &self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+        batch: Option<&Batch>,
+    ) -> Result<Value, HostError> {
+        self.instrumented(method, &args, || {
+            let call = InterceptedCall {
+                tapplet: &self.config.name,
+                method,
+                args: &args,
+                context,
+            };
+            self.interceptors
+                .around(&call, || self.call_method(method, &args, context, batch))
+        })
+    }
+
+    /// Run `call` under the limits of `method`, admitted by the governor, and
+    /// record it in the audit log, metrics and log sink
+    fn instrumented<R>(
+        &self,
+        method: &str,
+        args: &Value,
+        call: impl FnOnce() -> Result<R, HostError>,
+    ) -> Result<R, HostError> {
+        let started = Instant::now();
+        // The method's own limits take the place of the host's
+        let definition = self.config.api.method(method);
+        let budget = definition
+            .and_then(|definition| definition.max_fuel)
+            .or(self.execution_budget);
+        self.budget_remaining
+            .store(budget.unwrap_or(u64::MAX), Ordering::Relaxed);
+        let timeout = definition
+            .and_then(MethodDefinition::timeout)
+            .or(self.timeout);
+        *self.deadline.write().unwrap() = timeout.map(|timeout| started + timeout);
+        let memory_limit = definition
+            .and_then(|definition| definition.max_memory)
+            .map(|max_memory| max_memory as usize)
+            .or(self.memory_limit);
+        if let Some(limit) = memory_limit {
+            self.lua.set_memory_limit(limit)?;
+        }
+
+        let warning = deprecation_warning(&self.config, method);
+        let result = trace::call(&self.config.name, method, || {
+            let mut permit = admit(self.governor.as_ref(), &self.config.name)?;
+            let result = call();
+            if let Some(permit) = &mut permit {
+                permit.report_memory(self.lua.used_memory());
+            }
+            result
+        });
+        *self.deadline.write().unwrap() = None;
+        if memory_limit.is_some() {
+            // 0 lifts the limit
+            self.lua
+                .set_memory_limit(self.memory_limit.unwrap_or_default())?;
+        }
+        self.audit.record_with_warning(
+            AuditKind::MethodCall,
+            method,
+            || summarize_args(args),
+            started,
+            &result,
+            warning,
+        );
+        #[cfg(feature = "metrics")]
+        self.metrics.record_call(
+            method,
+            started.elapsed(),
+            budget.map(|budget| budget - self.budget_remaining.load(Ordering::Relaxed)),
+            result.as_ref().err().map(HostError::code),
+        );
+        if let (Some(log), Err(e @ HostError::LuaExecutionError(_))) = (self.log.sink(), &result) {
+            log.log(LogRecord {
+                tapplet: self.config.name.clone(),
+                level: LogLevel::Error,
+                message: format!("{} failed: {}", method, e),
+            });
+        }
+        result
+    }
+
+    /// [`Self::run`], failing with [`HostError::Cancelled`] once `token` is
+    /// cancelled. The script is stopped at its next function call or loop
+    /// iteration; a host function it is waiting on finishes first.
+    pub async fn run_with_cancel(
+        &self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+        token: &CancellationToken,
+    ) -> Result<Value, HostError> {
+        *self.cancel.write().unwrap() = Some(token.clone());
+        self.set_interrupt();
+        let result = self.run(method, args, context).await;
+        *self.cancel.write().unwrap() = None;
+        result
+    }
+
+    /// Call the script's global [`GUEST_HEALTH_EXPORT`] function, if it has one,
+    /// returning whether it did. Fails if the function raises an error, returns
+    /// `false` or doesn't return within `timeout`. Host functions are only
+    /// available to it if a call registered them before.
+    pub fn check_health(&self, timeout: Duration) -> Result<bool, HostError> {
+        let Ok(mlua::Value::Function(health)) = self.lua.globals().get(GUEST_HEALTH_EXPORT) else {
+            return Ok(false);
+        };
+        self.set_interrupt();
+        self.budget_remaining.store(u64::MAX, Ordering::Relaxed);
+        *self.deadline.write().unwrap() = Some(Instant::now() + timeout);
+        let result = health.call::<mlua::Value>(());
+        let result = match result {
+            Ok(mlua::Value::Boolean(false)) => Err(HostError::ExecutionError(format!(
+                "{} returned false",
+                GUEST_HEALTH_EXPORT
+            ))),
+            Ok(_) => Ok(true),
+            Err(e) => Err(self.call_error(GUEST_HEALTH_EXPORT, e)),
+        };
+        *self.deadline.write().unwrap() = None;
+        result
+    }
+
+    /// Memory the Lua state uses, in bytes
+    pub fn memory_usage(&self) -> u64 {
+        self.lua.used_memory() as u64
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    fn is_past_deadline(&self) -> bool {
+        self.deadline
+            .read()
+            .unwrap()
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    fn call_method(
+        &self,
+        method: &str,
+        args: &Value,
+        context: &CallContext,
+        batch: Option<&Batch>,
+    ) -> Result<Value, HostError> {
+        let args = &self.check_call(method, args, context)?;
+        let cache = pure_method_cache(self.result_cache.as_ref(), &self.config, method);
+        if let Some(cache) = cache
+            && let Some(result) = cache.get(&self.config, method, args)
+        {
+            return Ok(result);
+        }
+        let (func, lua_args) = self.prepare_call(method, args, context, batch)?;
+        // Call the function; `run` has reset the budget and set the deadline
+        let result: mlua::Value = func
+            .call(lua_args)
+            .map_err(|e| self.call_error(method, e))?;
+        let result = self.result_to_json(&result, self.return_type(method))?;
+        if let Some(cache) = cache {
+            cache.insert(&self.config, method, args, &result);
+        }
+        Ok(result)
+    }
+
+    /// Check that the caller may make the call, returning the arguments coerced
+    /// to the method's parameter types
+    fn check_call(
+        &self,
+        method: &str,
+        args: &Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError> {
+        if self.is_cancelled() {
+            return Err(HostError::Cancelled(method.to_string()));
         }
+        // Verify the method exists in the API config and the caller may call it
+        context.ensure_allowed(&self.config, method)?;
+        self.config
+            .api
+            .coerce_args(method, args.clone())
+            .map_err(HostError::InvalidArguments)
+    }
 
+    /// Get the method's function and the checked arguments ready
+    fn prepare_call(
+        &self,
+        method: &str,
+        args: &Value,
+        context: &CallContext,
+        batch: Option<&Batch>,
+    ) -> Result<(mlua::Function, mlua::Value), HostError> {
         // Get the Lua function
         let func: mlua::Function = self
             .lua
@@ -397,49 +2644,144 @@ impl<T: MinotariTappletApiV1 + 'static> LuaTappletHost<T> {
             .map_err(|_| HostError::MethodNotFound(method.to_string()))?;
 
         // Convert JSON args to Lua values
-        let lua_args = self.json_to_lua_value(&args)?;
+        let definition = self.config.api.method(method);
+        let lua_args = match definition {
+            Some(definition) => {
+                lua_json::typed_json_to_lua(&self.lua, args, &definition.params_type())?
+            }
+            None => lua_json::json_to_lua(&self.lua, args)?,
+        };
+
+        // Host functions are registered once for a whole batch
+        match batch {
+            Some(batch) if batch.registered => {}
+            Some(batch) => self.register_host_functions(context, batch)?,
+            None => self.register_host_functions(context, &Batch::default())?,
+        }
+
+        Ok((func, lua_args))
+    }
+
+    /// What made a call of `method` fail
+    fn call_error(&self, method: &str, e: mlua::Error) -> HostError {
+        if self.is_cancelled() {
+            HostError::Cancelled(method.to_string())
+        } else if self.budget_remaining.load(Ordering::Relaxed) == 0 {
+            HostError::ExecutionBudgetExceeded(method.to_string())
+        } else if self.is_past_deadline() {
+            HostError::Timeout(method.to_string())
+        } else if matches!(e, mlua::Error::MemoryError(_)) {
+            HostError::MemoryLimitExceeded(method.to_string())
+        } else if let Some(err) = find_host_error(&e)
+            && let Some(err) = propagated(err)
+        {
+            err
+        } else {
+            HostError::LuaExecutionError(LuaErrorDetails::from_lua_error(&e))
+        }
+    }
 
-        // load API
-        let api2 = self.api.clone();
+    fn return_type(&self, method: &str) -> Option<&ParamType> {
+        self.config
+            .api
+            .method(method)
+            .map(|definition| &definition.returns.return_type)
+    }
+
+    /// Convert a value returned by the script, checked against `return_type`
+    fn result_to_json(
+        &self,
+        value: &mlua::Value,
+        return_type: Option<&ParamType>,
+    ) -> Result<Value, HostError> {
+        match return_type {
+            Some(return_type) => {
+                lua_json::typed_lua_to_json(&self.lua, value, return_type, self.table_conversion)
+            }
+            None => lua_json::lua_to_json(&self.lua, value, self.table_conversion),
+        }
+    }
 
+    /// Expose the host API to the script for a call made by `context`
+    fn register_host_functions(
+        &self,
+        context: &CallContext,
+        batch: &Batch,
+    ) -> Result<(), HostError> {
+        let storage = Arc::new(
+            TappletStorage::new(
+                self.api.clone(),
+                &self.config.qualified_name(),
+                self.storage_quota,
+            )
+            .with_staged(batch.staged.clone())
+            .with_events(self.events.clone()),
+        );
+        let storage2 = storage.clone();
+        let audit2 = self.audit.clone();
+        let limiter = self.rate_limiter.clone();
         let rust_append_data =
             self.lua
                 .create_function(move |_, (slot, value): (String, String)| {
-                    task::block_in_place(|| {
-                        Handle::current().block_on(async {
-                            api2.append_data(&slot, &value).await?;
-                            Result::<_, anyhow::Error>::Ok(())
-                        })?;
-                        Ok(())
-                    })
-                })?;
-        let api3 = self.api.clone();
-        let rust_load_data_entries = self.lua.create_function(move |l, slot: String| {
-            task::block_in_place(|| {
-                let result = Handle::current().block_on(async {
-                    let table = l.create_table()?;
-                    // println!("Loading data entries from slot '{}'", slot);
-                    let entries = api3.load_data_entries(&slot).await?;
-                    for (i, entry) in entries.iter().enumerate() {
-                        table.set(i + 1, entry.clone())?;
-                    }
-                    Result::<_, anyhow::Error>::Ok(entries)
+                    limiter
+                        .check("minotari_append_data")
+                        .map_err(|e| to_lua_error(e.into()))?;
+                    let started = Instant::now();
+                    let result = task::block_in_place(|| {
+                        Handle::current().block_on(storage2.append(&slot, &value))
+                    });
+                    audit2.record(
+                        AuditKind::HostCall,
+                        "minotari_append_data",
+                        || format!("slot: {}, value: string({})", slot, value.len()),
+                        started,
+                        &result,
+                    );
+                    result.map_err(to_lua_error)
                 })?;
-                Ok(result)
-            })
+
+        let storage3 = storage.clone();
+        let audit3 = self.audit.clone();
+        let limiter = self.rate_limiter.clone();
+        let rust_load_data_entries = self.lua.create_function(move |_, slot: String| {
+            limiter
+                .check("minotari_load_data_entries")
+                .map_err(|e| to_lua_error(e.into()))?;
+            let started = Instant::now();
+            let result = task::block_in_place(|| Handle::current().block_on(storage3.load(&slot)));
+            audit3.record(
+                AuditKind::HostCall,
+                "minotari_load_data_entries",
+                || format!("slot: {}", slot),
+                started,
+                &result,
+            );
+            Ok(result?)
         })?;
 
         let api4 = self.api.clone();
+        let audit4 = self.audit.clone();
+        let limiter = self.rate_limiter.clone();
         let rust_add_watched_viewkey =
             self.lua
                 .create_function(move |_, (viewkey, birthday): (String, i32)| {
-                    task::block_in_place(|| {
-                        Handle::current().block_on(async {
-                            api4.add_watched_viewkey(&viewkey, birthday as u64).await?;
-                            Result::<_, anyhow::Error>::Ok(())
-                        })?;
-                        Ok(())
-                    })
+                    limiter
+                        .check("minotari_add_watched_viewkey")
+                        .map_err(|e| to_lua_error(e.into()))?;
+                    let started = Instant::now();
+                    let result = task::block_in_place(|| {
+                        Handle::current()
+                            .block_on(api4.add_watched_viewkey(&viewkey, birthday as u64))
+                    });
+                    audit4.record(
+                        AuditKind::HostCall,
+                        "minotari_add_watched_viewkey",
+                        || format!("viewkey: string({}), birthday: {}", viewkey.len(), birthday),
+                        started,
+                        &result,
+                    );
+                    result?;
+                    Ok(())
                 })?;
 
         self.lua
@@ -451,145 +2793,457 @@ impl<T: MinotariTappletApiV1 + 'static> LuaTappletHost<T> {
         self.lua
             .globals()
             .set("minotari_add_watched_viewkey", rust_add_watched_viewkey)?;
+        if let Some(key) = &self.storage_key {
+            self.register_secure_storage(key, &storage)?;
+        }
+        self.register_network()?;
+        self.register_chain()?;
+        if let Some(register_api_v2) = self.register_api_v2 {
+            register_api_v2(self, context)?;
+        }
+        if let Some(router) = &self.router {
+            self.register_router(router)?;
+        }
+        Ok(())
+    }
 
-        // self.lua.globals().set("api", self.lua.create_table()?)?;
+    /// Record every call into and out of this tapplet in the given audit sink
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.audit = Auditor::new(Some(sink), &self.config.name);
+        self
+    }
 
-        // Call the function
-        let result: mlua::Value = func
-            .call(lua_args)
-            .map_err(|e| HostError::LuaExecutionError(e.to_string()))?;
+    /// Report call counts, durations, errors and, with an execution budget, the
+    /// budget used to the given metrics sink
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics_sink(mut self, sink: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = Meter::new(sink, &self.config.name);
+        self
+    }
 
-        // Convert result back to JSON
-        let json_result = self.lua_value_to_json(&result)?;
+    /// Abort a call with [`HostError::ExecutionBudgetExceeded`] once the script
+    /// has passed `budget` interrupt checks.
+    ///
+    /// Luau has no per-instruction hook; it checks for interrupts on every function
+    /// call and loop iteration, which is enough to stop runaway loops and recursion.
+    /// The budget is reset at the start of each `run()`.
+    pub fn with_execution_budget(mut self, budget: u64) -> Self {
+        self.execution_budget = Some(budget);
+        self.set_interrupt();
+        self
+    }
 
-        Ok(json_result)
+    /// Abort a call with [`HostError::Timeout`] once it has run for `timeout`.
+    ///
+    /// Like the execution budget, the timeout is checked on every function call and
+    /// loop iteration, so a script waiting on a host function isn't stopped until
+    /// the function returns. Methods may set their own `timeout_ms`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self.set_interrupt();
+        self
     }
 
-    /// Convert JSON value to Lua value
-    fn json_to_lua_value(&self, value: &Value) -> Result<mlua::Value, HostError> {
-        match value {
-            Value::Null => Ok(mlua::Value::Nil),
-            Value::Bool(b) => Ok(mlua::Value::Boolean(*b)),
-            Value::Number(n) => {
-                if let Some(i) = n.as_i64() {
-                    if i >= i32::MIN as i64 && i <= i32::MAX as i64 {
-                        Ok(mlua::Value::Integer(i as i32))
-                    } else {
-                        Err(HostError::InvalidArguments(format!(
-                            "Integer out of range for Lua: {}",
-                            i
-                        )))
-                    }
-                } else if let Some(f) = n.as_f64() {
-                    Ok(mlua::Value::Number(f))
-                } else {
-                    Err(HostError::InvalidArguments(format!(
-                        "Unsupported number type: {}",
-                        n
-                    )))
-                }
-            }
-            Value::String(s) => self
-                .lua
-                .create_string(s)
-                .map(mlua::Value::String)
-                .map_err(|e| HostError::InvalidArguments(e.to_string())),
-            Value::Array(arr) => {
-                let table = self.lua.create_table().map_err(|e| {
-                    HostError::InvalidArguments(format!("Failed to create table: {}", e))
-                })?;
-                for (i, item) in arr.iter().enumerate() {
-                    let lua_value = self.json_to_lua_value(item)?;
-                    table
-                        .set(i + 1, lua_value)
-                        .map_err(|e| HostError::InvalidArguments(e.to_string()))?;
-                }
-                Ok(mlua::Value::Table(table))
-            }
-            Value::Object(obj) => {
-                let table = self.lua.create_table().map_err(|e| {
-                    HostError::InvalidArguments(format!("Failed to create table: {}", e))
-                })?;
-                for (key, val) in obj {
-                    let lua_value = self.json_to_lua_value(val)?;
-                    table
-                        .set(key.as_str(), lua_value)
-                        .map_err(|e| HostError::InvalidArguments(e.to_string()))?;
-                }
-                Ok(mlua::Value::Table(table))
-            }
+    /// Abort a call with [`HostError::MemoryLimitExceeded`] once the script's Lua
+    /// state holds more than `bytes`. Methods may set their own `max_memory`.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        // Luau only fails to set a limit when it doesn't support limits at all
+        let _ = self.lua.set_memory_limit(bytes);
+        self
+    }
+
+    /// Fail a call once more than `depth` functions are running at once, e.g.
+    /// in a runaway recursion. Like the execution budget, the depth is checked
+    /// on every function call; the script can catch the error with `pcall`.
+    pub fn with_stack_limit(mut self, depth: usize) -> Self {
+        self.stack_limit = Some(depth);
+        self.set_interrupt();
+        self
+    }
+
+    /// Apply the limits and hooks of `options`. The sandbox is set when the
+    /// script is loaded, see [`LuaTappletHost::new_with_options`].
+    pub(crate) fn apply_options(mut self, options: &HostOptions) -> Self {
+        if let Some(fuel) = options.fuel {
+            self = self.with_execution_budget(fuel);
+        }
+        if let Some(timeout) = options.timeout {
+            self = self.with_timeout(timeout);
+        }
+        if let Some(bytes) = options.memory_limit {
+            self = self.with_memory_limit(bytes);
         }
+        if let Some(depth) = options.stack_limit {
+            self = self.with_stack_limit(depth);
+        }
+        if let Some(quota) = options.storage_quota {
+            self = self.with_storage_quota(quota);
+        }
+        if let Some(events) = &options.events {
+            self = self.with_event_sender(events.clone());
+        }
+        apply_common_options!(self, options)
     }
 
-    /// Convert Lua value to JSON value
-    fn lua_value_to_json(&self, value: &mlua::Value) -> Result<Value, HostError> {
-        match value {
-            mlua::Value::Nil => Ok(Value::Null),
-            mlua::Value::Boolean(b) => Ok(Value::Bool(*b)),
-            mlua::Value::Integer(i) => Ok(Value::Number((*i).into())),
-            mlua::Value::Number(n) => {
-                if let Some(num) = serde_json::Number::from_f64(*n) {
-                    Ok(Value::Number(num))
-                } else {
-                    Err(HostError::ExecutionError(
-                        "Failed to convert Lua number to JSON".to_string(),
-                    ))
-                }
+    /// Count calls against the global budgets of `governor`, which other hosts
+    /// may share. The memory of the Lua state is reported after every call, so
+    /// the host may be evicted when tapplets use too much together.
+    pub fn with_governor(mut self, governor: &ResourceGovernor) -> Self {
+        self.governor = Some(governor.clone());
+        self
+    }
+
+    /// Run `interceptor` around every call, after the ones already registered
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn CallInterceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Answer repeated calls of methods marked `pure` from `cache`, which other
+    /// hosts may share
+    pub fn with_result_cache(mut self, cache: &ResultCache) -> Self {
+        self.result_cache = Some(cache.clone());
+        self
+    }
+
+    /// Whether calls need the interrupt, as the host or one of the methods has a
+    /// budget or timeout
+    fn needs_interrupt(&self) -> bool {
+        self.execution_budget.is_some()
+            || self.timeout.is_some()
+            || self.stack_limit.is_some()
+            || self
+                .config
+                .api
+                .method_definitions
+                .values()
+                .any(|method| method.max_fuel.is_some() || method.timeout_ms.is_some())
+    }
+
+    /// Stop the script once its call is cancelled, times out or has used up its budget
+    fn set_interrupt(&self) {
+        let remaining = self.budget_remaining.clone();
+        let deadline = self.deadline.clone();
+        let cancel = self.cancel.clone();
+        let stack_limit = self.stack_limit;
+        self.lua.set_interrupt(move |lua| {
+            // Keep failing once cancelled or exhausted, so `pcall` can't swallow the
+            // error and carry on
+            if cancel
+                .read()
+                .unwrap()
+                .as_ref()
+                .is_some_and(CancellationToken::is_cancelled)
+            {
+                return Err(mlua::Error::runtime("call cancelled"));
             }
-            mlua::Value::String(s) => {
-                let str_val = s
-                    .to_str()
-                    .map_err(|e| HostError::ExecutionError(e.to_string()))?;
-                Ok(Value::String(str_val.to_string()))
+            if remaining.load(Ordering::Relaxed) == 0 {
+                return Err(mlua::Error::runtime("execution budget exceeded"));
             }
-            mlua::Value::Table(table) => {
-                // Check if it's an array (sequential integer keys starting from 1)
-                let len = table
-                    .len()
-                    .map_err(|e| HostError::ExecutionError(e.to_string()))?;
-
-                if len > 0 {
-                    // Try to treat as array
-                    let mut arr = Vec::new();
-                    for i in 1..=len {
-                        let val: mlua::Value = table
-                            .get(i)
-                            .map_err(|e| HostError::ExecutionError(e.to_string()))?;
-                        arr.push(self.lua_value_to_json(&val)?);
-                    }
-                    Ok(Value::Array(arr))
-                } else {
-                    // Treat as object
-                    let mut obj = serde_json::Map::new();
-                    for pair in table.pairs::<mlua::Value, mlua::Value>() {
-                        let (key, val) =
-                            pair.map_err(|e| HostError::ExecutionError(e.to_string()))?;
-
-                        // Convert key to string
-                        let key_str = match key {
-                            mlua::Value::String(s) => s
-                                .to_str()
-                                .map_err(|e| HostError::ExecutionError(e.to_string()))?
-                                .to_string(),
-                            mlua::Value::Integer(i) => i.to_string(),
-                            mlua::Value::Number(n) => n.to_string(),
-                            _ => {
-                                return Err(HostError::ExecutionError(
-                                    "Unsupported table key type".to_string(),
-                                ));
-                            }
-                        };
-
-                        obj.insert(key_str, self.lua_value_to_json(&val)?);
-                    }
-                    Ok(Value::Object(obj))
-                }
+            remaining.fetch_sub(1, Ordering::Relaxed);
+            if deadline
+                .read()
+                .unwrap()
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return Err(mlua::Error::runtime("call timed out"));
             }
-            _ => Err(HostError::ExecutionError(format!(
-                "Unsupported Lua value type: {:?}",
-                value
-            ))),
+            if let Some(limit) = stack_limit
+                && lua.inspect_stack(limit).is_some()
+            {
+                return Err(mlua::Error::runtime("stack limit exceeded"));
+            }
+            Ok(mlua::VmState::Continue)
+        });
+    }
+
+    /// Limit how much the tapplet may store in each of its data slots
+    pub fn with_storage_quota(mut self, quota: StorageQuota) -> Self {
+        self.storage_quota = quota;
+        self
+    }
+
+    /// Raise a `slot_changed` event on `events` whenever the tapplet appends to one
+    /// of its slots, see [`crate::events::TappletEventBus::sender`]
+    pub fn with_event_sender(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Limit how often the tapplet may call host functions. A call over the limit
+    /// raises an error the script can catch with `pcall`; if it doesn't, the
+    /// method call fails with [`HostError::RateLimited`].
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limiter = RateLimiter::new(limit);
+        self
+    }
+
+    /// Expose `minotari_append_encrypted_data` and `minotari_load_encrypted_entries`,
+    /// which encrypt values with `key` before they are passed to the API
+    pub fn with_storage_key(mut self, key: StorageKey) -> Self {
+        self.storage_key = Some(key);
+        self
+    }
+
+    /// `minotari_http_get(url)` and `minotari_http_post(url, body, content_type?)`,
+    /// which return a table with the `status` and `body` of the response
+    fn register_network(&self) -> Result<(), HostError> {
+        let names = [
+            (HttpMethod::Get, "minotari_http_get"),
+            (HttpMethod::Post, "minotari_http_post"),
+        ];
+        for (method, name) in names {
+            let network = self.network.clone();
+            let audit = self.audit.clone();
+            let limiter = self.rate_limiter.clone();
+            let function = self.lua.create_function(
+                move |lua, (url, body, content_type): (String, Option<String>, Option<String>)| {
+                    limiter.check(name).map_err(|e| to_lua_error(e.into()))?;
+                    let request = match method {
+                        HttpMethod::Get => HttpRequest::get(&url),
+                        HttpMethod::Post => {
+                            HttpRequest::post(&url, body.unwrap_or_default(), content_type)
+                        }
+                    };
+                    let started = Instant::now();
+                    let result = task::block_in_place(|| network.fetch(&request));
+                    audit.record(
+                        AuditKind::HostCall,
+                        name,
+                        || match &request.body {
+                            Some(body) => format!("url: {}, body: string({})", url, body.len()),
+                            None => format!("url: {}", url),
+                        },
+                        started,
+                        &result,
+                    );
+                    let response = result.map_err(mlua::Error::external)?;
+                    let table = lua.create_table()?;
+                    table.set("status", response.status)?;
+                    table.set("body", response.body)?;
+                    Ok(table)
+                },
+            )?;
+            self.lua.globals().set(name, function)?;
         }
+        Ok(())
+    }
+
+    /// `minotari_get_tip_height()`, `minotari_get_block_header(height)` and
+    /// `minotari_get_kernel(excess)`; headers and kernels are tables, or `nil`
+    /// if there is none
+    fn register_chain(&self) -> Result<(), HostError> {
+        let chain = self.chain.clone();
+        let audit = self.audit.clone();
+        let limiter = self.rate_limiter.clone();
+        let get_tip_height = self.lua.create_function(move |_, ()| {
+            limiter
+                .check("minotari_get_tip_height")
+                .map_err(|e| to_lua_error(e.into()))?;
+            let started = Instant::now();
+            let result = chain.tip_height();
+            audit.record(
+                AuditKind::HostCall,
+                "minotari_get_tip_height",
+                String::new,
+                started,
+                &result,
+            );
+            result.map_err(mlua::Error::external)
+        })?;
+
+        let chain = self.chain.clone();
+        let audit = self.audit.clone();
+        let limiter = self.rate_limiter.clone();
+        let get_block_header = self.lua.create_function(move |lua, height: u64| {
+            limiter
+                .check("minotari_get_block_header")
+                .map_err(|e| to_lua_error(e.into()))?;
+            let started = Instant::now();
+            let result = chain.block_header(height);
+            audit.record(
+                AuditKind::HostCall,
+                "minotari_get_block_header",
+                || format!("height: {}", height),
+                started,
+                &result,
+            );
+            let header = result.map_err(mlua::Error::external)?;
+            serializable_to_lua(lua, &header)
+        })?;
+
+        let chain = self.chain.clone();
+        let audit = self.audit.clone();
+        let limiter = self.rate_limiter.clone();
+        let get_kernel = self.lua.create_function(move |lua, excess: String| {
+            limiter
+                .check("minotari_get_kernel")
+                .map_err(|e| to_lua_error(e.into()))?;
+            let started = Instant::now();
+            let result = chain.kernel(&excess);
+            audit.record(
+                AuditKind::HostCall,
+                "minotari_get_kernel",
+                || format!("excess: {}", excess),
+                started,
+                &result,
+            );
+            let kernel = result.map_err(mlua::Error::external)?;
+            serializable_to_lua(lua, &kernel)
+        })?;
+
+        let globals = self.lua.globals();
+        globals.set("minotari_get_tip_height", get_tip_height)?;
+        globals.set("minotari_get_block_header", get_block_header)?;
+        globals.set("minotari_get_kernel", get_kernel)?;
+        Ok(())
+    }
+
+    fn register_secure_storage(
+        &self,
+        key: &StorageKey,
+        storage: &Arc<TappletStorage<T>>,
+    ) -> Result<(), HostError> {
+        let storage2 = storage.clone();
+        let audit = self.audit.clone();
+        let encryption_key = key.clone();
+        let limiter = self.rate_limiter.clone();
+        let append_encrypted_data =
+            self.lua
+                .create_function(move |_, (slot, value): (String, String)| {
+                    limiter
+                        .check("minotari_append_encrypted_data")
+                        .map_err(|e| to_lua_error(e.into()))?;
+                    let started = Instant::now();
+                    let result = encryption_key.encrypt(&slot, &value).and_then(|entry| {
+                        task::block_in_place(|| {
+                            Handle::current().block_on(storage2.append(&slot, &entry))
+                        })
+                    });
+                    audit.record(
+                        AuditKind::HostCall,
+                        "minotari_append_encrypted_data",
+                        || format!("slot: {}, value: string({})", slot, value.len()),
+                        started,
+                        &result,
+                    );
+                    result.map_err(to_lua_error)
+                })?;
+
+        let storage3 = storage.clone();
+        let audit = self.audit.clone();
+        let decryption_key = key.clone();
+        let limiter = self.rate_limiter.clone();
+        let load_encrypted_entries = self.lua.create_function(move |_, slot: String| {
+            limiter
+                .check("minotari_load_encrypted_entries")
+                .map_err(|e| to_lua_error(e.into()))?;
+            let started = Instant::now();
+            let result = task::block_in_place(|| Handle::current().block_on(storage3.load(&slot)))
+                .and_then(|entries| {
+                    entries
+                        .iter()
+                        .map(|entry| decryption_key.decrypt(&slot, entry))
+                        .collect::<anyhow::Result<Vec<_>>>()
+                });
+            audit.record(
+                AuditKind::HostCall,
+                "minotari_load_encrypted_entries",
+                || format!("slot: {}", slot),
+                started,
+                &result,
+            );
+            Ok(result?)
+        })?;
+
+        let globals = self.lua.globals();
+        globals.set("minotari_append_encrypted_data", append_encrypted_data)?;
+        globals.set("minotari_load_encrypted_entries", load_encrypted_entries)?;
+        Ok(())
+    }
+
+    /// Let the tapplet call other tapplets registered with `router` through
+    /// `minotari_call_tapplet`, see [`crate::router`]
+    pub fn with_router(mut self, router: &TappletRouter) -> Self {
+        self.router = Some(router.handle());
+        self
+    }
+
+    fn register_router(&self, router: &RouterHandle) -> Result<(), HostError> {
+        let router = router.clone();
+        let caller = self.config.clone();
+        let audit = self.audit.clone();
+        let limiter = self.rate_limiter.clone();
+        let call_tapplet = self.lua.create_function(
+            move |l, (name, method, args_json): (String, String, Option<String>)| {
+                limiter
+                    .check("minotari_call_tapplet")
+                    .map_err(|e| to_lua_error(e.into()))?;
+                let started = Instant::now();
+                let result = serde_json::from_str(args_json.as_deref().unwrap_or("null"))
+                    .map_err(|e| HostError::InvalidArguments(format!("args_json: {}", e)))
+                    .and_then(|args| {
+                        task::block_in_place(|| {
+                            Handle::current()
+                                .block_on(router.call_from(&caller, &name, &method, args))
+                        })
+                    });
+                audit.record(
+                    AuditKind::HostCall,
+                    "minotari_call_tapplet",
+                    || format!("tapplet: {}, method: {}", name, method),
+                    started,
+                    &result,
+                );
+                let result = result.map_err(|e| to_lua_error(e.into()))?;
+                lua_json::json_to_lua(l, &result).map_err(mlua::Error::external)
+            },
+        )?;
+        self.lua
+            .globals()
+            .set("minotari_call_tapplet", call_tapplet)?;
+        Ok(())
+    }
+
+    /// Send the script's `print` output, and the tracebacks of failed calls, to `sink`
+    pub fn with_log_sink(self, sink: Arc<dyn LogSink>) -> Self {
+        self.log.set_sink(sink);
+        self
+    }
+
+    /// Drop `print` and `minotari_log` output below `level`, [`LogLevel::Info`]
+    /// by default
+    pub fn with_log_level(self, level: LogLevel) -> Self {
+        self.log.set_level(level);
+        self
+    }
+
+    /// Where `minotari_now` and `minotari_random_bytes` get their values from,
+    /// [`AmbientMode::Real`] unless the sandbox is deterministic
+    pub fn with_ambient_mode(self, mode: AmbientMode) -> Self {
+        self.ambient.set_mode(mode);
+        self
+    }
+
+    /// Let the script send HTTP requests through `access` to the hosts its
+    /// manifest allows, see [`crate::network`]. Without it every request fails.
+    pub fn with_network_access(self, access: NetworkAccess) -> Self {
+        self.network.set_access(Some(access));
+        self
+    }
+
+    /// Answer the script's chain queries with `api`, if its manifest asks for
+    /// `permissions.chain.read`, see [`crate::chain`]
+    pub fn with_chain_api(self, api: Arc<dyn MinotariChainApi>) -> Self {
+        self.chain.set_api(Some(api));
+        self
+    }
+
+    /// How returned tables that could be either a JSON array or object are converted
+    pub fn with_table_conversion(mut self, conversion: TableConversion) -> Self {
+        self.table_conversion = conversion;
+        self
     }
 
     /// Get the tapplet configuration
@@ -597,3 +3251,2037 @@ impl<T: MinotariTappletApiV1 + 'static> LuaTappletHost<T> {
         &self.config
     }
 }
+
+impl<T: MinotariTappletApiV2 + ?Sized + 'static> LuaTappletHost<T> {
+    /// Expose the wallet functions of [`MinotariTappletApiV2`] to the tapplet
+    pub fn with_api_v2(mut self) -> Self {
+        self.register_api_v2 = Some(Self::register_api_v2);
+        self
+    }
+
+    fn register_api_v2(&self, context: &CallContext) -> Result<(), HostError> {
+        let api = self.api.clone();
+        let audit = self.audit.clone();
+        let ctx = context.clone();
+        let limiter = self.rate_limiter.clone();
+        let get_balance = self.lua.create_function(move |l, ()| {
+            limiter
+                .check("minotari_get_balance")
+                .map_err(|e| to_lua_error(e.into()))?;
+            let started = Instant::now();
+            let result = ctx
+                .require_permission(PERMISSION_READ_BALANCE)
+                .map_err(anyhow::Error::from)
+                .and_then(|_| {
+                    task::block_in_place(|| Handle::current().block_on(api.get_balance()))
+                });
+            audit.record(
+                AuditKind::HostCall,
+                "minotari_get_balance",
+                String::new,
+                started,
+                &result,
+            );
+            balance_to_lua(l, &result?)
+        })?;
+
+        let api = self.api.clone();
+        let audit = self.audit.clone();
+        let ctx = context.clone();
+        let limiter = self.rate_limiter.clone();
+        let send_transaction = self.lua.create_function(
+            move |_, (destination, amount, fee): (String, BoxedInteger, BoxedInteger)| {
+                limiter
+                    .check("minotari_send_transaction")
+                    .map_err(|e| to_lua_error(e.into()))?;
+                let started = Instant::now();
+                let amount = u64::try_from(amount)?;
+                let fee = u64::try_from(fee)?;
+                let result = ctx
+                    .require_permission(PERMISSION_SEND_TRANSACTION)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|_| {
+                        task::block_in_place(|| {
+                            Handle::current().block_on(api.send_transaction(
+                                &destination,
+                                amount,
+                                fee,
+                            ))
+                        })
+                    });
+                audit.record(
+                    AuditKind::HostCall,
+                    "minotari_send_transaction",
+                    || {
+                        format!(
+                            "destination: string({}), amount: {}, fee: {}",
+                            destination.len(),
+                            amount,
+                            fee
+                        )
+                    },
+                    started,
+                    &result,
+                );
+                Ok(result?)
+            },
+        )?;
+
+        let api = self.api.clone();
+        let audit = self.audit.clone();
+        let ctx = context.clone();
+        let limiter = self.rate_limiter.clone();
+        let get_transactions =
+            self.lua
+                .create_function(move |l, filter: Option<mlua::Table>| {
+                    limiter
+                        .check("minotari_get_transactions")
+                        .map_err(|e| to_lua_error(e.into()))?;
+                    let started = Instant::now();
+                    let filter = transaction_filter_from_lua(filter)?;
+                    let result = ctx
+                        .require_permission(PERMISSION_READ_TRANSACTIONS)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|_| {
+                            task::block_in_place(|| {
+                                Handle::current().block_on(api.get_transactions(&filter))
+                            })
+                        });
+                    audit.record(
+                        AuditKind::HostCall,
+                        "minotari_get_transactions",
+                        || format!("{:?}", filter),
+                        started,
+                        &result,
+                    );
+                    let table = l.create_table()?;
+                    for (i, transaction) in result?.iter().enumerate() {
+                        table.set(i + 1, transaction_to_lua(l, transaction)?)?;
+                    }
+                    Ok(table)
+                })?;
+
+        let globals = self.lua.globals();
+        globals.set("minotari_get_balance", get_balance)?;
+        globals.set("minotari_send_transaction", send_transaction)?;
+        globals.set("minotari_get_transactions", get_transactions)?;
+        Ok(())
+    }
+}
+
+/// `value` as Lua through its JSON form, `nil` for `None`
+fn serializable_to_lua(lua: &Lua, value: &impl Serialize) -> mlua::Result<mlua::Value> {
+    let value = serde_json::to_value(value).map_err(mlua::Error::external)?;
+    lua_json::json_to_lua(lua, &value).map_err(mlua::Error::external)
+}
+
+/// Amounts in microMinotari can exceed what a Lua number holds exactly
+fn amount_to_lua(lua: &Lua, amount: u64) -> mlua::Result<mlua::Value> {
+    lua_json::integer_to_lua(lua, amount.into())
+}
+
+fn balance_to_lua(lua: &Lua, balance: &Balance) -> mlua::Result<mlua::Table> {
+    let table = lua.create_table()?;
+    table.set("available", amount_to_lua(lua, balance.available)?)?;
+    table.set(
+        "pending_incoming",
+        amount_to_lua(lua, balance.pending_incoming)?,
+    )?;
+    table.set(
+        "pending_outgoing",
+        amount_to_lua(lua, balance.pending_outgoing)?,
+    )?;
+    table.set("timelocked", amount_to_lua(lua, balance.timelocked)?)?;
+    Ok(table)
+}
+
+fn transaction_to_lua(lua: &Lua, transaction: &TransactionInfo) -> mlua::Result<mlua::Table> {
+    let table = lua.create_table()?;
+    table.set("id", transaction.id.as_str())?;
+    table.set("direction", transaction.direction.as_str())?;
+    table.set("status", transaction.status.as_str())?;
+    table.set("amount", amount_to_lua(lua, transaction.amount)?)?;
+    table.set("fee", amount_to_lua(lua, transaction.fee)?)?;
+    table.set("counterparty", transaction.counterparty.as_str())?;
+    table.set("timestamp", transaction.timestamp)?;
+    Ok(table)
+}
+
+fn transaction_filter_from_lua(filter: Option<mlua::Table>) -> mlua::Result<TransactionFilter> {
+    let Some(filter) = filter else {
+        return Ok(TransactionFilter::default());
+    };
+    let parse = |field: &str| -> mlua::Result<Option<Value>> {
+        Ok(filter.get::<Option<String>>(field)?.map(Value::String))
+    };
+    let direction = parse("direction")?
+        .map(serde_json::from_value)
+        .transpose()
+        .map_err(mlua::Error::external)?;
+    let status = parse("status")?
+        .map(serde_json::from_value)
+        .transpose()
+        .map_err(mlua::Error::external)?;
+    Ok(TransactionFilter {
+        direction,
+        status,
+        limit: filter.get("limit")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{BlockHeader, KernelInfo};
+    use crate::network::{HttpClient, HttpLimits, HttpResponse};
+
+    #[test]
+    fn test_host_error_display() {
+        let err = HostError::MethodNotFound("test_method".to_string());
+        assert_eq!(err.to_string(), "Method not found: test_method");
+    }
+
+    #[test]
+    fn test_host_error_codes() {
+        let err = HostError::MethodNotFound("test_method".to_string());
+        assert_eq!(err.code(), "METHOD_NOT_FOUND");
+        assert_eq!(
+            err.to_json(),
+            serde_json::json!({
+                "code": "METHOD_NOT_FOUND",
+                "message": "Method not found: test_method"
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_wasm_error() {
+        let config = TappletManifest {
+            name: "test".to_string(),
+            version: "0.1.0".to_string(),
+            friendly_name: "Test".to_string(),
+            description: Some("Test tapplet".to_string()),
+            publisher: "test_publisher".to_string(),
+            git: Some(crate::model::GitConfig {
+                url: "https://example.com".to_string(),
+                rev: "main".to_string(),
+            }),
+            runtime: None,
+            api: crate::model::ApiConfig {
+                methods: vec!["test".to_string()],
+                method_definitions: std::collections::HashMap::new(),
+            },
+            sigs: crate::model::SigsConfig {
+                todo: "test".to_string(),
+            },
+            public_key: "test_public_key".to_string(),
+            license: None,
+            requires_host_api: None,
+            yanked: false,
+            deprecated: None,
+            categories: Vec::new(),
+            tags: Vec::new(),
+            assets: Default::default(),
+            i18n: Default::default(),
+            tests: Default::default(),
+            events: Default::default(),
+            permissions: Default::default(),
+            schedule: Default::default(),
+            dependencies: Default::default(),
+            artifacts: Default::default(),
+            extra: Default::default(),
+        };
+
+        // Create an invalid WASM module for testing error handling
+        let wasm_bytes = vec![0x00, 0x61, 0x73, 0x6d];
+
+        let result = WasmTappletHost::from_bytes(config, &wasm_bytes);
+        // This should fail because it's not a complete valid WASM module
+        assert!(result.is_err());
+        if let Err(e) = result {
+            // Verify we get a proper error message
+            assert!(!e.to_string().is_empty());
+        }
+    }
+
+    /// A module following the `tari-tapplet-guest` calling convention, written by hand
+    const JSON_ABI_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"ok\":")
+          (data (i32.const 16) "\0e\00\00\00{\"err\":\"boom\"}")
+          (data (i32.const 48) "\00\00\01\00")
+          (global $next (mut i32) (i32.const 1024))
+          (func $alloc (export "tapplet_alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "tapplet_dealloc") (param i32 i32))
+          ;; Returns its arguments as the result
+          (func (export "echo") (param $ptr i32) (param $len i32) (result i32)
+            (local $out i32)
+            (local.set $out (call $alloc (i32.add (local.get $len) (i32.const 11))))
+            (i32.store (local.get $out) (i32.add (local.get $len) (i32.const 7)))
+            (memory.copy (i32.add (local.get $out) (i32.const 4)) (i32.const 0) (i32.const 6))
+            (memory.copy (i32.add (local.get $out) (i32.const 10)) (local.get $ptr) (local.get $len))
+            (i32.store8
+              (i32.add (i32.add (local.get $out) (i32.const 10)) (local.get $len))
+              (i32.const 125))
+            (local.get $out))
+          (func (export "fail") (param i32 i32) (result i32)
+            (i32.const 16))
+          ;; Claims a response longer than its memory
+          (func (export "overflow") (param i32 i32) (result i32)
+            (i32.const 48)))
+    "#;
+
+    /// `count` streams 1 and 2 through `tapplet_next_chunk`
+    const STREAM_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 100) "\0b\00\00\00{\"ok\":null}")
+          (data (i32.const 200) "\08\00\00\00{\"ok\":1}")
+          (data (i32.const 216) "\08\00\00\00{\"ok\":2}")
+          (data (i32.const 232) "\0d\00\00\00{\"done\":true}")
+          (global $pulled (mut i32) (i32.const 0))
+          (func (export "tapplet_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "tapplet_dealloc") (param i32 i32))
+          (func (export "count") (param i32 i32) (result i32)
+            (global.set $pulled (i32.const 0))
+            (i32.const 100))
+          (func (export "tapplet_next_chunk") (result i32)
+            (local $chunk i32)
+            (local.set $chunk (global.get $pulled))
+            (global.set $pulled (i32.add (global.get $pulled) (i32.const 1)))
+            (if (i32.gt_u (local.get $chunk) (i32.const 2))
+              (then (local.set $chunk (i32.const 2))))
+            (i32.add (i32.const 200) (i32.mul (local.get $chunk) (i32.const 16)))))
+    "#;
+
+    /// `greet` returns whether `tapplet_init` ran, and the module is only
+    /// healthy once it has
+    const INIT_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 100) "\0b\00\00\00{\"ok\":null}")
+          (data (i32.const 300) "\0e\00\00\00{\"err\":\"boom\"}")
+          (data (i32.const 200) "\08\00\00\00{\"ok\":0}")
+          (data (i32.const 216) "\08\00\00\00{\"ok\":1}")
+          (global $ready (mut i32) (i32.const 0))
+          (func (export "tapplet_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "tapplet_dealloc") (param i32 i32))
+          (func (export "tapplet_init") (result i32)
+            (global.set $ready (i32.const 1))
+            (i32.const 100))
+          (func (export "greet") (param i32 i32) (result i32)
+            (i32.add (i32.const 200) (i32.mul (global.get $ready) (i32.const 16))))
+          (func (export "tapplet_health") (result i32)
+            (i32.sub (i32.const 300) (i32.mul (global.get $ready) (i32.const 200)))))
+    "#;
+
+    const LOG_WAT: &str = r#"
+        (module
+          (import "minotari" "minotari_log" (func $log (param i32 i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 100) "\08\00\00\00{\"ok\":0}")
+          (data (i32.const 200) "probe")
+          (data (i32.const 300) "low fee")
+          (func (export "tapplet_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "tapplet_dealloc") (param i32 i32))
+          (func (export "greet") (param i32 i32) (result i32)
+            (call $log (i32.const 0) (i32.const 200) (i32.const 5))
+            (call $log (i32.const 2) (i32.const 300) (i32.const 7))
+            (i32.const 100)))
+    "#;
+
+    const AMBIENT_WAT: &str = r#"
+        (module
+          (import "minotari" "minotari_now" (func $now (result i64)))
+          (import "minotari" "minotari_random_bytes" (func $random_bytes (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 100) "\08\00\00\00{\"ok\":0}")
+          (func (export "tapplet_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "tapplet_dealloc") (param i32 i32))
+          (func (export "greet") (param i32 i32) (result i32)
+            (i64.store (i32.const 500) (call $now))
+            (call $random_bytes (i32.const 508) (i32.const 12))
+            (i32.const 100)))
+    "#;
+
+    const NETWORK_WAT: &str = r#"
+        (module
+          (import "minotari" "minotari_http_get" (func $http_get (param i32 i32) (result i32)))
+          (import "minotari" "minotari_take_result" (func $take_result (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 100) "\08\00\00\00{\"ok\":0}")
+          (data (i32.const 200) "https://api.example.com/price")
+          (func (export "tapplet_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "tapplet_dealloc") (param i32 i32))
+          (func (export "greet") (param i32 i32) (result i32)
+            (i32.store (i32.const 596) (call $http_get (i32.const 200) (i32.const 29)))
+            (call $take_result (i32.const 600) (i32.load (i32.const 596)))
+            (i32.const 100)))
+    "#;
+
+    /// Answers every request with its method and URL
+    struct EchoClient;
+
+    impl HttpClient for EchoClient {
+        fn send(&self, request: &HttpRequest, _: &HttpLimits) -> Result<HttpResponse, String> {
+            Ok(HttpResponse {
+                status: 200,
+                body: format!("{} {}", request.method.as_str(), request.url),
+            })
+        }
+    }
+
+    fn network_manifest(methods: &str) -> TappletManifest {
+        let toml = crate::test_utils::manifest_toml("ticker", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, methods)
+            + "[permissions.network]\nallowed_hosts = [\"api.example.com\"]\n";
+        TappletManifest::from_toml_str(&toml).unwrap()
+    }
+
+    const CHAIN_WAT: &str = r#"
+        (module
+          (import "minotari" "minotari_get_block_header" (func $get_block_header (param i64) (result i32)))
+          (import "minotari" "minotari_take_result" (func $take_result (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 100) "\08\00\00\00{\"ok\":0}")
+          (func (export "tapplet_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "tapplet_dealloc") (param i32 i32))
+          (func (export "greet") (param i32 i32) (result i32)
+            (i32.store (i32.const 596) (call $get_block_header (i64.const 7)))
+            (call $take_result (i32.const 600) (i32.load (i32.const 596)))
+            (i32.const 100)))
+    "#;
+
+    const WALLET_WAT: &str = r#"
+        (module
+          (import "minotari" "minotari_send_transaction" (func $send_transaction (param i32 i32 i64 i64) (result i32)))
+          (import "minotari" "minotari_take_result" (func $take_result (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 100) "\08\00\00\00{\"ok\":0}")
+          (data (i32.const 200) "address")
+          (func (export "tapplet_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "tapplet_dealloc") (param i32 i32))
+          (func (export "greet") (param i32 i32) (result i32)
+            (i32.store (i32.const 596)
+              (call $send_transaction (i32.const 200) (i32.const 7) (i64.const 60) (i64.const 1)))
+            (call $take_result (i32.const 600) (i32.load (i32.const 596)))
+            (i32.const 100)))
+    "#;
+
+    /// A chain whose tip is at height 7
+    struct ShortChain;
+
+    #[async_trait]
+    impl MinotariChainApi for ShortChain {
+        async fn get_tip_height(&self) -> Result<u64, anyhow::Error> {
+            Ok(7)
+        }
+
+        async fn get_block_header(
+            &self,
+            height: u64,
+        ) -> Result<Option<BlockHeader>, anyhow::Error> {
+            Ok((height <= 7).then(|| BlockHeader {
+                height,
+                hash: format!("{:064x}", height),
+                prev_hash: format!("{:064x}", height.saturating_sub(1)),
+                timestamp: 1_700_000_000,
+                output_mr: "00".repeat(32),
+                kernel_mr: "00".repeat(32),
+            }))
+        }
+
+        async fn get_kernel(&self, _: &str) -> Result<Option<KernelInfo>, anyhow::Error> {
+            Ok(None)
+        }
+    }
+
+    fn chain_manifest(methods: &str) -> TappletManifest {
+        let toml = crate::test_utils::manifest_toml("explorer", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, methods)
+            + "[permissions.chain]\nread = true\n";
+        TappletManifest::from_toml_str(&toml).unwrap()
+    }
+
+    const PANIC_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 100) "\2e\00\00\00{\"ok\":\"panicked at src/lib.rs:3:5:\\nno funds\"}")
+          (func (export "tapplet_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "tapplet_dealloc") (param i32 i32))
+          (func $validate_amount unreachable)
+          (func (export "transfer") (param i32 i32) (result i32)
+            (call $validate_amount)
+            (i32.const 0))
+          (func (export "tapplet_last_panic") (result i32) (i32.const 100)))
+    "#;
+
+    #[test]
+    fn test_wasm_panic_message() {
+        let toml = crate::test_utils::manifest_toml("wallet", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["transfer"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let mut host = WasmTappletHost::from_bytes(config, PANIC_WAT.as_bytes()).unwrap();
+        let err = host
+            .run("transfer", Value::Null, &CallContext::user())
+            .unwrap_err();
+        let HostError::ExecutionError(detail) = err else {
+            panic!("{:?}", err);
+        };
+        assert!(
+            detail.starts_with("tapplet panicked at src/lib.rs:3:5:\nno funds\n"),
+            "{}",
+            detail
+        );
+        assert!(detail.contains("0: validate_amount"), "{}", detail);
+    }
+
+    #[test]
+    fn test_wasm_stream() {
+        let toml = crate::test_utils::manifest_toml("counter", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["count"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let mut host = WasmTappletHost::from_bytes(config, STREAM_WAT.as_bytes()).unwrap();
+        let chunks: Vec<_> = host
+            .run_stream("count", Value::Null, &CallContext::user())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(chunks, [1, 2]);
+
+        // Modules that can't stream hand over the whole result as one chunk
+        let toml = crate::test_utils::manifest_toml("echo", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["echo"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let mut host = WasmTappletHost::from_bytes(config, JSON_ABI_WAT.as_bytes()).unwrap();
+        let args = serde_json::json!([1, 2]);
+        let chunks: Vec<_> = host
+            .run_stream("echo", args.clone(), &CallContext::user())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(chunks, [args]);
+    }
+
+    #[test]
+    fn test_validate_against_manifest() {
+        let toml = crate::test_utils::manifest_toml("wallet", "0.1.0").replace(
+            r#"methods = ["greet"]"#,
+            r#"methods = ["greet", "transfer", "balance"]"#,
+        );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let wat = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "tapplet_alloc") (param i32) (result i32) (i32.const 0))
+              (func (export "greet") (param i32 i32) (result i32) (i32.const 0))
+              (func (export "transfer") (param i64) (result i32) (i32.const 0))
+              (func (export "debug_dump")))
+        "#;
+        let mut host = WasmTappletHost::from_wat(config, wat).unwrap();
+
+        let exports = host.list_exports();
+        let signatures: Vec<_> = exports.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            signatures,
+            [
+                "tapplet_alloc(i32) -> (i32)",
+                "greet(i32, i32) -> (i32)",
+                "transfer(i64) -> (i32)",
+                "debug_dump() -> ()",
+            ]
+        );
+
+        let report = host.validate_against_manifest();
+        assert!(!report.is_ok());
+        assert_eq!(report.missing, ["balance"]);
+        assert_eq!(report.unlisted, ["debug_dump"]);
+        assert_eq!(report.mismatched.len(), 1);
+        assert_eq!(report.mismatched[0].0, "transfer");
+    }
+
+    #[test]
+    fn test_wasm_init() {
+        let toml = crate::test_utils::manifest_toml("init", "0.1.0");
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("init.wat");
+        std::fs::write(&path, INIT_WAT).unwrap();
+        let mut host = WasmTappletHost::new(config.clone(), &path).unwrap();
+        let user = CallContext::user();
+        assert_eq!(host.run("greet", Value::Null, &user).unwrap(), 0);
+        let err = host.check_health(Duration::from_secs(1)).unwrap_err();
+        assert_eq!(err.to_string(), "Execution error: boom");
+
+        assert!(host.init().unwrap());
+        assert!(host.check_health(Duration::from_secs(1)).unwrap());
+        assert_eq!(host.memory_usage(), 65536);
+        assert_eq!(host.run("greet", Value::Null, &user).unwrap(), 1);
+        // The reloaded module is initialized too
+        host.reload(&path).unwrap();
+        assert_eq!(host.run("greet", Value::Null, &user).unwrap(), 1);
+
+        // The export is optional
+        let mut host = WasmTappletHost::from_wat(config, STREAM_WAT).unwrap();
+        assert!(!host.init().unwrap());
+    }
+
+    #[test]
+    fn test_wasm_batch() {
+        let toml = crate::test_utils::manifest_toml("logger", "0.1.0");
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let sink = Arc::new(crate::log_sink::MemoryLogSink::new());
+        let mut host = WasmTappletHost::from_wat(config, LOG_WAT)
+            .unwrap()
+            .with_log_sink(sink.clone());
+        let call = |method: &str| (method.to_string(), Value::Null);
+        let results = host.run_batch(
+            vec![call("greet"), call("missing"), call("greet")],
+            &CallContext::user(),
+        );
+        assert_eq!(results[0].as_ref().unwrap(), 0);
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap(), 0);
+        // Both calls logged through the same imports
+        assert_eq!(sink.records().len(), 2);
+    }
+
+    #[test]
+    fn test_wasm_log() {
+        let toml = crate::test_utils::manifest_toml("logger", "0.1.0");
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let sink = Arc::new(crate::log_sink::MemoryLogSink::new());
+        let mut host = WasmTappletHost::from_wat(config.clone(), LOG_WAT)
+            .unwrap()
+            .with_log_sink(sink.clone());
+        host.run("greet", Value::Null, &CallContext::user())
+            .unwrap();
+        // Debug output is dropped by default
+        let records = sink.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].tapplet, "logger");
+        assert_eq!(records[0].level, LogLevel::Warn);
+        assert_eq!(records[0].message, "low fee");
+
+        sink.clear();
+        let options = HostOptions::unlimited()
+            .with_log_sink(sink.clone())
+            .with_log_level(LogLevel::Error)
+            .with_tapplet_log_level("logger", LogLevel::Debug);
+        let mut host = WasmTappletHost::from_wat(config.clone(), LOG_WAT)
+            .unwrap()
+            .apply_options(&options)
+            .unwrap();
+        host.run("greet", Value::Null, &CallContext::user())
+            .unwrap();
+        let messages: Vec<_> = sink.records().into_iter().map(|r| r.message).collect();
+        assert_eq!(messages, ["probe", "low fee"]);
+
+        // Long messages are truncated, lengths beyond the memory refused unread
+        sink.clear();
+        let long = LOG_WAT.replace(
+            "(i32.const 300) (i32.const 7)",
+            "(i32.const 0) (i32.const 65536)",
+        );
+        let mut host = WasmTappletHost::from_wat(config.clone(), &long)
+            .unwrap()
+            .apply_options(&options)
+            .unwrap();
+        host.run("greet", Value::Null, &CallContext::user())
+            .unwrap();
+        assert_eq!(sink.records()[1].message.len(), MAX_LOG_MESSAGE_BYTES);
+        let huge = LOG_WAT.replace(
+            "(i32.const 300) (i32.const 7)",
+            "(i32.const 0) (i32.const -1)",
+        );
+        let mut host = WasmTappletHost::from_wat(config.clone(), &huge).unwrap();
+        let err = host
+            .run("greet", Value::Null, &CallContext::user())
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("outside of the module's memory"),
+            "{}",
+            err
+        );
+
+        // Modules can't import host functions that don't exist, or with another signature
+        let unknown = LOG_WAT.replace(r#""minotari_log""#, r#""minotari_exec""#);
+        let err = WasmTappletHost::from_wat(config.clone(), &unknown)
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("unknown host function minotari.minotari_exec")
+        );
+        let mismatched = r#"(module (import "minotari" "minotari_log" (func (param i32 i32))))"#;
+        let err = WasmTappletHost::from_wat(config, mismatched).err().unwrap();
+        assert!(
+            err.to_string().contains("minotari_log(i32, i32) -> ()"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_wasm_ambient() {
+        let toml = crate::test_utils::manifest_toml("dice", "0.1.0");
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let mode = AmbientMode::Deterministic {
+            seed: 3,
+            now_ms: 1_700_000_000_000,
+        };
+        let roll = |host: &mut WasmTappletHost| {
+            host.run("greet", Value::Null, &CallContext::user())
+                .unwrap();
+            let mut memory = [0; 20];
+            host.instance.read_memory(500, &mut memory).unwrap();
+            let now = u64::from_le_bytes(memory[..8].try_into().unwrap());
+            (now, memory[8..].to_vec())
+        };
+
+        let options = HostOptions::unlimited().with_ambient_mode(mode);
+        let mut host = WasmTappletHost::from_wat(config.clone(), AMBIENT_WAT)
+            .unwrap()
+            .apply_options(&options)
+            .unwrap();
+        let (now, bytes) = roll(&mut host);
+        assert_eq!(now, 1_700_000_000_000);
+        assert_eq!(bytes, Ambient::new(mode).random_bytes(12).unwrap());
+        // The next call gets the next bytes of the sequence
+        assert_ne!(roll(&mut host).1, bytes);
+
+        let mut host = WasmTappletHost::from_wat(config, AMBIENT_WAT).unwrap();
+        let (now, bytes) = roll(&mut host);
+        assert!(now > 1_700_000_000_000);
+        assert_ne!(roll(&mut host).1, bytes);
+    }
+
+    #[test]
+    fn test_wasm_network() {
+        let fetch = |host: &mut WasmTappletHost| {
+            host.run("greet", Value::Null, &CallContext::user())
+                .unwrap();
+            let mut len = [0; 4];
+            host.instance.read_memory(596, &mut len).unwrap();
+            let mut result = vec![0; u32::from_le_bytes(len) as usize];
+            host.instance.read_memory(600, &mut result).unwrap();
+            serde_json::from_slice::<Value>(&result).unwrap()
+        };
+
+        let mut host =
+            WasmTappletHost::from_wat(network_manifest(r#"methods = ["greet"]"#), NETWORK_WAT)
+                .unwrap();
+        assert_eq!(fetch(&mut host)["err"]["code"], "PERMISSION_DENIED");
+
+        let options =
+            HostOptions::unlimited().with_network_access(NetworkAccess::new(Arc::new(EchoClient)));
+        let mut host =
+            WasmTappletHost::from_wat(network_manifest(r#"methods = ["greet"]"#), NETWORK_WAT)
+                .unwrap()
+                .apply_options(&options)
+                .unwrap();
+        assert_eq!(
+            fetch(&mut host),
+            serde_json::json!({
+                "ok": { "status": 200, "body": "GET https://api.example.com/price" }
+            })
+        );
+
+        // Lengths from the module are checked before anything is read
+        let huge = NETWORK_WAT.replace(
+            "(i32.const 200) (i32.const 29)",
+            "(i32.const 200) (i32.const -1)",
+        );
+        let mut host =
+            WasmTappletHost::from_wat(network_manifest(r#"methods = ["greet"]"#), &huge).unwrap();
+        let err = host
+            .run("greet", Value::Null, &CallContext::user())
+            .unwrap_err();
+        assert!(err.to_string().contains("longer than the limit"), "{}", err);
+    }
+
+    #[test]
+    fn test_wasm_chain_api() {
+        let options = HostOptions::unlimited().with_chain_api(Arc::new(ShortChain));
+        let mut host =
+            WasmTappletHost::from_wat(chain_manifest(r#"methods = ["greet"]"#), CHAIN_WAT)
+                .unwrap()
+                .apply_options(&options)
+                .unwrap();
+        host.run("greet", Value::Null, &CallContext::user())
+            .unwrap();
+        let mut len = [0; 4];
+        host.instance.read_memory(596, &mut len).unwrap();
+        let mut result = vec![0; u32::from_le_bytes(len) as usize];
+        host.instance.read_memory(600, &mut result).unwrap();
+        let result: Value = serde_json::from_slice(&result).unwrap();
+        assert_eq!(result["ok"]["height"], 7);
+        assert_eq!(result["ok"]["prev_hash"], format!("{:064x}", 6));
+    }
+
+    #[test]
+    fn test_wasm_wallet_api() {
+        let send = |host: &mut WasmTappletHost, context: &CallContext| {
+            host.run("greet", Value::Null, context).unwrap();
+            let mut len = [0; 4];
+            host.instance.read_memory(596, &mut len).unwrap();
+            let mut result = vec![0; u32::from_le_bytes(len) as usize];
+            host.instance.read_memory(600, &mut result).unwrap();
+            serde_json::from_slice::<Value>(&result).unwrap()
+        };
+        let config =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("payer", "0.1.0"))
+                .unwrap();
+        let allowed = CallContext::user().with_permission(PERMISSION_SEND_TRANSACTION);
+
+        let mut host = WasmTappletHost::from_wat(config.clone(), WALLET_WAT).unwrap();
+        assert_eq!(
+            send(&mut host, &allowed)["err"]["code"],
+            "PERMISSION_DENIED"
+        );
+
+        let api = Arc::new(crate::reference_api::MemoryTappletApi::with_balance(
+            Balance {
+                available: 100,
+                ..Balance::default()
+            },
+        ));
+        let mut host = WasmTappletHost::from_wat(config, WALLET_WAT)
+            .unwrap()
+            .with_wallet_api(api.clone());
+        let result = send(&mut host, &CallContext::user());
+        assert_eq!(result["err"]["code"], "PERMISSION_DENIED");
+        assert_eq!(api.state().balance.available, 100);
+        assert!(send(&mut host, &allowed)["ok"].is_string());
+        assert_eq!(api.state().balance.available, 39);
+    }
+
+    #[test]
+    fn test_wasm_from_wat() {
+        let toml = crate::test_utils::manifest_toml("echo", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["echo"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let args = serde_json::json!({"name": "Alice"});
+        let mut host = WasmTappletHost::from_wat(config.clone(), JSON_ABI_WAT).unwrap();
+        assert_eq!(
+            host.run("echo", args.clone(), &CallContext::user())
+                .unwrap(),
+            args
+        );
+
+        // `.wat` files load like binary modules
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("echo.wat");
+        std::fs::write(&path, JSON_ABI_WAT).unwrap();
+        let mut host = WasmTappletHost::new(config.clone(), &path).unwrap();
+        assert_eq!(
+            host.run("echo", args.clone(), &CallContext::user())
+                .unwrap(),
+            args
+        );
+
+        // Syntax errors point at the line and column
+        let err =
+            WasmTappletHost::from_wat(config, "(module\n  (func (export \"echo\") (i32.bogus)))")
+                .err()
+                .unwrap();
+        assert_eq!(err.code(), "WASM_COMPILE_ERROR");
+        assert!(err.to_string().contains(":2:"), "{}", err);
+    }
+
+    #[test]
+    fn test_json_calling_convention() {
+        let toml = crate::test_utils::manifest_toml("echo", "0.1.0").replace(
+            r#"methods = ["greet"]"#,
+            r#"methods = ["echo", "fail", "overflow"]"#,
+        );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let mut host = WasmTappletHost::from_bytes(config, JSON_ABI_WAT.as_bytes()).unwrap();
+
+        let args = serde_json::json!({"name": "Alice", "tags": ["a", "b"]});
+        let result = host
+            .run("echo", args.clone(), &CallContext::user())
+            .unwrap();
+        assert_eq!(result, args);
+
+        let err = host
+            .run("fail", Value::Null, &CallContext::user())
+            .unwrap_err();
+        assert!(matches!(err, HostError::ExecutionError(ref message) if message == "boom"));
+
+        let err = host
+            .run("overflow", Value::Null, &CallContext::user())
+            .unwrap_err();
+        assert!(err.to_string().contains("outside of its memory"), "{}", err);
+    }
+
+    #[test]
+    fn test_wasm_reload() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("echo.wasm");
+        std::fs::write(
+            &path,
+            r#"(module (func (export "echo") (result i32) (i32.const 1)))"#,
+        )
+        .unwrap();
+        let toml = crate::test_utils::manifest_toml("echo", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["echo"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let mut host = WasmTappletHost::new(config, &path).unwrap();
+        assert_eq!(
+            host.run("echo", serde_json::json!([]), &CallContext::user())
+                .unwrap(),
+            1
+        );
+
+        std::fs::write(&path, JSON_ABI_WAT).unwrap();
+        host.reload(&path).unwrap();
+        let args = serde_json::json!({"reloaded": true});
+        assert_eq!(
+            host.run("echo", args.clone(), &CallContext::user())
+                .unwrap(),
+            args
+        );
+    }
+
+    #[test]
+    fn test_wasm_run_with_cancel() {
+        let toml = crate::test_utils::manifest_toml("looper", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["spin", "one"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let wat = r#"(module
+            (func (export "spin") (loop (br 0)))
+            (func (export "one") (result i32) (i32.const 1)))"#;
+        let mut host = WasmTappletHost::from_bytes(config, wat.as_bytes()).unwrap();
+        let context = CallContext::user();
+
+        let token = CancellationToken::new();
+        let result = host.run_with_cancel("one", serde_json::json!([]), &context, &token);
+        assert_eq!(result.unwrap(), 1);
+
+        let canceller = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            canceller.cancel();
+        });
+        let err = host
+            .run_with_cancel("spin", serde_json::json!([]), &context, &token)
+            .unwrap_err();
+        assert!(matches!(err, HostError::Cancelled(ref method) if method == "spin"));
+
+        token.cancel();
+        let err = host
+            .run_with_cancel("one", serde_json::json!([]), &context, &token)
+            .unwrap_err();
+        assert_eq!(err.code(), "CANCELLED");
+        let result = host.run("one", serde_json::json!([]), &context);
+        assert_eq!(result.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_wasm_method_timeout() {
+        let toml = crate::test_utils::manifest_toml("looper", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["slow", "one"]"#)
+            + "\n[api.slow]\ndescription = \"\"\ntimeout_ms = 1\n\n\
+               [api.slow.returns]\ntype = \"any\"\ndescription = \"\"\n";
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let wat = r#"(module
+            (func (export "slow") (loop (br 0)))
+            (func (export "one") (result i32) (i32.const 1)))"#;
+        let mut host = WasmTappletHost::from_bytes(config, wat.as_bytes())
+            .unwrap()
+            .with_timeout(Duration::from_secs(60));
+        let context = CallContext::user();
+
+        let err = host
+            .run("slow", serde_json::json!([]), &context)
+            .unwrap_err();
+        assert!(matches!(err, HostError::Timeout(ref method) if method == "slow"));
+        let result = host.run("one", serde_json::json!([]), &context);
+        assert_eq!(result.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_wasm_limits() {
+        let method = || MethodDefinition::new("", ParamType::Any, "");
+        let config = TappletManifest::builder("limits", "0.1.0")
+            .with_method("spin", method())
+            .with_method("count", method())
+            .with_method("hog", method().with_max_memory(1024 * 1024))
+            .with_method("grow", method())
+            .build_unchecked();
+        let wat = r#"(module
+            (memory (export "memory") 1)
+            (func (export "spin") (loop (br 0)))
+            (func (export "count") (result i32) (local $i i32) (local $n i32)
+              (loop
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (local.set $n (i32.add (local.get $n) (local.get $i)))
+                (br_if 0 (i32.lt_u (local.get $i) (i32.const 10))))
+              (local.get $n))
+            (func (export "hog") (result i32)
+              (if (i32.eq (memory.grow (i32.const 100)) (i32.const -1)) (then unreachable))
+              (i32.const 1))
+            (func (export "grow") (result i32) (memory.grow (i32.const 100))))"#;
+        let mut host = WasmTappletHost::from_bytes(config, wat.as_bytes())
+            .unwrap()
+            .with_execution_budget(10_000);
+        let context = CallContext::user();
+
+        let err = host
+            .run("spin", serde_json::json!([]), &context)
+            .unwrap_err();
+        assert_eq!(err.code(), "EXECUTION_BUDGET_EXCEEDED");
+        // The budget is reset for each call
+        assert_eq!(
+            host.run("count", serde_json::json!([]), &context).unwrap(),
+            55
+        );
+        let err = host
+            .run("hog", serde_json::json!([]), &context)
+            .unwrap_err();
+        assert_eq!(err.code(), "MEMORY_LIMIT_EXCEEDED");
+        // Only the method's own limit kept the memory from growing
+        assert_eq!(
+            host.run("grow", serde_json::json!([]), &context).unwrap(),
+            1
+        );
+        assert_eq!(host.memory_usage(), 101 * 64 * 1024);
+    }
+
+    #[test]
+    fn test_wasm_stack_limit() {
+        let config = TappletManifest::builder("recursion", "0.1.0")
+            .with_method("deep", MethodDefinition::new("", ParamType::Any, ""))
+            .with_method("shallow", MethodDefinition::new("", ParamType::Any, ""))
+            .build_unchecked();
+        let wat = r#"(module
+            (func $depth (param i32) (result i32)
+              (if (result i32) (i32.eqz (local.get 0))
+                (then (i32.const 0))
+                (else (i32.add (i32.const 1)
+                  (call $depth (i32.sub (local.get 0) (i32.const 1)))))))
+            (func (export "deep") (result i32) (call $depth (i32.const 100)))
+            (func (export "shallow") (result i32) (call $depth (i32.const 10))))"#;
+        let host = WasmTappletHost::from_bytes(config, wat.as_bytes()).unwrap();
+        if !engine::default_engine().limits_stack() {
+            let err = host.with_stack_limit(50).err().unwrap();
+            assert_eq!(err.code(), "UNSUPPORTED");
+            return;
+        }
+        let mut host = host.with_stack_limit(50).unwrap();
+        let context = CallContext::user();
+
+        let err = host
+            .run("deep", serde_json::json!([]), &context)
+            .unwrap_err();
+        assert!(err.to_string().contains("stack limit exceeded"), "{}", err);
+        // Frames left behind by the trap don't count against the next call
+        assert_eq!(
+            host.run("shallow", serde_json::json!([]), &context)
+                .unwrap(),
+            10
+        );
+    }
+
+    #[test]
+    fn test_wasm_governor() {
+        let governor = ResourceGovernor::new(
+            crate::governor::ResourceBudgets::default().with_total_memory(1024 * 1024),
+        );
+        let load = |name: &str, pages: u32| {
+            let config = TappletManifest::builder(name, "0.1.0")
+                .with_method("one", MethodDefinition::new("", ParamType::Any, ""))
+                .build_unchecked();
+            let wat = format!(
+                r#"(module
+                    (memory (export "memory") {pages})
+                    (func (export "one") (result i32) (i32.const 1)))"#
+            );
+            WasmTappletHost::from_bytes(config, wat.as_bytes())
+                .unwrap()
+                .with_governor(&governor)
+        };
+        let mut small = load("small", 1);
+        let mut big = load("big", 16);
+        let context = CallContext::user();
+
+        small.run("one", serde_json::json!([]), &context).unwrap();
+        // Together they hold more than the budget, so the biggest is evicted
+        big.run("one", serde_json::json!([]), &context).unwrap();
+        let err = big.run("one", serde_json::json!([]), &context).unwrap_err();
+        assert_eq!(err.code(), "THROTTLED");
+        let usage = governor.usage();
+        assert!(usage.tapplets["big"].evicted);
+        assert_eq!(usage.tapplets["big"].memory, 16 * 64 * 1024);
+        small.run("one", serde_json::json!([]), &context).unwrap();
+    }
+
+    #[test]
+    fn test_wasm_from_precompiled() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("echo.wasm");
+        std::fs::write(&path, JSON_ABI_WAT).unwrap();
+        let toml = crate::test_utils::manifest_toml("echo", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["echo"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+
+        let engine = engine::default_engine();
+        let module_cache = ModuleCache::in_cache_directory(temp.path());
+        let precompiled =
+            engine::precompile_file(&path, module_cache.directory(), engine.as_ref()).unwrap();
+        assert_eq!(
+            precompiled,
+            module_cache.precompiled_path(&std::fs::read(&path).unwrap())
+        );
+        let mut host =
+            unsafe { WasmTappletHost::from_precompiled(config.clone(), &precompiled) }.unwrap();
+        let args = serde_json::json!({"precompiled": true});
+        assert_eq!(
+            host.run("echo", args.clone(), &CallContext::user())
+                .unwrap(),
+            args
+        );
+
+        let artifact = std::fs::read(&precompiled).unwrap();
+        let key = engine.artifact_key();
+        let position = artifact
+            .windows(key.len())
+            .position(|window| window == key.as_bytes())
+            .unwrap();
+        let mut other_engine = artifact.clone();
+        other_engine[position] ^= 1;
+        std::fs::write(&precompiled, other_engine).unwrap();
+        let err = unsafe { WasmTappletHost::from_precompiled(config.clone(), &precompiled) }
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("precompiled for"), "{}", err);
+
+        std::fs::write(&precompiled, b"garbage").unwrap();
+        assert!(unsafe { WasmTappletHost::from_precompiled(config, &precompiled) }.is_err());
+    }
+
+    struct NoopApi;
+
+    #[async_trait]
+    impl MinotariTappletApiV1 for NoopApi {
+        async fn append_data(&self, _slot: &str, _value: &str) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+
+        async fn load_data_entries(&self, _slot: &str) -> Result<Vec<String>, anyhow::Error> {
+            Ok(Vec::new())
+        }
+
+        async fn add_watched_viewkey(
+            &self,
+            _viewkey: &str,
+            _birthday: u64,
+        ) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_dyn_hosts_in_one_collection() {
+        let api: Arc<dyn MinotariTappletApiV1> = Arc::new(NoopApi);
+        let hosts: Vec<DynLuaTappletHost> = ["a", "b"]
+            .into_iter()
+            .map(|name| {
+                let config = TappletManifest::from_toml_str(&crate::test_utils::manifest_toml(
+                    name, "0.1.0",
+                ))
+                .unwrap();
+                LuaTappletHost::from_string_shared(config, "function greet() end", api.clone())
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(hosts[1].config().name, "b");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_sandbox_denies_files_and_processes() {
+        let toml = crate::test_utils::manifest_toml("sandboxed", "0.1.0").replace(
+            r#"methods = ["greet"]"#,
+            r#"methods = ["read", "spawn", "eval"]"#,
+        );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function read() return io.open("/etc/passwd"):read("*a") end
+            function spawn() return os.execute("echo pwned") end
+            function eval() return loadstring("return 1")() end
+        "#;
+        let host = LuaTappletHost::from_string(config.clone(), code, NoopApi).unwrap();
+        for method in ["read", "spawn", "eval"] {
+            assert!(matches!(
+                host.run(method, Value::Null, &CallContext::user()).await,
+                Err(HostError::LuaExecutionError(_))
+            ));
+        }
+
+        // Globals can't be reassigned to smuggle values between calls either
+        assert!(LuaTappletHost::from_string(config, "string.rep = nil", NoopApi).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_deprecated_method_is_audited() {
+        let toml = crate::test_utils::manifest_toml("greeter", "0.1.0").replace(
+            "[api.greet.returns]",
+            "[api.greet.deprecated]\nsince = \"0.3.0\"\nmessage = \"Too informal\"\n\n[api.greet.returns]",
+        );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let audit = Arc::new(crate::audit::MemoryAuditSink::default());
+        let host =
+            LuaTappletHost::from_string(config, "function greet() return 'hello' end", NoopApi)
+                .unwrap()
+                .with_audit_sink(audit.clone());
+
+        let greeting = host.run("greet", Value::Null, &CallContext::user()).await;
+        assert_eq!(greeting.unwrap(), "hello");
+        assert_eq!(
+            audit.events()[0].warning.as_deref(),
+            Some("greeter.greet is deprecated since 0.3.0: Too informal")
+        );
+    }
+
+    #[test]
+    fn test_hosts_refuse_newer_host_api() {
+        let mut config =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("future", "0.1.0"))
+                .unwrap();
+        config.requires_host_api = Some(format!(">{}", HOST_API_VERSION));
+        let code = "function greet() return 'hello' end";
+        assert!(matches!(
+            LuaTappletHost::from_string(config.clone(), code, NoopApi),
+            Err(HostError::UnsupportedHostApi(_))
+        ));
+        assert!(matches!(
+            WasmTappletHost::from_bytes(config.clone(), b"\0asm"),
+            Err(HostError::UnsupportedHostApi(_))
+        ));
+
+        config.requires_host_api = Some(HOST_API_VERSION.major.to_string());
+        assert!(LuaTappletHost::from_string(config, code, NoopApi).is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_execution_budget() {
+        let toml = crate::test_utils::manifest_toml("looper", "0.1.0").replace(
+            r#"methods = ["greet"]"#,
+            r#"methods = ["spin", "guarded", "count"]"#,
+        );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function spin() while true do end end
+            function guarded() while true do pcall(spin) end end
+            function count() local n = 0 for i = 1, 10 do n = n + i end return n end
+        "#;
+        let host = LuaTappletHost::from_string(config, code, NoopApi)
+            .unwrap()
+            .with_execution_budget(10_000);
+        for method in ["spin", "guarded"] {
+            let err = host
+                .run(method, Value::Null, &CallContext::user())
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), "EXECUTION_BUDGET_EXCEEDED");
+        }
+        // The budget is reset for each call
+        let result = host.run("count", Value::Null, &CallContext::user()).await;
+        assert_eq!(result.unwrap(), serde_json::json!(55));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_method_limits() {
+        let mut toml = crate::test_utils::manifest_toml("limits", "0.1.0").replace(
+            r#"methods = ["greet"]"#,
+            r#"methods = ["count", "report", "spin", "hog"]"#,
+        );
+        for (method, limits) in [
+            ("report", "max_fuel = 1000000"),
+            ("spin", "max_fuel = 1000000000000\ntimeout_ms = 50"),
+            ("hog", "max_fuel = 1000000000000\nmax_memory = 1048576"),
+        ] {
+            toml += &format!(
+                "\n[api.{method}]\ndescription = \"\"\n{limits}\n\n\
+                 [api.{method}.returns]\ntype = \"any\"\ndescription = \"\"\n"
+            );
+        }
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function count() local n = 0 for i = 1, 10000 do n = n + 1 end return n end
+            report = count
+            function spin() while true do end end
+            function hog() local t = {} for i = 1, 10000000 do t[i] = i end end
+        "#;
+        let host = LuaTappletHost::from_string(config, code, NoopApi)
+            .unwrap()
+            .with_execution_budget(1_000);
+        let context = CallContext::user();
+
+        // A method's own limits replace the host's
+        let err = host.run("count", Value::Null, &context).await.unwrap_err();
+        assert_eq!(err.code(), "EXECUTION_BUDGET_EXCEEDED");
+        let result = host.run("report", Value::Null, &context).await;
+        assert_eq!(result.unwrap(), serde_json::json!(10000));
+        let err = host.run("spin", Value::Null, &context).await.unwrap_err();
+        assert_eq!(err.code(), "TIMEOUT");
+        let err = host.run("hog", Value::Null, &context).await.unwrap_err();
+        assert_eq!(err.code(), "MEMORY_LIMIT_EXCEEDED");
+        // The memory limit only applied to that call
+        let result = host.run("report", Value::Null, &context).await;
+        assert_eq!(result.unwrap(), serde_json::json!(10000));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_run_with_cancel() {
+        let toml = crate::test_utils::manifest_toml("looper", "0.1.0").replace(
+            r#"methods = ["greet"]"#,
+            r#"methods = ["guarded", "count"]"#,
+        );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function guarded() while true do pcall(function() while true do end end) end end
+            function count() local n = 0 for i = 1, 10 do n = n + i end return n end
+        "#;
+        let host = LuaTappletHost::from_string(config, code, NoopApi).unwrap();
+        let context = CallContext::user();
+
+        let token = CancellationToken::new();
+        let result = host
+            .run_with_cancel("count", Value::Null, &context, &token)
+            .await;
+        assert_eq!(result.unwrap(), serde_json::json!(55));
+
+        let canceller = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            canceller.cancel();
+        });
+        let err = host
+            .run_with_cancel("guarded", Value::Null, &context, &token)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, HostError::Cancelled(ref method) if method == "guarded"));
+
+        // A cancelled token fails calls straight away, and doesn't affect later runs
+        let err = host
+            .run_with_cancel("count", Value::Null, &context, &token)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "CANCELLED");
+        let result = host.run("count", Value::Null, &context).await;
+        assert_eq!(result.unwrap(), serde_json::json!(55));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_metrics() {
+        use crate::metrics::{self, MemoryMetricsSink};
+
+        let toml = crate::test_utils::manifest_toml("looper", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["spin", "count"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function spin() while true do end end
+            function count() local n = 0 for i = 1, 10 do n = n + i end return n end
+        "#;
+        let sink = Arc::new(MemoryMetricsSink::new());
+        let host = LuaTappletHost::from_string(config, code, NoopApi)
+            .unwrap()
+            .with_execution_budget(10_000)
+            .with_metrics_sink(sink.clone());
+        let ctx = CallContext::user();
+        host.run("count", Value::Null, &ctx).await.unwrap();
+        host.run("spin", Value::Null, &ctx).await.unwrap_err();
+        host.run("missing", Value::Null, &ctx).await.unwrap_err();
+
+        let count = [("tapplet", "looper"), ("method", "count")];
+        let spin = [("tapplet", "looper"), ("method", "spin")];
+        assert_eq!(sink.counter(metrics::CALLS_TOTAL, &count), 1);
+        assert_eq!(
+            sink.histogram(metrics::CALL_DURATION_SECONDS, &count).count,
+            1
+        );
+        let spin_fuel = sink.histogram(metrics::CALL_FUEL_USED, &spin);
+        assert_eq!(spin_fuel.sum, 10_000.0);
+        assert!(sink.histogram(metrics::CALL_FUEL_USED, &count).sum < 10_000.0);
+        let errors = [
+            ("tapplet", "looper"),
+            ("method", "spin"),
+            ("code", "EXECUTION_BUDGET_EXCEEDED"),
+        ];
+        assert_eq!(sink.counter(metrics::CALL_ERRORS_TOTAL, &errors), 1);
+        let errors = [
+            ("tapplet", "looper"),
+            ("method", "missing"),
+            ("code", "METHOD_NOT_FOUND"),
+        ];
+        assert_eq!(sink.counter(metrics::CALL_ERRORS_TOTAL, &errors), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_reload() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(temp.path().join("util.lua"), "return { name = 'one' }").unwrap();
+        let path = temp.path().join("reloaded.lua");
+        std::fs::write(
+            &path,
+            "function greet() print('v1') return require('util').name end\nfunction old() end",
+        )
+        .unwrap();
+        let config =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("reloaded", "0.1.0"))
+                .unwrap();
+        let sink = Arc::new(crate::log_sink::MemoryLogSink::new());
+        let mut host = LuaTappletHost::new(config, &path, NoopApi)
+            .unwrap()
+            .with_log_sink(sink.clone())
+            .with_execution_budget(10_000);
+        let greet = async |host: &LuaTappletHost<NoopApi>| {
+            host.run("greet", Value::Null, &CallContext::user()).await
+        };
+        assert_eq!(greet(&host).await.unwrap(), "one");
+
+        std::fs::write(temp.path().join("util.lua"), "return { name = 'two' }").unwrap();
+        std::fs::write(&path, "function greet() print('v2') while true do end end").unwrap();
+        host.reload(&path).unwrap();
+        // New code, modules required again, and the sink and budget kept
+        let err = greet(&host).await.unwrap_err();
+        assert_eq!(err.code(), "EXECUTION_BUDGET_EXCEEDED");
+        assert!(host.lua.globals().get::<mlua::Function>("old").is_err());
+        let messages: Vec<_> = sink.records().into_iter().map(|r| r.message).collect();
+        assert_eq!(messages[..2], ["v1", "v2"]);
+
+        std::fs::write(&path, "function greet() return require('util').name end").unwrap();
+        host.reload(&path).unwrap();
+        assert_eq!(greet(&host).await.unwrap(), "two");
+
+        // A script that fails to load leaves the old one in place
+        std::fs::write(&path, "function greet(").unwrap();
+        assert!(host.reload(&path).is_err());
+        assert_eq!(greet(&host).await.unwrap(), "two");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_print_and_tracebacks() {
+        let toml = crate::test_utils::manifest_toml("noisy", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["greet", "fail"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = "function greet(name)\n  print('greeting', name)\n  return 'hi'\nend\n\nlocal function inner()\n  error('boom')\nend\n\nfunction fail() inner() end\n";
+        let sink = Arc::new(crate::log_sink::MemoryLogSink::new());
+        let host = LuaTappletHost::from_string(config, code, NoopApi)
+            .unwrap()
+            .with_log_sink(sink.clone());
+
+        host.run("greet", serde_json::json!("Alice"), &CallContext::user())
+            .await
+            .unwrap();
+        let err = host
+            .run("fail", Value::Null, &CallContext::user())
+            .await
+            .unwrap_err();
+        let HostError::LuaExecutionError(details) = err else {
+            panic!("unexpected error: {}", err);
+        };
+        assert_eq!(details.message, "noisy:7: boom");
+        assert_eq!(details.file.as_deref(), Some("noisy"));
+        assert_eq!(details.line, Some(7));
+        assert!(
+            details
+                .traceback
+                .unwrap()
+                .contains("noisy:10: in function 'fail'")
+        );
+
+        let records = sink.records();
+        assert_eq!(records[0].message, "greeting\tAlice");
+        assert_eq!(records[1].level, crate::log_sink::LogLevel::Error);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_ambient() {
+        let toml = crate::test_utils::manifest_toml("dice", "0.1.0");
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = "function greet()\n  return { now = minotari_now(), bytes = { string.byte(minotari_random_bytes(4), 1, 4) } }\nend\n";
+        let user = CallContext::user();
+        let options = HostOptions::unlimited().with_deterministic();
+        let roll = async |host: &LuaTappletHost<NoopApi>| {
+            host.run("greet", Value::Null, &user).await.unwrap()
+        };
+
+        // Deterministic hosts give the same values
+        let first = LuaTappletHost::from_string_with_options(
+            config.clone(),
+            code,
+            Arc::new(NoopApi),
+            &options,
+        )
+        .unwrap();
+        let second = LuaTappletHost::from_string_with_options(
+            config.clone(),
+            code,
+            Arc::new(NoopApi),
+            &options,
+        )
+        .unwrap();
+        let expected = Ambient::new(AmbientMode::deterministic(0))
+            .random_bytes(4)
+            .unwrap();
+        let rolled = serde_json::json!({ "now": 0, "bytes": expected });
+        assert_eq!(roll(&first).await, rolled);
+        assert_eq!(roll(&second).await, rolled);
+
+        let host = LuaTappletHost::from_string(config, code, NoopApi).unwrap();
+        assert!(roll(&host).await["now"].as_u64().unwrap() > 1_700_000_000_000);
+        let err = host
+            .lua
+            .load("minotari_random_bytes(1000000)")
+            .exec()
+            .unwrap_err();
+        assert!(err.to_string().contains("at most 65536"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_network() {
+        let code = r#"
+function greet()
+  local get = minotari_http_get("https://api.example.com/price")
+  local post = minotari_http_post("https://api.example.com/orders", "{}")
+  return { get = get.body, post = post.body, status = post.status }
+end
+function denied()
+  return minotari_http_get("https://example.org/")
+end
+"#;
+        let user = CallContext::user();
+        let config = network_manifest(r#"methods = ["greet", "denied"]"#);
+        let host = LuaTappletHost::from_string(config, code, NoopApi).unwrap();
+        let err = host.run("greet", Value::Null, &user).await.unwrap_err();
+        assert!(
+            err.to_string().contains("network access is not enabled"),
+            "{}",
+            err
+        );
+
+        let host = host.with_network_access(NetworkAccess::new(Arc::new(EchoClient)));
+        assert_eq!(
+            host.run("greet", Value::Null, &user).await.unwrap(),
+            serde_json::json!({
+                "get": "GET https://api.example.com/price",
+                "post": "POST https://api.example.com/orders",
+                "status": 200
+            })
+        );
+        let err = host.run("denied", Value::Null, &user).await.unwrap_err();
+        assert!(err.to_string().contains("allowed_hosts"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_chain_api() {
+        let code = r#"
+function greet()
+  local tip = minotari_get_tip_height()
+  return {
+    tip = tip,
+    hash = minotari_get_block_header(tip).hash,
+    beyond_tip = minotari_get_block_header(tip + 1) == nil,
+    kernel = minotari_get_kernel("abcd") == nil,
+  }
+end
+"#;
+        let user = CallContext::user();
+        let config = chain_manifest(r#"methods = ["greet"]"#);
+        let host = LuaTappletHost::from_string(config, code, NoopApi).unwrap();
+        let err = host.run("greet", Value::Null, &user).await.unwrap_err();
+        assert!(
+            err.to_string().contains("chain queries are not enabled"),
+            "{}",
+            err
+        );
+
+        let host = host.with_chain_api(Arc::new(ShortChain));
+        assert_eq!(
+            host.run("greet", Value::Null, &user).await.unwrap(),
+            serde_json::json!({
+                "tip": 7,
+                "hash": format!("{:064x}", 7),
+                "beyond_tip": true,
+                "kernel": true
+            })
+        );
+
+        // Without the manifest permission the API stays out of reach
+        let toml = crate::test_utils::manifest_toml("explorer", "0.1.0");
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let host = LuaTappletHost::from_string(config, code, NoopApi)
+            .unwrap()
+            .with_chain_api(Arc::new(ShortChain));
+        let err = host.run("greet", Value::Null, &user).await.unwrap_err();
+        assert!(
+            err.to_string().contains("permissions.chain.read"),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_log_levels() {
+        let toml = crate::test_utils::manifest_toml("logger", "0.1.0");
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = "function greet(level)\n  minotari_log('debug', 'probe')\n  minotari_log(level, 'low fee')\n  print('done')\nend\n";
+        let sink = Arc::new(crate::log_sink::MemoryLogSink::new());
+        let host = LuaTappletHost::from_string(config, code, NoopApi)
+            .unwrap()
+            .with_log_sink(sink.clone())
+            .with_log_level(LogLevel::Warn);
+        let user = CallContext::user();
+
+        host.run("greet", serde_json::json!("warn"), &user)
+            .await
+            .unwrap();
+        let records = sink.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].tapplet, "logger");
+        assert_eq!(records[0].level, LogLevel::Warn);
+        assert_eq!(records[0].message, "low fee");
+
+        let err = host
+            .run("greet", serde_json::json!("loud"), &user)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("unknown log level 'loud'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_lua_error_details_for_host_function_errors() {
+        let err = mlua::Error::CallbackError {
+            traceback:
+                "stack traceback:\n\t[C]: in function 'hostf'\n\tmain.lua:5: in function 'b'"
+                    .to_string(),
+            cause: Arc::new(mlua::Error::runtime("host failed")),
+        };
+        let details = LuaErrorDetails::from_lua_error(&err);
+        assert_eq!(details.message, "host failed");
+        assert_eq!(details.file.as_deref(), Some("main.lua"));
+        assert_eq!(details.line, Some(5));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_encrypted_slots() {
+        let toml = crate::test_utils::manifest_toml("vault", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["store", "load"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function store(secret) minotari_append_encrypted_data("secrets", secret) end
+            function load() return minotari_load_encrypted_entries("secrets") end
+        "#;
+        let api = Arc::new(crate::reference_api::MemoryTappletApi::new());
+        let host = LuaTappletHost::from_string_shared(config, code, api.clone())
+            .unwrap()
+            .with_storage_key(StorageKey::derive(b"wallet secret", "vault"));
+
+        host.run("store", serde_json::json!("hunter2"), &CallContext::user())
+            .await
+            .unwrap();
+        let stored = api.state().slots["test_publisher/vault/secrets"].clone();
+        assert!(!stored[0].contains("hunter2"));
+        let loaded = host.run("load", Value::Null, &CallContext::user()).await;
+        assert_eq!(loaded.unwrap(), serde_json::json!(["hunter2"]));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_storage_quota() {
+        let toml = crate::test_utils::manifest_toml("notes", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["add", "try_add"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function add(note) minotari_append_data("notes", note) end
+            function try_add(note) return pcall(minotari_append_data, "notes", note) end
+        "#;
+        let host = LuaTappletHost::from_string(
+            config,
+            code,
+            crate::reference_api::MemoryTappletApi::new(),
+        )
+        .unwrap()
+        .with_storage_quota(StorageQuota::unlimited().with_max_entries(1));
+
+        let context = CallContext::user();
+        host.run("add", serde_json::json!("first"), &context)
+            .await
+            .unwrap();
+        // The guest can handle the error itself...
+        let handled = host
+            .run("try_add", serde_json::json!("second"), &context)
+            .await;
+        assert_eq!(handled.unwrap(), serde_json::json!(false));
+        // ...or let it fail the call
+        let err = host
+            .run("add", serde_json::json!("second"), &context)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "STORAGE_QUOTA_EXCEEDED");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_stream() {
+        use tokio_stream::StreamExt;
+
+        let toml = crate::test_utils::manifest_toml("vault", "0.1.0").replace(
+            r#"methods = ["greet"]"#,
+            r#"methods = ["entries", "total", "broken"]"#,
+        );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function entries(args)
+                for i = 1, args.n do coroutine.yield({ id = i }) end
+            end
+            function total() return 3 end
+            function broken() coroutine.yield(1) error("nope") end
+        "#;
+        let mut host = LuaTappletHost::from_string(config, code, NoopApi).unwrap();
+        let context = CallContext::user();
+
+        let chunks: Vec<_> = host
+            .run_stream("entries", serde_json::json!({"n": 3}), &context)
+            .collect::<Result<_, _>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            chunks,
+            [
+                serde_json::json!({"id": 1}),
+                serde_json::json!({"id": 2}),
+                serde_json::json!({"id": 3})
+            ]
+        );
+
+        // A method that doesn't yield streams its result as one chunk
+        let chunks: Vec<_> = host
+            .run_stream("total", Value::Null, &context)
+            .collect::<Result<_, _>>()
+            .await
+            .unwrap();
+        assert_eq!(chunks, [3]);
+
+        // Chunks before a failure are still handed over, then the stream ends
+        let mut stream = host.run_stream("broken", Value::Null, &context);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(err.code(), "LUA_EXECUTION_ERROR");
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_batch() {
+        let toml = crate::test_utils::manifest_toml("notes", "0.1.0").replace(
+            r#"methods = ["greet"]"#,
+            r#"methods = ["add", "count", "fail"]"#,
+        );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function add(note) minotari_append_data("notes", note) end
+            function count() return #minotari_load_data_entries("notes") end
+            function fail() error("nope") end
+        "#;
+        let api = Arc::new(crate::reference_api::MemoryTappletApi::new());
+        let mut host = LuaTappletHost::from_string_shared(config, code, api.clone()).unwrap();
+        let context = CallContext::user();
+        let call = |method: &str, args: Value| (method.to_string(), args);
+
+        let results = host
+            .run_batch(
+                vec![
+                    call("add", serde_json::json!("one")),
+                    call("fail", Value::Null),
+                    call("count", Value::Null),
+                ],
+                &context,
+            )
+            .await;
+        assert_eq!(results[0].as_ref().unwrap(), &Value::Null);
+        assert_eq!(
+            results[1].as_ref().unwrap_err().code(),
+            "LUA_EXECUTION_ERROR"
+        );
+        assert_eq!(results[2].as_ref().unwrap(), 1);
+
+        // Atomic batches see their own appends, but only make them if every
+        // call succeeds
+        let atomic = |last: &str| {
+            vec![
+                call("add", serde_json::json!("two")),
+                call("count", Value::Null),
+                call(last, Value::Null),
+            ]
+        };
+        let results = host.run_batch_atomic(atomic("fail"), &context).await;
+        let codes: Vec<_> = results
+            .iter()
+            .map(|result| result.as_ref().unwrap_err().code())
+            .collect();
+        assert_eq!(
+            codes,
+            ["BATCH_ABORTED", "BATCH_ABORTED", "LUA_EXECUTION_ERROR"]
+        );
+        assert_eq!(api.state().slots["test_publisher/notes/notes"], ["one"]);
+
+        let results = host.run_batch_atomic(atomic("count"), &context).await;
+        assert_eq!(results[1].as_ref().unwrap(), 2);
+        assert_eq!(results[2].as_ref().unwrap(), 2);
+        assert_eq!(
+            api.state().slots["test_publisher/notes/notes"],
+            ["one", "two"]
+        );
+
+        // Calls outside a batch write straight away again
+        host.run("add", serde_json::json!("three"), &context)
+            .await
+            .unwrap();
+        assert_eq!(api.state().slots["test_publisher/notes/notes"].len(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_arguments_checked_against_param_types() {
+        let toml = crate::test_utils::manifest_toml("typed", "0.1.0").replace(
+            "[api.greet.returns]",
+            "[api.greet.params]\n\
+             name = { type = \"string\", description = \"Who to greet\" }\n\
+             times = { type = \"optional<u64>\", description = \"Repeats\" }\n\
+             suffix = { type = \"string\", description = \"Appended\", default = \"!\" }\n\n\
+             [api.greet.returns]",
+        );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code =
+            "function greet(args) return string.rep(args.name, args.times or 1) .. args.suffix end";
+        let host = LuaTappletHost::from_string(config, code, NoopApi).unwrap();
+        let context = CallContext::user();
+
+        // Integers given as strings are converted before the call
+        let result = host
+            .run(
+                "greet",
+                serde_json::json!({"name": "hi", "times": "2"}),
+                &context,
+            )
+            .await;
+        assert_eq!(result.unwrap(), serde_json::json!("hihi!"));
+        // Defaults are only used for params that are left out
+        let result = host
+            .run(
+                "greet",
+                serde_json::json!({"name": "hi", "suffix": "?"}),
+                &context,
+            )
+            .await;
+        assert_eq!(result.unwrap(), serde_json::json!("hi?"));
+
+        for args in [
+            serde_json::json!({"times": 2}),
+            serde_json::json!({"name": 1}),
+            serde_json::json!({"name": "hi", "times": -1}),
+        ] {
+            let err = host.run("greet", args, &context).await.unwrap_err();
+            assert_eq!(err.code(), "INVALID_ARGUMENTS");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_bytes_params_and_results() {
+        let toml = crate::test_utils::manifest_toml("hasher", "0.1.0")
+            .replace(
+                "[api.greet.returns]",
+                "[api.greet.params]\n\
+                 data = { type = \"bytes\", description = \"Data to reverse\" }\n\n\
+                 [api.greet.returns]",
+            )
+            .replace("type = \"string\"", "type = \"bytes\"");
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = "function greet(args) return string.reverse(args.data) .. '\\255' end";
+        let host = LuaTappletHost::from_string(config, code, NoopApi).unwrap();
+        let context = CallContext::user();
+
+        // The script sees raw bytes, whether they were sent as base64 or an array
+        for data in [serde_json::json!("AAEC"), serde_json::json!([0, 1, 2])] {
+            let result = host
+                .run("greet", serde_json::json!({ "data": data }), &context)
+                .await;
+            assert_eq!(result.unwrap(), serde_json::json!("AgEA/w=="));
+        }
+        let err = host
+            .run("greet", serde_json::json!({"data": "%%"}), &context)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "INVALID_ARGUMENTS");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_rate_limit() {
+        let toml = crate::test_utils::manifest_toml("hammer", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["read", "guarded"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function read() for i = 1, 3 do minotari_load_data_entries("slot") end end
+            function guarded()
+                local ok, err = pcall(minotari_load_data_entries, "slot")
+                return tostring(err)
+            end
+        "#;
+        let host = LuaTappletHost::from_string(config, code, NoopApi)
+            .unwrap()
+            .with_rate_limit(RateLimit::new(0.001, 2));
+        let context = CallContext::user();
+
+        let err = host.run("read", Value::Null, &context).await.unwrap_err();
+        assert_eq!(err.code(), "RATE_LIMITED");
+        // The script can catch the error instead
+        let result = host.run("guarded", Value::Null, &context).await.unwrap();
+        assert!(
+            result
+                .as_str()
+                .unwrap()
+                .contains("minotari_load_data_entries called more than"),
+            "{}",
+            result
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lua_governor() {
+        let governor = ResourceGovernor::new(
+            crate::governor::ResourceBudgets::default().with_total_memory(1024 * 1024),
+        );
+        let load = |name: &str, items: usize| {
+            let toml = crate::test_utils::manifest_toml(name, "0.1.0");
+            let config = TappletManifest::from_toml_str(&toml).unwrap();
+            let code = format!(
+                "hoard = {{}} function greet() for i = 1, {} do hoard[i] = 'item ' .. i end end",
+                items
+            );
+            LuaTappletHost::from_string(config, &code, NoopApi)
+                .unwrap()
+                .with_governor(&governor)
+        };
+        let small = load("small", 10);
+        let mut big = load("big", 50_000);
+        let context = CallContext::user();
+
+        small.run("greet", Value::Null, &context).await.unwrap();
+        // Together they hold more than the budget, so the biggest is evicted
+        big.run("greet", Value::Null, &context).await.unwrap();
+        let err = big.run("greet", Value::Null, &context).await.unwrap_err();
+        assert_eq!(err.code(), "THROTTLED");
+        assert!(governor.usage().tapplets["big"].evicted);
+        small.run("greet", Value::Null, &context).await.unwrap();
+
+        // Reloading gives the host a fresh Lua state and lifts the eviction
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.lua");
+        std::fs::write(&path, "function greet() return 1 end").unwrap();
+        big.reload(&path).unwrap();
+        assert_eq!(big.run("greet", Value::Null, &context).await.unwrap(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_result_cache() {
+        let toml = crate::test_utils::manifest_toml("greeter", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["greet", "count"]"#)
+            .replace(
+                r#"description = "Returns a greeting message.""#,
+                "description = \"Returns a greeting message.\"\npure = true",
+            );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        assert!(config.api.method("greet").unwrap().pure);
+        let code = r#"
+            calls = 0
+            function greet() calls = calls + 1 return "hello" end
+            function count() calls = calls + 1 return calls end
+        "#;
+        let cache = ResultCache::new(16);
+        let mut host = LuaTappletHost::from_string(config, code, NoopApi)
+            .unwrap()
+            .with_result_cache(&cache);
+        let context = CallContext::user();
+
+        // Only the first call of the pure method runs the script
+        for _ in 0..3 {
+            assert_eq!(
+                host.run("greet", Value::Null, &context).await.unwrap(),
+                "hello"
+            );
+        }
+        assert_eq!(host.run("count", Value::Null, &context).await.unwrap(), 2);
+        assert_eq!(host.run("count", Value::Null, &context).await.unwrap(), 3);
+        assert_eq!(cache.stats().hits, 2);
+
+        // Reloading drops the cached results
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.lua");
+        std::fs::write(&path, "function greet() return 'hi' end").unwrap();
+        host.reload(&path).unwrap();
+        assert_eq!(
+            host.run("greet", Value::Null, &context).await.unwrap(),
+            "hi"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lua_host_options() {
+        let toml = crate::test_utils::manifest_toml("dice", "0.1.0").replace(
+            r#"methods = ["greet"]"#,
+            r#"methods = ["roll", "clock", "shallow", "deep"]"#,
+        );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            function roll() return math.random(1, 1000000) end
+            function clock() return os ~= nil end
+            function depth(n) if n == 0 then return 0 end return 1 + depth(n - 1) end
+            function shallow() return depth(10) end
+            function deep() return depth(100) end
+        "#;
+        let options = HostOptions::default()
+            .with_stack_limit(50)
+            .with_deterministic()
+            .with_sandbox(SandboxOptions::new().with_allowed_global("os"));
+        let load = || {
+            LuaTappletHost::from_string_with_options(
+                config.clone(),
+                code,
+                Arc::new(NoopApi),
+                &options,
+            )
+            .unwrap()
+        };
+        let (first, second) = (load(), load());
+        let context = CallContext::user();
+
+        // Deterministic hosts roll the same numbers and can't read the clock
+        for _ in 0..3 {
+            assert_eq!(
+                first.run("roll", Value::Null, &context).await.unwrap(),
+                second.run("roll", Value::Null, &context).await.unwrap()
+            );
+        }
+        assert_eq!(
+            first.run("clock", Value::Null, &context).await.unwrap(),
+            false
+        );
+
+        assert_eq!(
+            first.run("shallow", Value::Null, &context).await.unwrap(),
+            10
+        );
+        let err = first.run("deep", Value::Null, &context).await.unwrap_err();
+        assert!(err.to_string().contains("stack limit exceeded"), "{}", err);
+    }
+}
@@ -0,0 +1,241 @@
+//! Runtime settings of a host in one value.
+//!
+//! Every setting also has a `with_*` method on the hosts. [`HostOptions`] gathers
+//! them so an embedder can keep one configuration, e.g. read from its settings
+//! file, and pass it to `WasmTappletHost::new_with_options`,
+//! `LuaTappletHost::new_with_options` or `TappletManager::get_host_with_options`.
+//!
+//! ```rust,ignore
+//! let options = HostOptions::default()
+//!     .with_timeout(Duration::from_secs(5))
+//!     .with_interceptor(Arc::new(RequireUnlocked(unlocked.clone())));
+//! let host = manager.get_host_with_options("my_tapplet", api, &options)?;
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::ambient::AmbientMode;
+use crate::audit::AuditSink;
+use crate::chain::MinotariChainApi;
+use crate::events::EventSender;
+use crate::governor::ResourceGovernor;
+use crate::intercept::CallInterceptor;
+use crate::log_sink::{LogLevel, LogSink};
+use crate::network::NetworkAccess;
+use crate::rate_limit::RateLimit;
+use crate::result_cache::ResultCache;
+use crate::sandbox::SandboxOptions;
+use crate::storage::StorageQuota;
+
+/// Timeout of a call unless the options say otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Memory a tapplet may use unless the options say otherwise, in bytes
+pub const DEFAULT_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+/// Depth of a tapplet's call stack unless the options say otherwise
+pub const DEFAULT_STACK_LIMIT: usize = 1000;
+
+/// Limits, sandbox and hooks of a host.
+///
+/// The default limits calls to [`DEFAULT_TIMEOUT`], [`DEFAULT_MEMORY_LIMIT`] and
+/// [`DEFAULT_STACK_LIMIT`], which is plenty for wallet tapplets while stopping
+/// runaway ones. [`HostOptions::unlimited`] sets no limits at all, like the
+/// hosts' plain constructors. WASM hosts have no Lua sandbox, so they ignore
+/// it, and fail with [`crate::host::HostError::Unsupported`] on limits their
+/// engine can't enforce, like the stack limit with wasmtime.
+#[derive(Clone)]
+pub struct HostOptions {
+    /// Execution budget of a call, see `LuaTappletHost::with_execution_budget`
+    /// and `WasmTappletHost::with_execution_budget`
+    pub fuel: Option<u64>,
+    pub timeout: Option<Duration>,
+    /// Memory the Lua state or the WASM module's memory may use, in bytes
+    pub memory_limit: Option<usize>,
+    /// Lua or WASM functions that may be running at once, e.g. in a recursion
+    pub stack_limit: Option<usize>,
+    /// Take away the Lua tapplet's clock and seed `math.random` the same way in
+    /// every host, see [`SandboxOptions::with_deterministic`]. Unless `ambient`
+    /// is deterministic already, this also makes it `AmbientMode::deterministic(0)`.
+    pub deterministic: bool,
+    /// Where `minotari_now` and `minotari_random_bytes` get their values from
+    pub ambient: AmbientMode,
+    pub sandbox: SandboxOptions,
+    pub interceptors: Vec<Arc<dyn CallInterceptor>>,
+    pub rate_limit: Option<RateLimit>,
+    pub storage_quota: Option<StorageQuota>,
+    /// Where appends raise `slot_changed` events, see [`crate::events::TappletEventBus::sender`]
+    pub events: Option<EventSender>,
+    pub governor: Option<ResourceGovernor>,
+    pub result_cache: Option<ResultCache>,
+    pub audit_sink: Option<Arc<dyn AuditSink>>,
+    pub log_sink: Option<Arc<dyn LogSink>>,
+    /// Lowest level of tapplet log output that is kept, `info` if unset
+    pub log_level: Option<LogLevel>,
+    /// Levels of single tapplets by name, overriding `log_level`
+    pub tapplet_log_levels: BTreeMap<String, LogLevel>,
+    /// Lets tapplets send HTTP requests to the hosts their manifests allow
+    pub network: Option<NetworkAccess>,
+    /// Answers the chain queries of tapplets whose manifests ask for them
+    pub chain_api: Option<Arc<dyn MinotariChainApi>>,
+}
+
+impl Default for HostOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Some(DEFAULT_TIMEOUT),
+            memory_limit: Some(DEFAULT_MEMORY_LIMIT),
+            stack_limit: Some(DEFAULT_STACK_LIMIT),
+            ..Self::unlimited()
+        }
+    }
+}
+
+impl HostOptions {
+    /// No limits, sandbox defaults and no hooks
+    pub fn unlimited() -> Self {
+        Self {
+            fuel: None,
+            timeout: None,
+            memory_limit: None,
+            stack_limit: None,
+            deterministic: false,
+            ambient: AmbientMode::Real,
+            sandbox: SandboxOptions::default(),
+            interceptors: Vec::new(),
+            rate_limit: None,
+            storage_quota: None,
+            events: None,
+            governor: None,
+            result_cache: None,
+            audit_sink: None,
+            log_sink: None,
+            log_level: None,
+            tapplet_log_levels: BTreeMap::new(),
+            network: None,
+            chain_api: None,
+        }
+    }
+
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    pub fn with_stack_limit(mut self, depth: usize) -> Self {
+        self.stack_limit = Some(depth);
+        self
+    }
+
+    pub fn with_deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    pub fn with_ambient_mode(mut self, mode: AmbientMode) -> Self {
+        self.ambient = mode;
+        self
+    }
+
+    pub fn with_sandbox(mut self, sandbox: SandboxOptions) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Run `interceptor` around every call, after the ones already added
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn CallInterceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    pub fn with_storage_quota(mut self, quota: StorageQuota) -> Self {
+        self.storage_quota = Some(quota);
+        self
+    }
+
+    pub fn with_event_sender(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn with_governor(mut self, governor: &ResourceGovernor) -> Self {
+        self.governor = Some(governor.clone());
+        self
+    }
+
+    pub fn with_result_cache(mut self, cache: &ResultCache) -> Self {
+        self.result_cache = Some(cache.clone());
+        self
+    }
+
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.audit_sink = Some(sink);
+        self
+    }
+
+    pub fn with_log_sink(mut self, sink: Arc<dyn LogSink>) -> Self {
+        self.log_sink = Some(sink);
+        self
+    }
+
+    pub fn with_log_level(mut self, level: LogLevel) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// Keep log output of `tapplet` from `level` up, e.g. to debug one tapplet
+    /// without the others' debug output
+    pub fn with_tapplet_log_level(mut self, tapplet: &str, level: LogLevel) -> Self {
+        self.tapplet_log_levels.insert(tapplet.to_string(), level);
+        self
+    }
+
+    pub fn with_network_access(mut self, access: NetworkAccess) -> Self {
+        self.network = Some(access);
+        self
+    }
+
+    pub fn with_chain_api(mut self, api: Arc<dyn MinotariChainApi>) -> Self {
+        self.chain_api = Some(api);
+        self
+    }
+
+    /// Log level of the tapplet called `tapplet`, if the options set one
+    pub fn log_level_for(&self, tapplet: &str) -> Option<LogLevel> {
+        self.tapplet_log_levels
+            .get(tapplet)
+            .copied()
+            .or(self.log_level)
+    }
+
+    /// Ambient mode of hosts, deterministic if the options are
+    pub(crate) fn ambient_mode(&self) -> AmbientMode {
+        match (self.deterministic, self.ambient) {
+            (true, AmbientMode::Real) => AmbientMode::deterministic(0),
+            (_, mode) => mode,
+        }
+    }
+
+    /// Sandbox of Lua hosts, made deterministic if the options are
+    pub(crate) fn lua_sandbox(&self) -> SandboxOptions {
+        match self.deterministic {
+            true => self.sandbox.clone().with_deterministic(),
+            false => self.sandbox.clone(),
+        }
+    }
+}
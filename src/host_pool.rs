@@ -0,0 +1,589 @@
+//! Constructed hosts kept around for reuse.
+//!
+//! Compiling and instantiating a tapplet costs far more than calling it, so a
+//! wallet UI that calls the same tapplets over and over should not construct a
+//! new host for every call. `TappletManager::get_pooled_host` takes hosts out of
+//! a [`HostPool`] and only constructs one when the pool has none for the
+//! installed artifact. Dropping the [`PooledHost`] puts it back.
+//!
+//! ```rust,ignore
+//! let pool = HostPool::new(api, 16).with_options(HostOptions::default());
+//! let mut host = manager.get_pooled_host(&pool, "my_tapplet")?;
+//! host.call("greet", json!({}), &CallContext::user()).await?;
+//! ```
+//!
+//! Hosts that stay in the pool for the lifetime of the wallet can break, leak
+//! memory or get stuck. Call [`HostPool::check_health`] periodically to run each
+//! idle host's [`crate::host::GUEST_HEALTH_EXPORT`] and drop the ones that fail it, hang or
+//! grew by more than [`HealthCheck::max_memory_growth`]. Hosts whose call
+//! timed out aren't put back either.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::TappletManifest;
+use crate::call_context::CallContext;
+use crate::host::{HostError, MinotariTappletApiV1, TappletRunner};
+use crate::host_options::HostOptions;
+use crate::manager::InstalledHost;
+#[cfg(feature = "metrics")]
+use crate::metrics::{self, MetricsSink};
+use crate::result_cache::CacheStats;
+
+/// A host over the type-erased API hosts in a pool share
+pub type DynInstalledHost = InstalledHost<dyn MinotariTappletApiV1>;
+
+/// Fully qualified name of the tapplet and SHA-256 of its installed artifact, so
+/// a reinstalled tapplet never gets a host of the code it replaced, and tapplets
+/// of the same name from different publishers never share hosts
+pub(crate) type Key = (String, String);
+
+/// How a [`HostPool`] checks its idle hosts, see [`HostPool::check_health`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// Time between checks of a host
+    pub interval: Duration,
+    /// Time the health function may run before the host counts as hung
+    pub timeout: Duration,
+    /// Memory a host may grow by after it was constructed, in bytes
+    pub max_memory_growth: Option<u64>,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(5),
+            max_memory_growth: None,
+        }
+    }
+}
+
+impl HealthCheck {
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_max_memory_growth(mut self, bytes: u64) -> Self {
+        self.max_memory_growth = Some(bytes);
+        self
+    }
+
+    /// Why `host` should be dropped, if it should
+    fn verdict(&self, host: &mut DynInstalledHost, baseline_memory: u64) -> Option<RecycleReason> {
+        match host.check_health(self.timeout) {
+            Ok(_) => {}
+            Err(HostError::Timeout(_)) => return Some(RecycleReason::Hung),
+            Err(e) => return Some(RecycleReason::Failed(e.to_string())),
+        }
+        let grown = host.memory_usage().saturating_sub(baseline_memory);
+        self.max_memory_growth
+            .filter(|max| grown > *max)
+            .map(|_| RecycleReason::MemoryGrowth(grown))
+    }
+}
+
+/// Why a pooled host was dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecycleReason {
+    /// The health check failed with this error
+    Failed(String),
+    /// The health check or a call timed out
+    Hung,
+    /// The host grew by this many bytes
+    MemoryGrowth(u64),
+}
+
+impl RecycleReason {
+    /// The `reason` label of [`crate::metrics::HOSTS_RECYCLED_TOTAL`]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecycleReason::Failed(_) => "failed",
+            RecycleReason::Hung => "hung",
+            RecycleReason::MemoryGrowth(_) => "memory",
+        }
+    }
+}
+
+/// Outcome of [`HostPool::check_health`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Number of hosts that were checked
+    pub checked: usize,
+    /// Canonical names of the tapplets whose hosts were dropped, and why
+    pub recycled: Vec<(String, RecycleReason)>,
+}
+
+/// What the pool knows about the health of a host
+#[derive(Clone, Copy)]
+struct Vitals {
+    /// Memory the host used when it was constructed
+    baseline_memory: u64,
+    checked: Instant,
+}
+
+struct Idle {
+    key: Key,
+    host: DynInstalledHost,
+    vitals: Vitals,
+}
+
+struct State {
+    max_instances: usize,
+    max_per_tapplet: Option<usize>,
+    health: HealthCheck,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<dyn MetricsSink>>,
+    /// Hosts not in use by when they were last put back, oldest first
+    idle: BTreeMap<u64, Idle>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl State {
+    fn put_back(&mut self, idle: Idle) {
+        if self.max_instances == 0 || self.max_per_tapplet == Some(0) {
+            return;
+        }
+        if let Some(max) = self.max_per_tapplet {
+            let same: Vec<u64> = self
+                .idle
+                .iter()
+                .filter(|(_, other)| other.key == idle.key)
+                .map(|(used, _)| *used)
+                .collect();
+            for used in same.iter().take((same.len() + 1).saturating_sub(max)) {
+                self.idle.remove(used);
+            }
+        }
+        while self.idle.len() >= self.max_instances {
+            self.idle.pop_first();
+        }
+        self.clock += 1;
+        self.idle.insert(self.clock, idle);
+    }
+
+    /// Count a health check of a host of `tapplet` in the metrics
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn record_check(&self, tapplet: &str, recycled: Option<&RecycleReason>) {
+        #[cfg(feature = "metrics")]
+        if let Some(sink) = &self.metrics {
+            let status = recycled.map_or("ok", RecycleReason::as_str);
+            let labels = [("tapplet", tapplet), ("status", status)];
+            sink.increment_counter(metrics::HEALTH_CHECKS_TOTAL, &labels, 1);
+        }
+        if let Some(reason) = recycled {
+            self.record_recycle(tapplet, reason);
+        }
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn record_recycle(&self, tapplet: &str, reason: &RecycleReason) {
+        #[cfg(feature = "metrics")]
+        if let Some(sink) = &self.metrics {
+            let labels = [("tapplet", tapplet), ("reason", reason.as_str())];
+            sink.increment_counter(metrics::HOSTS_RECYCLED_TOTAL, &labels, 1);
+        }
+    }
+}
+
+/// LRU pool of constructed hosts, keyed by tapplet and artifact.
+///
+/// Every host in the pool is constructed with the pool's API and options. Hosts
+/// can't be moved between threads, so neither can the pool; clones share the
+/// pooled hosts.
+#[derive(Clone)]
+pub struct HostPool {
+    api: Arc<dyn MinotariTappletApiV1>,
+    options: HostOptions,
+    state: Rc<RefCell<State>>,
+}
+
+impl HostPool {
+    /// Keep up to `max_instances` idle hosts, dropping the least recently used
+    /// ones first. Hosts have no limits unless set with [`HostPool::with_options`].
+    pub fn new(api: Arc<dyn MinotariTappletApiV1>, max_instances: usize) -> Self {
+        Self {
+            api,
+            options: HostOptions::unlimited(),
+            state: Rc::new(RefCell::new(State {
+                max_instances,
+                max_per_tapplet: None,
+                health: HealthCheck::default(),
+                #[cfg(feature = "metrics")]
+                metrics: None,
+                idle: BTreeMap::new(),
+                clock: 0,
+                hits: 0,
+                misses: 0,
+            })),
+        }
+    }
+
+    /// Construct hosts with these limits, sandbox and hooks
+    pub fn with_options(mut self, options: HostOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Keep at most `max` idle hosts of one tapplet, so a tapplet that is called
+    /// concurrently doesn't take up the whole pool
+    pub fn with_max_per_tapplet(self, max: usize) -> Self {
+        self.state.borrow_mut().max_per_tapplet = Some(max);
+        self
+    }
+
+    /// Check idle hosts as `health` says, see [`HostPool::check_health`]
+    pub fn with_health_check(self, health: HealthCheck) -> Self {
+        self.state.borrow_mut().health = health;
+        self
+    }
+
+    /// Count health checks and recycled hosts in `sink`
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics_sink(self, sink: Arc<dyn MetricsSink>) -> Self {
+        self.state.borrow_mut().metrics = Some(sink);
+        self
+    }
+
+    /// Check every idle host whose last check is older than the
+    /// [`HealthCheck::interval`], dropping the ones that fail. Call it
+    /// periodically, e.g. from a timer on the thread that uses the pool.
+    pub fn check_health(&self) -> HealthReport {
+        let now = Instant::now();
+        let (health, due): (HealthCheck, Vec<u64>) = {
+            let state = self.state.borrow();
+            let due = state
+                .idle
+                .iter()
+                .filter(|(_, idle)| {
+                    now.saturating_duration_since(idle.vitals.checked) >= state.health.interval
+                })
+                .map(|(used, _)| *used)
+                .collect();
+            (state.health, due)
+        };
+        let mut report = HealthReport::default();
+        for used in due {
+            // Taken out while it runs, like a host in use
+            let Some(mut idle) = self.state.borrow_mut().idle.remove(&used) else {
+                continue;
+            };
+            report.checked += 1;
+            let verdict = health.verdict(&mut idle.host, idle.vitals.baseline_memory);
+            let mut state = self.state.borrow_mut();
+            state.record_check(&idle.host.manifest().name, verdict.as_ref());
+            match verdict {
+                None => {
+                    idle.vitals.checked = now;
+                    // Without counting as used, so the LRU order stays the same
+                    state.idle.insert(used, idle);
+                    while state.idle.len() > state.max_instances {
+                        state.idle.pop_first();
+                    }
+                }
+                Some(reason) => report.recycled.push((idle.key.0, reason)),
+            }
+        }
+        report
+    }
+
+    /// How often a host could be reused; `entries` counts the idle hosts
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.borrow();
+        CacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            entries: state.idle.len(),
+        }
+    }
+
+    /// Drop every idle host. Hosts in use are still put back when dropped.
+    pub fn clear(&self) {
+        self.state.borrow_mut().idle.clear();
+    }
+
+    /// Drop the idle hosts of every version of a tapplet, given by qualified
+    /// name, or by name for the tapplets of that name of every publisher
+    pub fn invalidate(&self, tapplet: &str) {
+        self.state.borrow_mut().idle.retain(|_, idle| {
+            let qualified = idle.key.0.split_once('@').map_or("", |(name, _)| name);
+            !crate::result_cache::is_tapplet(qualified, tapplet)
+        });
+    }
+
+    pub(crate) fn api(&self) -> Arc<dyn MinotariTappletApiV1> {
+        self.api.clone()
+    }
+
+    pub(crate) fn options(&self) -> &HostOptions {
+        &self.options
+    }
+
+    /// The most recently used idle host for `key`
+    pub(crate) fn take(&self, key: &Key) -> Option<PooledHost> {
+        let mut state = self.state.borrow_mut();
+        let used = state
+            .idle
+            .iter()
+            .rev()
+            .find(|(_, idle)| &idle.key == key)
+            .map(|(used, _)| *used);
+        let Some(idle) = used.and_then(|used| state.idle.remove(&used)) else {
+            state.misses += 1;
+            return None;
+        };
+        state.hits += 1;
+        Some(self.hand_out(idle))
+    }
+
+    /// Hand out a newly constructed host that goes into the pool when dropped
+    pub(crate) fn lend(&self, key: Key, mut host: DynInstalledHost) -> PooledHost {
+        let vitals = Vitals {
+            baseline_memory: host.memory_usage(),
+            checked: Instant::now(),
+        };
+        self.hand_out(Idle { key, host, vitals })
+    }
+
+    fn hand_out(&self, idle: Idle) -> PooledHost {
+        PooledHost {
+            key: idle.key,
+            host: Some(idle.host),
+            vitals: idle.vitals,
+            hung: false,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl std::fmt::Debug for HostPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("HostPool")
+            .field("max_instances", &state.max_instances)
+            .field("max_per_tapplet", &state.max_per_tapplet)
+            .field("idle", &state.idle.len())
+            .finish()
+    }
+}
+
+/// A host taken out of a [`HostPool`], put back when dropped
+pub struct PooledHost {
+    key: Key,
+    /// Only `None` while being dropped or discarded
+    host: Option<DynInstalledHost>,
+    vitals: Vitals,
+    /// Whether a call timed out, so the host isn't put back
+    hung: bool,
+    state: Rc<RefCell<State>>,
+}
+
+impl PooledHost {
+    /// Drop the host instead of putting it back, e.g. after it trapped and may
+    /// have been left in a bad state
+    pub fn discard(mut self) {
+        self.host = None;
+    }
+}
+
+impl Deref for PooledHost {
+    type Target = DynInstalledHost;
+
+    fn deref(&self) -> &Self::Target {
+        self.host
+            .as_ref()
+            .expect("pooled host is only taken when dropped")
+    }
+}
+
+impl DerefMut for PooledHost {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.host
+            .as_mut()
+            .expect("pooled host is only taken when dropped")
+    }
+}
+
+impl Drop for PooledHost {
+    fn drop(&mut self) {
+        let Some(host) = self.host.take() else {
+            return;
+        };
+        let mut state = self.state.borrow_mut();
+        if self.hung {
+            state.record_recycle(&host.manifest().name, &RecycleReason::Hung);
+            return;
+        }
+        state.put_back(Idle {
+            key: std::mem::take(&mut self.key),
+            host,
+            vitals: self.vitals,
+        });
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl TappletRunner for PooledHost {
+    fn manifest(&self) -> &TappletManifest {
+        (**self).manifest()
+    }
+
+    async fn call(
+        &mut self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError> {
+        let result = (**self).call(method, args, context).await;
+        self.hung |= matches!(result, Err(HostError::Timeout(_)));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TappletManager;
+    use crate::manager::TappletSource;
+    use crate::reference_api::MemoryTappletApi;
+    use crate::test_utils;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pooled_hosts_are_reused() {
+        let temp = tempfile::tempdir().unwrap();
+        let source_dir = temp.path().join("source");
+        test_utils::write_lua_tapplet(&source_dir, "counter", "0.1.0");
+        std::fs::write(
+            source_dir.join("main.lua"),
+            "n = 0 function greet() n = n + 1 return n end",
+        )
+        .unwrap();
+        let manager = TappletManager::new(temp.path().join("cache"));
+        let installed = manager
+            .install(TappletSource::LocalLua { path: source_dir })
+            .unwrap();
+        let pool = HostPool::new(Arc::new(MemoryTappletApi::new()), 4).with_max_per_tapplet(1);
+        let user = CallContext::user();
+
+        // The second host is the first one again, with its state
+        for expected in [1, 2] {
+            let mut host = manager.get_pooled_host(&pool, "counter").unwrap();
+            let result = host.call("greet", Value::Null, &user).await.unwrap();
+            assert_eq!(result, expected);
+        }
+
+        // A host in use isn't handed out twice, and only one goes back
+        let first = manager.get_pooled_host(&pool, "counter").unwrap();
+        let second = manager.get_pooled_host(&pool, "counter").unwrap();
+        drop(first);
+        drop(second);
+        assert_eq!(
+            pool.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 2,
+                entries: 1
+            }
+        );
+
+        // Changed code is a different artifact, which gets a new host
+        std::fs::write(
+            installed.lua_path().unwrap(),
+            "function greet() return 'changed' end",
+        )
+        .unwrap();
+        let mut host = manager.get_pooled_host(&pool, "counter").unwrap();
+        let result = host.call("greet", Value::Null, &user).await.unwrap();
+        assert_eq!(result, "changed");
+        host.discard();
+        assert_eq!(pool.stats().misses, 3);
+
+        pool.invalidate("counter");
+        assert_eq!(pool.stats().entries, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_health_check_recycles_hosts() {
+        let temp = tempfile::tempdir().unwrap();
+        let source_dir = temp.path().join("source");
+        test_utils::write_lua_tapplet(&source_dir, "counter", "0.1.0");
+        let code = r#"
+            n = 0
+            function greet()
+                n = n + 1
+                if n == 3 then grown = string.rep("x", 100000) end
+                return n
+            end
+            function tapplet_health()
+                if n == 1 then return false end
+                if n == 2 then while true do end end
+                return true
+            end
+        "#;
+        std::fs::write(source_dir.join("main.lua"), code).unwrap();
+        let manager = TappletManager::new(temp.path().join("cache"));
+        manager
+            .install(TappletSource::LocalLua { path: source_dir })
+            .unwrap();
+        let health = HealthCheck::default()
+            .with_interval(Duration::ZERO)
+            .with_timeout(Duration::from_millis(50))
+            .with_max_memory_growth(50_000);
+        let pool = HostPool::new(Arc::new(MemoryTappletApi::new()), 4).with_health_check(health);
+        let user = CallContext::user();
+        // A fresh host called `times` times
+        let use_host = async |times| {
+            let mut host = manager.get_pooled_host(&pool, "counter").unwrap();
+            for _ in 0..times {
+                host.call("greet", Value::Null, &user).await.unwrap();
+            }
+        };
+        let recycled = |reason| HealthReport {
+            checked: 1,
+            recycled: vec![("test_publisher/counter@0.1.0".to_string(), reason)],
+        };
+
+        use_host(0).await;
+        assert_eq!(
+            pool.check_health(),
+            HealthReport {
+                checked: 1,
+                recycled: vec![],
+            }
+        );
+        pool.clear();
+
+        use_host(1).await;
+        let report = pool.check_health();
+        assert_eq!(
+            report,
+            recycled(RecycleReason::Failed(
+                "Execution error: tapplet_health returned false".to_string()
+            ))
+        );
+        use_host(2).await;
+        assert_eq!(pool.check_health(), recycled(RecycleReason::Hung));
+        use_host(3).await;
+        let report = pool.check_health();
+        assert!(
+            matches!(report.recycled[0].1, RecycleReason::MemoryGrowth(grown) if grown > 100_000),
+            "{:?}",
+            report
+        );
+        assert_eq!(pool.stats().entries, 0);
+    }
+}
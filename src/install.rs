@@ -0,0 +1,344 @@
+//! Options, results and progress of installing a tapplet into a cache directory
+
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+
+use crate::TappletManifest;
+use crate::build::ModuleOptimization;
+use crate::error::TappletError;
+use crate::manager::{InstalledTapplet, read_installed};
+use crate::registry::NoProgress;
+
+/// What to do when a different install of a tapplet is in its install directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Keep the installed tapplet
+    #[default]
+    Skip,
+    /// Replace the installed tapplet
+    Overwrite,
+    /// Fail with [`TappletError::AlreadyInstalled`]
+    Error,
+}
+
+/// How installers treat tapplets that are already installed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InstallOptions {
+    /// Replace the installed tapplet even if it has the same manifest, e.g. because
+    /// its scripts changed
+    pub force: bool,
+    /// What to do when another version, or another manifest of the same version,
+    /// is installed
+    pub on_conflict: OnConflict,
+    /// Install into `<name>@<version>` so that versions are installed side by side
+    pub versioned_dirs: bool,
+    /// Install into `<publisher>/<name>` so that tapplets of the same name from
+    /// different publishers are installed side by side
+    pub publisher_dirs: bool,
+}
+
+impl InstallOptions {
+    /// Where `manifest`'s tapplet is installed in `cache_directory`.
+    ///
+    /// Fails with [`TappletError::InvalidManifest`] if the publisher or name isn't
+    /// a plain directory name, since installers replace whatever is at the path.
+    pub fn install_dir(
+        &self,
+        cache_directory: &Path,
+        manifest: &TappletManifest,
+    ) -> Result<PathBuf> {
+        let parent = if self.publisher_dirs {
+            cache_directory.join(dir_name("publisher", &manifest.publisher)?)
+        } else {
+            cache_directory.to_path_buf()
+        };
+        let name = if self.versioned_dirs {
+            format!("{}@{}", manifest.name, manifest.version)
+        } else {
+            manifest.name.clone()
+        };
+        Ok(parent.join(dir_name("name", &name)?))
+    }
+
+    /// Decide what to do about `target_path`, the install directory of `manifest`
+    pub(crate) fn existing_install(
+        &self,
+        target_path: &Path,
+        manifest: &TappletManifest,
+    ) -> Result<ExistingInstall> {
+        if !target_path.exists() {
+            return Ok(ExistingInstall::None);
+        }
+        if self.force {
+            return Ok(ExistingInstall::Replace);
+        }
+        if is_same_install(target_path, manifest) {
+            return Ok(ExistingInstall::Keep);
+        }
+        match self.on_conflict {
+            OnConflict::Skip => Ok(ExistingInstall::Keep),
+            OnConflict::Overwrite => Ok(ExistingInstall::Replace),
+            OnConflict::Error => bail!(TappletError::AlreadyInstalled {
+                name: manifest.name.clone(),
+                path: target_path.to_path_buf(),
+            }),
+        }
+    }
+}
+
+/// `value` if it is a single directory name: no separators or `..`, and not
+/// hidden, as dot directories such as the module cache belong to the host
+fn dir_name<'a>(field: &str, value: &'a str) -> Result<&'a str> {
+    let mut components = Path::new(value).components();
+    let single = matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    );
+    if !single || value.starts_with('.') || value.contains(['/', '\\']) {
+        bail!(TappletError::InvalidManifest(format!(
+            "{} '{}' can't be used as a directory name",
+            field, value
+        )));
+    }
+    Ok(value)
+}
+
+/// An install directory found by [`InstallOptions::existing_install`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ExistingInstall {
+    None,
+    Keep,
+    /// Remove it once the new install is ready to be written
+    Replace,
+}
+
+impl ExistingInstall {
+    /// Remove `target_path` if it is to be replaced
+    pub(crate) fn clear(self, target_path: &Path) -> Result<()> {
+        if self == ExistingInstall::Replace {
+            std::fs::remove_dir_all(target_path).with_context(|| {
+                format!(
+                    "Failed to remove installed tapplet: {}",
+                    target_path.display()
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The installed manifest has the same digest, and so the same version, as `manifest`
+fn is_same_install(target_path: &Path, manifest: &TappletManifest) -> bool {
+    let Ok(installed) = TappletManifest::from_file(target_path.join("manifest.toml")) else {
+        return false;
+    };
+    matches!((installed.digest(), manifest.digest()), (Ok(a), Ok(b)) if a == b)
+}
+
+/// List the tapplets installed in `cache_directory`, by name and then version.
+///
+/// Every directory with a `manifest.toml` is an install, so tapplets installed with
+/// [`InstallOptions::versioned_dirs`] are listed once per version. Installs in
+/// [`InstallOptions::publisher_dirs`] are found in the directory of their publisher.
+pub fn list_installed(cache_directory: &Path) -> Result<Vec<InstalledTapplet>> {
+    if !cache_directory.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut installed = Vec::new();
+    for entry in std::fs::read_dir(cache_directory).with_context(|| {
+        format!(
+            "Failed to read cache directory: {}",
+            cache_directory.display()
+        )
+    })? {
+        let path = entry?.path();
+        if path.join("manifest.toml").exists() {
+            installed.push(read_installed(path)?);
+        } else if path.is_dir()
+            && !path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .starts_with('.')
+        {
+            installed.extend(list_publisher_dir(&path)?);
+        }
+    }
+    installed.sort_by(|a, b| {
+        a.manifest
+            .name
+            .cmp(&b.manifest.name)
+            .then_with(|| a.manifest.cmp_version(&b.manifest))
+    });
+    Ok(installed)
+}
+
+/// Installs in a publisher's directory. Other directories holding tapplets, such
+/// as git checkouts, are skipped as their tapplets aren't from that publisher.
+fn list_publisher_dir(dir: &Path) -> Result<Vec<InstalledTapplet>> {
+    let publisher = dir.file_name().unwrap_or_default();
+    let mut installed = Vec::new();
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if !path.join("manifest.toml").exists() {
+            continue;
+        }
+        let tapplet = read_installed(path)?;
+        if publisher == tapplet.manifest.publisher.as_str() {
+            installed.push(tapplet);
+        }
+    }
+    Ok(installed)
+}
+
+/// Remove every installed version of `name` from `cache_directory` and return
+/// what was removed.
+///
+/// Fails with [`TappletError::NotInstalled`] if no version is installed.
+pub fn uninstall(name: &str, cache_directory: &Path) -> Result<Vec<InstalledTapplet>> {
+    let removed: Vec<_> = list_installed(cache_directory)?
+        .into_iter()
+        .filter(|tapplet| tapplet.manifest.name_matches(name))
+        .collect();
+    if removed.is_empty() {
+        bail!(TappletError::NotInstalled {
+            name: name.to_string()
+        });
+    }
+    for tapplet in &removed {
+        std::fs::remove_dir_all(&tapplet.path).with_context(|| {
+            format!(
+                "Failed to remove installed tapplet: {}",
+                tapplet.path.display()
+            )
+        })?;
+    }
+    Ok(removed)
+}
+
+/// What an install did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstallReport {
+    /// The tapplet's directory in the cache
+    pub installed_path: PathBuf,
+    /// Files written into `installed_path`, in the order they were written
+    pub artifacts: Vec<PathBuf>,
+    /// The tapplet was already installed and was left as it was
+    pub skipped: bool,
+    pub duration: Duration,
+    /// Sizes of the WASM module before and after it was optimized, if it was
+    pub optimization: Option<ModuleOptimization>,
+    /// Problems that didn't stop the install, e.g. WASM exports that aren't
+    /// listed in the manifest
+    pub warnings: Vec<String>,
+}
+
+/// A step of an install
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallProgress {
+    /// The install of `name` started
+    Started { name: String },
+    /// The tapplet's git repository is being cloned
+    Cloning { url: String },
+    /// A revision of the tapplet's git repository is being checked out
+    CheckingOut { rev: String },
+    /// A WASM tapplet's crate is being compiled
+    Building,
+    /// The installed WASM module is being optimized
+    Optimizing { path: PathBuf },
+    /// The installed WASM module is being compiled ahead of time, see
+    /// [`crate::build::BuildOptions::precompile`]
+    Precompiling { path: PathBuf },
+    /// A file is being copied into the install directory
+    Copying { source: PathBuf, target: PathBuf },
+    /// A manifest in another format is being written as TOML
+    ConvertingManifest { source: PathBuf, target: PathBuf },
+    /// The tapplet is installed at `path`, possibly by an earlier install
+    Done { path: PathBuf, skipped: bool },
+}
+
+/// Receives progress updates while a tapplet is installed.
+///
+/// Called from the blocking install thread, so implementations should be cheap
+/// and forward the update (e.g. over a channel) rather than do work inline.
+pub trait InstallReporter: Send + Sync {
+    fn report(&self, progress: InstallProgress);
+}
+
+impl<F> InstallReporter for F
+where
+    F: Fn(InstallProgress) + Send + Sync,
+{
+    fn report(&self, progress: InstallProgress) {
+        self(progress)
+    }
+}
+
+impl InstallReporter for NoProgress {
+    fn report(&self, _progress: InstallProgress) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::write_lua_tapplet;
+
+    #[test]
+    fn test_list_and_uninstall() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache = temp.path();
+        assert!(list_installed(&cache.join("missing")).unwrap().is_empty());
+
+        write_lua_tapplet(&cache.join("wallet@0.10.0"), "wallet", "0.10.0");
+        write_lua_tapplet(&cache.join("wallet@0.9.0"), "wallet", "0.9.0");
+        write_lua_tapplet(&cache.join("clock"), "clock", "1.0.0");
+        std::fs::create_dir_all(cache.join("modules")).unwrap();
+
+        let listed: Vec<_> = list_installed(cache)
+            .unwrap()
+            .into_iter()
+            .map(|tapplet| format!("{}@{}", tapplet.manifest.name, tapplet.manifest.version))
+            .collect();
+        assert_eq!(listed, ["clock@1.0.0", "wallet@0.9.0", "wallet@0.10.0"]);
+
+        let removed = uninstall("wallet", cache).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert!(!cache.join("wallet@0.9.0").exists());
+        assert_eq!(list_installed(cache).unwrap().len(), 1);
+
+        let err = uninstall("wallet", cache).unwrap_err();
+        assert_eq!(crate::error_code(&err), "NOT_INSTALLED");
+    }
+
+    #[test]
+    fn test_install_dir_stays_in_cache() {
+        let toml = crate::test_utils::manifest_toml("wallet", "0.1.0");
+        let mut manifest = TappletManifest::from_toml_str(&toml).unwrap();
+        let options = InstallOptions {
+            publisher_dirs: true,
+            ..Default::default()
+        };
+        let cache = Path::new("cache");
+        assert_eq!(
+            options.install_dir(cache, &manifest).unwrap(),
+            cache.join("test_publisher/wallet")
+        );
+
+        for publisher in ["..", "../..", "/etc", "a/b", ".wasm_modules", ""] {
+            manifest.publisher = publisher.to_string();
+            let err = options.install_dir(cache, &manifest).unwrap_err();
+            assert_eq!(crate::error_code(&err), "INVALID_MANIFEST", "{}", publisher);
+        }
+        manifest.publisher = "test_publisher".to_string();
+        manifest.name = "../wallet".to_string();
+        let err = InstallOptions::default()
+            .install_dir(cache, &manifest)
+            .unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_MANIFEST");
+    }
+}
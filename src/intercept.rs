@@ -0,0 +1,192 @@
+//! Hooks around tapplet method calls.
+//!
+//! Interceptors registered on a host with `with_interceptor` see every call of
+//! `run()`, so applications can add authorization, caching, logging or metrics
+//! of their own without patching the crate.
+
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::call_context::CallContext;
+use crate::host::HostError;
+
+/// A call about to be made, or just made, by a host
+#[derive(Debug, Clone, Copy)]
+pub struct InterceptedCall<'a> {
+    pub tapplet: &'a str,
+    pub method: &'a str,
+    pub args: &'a Value,
+    pub context: &'a CallContext,
+}
+
+/// Hooks a host runs around every method call.
+///
+/// Interceptors run in the order they were registered. The first `before_call`
+/// to reject or answer a call stops it, and the ones after it aren't asked.
+/// Every interceptor then sees the outcome through `after_call` or `on_error`,
+/// including calls that were answered or rejected, so logging interceptors see
+/// everything. Streams are checked with `before_call` when they start; the
+/// chunks pulled after that don't pass through the interceptors.
+pub trait CallInterceptor: Send + Sync {
+    /// Called before the method runs. Return an error to reject the call, or
+    /// `Some` result to answer it without running the tapplet, e.g. from a cache.
+    fn before_call(&self, _call: &InterceptedCall<'_>) -> Result<Option<Value>, HostError> {
+        Ok(None)
+    }
+
+    /// Called with the result of a call that succeeded
+    fn after_call(&self, _call: &InterceptedCall<'_>, _result: &Value) {}
+
+    /// Called with the error of a call that failed or was rejected
+    fn on_error(&self, _call: &InterceptedCall<'_>, _error: &HostError) {}
+}
+
+/// The interceptors of a host. Clones share the list, so hosts can hold on to it
+/// while they are borrowed mutably.
+#[derive(Clone, Default)]
+pub(crate) struct Interceptors(Arc<Vec<Arc<dyn CallInterceptor>>>);
+
+impl Interceptors {
+    pub fn push(&mut self, interceptor: Arc<dyn CallInterceptor>) {
+        Arc::make_mut(&mut self.0).push(interceptor);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Run `before_call` of each interceptor until one rejects or answers the call
+    pub fn before(&self, call: &InterceptedCall<'_>) -> Option<Result<Value, HostError>> {
+        self.0
+            .iter()
+            .find_map(|interceptor| interceptor.before_call(call).transpose())
+    }
+
+    /// Tell every interceptor how the call went
+    pub fn after(&self, call: &InterceptedCall<'_>, result: &Result<Value, HostError>) {
+        for interceptor in self.0.iter() {
+            match result {
+                Ok(value) => interceptor.after_call(call, value),
+                Err(e) => interceptor.on_error(call, e),
+            }
+        }
+    }
+
+    /// Make the call with `run` unless an interceptor answers or rejects it
+    pub fn around(
+        &self,
+        call: &InterceptedCall<'_>,
+        run: impl FnOnce() -> Result<Value, HostError>,
+    ) -> Result<Value, HostError> {
+        if self.is_empty() {
+            return run();
+        }
+        let result = self.before(call).unwrap_or_else(run);
+        self.after(call, &result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::TappletManifest;
+    use crate::host::LuaTappletHost;
+    use crate::reference_api::MemoryTappletApi;
+
+    /// Rejects `secret` unless the caller may read secrets
+    struct Authz;
+
+    impl CallInterceptor for Authz {
+        fn before_call(&self, call: &InterceptedCall<'_>) -> Result<Option<Value>, HostError> {
+            if call.method == "secret" && !call.context.has_permission("secrets:read") {
+                return Err(HostError::PermissionDenied("secrets:read".to_string()));
+            }
+            Ok(None)
+        }
+    }
+
+    /// Answers repeated calls from the results of earlier ones
+    #[derive(Default)]
+    struct Cache(Mutex<HashMap<String, Value>>);
+
+    impl CallInterceptor for Cache {
+        fn before_call(&self, call: &InterceptedCall<'_>) -> Result<Option<Value>, HostError> {
+            Ok(self.0.lock().unwrap().get(call.method).cloned())
+        }
+
+        fn after_call(&self, call: &InterceptedCall<'_>, result: &Value) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(call.method.to_string(), result.clone());
+        }
+    }
+
+    /// Records the outcome of every call
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl CallInterceptor for Recorder {
+        fn after_call(&self, call: &InterceptedCall<'_>, result: &Value) {
+            let event = format!("{}/{}: {}", call.tapplet, call.method, result);
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_error(&self, call: &InterceptedCall<'_>, error: &HostError) {
+            let event = format!("{}/{}: {}", call.tapplet, call.method, error.code());
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_interceptor_chain() {
+        let toml = crate::test_utils::manifest_toml("vault", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["greet", "secret"]"#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let code = r#"
+            calls = 0
+            function greet() calls = calls + 1 return "hello " .. calls end
+            function secret() return "hunter2" end
+        "#;
+        let recorder = Arc::new(Recorder::default());
+        let host = LuaTappletHost::from_string(config, code, MemoryTappletApi::new())
+            .unwrap()
+            .with_interceptor(Arc::new(Authz))
+            .with_interceptor(Arc::new(Cache::default()))
+            .with_interceptor(recorder.clone());
+        let user = CallContext::user();
+
+        // The second call is answered from the cache without running the script
+        assert_eq!(
+            host.run("greet", Value::Null, &user).await.unwrap(),
+            "hello 1"
+        );
+        assert_eq!(
+            host.run("greet", Value::Null, &user).await.unwrap(),
+            "hello 1"
+        );
+
+        let err = host.run("secret", Value::Null, &user).await.unwrap_err();
+        assert_eq!(err.code(), "PERMISSION_DENIED");
+        let reader = CallContext::user().with_permission("secrets:read");
+        assert_eq!(
+            host.run("secret", Value::Null, &reader).await.unwrap(),
+            "hunter2"
+        );
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                r#"vault/greet: "hello 1""#,
+                r#"vault/greet: "hello 1""#,
+                "vault/secret: PERMISSION_DENIED",
+                r#"vault/secret: "hunter2""#,
+            ]
+        );
+    }
+}
@@ -1,20 +1,106 @@
+pub mod audit;
+pub mod build;
+pub mod checksum;
+pub mod codegen;
+pub mod error;
+pub mod log_sink;
 pub mod model;
 
-#[cfg(feature = "host")]
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "host-core")]
+pub mod ambient;
+#[cfg(feature = "host-core")]
+pub mod call_context;
+#[cfg(feature = "host-core")]
+pub mod cancel;
+#[cfg(feature = "host-core")]
+pub mod chain;
+#[cfg(feature = "host-core")]
+pub mod engine;
+#[cfg(feature = "host-core")]
+pub mod events;
+#[cfg(feature = "host-core")]
+pub mod governor;
+#[cfg(feature = "host-core")]
 pub mod host;
+#[cfg(feature = "host-core")]
+pub mod host_options;
+#[cfg(feature = "host-core")]
+pub mod host_pool;
+#[cfg(feature = "host-core")]
+pub mod intercept;
+#[cfg(feature = "host-core")]
+pub mod lua_json;
+#[cfg(feature = "host-core")]
+pub mod module_cache;
+#[cfg(feature = "host-core")]
+pub mod network;
+#[cfg(feature = "host-core")]
+pub mod process;
+#[cfg(feature = "host-core")]
+pub mod rate_limit;
+#[cfg(feature = "host-core")]
+pub mod reference_api;
+#[cfg(feature = "host-core")]
+pub mod replay;
+#[cfg(feature = "host-core")]
+pub mod result_cache;
+#[cfg(feature = "host-core")]
+pub mod router;
+#[cfg(feature = "host-core")]
+pub mod sandbox;
+#[cfg(feature = "host-core")]
+pub mod scheduler;
+#[cfg(feature = "host-core")]
+pub mod secure_storage;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "host-core")]
+pub mod storage;
+#[cfg(feature = "host-core")]
+pub mod testing;
+#[cfg(feature = "host-core")]
+pub mod wallet;
+#[cfg(feature = "host-core")]
+pub mod wasi;
 
 pub mod git_tapplet;
+pub mod install;
 pub mod local_folder_lua_tapplet;
 pub mod local_folder_tapplet;
+pub mod lock;
+pub mod manager;
+pub mod package;
 pub mod registry;
+pub mod registry_manager;
+pub mod resolver;
+pub mod trust;
+pub mod watch;
+
+mod trace;
+
+#[cfg(test)]
+mod test_utils;
 
 use std::path::Path;
 
+pub use error::{TappletError, error_code, error_to_json};
+pub use manager::TappletManager;
 pub use model::TappletManifest;
 pub use registry::TappletRegistry;
+pub use registry_manager::RegistryManager;
+pub use trust::TrustPolicy;
 
-#[cfg(feature = "host")]
-pub use host::{HostError, LuaTappletHost, WasmTappletHost, run};
+#[cfg(feature = "host-core")]
+pub use call_context::{CallContext, Caller};
+#[cfg(feature = "host-core")]
+pub use host::{DynLuaTappletHost, HostError, LuaTappletHost, WasmTappletHost, run};
+#[cfg(feature = "host-core")]
+pub use module_cache::ModuleCache;
+#[cfg(feature = "host-core")]
+pub use process::ProcessTappletHost;
 
 use anyhow::Result;
 
@@ -1,78 +1,198 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::TappletManifest;
+use crate::error::TappletError;
+use crate::install::{
+    ExistingInstall, InstallOptions, InstallProgress, InstallReport, InstallReporter,
+};
+use crate::local_folder_tapplet::{finished_install, install_manifest, skipped_install};
+use crate::model::{HOST_API_VERSION, RuntimeKind, find_manifest_file};
+use crate::registry::NoProgress;
+#[cfg(feature = "host-core")]
+use crate::sandbox::{SandboxOptions, check_script};
+use crate::trace;
+use crate::watch::{DEFAULT_POLL_INTERVAL, TappletWatcher};
 use anyhow::{Context, Result, bail};
+use walkdir::WalkDir;
 
+#[derive(Clone)]
 pub struct LocalFolderLuaTapplet {
     path: PathBuf,
     pub config: TappletManifest,
+    options: InstallOptions,
+    #[cfg(feature = "host-core")]
+    sandbox: SandboxOptions,
 }
 
 impl LocalFolderLuaTapplet {
     pub fn load(path: PathBuf) -> Result<Self> {
-        let manifest_file = path.join("manifest.toml");
-        if !manifest_file.exists() {
-            bail!(
-                "No manifest.toml found in the specified directory: {}",
-                path.display()
-            );
-        }
+        let Some(manifest_file) = find_manifest_file(&path) else {
+            bail!(TappletError::ManifestNotFound { path });
+        };
         let config = TappletManifest::from_file(&manifest_file)?;
 
-        Ok(Self { path, config })
+        Ok(Self {
+            path,
+            config,
+            options: InstallOptions::default(),
+            #[cfg(feature = "host-core")]
+            sandbox: SandboxOptions::default(),
+        })
     }
 
-    pub fn install(&self, cache_directory: PathBuf) -> Result<()> {
-        println!("Installing Lua tapplet: {}", self.config.name);
+    /// Choose how an already installed copy of the tapplet is treated
+    pub fn with_install_options(mut self, options: InstallOptions) -> Self {
+        self.options = options;
+        self
+    }
 
-        // Create the target directory path: cache_directory/tapplet_name
-        let target_path = cache_directory.join(&self.config.name);
+    /// The sandbox the installed scripts are checked against, which should match the
+    /// one hosts run them in, e.g. to allow `os`
+    #[cfg(feature = "host-core")]
+    pub fn with_sandbox_options(mut self, sandbox: SandboxOptions) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
 
-        // Check if the directory already exists
-        if target_path.exists() {
-            println!("Tapplet already installed at: {}", target_path.display());
-            return Ok(());
+    /// The script the host runs: the declared runtime entrypoint, or else `<name>.lua`,
+    /// `main.lua` or the first Lua file in the root of the tapplet directory
+    pub(crate) fn main_script(&self) -> Result<PathBuf> {
+        if let Some(entrypoint) = self.config.entrypoint_path(RuntimeKind::Lua, &self.path)? {
+            if !entrypoint.is_file() {
+                bail!(TappletError::ArtifactNotFound(format!(
+                    "Lua entrypoint {} does not exist",
+                    entrypoint.display()
+                )));
+            }
+            return Ok(entrypoint);
         }
 
-        // Create the target directory
-        std::fs::create_dir_all(&target_path).with_context(|| {
-            format!(
-                "Failed to create target directory: {}",
-                target_path.display()
-            )
-        })?;
+        for candidate in [format!("{}.lua", self.config.name), "main.lua".to_string()] {
+            let path = self.path.join(candidate);
+            if path.is_file() {
+                return Ok(path);
+            }
+        }
 
-        // Find the Lua file in the source directory
-        // Look for .lua files in the root of the tapplet directory
-        let lua_files: Vec<_> = std::fs::read_dir(&self.path)
+        let mut lua_files: Vec<_> = std::fs::read_dir(&self.path)
             .with_context(|| format!("Failed to read source directory: {}", self.path.display()))?
             .filter_map(|entry| entry.ok())
-            .filter(|entry| {
-                entry
-                    .path()
-                    .extension()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
                     .and_then(|ext| ext.to_str())
                     .map(|ext| ext == "lua")
                     .unwrap_or(false)
             })
             .collect();
+        lua_files.sort();
 
-        if lua_files.is_empty() {
-            bail!(
-                "No Lua file found in source directory: {}",
+        lua_files.into_iter().next().ok_or_else(|| {
+            TappletError::ArtifactNotFound(format!(
+                "no Lua file in source directory {}",
                 self.path.display()
-            );
+            ))
+            .into()
+        })
+    }
+
+    /// Install the tapplet into its directory in `cache_directory`, see
+    /// [`InstallOptions::install_dir`]
+    pub async fn install(&self, cache_directory: PathBuf) -> Result<InstallReport> {
+        self.install_with_progress(cache_directory, Arc::new(NoProgress))
+            .await
+    }
+
+    /// Install like [`LocalFolderLuaTapplet::install`], reporting each step to `reporter`
+    pub async fn install_with_progress(
+        &self,
+        cache_directory: PathBuf,
+        reporter: Arc<dyn InstallReporter>,
+    ) -> Result<InstallReport> {
+        // Copying blocks, so run it in a separate thread
+        let tapplet = self.clone();
+        tokio::task::spawn_blocking(move || tapplet.install_blocking(&cache_directory, &*reporter))
+            .await
+            .context("Failed to spawn blocking task")?
+    }
+
+    /// Install the tapplet now and again whenever its sources change, until the
+    /// watcher is dropped. Must be called from within a tokio runtime.
+    pub fn watch(&self, cache_directory: PathBuf) -> TappletWatcher {
+        self.watch_with_interval(cache_directory, DEFAULT_POLL_INTERVAL)
+    }
+
+    /// Watch like [`LocalFolderLuaTapplet::watch`], checking for changes every `interval`
+    pub fn watch_with_interval(
+        &self,
+        cache_directory: PathBuf,
+        interval: Duration,
+    ) -> TappletWatcher {
+        let path = self.path.clone();
+        let options = InstallOptions {
+            force: true,
+            ..self.options
+        };
+        #[cfg(feature = "host-core")]
+        let sandbox = self.sandbox.clone();
+        TappletWatcher::spawn(self.path.clone(), interval, move || {
+            // Loaded again, since the manifest may have changed too
+            let tapplet = Self::load(path.clone())?.with_install_options(options);
+            #[cfg(feature = "host-core")]
+            let tapplet = tapplet.with_sandbox_options(sandbox.clone());
+            tapplet.install_blocking(&cache_directory, &NoProgress)
+        })
+    }
+
+    pub(crate) fn install_blocking(
+        &self,
+        cache_directory: &Path,
+        reporter: &dyn InstallReporter,
+    ) -> Result<InstallReport> {
+        let _span = trace::span!(
+            "install",
+            tapplet = %self.config.name,
+            version = %self.config.version
+        );
+        let started = Instant::now();
+        reporter.report(InstallProgress::Started {
+            name: self.config.name.clone(),
+        });
+
+        self.config.ensure_host_api(&HOST_API_VERSION)?;
+        let target_path = self.options.install_dir(cache_directory, &self.config)?;
+        let existing = self.options.existing_install(&target_path, &self.config)?;
+        if existing == ExistingInstall::Keep {
+            return Ok(skipped_install(target_path, started, reporter));
         }
 
-        // Use the first Lua file found (or we could use the package name to find the right one)
-        let lua_source = lua_files[0].path();
+        let lua_source = self.main_script()?;
+        self.config.verify_artifacts(&self.path)?;
+        // `copy_tree` below copies the assets along with the scripts
+        self.config.verify_assets(&self.path)?;
+        #[cfg(feature = "host-core")]
+        check_scripts(&self.path, &self.sandbox)?;
+
+        // Only replace an existing install once the new one is known to be good
+        existing.clear(&target_path)?;
+        std::fs::create_dir_all(&target_path).with_context(|| {
+            format!(
+                "Failed to create target directory: {}",
+                target_path.display()
+            )
+        })?;
+
+        // Copy the whole tree so the script can `require` its helper modules
+        let mut artifacts = copy_tree(&self.path, &target_path)?;
+
         let lua_target = target_path.join(format!("{}.lua", self.config.name));
 
-        println!(
-            "Copying Lua file: {} -> {}",
-            lua_source.display(),
-            lua_target.display()
-        );
+        reporter.report(InstallProgress::Copying {
+            source: lua_source.clone(),
+            target: lua_target.clone(),
+        });
         std::fs::copy(&lua_source, &lua_target).with_context(|| {
             format!(
                 "Failed to copy Lua file from {} to {}",
@@ -80,28 +200,385 @@ impl LocalFolderLuaTapplet {
                 lua_target.display()
             )
         })?;
+        artifacts.push(lua_target);
+
+        artifacts.push(install_manifest(
+            &self.path,
+            &self.config,
+            &target_path,
+            reporter,
+        )?);
+
+        Ok(finished_install(target_path, artifacts, started, reporter))
+    }
+}
 
-        // Copy the manifest.toml
-        let manifest_source = self.path.join("manifest.toml");
-        let manifest_target = target_path.join("manifest.toml");
+/// Copy every file below `source` to `target`, skipping hidden files and directories like
+/// `.git`, and return the copied files
+fn copy_tree(source: &Path, target: &Path) -> Result<Vec<PathBuf>> {
+    let mut copied = Vec::new();
+    let walker = WalkDir::new(source)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'));
+    for entry in walker {
+        let entry = entry
+            .with_context(|| format!("Failed to read source directory: {}", source.display()))?;
+        let relative = entry.path().strip_prefix(source)?;
+        let destination = target.join(relative);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&destination).with_context(|| {
+                format!("Failed to create directory: {}", destination.display())
+            })?;
+        } else if entry.file_type().is_file() {
+            std::fs::copy(entry.path(), &destination).with_context(|| {
+                format!(
+                    "Failed to copy {} to {}",
+                    entry.path().display(),
+                    destination.display()
+                )
+            })?;
+            copied.push(destination);
+        }
+    }
+    Ok(copied)
+}
 
-        println!(
-            "Copying manifest: {} -> {}",
-            manifest_source.display(),
-            manifest_target.display()
+/// Compile every Lua script below `dir` in a throwaway sandbox, see [`check_script`],
+/// and fail with [`TappletError::InvalidScript`] for the first one with problems.
+///
+/// Scripts can `require` modules from `dir` unless `sandbox` sets another module root.
+#[cfg(feature = "host-core")]
+pub fn check_scripts(dir: &Path, sandbox: &SandboxOptions) -> Result<()> {
+    let sandbox = match sandbox.module_root() {
+        Some(_) => sandbox.clone(),
+        None => sandbox.clone().with_module_root(dir),
+    };
+    let walker = WalkDir::new(dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'));
+    for entry in walker {
+        let entry =
+            entry.with_context(|| format!("Failed to read source directory: {}", dir.display()))?;
+        let is_script = entry
+            .path()
+            .extension()
+            .is_some_and(|ext| ext == "lua" || ext == "luau");
+        if !entry.file_type().is_file() || !is_script {
+            continue;
+        }
+        let code = std::fs::read_to_string(entry.path())
+            .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+        let issues = check_script(&code, &sandbox);
+        if !issues.is_empty() {
+            bail!(TappletError::InvalidScript {
+                path: entry.path().to_path_buf(),
+                issues: issues.iter().map(|issue| issue.to_string()).collect(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The runtime of a tapplet source directory: the one its manifest declares, or else
+/// Lua if it contains a Lua script at its root and WASM otherwise
+pub(crate) fn tapplet_dir_runtime(dir: &Path) -> Result<RuntimeKind> {
+    if let Some(manifest_file) = find_manifest_file(dir)
+        && let Some(runtime) = TappletManifest::from_file(&manifest_file)?.runtime
+    {
+        return Ok(runtime.kind);
+    }
+    let has_lua_script = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read source directory: {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .any(|entry| {
+            entry
+                .path()
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| ext == "lua")
+                .unwrap_or(false)
+        });
+    Ok(if has_lua_script {
+        RuntimeKind::Lua
+    } else {
+        RuntimeKind::Wasm
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install::OnConflict;
+
+    #[tokio::test]
+    async fn test_install_copies_modules() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        crate::test_utils::write_lua_tapplet(&source, "multi", "0.1.0");
+        std::fs::create_dir_all(source.join("utils")).unwrap();
+        std::fs::write(source.join("utils/format.lua"), "return {}").unwrap();
+        std::fs::write(source.join("aaa.lua"), "return {}").unwrap();
+        std::fs::create_dir_all(source.join(".git")).unwrap();
+        std::fs::write(source.join(".git/HEAD"), "ref").unwrap();
+
+        let cache = temp.path().join("cache");
+        LocalFolderLuaTapplet::load(source)
+            .unwrap()
+            .install(cache.clone())
+            .await
+            .unwrap();
+
+        let installed = cache.join("multi");
+        assert_eq!(
+            std::fs::read_to_string(installed.join("multi.lua")).unwrap(),
+            "function greet() return 'hello' end"
         );
-        std::fs::copy(&manifest_source, &manifest_target).with_context(|| {
-            format!(
-                "Failed to copy manifest from {} to {}",
-                manifest_source.display(),
-                manifest_target.display()
-            )
-        })?;
+        assert!(installed.join("utils/format.lua").is_file());
+        assert!(!installed.join(".git").exists());
+    }
+
+    #[tokio::test]
+    async fn test_install_reports_progress() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        crate::test_utils::write_lua_tapplet(&source, "reported", "0.1.0");
+        let cache = temp.path().join("cache");
+        let tapplet = LocalFolderLuaTapplet::load(source).unwrap();
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let reporter = Arc::new(move |progress| sink.lock().unwrap().push(progress));
+        let report = tapplet
+            .install_with_progress(cache.clone(), reporter)
+            .await
+            .unwrap();
+        let installed = cache.join("reported");
+        assert_eq!(report.installed_path, installed);
+        assert!(!report.skipped);
+        assert!(report.artifacts.contains(&installed.join("reported.lua")));
+        assert!(report.artifacts.contains(&installed.join("manifest.toml")));
+
+        let events = events.lock().unwrap().clone();
+        assert_eq!(
+            events.first(),
+            Some(&InstallProgress::Started {
+                name: "reported".to_string()
+            })
+        );
+        assert_eq!(
+            events.last(),
+            Some(&InstallProgress::Done {
+                path: installed.clone(),
+                skipped: false
+            })
+        );
+
+        let report = tapplet.install(cache).await.unwrap();
+        assert!(report.skipped);
+        assert!(report.artifacts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_install_options() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        crate::test_utils::write_lua_tapplet(&source, "options", "0.1.0");
+        std::fs::write(source.join("stale.lua"), "return {}").unwrap();
+        let cache = temp.path().join("cache");
+        let installed = cache.join("options");
+        let load = |options| {
+            LocalFolderLuaTapplet::load(source.clone())
+                .unwrap()
+                .with_install_options(options)
+        };
+        load(InstallOptions::default())
+            .install(cache.clone())
+            .await
+            .unwrap();
+
+        // Same manifest: kept unless forced
+        std::fs::remove_file(source.join("stale.lua")).unwrap();
+        std::fs::write(source.join("main.lua"), "function greet() return 'hi' end").unwrap();
+        let report = load(InstallOptions::default())
+            .install(cache.clone())
+            .await
+            .unwrap();
+        assert!(report.skipped);
+        let force = InstallOptions {
+            force: true,
+            ..Default::default()
+        };
+        assert!(!load(force).install(cache.clone()).await.unwrap().skipped);
+        assert_eq!(
+            std::fs::read_to_string(installed.join("options.lua")).unwrap(),
+            "function greet() return 'hi' end"
+        );
+        assert!(!installed.join("stale.lua").exists());
+
+        // Another version
+        let manifest = crate::test_utils::manifest_toml("options", "0.2.0");
+        std::fs::write(source.join("manifest.toml"), manifest).unwrap();
+        let report = load(InstallOptions::default())
+            .install(cache.clone())
+            .await
+            .unwrap();
+        assert!(report.skipped);
+        let error = InstallOptions {
+            on_conflict: OnConflict::Error,
+            ..Default::default()
+        };
+        let err = load(error).install(cache.clone()).await.unwrap_err();
+        assert_eq!(crate::error_code(&err), "ALREADY_INSTALLED");
+        let overwrite = InstallOptions {
+            on_conflict: OnConflict::Overwrite,
+            ..Default::default()
+        };
+        load(overwrite).install(cache.clone()).await.unwrap();
+        let manifest = TappletManifest::from_file(installed.join("manifest.toml")).unwrap();
+        assert_eq!(manifest.version, "0.2.0");
 
-        println!(
-            "Successfully installed Lua tapplet to: {}",
-            target_path.display()
+        let versioned = InstallOptions {
+            versioned_dirs: true,
+            ..Default::default()
+        };
+        let report = load(versioned).install(cache.clone()).await.unwrap();
+        assert_eq!(report.installed_path, cache.join("options@0.2.0"));
+        assert!(report.installed_path.join("options.lua").is_file());
+    }
+
+    #[tokio::test]
+    async fn test_watch_reinstalls_on_change() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        crate::test_utils::write_lua_tapplet(&source, "watched", "0.1.0");
+        let cache = temp.path().join("cache");
+        let installed = cache.join("watched/watched.lua");
+
+        let mut watcher = LocalFolderLuaTapplet::load(source.clone())
+            .unwrap()
+            .watch_with_interval(cache.clone(), Duration::from_millis(10));
+        let report = watcher.next().await.unwrap().unwrap();
+        assert_eq!(report.installed_path, cache.join("watched"));
+        assert!(installed.is_file());
+
+        std::fs::write(source.join("main.lua"), "function greet() return 'hi' end").unwrap();
+        assert!(!watcher.next().await.unwrap().unwrap().skipped);
+        assert_eq!(
+            std::fs::read_to_string(&installed).unwrap(),
+            "function greet() return 'hi' end"
         );
-        Ok(())
+
+        // A broken manifest fails that install, and the next change is picked up
+        std::fs::write(source.join("manifest.toml"), "name = ").unwrap();
+        assert!(watcher.next().await.unwrap().is_err());
+        let manifest = crate::test_utils::manifest_toml("watched", "0.2.0");
+        std::fs::write(source.join("manifest.toml"), manifest).unwrap();
+        watcher.next().await.unwrap().unwrap();
+        let manifest = TappletManifest::from_file(cache.join("watched/manifest.toml")).unwrap();
+        assert_eq!(manifest.version, "0.2.0");
+    }
+
+    #[cfg(feature = "host-core")]
+    #[tokio::test]
+    async fn test_install_checks_scripts() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        crate::test_utils::write_lua_tapplet(&source, "checked", "0.1.0");
+        std::fs::create_dir_all(source.join("utils")).unwrap();
+        std::fs::write(source.join("utils/clock.lua"), "return os.time()").unwrap();
+        let cache = temp.path().join("cache");
+
+        let tapplet = LocalFolderLuaTapplet::load(source.clone()).unwrap();
+        let err = tapplet.install(cache.clone()).await.unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_SCRIPT");
+        assert!(
+            err.to_string()
+                .contains("line 1: uses the denied global 'os'")
+        );
+        assert!(!cache.join("checked").exists());
+
+        let allow_os = SandboxOptions::new().with_allowed_global("os");
+        let tapplet = tapplet.with_sandbox_options(allow_os.clone());
+        tapplet.install(cache.clone()).await.unwrap();
+
+        std::fs::write(source.join("main.lua"), "function greet( return 'hi' end").unwrap();
+        let err = check_scripts(&source, &allow_os).unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_SCRIPT");
+    }
+
+    #[tokio::test]
+    async fn test_install_converts_json_manifest() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        crate::test_utils::write_lua_tapplet(&source, "json", "0.1.0");
+        let manifest = TappletManifest::from_file(source.join("manifest.toml")).unwrap();
+        std::fs::remove_file(source.join("manifest.toml")).unwrap();
+        std::fs::write(
+            source.join("manifest.json"),
+            serde_json::to_string_pretty(&manifest).unwrap(),
+        )
+        .unwrap();
+
+        let cache = temp.path().join("cache");
+        LocalFolderLuaTapplet::load(source)
+            .unwrap()
+            .install(cache.clone())
+            .await
+            .unwrap();
+
+        let installed = TappletManifest::from_file(cache.join("json/manifest.toml")).unwrap();
+        assert_eq!(installed.digest().unwrap(), manifest.digest().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_install_verifies_artifacts() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        crate::test_utils::write_lua_tapplet(&source, "checked", "0.1.0");
+        let script_hash = crate::checksum::sha256_file(&source.join("main.lua")).unwrap();
+        let manifest = crate::test_utils::manifest_toml("checked", "0.1.0")
+            + &format!("\n[artifacts]\n\"main.lua\" = \"{}\"\n", script_hash);
+        std::fs::write(source.join("manifest.toml"), manifest).unwrap();
+        std::fs::write(
+            source.join("main.lua"),
+            "function greet() return 'pwned' end",
+        )
+        .unwrap();
+
+        let cache = temp.path().join("cache");
+        let tapplet = LocalFolderLuaTapplet::load(source.clone()).unwrap();
+        let err = tapplet.install(cache.clone()).await.unwrap_err();
+        assert_eq!(crate::error_code(&err), "INTEGRITY_MISMATCH");
+        assert!(!cache.join("checked").exists());
+
+        std::fs::write(
+            source.join("main.lua"),
+            "function greet() return 'hello' end",
+        )
+        .unwrap();
+        tapplet.install(cache.clone()).await.unwrap();
+        assert!(cache.join("checked/checked.lua").is_file());
+    }
+
+    #[tokio::test]
+    async fn test_install_refuses_newer_host_api() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        crate::test_utils::write_lua_tapplet(&source, "future", "0.1.0");
+        let manifest = crate::test_utils::manifest_toml("future", "0.1.0")
+            .replace("[api]", "requires_host_api = \">=99\"\n\n[api]");
+        std::fs::write(source.join("manifest.toml"), manifest).unwrap();
+
+        let cache = temp.path().join("cache");
+        let err = LocalFolderLuaTapplet::load(source)
+            .unwrap()
+            .install(cache.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(crate::error_code(&err), "UNSUPPORTED_HOST_API");
+        assert!(!cache.join("future").exists());
     }
 }
@@ -1,106 +1,146 @@
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::TappletManifest;
+use crate::build::{self, BuildOptions};
+use crate::error::TappletError;
+use crate::install::{
+    ExistingInstall, InstallOptions, InstallProgress, InstallReport, InstallReporter,
+};
+use crate::model::{HOST_API_VERSION, RuntimeKind, find_manifest_file};
+use crate::registry::NoProgress;
+use crate::trace;
+use crate::watch::{DEFAULT_POLL_INTERVAL, TappletWatcher};
 use anyhow::{Context, Result, bail};
 
+#[derive(Clone)]
 pub struct LocalFolderTapplet {
     path: PathBuf,
-    config: TappletManifest,
+    pub config: TappletManifest,
+    options: InstallOptions,
+    build_options: BuildOptions,
 }
 
 impl LocalFolderTapplet {
     pub fn load(path: PathBuf) -> Result<Self> {
-        let manifest_file = path.join("manifest.toml");
-        if !manifest_file.exists() {
-            bail!(
-                "No manifest.toml found in the specified directory: {}",
-                path.display()
-            );
-        }
+        let Some(manifest_file) = find_manifest_file(&path) else {
+            bail!(TappletError::ManifestNotFound { path });
+        };
         let config = TappletManifest::from_file(&manifest_file)?;
 
-        Ok(Self { path, config })
+        Ok(Self {
+            path,
+            config,
+            options: InstallOptions::default(),
+            build_options: BuildOptions::default(),
+        })
+    }
+
+    /// Choose how an already installed copy of the tapplet is treated
+    pub fn with_install_options(mut self, options: InstallOptions) -> Self {
+        self.options = options;
+        self
     }
 
-    pub fn install(&self, cache_directory: PathBuf) -> Result<()> {
-        println!("Installing tapplet: {}", self.config.name);
+    /// Choose how cargo builds the tapplet if it has no prebuilt module
+    pub fn with_build_options(mut self, build_options: BuildOptions) -> Self {
+        self.build_options = build_options;
+        self
+    }
+
+    /// Install the tapplet into its directory in `cache_directory`, see
+    /// [`InstallOptions::install_dir`], building it first if needed
+    pub async fn install(&self, cache_directory: PathBuf) -> Result<InstallReport> {
+        self.install_with_progress(cache_directory, Arc::new(NoProgress))
+            .await
+    }
 
-        // Create the target directory path: cache_directory/tapplet_name
-        let target_path = cache_directory.join(&self.config.name);
+    /// Install like [`LocalFolderTapplet::install`], reporting each step to `reporter`
+    pub async fn install_with_progress(
+        &self,
+        cache_directory: PathBuf,
+        reporter: Arc<dyn InstallReporter>,
+    ) -> Result<InstallReport> {
+        // Building and copying block, so run them in a separate thread
+        let tapplet = self.clone();
+        tokio::task::spawn_blocking(move || tapplet.install_blocking(&cache_directory, &*reporter))
+            .await
+            .context("Failed to spawn blocking task")?
+    }
 
-        // Check if the directory already exists
-        if target_path.exists() {
-            println!("Tapplet already installed at: {}", target_path.display());
-            return Ok(());
+    /// Install the tapplet now and again whenever its sources change, building it
+    /// each time, until the watcher is dropped. Must be called from within a tokio
+    /// runtime.
+    pub fn watch(&self, cache_directory: PathBuf) -> TappletWatcher {
+        self.watch_with_interval(cache_directory, DEFAULT_POLL_INTERVAL)
+    }
+
+    /// Watch like [`LocalFolderTapplet::watch`], checking for changes every `interval`
+    pub fn watch_with_interval(
+        &self,
+        cache_directory: PathBuf,
+        interval: Duration,
+    ) -> TappletWatcher {
+        let path = self.path.clone();
+        let options = InstallOptions {
+            force: true,
+            ..self.options
+        };
+        let build_options = self.build_options.clone();
+        TappletWatcher::spawn(self.path.clone(), interval, move || {
+            // Loaded again, since the manifest may have changed too
+            Self::load(path.clone())?
+                .with_install_options(options)
+                .with_build_options(build_options.clone())
+                .install_blocking(&cache_directory, &NoProgress)
+        })
+    }
+
+    pub(crate) fn install_blocking(
+        &self,
+        cache_directory: &Path,
+        reporter: &dyn InstallReporter,
+    ) -> Result<InstallReport> {
+        let _span = trace::span!(
+            "install",
+            tapplet = %self.config.name,
+            version = %self.config.version
+        );
+        let started = Instant::now();
+        reporter.report(InstallProgress::Started {
+            name: self.config.name.clone(),
+        });
+
+        self.config.ensure_host_api(&HOST_API_VERSION)?;
+        let target_path = self.options.install_dir(cache_directory, &self.config)?;
+        let existing = self.options.existing_install(&target_path, &self.config)?;
+        if existing == ExistingInstall::Keep {
+            return Ok(skipped_install(target_path, started, reporter));
         }
 
-        // Create the target directory
+        let wasm_source = self.wasm_module(reporter)?;
+        // Checked after building, so `[artifacts]` can list the built module
+        self.config.verify_artifacts(&self.path)?;
+        self.config.verify_assets(&self.path)?;
+        let module = std::fs::read(&wasm_source)
+            .with_context(|| format!("Failed to read {}", wasm_source.display()))?;
+        let warnings = build::check_exports(&self.config, &module)?;
+
+        // Only replace an existing install once the new one is known to be good
+        existing.clear(&target_path)?;
         std::fs::create_dir_all(&target_path).with_context(|| {
             format!(
                 "Failed to create target directory: {}",
                 target_path.display()
             )
         })?;
-
-        // Compile the code from rust to wasm32-unknown-unknown
-        println!("Compiling tapplet to WASM...");
-        let output = Command::new("cargo")
-            .current_dir(&self.path)
-            .args(["build", "--release", "--target", "wasm32-unknown-unknown"])
-            .output()
-            .context("Failed to execute cargo build. Is cargo installed?")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!("Failed to compile tapplet:\n{}", stderr);
-        }
-
-        println!("Compilation successful!");
-
-        // Find the compiled WASM file
-        // The WASM file should be in target/wasm32-unknown-unknown/release/
-        let wasm_target_dir = self
-            .path
-            .join("target")
-            .join("wasm32-unknown-unknown")
-            .join("release");
-
-        // Find .wasm files in the target directory
-        let wasm_files: Vec<_> = std::fs::read_dir(&wasm_target_dir)
-            .with_context(|| {
-                format!(
-                    "Failed to read WASM target directory: {}",
-                    wasm_target_dir.display()
-                )
-            })?
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| {
-                entry
-                    .path()
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                    .map(|ext| ext == "wasm")
-                    .unwrap_or(false)
-            })
-            .collect();
-
-        if wasm_files.is_empty() {
-            bail!(
-                "No WASM file found in target directory: {}",
-                wasm_target_dir.display()
-            );
-        }
-
-        // Use the first WASM file found (or we could use the package name to find the right one)
-        let wasm_source = wasm_files[0].path();
         let wasm_target = target_path.join(format!("{}.wasm", self.config.name));
 
-        println!(
-            "Copying WASM file: {} -> {}",
-            wasm_source.display(),
-            wasm_target.display()
-        );
+        reporter.report(InstallProgress::Copying {
+            source: wasm_source.clone(),
+            target: wasm_target.clone(),
+        });
         std::fs::copy(&wasm_source, &wasm_target).with_context(|| {
             format!(
                 "Failed to copy WASM file from {} to {}",
@@ -109,15 +149,138 @@ impl LocalFolderTapplet {
             )
         })?;
 
-        // Copy the manifest.toml
-        let manifest_source = self.path.join("manifest.toml");
-        let manifest_target = target_path.join("manifest.toml");
+        let optimization = if self.build_options.optimize {
+            reporter.report(InstallProgress::Optimizing {
+                path: wasm_target.clone(),
+            });
+            let optimization = build::optimize_module(&wasm_target, &self.build_options).and_then(
+                |optimization| {
+                    // Hosts check the installed module against a hash pinned in
+                    // `[artifacts]`, so it must be the hash of the optimized module
+                    let module = std::fs::read(&wasm_target)?;
+                    self.config
+                        .verify_entrypoint(RuntimeKind::Wasm, &wasm_target, &module)?;
+                    Ok(optimization)
+                },
+            );
+            if optimization.is_err() {
+                let _ = std::fs::remove_dir_all(&target_path);
+            }
+            Some(optimization?)
+        } else {
+            None
+        };
 
-        println!(
-            "Copying manifest: {} -> {}",
-            manifest_source.display(),
-            manifest_target.display()
-        );
+        let mut files = vec![wasm_target.clone()];
+        if self.build_options.precompile {
+            reporter.report(InstallProgress::Precompiling {
+                path: wasm_target.clone(),
+            });
+            // Written to the module cache rather than `target_path`, so it isn't
+            // one of the install's artifacts
+            let precompiled = precompile(&wasm_target, cache_directory);
+            if precompiled.is_err() {
+                let _ = std::fs::remove_dir_all(&target_path);
+            }
+            precompiled?;
+        }
+
+        files.extend(install_assets(
+            &self.path,
+            &self.config,
+            &target_path,
+            reporter,
+        )?);
+        files.push(install_manifest(
+            &self.path,
+            &self.config,
+            &target_path,
+            reporter,
+        )?);
+
+        let mut report = finished_install(target_path, files, started, reporter);
+        report.optimization = optimization;
+        report.warnings = warnings;
+        Ok(report)
+    }
+
+    /// The module to install: a declared entrypoint that already exists is a prebuilt
+    /// module, anything else is compiled from the Rust crate in the tapplet directory
+    pub(crate) fn wasm_module(&self, reporter: &dyn InstallReporter) -> Result<PathBuf> {
+        match self.config.entrypoint_path(RuntimeKind::Wasm, &self.path)? {
+            Some(path) if path.is_file() => Ok(path),
+            // Only an explicit entrypoint names the module to pick from the build output
+            _ => self.build(
+                reporter,
+                self.config
+                    .runtime
+                    .as_ref()
+                    .and_then(|runtime| runtime.entrypoint.as_deref())
+                    .map(Path::new),
+            ),
+        }
+    }
+
+    /// Compile the crate to `wasm32-unknown-unknown` and return the built module
+    fn build(&self, reporter: &dyn InstallReporter, entrypoint: Option<&Path>) -> Result<PathBuf> {
+        reporter.report(InstallProgress::Building);
+        build::cargo_build(&self.path, &self.build_options)?;
+
+        let built = build::built_module(&self.path, &self.build_options, entrypoint)?;
+        if !built.is_file() {
+            bail!(TappletError::ArtifactNotFound(format!(
+                "WASM module {} was not built",
+                built.display()
+            )));
+        }
+        Ok(built)
+    }
+}
+
+/// Compile the installed module with the default engine into the module cache of
+/// `cache_directory`, see [`BuildOptions::precompile`]
+#[cfg(feature = "host-core")]
+fn precompile(wasm_path: &Path, cache_directory: &Path) -> Result<()> {
+    let module_cache = crate::module_cache::ModuleCache::in_cache_directory(cache_directory);
+    crate::engine::precompile_file(
+        wasm_path,
+        module_cache.directory(),
+        module_cache.engine().as_ref(),
+    )?;
+    Ok(())
+}
+
+#[cfg(not(feature = "host-core"))]
+fn precompile(wasm_path: &Path, _cache_directory: &Path) -> Result<()> {
+    trace::warning!(
+        "Not precompiling {}: built without a WASM engine",
+        wasm_path.display()
+    );
+    Ok(())
+}
+
+/// Put the manifest into an installed tapplet's directory as `manifest.toml` and
+/// return its path.
+///
+/// TOML manifests are copied as they are, manifests in other formats are
+/// converted to canonical TOML.
+pub(crate) fn install_manifest(
+    source_dir: &Path,
+    config: &TappletManifest,
+    target_path: &Path,
+    reporter: &dyn InstallReporter,
+) -> Result<PathBuf> {
+    let manifest_source =
+        find_manifest_file(source_dir).ok_or_else(|| TappletError::ManifestNotFound {
+            path: source_dir.to_path_buf(),
+        })?;
+    let manifest_target = target_path.join("manifest.toml");
+
+    if manifest_source.extension().is_some_and(|ext| ext == "toml") {
+        reporter.report(InstallProgress::Copying {
+            source: manifest_source.clone(),
+            target: manifest_target.clone(),
+        });
         std::fs::copy(&manifest_source, &manifest_target).with_context(|| {
             format!(
                 "Failed to copy manifest from {} to {}",
@@ -125,11 +288,95 @@ impl LocalFolderTapplet {
                 manifest_target.display()
             )
         })?;
+    } else {
+        reporter.report(InstallProgress::ConvertingManifest {
+            source: manifest_source.clone(),
+            target: manifest_target.clone(),
+        });
+        std::fs::write(&manifest_target, config.to_canonical_toml()?).with_context(|| {
+            format!("Failed to write manifest to {}", manifest_target.display())
+        })?;
+    }
+    Ok(manifest_target)
+}
 
-        println!(
-            "Successfully installed tapplet to: {}",
-            target_path.display()
-        );
-        Ok(())
+/// Copy the files listed in `[assets]` into an installed tapplet's directory,
+/// keeping their relative paths, and return the copies. Checked with
+/// [`TappletManifest::verify_assets`] beforehand.
+pub(crate) fn install_assets(
+    source_dir: &Path,
+    config: &TappletManifest,
+    target_path: &Path,
+    reporter: &dyn InstallReporter,
+) -> Result<Vec<PathBuf>> {
+    let assets = &config.assets;
+    let mut files = Vec::new();
+    for file in assets.icon.iter().chain(&assets.screenshots) {
+        let source = source_dir.join(file);
+        let target = target_path.join(file);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        reporter.report(InstallProgress::Copying {
+            source: source.clone(),
+            target: target.clone(),
+        });
+        std::fs::copy(&source, &target).with_context(|| {
+            format!(
+                "Failed to copy asset from {} to {}",
+                source.display(),
+                target.display()
+            )
+        })?;
+        files.push(target);
+    }
+    Ok(files)
+}
+
+/// Report an install that found the tapplet already installed
+pub(crate) fn skipped_install(
+    installed_path: PathBuf,
+    started: Instant,
+    reporter: &dyn InstallReporter,
+) -> InstallReport {
+    trace::info!("Already installed at {}", installed_path.display());
+    reporter.report(InstallProgress::Done {
+        path: installed_path.clone(),
+        skipped: true,
+    });
+    InstallReport {
+        installed_path,
+        artifacts: Vec::new(),
+        skipped: true,
+        duration: started.elapsed(),
+        optimization: None,
+        warnings: Vec::new(),
+    }
+}
+
+/// Report an install that wrote `artifacts` into `installed_path`
+pub(crate) fn finished_install(
+    installed_path: PathBuf,
+    artifacts: Vec<PathBuf>,
+    started: Instant,
+    reporter: &dyn InstallReporter,
+) -> InstallReport {
+    trace::info!(
+        "Installed {} files to {}",
+        artifacts.len(),
+        installed_path.display()
+    );
+    reporter.report(InstallProgress::Done {
+        path: installed_path.clone(),
+        skipped: false,
+    });
+    InstallReport {
+        installed_path,
+        artifacts,
+        skipped: false,
+        duration: started.elapsed(),
+        optimization: None,
+        warnings: Vec::new(),
     }
 }
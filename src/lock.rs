@@ -0,0 +1,138 @@
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::manager::TappletSource;
+
+/// File name of the lock file kept in a [`crate::TappletManager`] cache directory
+pub const LOCK_FILE_NAME: &str = "tapplets.lock";
+
+/// Record of exactly what is installed, so a setup can be verified and reproduced
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LockFile {
+    #[serde(default)]
+    pub tapplets: Vec<LockedTapplet>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LockedTapplet {
+    /// Publisher-qualified name, `publisher/name`, so that tapplets of the same
+    /// name from different publishers are locked separately
+    pub name: String,
+    pub version: String,
+    /// Commit of the registry checkout the tapplet was installed from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry_revision: Option<String>,
+    /// Git revision the tapplet was cloned at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_rev: Option<String>,
+    /// Hex encoded SHA-256 of the installed WASM or Lua artifact
+    pub artifact_sha256: String,
+    pub source: TappletSource,
+}
+
+/// A difference between the lock file and what is actually installed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockMismatch {
+    /// Locked but not installed
+    Missing { name: String },
+    /// Installed but not locked
+    Unlocked { name: String },
+    VersionMismatch {
+        name: String,
+        locked: String,
+        installed: String,
+    },
+    ArtifactMismatch {
+        name: String,
+        locked: String,
+        installed: String,
+    },
+}
+
+impl fmt::Display for LockMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockMismatch::Missing { name } => write!(f, "{} is locked but not installed", name),
+            LockMismatch::Unlocked { name } => write!(f, "{} is installed but not locked", name),
+            LockMismatch::VersionMismatch {
+                name,
+                locked,
+                installed,
+            } => write!(
+                f,
+                "{} is locked at version {} but {} is installed",
+                name, locked, installed
+            ),
+            LockMismatch::ArtifactMismatch {
+                name,
+                locked,
+                installed,
+            } => write!(
+                f,
+                "{} artifact hash {} does not match locked hash {}",
+                name, installed, locked
+            ),
+        }
+    }
+}
+
+impl LockFile {
+    /// Load a lock file, returning an empty lock if it doesn't exist
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read lock file: {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse lock file: {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self).context("Failed to serialize lock file")?;
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to write lock file: {}", path.display()))
+    }
+
+    /// The entry for `name`, either publisher-qualified or a bare name matching
+    /// the first entry of that name
+    pub fn get(&self, name: &str) -> Option<&LockedTapplet> {
+        self.tapplets
+            .iter()
+            .find(|tapplet| names_match(&tapplet.name, name))
+    }
+
+    /// Add or replace the entry for a tapplet
+    pub fn upsert(&mut self, locked: LockedTapplet) {
+        self.remove(&locked.name);
+        self.tapplets.push(locked);
+        self.tapplets.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<LockedTapplet> {
+        let index = self
+            .tapplets
+            .iter()
+            .position(|tapplet| names_match(&tapplet.name, name))?;
+        Some(self.tapplets.remove(index))
+    }
+}
+
+/// Names match if they are the same tapplet name and, where both are qualified,
+/// the same publisher. Entries locked before names were qualified match any publisher.
+fn names_match(a: &str, b: &str) -> bool {
+    let split = |name: &str| match name.split_once('/') {
+        Some((publisher, name)) => (Some(publisher.to_string()), name.replace("-", "_")),
+        None => (None, name.replace("-", "_")),
+    };
+    let (a_publisher, a) = split(a);
+    let (b_publisher, b) = split(b);
+    let same_publisher = match (a_publisher, b_publisher) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    };
+    same_publisher && a == b
+}
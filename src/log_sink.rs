@@ -0,0 +1,175 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+#[cfg(feature = "host-core")]
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+/// Longest message a tapplet may log, in bytes. Longer messages are truncated.
+pub const MAX_LOG_MESSAGE_BYTES: usize = 8 * 1024;
+
+/// Severity of a [`LogRecord`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        };
+        write!(f, "{}", level)
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            other => Err(format!(
+                "unknown log level '{}', expected debug, info, warn or error",
+                other
+            )),
+        }
+    }
+}
+
+/// A line of output from a tapplet, e.g. from Lua's `print`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub tapplet: String,
+    pub level: LogLevel,
+    pub message: String,
+}
+
+/// Receives tapplet output and failures, so hosts can show them in their own logs
+pub trait LogSink: Send + Sync {
+    fn log(&self, record: LogRecord);
+}
+
+/// Collects log records in memory, useful for tests and developer tooling
+#[derive(Debug, Default)]
+pub struct MemoryLogSink {
+    records: Mutex<Vec<LogRecord>>,
+}
+
+impl MemoryLogSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Logged records, oldest first
+    pub fn records(&self) -> Vec<LogRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+}
+
+impl LogSink for MemoryLogSink {
+    fn log(&self, record: LogRecord) {
+        self.records.lock().unwrap().push(record);
+    }
+}
+
+/// Where a host sends its tapplet's log output: to its [`LogSink`] if it has one,
+/// otherwise through `tracing`. Without either, the output is dropped.
+/// Messages below the tapplet's level, [`LogLevel::Info`] by default, are dropped.
+/// Clones share the sink and level, so the host functions tapplets log through
+/// can hold one while the host changes them.
+#[cfg(feature = "host-core")]
+#[derive(Clone)]
+pub(crate) struct GuestLog {
+    tapplet: String,
+    sink: Arc<RwLock<Option<Arc<dyn LogSink>>>>,
+    level: Arc<RwLock<LogLevel>>,
+}
+
+#[cfg(feature = "host-core")]
+impl GuestLog {
+    pub fn new(tapplet: &str) -> Self {
+        Self {
+            tapplet: tapplet.to_string(),
+            sink: Arc::default(),
+            level: Arc::new(RwLock::new(LogLevel::Info)),
+        }
+    }
+
+    pub fn sink(&self) -> Option<Arc<dyn LogSink>> {
+        self.sink.read().unwrap().clone()
+    }
+
+    pub fn set_sink(&self, sink: Arc<dyn LogSink>) {
+        *self.sink.write().unwrap() = Some(sink);
+    }
+
+    pub fn level(&self) -> LogLevel {
+        *self.level.read().unwrap()
+    }
+
+    pub fn set_level(&self, level: LogLevel) {
+        *self.level.write().unwrap() = level;
+    }
+
+    /// Take over the sink and level of `other`, e.g. the log of the host this one
+    /// replaces
+    pub fn inherit(&self, other: &GuestLog) {
+        *self.sink.write().unwrap() = other.sink();
+        self.set_level(other.level());
+    }
+
+    pub fn log(&self, level: LogLevel, mut message: String) {
+        if level < self.level() {
+            return;
+        }
+        if message.len() > MAX_LOG_MESSAGE_BYTES {
+            let mut end = MAX_LOG_MESSAGE_BYTES;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+        }
+        let tapplet = &self.tapplet;
+        match self.sink() {
+            Some(sink) => sink.log(LogRecord {
+                tapplet: tapplet.clone(),
+                level,
+                message,
+            }),
+            #[cfg(feature = "tracing")]
+            None => match level {
+                LogLevel::Debug => {
+                    tracing::debug!(target: "tapplet", tapplet = %tapplet, "{}", message)
+                }
+                LogLevel::Info => {
+                    tracing::info!(target: "tapplet", tapplet = %tapplet, "{}", message)
+                }
+                LogLevel::Warn => {
+                    tracing::warn!(target: "tapplet", tapplet = %tapplet, "{}", message)
+                }
+                LogLevel::Error => {
+                    tracing::error!(target: "tapplet", tapplet = %tapplet, "{}", message)
+                }
+            },
+            // Without a sink or `tracing` there is nowhere to send it
+            #[cfg(not(feature = "tracing"))]
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,681 @@
+//! Conversion between JSON values and Lua values for the Lua host.
+//!
+//! Lua has a single table type, so JSON arrays and objects can't always be told
+//! apart on the way back. Sequences (`{1, 2, 3}`) and tables with only string keys
+//! are unambiguous. Everything else is resolved by [`TableConversion`], unless the
+//! script tagged the table with `minotari_json_array(t)` or `minotari_json_object(t)`.
+//!
+//! Luau numbers are doubles, so integers beyond 2^53 (e.g. large amounts in
+//! microMinotari) are passed to scripts as a [`BoxedInteger`] userdata instead.
+//!
+//! Values declared as `bytes` are passed to scripts as Lua strings holding the
+//! raw bytes, and strings a script returns where the manifest declares `bytes`
+//! are encoded as base64.
+//!
+//! Tables that contain themselves, or are nested deeper than [`MAX_TABLE_DEPTH`],
+//! fail to convert rather than overflow the host's stack.
+
+use std::collections::HashSet;
+use std::ffi::c_void;
+use std::fmt;
+
+use mlua::{FromLua, Lua, MetaMethod, Table, UserData, UserDataMethods, Value as LuaValue};
+use serde_json::Value;
+
+use crate::host::HostError;
+use crate::model::{ParamType, decode_base64, encode_base64};
+
+const ARRAY_TAG: &str = "minotari_json_array";
+const OBJECT_TAG: &str = "minotari_json_object";
+
+/// Sparse arrays are only padded with `null` while at least half their slots are set
+const MIN_SPARSE_DENSITY: usize = 2;
+
+/// Largest integer a Lua number represents exactly
+pub const MAX_SAFE_INTEGER: i64 = 1 << 53;
+
+/// Deepest nesting of tables converted to JSON, the same limit `serde_json`
+/// applies when parsing
+pub const MAX_TABLE_DEPTH: usize = 128;
+
+/// An integer in the `i64::MIN..=u64::MAX` range that doesn't fit in a Lua number.
+///
+/// Scripts create one with `minotari_int(value)`, from a number or a decimal string,
+/// and can add, subtract, compare and `tostring` them. Comparisons need both sides
+/// boxed, e.g. `minotari_int(limit) < amount`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BoxedInteger(i128);
+
+impl BoxedInteger {
+    pub fn new(value: i128) -> Option<Self> {
+        (i64::MIN as i128..=u64::MAX as i128)
+            .contains(&value)
+            .then_some(Self(value))
+    }
+
+    pub fn value(&self) -> i128 {
+        self.0
+    }
+
+    fn to_json(self) -> Value {
+        match i64::try_from(self.0) {
+            Ok(i) => Value::from(i),
+            Err(_) => Value::from(self.0 as u64),
+        }
+    }
+}
+
+impl fmt::Display for BoxedInteger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<u64> for BoxedInteger {
+    fn from(value: u64) -> Self {
+        Self(value as i128)
+    }
+}
+
+impl TryFrom<BoxedInteger> for u64 {
+    type Error = mlua::Error;
+
+    fn try_from(value: BoxedInteger) -> mlua::Result<Self> {
+        u64::try_from(value.0)
+            .map_err(|_| mlua::Error::runtime(format!("{} is not a valid amount", value)))
+    }
+}
+
+impl UserData for BoxedInteger {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| Ok(this.to_string()));
+        methods.add_meta_function(MetaMethod::Eq, |_, (a, b): (Self, Self)| Ok(a == b));
+        methods.add_meta_function(MetaMethod::Lt, |_, (a, b): (Self, Self)| Ok(a < b));
+        methods.add_meta_function(MetaMethod::Le, |_, (a, b): (Self, Self)| Ok(a <= b));
+        methods.add_meta_function(MetaMethod::Add, |_, (a, b): (Self, Self)| {
+            Self::new(a.0 + b.0).ok_or_else(|| mlua::Error::runtime("integer overflow"))
+        });
+        methods.add_meta_function(MetaMethod::Sub, |_, (a, b): (Self, Self)| {
+            Self::new(a.0 - b.0).ok_or_else(|| mlua::Error::runtime("integer overflow"))
+        });
+    }
+}
+
+impl FromLua for BoxedInteger {
+    fn from_lua(value: LuaValue, _: &Lua) -> mlua::Result<Self> {
+        let parsed = match &value {
+            LuaValue::Integer(i) => Some(*i as i128),
+            LuaValue::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER as f64 => {
+                Some(*n as i128)
+            }
+            LuaValue::String(s) => s.to_str()?.parse::<i128>().ok(),
+            LuaValue::UserData(ud) => return Ok(*ud.borrow::<Self>()?),
+            _ => None,
+        };
+        parsed.and_then(Self::new).ok_or_else(|| {
+            mlua::Error::runtime(format!("expected an integer, got {}", value.type_name()))
+        })
+    }
+}
+
+/// Convert an integer to a Lua number if it's exact, otherwise to a [`BoxedInteger`]
+pub(crate) fn integer_to_lua(lua: &Lua, value: i128) -> mlua::Result<LuaValue> {
+    if let Ok(i) = i32::try_from(value) {
+        return Ok(LuaValue::Integer(i));
+    }
+    if value.abs() <= MAX_SAFE_INTEGER as i128 {
+        return Ok(LuaValue::Number(value as f64));
+    }
+    let boxed = BoxedInteger::new(value)
+        .ok_or_else(|| mlua::Error::runtime(format!("Integer out of range: {}", value)))?;
+    lua.create_userdata(boxed).map(LuaValue::UserData)
+}
+
+/// How untagged tables that could be either a JSON array or object are converted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TableConversion {
+    /// Empty tables become `{}`, and tables with gaps or non-sequence keys become
+    /// objects with stringified integer keys, so no entries are lost
+    #[default]
+    PreferObject,
+    /// Empty tables become `[]` and sparse integer keys become arrays padded with
+    /// `null`. Tables mixing integer and string keys still become objects.
+    PreferArray,
+    /// Fail with an error on empty, sparse or mixed tables that aren't tagged
+    Strict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tag {
+    Array,
+    Object,
+}
+
+/// Register `minotari_json_array` and `minotari_json_object`, which tag a table so it
+/// always converts to that JSON type, e.g. `return minotari_json_array({})`, and
+/// `minotari_int`, which creates a [`BoxedInteger`]
+pub(crate) fn register_tags(lua: &Lua) -> mlua::Result<()> {
+    let int = lua.create_function(|_, value: BoxedInteger| Ok(value))?;
+    lua.globals().set("minotari_int", int)?;
+    for name in [ARRAY_TAG, OBJECT_TAG] {
+        let metatable = lua.create_table()?;
+        lua.set_named_registry_value(name, &metatable)?;
+        let tag = lua.create_function(move |_, table: Table| {
+            table.set_metatable(Some(metatable.clone()));
+            Ok(table)
+        })?;
+        lua.globals().set(name, tag)?;
+    }
+    Ok(())
+}
+
+fn tag_of(lua: &Lua, table: &Table) -> Option<Tag> {
+    let metatable = table.metatable()?;
+    [(ARRAY_TAG, Tag::Array), (OBJECT_TAG, Tag::Object)]
+        .into_iter()
+        .find(|(name, _)| {
+            // Not registered if the Lua state wasn't created by a host
+            lua.named_registry_value::<Table>(name)
+                .is_ok_and(|tagged| tagged == metatable)
+        })
+        .map(|(_, tag)| tag)
+}
+
+/// Convert a JSON value to a Lua value
+pub(crate) fn json_to_lua(lua: &Lua, value: &Value) -> Result<LuaValue, HostError> {
+    match value {
+        Value::Null => Ok(LuaValue::Nil),
+        Value::Bool(b) => Ok(LuaValue::Boolean(*b)),
+        Value::Number(n) => {
+            let integer = n
+                .as_i64()
+                .map(i128::from)
+                .or_else(|| n.as_u64().map(i128::from));
+            if let Some(i) = integer {
+                integer_to_lua(lua, i).map_err(|e| HostError::InvalidArguments(e.to_string()))
+            } else if let Some(f) = n.as_f64() {
+                Ok(LuaValue::Number(f))
+            } else {
+                Err(HostError::InvalidArguments(format!(
+                    "Unsupported number type: {}",
+                    n
+                )))
+            }
+        }
+        Value::String(s) => lua
+            .create_string(s)
+            .map(LuaValue::String)
+            .map_err(|e| HostError::InvalidArguments(e.to_string())),
+        Value::Array(arr) => {
+            let table = lua.create_table().map_err(|e| {
+                HostError::InvalidArguments(format!("Failed to create table: {}", e))
+            })?;
+            for (i, item) in arr.iter().enumerate() {
+                let lua_value = json_to_lua(lua, item)?;
+                table
+                    .set(i + 1, lua_value)
+                    .map_err(|e| HostError::InvalidArguments(e.to_string()))?;
+            }
+            Ok(LuaValue::Table(table))
+        }
+        Value::Object(obj) => {
+            let table = lua.create_table().map_err(|e| {
+                HostError::InvalidArguments(format!("Failed to create table: {}", e))
+            })?;
+            for (key, val) in obj {
+                let lua_value = json_to_lua(lua, val)?;
+                table
+                    .set(key.as_str(), lua_value)
+                    .map_err(|e| HostError::InvalidArguments(e.to_string()))?;
+            }
+            Ok(LuaValue::Table(table))
+        }
+    }
+}
+
+/// Convert a JSON value of the given type to a Lua value, decoding `bytes` into
+/// Lua strings. The value must have been coerced to the type first.
+pub(crate) fn typed_json_to_lua(
+    lua: &Lua,
+    value: &Value,
+    param_type: &ParamType,
+) -> Result<LuaValue, HostError> {
+    if !param_type.contains_bytes() {
+        return json_to_lua(lua, value);
+    }
+    let lua_error = |e: mlua::Error| HostError::InvalidArguments(e.to_string());
+    match (param_type, value) {
+        (ParamType::Bytes, Value::String(s)) => {
+            let bytes = decode_base64(s)
+                .ok_or_else(|| HostError::InvalidArguments(format!("invalid base64: {}", s)))?;
+            lua.create_string(bytes)
+                .map(LuaValue::String)
+                .map_err(lua_error)
+        }
+        (ParamType::Optional(inner), value) => typed_json_to_lua(lua, value, inner),
+        (ParamType::Array(item_type), Value::Array(items)) => {
+            let table = lua.create_table().map_err(lua_error)?;
+            for (i, item) in items.iter().enumerate() {
+                let lua_value = typed_json_to_lua(lua, item, item_type)?;
+                table.set(i + 1, lua_value).map_err(lua_error)?;
+            }
+            Ok(LuaValue::Table(table))
+        }
+        (ParamType::Object(fields), Value::Object(object)) => {
+            let table = lua.create_table().map_err(lua_error)?;
+            for (key, val) in object {
+                let lua_value = match fields.get(key) {
+                    Some(field_type) => typed_json_to_lua(lua, val, field_type)?,
+                    None => json_to_lua(lua, val)?,
+                };
+                table.set(key.as_str(), lua_value).map_err(lua_error)?;
+            }
+            Ok(LuaValue::Table(table))
+        }
+        (_, value) => json_to_lua(lua, value),
+    }
+}
+
+/// Convert a Lua value of the given type to a JSON value, encoding `bytes`,
+/// whether a string or an array of byte values, as base64
+pub(crate) fn typed_lua_to_json(
+    lua: &Lua,
+    value: &LuaValue,
+    param_type: &ParamType,
+    conversion: TableConversion,
+) -> Result<Value, HostError> {
+    if !param_type.contains_bytes() {
+        return lua_to_json(lua, value, conversion);
+    }
+    let value = encode_lua_bytes(lua, value.clone(), param_type, 0)
+        .map_err(|e| HostError::ExecutionError(e.to_string()))?;
+    let json = lua_to_json(lua, &value, conversion)?;
+    Ok(param_type.encode_bytes(json))
+}
+
+/// Replace the strings at the `bytes` positions of `value` with their base64
+/// encoding, copying tables rather than changing the script's own
+fn encode_lua_bytes(
+    lua: &Lua,
+    value: LuaValue,
+    param_type: &ParamType,
+    depth: usize,
+) -> mlua::Result<LuaValue> {
+    if !param_type.contains_bytes() {
+        return Ok(value);
+    }
+    if depth > MAX_TABLE_DEPTH {
+        return Err(mlua::Error::runtime(too_deep()));
+    }
+    match (param_type, value) {
+        (ParamType::Bytes, LuaValue::String(s)) => lua
+            .create_string(encode_base64(&s.as_bytes()))
+            .map(LuaValue::String),
+        (ParamType::Optional(inner), value) => encode_lua_bytes(lua, value, inner, depth + 1),
+        (ParamType::Array(item_type), LuaValue::Table(table)) => {
+            copy_table(lua, &table, |key, value| match key {
+                LuaValue::String(_) => Ok(value),
+                _ => encode_lua_bytes(lua, value, item_type, depth + 1),
+            })
+        }
+        (ParamType::Object(fields), LuaValue::Table(table)) => {
+            copy_table(lua, &table, |key, value| {
+                let field_type = match key {
+                    LuaValue::String(s) => s.to_str().ok().and_then(|s| fields.get(&*s)),
+                    _ => None,
+                };
+                match field_type {
+                    Some(field_type) => encode_lua_bytes(lua, value, field_type, depth + 1),
+                    None => Ok(value),
+                }
+            })
+        }
+        (_, value) => Ok(value),
+    }
+}
+
+/// A copy of `table`, including its tag, with each value passed through `convert`
+fn copy_table(
+    lua: &Lua,
+    table: &Table,
+    mut convert: impl FnMut(&LuaValue, LuaValue) -> mlua::Result<LuaValue>,
+) -> mlua::Result<LuaValue> {
+    let copy = lua.create_table()?;
+    for pair in table.pairs::<LuaValue, LuaValue>() {
+        let (key, value) = pair?;
+        let value = convert(&key, value)?;
+        copy.raw_set(key, value)?;
+    }
+    copy.set_metatable(table.metatable());
+    Ok(LuaValue::Table(copy))
+}
+
+/// Convert a Lua value to a JSON value
+pub(crate) fn lua_to_json(
+    lua: &Lua,
+    value: &LuaValue,
+    conversion: TableConversion,
+) -> Result<Value, HostError> {
+    value_to_json(lua, value, conversion, &mut HashSet::new())
+}
+
+/// [`lua_to_json`] inside the tables of `path`, which are being converted
+fn value_to_json(
+    lua: &Lua,
+    value: &LuaValue,
+    conversion: TableConversion,
+    path: &mut HashSet<*const c_void>,
+) -> Result<Value, HostError> {
+    match value {
+        LuaValue::Nil => Ok(Value::Null),
+        LuaValue::Boolean(b) => Ok(Value::Bool(*b)),
+        LuaValue::Integer(i) => Ok(Value::Number((*i).into())),
+        LuaValue::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER as f64 => {
+            Ok(Value::from(*n as i64))
+        }
+        LuaValue::Number(n) => {
+            if let Some(num) = serde_json::Number::from_f64(*n) {
+                Ok(Value::Number(num))
+            } else {
+                Err(HostError::ExecutionError(
+                    "Failed to convert Lua number to JSON".to_string(),
+                ))
+            }
+        }
+        LuaValue::String(s) => {
+            let str_val = s
+                .to_str()
+                .map_err(|e| HostError::ExecutionError(e.to_string()))?;
+            Ok(Value::String(str_val.to_string()))
+        }
+        LuaValue::Table(table) => {
+            let pointer = table.to_pointer();
+            if path.len() >= MAX_TABLE_DEPTH {
+                return Err(HostError::ExecutionError(too_deep()));
+            }
+            if !path.insert(pointer) {
+                return Err(HostError::ExecutionError(
+                    "Table contains itself and can't be converted to JSON".to_string(),
+                ));
+            }
+            let json = table_to_json(lua, table, conversion, path);
+            path.remove(&pointer);
+            json
+        }
+        LuaValue::UserData(ud) if ud.is::<BoxedInteger>() => ud
+            .borrow::<BoxedInteger>()
+            .map(|boxed| boxed.to_json())
+            .map_err(|e| HostError::ExecutionError(e.to_string())),
+        _ => Err(HostError::ExecutionError(format!(
+            "Unsupported Lua value type: {:?}",
+            value
+        ))),
+    }
+}
+
+fn table_to_json(
+    lua: &Lua,
+    table: &Table,
+    conversion: TableConversion,
+    path: &mut HashSet<*const c_void>,
+) -> Result<Value, HostError> {
+    // Split keys into array indices (positive integers) and everything else
+    let mut indices = Vec::new();
+    let mut fields = Vec::new();
+    for pair in table.pairs::<LuaValue, LuaValue>() {
+        let (key, val) = pair.map_err(|e| HostError::ExecutionError(e.to_string()))?;
+        let val = value_to_json(lua, &val, conversion, path)?;
+        match key {
+            LuaValue::Integer(i) if i >= 1 => indices.push((i as usize, val)),
+            LuaValue::Number(n) if n >= 1.0 && n.fract() == 0.0 && n < usize::MAX as f64 => {
+                indices.push((n as usize, val))
+            }
+            LuaValue::String(s) => fields.push((
+                s.to_str()
+                    .map_err(|e| HostError::ExecutionError(e.to_string()))?
+                    .to_string(),
+                val,
+            )),
+            LuaValue::Integer(i) => fields.push((i.to_string(), val)),
+            LuaValue::Number(n) => fields.push((n.to_string(), val)),
+            _ => {
+                return Err(HostError::ExecutionError(
+                    "Unsupported table key type".to_string(),
+                ));
+            }
+        }
+    }
+    indices.sort_by_key(|(index, _)| *index);
+
+    let max_index = indices.last().map_or(0, |(index, _)| *index);
+    let is_sequence = max_index == indices.len();
+    let is_dense = max_index <= indices.len() * MIN_SPARSE_DENSITY;
+
+    let as_array = match tag_of(lua, table) {
+        Some(Tag::Array) if !fields.is_empty() => {
+            return Err(HostError::ExecutionError(
+                "Table tagged as array has non-integer keys".to_string(),
+            ));
+        }
+        Some(Tag::Array) => {
+            if !is_dense {
+                return Err(HostError::ExecutionError(
+                    "Table tagged as array is too sparse".to_string(),
+                ));
+            }
+            true
+        }
+        Some(Tag::Object) => false,
+        None if indices.is_empty() && fields.is_empty() => match conversion {
+            TableConversion::PreferObject => false,
+            TableConversion::PreferArray => true,
+            TableConversion::Strict => return Err(ambiguous("Empty")),
+        },
+        None if fields.is_empty() && is_sequence => true,
+        None if fields.is_empty() => match conversion {
+            TableConversion::PreferObject => false,
+            TableConversion::PreferArray => is_dense,
+            TableConversion::Strict => return Err(ambiguous("Sparse")),
+        },
+        None if indices.is_empty() => false,
+        None => match conversion {
+            TableConversion::Strict => return Err(ambiguous("Mixed")),
+            _ => false,
+        },
+    };
+
+    if as_array {
+        let mut arr = vec![Value::Null; max_index];
+        for (index, val) in indices {
+            arr[index - 1] = val;
+        }
+        Ok(Value::Array(arr))
+    } else {
+        let mut obj = serde_json::Map::new();
+        for (index, val) in indices {
+            obj.insert(index.to_string(), val);
+        }
+        obj.extend(fields);
+        Ok(Value::Object(obj))
+    }
+}
+
+fn too_deep() -> String {
+    format!("Tables are nested deeper than {} levels", MAX_TABLE_DEPTH)
+}
+
+fn ambiguous(kind: &str) -> HostError {
+    HostError::ExecutionError(format!(
+        "{} table is ambiguous, tag it with minotari_json_array or minotari_json_object",
+        kind
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn convert(code: &str, conversion: TableConversion) -> Result<Value, HostError> {
+        let lua = Lua::new();
+        register_tags(&lua).unwrap();
+        let value: LuaValue = lua.load(code).eval().unwrap();
+        lua_to_json(&lua, &value, conversion)
+    }
+
+    #[test]
+    fn test_typed_bytes() {
+        let lua = Lua::new();
+        register_tags(&lua).unwrap();
+        let param_type: ParamType = "object{key: bytes, keys: bytes[], name: string}"
+            .parse()
+            .unwrap();
+        let args = json!({"key": "AP8=", "keys": ["AQ=="], "name": "AP8="});
+        let value = typed_json_to_lua(&lua, &args, &param_type).unwrap();
+        lua.globals().set("args", value).unwrap();
+        let (key, first, name): (mlua::String, mlua::String, String) = lua
+            .load("return args.key, args.keys[1], args.name")
+            .eval()
+            .unwrap();
+        assert_eq!(*key.as_bytes(), [0, 255]);
+        assert_eq!(*first.as_bytes(), [1]);
+        assert_eq!(name, "AP8=");
+
+        // Raw strings, even if not UTF-8, and arrays of byte values are encoded
+        let result: LuaValue = lua
+            .load(r#"return minotari_json_object({key = "\0\255", keys = {{1, 2}}, name = "x"})"#)
+            .eval()
+            .unwrap();
+        assert_eq!(
+            typed_lua_to_json(&lua, &result, &param_type, TableConversion::Strict).unwrap(),
+            json!({"key": "AP8=", "keys": ["AQI="], "name": "x"})
+        );
+    }
+
+    #[test]
+    fn test_unambiguous_tables() {
+        for conversion in [
+            TableConversion::PreferObject,
+            TableConversion::PreferArray,
+            TableConversion::Strict,
+        ] {
+            assert_eq!(convert("{1, 2, 3}", conversion).unwrap(), json!([1, 2, 3]));
+            assert_eq!(convert("{a = 1}", conversion).unwrap(), json!({"a": 1}));
+            assert_eq!(
+                convert("minotari_json_array({})", conversion).unwrap(),
+                json!([])
+            );
+            assert_eq!(
+                convert("minotari_json_object({1, 2})", conversion).unwrap(),
+                json!({"1": 1, "2": 2})
+            );
+        }
+    }
+
+    #[test]
+    fn test_ambiguous_tables() {
+        let prefer_object = TableConversion::PreferObject;
+        assert_eq!(convert("{}", prefer_object).unwrap(), json!({}));
+        assert_eq!(
+            convert("{[1] = 'a', [3] = 'c'}", prefer_object).unwrap(),
+            json!({"1": "a", "3": "c"})
+        );
+        // Hash keys used to be dropped when the table also had an array part
+        assert_eq!(
+            convert("{'a', b = 2}", prefer_object).unwrap(),
+            json!({"1": "a", "b": 2})
+        );
+
+        let prefer_array = TableConversion::PreferArray;
+        assert_eq!(convert("{}", prefer_array).unwrap(), json!([]));
+        assert_eq!(
+            convert("{[1] = 'a', [3] = 'c'}", prefer_array).unwrap(),
+            json!(["a", null, "c"])
+        );
+        assert_eq!(
+            convert("{[1000] = 'far'}", prefer_array).unwrap(),
+            json!({"1000": "far"})
+        );
+
+        for code in ["{}", "{[1] = 'a', [3] = 'c'}", "{'a', b = 2}"] {
+            assert!(convert(code, TableConversion::Strict).is_err());
+        }
+    }
+
+    #[test]
+    fn test_self_referencing_table() {
+        let err = convert("local t = {} t.me = t return t", TableConversion::default());
+        assert!(err.unwrap_err().to_string().contains("contains itself"));
+        let err = convert(
+            "local t = {} t[1] = {t} return t",
+            TableConversion::default(),
+        );
+        assert!(err.unwrap_err().to_string().contains("contains itself"));
+
+        // The same table twice, but not inside itself, is fine
+        assert_eq!(
+            convert(
+                "local t = {1} return {a = t, b = t}",
+                TableConversion::default()
+            )
+            .unwrap(),
+            json!({"a": [1], "b": [1]})
+        );
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let nested = |depth: usize| {
+            format!(
+                "local t = {{}} for _ = 2, {} do t = {{t}} end return t",
+                depth
+            )
+        };
+        assert!(convert(&nested(MAX_TABLE_DEPTH), TableConversion::PreferArray).is_ok());
+        let err = convert(&nested(MAX_TABLE_DEPTH + 1), TableConversion::PreferArray);
+        assert!(err.unwrap_err().to_string().contains("nested deeper"));
+        let err = convert(&nested(100_000), TableConversion::PreferArray);
+        assert!(err.unwrap_err().to_string().contains("nested deeper"));
+    }
+
+    #[test]
+    fn test_large_integers_round_trip() {
+        let lua = Lua::new();
+        register_tags(&lua).unwrap();
+        let double = lua
+            .load("return function(x) return x + x end")
+            .eval::<mlua::Function>()
+            .unwrap();
+        let roundtrip = |value: Value| {
+            let arg = json_to_lua(&lua, &value).unwrap();
+            let result: LuaValue = double.call(arg).unwrap();
+            lua_to_json(&lua, &result, TableConversion::default()).unwrap()
+        };
+
+        // 5000 Minotari in microMinotari, beyond i32
+        assert_eq!(roundtrip(json!(5_000_000_000u64)), json!(10_000_000_000u64));
+        assert_eq!(roundtrip(json!(1.25)), json!(2.5));
+        // Beyond 2^53, boxed so no precision is lost
+        assert_eq!(
+            roundtrip(json!(10_000_000_000_000_001u64)),
+            json!(20_000_000_000_000_002u64)
+        );
+        // Results beyond i64 still fit the u64 amounts used by the wallet
+        assert_eq!(roundtrip(json!((1u64 << 62) + 1)), json!((1u64 << 63) + 2));
+
+        let parsed: Value = lua_to_json(
+            &lua,
+            &lua.load(r#"return tostring(minotari_int("18446744073709551615"))"#)
+                .eval()
+                .unwrap(),
+            TableConversion::default(),
+        )
+        .unwrap();
+        assert_eq!(parsed, json!("18446744073709551615"));
+        assert!(
+            lua.load(r#"return minotari_int("18446744073709551615") + 1"#)
+                .exec()
+                .is_err()
+        );
+    }
+}
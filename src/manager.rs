@@ -0,0 +1,1402 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::TappletManifest;
+use crate::checksum::sha256_file;
+use crate::error::TappletError;
+use crate::git_tapplet::GitTapplet;
+use crate::install::{self, InstallOptions};
+use crate::local_folder_lua_tapplet::{LocalFolderLuaTapplet, tapplet_dir_runtime};
+use crate::local_folder_tapplet::LocalFolderTapplet;
+use crate::lock::{LOCK_FILE_NAME, LockFile, LockMismatch, LockedTapplet};
+use crate::model::{RuntimeKind, find_manifest_file};
+use crate::package::{self, Package};
+use crate::registry::{NoProgress, TappletRegistry};
+use crate::resolver;
+use crate::trust::TrustPolicy;
+
+#[cfg(feature = "host-core")]
+use crate::call_context::CallContext;
+#[cfg(feature = "host-core")]
+use crate::host::{
+    HostError, LuaTappletHost, MinotariTappletApiV1, TappletRunner, WasmTappletHost,
+};
+#[cfg(feature = "host-core")]
+use crate::host_options::HostOptions;
+#[cfg(feature = "host-core")]
+use crate::host_pool::{HostPool, PooledHost};
+#[cfg(feature = "host-core")]
+use crate::module_cache::ModuleCache;
+use crate::trace;
+#[cfg(feature = "host-core")]
+use std::sync::Arc;
+#[cfg(feature = "host-core")]
+use std::time::Duration;
+
+/// Name of the file written next to an installed tapplet recording where it came from
+const SOURCE_FILE_NAME: &str = "source.toml";
+
+/// Directory in the cache directory where reinstalls are staged
+const STAGING_DIR: &str = ".staging";
+
+/// Where a tapplet should be installed from
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TappletSource {
+    /// A local folder containing a Rust tapplet that is compiled to WASM
+    LocalWasm { path: PathBuf },
+    /// A local folder containing a Lua tapplet
+    LocalLua { path: PathBuf },
+    /// A tapplet cloned from the git repository declared in its manifest
+    Git { manifest: Box<TappletManifest> },
+    /// A tapplet directory inside an already fetched registry
+    Registry { registry: String, path: PathBuf },
+    /// A `.tapplet` archive, see [`crate::package`]
+    Package { path: PathBuf },
+}
+
+impl TappletSource {
+    /// Resolve the newest version of a tapplet, prereleases excluded, in a loaded
+    /// registry. See [`TappletRegistry::latest`].
+    ///
+    /// `name` may be a qualified `publisher/name`, and has to be when several
+    /// publishers have a tapplet of that name.
+    pub fn from_registry(registry: &TappletRegistry, name: &str) -> Result<Self> {
+        let context = || format!("Failed to resolve in registry '{}'", registry.name);
+        let manifest = match registry.qualify(name).with_context(context)? {
+            Some(qualified) => registry.latest(&qualified)?,
+            None => None,
+        };
+        let manifest = manifest
+            .ok_or_else(|| TappletError::TappletNotFound {
+                name: name.to_string(),
+            })
+            .with_context(context)?;
+        Ok(TappletSource::Registry {
+            registry: registry.name.clone(),
+            path: registry.tapplet_dir(manifest),
+        })
+    }
+
+    /// Read the manifest of the tapplet this source would install
+    pub fn manifest(&self) -> Result<TappletManifest> {
+        match self {
+            TappletSource::Git { manifest } => Ok(manifest.as_ref().clone()),
+            TappletSource::Package { path } => Ok(Package::read(path)?.manifest),
+            TappletSource::LocalWasm { path }
+            | TappletSource::LocalLua { path }
+            | TappletSource::Registry { path, .. } => {
+                let manifest_file =
+                    find_manifest_file(path).ok_or_else(|| TappletError::ManifestNotFound {
+                        path: path.to_path_buf(),
+                    })?;
+                TappletManifest::from_file(&manifest_file)
+                    .with_context(|| format!("Failed to read {}", manifest_file.display()))
+            }
+        }
+    }
+}
+
+/// One step of an [`InstallPlan`]
+#[derive(Debug, Clone)]
+pub enum InstallStep {
+    /// The installed version already satisfies every requirement on it
+    Keep { name: String, version: String },
+    Install {
+        manifest: TappletManifest,
+        source: TappletSource,
+    },
+    /// Replace an installed version that doesn't satisfy the requirements
+    Replace {
+        installed_version: String,
+        manifest: TappletManifest,
+        source: TappletSource,
+    },
+}
+
+/// What [`TappletManager::install_with_dependencies`] will do, dependencies first
+#[derive(Debug, Clone)]
+pub struct InstallPlan {
+    pub steps: Vec<InstallStep>,
+}
+
+/// A tapplet that is present in the manager's cache directory
+#[derive(Debug, Clone)]
+pub struct InstalledTapplet {
+    pub manifest: TappletManifest,
+    pub path: PathBuf,
+    pub source: Option<TappletSource>,
+}
+
+impl InstalledTapplet {
+    /// The runtime declared in the manifest, or else the one whose artifact is installed
+    pub fn runtime(&self) -> Option<RuntimeKind> {
+        match &self.manifest.runtime {
+            Some(runtime) => Some(runtime.kind),
+            None => [RuntimeKind::Wasm, RuntimeKind::Lua]
+                .into_iter()
+                .find(|kind| self.installed_artifact(*kind).exists()),
+        }
+    }
+
+    /// Path of the WASM artifact, if this tapplet was installed as WASM
+    pub fn wasm_path(&self) -> Option<PathBuf> {
+        self.artifact_for(RuntimeKind::Wasm)
+    }
+
+    /// Path of the Lua script, if this tapplet was installed as Lua
+    pub fn lua_path(&self) -> Option<PathBuf> {
+        self.artifact_for(RuntimeKind::Lua)
+    }
+
+    /// Path of the installed icon, if the manifest declares one in `[assets]`
+    pub fn icon_path(&self) -> Option<PathBuf> {
+        let icon = self.manifest.assets.icon.as_ref()?;
+        let path = self.path.join(icon);
+        path.is_file().then_some(path)
+    }
+
+    /// Paths of the installed screenshots, in display order
+    pub fn screenshot_paths(&self) -> Vec<PathBuf> {
+        self.manifest
+            .assets
+            .screenshots
+            .iter()
+            .map(|screenshot| self.path.join(screenshot))
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    fn artifact_for(&self, kind: RuntimeKind) -> Option<PathBuf> {
+        let path = self.installed_artifact(kind);
+        (self.runtime() == Some(kind) && path.exists()).then_some(path)
+    }
+
+    /// Installers copy the entrypoint to `<name>.wasm` or `<name>.lua`
+    fn installed_artifact(&self, kind: RuntimeKind) -> PathBuf {
+        self.path
+            .join(format!("{}.{}", self.manifest.name, kind.extension()))
+    }
+
+    /// Path of the installed WASM or Lua artifact
+    pub fn artifact_path(&self) -> Option<PathBuf> {
+        self.wasm_path().or_else(|| self.lua_path())
+    }
+
+    /// Hex encoded SHA-256 of the installed artifact
+    pub fn artifact_sha256(&self) -> Result<String> {
+        let path = self.artifact_path().ok_or_else(|| {
+            TappletError::ArtifactNotFound(format!(
+                "no WASM or Lua artifact for tapplet '{}' in {}",
+                self.manifest.name,
+                self.path.display()
+            ))
+        })?;
+        sha256_file(&path)
+    }
+}
+
+/// A host constructed for an installed tapplet
+#[cfg(feature = "host-core")]
+pub enum InstalledHost<T: ?Sized> {
+    Wasm(WasmTappletHost),
+    Lua(LuaTappletHost<T>),
+}
+
+#[cfg(feature = "host-core")]
+impl<T: MinotariTappletApiV1 + ?Sized + 'static> InstalledHost<T> {
+    /// See [`WasmTappletHost::check_health`] and [`LuaTappletHost::check_health`]
+    pub fn check_health(&mut self, timeout: Duration) -> Result<bool, HostError> {
+        match self {
+            InstalledHost::Wasm(host) => host.check_health(timeout),
+            InstalledHost::Lua(host) => host.check_health(timeout),
+        }
+    }
+
+    /// Memory the tapplet uses, in bytes
+    pub fn memory_usage(&mut self) -> u64 {
+        match self {
+            InstalledHost::Wasm(host) => host.memory_usage(),
+            InstalledHost::Lua(host) => host.memory_usage(),
+        }
+    }
+}
+
+#[cfg(feature = "host-core")]
+#[async_trait::async_trait(?Send)]
+impl<T: MinotariTappletApiV1 + ?Sized + 'static> TappletRunner for InstalledHost<T> {
+    fn manifest(&self) -> &TappletManifest {
+        match self {
+            InstalledHost::Wasm(host) => host.manifest(),
+            InstalledHost::Lua(host) => host.manifest(),
+        }
+    }
+
+    async fn call(
+        &mut self,
+        method: &str,
+        args: serde_json::Value,
+        context: &CallContext,
+    ) -> Result<serde_json::Value, HostError> {
+        match self {
+            InstalledHost::Wasm(host) => host.call(method, args, context).await,
+            InstalledHost::Lua(host) => host.call(method, args, context).await,
+        }
+    }
+}
+
+fn ensure_not_yanked(manifest: &TappletManifest) -> Result<()> {
+    if manifest.yanked {
+        bail!(TappletError::Yanked {
+            name: manifest.name.clone(),
+            version: manifest.version.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Owns a cache directory of installed tapplets and manages their lifecycle
+pub struct TappletManager {
+    cache_directory: PathBuf,
+    trust_policy: TrustPolicy,
+    install_options: InstallOptions,
+}
+
+impl TappletManager {
+    pub fn new(cache_directory: PathBuf) -> Self {
+        Self {
+            cache_directory,
+            trust_policy: TrustPolicy::default(),
+            install_options: InstallOptions::default(),
+        }
+    }
+
+    /// Install with these options, e.g. into [`InstallOptions::publisher_dirs`]
+    pub fn with_install_options(mut self, install_options: InstallOptions) -> Self {
+        self.install_options = install_options;
+        self
+    }
+
+    /// Refuse to install tapplets that the policy doesn't trust
+    pub fn with_trust_policy(mut self, trust_policy: TrustPolicy) -> Self {
+        self.trust_policy = trust_policy;
+        self
+    }
+
+    pub fn trust_policy(&self) -> &TrustPolicy {
+        &self.trust_policy
+    }
+
+    pub fn cache_directory(&self) -> &Path {
+        &self.cache_directory
+    }
+
+    /// Install a tapplet from the given source and return the installed entry.
+    ///
+    /// Fails with [`TappletError::Yanked`] for yanked tapplets, see [`Self::force_install`].
+    pub fn install(&self, source: TappletSource) -> Result<InstalledTapplet> {
+        ensure_not_yanked(&source.manifest()?)?;
+        self.force_install(source)
+    }
+
+    /// Install a tapplet like [`Self::install`], even if it has been yanked.
+    /// The trust policy still applies.
+    pub fn force_install(&self, source: TappletSource) -> Result<InstalledTapplet> {
+        let installed = self.install_into(&self.cache_directory, &source)?;
+        self.lock(&installed, source)?;
+        Ok(installed)
+    }
+
+    /// Install `source` into `cache_directory` and record where it came from,
+    /// without touching the lock file
+    fn install_into(
+        &self,
+        cache_directory: &Path,
+        source: &TappletSource,
+    ) -> Result<InstalledTapplet> {
+        let manifest = source.manifest()?;
+        self.trust_policy.ensure_trusted(&manifest)?;
+        if let Some(deprecation) = &manifest.deprecated {
+            trace::warning!(
+                "Installing {}, which is {}",
+                manifest.canonical_name(),
+                deprecation
+            );
+        }
+
+        let options = self.install_options;
+        let install_dir = match &source {
+            TappletSource::LocalWasm { path } => {
+                let tapplet = LocalFolderTapplet::load(path.clone())?.with_install_options(options);
+                tapplet.install_blocking(cache_directory, &NoProgress)?;
+                options.install_dir(cache_directory, &tapplet.config)?
+            }
+            TappletSource::LocalLua { path } => {
+                let tapplet =
+                    LocalFolderLuaTapplet::load(path.clone())?.with_install_options(options);
+                tapplet.install_blocking(cache_directory, &NoProgress)?;
+                options.install_dir(cache_directory, &tapplet.config)?
+            }
+            TappletSource::Git { manifest } => {
+                let tapplet =
+                    GitTapplet::new(manifest.as_ref().clone())?.with_install_options(options);
+                tapplet.install_blocking(cache_directory, &NoProgress)?;
+                options.install_dir(cache_directory, manifest)?
+            }
+            TappletSource::Package { path } => {
+                let manifest = package::install_with_options(
+                    path,
+                    cache_directory,
+                    &TrustPolicy::default(),
+                    &options,
+                )?;
+                options.install_dir(cache_directory, &manifest)?
+            }
+            TappletSource::Registry { path, .. } => {
+                // Registry entries carry their sources; Lua tapplets ship a script,
+                // WASM tapplets a Rust crate or prebuilt module
+                if tapplet_dir_runtime(path)? == RuntimeKind::Lua {
+                    let tapplet =
+                        LocalFolderLuaTapplet::load(path.clone())?.with_install_options(options);
+                    tapplet.install_blocking(cache_directory, &NoProgress)?;
+                    options.install_dir(cache_directory, &tapplet.config)?
+                } else {
+                    let tapplet =
+                        LocalFolderTapplet::load(path.clone())?.with_install_options(options);
+                    tapplet.install_blocking(cache_directory, &NoProgress)?;
+                    options.install_dir(cache_directory, &tapplet.config)?
+                }
+            }
+        };
+
+        let source_toml = toml::to_string(&source).context("Failed to serialize tapplet source")?;
+        std::fs::write(install_dir.join(SOURCE_FILE_NAME), source_toml).with_context(|| {
+            format!(
+                "Failed to record tapplet source in {}",
+                install_dir.display()
+            )
+        })?;
+
+        install::list_installed(cache_directory)?
+            .into_iter()
+            .find(|tapplet| tapplet.path == install_dir)
+            .with_context(|| format!("Tapplet '{}' was not installed", manifest.name))
+    }
+
+    /// Install `source` into a staging directory, run `check` on it and only then
+    /// replace the installed versions of its tapplet with it, so a failed reinstall
+    /// leaves the installed tapplet as it was. The lock file isn't touched.
+    fn reinstall(
+        &self,
+        source: &TappletSource,
+        check: impl FnOnce(&InstalledTapplet) -> Result<()>,
+    ) -> Result<InstalledTapplet> {
+        let staging = self.clear_staging()?;
+        let result = self.install_into(&staging, source).and_then(|staged| {
+            check(&staged)?;
+            self.commit_staged(&staged)
+        });
+        let _ = std::fs::remove_dir_all(&staging);
+        result
+    }
+
+    /// The empty directory installs are staged in
+    fn clear_staging(&self) -> Result<PathBuf> {
+        let staging = self.cache_directory.join(STAGING_DIR);
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)
+                .with_context(|| format!("Failed to remove {}", staging.display()))?;
+        }
+        Ok(staging)
+    }
+
+    /// Replace the installed versions of a tapplet, from the same publisher, with
+    /// the tapplet installed into a staging directory
+    fn commit_staged(&self, staged: &InstalledTapplet) -> Result<InstalledTapplet> {
+        let name = staged.manifest.qualified_name();
+        let target = self
+            .install_options
+            .install_dir(&self.cache_directory, &staged.manifest)?;
+        if self.get_installed(&name)?.is_some() {
+            install::uninstall(&name, &self.cache_directory)?;
+        }
+        if target.exists() {
+            std::fs::remove_dir_all(&target)
+                .with_context(|| format!("Failed to remove {}", target.display()))?;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(&staged.path, &target)
+            .with_context(|| format!("Failed to move tapplet to {}", target.display()))?;
+        read_installed(target)
+    }
+
+    /// Work out which tapplets installing `name` from a registry involves: the newest
+    /// version matching `version_req` and its dependencies, transitively.
+    ///
+    /// Fails if the dependencies form a cycle or require conflicting versions.
+    pub fn plan_install(
+        &self,
+        registry: &TappletRegistry,
+        name: &str,
+        version_req: &str,
+    ) -> Result<InstallPlan> {
+        let installed = self.list_installed()?;
+        let manifests: Vec<_> = installed
+            .iter()
+            .map(|tapplet| tapplet.manifest.clone())
+            .collect();
+        let resolved = resolver::resolve(registry, &manifests, name, version_req)?;
+
+        let steps = resolved
+            .into_iter()
+            .map(|resolved| {
+                let manifest = resolved.manifest;
+                if resolved.installed {
+                    return InstallStep::Keep {
+                        name: manifest.name,
+                        version: manifest.version,
+                    };
+                }
+                let source = TappletSource::Registry {
+                    registry: registry.name.clone(),
+                    path: registry.tapplet_dir(&manifest),
+                };
+                match installed
+                    .iter()
+                    .find(|tapplet| tapplet.manifest.name_matches(&manifest.qualified_name()))
+                {
+                    Some(tapplet) => InstallStep::Replace {
+                        installed_version: tapplet.manifest.version.clone(),
+                        manifest,
+                        source,
+                    },
+                    None => InstallStep::Install { manifest, source },
+                }
+            })
+            .collect();
+        Ok(InstallPlan { steps })
+    }
+
+    /// Install a tapplet from a registry with its dependencies, following
+    /// [`TappletManager::plan_install`]. Returns the tapplets that were installed.
+    ///
+    /// Nothing is installed unless the trust policy accepts every tapplet in the plan.
+    /// Every install and replacement is staged first, so installed versions are
+    /// only replaced once the whole plan installed.
+    pub fn install_with_dependencies(
+        &self,
+        registry: &TappletRegistry,
+        name: &str,
+        version_req: &str,
+    ) -> Result<Vec<InstalledTapplet>> {
+        let plan = self.plan_install(registry, name, version_req)?;
+        for step in &plan.steps {
+            if let InstallStep::Install { manifest, .. } | InstallStep::Replace { manifest, .. } =
+                step
+            {
+                self.trust_policy.ensure_trusted(manifest)?;
+            }
+        }
+
+        let staging = self.clear_staging()?;
+        let result = self.install_plan(&staging, plan);
+        let _ = std::fs::remove_dir_all(&staging);
+        result
+    }
+
+    /// Install the steps of `plan` into `staging`, then move them into place
+    fn install_plan(&self, staging: &Path, plan: InstallPlan) -> Result<Vec<InstalledTapplet>> {
+        let mut staged = Vec::new();
+        for (index, step) in plan.steps.into_iter().enumerate() {
+            let (InstallStep::Install { manifest, source }
+            | InstallStep::Replace {
+                manifest, source, ..
+            }) = step
+            else {
+                continue;
+            };
+            ensure_not_yanked(&manifest)?;
+            let tapplet = self.install_into(&staging.join(index.to_string()), &source)?;
+            staged.push((tapplet, source));
+        }
+
+        let mut installed = Vec::new();
+        for (tapplet, source) in staged {
+            let tapplet = self.commit_staged(&tapplet)?;
+            self.lock(&tapplet, source)?;
+            installed.push(tapplet);
+        }
+        Ok(installed)
+    }
+
+    /// Path of the lock file recording installed tapplets
+    pub fn lock_file_path(&self) -> PathBuf {
+        self.cache_directory.join(LOCK_FILE_NAME)
+    }
+
+    pub fn load_lock_file(&self) -> Result<LockFile> {
+        LockFile::load(&self.lock_file_path())
+    }
+
+    /// Compare installed tapplets against the lock file
+    pub fn verify_lock(&self) -> Result<Vec<LockMismatch>> {
+        let lock = self.load_lock_file()?;
+        let installed = self.list_installed()?;
+        let mut mismatches = Vec::new();
+
+        for locked in &lock.tapplets {
+            let Some(tapplet) = installed
+                .iter()
+                .find(|tapplet| tapplet.manifest.name_matches(&locked.name))
+            else {
+                mismatches.push(LockMismatch::Missing {
+                    name: locked.name.clone(),
+                });
+                continue;
+            };
+            if tapplet.manifest.version != locked.version {
+                mismatches.push(LockMismatch::VersionMismatch {
+                    name: locked.name.clone(),
+                    locked: locked.version.clone(),
+                    installed: tapplet.manifest.version.clone(),
+                });
+            }
+            let artifact_sha256 = tapplet.artifact_sha256()?;
+            if artifact_sha256 != locked.artifact_sha256 {
+                mismatches.push(LockMismatch::ArtifactMismatch {
+                    name: locked.name.clone(),
+                    locked: locked.artifact_sha256.clone(),
+                    installed: artifact_sha256,
+                });
+            }
+        }
+
+        for tapplet in &installed {
+            if lock.get(&tapplet.manifest.qualified_name()).is_none() {
+                mismatches.push(LockMismatch::Unlocked {
+                    name: tapplet.manifest.qualified_name(),
+                });
+            }
+        }
+
+        Ok(mismatches)
+    }
+
+    /// Reinstall every tapplet in the lock file that is missing or differs from the lock.
+    ///
+    /// Fails if a reinstalled tapplet doesn't reproduce the locked version and artifact.
+    /// Locked versions are reinstalled even if they have been yanked since.
+    pub fn install_from_lock(&self) -> Result<Vec<InstalledTapplet>> {
+        let lock = self.load_lock_file()?;
+        let mut reinstalled = Vec::new();
+
+        for locked in &lock.tapplets {
+            if let Some(tapplet) = self.get_installed(&locked.name)?
+                && tapplet.manifest.version == locked.version
+                && tapplet.artifact_sha256()? == locked.artifact_sha256
+            {
+                continue;
+            }
+
+            // Checked before the install replaces anything, and the lock entry is
+            // already the one to keep, so the lock file is left as it is
+            let installed = self.reinstall(&locked.source, |staged| {
+                let artifact_sha256 = staged.artifact_sha256()?;
+                if staged.manifest.version != locked.version
+                    || artifact_sha256 != locked.artifact_sha256
+                {
+                    bail!(TappletError::LockMismatch(format!(
+                        "reinstalling '{}' produced {}@{} with artifact {}, but the lock requires {}@{} with artifact {}",
+                        locked.name,
+                        staged.manifest.name,
+                        staged.manifest.version,
+                        artifact_sha256,
+                        locked.name,
+                        locked.version,
+                        locked.artifact_sha256
+                    )));
+                }
+                Ok(())
+            })?;
+            reinstalled.push(installed);
+        }
+
+        Ok(reinstalled)
+    }
+
+    /// Record an installed tapplet in the lock file
+    fn lock(&self, installed: &InstalledTapplet, source: TappletSource) -> Result<()> {
+        let registry_revision = match &source {
+            TappletSource::Registry { path, .. } => git2::Repository::discover(path)
+                .ok()
+                .and_then(|repo| Some(repo.head().ok()?.peel_to_commit().ok()?.id()))
+                .map(|id| id.to_string()),
+            _ => None,
+        };
+        let git_rev = match &source {
+            TappletSource::Git { manifest } => manifest.git.as_ref().map(|git| git.rev.clone()),
+            _ => None,
+        };
+
+        let mut lock = self.load_lock_file()?;
+        lock.upsert(LockedTapplet {
+            name: installed.manifest.qualified_name(),
+            version: installed.manifest.version.clone(),
+            registry_revision,
+            git_rev,
+            artifact_sha256: installed.artifact_sha256()?,
+            source,
+        });
+        lock.save(&self.lock_file_path())
+    }
+
+    /// List all tapplets installed in the cache directory
+    pub fn list_installed(&self) -> Result<Vec<InstalledTapplet>> {
+        install::list_installed(&self.cache_directory)
+    }
+
+    /// Find an installed tapplet by name
+    pub fn get_installed(&self, name: &str) -> Result<Option<InstalledTapplet>> {
+        Ok(self
+            .list_installed()?
+            .into_iter()
+            .find(|tapplet| tapplet.manifest.name_matches(name)))
+    }
+
+    /// Remove every installed version of a tapplet from the cache directory
+    pub fn uninstall(&self, name: &str) -> Result<()> {
+        let removed = install::uninstall(name, &self.cache_directory)?;
+
+        let mut lock = self.load_lock_file()?;
+        let mut changed = false;
+        for tapplet in &removed {
+            changed |= lock.remove(&tapplet.manifest.qualified_name()).is_some();
+        }
+        if changed {
+            lock.save(&self.lock_file_path())?;
+        }
+        Ok(())
+    }
+
+    /// Reinstall a tapplet from the source it was originally installed from
+    pub fn update(&self, name: &str) -> Result<InstalledTapplet> {
+        let Some(tapplet) = self.get_installed(name)? else {
+            bail!(TappletError::NotInstalled {
+                name: name.to_string()
+            });
+        };
+        let Some(source) = tapplet.source else {
+            bail!(
+                "Tapplet '{}' has no recorded source and cannot be updated",
+                name
+            );
+        };
+        // Check before reinstalling, so a yanked update leaves the installed version
+        ensure_not_yanked(&source.manifest()?)?;
+        let installed = self.reinstall(&source, |_| Ok(()))?;
+        self.lock(&installed, source)?;
+        Ok(installed)
+    }
+
+    /// Cache of compiled WASM modules used by `get_host`
+    #[cfg(feature = "host-core")]
+    pub fn module_cache(&self) -> ModuleCache {
+        ModuleCache::in_cache_directory(&self.cache_directory)
+    }
+
+    /// Construct a host for an installed tapplet, choosing the runtime from its
+    /// manifest. The host has no limits; see [`TappletManager::get_host_with_options`].
+    #[cfg(feature = "host-core")]
+    pub fn get_host<T: MinotariTappletApiV1 + 'static>(
+        &self,
+        name: &str,
+        api: T,
+    ) -> Result<InstalledHost<T>> {
+        self.get_host_with_options(name, api, &HostOptions::unlimited())
+    }
+
+    /// Construct a host for an installed tapplet with the limits, sandbox and
+    /// hooks of `options`
+    #[cfg(feature = "host-core")]
+    pub fn get_host_with_options<T: MinotariTappletApiV1 + 'static>(
+        &self,
+        name: &str,
+        api: T,
+        options: &HostOptions,
+    ) -> Result<InstalledHost<T>> {
+        let tapplet = self.installed_or_bail(name)?;
+        self.build_host(tapplet, Arc::new(api), options)
+    }
+
+    /// Compile the WASM modules of installed tapplets into the module cache on a
+    /// background thread, e.g. at wallet startup, so constructing their hosts
+    /// later doesn't wait for the compiler. Lua tapplets have nothing to compile.
+    #[cfg(feature = "host-core")]
+    pub fn preload(&self, names: &[&str]) -> PreloadHandle {
+        let cache_directory = self.cache_directory.clone();
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let module_cache = self.module_cache();
+        let thread = std::thread::spawn(move || {
+            names
+                .into_iter()
+                .map(|name| {
+                    let result = preload_module(&cache_directory, &module_cache, &name);
+                    (name, result)
+                })
+                .collect()
+        });
+        PreloadHandle { thread }
+    }
+
+    /// Construct hosts for installed tapplets into `pool`, running their
+    /// [`crate::host::GUEST_INIT_EXPORT`], so the first call of each is answered
+    /// by a ready host. Hosts can't be moved between threads, so this runs on
+    /// the calling thread; call it once [`TappletManager::preload`] has finished.
+    #[cfg(feature = "host-core")]
+    pub fn preload_into(&self, pool: &HostPool, names: &[&str]) -> Result<()> {
+        for name in names {
+            drop(self.get_pooled_host(pool, name)?);
+        }
+        Ok(())
+    }
+
+    /// Take a host for an installed tapplet out of `pool`, constructing one with
+    /// the pool's API and options if it has none for the installed artifact. The
+    /// host goes back to the pool when it is dropped.
+    #[cfg(feature = "host-core")]
+    pub fn get_pooled_host(&self, pool: &HostPool, name: &str) -> Result<PooledHost> {
+        let tapplet = self.installed_or_bail(name)?;
+        let key = (
+            tapplet.manifest.fully_qualified_name(),
+            tapplet.artifact_sha256()?,
+        );
+        if let Some(host) = pool.take(&key) {
+            return Ok(host);
+        }
+        let host = self.build_host(tapplet, pool.api(), pool.options())?;
+        Ok(pool.lend(key, host))
+    }
+
+    #[cfg(feature = "host-core")]
+    fn installed_or_bail(&self, name: &str) -> Result<InstalledTapplet> {
+        match self.get_installed(name)? {
+            Some(tapplet) => Ok(tapplet),
+            None => bail!(TappletError::NotInstalled {
+                name: name.to_string()
+            }),
+        }
+    }
+
+    /// Construct a host for `tapplet`, e.g. one of [`TappletManager::list_installed`]
+    #[cfg(feature = "host-core")]
+    pub(crate) fn build_host<T: MinotariTappletApiV1 + ?Sized + 'static>(
+        &self,
+        tapplet: InstalledTapplet,
+        api: Arc<T>,
+        options: &HostOptions,
+    ) -> Result<InstalledHost<T>> {
+        if let Some(wasm_path) = tapplet.wasm_path() {
+            let module_cache = self.module_cache();
+            let precompiled = module_cache.precompiled_path(&std::fs::read(&wasm_path)?);
+            if precompiled.is_file() {
+                // SAFETY: the artifact is looked up by the hash of the installed
+                // module in the module cache directory, which only the host writes;
+                // installed packages can't put files there
+                match unsafe {
+                    WasmTappletHost::from_precompiled(tapplet.manifest.clone(), &precompiled)
+                } {
+                    Ok(mut host) => {
+                        host.init()?;
+                        return Ok(InstalledHost::Wasm(host.apply_options(options)?));
+                    }
+                    Err(e) => trace::warning!(
+                        "Ignoring precompiled module {}: {}",
+                        precompiled.display(),
+                        e
+                    ),
+                }
+            }
+            let mut host =
+                WasmTappletHost::with_module_cache(tapplet.manifest, wasm_path, &module_cache)?;
+            host.init()?;
+            Ok(InstalledHost::Wasm(host.apply_options(options)?))
+        } else if let Some(lua_path) = tapplet.lua_path() {
+            Ok(InstalledHost::Lua(LuaTappletHost::new_with_options(
+                tapplet.manifest,
+                lua_path,
+                api,
+                options,
+            )?))
+        } else {
+            bail!(TappletError::ArtifactNotFound(format!(
+                "no WASM or Lua artifact for tapplet '{}' in {}",
+                tapplet.manifest.name,
+                tapplet.path.display()
+            )));
+        }
+    }
+}
+
+/// Tapplets being compiled in the background, from [`TappletManager::preload`]
+#[cfg(feature = "host-core")]
+pub struct PreloadHandle {
+    thread: std::thread::JoinHandle<Vec<(String, Result<()>)>>,
+}
+
+#[cfg(feature = "host-core")]
+impl PreloadHandle {
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Wait for every tapplet to be compiled, returning the outcome for each name
+    pub fn wait(self) -> Vec<(String, Result<()>)> {
+        self.thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+/// Compile the WASM module of an installed tapplet into `module_cache`, unless
+/// it was precompiled when it was installed
+#[cfg(feature = "host-core")]
+fn preload_module(cache_directory: &Path, module_cache: &ModuleCache, name: &str) -> Result<()> {
+    let Some(tapplet) = install::list_installed(cache_directory)?
+        .into_iter()
+        .find(|tapplet| tapplet.manifest.name_matches(name))
+    else {
+        bail!(TappletError::NotInstalled {
+            name: name.to_string()
+        });
+    };
+    if let Some(wasm_path) = tapplet.wasm_path() {
+        let wasm_bytes = std::fs::read(&wasm_path)?;
+        if !module_cache.precompiled_path(&wasm_bytes).is_file() {
+            module_cache.prewarm(&wasm_bytes)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read an installed tapplet's manifest and recorded source from its directory
+pub(crate) fn read_installed(path: PathBuf) -> Result<InstalledTapplet> {
+    let manifest = TappletManifest::from_file(path.join("manifest.toml"))
+        .with_context(|| format!("Failed to read installed manifest in {}", path.display()))?;
+    let source_file = path.join(SOURCE_FILE_NAME);
+    let source = if source_file.exists() {
+        let content = std::fs::read_to_string(&source_file)?;
+        Some(toml::from_str(&content).with_context(|| {
+            format!("Failed to parse tapplet source: {}", source_file.display())
+        })?)
+    } else {
+        None
+    };
+    Ok(InstalledTapplet {
+        manifest,
+        path,
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn test_install_list_update_uninstall() {
+        let temp = tempfile::tempdir().unwrap();
+        let source_dir = temp.path().join("source");
+        test_utils::write_lua_tapplet(&source_dir, "hello-lua", "0.1.0");
+
+        let manager = TappletManager::new(temp.path().join("cache"));
+        assert!(manager.list_installed().unwrap().is_empty());
+
+        let installed = manager
+            .install(TappletSource::LocalLua {
+                path: source_dir.clone(),
+            })
+            .unwrap();
+        assert!(manager.load_lock_file().unwrap().get("hello-lua").is_some());
+        assert_eq!(installed.manifest.name, "hello-lua");
+        assert!(installed.lua_path().is_some());
+        assert!(installed.wasm_path().is_none());
+        assert!(matches!(
+            installed.source,
+            Some(TappletSource::LocalLua { .. })
+        ));
+
+        let listed = manager.list_installed().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(manager.get_installed("hello_lua").unwrap().is_some());
+
+        let updated = manager.update("hello-lua").unwrap();
+        assert_eq!(updated.path, installed.path);
+
+        // A failing update leaves the installed version and its lock entry
+        let lock = std::fs::read_to_string(manager.lock_file_path()).unwrap();
+        std::fs::remove_file(source_dir.join("main.lua")).unwrap();
+        assert!(manager.update("hello-lua").is_err());
+        assert!(installed.lua_path().unwrap().is_file());
+        assert!(manager.get_installed("hello-lua").unwrap().is_some());
+        assert_eq!(
+            std::fs::read_to_string(manager.lock_file_path()).unwrap(),
+            lock
+        );
+
+        manager.uninstall("hello-lua").unwrap();
+        assert!(manager.list_installed().unwrap().is_empty());
+        assert!(manager.uninstall("hello-lua").is_err());
+        assert!(manager.load_lock_file().unwrap().tapplets.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_install_with_dependencies_is_staged() {
+        let temp = tempfile::tempdir().unwrap();
+        let old_lib = temp.path().join("lib");
+        test_utils::write_lua_tapplet(&old_lib, "lib", "0.1.0");
+        let manager = TappletManager::new(temp.path().join("cache"));
+        manager
+            .install(TappletSource::LocalLua { path: old_lib })
+            .unwrap();
+
+        // `app` needs a newer `lib`, but can't be installed without its script
+        let remote = temp.path().join("remote");
+        test_utils::init_registry_repo(&remote, &[("lib", "0.2.0"), ("app", "1.0.0")]);
+        let app = remote.join("tapplets/app");
+        let manifest = std::fs::read_to_string(app.join("manifest.toml")).unwrap();
+        std::fs::write(
+            app.join("manifest.toml"),
+            manifest + "\n[dependencies]\nlib = \">=0.2\"\n",
+        )
+        .unwrap();
+        std::fs::remove_file(app.join("main.lua")).unwrap();
+        test_utils::commit_all(&remote);
+        let mut registry = TappletRegistry::new(
+            "test",
+            remote.to_str().unwrap(),
+            temp.path().join("registry"),
+        );
+        registry.fetch().await.unwrap();
+
+        let plan = manager.plan_install(&registry, "app", "*").unwrap();
+        assert!(matches!(
+            &plan.steps[..],
+            [InstallStep::Replace { installed_version, .. }, InstallStep::Install { .. }]
+                if installed_version == "0.1.0"
+        ));
+        assert!(
+            manager
+                .install_with_dependencies(&registry, "app", "*")
+                .is_err()
+        );
+        // Nothing was replaced, since the plan didn't install as a whole
+        let installed = manager.list_installed().unwrap();
+        assert_eq!(installed.len(), 1);
+        assert_eq!(installed[0].manifest.version, "0.1.0");
+        assert_eq!(manager.load_lock_file().unwrap().tapplets.len(), 1);
+        assert!(!manager.cache_directory().join(STAGING_DIR).exists());
+
+        std::fs::write(app.join("main.lua"), "function greet() return 'hi' end").unwrap();
+        test_utils::commit_all(&remote);
+        registry.fetch().await.unwrap();
+        let installed = manager
+            .install_with_dependencies(&registry, "app", "*")
+            .unwrap();
+        let versions: Vec<_> = installed
+            .iter()
+            .map(|tapplet| tapplet.manifest.canonical_name())
+            .collect();
+        assert_eq!(versions, ["lib@0.2.0", "app@1.0.0"]);
+        assert_eq!(manager.list_installed().unwrap().len(), 2);
+        assert_eq!(
+            manager
+                .load_lock_file()
+                .unwrap()
+                .get("lib")
+                .unwrap()
+                .version,
+            "0.2.0"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_source_from_registry_is_the_latest_version() {
+        let temp = tempfile::tempdir().unwrap();
+        let remote = temp.path().join("remote");
+        for version in ["0.1.0", "0.2.0", "0.3.0-beta.1"] {
+            let dir = remote.join("tapplets/wallet").join(version);
+            test_utils::write_lua_tapplet(&dir, "wallet", version);
+        }
+        test_utils::commit_all(&remote);
+        let mut registry = TappletRegistry::new(
+            "test",
+            remote.to_str().unwrap(),
+            temp.path().join("registry"),
+        );
+        registry.fetch().await.unwrap();
+
+        // Walk order would give 0.1.0
+        let source = TappletSource::from_registry(&registry, "wallet").unwrap();
+        assert_eq!(source.manifest().unwrap().version, "0.2.0");
+        let err = TappletSource::from_registry(&registry, "missing").unwrap_err();
+        assert_eq!(crate::error_code(&err), "TAPPLET_NOT_FOUND");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_source_from_registry_of_several_publishers() {
+        let temp = tempfile::tempdir().unwrap();
+        let remote = temp.path().join("remote");
+        for publisher in ["alice", "bob"] {
+            let dir = remote.join("tapplets").join(publisher);
+            test_utils::write_lua_tapplet(&dir, "wallet", "0.1.0");
+            let manifest =
+                test_utils::manifest_toml("wallet", "0.1.0").replace("test_publisher", publisher);
+            std::fs::write(dir.join("manifest.toml"), manifest).unwrap();
+        }
+        test_utils::commit_all(&remote);
+        let mut registry = TappletRegistry::new(
+            "test",
+            remote.to_str().unwrap(),
+            temp.path().join("registry"),
+        );
+        registry.fetch().await.unwrap();
+
+        let err = TappletSource::from_registry(&registry, "wallet").unwrap_err();
+        assert_eq!(crate::error_code(&err), "AMBIGUOUS_NAME");
+        let source = TappletSource::from_registry(&registry, "bob/wallet").unwrap();
+        assert_eq!(source.manifest().unwrap().publisher, "bob");
+    }
+
+    #[test]
+    fn test_install_into_publisher_dirs() {
+        let temp = tempfile::tempdir().unwrap();
+        let ours = temp.path().join("ours");
+        let theirs = temp.path().join("theirs");
+        test_utils::write_lua_tapplet(&ours, "greeter", "0.1.0");
+        test_utils::write_lua_tapplet(&theirs, "greeter", "0.1.0");
+        let manifest = std::fs::read_to_string(theirs.join("manifest.toml")).unwrap();
+        std::fs::write(
+            theirs.join("manifest.toml"),
+            manifest.replace("test_publisher", "other_publisher"),
+        )
+        .unwrap();
+
+        let cache = temp.path().join("cache");
+        let manager = TappletManager::new(cache.clone()).with_install_options(InstallOptions {
+            publisher_dirs: true,
+            ..Default::default()
+        });
+        for path in [ours, theirs] {
+            manager.install(TappletSource::LocalLua { path }).unwrap();
+        }
+        let paths: Vec<_> = manager
+            .list_installed()
+            .unwrap()
+            .into_iter()
+            .map(|tapplet| tapplet.path)
+            .collect();
+        assert_eq!(paths.len(), 2);
+        assert!(paths.contains(&cache.join("test_publisher/greeter")));
+        assert!(paths.contains(&cache.join("other_publisher/greeter")));
+        assert_eq!(manager.load_lock_file().unwrap().tapplets.len(), 2);
+        assert!(manager.verify_lock().unwrap().is_empty());
+
+        // Updating one publisher's tapplet leaves the other's install and lock entry
+        manager.update("test_publisher/greeter").unwrap();
+        assert_eq!(manager.list_installed().unwrap().len(), 2);
+        assert!(cache.join("other_publisher/greeter/main.lua").is_file());
+        let lock = manager.load_lock_file().unwrap();
+        assert!(lock.get("other_publisher/greeter").is_some());
+        assert!(lock.get("test_publisher/greeter").is_some());
+        assert!(manager.verify_lock().unwrap().is_empty());
+
+        manager.uninstall("other_publisher/greeter").unwrap();
+        let installed = manager.get_installed("greeter").unwrap().unwrap();
+        assert_eq!(installed.manifest.publisher, "test_publisher");
+    }
+
+    #[test]
+    fn test_verify_and_install_from_lock() {
+        let temp = tempfile::tempdir().unwrap();
+        let source_dir = temp.path().join("source");
+        test_utils::write_lua_tapplet(&source_dir, "hello-lua", "0.1.0");
+
+        let manager = TappletManager::new(temp.path().join("cache"));
+        let installed = manager
+            .install(TappletSource::LocalLua {
+                path: source_dir.clone(),
+            })
+            .unwrap();
+        assert!(manager.verify_lock().unwrap().is_empty());
+
+        // Tampering with the artifact is detected and repaired from the lock
+        std::fs::write(installed.lua_path().unwrap(), "function greet() end").unwrap();
+        assert!(matches!(
+            manager.verify_lock().unwrap().as_slice(),
+            [LockMismatch::ArtifactMismatch { .. }]
+        ));
+        assert_eq!(manager.install_from_lock().unwrap().len(), 1);
+        assert!(manager.verify_lock().unwrap().is_empty());
+
+        // A missing tapplet is reinstalled, but only if it reproduces the locked artifact
+        std::fs::remove_dir_all(&installed.path).unwrap();
+        assert!(matches!(
+            manager.verify_lock().unwrap().as_slice(),
+            [LockMismatch::Missing { .. }]
+        ));
+        std::fs::write(source_dir.join("main.lua"), "function greet() end").unwrap();
+        let lock = std::fs::read_to_string(manager.lock_file_path()).unwrap();
+        let err = manager.install_from_lock().unwrap_err();
+        assert_eq!(crate::error_code(&err), "LOCK_MISMATCH");
+        assert_eq!(
+            std::fs::read_to_string(manager.lock_file_path()).unwrap(),
+            lock
+        );
+        assert!(manager.get_installed("hello-lua").unwrap().is_none());
+
+        // A failed reinstall leaves the installed tapplet as it was
+        test_utils::write_lua_tapplet(&source_dir, "hello-lua", "0.1.0");
+        assert_eq!(manager.install_from_lock().unwrap().len(), 1);
+        std::fs::write(
+            installed.lua_path().unwrap(),
+            "function greet() return 1 end",
+        )
+        .unwrap();
+        std::fs::write(source_dir.join("main.lua"), "function greet() end").unwrap();
+        assert!(manager.install_from_lock().is_err());
+        assert_eq!(
+            std::fs::read_to_string(manager.lock_file_path()).unwrap(),
+            lock
+        );
+        assert_eq!(
+            std::fs::read_to_string(installed.lua_path().unwrap()).unwrap(),
+            "function greet() return 1 end"
+        );
+        assert!(!temp.path().join("cache").join(STAGING_DIR).exists());
+    }
+
+    #[test]
+    fn test_install_rejects_untrusted_tapplet() {
+        let temp = tempfile::tempdir().unwrap();
+        let source_dir = temp.path().join("source");
+        test_utils::write_lua_tapplet(&source_dir, "hello-lua", "0.1.0");
+
+        let manager = TappletManager::new(temp.path().join("cache"))
+            .with_trust_policy(TrustPolicy::new().allow_publisher("official"));
+        let err = manager
+            .install(TappletSource::LocalLua { path: source_dir })
+            .unwrap_err();
+        assert!(err.to_string().contains("not trusted"));
+        assert!(manager.list_installed().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_install_refuses_yanked_tapplet() {
+        let temp = tempfile::tempdir().unwrap();
+        let source_dir = temp.path().join("source");
+        test_utils::write_lua_tapplet(&source_dir, "hello-lua", "0.1.0");
+        let manager = TappletManager::new(temp.path().join("cache"));
+        let source = TappletSource::LocalLua {
+            path: source_dir.clone(),
+        };
+        manager.install(source.clone()).unwrap();
+
+        let manifest = test_utils::manifest_toml("hello-lua", "0.1.0")
+            .replace("[api]", "yanked = true\n\n[api]");
+        std::fs::write(source_dir.join("manifest.toml"), manifest).unwrap();
+        let err = manager.update("hello-lua").unwrap_err();
+        assert_eq!(crate::error_code(&err), "TAPPLET_YANKED");
+        assert!(manager.get_installed("hello-lua").unwrap().is_some());
+
+        manager.uninstall("hello-lua").unwrap();
+        assert!(manager.install(source.clone()).is_err());
+        let installed = manager.force_install(source).unwrap();
+        assert!(installed.manifest.yanked);
+    }
+
+    #[test]
+    fn test_install_copies_assets() {
+        let temp = tempfile::tempdir().unwrap();
+        let source_dir = temp.path().join("source");
+        test_utils::write_lua_tapplet(&source_dir, "hello-lua", "0.1.0");
+        let manifest = test_utils::manifest_toml("hello-lua", "0.1.0").replace(
+            "[api]",
+            "[assets]\nicon = \"assets/icon.svg\"\naccent_color = \"#336699\"\n\n[api]",
+        );
+        std::fs::write(source_dir.join("manifest.toml"), manifest).unwrap();
+        let manager = TappletManager::new(temp.path().join("cache"));
+        let source = TappletSource::LocalLua {
+            path: source_dir.clone(),
+        };
+
+        let err = manager.install(source.clone()).unwrap_err();
+        assert_eq!(crate::error_code(&err), "ARTIFACT_NOT_FOUND");
+        std::fs::create_dir(source_dir.join("assets")).unwrap();
+        std::fs::write(source_dir.join("assets/icon.svg"), "GIF89a").unwrap();
+        let err = manager.install(source.clone()).unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_ASSET");
+
+        std::fs::write(source_dir.join("assets/icon.svg"), "<svg></svg>").unwrap();
+        let installed = manager.install(source).unwrap();
+        assert_eq!(
+            installed.icon_path(),
+            Some(installed.path.join("assets/icon.svg"))
+        );
+        assert!(installed.screenshot_paths().is_empty());
+    }
+
+    #[test]
+    fn test_install_uses_declared_runtime() {
+        let temp = tempfile::tempdir().unwrap();
+        let source_dir = temp.path().join("source");
+        test_utils::write_lua_tapplet(&source_dir, "hello-lua", "0.1.0");
+        std::fs::create_dir_all(source_dir.join("src")).unwrap();
+        std::fs::write(
+            source_dir.join("src/app.lua"),
+            "function greet() return 'app' end",
+        )
+        .unwrap();
+        let manifest = test_utils::manifest_toml("hello-lua", "0.1.0")
+            + "[runtime]\nkind = \"lua\"\nentrypoint = \"src/app.lua\"\n";
+        std::fs::write(source_dir.join("manifest.toml"), &manifest).unwrap();
+
+        let manager = TappletManager::new(temp.path().join("cache"));
+        let installed = manager
+            .install(TappletSource::Registry {
+                registry: "local".to_string(),
+                path: source_dir.clone(),
+            })
+            .unwrap();
+        assert_eq!(installed.runtime(), Some(RuntimeKind::Lua));
+        assert!(installed.wasm_path().is_none());
+        assert_eq!(
+            std::fs::read_to_string(installed.lua_path().unwrap()).unwrap(),
+            "function greet() return 'app' end"
+        );
+
+        // A tapplet declared as WASM isn't installed as Lua, whatever files it has
+        manager.uninstall("hello-lua").unwrap();
+        std::fs::write(
+            source_dir.join("manifest.toml"),
+            manifest.replace("kind = \"lua\"", "kind = \"wasm\""),
+        )
+        .unwrap();
+        let err = manager
+            .install(TappletSource::LocalLua { path: source_dir })
+            .unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_MANIFEST", "{:#}", err);
+    }
+
+    #[test]
+    fn test_install_from_package() {
+        let temp = tempfile::tempdir().unwrap();
+        let source_dir = temp.path().join("source");
+        test_utils::write_lua_tapplet(&source_dir, "packaged", "0.3.0");
+        let archive = package::build(&source_dir).unwrap();
+
+        let manager = TappletManager::new(temp.path().join("cache"));
+        let installed = manager
+            .install(TappletSource::Package { path: archive })
+            .unwrap();
+        assert_eq!(installed.runtime(), Some(RuntimeKind::Lua));
+        assert!(installed.lua_path().unwrap().is_file());
+        assert_eq!(
+            manager
+                .load_lock_file()
+                .unwrap()
+                .get("packaged")
+                .unwrap()
+                .version,
+            "0.3.0"
+        );
+    }
+
+    #[cfg(feature = "host-core")]
+    #[tokio::test]
+    async fn test_precompiled_module_is_kept_in_module_cache() {
+        let temp = tempfile::tempdir().unwrap();
+        let source_dir = temp.path().join("source");
+        std::fs::create_dir_all(&source_dir).unwrap();
+        let module = wat::parse_str(r#"(module (func (export "greet")))"#).unwrap();
+        std::fs::write(source_dir.join("greeter.wasm"), &module).unwrap();
+        let manifest = test_utils::manifest_toml("greeter", "0.1.0")
+            + "[runtime]\nkind = \"wasm\"\nentrypoint = \"greeter.wasm\"\n";
+        std::fs::write(source_dir.join("manifest.toml"), manifest).unwrap();
+
+        let cache = temp.path().join("cache");
+        let report = LocalFolderTapplet::load(source_dir)
+            .unwrap()
+            .with_build_options(crate::build::BuildOptions {
+                precompile: true,
+                ..Default::default()
+            })
+            .install(cache.clone())
+            .await
+            .unwrap();
+        let module_cache = ModuleCache::in_cache_directory(&cache);
+        assert!(module_cache.precompiled_path(&module).is_file());
+        assert!(
+            report
+                .artifacts
+                .iter()
+                .all(|file| file.starts_with(&report.installed_path))
+        );
+
+        // Artifacts next to the installed files, e.g. from a package, are never run:
+        // this one would trap as soon as it is instantiated
+        let trapping = temp.path().join("trapping.wasm");
+        std::fs::write(
+            &trapping,
+            wat::parse_str(r#"(module (func $start unreachable) (start $start))"#).unwrap(),
+        )
+        .unwrap();
+        let engine = module_cache.engine();
+        let planted =
+            crate::engine::precompile_file(&trapping, &report.installed_path, engine.as_ref())
+                .unwrap();
+        std::fs::rename(
+            planted,
+            report.installed_path.join(format!(
+                "greeter.{}.{}",
+                engine.artifact_key(),
+                engine.artifact_extension()
+            )),
+        )
+        .unwrap();
+        std::fs::remove_file(module_cache.precompiled_path(&module)).unwrap();
+        let manager = TappletManager::new(cache);
+        let api = crate::reference_api::MemoryTappletApi::new();
+        assert!(manager.get_host("greeter", api).is_ok());
+    }
+
+    #[cfg(feature = "host-core")]
+    #[test]
+    fn test_preload() {
+        let temp = tempfile::tempdir().unwrap();
+        let source_dir = temp.path().join("source");
+        test_utils::write_lua_tapplet(&source_dir, "hello-lua", "0.1.0");
+        let manager = TappletManager::new(temp.path().join("cache"));
+        manager
+            .install(TappletSource::LocalLua { path: source_dir })
+            .unwrap();
+
+        let outcomes = manager.preload(&["hello-lua", "missing"]).wait();
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes[0].1.is_ok());
+        let err = outcomes[1].1.as_ref().unwrap_err();
+        assert_eq!(crate::error_code(err), "NOT_INSTALLED");
+
+        // The first host is ready in the pool
+        let api = Arc::new(crate::reference_api::MemoryTappletApi::new());
+        let pool = HostPool::new(api, 4);
+        manager.preload_into(&pool, &["hello-lua"]).unwrap();
+        assert_eq!(pool.stats().entries, 1);
+        drop(manager.get_pooled_host(&pool, "hello-lua").unwrap());
+        assert_eq!(pool.stats().hits, 1);
+    }
+}
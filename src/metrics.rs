@@ -0,0 +1,241 @@
+//! Counters and histograms for tapplet calls and registry fetches.
+//!
+//! Hosts and registries report to a [`MetricsSink`] set with `with_metrics_sink`.
+//! Implement the trait to forward to an existing exporter, or use
+//! [`MemoryMetricsSink`] and serve [`MemoryMetricsSink::render`] as a Prometheus
+//! scrape endpoint.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Counter of tapplet method calls, labelled `tapplet` and `method`
+pub const CALLS_TOTAL: &str = "tapplet_calls_total";
+/// Counter of failed tapplet method calls, labelled `tapplet`, `method` and `code`
+pub const CALL_ERRORS_TOTAL: &str = "tapplet_call_errors_total";
+/// Histogram of call durations in seconds, labelled `tapplet` and `method`
+pub const CALL_DURATION_SECONDS: &str = "tapplet_call_duration_seconds";
+/// Histogram of execution budget used per call, for hosts with a budget,
+/// labelled `tapplet` and `method`
+pub const CALL_FUEL_USED: &str = "tapplet_call_fuel_used";
+/// Counter of calls refused by a [`crate::governor::ResourceGovernor`], labelled
+/// `tapplet` and `reason` (`concurrency`, `call_time` or `evicted`)
+pub const CALLS_THROTTLED_TOTAL: &str = "tapplet_calls_throttled_total";
+/// Counter of tapplets a [`crate::governor::ResourceGovernor`] evicted for using
+/// the most memory, labelled `tapplet`
+pub const TAPPLETS_EVICTED_TOTAL: &str = "tapplets_evicted_total";
+/// Counter of health checks of pooled hosts, labelled `tapplet` and `status`
+/// (`ok`, `failed`, `hung` or `memory`)
+pub const HEALTH_CHECKS_TOTAL: &str = "tapplet_health_checks_total";
+/// Counter of pooled hosts dropped for being unhealthy, labelled `tapplet` and
+/// `reason` (`failed`, `hung` or `memory`)
+pub const HOSTS_RECYCLED_TOTAL: &str = "tapplet_hosts_recycled_total";
+/// Histogram of registry fetch durations in seconds, labelled `registry` and
+/// `status` (`ok` or `error`)
+pub const REGISTRY_FETCH_DURATION_SECONDS: &str = "registry_fetch_duration_seconds";
+
+/// Label names and values of a metric
+pub type Labels<'a> = &'a [(&'static str, &'a str)];
+
+/// Receives metric updates from hosts and registries.
+///
+/// Called inline on every call, so implementations should be cheap.
+pub trait MetricsSink: Send + Sync {
+    /// Add `value` to a counter
+    fn increment_counter(&self, name: &'static str, labels: Labels<'_>, value: u64);
+    /// Record one observation of a histogram
+    fn observe_histogram(&self, name: &'static str, labels: Labels<'_>, value: f64);
+}
+
+/// Count and sum of a histogram's observations
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HistogramStats {
+    pub count: u64,
+    pub sum: f64,
+}
+
+type MetricKey = (&'static str, Vec<(&'static str, String)>);
+
+/// Keeps metrics in memory and renders them in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct MemoryMetricsSink {
+    counters: Mutex<BTreeMap<MetricKey, u64>>,
+    histograms: Mutex<BTreeMap<MetricKey, HistogramStats>>,
+}
+
+impl MemoryMetricsSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current value of a counter, 0 if it was never incremented
+    pub fn counter(&self, name: &'static str, labels: Labels<'_>) -> u64 {
+        let counters = self.counters.lock().unwrap();
+        counters.get(&key(name, labels)).copied().unwrap_or(0)
+    }
+
+    /// Observations of a histogram so far
+    pub fn histogram(&self, name: &'static str, labels: Labels<'_>) -> HistogramStats {
+        let histograms = self.histograms.lock().unwrap();
+        histograms
+            .get(&key(name, labels))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// All metrics in the Prometheus text exposition format. Histograms are
+    /// exposed as summaries with their `_sum` and `_count`.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut last_name = None;
+        for ((name, labels), value) in self.counters.lock().unwrap().iter() {
+            if last_name != Some(*name) {
+                let _ = writeln!(out, "# TYPE {} counter", name);
+                last_name = Some(*name);
+            }
+            let _ = writeln!(out, "{}{} {}", name, render_labels(labels), value);
+        }
+        for ((name, labels), stats) in self.histograms.lock().unwrap().iter() {
+            if last_name != Some(*name) {
+                let _ = writeln!(out, "# TYPE {} summary", name);
+                last_name = Some(*name);
+            }
+            let labels = render_labels(labels);
+            let _ = writeln!(out, "{}_sum{} {}", name, labels, stats.sum);
+            let _ = writeln!(out, "{}_count{} {}", name, labels, stats.count);
+        }
+        out
+    }
+}
+
+impl MetricsSink for MemoryMetricsSink {
+    fn increment_counter(&self, name: &'static str, labels: Labels<'_>, value: u64) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(key(name, labels))
+            .or_default() += value;
+    }
+
+    fn observe_histogram(&self, name: &'static str, labels: Labels<'_>, value: f64) {
+        let mut histograms = self.histograms.lock().unwrap();
+        let stats = histograms.entry(key(name, labels)).or_default();
+        stats.count += 1;
+        stats.sum += value;
+    }
+}
+
+fn key(name: &'static str, labels: Labels<'_>) -> MetricKey {
+    let mut labels: Vec<_> = labels
+        .iter()
+        .map(|(label, value)| (*label, value.to_string()))
+        .collect();
+    labels.sort();
+    (name, labels)
+}
+
+fn render_labels(labels: &[(&'static str, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<_> = labels
+        .iter()
+        .map(|(label, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", label, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Reports calls of one tapplet, doing nothing if no sink is set
+#[derive(Clone, Default)]
+#[cfg_attr(not(feature = "host-core"), allow(dead_code))]
+pub(crate) struct Meter {
+    sink: Option<Arc<dyn MetricsSink>>,
+    tapplet: String,
+}
+
+#[cfg_attr(not(feature = "host-core"), allow(dead_code))]
+impl Meter {
+    pub fn new(sink: Arc<dyn MetricsSink>, tapplet: &str) -> Self {
+        Self {
+            sink: Some(sink),
+            tapplet: tapplet.to_string(),
+        }
+    }
+
+    /// Record a finished call; `error_code` is set if it failed
+    pub fn record_call(
+        &self,
+        method: &str,
+        duration: Duration,
+        fuel_used: Option<u64>,
+        error_code: Option<&str>,
+    ) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        let labels = [("tapplet", self.tapplet.as_str()), ("method", method)];
+        sink.increment_counter(CALLS_TOTAL, &labels, 1);
+        sink.observe_histogram(CALL_DURATION_SECONDS, &labels, duration.as_secs_f64());
+        if let Some(fuel_used) = fuel_used {
+            sink.observe_histogram(CALL_FUEL_USED, &labels, fuel_used as f64);
+        }
+        if let Some(code) = error_code {
+            let labels = [
+                ("tapplet", self.tapplet.as_str()),
+                ("method", method),
+                ("code", code),
+            ];
+            sink.increment_counter(CALL_ERRORS_TOTAL, &labels, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_sink_render() {
+        let sink = Arc::new(MemoryMetricsSink::new());
+        let meter = Meter::new(sink.clone(), "wallet");
+        meter.record_call("balance", Duration::from_millis(500), Some(40), None);
+        meter.record_call(
+            "balance",
+            Duration::from_millis(250),
+            None,
+            Some("METHOD_NOT_FOUND"),
+        );
+
+        let labels = [("method", "balance"), ("tapplet", "wallet")];
+        assert_eq!(sink.counter(CALLS_TOTAL, &labels), 2);
+        assert_eq!(
+            sink.histogram(CALL_DURATION_SECONDS, &labels),
+            HistogramStats {
+                count: 2,
+                sum: 0.75
+            }
+        );
+        assert_eq!(sink.histogram(CALL_FUEL_USED, &labels).count, 1);
+        assert_eq!(
+            sink.render(),
+            "# TYPE tapplet_call_errors_total counter\n\
+             tapplet_call_errors_total{code=\"METHOD_NOT_FOUND\",method=\"balance\",tapplet=\"wallet\"} 1\n\
+             # TYPE tapplet_calls_total counter\n\
+             tapplet_calls_total{method=\"balance\",tapplet=\"wallet\"} 2\n\
+             # TYPE tapplet_call_duration_seconds summary\n\
+             tapplet_call_duration_seconds_sum{method=\"balance\",tapplet=\"wallet\"} 0.75\n\
+             tapplet_call_duration_seconds_count{method=\"balance\",tapplet=\"wallet\"} 2\n\
+             # TYPE tapplet_call_fuel_used summary\n\
+             tapplet_call_fuel_used_sum{method=\"balance\",tapplet=\"wallet\"} 40\n\
+             tapplet_call_fuel_used_count{method=\"balance\",tapplet=\"wallet\"} 1\n"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Integrity checks of the files listed in a manifest's `[artifacts]` section
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use super::{RuntimeKind, TappletManifest, is_contained};
+use crate::checksum::{sha256_file, sha256_hex};
+use crate::error::TappletError;
+
+impl TappletManifest {
+    /// Check every file listed in `[artifacts]` exists in `dir` with the listed hash.
+    ///
+    /// Fails with [`TappletError::ArtifactNotFound`] for missing files and
+    /// [`TappletError::IntegrityMismatch`] for files whose contents changed.
+    pub fn verify_artifacts(&self, dir: &Path) -> Result<()> {
+        for (file, expected) in &self.artifacts {
+            if !is_contained(Path::new(file)) {
+                return Err(TappletError::InvalidManifest(format!(
+                    "artifact '{}' must be a relative path inside the tapplet directory",
+                    file
+                ))
+                .into());
+            }
+            let path = dir.join(file);
+            if !path.is_file() {
+                return Err(TappletError::ArtifactNotFound(format!(
+                    "{} is listed in [artifacts] but does not exist",
+                    path.display()
+                ))
+                .into());
+            }
+            check_hash(file, expected, &sha256_file(&path)?)?;
+        }
+        Ok(())
+    }
+
+    /// Check the contents of the file a host is about to run, if it is listed in
+    /// `[artifacts]`.
+    ///
+    /// Installers rename the entrypoint to `<name>.wasm` or `<name>.lua`, so the
+    /// file is looked up by its file name and then as the runtime's entrypoint.
+    pub fn verify_entrypoint(
+        &self,
+        kind: RuntimeKind,
+        path: &Path,
+        contents: &[u8],
+    ) -> Result<(), TappletError> {
+        self.verify_entrypoint_hash(kind, path, &sha256_hex(contents))
+    }
+
+    /// Check a file like [`TappletManifest::verify_entrypoint`], given the SHA-256
+    /// of its contents, e.g. the one recorded in a precompiled module
+    pub fn verify_entrypoint_hash(
+        &self,
+        kind: RuntimeKind,
+        path: &Path,
+        sha256: &str,
+    ) -> Result<(), TappletError> {
+        let entrypoint = match &self.runtime {
+            Some(runtime) if runtime.kind == kind => runtime.entrypoint(),
+            _ => kind.default_entrypoint(),
+        };
+        let file_name = path.file_name().and_then(|name| name.to_str());
+        let listed = file_name
+            .and_then(|name| self.artifacts.get_key_value(name))
+            .or_else(|| self.artifacts.get_key_value(entrypoint));
+        match listed {
+            Some((file, expected)) => check_hash(file, expected, sha256),
+            None => Ok(()),
+        }
+    }
+}
+
+fn check_hash(file: &str, expected: &str, actual: &str) -> Result<(), TappletError> {
+    if expected.eq_ignore_ascii_case(actual) {
+        return Ok(());
+    }
+    Err(TappletError::IntegrityMismatch {
+        file: file.to_string(),
+        expected: expected.to_string(),
+        actual: actual.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("lib")).unwrap();
+        std::fs::write(dir.path().join("main.lua"), "function greet() end").unwrap();
+        std::fs::write(dir.path().join("lib/util.lua"), "return {}").unwrap();
+
+        let mut manifest =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("notes", "0.1.0"))
+                .unwrap();
+        manifest
+            .artifacts
+            .insert("main.lua".to_string(), sha256_hex(b"function greet() end"));
+        manifest
+            .artifacts
+            .insert("lib/util.lua".to_string(), sha256_hex(b"return {}"));
+        manifest.verify_artifacts(dir.path()).unwrap();
+
+        // Installed as notes.lua, but checked against the hash of main.lua
+        let installed = dir.path().join("notes.lua");
+        assert!(
+            manifest
+                .verify_entrypoint(RuntimeKind::Lua, &installed, b"function greet() end")
+                .is_ok()
+        );
+        let err = manifest
+            .verify_entrypoint(RuntimeKind::Lua, &installed, b"function greet() evil() end")
+            .unwrap_err();
+        assert_eq!(err.code(), "INTEGRITY_MISMATCH");
+        // Files that aren't listed aren't checked
+        assert!(
+            manifest
+                .verify_entrypoint(RuntimeKind::Wasm, &dir.path().join("x.wasm"), b"")
+                .is_ok()
+        );
+
+        std::fs::write(dir.path().join("lib/util.lua"), "return nil").unwrap();
+        let err = manifest.verify_artifacts(dir.path()).unwrap_err();
+        assert_eq!(crate::error_code(&err), "INTEGRITY_MISMATCH");
+        assert!(
+            err.to_string()
+                .starts_with("Integrity check failed for lib/util.lua")
+        );
+
+        std::fs::remove_file(dir.path().join("lib/util.lua")).unwrap();
+        let err = manifest.verify_artifacts(dir.path()).unwrap_err();
+        assert_eq!(crate::error_code(&err), "ARTIFACT_NOT_FOUND");
+    }
+}
@@ -0,0 +1,228 @@
+//! Images a store front shows for a tapplet, declared in the manifest's `[assets]` section
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{ManifestIssue, TappletManifest, is_contained};
+use crate::error::TappletError;
+
+/// Largest icon installers accept
+pub const MAX_ICON_SIZE: u64 = 256 * 1024;
+/// Largest screenshot installers accept
+pub const MAX_SCREENSHOT_SIZE: u64 = 2 * 1024 * 1024;
+
+const ICON_FORMATS: &[&str] = &["png", "svg", "webp"];
+const SCREENSHOT_FORMATS: &[&str] = &["png", "jpg", "jpeg", "webp"];
+
+/// The manifest's `[assets]` section. Paths are relative to the tapplet directory.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct AssetsConfig {
+    /// Square icon, PNG, SVG or WebP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// PNG, JPEG or WebP screenshots, in display order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub screenshots: Vec<String>,
+    /// Color a store front may theme the tapplet's page with, as `#rrggbb`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accent_color: Option<String>,
+}
+
+impl AssetsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.icon.is_none() && self.screenshots.is_empty() && self.accent_color.is_none()
+    }
+
+    /// Every asset file, with its manifest field, allowed formats and size limit
+    fn files(&self) -> impl Iterator<Item = (String, &str, &'static [&'static str], u64)> {
+        let icon = self.icon.iter().map(|icon| {
+            (
+                "assets.icon".to_string(),
+                icon.as_str(),
+                ICON_FORMATS,
+                MAX_ICON_SIZE,
+            )
+        });
+        let screenshots = self.screenshots.iter().enumerate().map(|(i, screenshot)| {
+            (
+                format!("assets.screenshots.{}", i),
+                screenshot.as_str(),
+                SCREENSHOT_FORMATS,
+                MAX_SCREENSHOT_SIZE,
+            )
+        });
+        icon.chain(screenshots)
+    }
+
+    /// Problems that show without looking at the files
+    pub(super) fn issues(&self) -> Vec<ManifestIssue> {
+        let mut issues = Vec::new();
+        for (field, file, formats, _) in self.files() {
+            if !is_contained(Path::new(file)) {
+                issues.push(ManifestIssue::new(
+                    field,
+                    "must be a relative path inside the tapplet directory",
+                ));
+            } else if !formats.contains(&extension(file).as_str()) {
+                issues.push(ManifestIssue::new(
+                    field,
+                    format!("must be one of: {}", formats.join(", ")),
+                ));
+            }
+        }
+        if let Some(color) = &self.accent_color
+            && !is_hex_color(color)
+        {
+            issues.push(ManifestIssue::new(
+                "assets.accent_color",
+                "must be a color like #1a2b3c",
+            ));
+        }
+        issues
+    }
+}
+
+impl TappletManifest {
+    /// Check every file in `[assets]` exists in `dir`, is within its size limit and
+    /// is an image of the format its extension says.
+    ///
+    /// Fails with [`TappletError::ArtifactNotFound`] for missing files and
+    /// [`TappletError::InvalidAsset`] for anything else.
+    pub fn verify_assets(&self, dir: &Path) -> Result<()> {
+        for (field, file, _, _) in self.assets.files() {
+            let path = dir.join(file);
+            if !is_contained(Path::new(file)) || !path.is_file() {
+                return Err(TappletError::ArtifactNotFound(format!(
+                    "{} {} does not exist",
+                    field,
+                    path.display()
+                ))
+                .into());
+            }
+            let contents = std::fs::read(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            self.verify_asset(file, &contents)?;
+        }
+        Ok(())
+    }
+
+    /// Check the contents of one file listed in `[assets]`. Files that aren't
+    /// listed pass.
+    pub fn verify_asset(&self, file: &str, contents: &[u8]) -> Result<(), TappletError> {
+        let Some((field, _, formats, max_size)) = self
+            .assets
+            .files()
+            .find(|(_, listed, _, _)| *listed == file)
+        else {
+            return Ok(());
+        };
+        let invalid =
+            |message: String| TappletError::InvalidAsset(format!("{}: {}", field, message));
+        if contents.len() as u64 > max_size {
+            return Err(invalid(format!(
+                "{} is {} bytes, more than the {} allowed",
+                file,
+                contents.len(),
+                max_size
+            )));
+        }
+        let format = extension(file);
+        if !formats.contains(&format.as_str()) {
+            return Err(invalid(format!(
+                "{} must be one of: {}",
+                file,
+                formats.join(", ")
+            )));
+        }
+        if !has_format(&format, contents) {
+            return Err(invalid(format!("{} is not a valid {} image", file, format)));
+        }
+        Ok(())
+    }
+}
+
+fn extension(file: &str) -> String {
+    Path::new(file)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Whether `contents` start like an image of `format`
+fn has_format(format: &str, contents: &[u8]) -> bool {
+    match format {
+        "png" => contents.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "jpg" | "jpeg" => contents.starts_with(&[0xff, 0xd8, 0xff]),
+        "webp" => contents.len() >= 12 && &contents[..4] == b"RIFF" && &contents[8..12] == b"WEBP",
+        "svg" => std::str::from_utf8(contents).is_ok_and(|text| {
+            let text = text.trim_start_matches('\u{feff}').trim_start();
+            (text.starts_with("<svg") || text.starts_with("<?xml")) && text.contains("<svg")
+        }),
+        _ => false,
+    }
+}
+
+fn is_hex_color(color: &str) -> bool {
+    color
+        .strip_prefix('#')
+        .is_some_and(|hex| hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn test_verify_assets() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut manifest =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("greeter", "0.1.0"))
+                .unwrap();
+        manifest.assets = AssetsConfig {
+            icon: Some("icon.svg".to_string()),
+            screenshots: vec!["shots/home.png".to_string()],
+            accent_color: Some("#1A2b3c".to_string()),
+        };
+        assert!(manifest.assets.issues().is_empty());
+
+        let err = manifest.verify_assets(temp.path()).unwrap_err();
+        assert_eq!(crate::error_code(&err), "ARTIFACT_NOT_FOUND");
+        std::fs::write(temp.path().join("icon.svg"), "<svg></svg>").unwrap();
+        std::fs::create_dir(temp.path().join("shots")).unwrap();
+        std::fs::write(temp.path().join("shots/home.png"), PNG).unwrap();
+        manifest.verify_assets(temp.path()).unwrap();
+
+        // A JPEG named .png, and an icon over the size limit
+        std::fs::write(temp.path().join("shots/home.png"), [0xff, 0xd8, 0xff, 0xe0]).unwrap();
+        let err = manifest.verify_assets(temp.path()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid asset: assets.screenshots.0: shots/home.png is not a valid png image"
+        );
+        let big_icon = format!("<svg>{}</svg>", " ".repeat(MAX_ICON_SIZE as usize));
+        let err = manifest
+            .verify_asset("icon.svg", big_icon.as_bytes())
+            .unwrap_err();
+        assert_eq!(err.code(), "INVALID_ASSET");
+
+        manifest.assets = AssetsConfig {
+            icon: Some("../icon.gif".to_string()),
+            screenshots: vec!["home.bmp".to_string()],
+            accent_color: Some("red".to_string()),
+        };
+        let fields: Vec<_> = manifest
+            .assets
+            .issues()
+            .into_iter()
+            .map(|issue| issue.field)
+            .collect();
+        assert_eq!(
+            fields,
+            ["assets.icon", "assets.screenshots.0", "assets.accent_color"]
+        );
+    }
+}
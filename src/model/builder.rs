@@ -0,0 +1,440 @@
+//! Constructing manifests in code
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::Result;
+
+use super::{
+    ApiConfig, AssetsConfig, ChainPermissions, EventKind, EventsConfig, GitConfig,
+    LocalizedStrings, MethodDefinition, MethodDeprecation, NetworkPermissions, ParamDefinition,
+    ParamType, PermissionsConfig, ReturnDefinition, RuntimeConfig, RuntimeKind, ScheduledTask,
+    SigsConfig, TappletManifest, TappletTest,
+};
+use crate::error::TappletError;
+
+/// Builds a [`TappletManifest`], checking it with [`TappletManifest::validate`]
+/// when it is built.
+///
+/// ```ignore
+/// let manifest = TappletManifest::builder("greeter", "0.1.0")
+///     .with_publisher("tari", public_key)
+///     .with_license("MIT")
+///     .with_runtime(RuntimeKind::Lua)
+///     .with_method(
+///         "greet",
+///         MethodDefinition::new("Returns a greeting.", ParamType::String, "A greeting.")
+///             .with_param("name", ParamType::String, "The name to greet."),
+///     )
+///     .build()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct TappletManifestBuilder {
+    manifest: TappletManifest,
+}
+
+impl TappletManifest {
+    pub fn builder(name: impl Into<String>, version: impl Into<String>) -> TappletManifestBuilder {
+        TappletManifestBuilder::new(name, version)
+    }
+}
+
+impl TappletManifestBuilder {
+    /// Start a manifest without methods. The friendly name defaults to `name`.
+    pub fn new(name: impl Into<String>, version: impl Into<String>) -> Self {
+        let name = name.into();
+        Self {
+            manifest: TappletManifest {
+                friendly_name: name.clone(),
+                name,
+                version: version.into(),
+                description: None,
+                publisher: String::new(),
+                git: None,
+                runtime: None,
+                api: ApiConfig {
+                    methods: Vec::new(),
+                    method_definitions: HashMap::new(),
+                },
+                sigs: SigsConfig {
+                    todo: String::new(),
+                },
+                public_key: String::new(),
+                license: None,
+                requires_host_api: None,
+                yanked: false,
+                deprecated: None,
+                categories: Vec::new(),
+                tags: Vec::new(),
+                assets: AssetsConfig::default(),
+                i18n: BTreeMap::new(),
+                tests: BTreeMap::new(),
+                events: EventsConfig::default(),
+                permissions: PermissionsConfig::default(),
+                schedule: BTreeMap::new(),
+                dependencies: BTreeMap::new(),
+                artifacts: BTreeMap::new(),
+                extra: BTreeMap::new(),
+            },
+        }
+    }
+
+    pub fn with_friendly_name(mut self, friendly_name: impl Into<String>) -> Self {
+        self.manifest.friendly_name = friendly_name.into();
+        self
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.manifest.description = Some(description.into());
+        self
+    }
+
+    /// Set the publisher and the hex encoded public key the tapplet is signed with
+    pub fn with_publisher(
+        mut self,
+        publisher: impl Into<String>,
+        public_key: impl Into<String>,
+    ) -> Self {
+        self.manifest.publisher = publisher.into();
+        self.manifest.public_key = public_key.into();
+        self
+    }
+
+    pub fn with_git(mut self, url: impl Into<String>, rev: impl Into<String>) -> Self {
+        self.manifest.git = Some(GitConfig {
+            url: url.into(),
+            rev: rev.into(),
+        });
+        self
+    }
+
+    /// Declare the runtime with its default entrypoint
+    pub fn with_runtime(mut self, kind: RuntimeKind) -> Self {
+        self.manifest.runtime = Some(RuntimeConfig {
+            kind,
+            entrypoint: None,
+        });
+        self
+    }
+
+    pub fn with_entrypoint(mut self, kind: RuntimeKind, entrypoint: impl Into<String>) -> Self {
+        self.manifest.runtime = Some(RuntimeConfig {
+            kind,
+            entrypoint: Some(entrypoint.into()),
+        });
+        self
+    }
+
+    /// Add a method to `api.methods` with its definition, replacing any with the same name
+    pub fn with_method(mut self, name: impl Into<String>, definition: MethodDefinition) -> Self {
+        let name = name.into();
+        if !self.manifest.api.methods.contains(&name) {
+            self.manifest.api.methods.push(name.clone());
+        }
+        self.manifest
+            .api
+            .method_definitions
+            .insert(name, definition);
+        self
+    }
+
+    pub fn with_category(mut self, category: impl Into<String>) -> Self {
+        self.manifest.categories.push(category.into());
+        self
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.manifest.tags.push(tag.into());
+        self
+    }
+
+    /// Set the SPDX license expression, e.g. `MIT OR Apache-2.0`
+    pub fn with_license(mut self, license: impl Into<String>) -> Self {
+        self.manifest.license = Some(license.into());
+        self
+    }
+
+    /// Require host API versions matching `requirement`, e.g. `>=2.1`
+    pub fn with_host_api(mut self, requirement: impl Into<String>) -> Self {
+        self.manifest.requires_host_api = Some(requirement.into());
+        self
+    }
+
+    /// Set the icon, a path relative to the tapplet directory
+    pub fn with_icon(mut self, icon: impl Into<String>) -> Self {
+        self.manifest.assets.icon = Some(icon.into());
+        self
+    }
+
+    pub fn with_screenshot(mut self, screenshot: impl Into<String>) -> Self {
+        self.manifest.assets.screenshots.push(screenshot.into());
+        self
+    }
+
+    /// Set the accent color, as `#rrggbb`
+    pub fn with_accent_color(mut self, color: impl Into<String>) -> Self {
+        self.manifest.assets.accent_color = Some(color.into());
+        self
+    }
+
+    /// Add display strings for `locale`, replacing any added before
+    pub fn with_localized(mut self, locale: impl Into<String>, strings: LocalizedStrings) -> Self {
+        self.manifest.i18n.insert(locale.into(), strings);
+        self
+    }
+
+    /// Attach another tool's metadata under `[extra.<key>]`
+    pub fn with_extra(mut self, key: impl Into<String>, value: impl Into<toml::Value>) -> Self {
+        self.manifest.extra.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn with_test(mut self, name: impl Into<String>, test: TappletTest) -> Self {
+        self.manifest.tests.insert(name.into(), test);
+        self
+    }
+
+    /// Subscribe to a host event, delivered to the `on_event` method unless
+    /// [`TappletManifestBuilder::with_event_handler`] names another one
+    pub fn with_event(mut self, kind: EventKind) -> Self {
+        if !self.manifest.events.is_subscribed(kind) {
+            self.manifest.events.subscribe.push(kind);
+        }
+        self
+    }
+
+    pub fn with_event_handler(mut self, method: impl Into<String>) -> Self {
+        self.manifest.events.handler = Some(method.into());
+        self
+    }
+
+    /// Let the tapplet send HTTP requests to `host`, see [`NetworkPermissions`]
+    pub fn with_allowed_host(mut self, host: impl Into<String>) -> Self {
+        self.manifest
+            .permissions
+            .network
+            .get_or_insert_with(NetworkPermissions::default)
+            .allowed_hosts
+            .push(host.into());
+        self
+    }
+
+    /// Let the tapplet query the chain, see [`ChainPermissions`]
+    pub fn with_chain_read(mut self) -> Self {
+        self.manifest.permissions.chain = Some(ChainPermissions { read: true });
+        self
+    }
+
+    pub fn with_scheduled_task(mut self, name: impl Into<String>, task: ScheduledTask) -> Self {
+        self.manifest.schedule.insert(name.into(), task);
+        self
+    }
+
+    /// Depend on another tapplet, e.g. `with_dependency("wallet", ">=1.2")`
+    pub fn with_dependency(
+        mut self,
+        name: impl Into<String>,
+        version_req: impl Into<String>,
+    ) -> Self {
+        self.manifest
+            .dependencies
+            .insert(name.into(), version_req.into());
+        self
+    }
+
+    /// List a file of the tapplet with the hex encoded SHA-256 of its contents
+    pub fn with_artifact(mut self, file: impl Into<String>, sha256: impl Into<String>) -> Self {
+        self.manifest.artifacts.insert(file.into(), sha256.into());
+        self
+    }
+
+    /// Finish the manifest, failing with [`TappletError::ManifestIssues`] if it is
+    /// incomplete or inconsistent
+    pub fn build(self) -> Result<TappletManifest> {
+        let issues = self.manifest.validate();
+        if !issues.is_empty() {
+            return Err(TappletError::ManifestIssues { issues }.into());
+        }
+        Ok(self.manifest)
+    }
+
+    /// Finish the manifest without checking it, e.g. for tests of invalid manifests
+    pub fn build_unchecked(self) -> TappletManifest {
+        self.manifest
+    }
+}
+
+impl MethodDefinition {
+    pub fn new(
+        description: impl Into<String>,
+        return_type: ParamType,
+        returns_description: impl Into<String>,
+    ) -> Self {
+        Self {
+            description: description.into(),
+            params: HashMap::new(),
+            returns: ReturnDefinition {
+                return_type,
+                description: returns_description.into(),
+            },
+            permissions: Vec::new(),
+            user_only: false,
+            deprecated: None,
+            timeout_ms: None,
+            max_fuel: None,
+            max_memory: None,
+            pure: false,
+        }
+    }
+
+    pub fn with_param(
+        mut self,
+        name: impl Into<String>,
+        param_type: ParamType,
+        description: impl Into<String>,
+    ) -> Self {
+        self.params.insert(
+            name.into(),
+            ParamDefinition {
+                param_type,
+                description: description.into(),
+                required: true,
+                default: None,
+            },
+        );
+        self
+    }
+
+    /// Add a param calls may leave out
+    pub fn with_optional_param(
+        self,
+        name: impl Into<String>,
+        param_type: ParamType,
+        description: impl Into<String>,
+    ) -> Self {
+        let name = name.into();
+        let mut method = self.with_param(name.clone(), param_type, description);
+        if let Some(param) = method.params.get_mut(&name) {
+            param.required = false;
+        }
+        method
+    }
+
+    /// Add a param that gets `default` when a call leaves it out
+    pub fn with_param_default(
+        self,
+        name: impl Into<String>,
+        param_type: ParamType,
+        description: impl Into<String>,
+        default: impl Into<toml::Value>,
+    ) -> Self {
+        let name = name.into();
+        let mut method = self.with_param(name.clone(), param_type, description);
+        if let Some(param) = method.params.get_mut(&name) {
+            param.default = Some(default.into());
+        }
+        method
+    }
+
+    /// Mark the method deprecated
+    pub fn with_deprecation(mut self, deprecation: MethodDeprecation) -> Self {
+        self.deprecated = Some(deprecation);
+        self
+    }
+
+    /// Let calls run for `timeout` instead of the host's timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout_ms = Some(timeout.as_millis() as u64);
+        self
+    }
+
+    /// Give calls this execution budget instead of the host's
+    pub fn with_max_fuel(mut self, max_fuel: u64) -> Self {
+        self.max_fuel = Some(max_fuel);
+        self
+    }
+
+    /// Let calls use this many bytes of memory instead of the host's limit
+    pub fn with_max_memory(mut self, max_memory: u64) -> Self {
+        self.max_memory = Some(max_memory);
+        self
+    }
+
+    /// Require the caller to hold a permission
+    pub fn with_permission(mut self, permission: impl Into<String>) -> Self {
+        self.permissions.push(permission.into());
+        self
+    }
+
+    /// Only allow calls initiated directly by the user
+    pub fn with_user_only(mut self) -> Self {
+        self.user_only = true;
+        self
+    }
+
+    /// Mark the result as depending only on the arguments, so hosts may cache it
+    pub fn with_pure(mut self) -> Self {
+        self.pure = true;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_manifest() {
+        let public_key = "ab".repeat(32);
+        let manifest = TappletManifest::builder("greeter", "0.1.0")
+            .with_friendly_name("Greeter")
+            .with_publisher("tari", &public_key)
+            .with_license("MIT")
+            .with_git("https://github.com/tari-project/greeter", "main")
+            .with_runtime(RuntimeKind::Lua)
+            .with_method(
+                "greet",
+                MethodDefinition::new("Returns a greeting.", ParamType::String, "A greeting.")
+                    .with_param("name", ParamType::String, "The name to greet.")
+                    .with_permission("greet"),
+            )
+            .with_event(EventKind::NewBlock)
+            .with_event_handler("greet")
+            .with_category("Social")
+            .with_tag("greeting")
+            .build()
+            .unwrap();
+        assert_eq!(manifest.api.methods, vec!["greet"]);
+        assert!(manifest.in_category("social") && manifest.has_tag("Greeting"));
+        assert_eq!(
+            manifest.api.method("greet").unwrap().permissions,
+            vec!["greet"]
+        );
+
+        // What the builder produces is a manifest the strict parser accepts
+        let toml = manifest.to_canonical_toml().unwrap();
+        let parsed = TappletManifest::from_toml_str_strict(&toml).unwrap();
+        assert_eq!(parsed.digest().unwrap(), manifest.digest().unwrap());
+
+        let err = TappletManifest::builder("", "one")
+            .with_event(EventKind::NewBlock)
+            .build()
+            .unwrap_err();
+        let Some(TappletError::ManifestIssues { issues }) = err.downcast_ref() else {
+            panic!("unexpected error: {:#}", err);
+        };
+        let fields: Vec<_> = issues.iter().map(|issue| issue.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "name",
+                "friendly_name",
+                "publisher",
+                "version",
+                "public_key",
+                "license",
+                "events.handler"
+            ]
+        );
+    }
+}
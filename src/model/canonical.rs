@@ -0,0 +1,183 @@
+//! Canonical encoding of manifests for hashing and signing
+
+use anyhow::Result;
+
+use super::TappletManifest;
+use crate::checksum::sha256_hex;
+use crate::error::TappletError;
+
+impl TappletManifest {
+    /// Serialize the manifest in its canonical form.
+    ///
+    /// Keys are sorted, optional fields that aren't set are left out and layout is
+    /// whatever the TOML serializer emits, so two manifests with the same content
+    /// always produce the same bytes, however their source files were written.
+    pub fn to_canonical_toml(&self) -> Result<String> {
+        self.canonical_toml(true)
+    }
+
+    /// Hex encoded SHA-256 of the canonical manifest, without its `[sigs]` section
+    /// so that signatures over the digest can be stored in the manifest itself
+    pub fn digest(&self) -> Result<String> {
+        Ok(sha256_hex(self.canonical_toml(false)?.as_bytes()))
+    }
+
+    fn canonical_toml(&self, include_sigs: bool) -> Result<String> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            TappletError::InvalidManifest(format!(
+                "failed to encode manifest '{}': {}",
+                self.name, e
+            ))
+        };
+        let mut value = sorted(toml::Value::try_from(self).map_err(|e| invalid(&e))?);
+        if !include_sigs && let Some(table) = value.as_table_mut() {
+            table.remove("sigs");
+        }
+        Ok(toml::to_string(&value).map_err(|e| invalid(&e))?)
+    }
+}
+
+/// `value` with the keys of every table in it sorted, whether or not `toml`
+/// keeps tables in insertion order, e.g. ones deserialized from hash maps
+fn sorted(value: toml::Value) -> toml::Value {
+    match value {
+        toml::Value::Table(table) => {
+            let mut entries: Vec<_> = table.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            toml::Value::Table(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sorted(value)))
+                    .collect(),
+            )
+        }
+        toml::Value::Array(items) => toml::Value::Array(items.into_iter().map(sorted).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Canonical form of the manifest in `test_canonical_toml_golden_bytes`.
+    /// Changing it changes every manifest digest, invalidating signatures.
+    const GOLDEN: &str = r#"description = "Fixed bytes"
+friendly_name = "Golden"
+name = "golden"
+public_key = "test_public_key"
+publisher = "test_publisher"
+version = "1.2.3"
+
+[api]
+methods = ["b", "a"]
+
+[dependencies]
+alpha = "*"
+zeta = "^1"
+
+[sigs]
+todo = "golden"
+"#;
+    const GOLDEN_DIGEST: &str = "add38c612d1367f6755ac80e14a2b99640474dd5a9602f1a6ad9dbfc1eba7b77";
+
+    #[test]
+    fn test_canonical_toml_and_digest() {
+        let manifest = TappletManifest::from_toml_str(
+            r#"
+name = "greeter"
+version = "0.1.0"
+friendly_name = "Greeter"
+description = "Says hello"
+publisher = "test_publisher"
+public_key = "test_public_key"
+
+[api]
+methods = ["greet", "wave"]
+
+[api.wave]
+description = "Waves."
+returns = { type = "null", description = "Nothing" }
+
+[api.greet]
+description = "Greets."
+params = { name = { type = "string", description = "Who" }, times = { type = "u32", description = "How often" } }
+returns = { type = "string", description = "A greeting" }
+
+[sigs]
+todo = "first"
+"#,
+        )
+        .unwrap();
+        // The same content written differently
+        let reordered = TappletManifest::from_toml_str(
+            r#"
+public_key = "test_public_key"
+publisher = "test_publisher"
+description = "Says hello"
+friendly_name   =   "Greeter"
+version = "0.1.0"
+name = "greeter"
+sigs = { todo = "second" }
+
+[api]
+methods = ["greet", "wave"]
+wave = { returns = { description = "Nothing", type = "void" }, description = "Waves." }
+
+[api.greet]
+description = "Greets."
+returns = { description = "A greeting", type = "string" }
+params.times = { description = "How often", type = "U32" }
+params.name = { description = "Who", type = "string" }
+"#,
+        )
+        .unwrap();
+
+        let canonical = manifest.to_canonical_toml().unwrap();
+        assert_eq!(
+            TappletManifest::from_toml_str(&canonical)
+                .unwrap()
+                .to_canonical_toml()
+                .unwrap(),
+            canonical
+        );
+        assert!(canonical.find("[api.greet]").unwrap() < canonical.find("[api.wave]").unwrap());
+        assert_ne!(canonical, reordered.to_canonical_toml().unwrap());
+
+        // Only the signatures differ, which the digest leaves out
+        assert_eq!(manifest.digest().unwrap(), reordered.digest().unwrap());
+        assert_eq!(manifest.digest().unwrap().len(), 64);
+
+        let mut changed = manifest.clone();
+        changed.version = "0.1.1".to_string();
+        assert_ne!(changed.digest().unwrap(), manifest.digest().unwrap());
+    }
+
+    #[test]
+    fn test_canonical_toml_golden_bytes() {
+        let manifest = TappletManifest::from_toml_str(
+            r#"
+version = "1.2.3"
+name = "golden"
+publisher = "test_publisher"
+friendly_name = "Golden"
+description = "Fixed bytes"
+public_key = "test_public_key"
+
+[dependencies]
+zeta = "^1"
+alpha = "*"
+
+[api]
+methods = ["b", "a"]
+
+[sigs]
+todo = "golden"
+"#,
+        )
+        .unwrap();
+        let canonical = manifest.to_canonical_toml().unwrap();
+        assert_eq!(canonical, GOLDEN);
+        assert_eq!(manifest.digest().unwrap(), GOLDEN_DIGEST);
+    }
+}
@@ -0,0 +1,158 @@
+//! Translated display strings, declared in the manifest's `[i18n.<locale>]` sections
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ManifestIssue, TappletManifest};
+
+/// Strings replacing the manifest's defaults for one locale. Anything left out
+/// falls back to the default.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct LocalizedStrings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub friendly_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Method descriptions, keyed by method name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub methods: BTreeMap<String, String>,
+}
+
+/// Lowercase with `-` separators, so `pt_BR` and `pt-br` are the same locale
+fn normalize(locale: &str) -> String {
+    locale.trim().replace('_', "-").to_lowercase()
+}
+
+impl TappletManifest {
+    /// Locales with translated strings, as declared
+    pub fn locales(&self) -> impl Iterator<Item = &str> {
+        self.i18n.keys().map(String::as_str)
+    }
+
+    /// The manifest with its display strings in `locale`.
+    ///
+    /// Strings are taken from the section for the exact locale, then from the
+    /// section for its language (`de` for `de-CH`), then from the defaults.
+    /// Locales match ignoring case and `_`/`-`.
+    pub fn localized(&self, locale: &str) -> TappletManifest {
+        let locale = normalize(locale);
+        let language = locale.split('-').next().unwrap_or_default();
+        let section = |wanted: &str| {
+            self.i18n
+                .iter()
+                .find(|(declared, _)| normalize(declared) == wanted)
+                .map(|(_, strings)| strings)
+        };
+        // Most specific first
+        let mut sections = Vec::new();
+        sections.extend(section(&locale));
+        if language != locale {
+            sections.extend(section(language));
+        }
+
+        let mut manifest = self.clone();
+        if let Some(friendly_name) = sections.iter().find_map(|s| s.friendly_name.as_ref()) {
+            manifest.friendly_name = friendly_name.clone();
+        }
+        if let Some(description) = sections.iter().find_map(|s| s.description.as_ref()) {
+            manifest.description = Some(description.clone());
+        }
+        for (name, method) in &mut manifest.api.method_definitions {
+            if let Some(description) = sections.iter().find_map(|s| s.methods.get(name)) {
+                method.description = description.clone();
+            }
+        }
+        manifest
+    }
+
+    /// Problems with the `[i18n]` sections
+    pub(super) fn i18n_issues(&self) -> Vec<ManifestIssue> {
+        let mut issues = Vec::new();
+        for (locale, strings) in &self.i18n {
+            let valid = !locale.is_empty()
+                && locale.split(['-', '_']).all(|part| {
+                    !part.is_empty() && part.bytes().all(|b| b.is_ascii_alphanumeric())
+                });
+            if !valid {
+                issues.push(ManifestIssue::new(
+                    format!("i18n.{}", locale),
+                    "must be a locale like en or pt-BR",
+                ));
+            }
+            if strings
+                .friendly_name
+                .as_ref()
+                .is_some_and(|name| name.trim().is_empty())
+            {
+                issues.push(ManifestIssue::new(
+                    format!("i18n.{}.friendly_name", locale),
+                    "must not be empty",
+                ));
+            }
+            for method in strings.methods.keys() {
+                if !self.api.method_definitions.contains_key(method) {
+                    issues.push(ManifestIssue::new(
+                        format!("i18n.{}.methods.{}", locale, method),
+                        "method is not defined in api",
+                    ));
+                }
+            }
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn test_localized_strings() {
+        let toml = test_utils::manifest_toml("greeter", "0.1.0").replace(
+            "[api]",
+            r#"[i18n.de]
+friendly_name = "Grüßer"
+description = "Ein Test-Tapplet"
+
+[i18n.de.methods]
+greet = "Gibt einen Gruß zurück."
+
+[i18n.de-CH]
+friendly_name = "Grüezi"
+
+[i18n.fr.methods]
+wave = "Fait signe."
+
+[api]"#,
+        );
+        let manifest = TappletManifest::from_toml_str(&toml).unwrap();
+        assert_eq!(
+            manifest.locales().collect::<Vec<_>>(),
+            ["de", "de-CH", "fr"]
+        );
+
+        // The region falls back to the language, then to the defaults
+        let swiss = manifest.localized("de_ch");
+        assert_eq!(swiss.friendly_name, "Grüezi");
+        assert_eq!(swiss.description.as_deref(), Some("Ein Test-Tapplet"));
+        assert_eq!(
+            swiss.api.method_definitions["greet"].description,
+            "Gibt einen Gruß zurück."
+        );
+        let unknown = manifest.localized("ja");
+        assert_eq!(unknown.friendly_name, "greeter tapplet");
+        assert_eq!(
+            unknown.api.method_definitions["greet"].description,
+            "Returns a greeting message."
+        );
+
+        let fields: Vec<_> = manifest
+            .i18n_issues()
+            .into_iter()
+            .map(|issue| issue.field)
+            .collect();
+        assert_eq!(fields, ["i18n.fr.methods.wave"]);
+    }
+}
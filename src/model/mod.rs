@@ -1,6 +1,31 @@
+mod artifacts;
+mod assets;
+mod builder;
+mod canonical;
+mod i18n;
+mod openrpc;
+mod param_type;
+mod validation;
+
 use anyhow::Result;
+
+use crate::error::TappletError;
+pub use assets::{AssetsConfig, MAX_ICON_SIZE, MAX_SCREENSHOT_SIZE};
+pub use builder::TappletManifestBuilder;
+pub use i18n::LocalizedStrings;
+pub use openrpc::{OPENRPC_VERSION, json_schema};
+pub use param_type::{ParamType, decode_base64, encode_base64};
+pub use semver::{Version, VersionReq};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::Path};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+pub use validation::ManifestIssue;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TappletManifest {
@@ -9,9 +34,81 @@ pub struct TappletManifest {
     pub friendly_name: String,
     pub description: Option<String>,
     pub publisher: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git: Option<GitConfig>,
+    /// Which host runs the tapplet. Older manifests without it are detected from their files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<RuntimeConfig>,
     pub api: ApiConfig,
     pub sigs: SigsConfig,
     pub public_key: String,
+    /// SPDX license expression, e.g. `MIT OR Apache-2.0`. Older manifests
+    /// without one still parse, but fail [`Self::validate`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    /// Host API versions the tapplet runs on, as a semver requirement such as
+    /// `>=2.1, <3`. A bare version like `2` is a minimum.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requires_host_api: Option<String>,
+    /// Withdrawn by the publisher: hidden from search and refused by installers
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub yanked: bool,
+    /// Still listed, but superseded or no longer maintained
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<TappletDeprecation>,
+    /// Store sections the tapplet is listed under, e.g. `finance`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    /// Free-form keywords for search
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Icon, screenshots and colors for store fronts
+    #[serde(default, skip_serializing_if = "AssetsConfig::is_empty")]
+    pub assets: AssetsConfig,
+    /// Translated display strings, keyed by locale such as `de` or `pt-BR`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub i18n: BTreeMap<String, LocalizedStrings>,
+    /// Example calls checked by [`crate::testing::TappletTestHarness`], keyed by test name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tests: BTreeMap<String, TappletTest>,
+    /// Host events the tapplet wants to be notified of
+    #[serde(default, skip_serializing_if = "EventsConfig::is_empty")]
+    pub events: EventsConfig,
+    /// Host capabilities the tapplet asks for beyond the wallet API, e.g. network access
+    #[serde(default, skip_serializing_if = "PermissionsConfig::is_empty")]
+    pub permissions: PermissionsConfig,
+    /// Methods the host runs periodically, keyed by task name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub schedule: BTreeMap<String, ScheduledTask>,
+    /// Other tapplets this one needs, with a semver requirement such as `>=1.2`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, String>,
+    /// Hex encoded SHA-256 of the tapplet's files, keyed by path relative to the
+    /// tapplet directory, checked by installers and hosts
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub artifacts: BTreeMap<String, String>,
+    /// Metadata of other tools, which this crate keeps but doesn't interpret,
+    /// keyed by tool. Read it with [`Self::extra`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, toml::Value>,
+}
+
+/// A license someone accepts, given by its SPDX id in any case
+fn licensee(name: &str) -> Option<spdx::Licensee> {
+    let name = spdx::identifiers::LICENSES
+        .iter()
+        .find(|(id, ..)| id.eq_ignore_ascii_case(name))
+        .map_or(name, |(id, ..)| *id);
+    let expression = spdx::Expression::parse(name).ok()?;
+    let req = &expression.requirements().next()?.req;
+    let license = match &req.license {
+        spdx::LicenseItem::Spdx { id, .. } => spdx::LicenseItem::Spdx {
+            id: *id,
+            or_later: false,
+        },
+        other => other.clone(),
+    };
+    Some(spdx::Licensee::new(license, req.exception))
 }
 
 impl TappletManifest {
@@ -19,11 +116,161 @@ impl TappletManifest {
         format!("{}@{}", self.name.replace("-", "_"), self.version)
     }
 
+    /// `publisher/name`, which tells apart tapplets of the same name from
+    /// different publishers
+    pub fn qualified_name(&self) -> String {
+        format!("{}/{}", self.publisher, self.name.replace("-", "_"))
+    }
+
+    /// The canonical name qualified with the publisher, `publisher/name@version`
+    pub fn fully_qualified_name(&self) -> String {
+        format!("{}/{}", self.publisher, self.canonical_name())
+    }
+
+    /// The `[extra.<key>]` metadata as a `T`, `None` if there is none.
+    ///
+    /// Fails with [`TappletError::InvalidManifest`] if it isn't a valid `T`.
+    pub fn extra<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let Some(value) = self.extra.get(key) else {
+            return Ok(None);
+        };
+        let extra = value.clone().try_into().map_err(|e| {
+            TappletError::InvalidManifest(format!("extra.{}: {}", key, e.message()))
+        })?;
+        Ok(Some(extra))
+    }
+
+    /// Whether the tapplet is listed under `category`, ignoring case
+    pub fn in_category(&self, category: &str) -> bool {
+        self.categories
+            .iter()
+            .any(|listed| listed.eq_ignore_ascii_case(category))
+    }
+
+    /// Whether the tapplet has `tag`, ignoring case
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags
+            .iter()
+            .any(|listed| listed.eq_ignore_ascii_case(tag))
+    }
+
+    /// Whether the tapplet may be used under the licenses in `allowed` alone,
+    /// compared ignoring case. `MIT OR GPL-3.0` only needs one of the two, `MIT AND
+    /// Apache-2.0` both. False without a valid license expression.
+    pub fn licensed_under<'a>(&self, allowed: impl IntoIterator<Item = &'a str>) -> bool {
+        let Some(Ok(expression)) = self.license.as_deref().map(spdx::Expression::parse) else {
+            return false;
+        };
+        let licensees: Vec<_> = allowed.into_iter().filter_map(licensee).collect();
+        expression.evaluate(|req| licensees.iter().any(|licensee| licensee.satisfies(req)))
+    }
+
+    /// Whether `other_name` is the tapplet's name, with `-` and `_` interchangeable.
+    /// It may be qualified with the publisher, as in `publisher/name`.
     pub fn name_matches(&self, other_name: &str) -> bool {
+        if let Some((publisher, other_name)) = other_name.split_once('/') {
+            return publisher == self.publisher && self.name_matches(other_name);
+        }
         self.name == other_name
             || self.name.replace("-", "_") == other_name
             || self.name.replace("_", "-") == other_name
     }
+
+    /// Parse the manifest version as semver
+    pub fn semver(&self) -> Result<Version> {
+        Version::parse(&self.version).map_err(|e| {
+            TappletError::InvalidVersion(format!("{} for {}: {}", self.version, self.name, e))
+                .into()
+        })
+    }
+
+    /// The host API versions the tapplet runs on, `None` if it runs on any
+    pub fn host_api_requirement(&self) -> Result<Option<VersionReq>> {
+        let Some(required) = &self.requires_host_api else {
+            return Ok(None);
+        };
+        let required = required.trim();
+        let bare =
+            !required.is_empty() && required.bytes().all(|b| b.is_ascii_digit() || b == b'.');
+        let parsed = if bare {
+            VersionReq::parse(&format!(">={}", required))
+        } else {
+            VersionReq::parse(required)
+        };
+        parsed.map(Some).map_err(|e| {
+            TappletError::InvalidManifest(format!(
+                "requires_host_api '{}' of {}: {}",
+                required, self.name, e
+            ))
+            .into()
+        })
+    }
+
+    /// Fail with [`TappletError::UnsupportedHostApi`] unless the tapplet runs on
+    /// host API `version`, usually [`HOST_API_VERSION`]
+    pub fn ensure_host_api(&self, version: &Version) -> Result<()> {
+        match self.host_api_requirement()? {
+            Some(required) if !required.matches(version) => Err(TappletError::UnsupportedHostApi {
+                name: self.canonical_name(),
+                required: required.to_string(),
+                supported: version.to_string(),
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether this tapplet's version satisfies a requirement.
+    ///
+    /// Tapplets whose version is not valid semver never satisfy a requirement.
+    pub fn satisfies(&self, req: &VersionReq) -> bool {
+        self.semver().is_ok_and(|version| req.matches(&version))
+    }
+
+    /// The declared entrypoint inside `dir`, or `None` for manifests without a
+    /// `[runtime]` section. Fails if the tapplet is declared for another runtime.
+    pub fn entrypoint_path(&self, kind: RuntimeKind, dir: &Path) -> Result<Option<PathBuf>> {
+        let Some(runtime) = &self.runtime else {
+            return Ok(None);
+        };
+        if runtime.kind != kind {
+            return Err(TappletError::InvalidManifest(format!(
+                "tapplet '{}' declares a {} runtime, not {}",
+                self.name, runtime.kind, kind
+            ))
+            .into());
+        }
+        runtime.entrypoint_path(dir).map(Some)
+    }
+
+    /// Whether the version is a semver prerelease such as `2.0.0-beta.1`
+    pub fn is_prerelease(&self) -> bool {
+        self.semver().is_ok_and(|version| !version.pre.is_empty())
+    }
+
+    /// Order two manifests by version, with invalid semver sorting before valid versions
+    pub fn cmp_version(&self, other: &Self) -> Ordering {
+        match (self.semver(), other.semver()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            (Err(_), Ok(_)) => Ordering::Less,
+            (Ok(_), Err(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => self.version.cmp(&other.version),
+        }
+    }
+}
+
+/// Whether a relative path stays inside the directory it is joined to
+fn is_contained(path: &Path) -> bool {
+    !path.as_os_str().is_empty()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Parse a version requirement such as `^1.2` or `>=0.3, <0.5`
+pub fn parse_version_req(req: &str) -> Result<VersionReq> {
+    VersionReq::parse(req)
+        .map_err(|e| TappletError::InvalidVersion(format!("requirement {}: {}", req, e)).into())
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,6 +279,97 @@ pub struct GitConfig {
     pub rev: String,
 }
 
+/// Host runtimes a tapplet can be written for
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum RuntimeKind {
+    Wasm,
+    Lua,
+}
+
+impl RuntimeKind {
+    /// File extension of the artifact, also used for the installed `<name>.<ext>` file
+    pub fn extension(self) -> &'static str {
+        match self {
+            RuntimeKind::Wasm => "wasm",
+            RuntimeKind::Lua => "lua",
+        }
+    }
+
+    pub fn default_entrypoint(self) -> &'static str {
+        match self {
+            RuntimeKind::Wasm => "tapplet.wasm",
+            RuntimeKind::Lua => "main.lua",
+        }
+    }
+}
+
+impl fmt::Display for RuntimeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// Why a tapplet is deprecated, from the manifest's or registry index's `deprecated` entry
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct TappletDeprecation {
+    /// Version the tapplet was deprecated in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Name of the tapplet to use instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<String>,
+}
+
+impl fmt::Display for TappletDeprecation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deprecated")?;
+        if let Some(since) = &self.since {
+            write!(f, " since {}", since)?;
+        }
+        if let Some(reason) = &self.reason {
+            write!(f, ": {}", reason)?;
+        }
+        if let Some(replacement) = &self.replacement {
+            write!(f, ", use {} instead", replacement)?;
+        }
+        Ok(())
+    }
+}
+
+/// The manifest's `[runtime]` section
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RuntimeConfig {
+    pub kind: RuntimeKind,
+    /// File the host loads, relative to the tapplet directory. Defaults to
+    /// `tapplet.wasm` or `main.lua`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entrypoint: Option<String>,
+}
+
+impl RuntimeConfig {
+    pub fn entrypoint(&self) -> &str {
+        self.entrypoint
+            .as_deref()
+            .unwrap_or(self.kind.default_entrypoint())
+    }
+
+    /// Resolve the entrypoint inside `dir`, refusing paths that would leave it
+    pub fn entrypoint_path(&self, dir: &Path) -> Result<PathBuf> {
+        let entrypoint = Path::new(self.entrypoint());
+        if !is_contained(entrypoint) {
+            return Err(TappletError::InvalidManifest(format!(
+                "runtime entrypoint '{}' must be a relative path inside the tapplet directory",
+                entrypoint.display()
+            ))
+            .into());
+        }
+        Ok(dir.join(entrypoint))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ApiConfig {
     pub methods: Vec<String>,
@@ -45,37 +383,380 @@ pub struct MethodDefinition {
     #[serde(default)]
     pub params: HashMap<String, ParamDefinition>,
     pub returns: ReturnDefinition,
+    /// Permissions the caller must hold to invoke this method
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<String>,
+    /// Only allow calls initiated directly by the user, not by other tapplets
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub user_only: bool,
+    /// Still callable, but hosts warn on every call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<MethodDeprecation>,
+    /// How long a call may run, in milliseconds, in place of the host's timeout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Execution budget of a call, in place of the host's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fuel: Option<u64>,
+    /// Most memory a call may use, in bytes, in place of the host's limit: the
+    /// Lua state, or the WASM module's memory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory: Option<u64>,
+    /// The result only depends on the arguments, so hosts with a result cache
+    /// may answer repeated calls without running the tapplet
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pure: bool,
+}
+
+/// Why a method is deprecated, from its `deprecated` entry
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct MethodDeprecation {
+    /// Tapplet version the method was deprecated in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Name of the method to call instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_instead: Option<String>,
+}
+
+impl fmt::Display for MethodDeprecation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deprecated")?;
+        if let Some(since) = &self.since {
+            write!(f, " since {}", since)?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        if let Some(use_instead) = &self.use_instead {
+            write!(f, ", use {} instead", use_instead)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ParamDefinition {
     #[serde(rename = "type")]
-    pub param_type: String,
+    pub param_type: ParamType,
     pub description: String,
+    /// Whether calls must pass the param. Params of type `optional<T>` and params
+    /// with a `default` may be left out either way.
+    #[serde(default = "required_by_default", skip_serializing_if = "is_required")]
+    pub required: bool,
+    /// Value passed to the tapplet when a call leaves the param out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<toml::Value>,
+}
+
+fn required_by_default() -> bool {
+    true
+}
+
+fn is_required(required: &bool) -> bool {
+    *required
+}
+
+impl ParamDefinition {
+    /// Whether a call may leave the param out
+    pub fn is_optional(&self) -> bool {
+        !self.required || self.default.is_some() || self.param_type.is_optional()
+    }
+
+    /// The default as JSON, converted with [`ParamType::coerce`]. Fails if it
+    /// doesn't match the param's type.
+    pub fn default_value(&self) -> Result<Option<serde_json::Value>, String> {
+        let Some(default) = &self.default else {
+            return Ok(None);
+        };
+        let value = serde_json::to_value(default).map_err(|e| e.to_string())?;
+        self.param_type.coerce(value).map(Some)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ReturnDefinition {
     #[serde(rename = "type")]
-    pub return_type: String,
+    pub return_type: ParamType,
     pub description: String,
 }
 
+/// A method call with its expected result, declared in the manifest's `[tests]` section
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TappletTest {
+    pub method: String,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub args: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<serde_json::Value>,
+    /// Expected error code instead of a result, e.g. `PERMISSION_DENIED`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_error: Option<String>,
+    /// Permissions granted to the caller for this test
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<String>,
+}
+
+/// Kinds of host events a tapplet can subscribe to
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    NewBlock,
+    TransactionReceived,
+    SlotChanged,
+}
+
+/// The manifest's `[events]` section
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct EventsConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subscribe: Vec<EventKind>,
+    /// Method called with each event, `on_event` if not set. It must be listed in `api.methods`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handler: Option<String>,
+}
+
+impl EventsConfig {
+    pub const DEFAULT_HANDLER: &'static str = "on_event";
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribe.is_empty() && self.handler.is_none()
+    }
+
+    pub fn handler(&self) -> &str {
+        self.handler.as_deref().unwrap_or(Self::DEFAULT_HANDLER)
+    }
+
+    pub fn is_subscribed(&self, kind: EventKind) -> bool {
+        self.subscribe.contains(&kind)
+    }
+}
+
+/// The manifest's `[permissions]` section: host capabilities the tapplet asks for,
+/// which hosts only grant to tapplets that declare them
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct PermissionsConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkPermissions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<ChainPermissions>,
+}
+
+impl PermissionsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.network.is_none() && self.chain.is_none()
+    }
+
+    /// Whether the tapplet may query the chain, see [`ChainPermissions::read`]
+    pub fn allows_chain_read(&self) -> bool {
+        self.chain.as_ref().is_some_and(|chain| chain.read)
+    }
+
+    /// Whether the tapplet may send HTTP requests to `host`
+    pub fn allows_network_host(&self, host: &str) -> bool {
+        self.network
+            .as_ref()
+            .is_some_and(|network| network.allows(host))
+    }
+}
+
+/// The manifest's `[permissions.network]` section
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct NetworkPermissions {
+    /// Hosts the tapplet may reach, such as `api.example.com`, or `*.example.com`
+    /// for every subdomain of `example.com`
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+}
+
+impl NetworkPermissions {
+    /// Whether `host` is one of the allowed hosts, ignoring case
+    pub fn allows(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.allowed_hosts.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+            match allowed.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .and_then(|subdomain| subdomain.strip_suffix('.'))
+                    .is_some_and(|subdomain| !subdomain.is_empty()),
+                None => host == allowed,
+            }
+        })
+    }
+}
+
+/// The manifest's `[permissions.chain]` section
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ChainPermissions {
+    /// Read block headers, kernels and the tip height from the base node
+    #[serde(default)]
+    pub read: bool,
+}
+
+/// A method run by [`crate::scheduler::TappletScheduler`], declared in the manifest's
+/// `[schedule]` section. Exactly one of `interval_secs` and `cron` must be set.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScheduledTask {
+    pub method: String,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub args: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+    /// Cron expression evaluated in UTC, e.g. `*/5 * * * *`. A leading seconds field is optional.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    /// Each run is delayed by a random amount of up to this many seconds
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub jitter_secs: u64,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SigsConfig {
     pub todo: String,
 }
 
+impl ApiConfig {
+    /// Definition of a declared method, if it has one
+    pub fn method(&self, name: &str) -> Option<&MethodDefinition> {
+        self.method_definitions.get(name)
+    }
+
+    /// Check a call's arguments against the method's declared params, see
+    /// [`MethodDefinition::coerce_args`]. Methods without a definition accept anything.
+    pub fn coerce_args(
+        &self,
+        method: &str,
+        args: serde_json::Value,
+    ) -> Result<serde_json::Value, String> {
+        match self.method(method) {
+            Some(definition) => definition.coerce_args(args),
+            None => Ok(args),
+        }
+    }
+}
+
+impl MethodDefinition {
+    /// `timeout_ms` as a duration
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+
+    /// The named params as the type of an object holding them
+    pub fn params_type(&self) -> ParamType {
+        ParamType::Object(
+            self.params
+                .iter()
+                .map(|(name, param)| (name.clone(), param.param_type.clone()))
+                .collect(),
+        )
+    }
+
+    /// Check named arguments against the declared params, converting them with
+    /// [`ParamType::coerce`].
+    ///
+    /// `null` counts as no arguments. Params that are left out get their default,
+    /// if they have one, and fail the call if they are required. Arguments that
+    /// aren't declared are passed through, as are positional (array) arguments,
+    /// which can't be matched to param names.
+    pub fn coerce_args(&self, args: serde_json::Value) -> Result<serde_json::Value, String> {
+        let was_null = args.is_null();
+        let mut args = match args {
+            serde_json::Value::Null => serde_json::Map::new(),
+            serde_json::Value::Object(args) => args,
+            args @ serde_json::Value::Array(_) => return Ok(args),
+            _ if self.params.is_empty() => return Ok(args),
+            _ => return Err("expected an object of named arguments".to_string()),
+        };
+        let mut params: Vec<_> = self.params.iter().collect();
+        params.sort_by_key(|(name, _)| *name);
+        for (name, param) in params {
+            match args.remove(name) {
+                Some(value) => {
+                    let value = param
+                        .param_type
+                        .coerce(value)
+                        .map_err(|e| format!("invalid argument {}: {}", name, e))?;
+                    args.insert(name.clone(), value);
+                }
+                None => match param
+                    .default_value()
+                    .map_err(|e| format!("invalid default for {}: {}", name, e))?
+                {
+                    Some(value) => {
+                        args.insert(name.clone(), value);
+                    }
+                    None if param.is_optional() => {}
+                    None => return Err(format!("missing argument {}", name)),
+                },
+            }
+        }
+        if was_null && args.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+        Ok(serde_json::Value::Object(args))
+    }
+}
+
+/// Version of the host API this build of the hosts provides, see
+/// [`TappletManifest::requires_host_api`]. The major version changes when host
+/// functions are removed or change, the minor version when some are added.
+pub const HOST_API_VERSION: Version = Version::new(2, 0, 0);
+
+/// File names a tapplet directory's manifest may have, in order of preference.
+/// TOML is canonical, the other formats are accepted for generated manifests.
+pub const MANIFEST_FILE_NAMES: [&str; 4] = [
+    "manifest.toml",
+    "manifest.json",
+    "manifest.yaml",
+    "manifest.yml",
+];
+
+/// The manifest file in a tapplet directory, if it has one
+pub fn find_manifest_file(dir: &Path) -> Option<PathBuf> {
+    MANIFEST_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
 impl TappletManifest {
     /// Parse a tapplet configuration from a TOML string
     pub fn from_toml_str(toml_str: &str) -> Result<Self> {
-        Ok(toml::from_str(toml_str)?)
+        toml::from_str(toml_str).map_err(|e| TappletError::InvalidManifest(e.to_string()).into())
+    }
+
+    /// Parse a tapplet configuration from a JSON string with the same structure as the TOML
+    pub fn from_json_str(json_str: &str) -> Result<Self> {
+        serde_json::from_str(json_str)
+            .map_err(|e| TappletError::InvalidManifest(e.to_string()).into())
     }
 
-    /// Load a tapplet configuration from a TOML file
+    /// Parse a tapplet configuration from a YAML string with the same structure as the TOML
+    pub fn from_yaml_str(yaml_str: &str) -> Result<Self> {
+        serde_yaml::from_str(yaml_str)
+            .map_err(|e| TappletError::InvalidManifest(e.to_string()).into())
+    }
+
+    /// Load a tapplet configuration from a file, choosing the format by its
+    /// extension: `.json`, `.yaml` or `.yml`, and TOML otherwise
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
         let content = std::fs::read_to_string(path)?;
-        Self::from_toml_str(&content)
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json") => Self::from_json_str(&content),
+            Some("yaml" | "yml") => Self::from_yaml_str(&content),
+            _ => Self::from_toml_str(&content),
+        }
     }
 }
 
@@ -117,10 +798,362 @@ todo = "add sigs here"
         assert_eq!(config.version, "0.1.0");
         assert_eq!(config.friendly_name, "Password Manager");
         assert_eq!(
-            config.git.url,
+            config.git.as_ref().unwrap().url,
             "https://github.com/stringhandler/password_manager_tapplet"
         );
         assert_eq!(config.api.methods, vec!["greet"]);
         assert!(config.api.method_definitions.contains_key("greet"));
     }
+
+    #[test]
+    fn test_parse_semver() {
+        let config =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("wallet", "0.1.0"))
+                .unwrap();
+        assert_eq!(config.semver().unwrap(), Version::new(0, 1, 0));
+    }
+
+    #[test]
+    fn test_parse_license() {
+        let toml = crate::test_utils::manifest_toml("wallet", "0.1.0")
+            .replace(r#"license = "MIT""#, r#"license = "MIT OR Apache-2.0""#);
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        assert_eq!(config.license.as_deref(), Some("MIT OR Apache-2.0"));
+        assert!(config.licensed_under(["apache-2.0"]));
+        assert!(!config.licensed_under(["GPL-3.0"]));
+
+        let toml =
+            crate::test_utils::manifest_toml("wallet", "0.1.0").replace("license = \"MIT\"\n", "");
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        assert!(config.license.is_none());
+        assert!(!config.licensed_under(["MIT"]));
+        assert!(
+            config
+                .validate()
+                .iter()
+                .any(|issue| issue.field == "license")
+        );
+    }
+
+    #[test]
+    fn test_parse_method_permissions() {
+        let toml = crate::test_utils::manifest_toml("wallet", "0.1.0")
+            + r#"
+[api.transfer]
+description = "Sends funds."
+permissions = ["wallet:send"]
+user_only = true
+
+[api.transfer.returns]
+type = "string"
+description = "The transaction id."
+"#;
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let greet = config.api.method("greet").unwrap();
+        assert!(greet.permissions.is_empty());
+        assert!(!greet.user_only);
+        let transfer = config.api.method("transfer").unwrap();
+        assert_eq!(transfer.permissions, vec!["wallet:send"]);
+        assert!(transfer.user_only);
+    }
+
+    #[test]
+    fn test_qualified_names() {
+        let toml = crate::test_utils::manifest_toml("password-manager", "0.1.0");
+        let mut manifest = TappletManifest::from_toml_str(&toml).unwrap();
+        assert_eq!(manifest.canonical_name(), "password_manager@0.1.0");
+        assert_eq!(manifest.qualified_name(), "test_publisher/password_manager");
+        assert_eq!(
+            manifest.fully_qualified_name(),
+            "test_publisher/password_manager@0.1.0"
+        );
+        assert!(manifest.name_matches("test_publisher/password-manager"));
+        assert!(!manifest.name_matches("other_publisher/password_manager"));
+
+        manifest.publisher = "../evil".to_string();
+        assert!(
+            manifest
+                .validate()
+                .iter()
+                .any(|issue| issue.field == "publisher")
+        );
+    }
+
+    #[test]
+    fn test_extra_metadata() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct StoreListing {
+            featured: bool,
+            rank: u32,
+        }
+
+        let toml = crate::test_utils::manifest_toml("greeter", "0.1.0")
+            .replace("test_public_key", &"ab".repeat(32))
+            + r#"
+[extra.store]
+featured = true
+rank = 3
+
+[extra.ci]
+nightly = ["lint", "test"]
+"#;
+        let manifest = TappletManifest::from_toml_str_strict(&toml).unwrap();
+        let listing: Option<StoreListing> = manifest.extra("store").unwrap();
+        assert_eq!(
+            listing,
+            Some(StoreListing {
+                featured: true,
+                rank: 3
+            })
+        );
+        assert_eq!(manifest.extra::<StoreListing>("wiki").unwrap(), None);
+        let err = manifest.extra::<StoreListing>("ci").unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_MANIFEST");
+
+        // Kept as written through a round trip
+        let written =
+            TappletManifest::from_toml_str(&manifest.to_canonical_toml().unwrap()).unwrap();
+        assert_eq!(written.extra, manifest.extra);
+    }
+
+    #[test]
+    fn test_version_requirements() {
+        let mut manifest =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("wallet", "1.2.3"))
+                .unwrap();
+        assert!(manifest.satisfies(&parse_version_req("^1.2").unwrap()));
+        assert!(manifest.satisfies(&parse_version_req(">=1.0, <2").unwrap()));
+        assert!(!manifest.satisfies(&parse_version_req("^1.3").unwrap()));
+        assert!(parse_version_req("one point two").is_err());
+
+        let newer = TappletManifest {
+            version: "1.10.0".to_string(),
+            ..manifest.clone()
+        };
+        assert_eq!(manifest.cmp_version(&newer), Ordering::Less);
+
+        manifest.version = "latest".to_string();
+        assert!(manifest.semver().is_err());
+        assert!(!manifest.satisfies(&VersionReq::STAR));
+        assert_eq!(manifest.cmp_version(&newer), Ordering::Less);
+    }
+
+    #[test]
+    fn test_host_api_requirement() {
+        let mut manifest =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("wallet", "1.2.3"))
+                .unwrap();
+        assert!(manifest.host_api_requirement().unwrap().is_none());
+        manifest.ensure_host_api(&HOST_API_VERSION).unwrap();
+
+        // A bare version is a minimum, anything else a semver requirement
+        manifest.requires_host_api = Some("2".to_string());
+        manifest.ensure_host_api(&Version::new(2, 3, 0)).unwrap();
+        manifest.ensure_host_api(&Version::new(3, 0, 0)).unwrap();
+        let err = manifest
+            .ensure_host_api(&Version::new(1, 4, 0))
+            .unwrap_err();
+        assert_eq!(crate::error_code(&err), "UNSUPPORTED_HOST_API");
+        assert_eq!(
+            err.to_string(),
+            "Tapplet wallet@1.2.3 requires host API >=2, but this host provides 1.4.0"
+        );
+        manifest.requires_host_api = Some("^2.1".to_string());
+        assert!(manifest.ensure_host_api(&Version::new(3, 0, 0)).is_err());
+
+        manifest.requires_host_api = Some("two".to_string());
+        let err = manifest.ensure_host_api(&HOST_API_VERSION).unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_MANIFEST");
+        assert!(
+            manifest
+                .validate()
+                .iter()
+                .any(|issue| issue.field == "requires_host_api")
+        );
+    }
+
+    #[test]
+    fn test_param_defaults() {
+        let toml = crate::test_utils::manifest_toml("greeter", "0.1.0").replace(
+            "[api.greet.returns]",
+            r#"[api.greet.params]
+name = { type = "string", description = "Who to greet" }
+times = { type = "u64", description = "Repeats", default = 1 }
+shout = { type = "bool", description = "Upper case", required = false }
+
+[api.greet.returns]"#,
+        );
+        let manifest = TappletManifest::from_toml_str(&toml).unwrap();
+        let greet = manifest.api.method("greet").unwrap();
+        assert!(!greet.params["name"].is_optional());
+        assert!(greet.params["times"].is_optional());
+
+        // Defaults fill in, optional params stay out, required ones must be passed
+        let args = greet.coerce_args(serde_json::json!({"name": "Alice"}));
+        assert_eq!(
+            args.unwrap(),
+            serde_json::json!({"name": "Alice", "times": 1})
+        );
+        let args = greet.coerce_args(serde_json::json!({"name": "Bob", "times": 3}));
+        assert_eq!(
+            args.unwrap(),
+            serde_json::json!({"name": "Bob", "times": 3})
+        );
+        assert_eq!(
+            greet.coerce_args(serde_json::Value::Null).unwrap_err(),
+            "missing argument name"
+        );
+
+        // Only explicit values of `required` are written back
+        let written = toml::to_string(&manifest).unwrap();
+        assert!(written.contains("required = false"));
+        assert!(!written.contains("required = true"));
+    }
+
+    #[test]
+    fn test_parse_tests_section() {
+        let toml = crate::test_utils::manifest_toml("greeter", "0.1.0")
+            + r#"
+[tests.greets_alice]
+method = "greet"
+args = { name = "Alice" }
+expected = "Hello, Alice"
+permissions = ["greet"]
+"#;
+        let manifest = TappletManifest::from_toml_str(&toml).unwrap();
+        let test = &manifest.tests["greets_alice"];
+        assert_eq!(test.method, "greet");
+        assert_eq!(test.args, serde_json::json!({"name": "Alice"}));
+        assert_eq!(test.expected, Some(serde_json::json!("Hello, Alice")));
+        assert_eq!(test.permissions, vec!["greet"]);
+
+        let reparsed =
+            TappletManifest::from_toml_str(&toml::to_string(&manifest).unwrap()).unwrap();
+        assert_eq!(reparsed.tests, manifest.tests);
+    }
+
+    #[test]
+    fn test_parse_events_section() {
+        let manifest =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("greeter", "0.1.0"))
+                .unwrap();
+        assert!(manifest.events.is_empty());
+        assert!(!toml::to_string(&manifest).unwrap().contains("[events]"));
+
+        let toml = crate::test_utils::manifest_toml("greeter", "0.1.0")
+            + "[events]\nsubscribe = [\"new_block\", \"slot_changed\"]\n";
+        let manifest = TappletManifest::from_toml_str(&toml).unwrap();
+        assert!(manifest.events.is_subscribed(EventKind::NewBlock));
+        assert!(
+            !manifest
+                .events
+                .is_subscribed(EventKind::TransactionReceived)
+        );
+        assert_eq!(manifest.events.handler(), "on_event");
+
+        let bad = crate::test_utils::manifest_toml("greeter", "0.1.0")
+            + "[events]\nsubscribe = [\"new_moon\"]\n";
+        assert!(TappletManifest::from_toml_str(&bad).is_err());
+    }
+
+    #[test]
+    fn test_parse_permissions_section() {
+        let manifest =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("ticker", "0.1.0"))
+                .unwrap();
+        assert!(manifest.permissions.is_empty());
+        assert!(!manifest.permissions.allows_network_host("api.example.com"));
+        assert!(!toml::to_string(&manifest).unwrap().contains("[permissions"));
+
+        let toml = crate::test_utils::manifest_toml("ticker", "0.1.0")
+            + "[permissions.network]\nallowed_hosts = [\"api.example.com\", \"*.prices.io\"]\n";
+        let manifest = TappletManifest::from_toml_str(&toml).unwrap();
+        let permissions = &manifest.permissions;
+        assert!(permissions.allows_network_host("API.example.com"));
+        assert!(!permissions.allows_network_host("example.com"));
+        assert!(!permissions.allows_network_host("evil-api.example.com"));
+        assert!(permissions.allows_network_host("eu.prices.io"));
+        assert!(!permissions.allows_network_host("prices.io"));
+        assert!(!permissions.allows_network_host("evilprices.io"));
+        assert!(!permissions.allows_chain_read());
+
+        let toml = crate::test_utils::manifest_toml("ticker", "0.1.0")
+            + "[permissions.chain]\nread = true\n";
+        let manifest = TappletManifest::from_toml_str(&toml).unwrap();
+        assert!(manifest.permissions.allows_chain_read());
+        assert!(!manifest.permissions.allows_network_host("api.example.com"));
+    }
+
+    #[test]
+    fn test_parse_runtime_section() {
+        let toml =
+            crate::test_utils::manifest_toml("greeter", "0.1.0") + "[runtime]\nkind = \"lua\"\n";
+        let runtime = TappletManifest::from_toml_str(&toml)
+            .unwrap()
+            .runtime
+            .unwrap();
+        assert_eq!(runtime.kind, RuntimeKind::Lua);
+        assert_eq!(runtime.entrypoint(), "main.lua");
+
+        let runtime = RuntimeConfig {
+            kind: RuntimeKind::Wasm,
+            entrypoint: Some("bin/greeter.wasm".to_string()),
+        };
+        assert_eq!(
+            runtime
+                .entrypoint_path(Path::new("/tapplets/greeter"))
+                .unwrap(),
+            Path::new("/tapplets/greeter/bin/greeter.wasm")
+        );
+        for escaping in ["../greeter.wasm", "/etc/passwd", ""] {
+            let runtime = RuntimeConfig {
+                kind: RuntimeKind::Wasm,
+                entrypoint: Some(escaping.to_string()),
+            };
+            assert!(runtime.entrypoint_path(Path::new("/tapplets")).is_err());
+        }
+
+        let bad =
+            crate::test_utils::manifest_toml("greeter", "0.1.0") + "[runtime]\nkind = \"python\"\n";
+        assert!(TappletManifest::from_toml_str(&bad).is_err());
+    }
+
+    #[test]
+    fn test_json_and_yaml_manifests() {
+        let manifest =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("greeter", "0.1.0"))
+                .unwrap();
+        let canonical = manifest.to_canonical_toml().unwrap();
+
+        let json = serde_json::to_string(&manifest).unwrap();
+        let from_json = TappletManifest::from_json_str(&json).unwrap();
+        assert_eq!(from_json.to_canonical_toml().unwrap(), canonical);
+
+        let yaml = r#"
+name: greeter
+version: 0.1.0
+friendly_name: greeter tapplet
+description: Test tapplet greeter
+publisher: test_publisher
+public_key: test_public_key
+license: MIT
+api:
+  methods: [greet]
+  greet:
+    description: Returns a greeting message.
+    returns: { type: string, description: A greeting message. }
+sigs:
+  todo: add sigs here
+"#;
+        let from_yaml = TappletManifest::from_yaml_str(yaml).unwrap();
+        assert_eq!(from_yaml.to_canonical_toml().unwrap(), canonical);
+
+        let temp = tempfile::tempdir().unwrap();
+        assert!(find_manifest_file(temp.path()).is_none());
+        std::fs::write(temp.path().join("manifest.yml"), yaml).unwrap();
+        let path = find_manifest_file(temp.path()).unwrap();
+        assert_eq!(TappletManifest::from_file(&path).unwrap().name, "greeter");
+        let err = TappletManifest::from_json_str("{\"name\": \"greeter\"}").unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_MANIFEST");
+    }
 }
@@ -0,0 +1,184 @@
+//! [OpenRPC](https://spec.open-rpc.org) description of a tapplet's API
+
+use serde_json::{Map, Value, json};
+
+use super::{MethodDefinition, ParamDefinition, ParamType, TappletManifest};
+
+/// Version of the OpenRPC specification the generated documents follow
+pub const OPENRPC_VERSION: &str = "1.2.6";
+
+impl TappletManifest {
+    /// Describe the tapplet's methods as an OpenRPC document.
+    ///
+    /// Params are passed by name. Method permissions and `user_only` are included
+    /// as the `x-permissions` and `x-user-only` extensions, deprecated methods are
+    /// marked `deprecated`.
+    pub fn to_openrpc(&self) -> Value {
+        let mut info = json!({
+            "title": self.friendly_name,
+            "version": self.version,
+        });
+        if let Some(description) = &self.description {
+            info["description"] = json!(description);
+        }
+        if let Some(license) = &self.license {
+            info["license"] = json!({ "name": license });
+        }
+        let methods: Vec<Value> = self
+            .api
+            .methods
+            .iter()
+            .map(|name| openrpc_method(name, self.api.method_definitions.get(name)))
+            .collect();
+        json!({
+            "openrpc": OPENRPC_VERSION,
+            "info": info,
+            "methods": methods,
+        })
+    }
+}
+
+fn openrpc_method(name: &str, definition: Option<&MethodDefinition>) -> Value {
+    let Some(definition) = definition else {
+        return json!({
+            "name": name,
+            "paramStructure": "by-name",
+            "params": [],
+            "result": { "name": "result", "schema": {} },
+        });
+    };
+    let mut params: Vec<_> = definition.params.iter().collect();
+    params.sort_by_key(|(name, _)| *name);
+    let params: Vec<Value> = params
+        .into_iter()
+        .map(|(name, param)| {
+            json!({
+                "name": name,
+                "description": param.description,
+                "required": !param.is_optional(),
+                "schema": param_schema(param),
+            })
+        })
+        .collect();
+    let mut method = json!({
+        "name": name,
+        "description": definition.description,
+        "paramStructure": "by-name",
+        "params": params,
+        "result": {
+            "name": "result",
+            "description": definition.returns.description,
+            "schema": json_schema(&definition.returns.return_type),
+        },
+    });
+    if !definition.permissions.is_empty() {
+        method["x-permissions"] = json!(definition.permissions);
+    }
+    if definition.user_only {
+        method["x-user-only"] = json!(true);
+    }
+    if definition.deprecated.is_some() {
+        method["deprecated"] = json!(true);
+    }
+    method
+}
+
+/// JSON Schema for a param, with its default
+fn param_schema(param: &ParamDefinition) -> Value {
+    let mut schema = json_schema(&param.param_type);
+    if let Ok(Some(default)) = param.default_value() {
+        schema["default"] = default;
+    }
+    schema
+}
+
+/// JSON Schema for a manifest param or return type
+pub fn json_schema(param_type: &ParamType) -> Value {
+    match param_type {
+        ParamType::String => json!({ "type": "string" }),
+        ParamType::U64 => json!({ "type": "integer", "minimum": 0 }),
+        ParamType::I64 => json!({ "type": "integer" }),
+        ParamType::U8
+        | ParamType::U16
+        | ParamType::U32
+        | ParamType::I8
+        | ParamType::I16
+        | ParamType::I32 => {
+            let (minimum, maximum) = param_type.integer_range().unwrap();
+            json!({ "type": "integer", "minimum": minimum, "maximum": maximum })
+        }
+        ParamType::F64 => json!({ "type": "number" }),
+        ParamType::Bool => json!({ "type": "boolean" }),
+        ParamType::Bytes => json!({
+            "oneOf": [
+                { "type": "string", "contentEncoding": "base64" },
+                { "type": "array", "items": { "type": "integer", "minimum": 0, "maximum": 255 } },
+            ]
+        }),
+        ParamType::Null => json!({ "type": "null" }),
+        ParamType::Any | ParamType::Other(_) => json!({}),
+        ParamType::Array(item) => json!({ "type": "array", "items": json_schema(item) }),
+        ParamType::Object(fields) => {
+            let mut schema = json!({ "type": "object" });
+            if !fields.is_empty() {
+                let properties: Map<String, Value> = fields
+                    .iter()
+                    .map(|(name, field_type)| (name.clone(), json_schema(field_type)))
+                    .collect();
+                let required: Vec<&String> = fields
+                    .iter()
+                    .filter(|(_, field_type)| !field_type.is_optional())
+                    .map(|(name, _)| name)
+                    .collect();
+                schema["properties"] = Value::Object(properties);
+                schema["required"] = json!(required);
+            }
+            schema
+        }
+        ParamType::Optional(inner) => json!({ "oneOf": [json_schema(inner), { "type": "null" }] }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_openrpc() {
+        let toml = crate::test_utils::manifest_toml("greeter", "0.1.0").replace(
+            "[api.greet.returns]",
+            r#"permissions = ["greet"]
+
+[api.greet.params]
+name = { type = "string", description = "The name to greet." }
+times = { type = "u32[]", description = "When." }
+
+[api.greet.returns]"#,
+        );
+        let document = TappletManifest::from_toml_str(&toml).unwrap().to_openrpc();
+
+        assert_eq!(document["openrpc"], "1.2.6");
+        assert_eq!(document["info"]["title"], "greeter tapplet");
+        assert_eq!(document["info"]["version"], "0.1.0");
+        let method = &document["methods"][0];
+        assert_eq!(method["name"], "greet");
+        assert_eq!(method["x-permissions"], json!(["greet"]));
+        assert_eq!(
+            method["params"][0],
+            json!({
+                "name": "name",
+                "description": "The name to greet.",
+                "required": true,
+                "schema": { "type": "string" },
+            })
+        );
+        assert_eq!(
+            method["params"][1]["schema"],
+            json!({
+                "type": "array",
+                "items": { "type": "integer", "minimum": 0, "maximum": 4294967295u32 },
+            })
+        );
+        assert_eq!(method["result"]["schema"], json!({ "type": "string" }));
+    }
+}
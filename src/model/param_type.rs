@@ -0,0 +1,520 @@
+//! Types of method parameters and return values declared in the manifest
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use base64::Engine;
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/// The type of a method parameter or return value.
+///
+/// Written as a string in the manifest, e.g. `"u64"`, `"array<string>"`,
+/// `"optional<bool>"` or `"object{name: string, tags: array<string>}"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamType {
+    String,
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F64,
+    Bool,
+    /// Base64 string or array of byte values, passed on as a padded base64 string
+    /// in the standard alphabet
+    Bytes,
+    Null,
+    /// Any JSON value
+    Any,
+    Array(Box<ParamType>),
+    /// An object with these fields, fields not listed are allowed. No fields means any object.
+    Object(BTreeMap<String, ParamType>),
+    /// The value may be `null` or, for object fields and params, missing
+    Optional(Box<ParamType>),
+    /// A type this crate doesn't know, kept as written. Values aren't checked.
+    Other(String),
+}
+
+impl ParamType {
+    pub fn is_optional(&self) -> bool {
+        matches!(self, ParamType::Optional(_))
+    }
+
+    /// Smallest and largest value of integer types
+    pub(crate) fn integer_range(&self) -> Option<(i128, i128)> {
+        Some(match self {
+            ParamType::U8 => (0, u8::MAX.into()),
+            ParamType::U16 => (0, u16::MAX.into()),
+            ParamType::U32 => (0, u32::MAX.into()),
+            ParamType::U64 => (0, u64::MAX.into()),
+            ParamType::I8 => (i8::MIN.into(), i8::MAX.into()),
+            ParamType::I16 => (i16::MIN.into(), i16::MAX.into()),
+            ParamType::I32 => (i32::MIN.into(), i32::MAX.into()),
+            ParamType::I64 => (i64::MIN.into(), i64::MAX.into()),
+            _ => return None,
+        })
+    }
+
+    /// Whether values of this type contain `bytes` anywhere
+    pub fn contains_bytes(&self) -> bool {
+        match self {
+            ParamType::Bytes => true,
+            ParamType::Array(inner) | ParamType::Optional(inner) => inner.contains_bytes(),
+            ParamType::Object(fields) => fields.values().any(ParamType::contains_bytes),
+            _ => false,
+        }
+    }
+
+    /// Encode arrays of byte values at the `bytes` positions of a method's result
+    /// as base64 strings. Unlike [`Self::coerce`] this never fails; anything that
+    /// doesn't match the type is left as it is.
+    pub fn encode_bytes(&self, value: Value) -> Value {
+        if !self.contains_bytes() {
+            return value;
+        }
+        match (self, value) {
+            (ParamType::Bytes, Value::Array(items)) => {
+                match items.iter().map(byte_value).collect::<Option<Vec<_>>>() {
+                    Some(bytes) => Value::String(encode_base64(&bytes)),
+                    None => Value::Array(items),
+                }
+            }
+            (ParamType::Optional(inner), value) => inner.encode_bytes(value),
+            (ParamType::Array(item_type), Value::Array(items)) => Value::Array(
+                items
+                    .into_iter()
+                    .map(|item| item_type.encode_bytes(item))
+                    .collect(),
+            ),
+            (ParamType::Object(fields), Value::Object(mut object)) => {
+                for (name, field_type) in fields {
+                    if let Some(value) = object.remove(name) {
+                        object.insert(name.clone(), field_type.encode_bytes(value));
+                    }
+                }
+                Value::Object(object)
+            }
+            (_, value) => value,
+        }
+    }
+
+    /// Check that a JSON value has this type
+    pub fn check(&self, value: &Value) -> Result<(), String> {
+        self.coerce(value.clone()).map(|_| ())
+    }
+
+    /// Check a JSON value, converting it where the intent is unambiguous.
+    ///
+    /// Integers may be given as decimal strings, since JavaScript callers can't
+    /// represent every `u64` as a number. Integers outside the range of their
+    /// type are rejected.
+    pub fn coerce(&self, value: Value) -> Result<Value, String> {
+        self.coerce_at("", value)
+    }
+
+    fn coerce_at(&self, path: &str, value: Value) -> Result<Value, String> {
+        let location = if path.is_empty() {
+            String::new()
+        } else {
+            format!(" at {}", path)
+        };
+        let mismatch = |value: &Value| {
+            Err(format!(
+                "expected {}{}, got {}",
+                self,
+                location,
+                describe(value)
+            ))
+        };
+        if let Some((min, max)) = self.integer_range() {
+            let n = match &value {
+                Value::Number(n) => n
+                    .as_i64()
+                    .map(i128::from)
+                    .or_else(|| n.as_u64().map(i128::from)),
+                Value::String(s) => s.parse::<i128>().ok(),
+                _ => None,
+            };
+            return match n {
+                Some(n) if (min..=max).contains(&n) => Ok(match u64::try_from(n) {
+                    Ok(n) => Value::from(n),
+                    Err(_) => Value::from(n as i64),
+                }),
+                Some(n) => Err(format!("{} is out of range for {}{}", n, self, location)),
+                None => mismatch(&value),
+            };
+        }
+        match (self, value) {
+            (ParamType::Any | ParamType::Other(_), value) => Ok(value),
+            (ParamType::Optional(_), Value::Null) => Ok(Value::Null),
+            (ParamType::Optional(inner), value) => inner.coerce_at(path, value),
+            (ParamType::String, value @ Value::String(_))
+            | (ParamType::Bool, value @ Value::Bool(_))
+            | (ParamType::Null, value @ Value::Null) => Ok(value),
+            (ParamType::F64, value @ Value::Number(_)) => Ok(value),
+            (ParamType::Bytes, Value::String(s)) => match decode_base64(&s) {
+                Some(bytes) => Ok(Value::String(encode_base64(&bytes))),
+                None => mismatch(&Value::String(s)),
+            },
+            (ParamType::Bytes, Value::Array(items)) => {
+                match items.iter().map(byte_value).collect::<Option<Vec<_>>>() {
+                    Some(bytes) => Ok(Value::String(encode_base64(&bytes))),
+                    None => mismatch(&Value::Array(items)),
+                }
+            }
+            (ParamType::Array(item_type), Value::Array(items)) => items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| item_type.coerce_at(&format!("{}[{}]", path, i), item))
+                .collect::<Result<_, _>>()
+                .map(Value::Array),
+            (ParamType::Object(fields), Value::Object(mut object)) => {
+                for (name, field_type) in fields {
+                    let field_path = if path.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}.{}", path, name)
+                    };
+                    match object.remove(name) {
+                        Some(value) => {
+                            object.insert(name.clone(), field_type.coerce_at(&field_path, value)?);
+                        }
+                        None if field_type.is_optional() => {}
+                        None => return Err(format!("missing {}", field_path)),
+                    }
+                }
+                Ok(Value::Object(object))
+            }
+            (_, value) => mismatch(&value),
+        }
+    }
+}
+
+fn describe(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+fn byte_value(value: &Value) -> Option<u8> {
+    value.as_u64().and_then(|byte| u8::try_from(byte).ok())
+}
+
+const BASE64_CONFIG: GeneralPurposeConfig =
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+const BASE64: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, BASE64_CONFIG);
+const BASE64_URL_SAFE: GeneralPurpose = GeneralPurpose::new(&alphabet::URL_SAFE, BASE64_CONFIG);
+
+/// Encode `bytes` the way `bytes` values are passed on: padded, standard alphabet
+pub fn encode_base64(bytes: &[u8]) -> String {
+    BASE64.encode(bytes)
+}
+
+/// Decode a base64 string in the standard or URL-safe alphabet, with or without padding
+pub fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    if s.contains(['-', '_']) {
+        BASE64_URL_SAFE.decode(s).ok()
+    } else {
+        BASE64.decode(s).ok()
+    }
+}
+
+impl fmt::Display for ParamType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamType::String => write!(f, "string"),
+            ParamType::U8 => write!(f, "u8"),
+            ParamType::U16 => write!(f, "u16"),
+            ParamType::U32 => write!(f, "u32"),
+            ParamType::U64 => write!(f, "u64"),
+            ParamType::I8 => write!(f, "i8"),
+            ParamType::I16 => write!(f, "i16"),
+            ParamType::I32 => write!(f, "i32"),
+            ParamType::I64 => write!(f, "i64"),
+            ParamType::F64 => write!(f, "f64"),
+            ParamType::Bool => write!(f, "bool"),
+            ParamType::Bytes => write!(f, "bytes"),
+            ParamType::Null => write!(f, "null"),
+            ParamType::Any => write!(f, "any"),
+            ParamType::Array(item) => write!(f, "array<{}>", item),
+            ParamType::Object(fields) if fields.is_empty() => write!(f, "object"),
+            ParamType::Object(fields) => {
+                write!(f, "object{{")?;
+                for (i, (name, field_type)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", name, field_type)?;
+                }
+                write!(f, "}}")
+            }
+            ParamType::Optional(inner) => write!(f, "optional<{}>", inner),
+            ParamType::Other(name) => f.write_str(name),
+        }
+    }
+}
+
+impl FromStr for ParamType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { input: s, pos: 0 };
+        let param_type = parser.parse_type()?;
+        parser.skip_whitespace();
+        if parser.pos != s.len() {
+            return Err(parser.error("unexpected trailing characters"));
+        }
+        Ok(param_type)
+    }
+}
+
+impl Serialize for ParamType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ParamType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Types that don't parse are kept as written, so manifests for newer
+        // versions of this crate still load
+        let s = String::deserialize(deserializer)?;
+        Ok(s.parse().unwrap_or(ParamType::Other(s)))
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse_type(&mut self) -> Result<ParamType, String> {
+        self.skip_whitespace();
+        let name = self.identifier();
+        let mut param_type = match name {
+            "array" if self.eat('<') => {
+                let item = self.parse_type()?;
+                self.expect('>')?;
+                ParamType::Array(Box::new(item))
+            }
+            "optional" => {
+                self.expect('<')?;
+                let inner = self.parse_type()?;
+                self.expect('>')?;
+                ParamType::Optional(Box::new(inner))
+            }
+            "object" | "map" if self.eat('{') => ParamType::Object(self.parse_fields()?),
+            "" => return Err(self.error("expected a type")),
+            name => named_type(name).unwrap_or_else(|| ParamType::Other(name.to_string())),
+        };
+        // `string[]` is shorthand for `array<string>`
+        while self.input[self.pos..].starts_with("[]") {
+            self.pos += 2;
+            param_type = ParamType::Array(Box::new(param_type));
+        }
+        Ok(param_type)
+    }
+
+    fn parse_fields(&mut self) -> Result<BTreeMap<String, ParamType>, String> {
+        let mut fields = BTreeMap::new();
+        loop {
+            self.skip_whitespace();
+            if self.eat('}') {
+                return Ok(fields);
+            }
+            let name = self.identifier().to_string();
+            if name.is_empty() {
+                return Err(self.error("expected a field name"));
+            }
+            self.expect(':')?;
+            let field_type = self.parse_type()?;
+            if fields.insert(name.clone(), field_type).is_some() {
+                return Err(format!("duplicate field '{}'", name));
+            }
+            if !self.eat(',') {
+                self.expect('}')?;
+                return Ok(fields);
+            }
+        }
+    }
+
+    fn identifier(&mut self) -> &'a str {
+        let start = self.pos;
+        let len = self.input[start..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.input.len() - start);
+        self.pos += len;
+        let input: &'a str = self.input;
+        &input[start..self.pos]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.input[self.pos..].starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at position {} in '{}'", message, self.pos, self.input)
+    }
+}
+
+/// Types without parameters, including aliases accepted for older manifests
+fn named_type(name: &str) -> Option<ParamType> {
+    Some(match name.to_ascii_lowercase().as_str() {
+        "string" | "str" => ParamType::String,
+        "u8" => ParamType::U8,
+        "u16" => ParamType::U16,
+        "u32" => ParamType::U32,
+        "u64" => ParamType::U64,
+        "i8" => ParamType::I8,
+        "i16" => ParamType::I16,
+        "i32" => ParamType::I32,
+        "i64" | "integer" | "int" => ParamType::I64,
+        "f64" | "f32" | "number" | "float" => ParamType::F64,
+        "bool" | "boolean" => ParamType::Bool,
+        "bytes" => ParamType::Bytes,
+        "null" | "void" | "none" => ParamType::Null,
+        "any" | "json" => ParamType::Any,
+        "array" => ParamType::Array(Box::new(ParamType::Any)),
+        "object" | "map" => ParamType::Object(BTreeMap::new()),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        let parsed: ParamType = "object{ tags: string[], name: string, age: optional<u32> }"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            "object{age: optional<u32>, name: string, tags: array<string>}"
+        );
+        assert_eq!(parsed.to_string().parse::<ParamType>().unwrap(), parsed);
+        assert_eq!("number".parse::<ParamType>().unwrap(), ParamType::F64);
+        assert_eq!(
+            "transaction[]".parse::<ParamType>().unwrap(),
+            ParamType::Array(Box::new(ParamType::Other("transaction".to_string())))
+        );
+
+        for invalid in ["array<string", "object{name}", "u64 u64", ""] {
+            assert!(invalid.parse::<ParamType>().is_err(), "{}", invalid);
+        }
+        // Manifests keep types this crate can't parse
+        for unknown in ["transaction", "array<string"] {
+            let parsed: ParamType = serde_json::from_value(json!(unknown)).unwrap();
+            assert_eq!(parsed.to_string(), unknown);
+        }
+    }
+
+    #[test]
+    fn test_coerce() {
+        let person: ParamType = "object{name: string, age: optional<u64>, key: bytes}"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            person
+                .coerce(json!({"name": "Alice", "age": "18446744073709551615", "key": "AAE="}))
+                .unwrap(),
+            json!({"name": "Alice", "age": 18446744073709551615u64, "key": "AAE="})
+        );
+        assert_eq!(
+            person
+                .coerce(json!({"name": "Bob", "key": [0, 255]}))
+                .unwrap(),
+            json!({"name": "Bob", "key": "AP8="})
+        );
+        assert_eq!(
+            ParamType::Bytes.coerce(json!("AP-_")).unwrap(),
+            json!("AP+/")
+        );
+        assert_eq!(
+            ParamType::Bytes.coerce(json!("AAE")).unwrap(),
+            json!("AAE=")
+        );
+        assert_eq!(
+            ParamType::Bytes.check(&json!("not base64!")),
+            Err("expected bytes, got a string".to_string())
+        );
+        assert_eq!(
+            person.check(&json!({"name": "Bob", "key": [256]})),
+            Err("expected bytes at key, got an array".to_string())
+        );
+        assert_eq!(
+            person.check(&json!({"key": ""})),
+            Err("missing name".to_string())
+        );
+        assert_eq!(
+            ParamType::Array(Box::new(ParamType::I64)).check(&json!([1, "x"])),
+            Err("expected i64 at [1], got a string".to_string())
+        );
+        assert_eq!(ParamType::U8.coerce(json!("255")).unwrap(), json!(255));
+        assert_eq!(ParamType::I8.coerce(json!(-128)).unwrap(), json!(-128));
+        assert_eq!(
+            ParamType::U8.check(&json!(300)),
+            Err("300 is out of range for u8".to_string())
+        );
+        assert_eq!(
+            person.check(&json!({"name": "Eve", "age": -1, "key": ""})),
+            Err("-1 is out of range for u64 at age".to_string())
+        );
+        let unknown = ParamType::Other("transaction".to_string());
+        assert_eq!(unknown.coerce(json!({"id": 1})).unwrap(), json!({"id": 1}));
+    }
+
+    #[test]
+    fn test_encode_bytes() {
+        let signed: ParamType = "object{signature: bytes, keys: optional<bytes[]>, n: u64}"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            signed.encode_bytes(json!({"signature": [1, 2], "keys": [[255], "AA=="], "n": 3})),
+            json!({"signature": "AQI=", "keys": ["/w==", "AA=="], "n": 3})
+        );
+        // Not bytes, so left for the caller to notice
+        assert_eq!(ParamType::Bytes.encode_bytes(json!([256])), json!([256]));
+        assert!(
+            !"object{n: u64}"
+                .parse::<ParamType>()
+                .unwrap()
+                .contains_bytes()
+        );
+    }
+}
@@ -0,0 +1,521 @@
+//! Strict manifest checks for publisher tooling
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::{MethodDefinition, TappletManifest};
+use crate::error::TappletError;
+
+/// A problem with one field of a manifest, found by [`TappletManifest::validate`]
+/// or [`TappletManifest::from_toml_str_strict`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestIssue {
+    /// Dotted path of the field, e.g. `api.greet.params.name`. Empty for the whole manifest.
+    pub field: String,
+    pub message: String,
+}
+
+impl ManifestIssue {
+    pub(super) fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ManifestIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.field.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.field, self.message)
+        }
+    }
+}
+
+impl TappletManifest {
+    /// Parse a manifest, rejecting anything [`TappletManifest::from_toml_str`] lets
+    /// through: unknown fields and the problems reported by [`TappletManifest::validate`].
+    ///
+    /// Fails with [`TappletError::ManifestIssues`] listing every problem found.
+    pub fn from_toml_str_strict(toml_str: &str) -> Result<Self> {
+        let value = toml::from_str(toml_str).map_err(|e| syntax_error(e.message()))?;
+        Self::from_value_strict(value)
+    }
+
+    /// Load a manifest like [`TappletManifest::from_file`], with the checks of
+    /// [`TappletManifest::from_toml_str_strict`] whatever its format
+    pub fn from_file_strict<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        let value = match extension.as_deref() {
+            Some("json") => {
+                serde_json::from_str(&content).map_err(|e| syntax_error(&e.to_string()))?
+            }
+            Some("yaml" | "yml") => {
+                serde_yaml::from_str(&content).map_err(|e| syntax_error(&e.to_string()))?
+            }
+            _ => return Self::from_toml_str_strict(&content),
+        };
+        Self::from_value_strict(value)
+    }
+
+    fn from_value_strict(value: toml::Value) -> Result<Self> {
+        let fail = |issues: Vec<ManifestIssue>| TappletError::ManifestIssues { issues }.into();
+        let mut issues = Vec::new();
+        // Methods are flattened into `api`, where serde_ignored can't see their
+        // fields, so each one is checked on its own
+        if let Some(toml::Value::Table(api)) = value.get("api") {
+            for (name, method) in api.iter().filter(|(name, _)| *name != "methods") {
+                let prefix = format!("api.{}", name);
+                // A method that doesn't deserialize fails the manifest below
+                let _: Result<MethodDefinition, _> =
+                    serde_ignored::deserialize(method.clone(), |path| {
+                        issues.push(unknown_field(&prefix, &path))
+                    });
+            }
+        }
+        let manifest: Result<Self, _> =
+            serde_ignored::deserialize(value, |path| issues.push(unknown_field("", &path)));
+        let manifest = match manifest {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                issues.push(ManifestIssue::new("", e.message()));
+                return Err(fail(issues));
+            }
+        };
+        issues.extend(manifest.validate());
+        if issues.is_empty() {
+            Ok(manifest)
+        } else {
+            Err(fail(issues))
+        }
+    }
+
+    /// Check the fields a manifest needs to be published, returning every problem found
+    pub fn validate(&self) -> Vec<ManifestIssue> {
+        let mut issues = Vec::new();
+        for (field, value) in [
+            ("name", &self.name),
+            ("friendly_name", &self.friendly_name),
+            ("publisher", &self.publisher),
+        ] {
+            if value.trim().is_empty() {
+                issues.push(ManifestIssue::new(field, "must not be empty"));
+            }
+        }
+        // Qualified names and install directories start with the publisher
+        if !self.publisher.is_empty()
+            && (self.publisher.starts_with('.')
+                || !self
+                    .publisher
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.')))
+        {
+            issues.push(ManifestIssue::new(
+                "publisher",
+                "must only contain letters, digits, '-', '_' and '.', and not start with '.'",
+            ));
+        }
+        if let Err(e) = self.semver() {
+            issues.push(ManifestIssue::new("version", format!("{:#}", e)));
+        }
+        if self.public_key.len() != 64 || !self.public_key.bytes().all(|b| b.is_ascii_hexdigit()) {
+            issues.push(ManifestIssue::new(
+                "public_key",
+                "must be 64 hex characters",
+            ));
+        }
+        match self.license.as_deref().map(spdx::Expression::parse) {
+            None => issues.push(ManifestIssue::new("license", "is required")),
+            Some(Err(e)) => issues.push(ManifestIssue::new(
+                "license",
+                format!("is not a valid SPDX expression: {}", e.reason),
+            )),
+            Some(Ok(_)) => {}
+        }
+        if let Err(e) = self.host_api_requirement() {
+            issues.push(ManifestIssue::new("requires_host_api", format!("{:#}", e)));
+        }
+        if let Some(git) = &self.git
+            && let Err(message) = check_git_url(&git.url)
+        {
+            issues.push(ManifestIssue::new("git.url", message));
+        }
+        if let Some(runtime) = &self.runtime
+            && let Err(e) = runtime.entrypoint_path(Path::new(""))
+        {
+            issues.push(ManifestIssue::new("runtime.entrypoint", format!("{:#}", e)));
+        }
+        issues.extend(self.assets.issues());
+        issues.extend(self.i18n_issues());
+
+        let mut listed = HashSet::new();
+        for method in &self.api.methods {
+            if !listed.insert(method.as_str()) {
+                issues.push(ManifestIssue::new(
+                    "api.methods",
+                    format!("'{}' is listed more than once", method),
+                ));
+            } else if !self.api.method_definitions.contains_key(method) {
+                issues.push(ManifestIssue::new(
+                    format!("api.{}", method),
+                    "method is listed in api.methods but not defined",
+                ));
+            }
+        }
+        let mut unlisted: Vec<_> = self
+            .api
+            .method_definitions
+            .keys()
+            .filter(|name| !listed.contains(name.as_str()))
+            .collect();
+        unlisted.sort();
+        for name in unlisted {
+            issues.push(ManifestIssue::new(
+                format!("api.{}", name),
+                "method is defined but not listed in api.methods",
+            ));
+        }
+
+        let mut methods: Vec<_> = self.api.method_definitions.iter().collect();
+        methods.sort_by_key(|(name, _)| *name);
+        for (name, method) in methods {
+            for (field, limit) in [
+                ("timeout_ms", method.timeout_ms),
+                ("max_fuel", method.max_fuel),
+                ("max_memory", method.max_memory),
+            ] {
+                if limit == Some(0) {
+                    issues.push(ManifestIssue::new(
+                        format!("api.{}.{}", name, field),
+                        "must be greater than 0",
+                    ));
+                }
+            }
+        }
+
+        let mut defaults: Vec<_> = self
+            .api
+            .method_definitions
+            .iter()
+            .flat_map(|(method, definition)| {
+                definition
+                    .params
+                    .iter()
+                    .map(move |(name, param)| (method, name, param))
+            })
+            .filter_map(|(method, name, param)| {
+                let e = param.default_value().err()?;
+                Some((method, name, e))
+            })
+            .collect();
+        defaults.sort();
+        for (method, name, e) in defaults {
+            issues.push(ManifestIssue::new(
+                format!("api.{}.params.{}.default", method, name),
+                e,
+            ));
+        }
+
+        let mut deprecated: Vec<_> = self
+            .api
+            .method_definitions
+            .iter()
+            .filter_map(|(name, method)| {
+                Some((name, method.deprecated.as_ref()?.use_instead.as_ref()?))
+            })
+            .collect();
+        deprecated.sort();
+        for (name, use_instead) in deprecated {
+            if !listed.contains(use_instead.as_str()) {
+                issues.push(ManifestIssue::new(
+                    format!("api.{}.deprecated.use_instead", name),
+                    format!("'{}' is not listed in api.methods", use_instead),
+                ));
+            }
+        }
+
+        if !self.events.subscribe.is_empty() && !listed.contains(self.events.handler()) {
+            issues.push(ManifestIssue::new(
+                "events.handler",
+                format!(
+                    "handler '{}' is not listed in api.methods",
+                    self.events.handler()
+                ),
+            ));
+        }
+        if let Some(network) = &self.permissions.network {
+            for host in &network.allowed_hosts {
+                if !is_host_pattern(host) {
+                    issues.push(ManifestIssue::new(
+                        "permissions.network.allowed_hosts",
+                        format!(
+                            "'{}' is not a host name such as 'api.example.com' or '*.example.com'",
+                            host
+                        ),
+                    ));
+                }
+            }
+        }
+        for (name, task) in &self.schedule {
+            if !listed.contains(task.method.as_str()) {
+                issues.push(ManifestIssue::new(
+                    format!("schedule.{}.method", name),
+                    format!("'{}' is not listed in api.methods", task.method),
+                ));
+            }
+        }
+        for (name, req) in &self.dependencies {
+            let field = format!("dependencies.{}", name);
+            if self.name_matches(name) {
+                issues.push(ManifestIssue::new(
+                    field,
+                    "a tapplet can't depend on itself",
+                ));
+            } else if let Err(e) = super::parse_version_req(req) {
+                issues.push(ManifestIssue::new(field, format!("{:#}", e)));
+            }
+        }
+        for (file, hash) in &self.artifacts {
+            let field = format!("artifacts.{}", file);
+            if !super::is_contained(Path::new(file)) {
+                issues.push(ManifestIssue::new(
+                    field,
+                    "must be a relative path inside the tapplet directory",
+                ));
+            } else if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                issues.push(ManifestIssue::new(
+                    field,
+                    "must be a SHA-256 hash of 64 hex characters",
+                ));
+            }
+        }
+        issues
+    }
+}
+
+fn syntax_error(message: &str) -> anyhow::Error {
+    TappletError::ManifestIssues {
+        issues: vec![ManifestIssue::new("", message)],
+    }
+    .into()
+}
+
+fn unknown_field(prefix: &str, path: &serde_ignored::Path) -> ManifestIssue {
+    ManifestIssue::new(dotted(prefix, path), "unknown field")
+}
+
+/// `path` below `prefix` as a dotted field name, leaving out the steps into
+/// options and newtypes that serde_ignored shows as `?`
+fn dotted(prefix: &str, path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+
+    let (parent, segment) = match path {
+        Path::Root => return prefix.to_string(),
+        Path::Seq { parent, index } => (parent, index.to_string()),
+        Path::Map { parent, key } => (parent, key.clone()),
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => {
+            return dotted(prefix, parent);
+        }
+    };
+    let parent = dotted(prefix, parent);
+    if parent.is_empty() {
+        segment
+    } else {
+        format!("{}.{}", parent, segment)
+    }
+}
+
+/// Accepts host names, optionally starting with `*.` to match their subdomains
+fn is_host_pattern(pattern: &str) -> bool {
+    let host = pattern.strip_prefix("*.").unwrap_or(pattern);
+    !host.is_empty()
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+/// Accepts URLs git can clone, including the scp-like `git@host:path` form
+fn check_git_url(url: &str) -> Result<(), String> {
+    if url.trim().is_empty() {
+        return Err("must not be empty".to_string());
+    }
+    if let Some((user_host, path)) = url.split_once(':')
+        && user_host.contains('@')
+        && !user_host.contains('/')
+        && !path.starts_with("//")
+    {
+        return if path.is_empty() {
+            Err(format!("'{}' has no repository path", url))
+        } else {
+            Ok(())
+        };
+    }
+    let parsed =
+        url::Url::parse(url).map_err(|e| format!("'{}' is not a valid URL: {}", url, e))?;
+    match parsed.scheme() {
+        "https" | "http" | "ssh" | "git" if parsed.host_str().is_some() => Ok(()),
+        "file" => Ok(()),
+        scheme => Err(format!(
+            "'{}' is not a git URL, unsupported scheme '{}'",
+            url, scheme
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strict_manifest() -> String {
+        let git = r#"git = { url = "git@github.com:tari-project/greeter.git", rev = "main" }"#;
+        crate::test_utils::manifest_toml("greeter", "0.1.0").replace(
+            "public_key = \"test_public_key\"",
+            &format!("public_key = \"{}\"\n{}", "ab".repeat(32), git),
+        )
+    }
+
+    fn issue_fields(toml: &str) -> Vec<String> {
+        let err = TappletManifest::from_toml_str_strict(toml).unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_MANIFEST");
+        match err.downcast_ref::<TappletError>() {
+            Some(TappletError::ManifestIssues { issues }) => {
+                issues.iter().map(|issue| issue.field.clone()).collect()
+            }
+            _ => panic!("unexpected error: {:#}", err),
+        }
+    }
+
+    #[test]
+    fn test_strict_parsing() {
+        assert!(TappletManifest::from_toml_str_strict(&strict_manifest()).is_ok());
+        let yanked = strict_manifest().replace(
+            "name = \"greeter\"",
+            "name = \"greeter\"\nyanked = true\ndeprecated = { since = \"0.1.0\", reason = \"Broken\" }",
+        );
+        assert!(TappletManifest::from_toml_str_strict(&yanked).is_ok());
+
+        // The lenient parser ignores unknown fields, the strict one reports all problems at once
+        let toml = strict_manifest()
+            .replace(
+                "name = \"greeter\"",
+                "name = \"\"\nhomepage = \"https://example.com\"",
+            )
+            .replace(&"ab".repeat(32), "not-a-key")
+            .replace("[api.greet]", "[api.greet]\nretries = 3")
+            .replace("methods = [\"greet\"]", "methods = [\"greet\", \"wave\"]");
+        assert!(TappletManifest::from_toml_str(&toml).is_ok());
+        assert_eq!(
+            issue_fields(&toml),
+            vec![
+                "api.greet.retries",
+                "homepage",
+                "name",
+                "public_key",
+                "api.wave"
+            ]
+        );
+
+        let toml = strict_manifest().replace("git@github.com:tari-project/greeter.git", "greeter");
+        assert_eq!(issue_fields(&toml), vec!["git.url"]);
+        let toml = strict_manifest().replace(
+            "[api.greet.returns]",
+            "[api.greet.deprecated]\nuse_instead = \"wave\"\n\n[api.greet.returns]",
+        );
+        assert_eq!(
+            issue_fields(&toml),
+            vec!["api.greet.deprecated.use_instead"]
+        );
+        let toml = strict_manifest().replace(
+            "[api.greet.returns]",
+            "[api.greet.params]\ntimes = { type = \"u64\", description = \"Repeats\", default = \"often\" }\n\n[api.greet.returns]",
+        );
+        assert_eq!(issue_fields(&toml), vec!["api.greet.params.times.default"]);
+        let toml = strict_manifest().replace(
+            "[api.greet.returns]",
+            "timeout_ms = 0\nmax_fuel = 1000\n\n[api.greet.returns]",
+        );
+        assert_eq!(issue_fields(&toml), vec!["api.greet.timeout_ms"]);
+        let toml = strict_manifest().replace("license = \"MIT\"\n", "");
+        assert_eq!(issue_fields(&toml), vec!["license"]);
+        let toml = strict_manifest().replace("\"MIT\"", "\"MIT OR Apache-2.0\"");
+        assert!(TappletManifest::from_toml_str_strict(&toml).is_ok());
+        let toml = strict_manifest().replace("\"MIT\"", "\"MIT OR proprietary\"");
+        assert_eq!(issue_fields(&toml), vec!["license"]);
+        let toml = strict_manifest().replace("version = \"0.1.0\"", "version = 1");
+        assert_eq!(issue_fields(&toml), vec![""]);
+        let toml = strict_manifest()
+            + "[permissions.network]\nallowed_hosts = [\"*.example.com\", \"https://example.com\"]\n";
+        assert_eq!(
+            issue_fields(&toml),
+            vec!["permissions.network.allowed_hosts"]
+        );
+    }
+
+    #[test]
+    fn test_strict_parsing_of_json_and_yaml() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut value: toml::Value = toml::from_str(&strict_manifest()).unwrap();
+        let path = dir.path().join("manifest.json");
+        std::fs::write(&path, serde_json::to_string(&value).unwrap()).unwrap();
+        assert!(TappletManifest::from_file_strict(&path).is_ok());
+
+        value["api"]["greet"]
+            .as_table_mut()
+            .unwrap()
+            .insert("retries".to_string(), 3.into());
+        value
+            .as_table_mut()
+            .unwrap()
+            .insert("homepage".to_string(), "https://example.com".into());
+        let path = dir.path().join("manifest.yaml");
+        std::fs::write(&path, serde_yaml::to_string(&value).unwrap()).unwrap();
+        assert!(TappletManifest::from_file(&path).is_ok());
+        let err = TappletManifest::from_file_strict(&path).unwrap_err();
+        match err.downcast_ref::<TappletError>() {
+            Some(TappletError::ManifestIssues { issues }) => assert_eq!(
+                issues
+                    .iter()
+                    .map(|issue| issue.field.as_str())
+                    .collect::<Vec<_>>(),
+                vec!["api.greet.retries", "homepage"]
+            ),
+            _ => panic!("unexpected error: {:#}", err),
+        }
+    }
+
+    #[test]
+    fn test_check_git_url() {
+        for valid in [
+            "https://github.com/tari-project/greeter",
+            "ssh://git@github.com/tari-project/greeter.git",
+            "git@github.com:tari-project/greeter.git",
+            "file:///srv/git/greeter",
+        ] {
+            assert!(check_git_url(valid).is_ok(), "{}", valid);
+        }
+        for invalid in [
+            "",
+            "greeter",
+            "ftp://example.com/greeter",
+            "git@github.com:",
+        ] {
+            assert!(check_git_url(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::checksum::sha256_hex;
+use crate::engine::{self, CompiledModule, WasmEngine};
+use crate::host::HostError;
+use crate::trace;
+
+/// Directory inside a cache directory where compiled WASM modules are kept
+pub const MODULE_CACHE_DIR: &str = ".wasm_modules";
+
+/// Cache of compiled WASM modules, keyed by the SHA-256 of the WASM bytes.
+///
+/// Compiled modules are only valid for the engine, engine version and platform
+/// that produced them, so entries are named after the engine's
+/// [`WasmEngine::artifact_key`] too. Entries that fail to deserialize are
+/// recompiled and replaced.
+#[derive(Clone)]
+pub struct ModuleCache {
+    directory: PathBuf,
+    engine: Arc<dyn WasmEngine>,
+}
+
+impl std::fmt::Debug for ModuleCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModuleCache")
+            .field("directory", &self.directory)
+            .field("engine", &self.engine.name())
+            .finish()
+    }
+}
+
+impl ModuleCache {
+    /// Cache for modules compiled by the [default engine](engine::default_engine)
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            engine: engine::default_engine(),
+        }
+    }
+
+    /// Compile and load modules with `engine` instead of the default one
+    pub fn with_engine(mut self, engine: Arc<dyn WasmEngine>) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn engine(&self) -> &Arc<dyn WasmEngine> {
+        &self.engine
+    }
+
+    /// Module cache stored in the `.wasm_modules` directory of a tapplet cache directory
+    pub fn in_cache_directory(cache_directory: &Path) -> Self {
+        Self::new(cache_directory.join(MODULE_CACHE_DIR))
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn module_path(&self, wasm_bytes: &[u8]) -> PathBuf {
+        self.directory.join(format!(
+            "{}-{}.{}",
+            sha256_hex(wasm_bytes),
+            self.engine.artifact_key(),
+            self.engine.artifact_extension()
+        ))
+    }
+
+    /// Where the artifact of these WASM bytes precompiled by the installer is kept,
+    /// see [`engine::precompile_file`]. Like the cached modules, it is only ever
+    /// written by the host.
+    pub fn precompiled_path(&self, wasm_bytes: &[u8]) -> PathBuf {
+        engine::precompiled_path(
+            &self.directory,
+            &sha256_hex(wasm_bytes),
+            self.engine.as_ref(),
+        )
+    }
+
+    /// Whether a compiled module for these WASM bytes is cached
+    pub fn contains(&self, wasm_bytes: &[u8]) -> bool {
+        self.module_path(wasm_bytes).exists()
+    }
+
+    /// Load the compiled module from the cache, compiling and caching it on a miss
+    pub fn load(&self, wasm_bytes: &[u8]) -> Result<Arc<dyn CompiledModule>, HostError> {
+        let module_path = self.module_path(wasm_bytes);
+        if module_path.exists() {
+            let serialized = std::fs::read(&module_path)?;
+            // SAFETY: the cache directory is only written by `ModuleCache`, which
+            // stores whole modules serialized by the engine itself under the
+            // engine's artifact key. Incompatible artifacts are rejected by
+            // `deserialize`.
+            match unsafe { self.engine.deserialize(&serialized) } {
+                Ok(module) => return Ok(module),
+                Err(e) => trace::warning!(
+                    "Discarding unusable cached module {}: {}",
+                    module_path.display(),
+                    e
+                ),
+            }
+        }
+
+        let module = self.engine.compile(wasm_bytes)?;
+        std::fs::create_dir_all(&self.directory)?;
+        write_atomically(&module_path, &module.serialize()?)?;
+        Ok(module)
+    }
+
+    /// Compile and cache a module ahead of time so the first host creation is fast
+    pub fn prewarm(&self, wasm_bytes: &[u8]) -> Result<(), HostError> {
+        if !self.contains(wasm_bytes) {
+            self.load(wasm_bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Compile and cache the module in a WASM file
+    pub fn prewarm_file(&self, wasm_path: impl AsRef<Path>) -> Result<(), HostError> {
+        self.prewarm(&std::fs::read(wasm_path)?)
+    }
+
+    /// Remove the cached module for these WASM bytes, returning whether one was cached
+    pub fn invalidate(&self, wasm_bytes: &[u8]) -> Result<bool, HostError> {
+        let module_path = self.module_path(wasm_bytes);
+        if !module_path.exists() {
+            return Ok(false);
+        }
+        std::fs::remove_file(module_path)?;
+        Ok(true)
+    }
+
+    /// Remove every cached module
+    pub fn clear(&self) -> Result<(), HostError> {
+        if self.directory.exists() {
+            std::fs::remove_dir_all(&self.directory)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write `bytes` to a temporary file next to `path` and rename it into place, so
+/// a crash or a concurrent load never leaves a truncated module at `path`
+fn write_atomically(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+    let temp_path = path.with_extension(format!(
+        "{}.{}.tmp",
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&temp_path, bytes)
+        .and_then(|()| std::fs::rename(&temp_path, path))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&temp_path);
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The smallest valid WASM module: just the magic number and version
+    const EMPTY_MODULE: &[u8] = &[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn test_prewarm_load_and_invalidate() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache = ModuleCache::in_cache_directory(temp.path());
+        assert!(!cache.contains(EMPTY_MODULE));
+
+        cache.prewarm(EMPTY_MODULE).unwrap();
+        assert!(cache.contains(EMPTY_MODULE));
+        assert!(cache.load(EMPTY_MODULE).is_ok());
+
+        // A corrupted entry is recompiled rather than failing
+        std::fs::write(cache.module_path(EMPTY_MODULE), b"garbage").unwrap();
+        assert!(cache.load(EMPTY_MODULE).is_ok());
+
+        assert!(cache.invalidate(EMPTY_MODULE).unwrap());
+        assert!(!cache.invalidate(EMPTY_MODULE).unwrap());
+        cache.clear().unwrap();
+        assert!(!cache.directory().exists());
+    }
+
+    #[test]
+    fn test_cached_modules_are_keyed_by_engine() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache = ModuleCache::in_cache_directory(temp.path());
+        cache.load(EMPTY_MODULE).unwrap();
+
+        // Only the finished module is left, named after the engine's artifact key
+        let files: Vec<_> = std::fs::read_dir(cache.directory())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].contains(&cache.engine().artifact_key()));
+        assert_ne!(
+            cache.module_path(EMPTY_MODULE),
+            cache.precompiled_path(EMPTY_MODULE)
+        );
+    }
+}
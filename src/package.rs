@@ -0,0 +1,607 @@
+//! `.tapplet` archives: a tapplet's manifest and files in a single zstd compressed tar.
+//!
+//! [`build`] packages a tapplet directory, compiling WASM tapplets first. The
+//! manifest in the archive declares the runtime and lists the SHA-256 of every
+//! other file in `[artifacts]`, and `sigs` is left out of the manifest digest, so
+//! signatures over the packaged manifest cover the whole archive. [`install`]
+//! checks the files against the manifest, and [`install_with_policy`] also checks
+//! the manifest against a [`TrustPolicy`], before anything is written.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use walkdir::WalkDir;
+
+use crate::TappletManifest;
+use crate::checksum::sha256_hex;
+use crate::error::TappletError;
+use crate::install::{ExistingInstall, InstallOptions};
+use crate::local_folder_lua_tapplet::{LocalFolderLuaTapplet, tapplet_dir_runtime};
+use crate::local_folder_tapplet::LocalFolderTapplet;
+use crate::model::{HOST_API_VERSION, MANIFEST_FILE_NAMES, RuntimeConfig, RuntimeKind};
+use crate::registry::NoProgress;
+use crate::trace;
+use crate::trust::TrustPolicy;
+
+/// File extension of tapplet archives
+pub const PACKAGE_EXTENSION: &str = "tapplet";
+
+/// Largest total size of the files in an archive, so a small archive can't
+/// unpack to fill the disk
+pub const MAX_UNPACKED_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Where the manifest is stored in an archive
+const MANIFEST_ENTRY: &str = "manifest.toml";
+
+/// Directory of a WASM tapplet packaged next to its module
+const ASSETS_DIR: &str = "assets";
+
+/// Extensions of precompiled engine artifacts, which contain native code and
+/// must only ever come from the host's own precompile step
+const PRECOMPILED_EXTENSIONS: &[&str] = &["wasmu", "cwasm"];
+
+/// Package the tapplet in `dir` as `target/<name>-<version>.tapplet` inside it and
+/// return the archive's path.
+///
+/// Lua tapplets are packaged with every file in their directory tree, WASM tapplets
+/// with their module, built if needed, and their `assets` directory. Hashes already
+/// listed in `[artifacts]` must match the packaged files.
+pub fn build(dir: &Path) -> Result<PathBuf> {
+    let (mut manifest, files) = collect_files(dir)?;
+
+    let mut artifacts = BTreeMap::new();
+    let mut contents = BTreeMap::new();
+    for (entry, source) in files {
+        let bytes = std::fs::read(&source)
+            .with_context(|| format!("Failed to read {}", source.display()))?;
+        let actual = sha256_hex(&bytes);
+        if let Some(expected) = manifest.artifacts.get(&entry)
+            && !expected.eq_ignore_ascii_case(&actual)
+        {
+            bail!(TappletError::IntegrityMismatch {
+                file: entry,
+                expected: expected.clone(),
+                actual,
+            });
+        }
+        artifacts.insert(entry.clone(), actual);
+        contents.insert(entry, bytes);
+    }
+    if let Some(missing) = manifest
+        .artifacts
+        .keys()
+        .find(|file| !artifacts.contains_key(*file))
+    {
+        bail!(TappletError::ArtifactNotFound(format!(
+            "{} is listed in [artifacts] but is not part of the package",
+            missing
+        )));
+    }
+    manifest.artifacts = artifacts;
+    contents.insert(
+        MANIFEST_ENTRY.to_string(),
+        manifest.to_canonical_toml()?.into_bytes(),
+    );
+
+    let target_dir = dir.join("target");
+    std::fs::create_dir_all(&target_dir)
+        .with_context(|| format!("Failed to create {}", target_dir.display()))?;
+    let archive = target_dir.join(format!(
+        "{}-{}.{}",
+        manifest.name, manifest.version, PACKAGE_EXTENSION
+    ));
+    write_archive(&archive, &contents)
+        .with_context(|| format!("Failed to write {}", archive.display()))?;
+    Ok(archive)
+}
+
+/// Install a `.tapplet` archive into `cache_directory/<name>`, laid out like the
+/// other installers do, and return its manifest.
+///
+/// Fails with [`TappletError::IntegrityMismatch`] if a file doesn't match the
+/// manifest, without installing anything.
+pub fn install(archive: &Path, cache_directory: &Path) -> Result<TappletManifest> {
+    install_with_policy(archive, cache_directory, &TrustPolicy::default())
+}
+
+/// Like [`install`], but first fail if `trust` doesn't trust the packaged manifest,
+/// e.g. because its signature doesn't verify
+pub fn install_with_policy(
+    archive: &Path,
+    cache_directory: &Path,
+    trust: &TrustPolicy,
+) -> Result<TappletManifest> {
+    install_with_options(archive, cache_directory, trust, &InstallOptions::default())
+}
+
+/// Like [`install_with_policy`], into the directory `options` lay out and
+/// treating an existing install as `options` say
+pub fn install_with_options(
+    archive: &Path,
+    cache_directory: &Path,
+    trust: &TrustPolicy,
+    options: &InstallOptions,
+) -> Result<TappletManifest> {
+    let _span = trace::span!("package_install", archive = %archive.display());
+    let package = Package::read(archive)?;
+    trust.ensure_trusted(&package.manifest)?;
+    package.manifest.ensure_host_api(&HOST_API_VERSION)?;
+    package.verify()?;
+    let target_path = options.install_dir(cache_directory, &package.manifest)?;
+    let existing = options.existing_install(&target_path, &package.manifest)?;
+    if existing == ExistingInstall::Keep {
+        trace::info!("Already installed at {}", target_path.display());
+        return Ok(package.manifest);
+    }
+    package.unpack_to(&target_path, existing)?;
+    Ok(package.manifest)
+}
+
+/// The contents of a `.tapplet` archive
+pub struct Package {
+    pub manifest: TappletManifest,
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Package {
+    /// Read an archive into memory, refusing entries that aren't plain files or
+    /// directories inside the archive
+    pub fn read(archive: &Path) -> Result<Self> {
+        let invalid = |message: String| TappletError::InvalidPackage(message);
+        let file = std::fs::File::open(archive)
+            .with_context(|| format!("Failed to open {}", archive.display()))?;
+        let decoder = zstd::Decoder::new(file)
+            .with_context(|| format!("Failed to read {}", archive.display()))?;
+        let mut tar = tar::Archive::new(decoder);
+
+        let mut files = BTreeMap::new();
+        let mut total_size = 0u64;
+        let entries = tar
+            .entries()
+            .map_err(|e| invalid(format!("{}: {}", archive.display(), e)))?;
+        for entry in entries {
+            let mut entry = entry.map_err(|e| invalid(format!("{}: {}", archive.display(), e)))?;
+            let path = entry
+                .path()
+                .map_err(|e| invalid(e.to_string()))?
+                .into_owned();
+            let name = entry_name(&path).ok_or_else(|| {
+                invalid(format!("entry {} is outside the archive", path.display()))
+            })?;
+            match entry.header().entry_type() {
+                tar::EntryType::Directory => continue,
+                tar::EntryType::Regular => {}
+                other => bail!(invalid(format!("{} is a {:?} entry", name, other))),
+            }
+            total_size += entry.size();
+            if total_size > MAX_UNPACKED_SIZE {
+                bail!(invalid(format!(
+                    "unpacks to more than {} bytes",
+                    MAX_UNPACKED_SIZE
+                )));
+            }
+            let mut bytes = Vec::with_capacity(entry.size() as usize);
+            entry
+                .read_to_end(&mut bytes)
+                .map_err(|e| invalid(format!("{}: {}", name, e)))?;
+            if files.insert(name.clone(), bytes).is_some() {
+                bail!(invalid(format!("{} appears more than once", name)));
+            }
+        }
+
+        let manifest = files
+            .remove(MANIFEST_ENTRY)
+            .ok_or_else(|| invalid(format!("{} has no {}", archive.display(), MANIFEST_ENTRY)))?;
+        let manifest = String::from_utf8(manifest)
+            .map_err(|e| invalid(format!("{}: {}", MANIFEST_ENTRY, e)))?;
+        let manifest = TappletManifest::from_toml_str(&manifest)?;
+        Ok(Self { manifest, files })
+    }
+
+    /// Paths of the packaged files, other than the manifest
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    /// Check that the files are exactly the ones listed in `[artifacts]`, with the
+    /// listed hashes, and include the runtime entrypoint and valid `[assets]`.
+    /// Precompiled engine artifacts are refused.
+    pub fn verify(&self) -> Result<()> {
+        for (file, bytes) in &self.files {
+            check_not_precompiled(file)?;
+            let Some(expected) = self.manifest.artifacts.get(file) else {
+                bail!(TappletError::InvalidPackage(format!(
+                    "{} is not listed in [artifacts]",
+                    file
+                )));
+            };
+            let actual = sha256_hex(bytes);
+            if !expected.eq_ignore_ascii_case(&actual) {
+                bail!(TappletError::IntegrityMismatch {
+                    file: file.clone(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        if let Some(missing) = self
+            .manifest
+            .artifacts
+            .keys()
+            .find(|file| !self.files.contains_key(*file))
+        {
+            bail!(TappletError::ArtifactNotFound(format!(
+                "{} is listed in [artifacts] but is not in the package",
+                missing
+            )));
+        }
+        let entrypoint = self.runtime()?.entrypoint();
+        if !self.files.contains_key(entrypoint) {
+            bail!(TappletError::ArtifactNotFound(format!(
+                "entrypoint {} is not in the package",
+                entrypoint
+            )));
+        }
+        let assets = &self.manifest.assets;
+        for file in assets.icon.iter().chain(&assets.screenshots) {
+            let Some(bytes) = self.files.get(file) else {
+                bail!(TappletError::ArtifactNotFound(format!(
+                    "asset {} is not in the package",
+                    file
+                )));
+            };
+            self.manifest.verify_asset(file, bytes)?;
+        }
+        Ok(())
+    }
+
+    fn runtime(&self) -> Result<&RuntimeConfig> {
+        self.manifest.runtime.as_ref().ok_or_else(|| {
+            TappletError::InvalidPackage("the manifest declares no [runtime]".to_string()).into()
+        })
+    }
+
+    /// Write the files to `target_path`, going through a staging directory so a
+    /// failed install leaves nothing behind, and the `existing` install is only
+    /// replaced once the new one is written
+    pub(crate) fn unpack_to(&self, target_path: &Path, existing: ExistingInstall) -> Result<()> {
+        let mut staging_name = std::ffi::OsString::from(".");
+        staging_name.push(target_path.file_name().unwrap_or_default());
+        staging_name.push(".partial");
+        let staging = target_path.with_file_name(staging_name);
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)
+                .with_context(|| format!("Failed to remove {}", staging.display()))?;
+        }
+        let result = self.write_files(&staging).and_then(|()| {
+            existing.clear(target_path)?;
+            std::fs::rename(&staging, target_path)
+                .with_context(|| format!("Failed to move tapplet to {}", target_path.display()))
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_dir_all(&staging);
+        }
+        result
+    }
+
+    fn write_files(&self, dir: &Path) -> Result<()> {
+        let runtime = self.runtime()?;
+        let entrypoint = runtime.entrypoint();
+        let write = |relative: &str, bytes: &[u8]| -> Result<()> {
+            let path = dir.join(relative);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            std::fs::write(&path, bytes)
+                .with_context(|| format!("Failed to write {}", path.display()))
+        };
+
+        for (file, bytes) in &self.files {
+            check_not_precompiled(file)?;
+            // WASM tapplets are installed as just `<name>.wasm`, Lua tapplets keep
+            // their tree so the script can `require` its modules
+            if runtime.kind == RuntimeKind::Lua || file != entrypoint {
+                write(file, bytes)?;
+            }
+        }
+        let installed_entrypoint = format!("{}.{}", self.manifest.name, runtime.kind.extension());
+        write(&installed_entrypoint, &self.files[entrypoint])?;
+        write(
+            MANIFEST_ENTRY,
+            self.manifest.to_canonical_toml()?.as_bytes(),
+        )
+    }
+}
+
+/// Refuse a packaged precompiled module: hosts would run its native code
+fn check_not_precompiled(file: &str) -> Result<()> {
+    let extension = Path::new(file)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    if let Some(extension) = extension
+        && PRECOMPILED_EXTENSIONS.contains(&extension.as_str())
+    {
+        bail!(TappletError::InvalidPackage(format!(
+            "{} is a precompiled module, which packages can't contain",
+            file
+        )));
+    }
+    Ok(())
+}
+
+/// The manifest of the tapplet in `dir` with its runtime made explicit, and the
+/// files to package keyed by their path in the archive
+fn collect_files(dir: &Path) -> Result<(TappletManifest, BTreeMap<String, PathBuf>)> {
+    let mut files = BTreeMap::new();
+    let (mut manifest, kind, entrypoint) = match tapplet_dir_runtime(dir)? {
+        RuntimeKind::Lua => {
+            let tapplet = LocalFolderLuaTapplet::load(dir.to_path_buf())?;
+            let main_script = tapplet.main_script()?;
+            let entrypoint = archive_path(dir, &main_script)?;
+            collect_tree(dir, dir, &mut files)?;
+            (tapplet.config, RuntimeKind::Lua, entrypoint)
+        }
+        RuntimeKind::Wasm => {
+            let tapplet = LocalFolderTapplet::load(dir.to_path_buf())?;
+            let module = tapplet.wasm_module(&NoProgress)?;
+            let entrypoint = match &tapplet.config.runtime {
+                Some(runtime) => runtime.entrypoint().to_string(),
+                None => RuntimeKind::Wasm.default_entrypoint().to_string(),
+            };
+            files.insert(entrypoint.clone(), module);
+            let assets = dir.join(ASSETS_DIR);
+            if assets.is_dir() {
+                collect_tree(dir, &assets, &mut files)?;
+            }
+            (tapplet.config, RuntimeKind::Wasm, entrypoint)
+        }
+    };
+    manifest.runtime = Some(RuntimeConfig {
+        kind,
+        entrypoint: Some(entrypoint),
+    });
+    Ok((manifest, files))
+}
+
+/// Add the files below `from` to `files`, skipping hidden files, the manifest and
+/// the `target` directory the archive is written to
+fn collect_tree(dir: &Path, from: &Path, files: &mut BTreeMap<String, PathBuf>) -> Result<()> {
+    let walker = WalkDir::new(from)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            let skipped_root_entry = entry.path().parent() == Some(dir)
+                && (entry.file_name() == "target"
+                    || MANIFEST_FILE_NAMES
+                        .iter()
+                        .any(|name| entry.file_name() == *name));
+            !hidden && !skipped_root_entry
+        });
+    for entry in walker {
+        let entry = entry.with_context(|| format!("Failed to read {}", from.display()))?;
+        if entry.file_type().is_file() {
+            files.insert(archive_path(dir, entry.path())?, entry.into_path());
+        }
+    }
+    Ok(())
+}
+
+/// `path` relative to `dir`, with `/` separators
+fn archive_path(dir: &Path, path: &Path) -> Result<String> {
+    let relative = path
+        .strip_prefix(dir)
+        .with_context(|| format!("{} is outside {}", path.display(), dir.display()))?;
+    Ok(relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+/// The normalized name of an archive entry, or `None` if it would leave the directory
+/// it is unpacked into
+fn entry_name(path: &Path) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Write the files with fixed metadata, so packaging the same files twice gives
+/// the same archive
+fn write_archive(archive: &Path, contents: &BTreeMap<String, Vec<u8>>) -> Result<()> {
+    let file = std::fs::File::create(archive)?;
+    let encoder = zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+    let mut tar = tar::Builder::new(encoder);
+    for (name, bytes) in contents {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        tar.append_data(&mut header, name, bytes.as_slice())?;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_and_install_lua_package() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        crate::test_utils::write_lua_tapplet(&source, "notes", "0.2.0");
+        std::fs::create_dir_all(source.join("utils")).unwrap();
+        std::fs::write(source.join("utils/format.lua"), "return {}").unwrap();
+        std::fs::create_dir_all(source.join(".git")).unwrap();
+        std::fs::write(source.join(".git/HEAD"), "ref").unwrap();
+
+        let archive = build(&source).unwrap();
+        assert_eq!(archive, source.join("target/notes-0.2.0.tapplet"));
+        // Packaging is reproducible and leaves out earlier archives
+        let bytes = std::fs::read(&archive).unwrap();
+        assert_eq!(std::fs::read(build(&source).unwrap()).unwrap(), bytes);
+
+        let package = Package::read(&archive).unwrap();
+        assert_eq!(
+            package.files().collect::<Vec<_>>(),
+            ["main.lua", "utils/format.lua"]
+        );
+        let runtime = package.manifest.runtime.as_ref().unwrap();
+        assert_eq!(runtime.entrypoint(), "main.lua");
+        assert_eq!(package.manifest.artifacts.len(), 2);
+
+        let cache = temp.path().join("cache");
+        let manifest = install(&archive, &cache).unwrap();
+        assert_eq!(manifest.name, "notes");
+        let installed = cache.join("notes");
+        assert_eq!(
+            std::fs::read_to_string(installed.join("notes.lua")).unwrap(),
+            "function greet() return 'hello' end"
+        );
+        assert!(installed.join("utils/format.lua").is_file());
+        TappletManifest::from_file(installed.join("manifest.toml"))
+            .unwrap()
+            .verify_artifacts(&installed)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reinstall_follows_install_options() {
+        let temp = tempfile::tempdir().unwrap();
+        let archive = |version: &str| {
+            let source = temp.path().join(version);
+            crate::test_utils::write_lua_tapplet(&source, "notes", version);
+            build(&source).unwrap()
+        };
+        let (old, new) = (archive("0.2.0"), archive("0.3.0"));
+        let cache = temp.path().join("cache");
+        let installed_version = || {
+            TappletManifest::from_file(cache.join("notes/manifest.toml"))
+                .unwrap()
+                .version
+        };
+        let install = |archive: &Path, options: InstallOptions| {
+            install_with_options(archive, &cache, &TrustPolicy::default(), &options)
+        };
+
+        install(&old, InstallOptions::default()).unwrap();
+        install(&new, InstallOptions::default()).unwrap();
+        assert_eq!(installed_version(), "0.2.0");
+        let err = install(
+            &new,
+            InstallOptions {
+                on_conflict: crate::install::OnConflict::Error,
+                ..InstallOptions::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(crate::error_code(&err), "ALREADY_INSTALLED");
+
+        std::fs::write(cache.join("notes/stale.lua"), "").unwrap();
+        install(
+            &new,
+            InstallOptions {
+                on_conflict: crate::install::OnConflict::Overwrite,
+                ..InstallOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(installed_version(), "0.3.0");
+        assert!(!cache.join("notes/stale.lua").exists());
+
+        std::fs::write(cache.join("notes/stale.lua"), "").unwrap();
+        install(&new, InstallOptions::default()).unwrap();
+        assert!(cache.join("notes/stale.lua").exists());
+        install(
+            &new,
+            InstallOptions {
+                force: true,
+                ..InstallOptions::default()
+            },
+        )
+        .unwrap();
+        assert!(!cache.join("notes/stale.lua").exists());
+    }
+
+    #[test]
+    fn test_install_rejects_tampered_package() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        crate::test_utils::write_lua_tapplet(&source, "notes", "0.2.0");
+        let archive = build(&source).unwrap();
+
+        let package = Package::read(&archive).unwrap();
+        let mut contents = package.files;
+        contents.insert(
+            "main.lua".to_string(),
+            b"function greet() evil() end".to_vec(),
+        );
+        contents.insert(
+            MANIFEST_ENTRY.to_string(),
+            package.manifest.to_canonical_toml().unwrap().into_bytes(),
+        );
+        let tampered = temp.path().join("tampered.tapplet");
+        write_archive(&tampered, &contents).unwrap();
+
+        let cache = temp.path().join("cache");
+        let err = install(&tampered, &cache).unwrap_err();
+        assert_eq!(crate::error_code(&err), "INTEGRITY_MISMATCH");
+        assert!(!cache.join("notes").exists());
+
+        // Precompiled modules are refused even when listed in `[artifacts]`
+        let artifact = "main.wasmer-4.4.0-x86_64-linux.wasmu";
+        let mut manifest = package.manifest.clone();
+        manifest
+            .artifacts
+            .insert(artifact.to_string(), sha256_hex(b"native code"));
+        let mut files = contents.clone();
+        files.insert(
+            "main.lua".to_string(),
+            std::fs::read(source.join("main.lua")).unwrap(),
+        );
+        files.insert(artifact.to_string(), b"native code".to_vec());
+        files.insert(
+            MANIFEST_ENTRY.to_string(),
+            manifest.to_canonical_toml().unwrap().into_bytes(),
+        );
+        let precompiled = temp.path().join("precompiled.tapplet");
+        write_archive(&precompiled, &files).unwrap();
+        let err = install(&precompiled, &cache).unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_PACKAGE");
+        assert!(!cache.join("notes").exists());
+
+        // Untrusted publishers are refused before the files are looked at
+        let trust = TrustPolicy::new().allow_publisher("someone_else");
+        let err = install_with_policy(&archive, &cache, &trust).unwrap_err();
+        assert_eq!(crate::error_code(&err), "UNTRUSTED_TAPPLET");
+
+        // tar::Builder refuses to write such paths, so set the raw name
+        let escaping = temp.path().join("escaping.tapplet");
+        let encoder = zstd::Encoder::new(std::fs::File::create(&escaping).unwrap(), 0).unwrap();
+        let mut tar = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..13].copy_from_slice(b"../escape.lua");
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        tar.append(&header, std::io::empty()).unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+        let err = Package::read(&escaping).err().unwrap();
+        assert_eq!(crate::error_code(&err), "INVALID_PACKAGE");
+    }
+}
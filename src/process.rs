@@ -0,0 +1,707 @@
+//! Running a tapplet in a child process, so a crash or abort inside wasmer or mlua
+//! only takes that process down instead of the wallet.
+//!
+//! ```ignore
+//! let mut host = ProcessTappletHost::new("./tapplets/greeter", api)?;
+//! let result = host.run("greet", json!({ "name": "Alice" }), &CallContext::user()).await?;
+//! ```
+//!
+//! The child is the `tapplet-runner` binary, which links this crate's hosts. The
+//! two sides exchange JSON-RPC 2.0 messages over the child's stdin and stdout,
+//! one per line:
+//!
+//! - the parent sends `call` requests, with the `method`, `args` and `context`
+//! - while handling a call, the runner asks the parent for the host API with
+//!   `append_data`, `load_data_entries` and `add_watched_viewkey` requests,
+//!   which the parent answers from its own [`MinotariTappletApiV1`]
+//! - the tapplet's `print` output arrives as `log` notifications
+//!
+//! Errors carry their [`HostError::code`] in `error.data.code`.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use crate::call_context::CallContext;
+use crate::host::{
+    HostError, LuaTappletHost, MinotariTappletApiV1, TappletRunner, WasmTappletHost,
+};
+use crate::log_sink::{LogLevel, LogRecord, LogSink};
+use crate::model::{RuntimeKind, TappletManifest};
+use crate::testing::locate_entrypoint;
+
+/// Name of the runner binary, built with the `host` or `engine-wasmtime` feature
+pub const RUNNER_BIN: &str = "tapplet-runner";
+
+/// JSON-RPC error code of errors raised by the tapplet or its host
+const HOST_ERROR: i64 = -32000;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Message {
+    jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    method: Option<String>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    params: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+impl Message {
+    fn new(id: Option<u64>, method: Option<&str>, params: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            method: method.map(str::to_string),
+            params,
+            result: None,
+            error: None,
+        }
+    }
+
+    fn request(id: u64, method: &str, params: Value) -> Self {
+        Self::new(Some(id), Some(method), params)
+    }
+
+    fn notification(method: &str, params: Value) -> Self {
+        Self::new(None, Some(method), params)
+    }
+
+    fn response(id: u64, result: Result<Value, RpcError>) -> Self {
+        let mut message = Self::new(Some(id), None, Value::Null);
+        match result {
+            Ok(result) => message.result = Some(result),
+            Err(error) => message.error = Some(error),
+        }
+        message
+    }
+
+    /// The result of a response. A `null` result deserializes as no result.
+    fn into_result(self) -> Result<Value, RpcError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.result.unwrap_or_default()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    data: Value,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: Value::Null,
+        }
+    }
+
+    fn host(error: &HostError) -> Self {
+        let mut data = json!({ "code": error.code() });
+        if let HostError::LuaExecutionError(details) = error
+            && let Some(traceback) = &details.traceback
+        {
+            data["traceback"] = json!(traceback);
+        }
+        Self {
+            data,
+            ..Self::new(HOST_ERROR, error.detail())
+        }
+    }
+
+    fn other(error: &anyhow::Error) -> Self {
+        match error.downcast_ref::<HostError>() {
+            Some(error) => Self::host(error),
+            None => Self {
+                data: json!({ "code": crate::error_code(error) }),
+                ..Self::new(HOST_ERROR, format!("{:#}", error))
+            },
+        }
+    }
+
+    fn into_host_error(self) -> HostError {
+        let code = self.data["code"].as_str().unwrap_or_default();
+        let mut error = HostError::from_code(code, self.message);
+        if let HostError::LuaExecutionError(details) = &mut error {
+            details.traceback = self.data["traceback"].as_str().map(str::to_string);
+        }
+        error
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CallParams {
+    method: String,
+    args: Value,
+    context: CallContext,
+}
+
+/// Host API requests from the runner, one per [`MinotariTappletApiV1`] method
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+enum ApiRequest {
+    AppendData { slot: String, value: String },
+    LoadDataEntries { slot: String },
+    AddWatchedViewkey { viewkey: String, birthday: u64 },
+}
+
+impl ApiRequest {
+    fn parse(method: &str, params: Value) -> Result<Self, RpcError> {
+        serde_json::from_value(json!({ "method": method, "params": params })).map_err(|e| {
+            match method {
+                "append_data" | "load_data_entries" | "add_watched_viewkey" => {
+                    RpcError::new(INVALID_PARAMS, e.to_string())
+                }
+                _ => RpcError::new(METHOD_NOT_FOUND, format!("Method not found: {}", method)),
+            }
+        })
+    }
+
+    async fn answer<T: MinotariTappletApiV1 + ?Sized>(self, api: &T) -> Result<Value> {
+        Ok(match self {
+            ApiRequest::AppendData { slot, value } => {
+                api.append_data(&slot, &value).await?;
+                Value::Null
+            }
+            ApiRequest::LoadDataEntries { slot } => json!(api.load_data_entries(&slot).await?),
+            ApiRequest::AddWatchedViewkey { viewkey, birthday } => {
+                api.add_watched_viewkey(&viewkey, birthday).await?;
+                Value::Null
+            }
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LogParams {
+    level: LogLevel,
+    message: String,
+}
+
+struct RunnerProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<tokio::io::BufReader<ChildStdout>>,
+}
+
+impl RunnerProcess {
+    async fn send(&mut self, message: &Message) -> io::Result<()> {
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        self.stdin.write_all(&line).await?;
+        self.stdin.flush().await
+    }
+}
+
+/// A tapplet run by a `tapplet-runner` child process, see the [module docs](self).
+///
+/// The process is started by the first call, and started again by the first call
+/// after it crashed. The call it crashed during fails with
+/// [`HostError::ProcessCrashed`]. Dropping the host kills the process.
+pub struct ProcessTappletHost<T: ?Sized> {
+    manifest: TappletManifest,
+    dir: PathBuf,
+    runner: PathBuf,
+    api: Arc<T>,
+    log_sink: Option<Arc<dyn LogSink>>,
+    process: Option<RunnerProcess>,
+    next_id: u64,
+}
+
+impl<T: MinotariTappletApiV1 + 'static> ProcessTappletHost<T> {
+    /// Run the tapplet in `dir`, a source or installed tapplet directory, with the
+    /// runner installed next to the current executable
+    pub fn new(dir: impl Into<PathBuf>, api: T) -> Result<Self> {
+        Self::new_shared(dir, Arc::new(api))
+    }
+}
+
+impl<T: MinotariTappletApiV1 + ?Sized + 'static> ProcessTappletHost<T> {
+    /// Like [`ProcessTappletHost::new`], for an API shared with other hosts
+    pub fn new_shared(dir: impl Into<PathBuf>, api: Arc<T>) -> Result<Self> {
+        let dir = dir.into();
+        let (manifest, _, _) = locate_entrypoint(&dir)?;
+        let runner = std::env::current_exe()
+            .context("Failed to locate the current executable")?
+            .with_file_name(format!("{}{}", RUNNER_BIN, std::env::consts::EXE_SUFFIX));
+        Ok(Self {
+            manifest,
+            dir,
+            runner,
+            api,
+            log_sink: None,
+            process: None,
+            next_id: 0,
+        })
+    }
+
+    /// Start `runner` instead of the `tapplet-runner` next to the current executable
+    pub fn with_runner(mut self, runner: impl Into<PathBuf>) -> Self {
+        self.runner = runner.into();
+        self
+    }
+
+    /// Send the tapplet's `print` output, and the tracebacks of failed calls, to `sink`
+    pub fn with_log_sink(mut self, sink: Arc<dyn LogSink>) -> Self {
+        self.log_sink = Some(sink);
+        self
+    }
+
+    pub fn config(&self) -> &TappletManifest {
+        &self.manifest
+    }
+
+    /// Whether the runner process has been started and hasn't crashed since
+    pub fn is_running(&self) -> bool {
+        self.process.is_some()
+    }
+
+    /// Call a method in the runner process, starting it if it isn't running
+    pub async fn run(
+        &mut self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError> {
+        if self.process.is_none() {
+            self.process = Some(self.start()?);
+        }
+        self.next_id += 1;
+        let request = Message::request(
+            self.next_id,
+            "call",
+            json!(CallParams {
+                method: method.to_string(),
+                args,
+                context: context.clone(),
+            }),
+        );
+        match self.exchange(request).await {
+            Ok(result) => result.map_err(RpcError::into_host_error),
+            Err(reason) => {
+                let mut process = self.process.take().expect("the process was started");
+                // Whatever is left of it is of no use any more
+                let _ = process.child.start_kill();
+                let status = process
+                    .child
+                    .wait()
+                    .await
+                    .map(|status| status.to_string())
+                    .unwrap_or_else(|e| e.to_string());
+                Err(HostError::ProcessCrashed(format!(
+                    "{} ({}) during {}: {}",
+                    self.manifest.name, status, method, reason
+                )))
+            }
+        }
+    }
+
+    fn start(&self) -> Result<RunnerProcess, HostError> {
+        let mut child = Command::new(&self.runner)
+            .arg(&self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                HostError::ExecutionError(format!(
+                    "Failed to start {}: {}",
+                    self.runner.display(),
+                    e
+                ))
+            })?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(RunnerProcess {
+            child,
+            stdin,
+            stdout: tokio::io::BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// Send a call and serve the runner's requests until the call's response
+    /// arrives. Fails with why the runner is unusable if it doesn't.
+    async fn exchange(&mut self, request: Message) -> Result<Result<Value, RpcError>, String> {
+        let Self {
+            manifest,
+            api,
+            log_sink,
+            process,
+            ..
+        } = self;
+        let process = process.as_mut().expect("the process was started");
+        process
+            .send(&request)
+            .await
+            .map_err(|e| format!("failed to send call: {}", e))?;
+        loop {
+            let line = match process.stdout.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => return Err("the runner exited".to_string()),
+                Err(e) => return Err(format!("failed to read from the runner: {}", e)),
+            };
+            let message: Message = serde_json::from_str(&line)
+                .map_err(|e| format!("the runner sent an invalid message: {}", e))?;
+            match (message.method.as_deref(), message.id) {
+                (Some("log"), None) => {
+                    forward_log(&manifest.name, log_sink.as_deref(), message.params)
+                }
+                (Some(method), Some(id)) => {
+                    let response = match ApiRequest::parse(method, message.params) {
+                        Ok(api_request) => api_request
+                            .answer(&**api)
+                            .await
+                            .map_err(|e| RpcError::new(HOST_ERROR, format!("{:#}", e))),
+                        Err(error) => Err(error),
+                    };
+                    process
+                        .send(&Message::response(id, response))
+                        .await
+                        .map_err(|e| format!("failed to answer the runner: {}", e))?;
+                }
+                (None, id) if id == request.id => return Ok(message.into_result()),
+                // Notifications this side doesn't know about
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Hand a `log` notification to the sink, or to `tracing` like a Lua host without one
+fn forward_log(tapplet: &str, sink: Option<&dyn LogSink>, params: Value) {
+    let Ok(LogParams { level, message }) = serde_json::from_value(params) else {
+        return;
+    };
+    match sink {
+        Some(sink) => sink.log(LogRecord {
+            tapplet: tapplet.to_string(),
+            level,
+            message,
+        }),
+        #[cfg(feature = "tracing")]
+        None => tracing::info!(target: "tapplet", tapplet = %tapplet, "{}", message),
+        #[cfg(not(feature = "tracing"))]
+        None => {}
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: MinotariTappletApiV1 + ?Sized + 'static> TappletRunner for ProcessTappletHost<T> {
+    fn manifest(&self) -> &TappletManifest {
+        self.config()
+    }
+
+    async fn call(
+        &mut self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError> {
+        self.run(method, args, context).await
+    }
+}
+
+/// The runner's end of the connection
+struct Pipe {
+    reader: Box<dyn BufRead + Send>,
+    writer: Box<dyn Write + Send>,
+}
+
+impl Pipe {
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, message)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+
+    /// The next message, `None` once the parent closed the connection
+    fn receive(&mut self) -> io::Result<Option<Message>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        serde_json::from_str(&line)
+            .map(Some)
+            .map_err(io::Error::other)
+    }
+}
+
+/// The host API of the runner, answered by the parent
+struct ParentApi {
+    pipe: Arc<Mutex<Pipe>>,
+    next_id: AtomicU64,
+}
+
+impl ParentApi {
+    fn request(&self, request: ApiRequest) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = serde_json::to_value(request)?;
+        let method = request["method"].as_str().unwrap_or_default();
+        let mut pipe = self.pipe.lock().unwrap();
+        pipe.send(&Message::request(id, method, request["params"].clone()))?;
+        let response = pipe
+            .receive()?
+            .context("The parent closed the connection")?;
+        if response.id != Some(id) {
+            anyhow::bail!("Expected the response to {} {}", method, id);
+        }
+        response
+            .into_result()
+            .map_err(|e| anyhow::anyhow!(e.message))
+    }
+}
+
+#[async_trait]
+impl MinotariTappletApiV1 for ParentApi {
+    async fn append_data(&self, slot: &str, value: &str) -> Result<(), anyhow::Error> {
+        self.request(ApiRequest::AppendData {
+            slot: slot.to_string(),
+            value: value.to_string(),
+        })
+        .map(drop)
+    }
+
+    async fn load_data_entries(&self, slot: &str) -> Result<Vec<String>, anyhow::Error> {
+        let entries = self.request(ApiRequest::LoadDataEntries {
+            slot: slot.to_string(),
+        })?;
+        Ok(serde_json::from_value(entries)?)
+    }
+
+    async fn add_watched_viewkey(&self, viewkey: &str, birthday: u64) -> Result<(), anyhow::Error> {
+        self.request(ApiRequest::AddWatchedViewkey {
+            viewkey: viewkey.to_string(),
+            birthday,
+        })
+        .map(drop)
+    }
+}
+
+/// Sends `print` output to the parent as `log` notifications
+struct ParentLogSink(Arc<Mutex<Pipe>>);
+
+impl LogSink for ParentLogSink {
+    fn log(&self, record: LogRecord) {
+        let params = json!(LogParams {
+            level: record.level,
+            message: record.message,
+        });
+        // The parent notices a broken connection when it reads the next message
+        let _ = self
+            .0
+            .lock()
+            .unwrap()
+            .send(&Message::notification("log", params));
+    }
+}
+
+/// Serve the tapplet in `dir` over stdin and stdout until stdin is closed. This is
+/// all the `tapplet-runner` binary does.
+///
+/// Needs a multi-threaded tokio runtime, like [`LuaTappletHost::run`].
+pub async fn serve_stdio(dir: &Path) -> Result<()> {
+    serve(
+        dir,
+        Pipe {
+            reader: Box::new(BufReader::new(io::stdin())),
+            writer: Box::new(io::stdout()),
+        },
+    )
+    .await
+}
+
+async fn serve(dir: &Path, pipe: Pipe) -> Result<()> {
+    let pipe = Arc::new(Mutex::new(pipe));
+    // A tapplet that doesn't load fails every call instead of the runner, so the
+    // parent gets the reason with its error code
+    let mut host = load(dir, &pipe).map_err(|e| RpcError::other(&e));
+    loop {
+        let Some(message) = pipe.lock().unwrap().receive()? else {
+            return Ok(());
+        };
+        let Some(id) = message.id else {
+            continue;
+        };
+        let response = match (message.method.as_deref(), &mut host) {
+            (Some("call"), Ok(host)) => {
+                match serde_json::from_value::<CallParams>(message.params) {
+                    Ok(call) => host
+                        .call(&call.method, call.args, &call.context)
+                        .await
+                        .map_err(|e| RpcError::host(&e)),
+                    Err(e) => Err(RpcError::new(INVALID_PARAMS, e.to_string())),
+                }
+            }
+            (Some("call"), Err(error)) => Err(error.clone()),
+            (method, _) => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Method not found: {}", method.unwrap_or_default()),
+            )),
+        };
+        pipe.lock()
+            .unwrap()
+            .send(&Message::response(id, response))?;
+    }
+}
+
+fn load(dir: &Path, pipe: &Arc<Mutex<Pipe>>) -> Result<Box<dyn TappletRunner>> {
+    let (manifest, kind, path) = locate_entrypoint(dir)?;
+    Ok(match kind {
+        RuntimeKind::Wasm => Box::new(WasmTappletHost::new(manifest, path)?),
+        RuntimeKind::Lua => {
+            let api = ParentApi {
+                pipe: pipe.clone(),
+                next_id: AtomicU64::new(0),
+            };
+            Box::new(
+                LuaTappletHost::new(manifest, path, api)?
+                    .with_log_sink(Arc::new(ParentLogSink(pipe.clone()))),
+            )
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    /// Collects what the runner writes
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_runner_protocol() {
+        let temp = tempfile::tempdir().unwrap();
+        test_utils::write_lua_tapplet(temp.path(), "greeter", "0.1.0");
+        std::fs::write(
+            temp.path().join("greeter.lua"),
+            "function greet()\n  print('saving')\n  minotari_append_data('greetings', 'hello')\n  return 'hello'\nend\n",
+        )
+        .unwrap();
+
+        // The parent's side: a call, the answer to the runner's append_data, and
+        // a call to an undeclared method
+        let lines = [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "call", "params": {
+                "method": "greet", "args": {}, "context": { "caller": "user", "session_id": null, "permissions": [] }
+            }}),
+            json!({ "jsonrpc": "2.0", "id": 0, "result": null }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "call", "params": {
+                "method": "missing", "args": {}, "context": { "caller": "system", "session_id": null, "permissions": [] }
+            }}),
+        ];
+        let input: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        let output = Output::default();
+        serve(
+            temp.path(),
+            Pipe {
+                reader: Box::new(io::Cursor::new(input)),
+                writer: Box::new(output.clone()),
+            },
+        )
+        .await
+        .unwrap();
+
+        let written = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let messages: Vec<Message> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let methods: Vec<_> = messages
+            .iter()
+            .map(|message| (message.method.as_deref(), message.id))
+            .collect();
+        assert_eq!(
+            methods,
+            [
+                (Some("log"), None),
+                (Some("append_data"), Some(0)),
+                (None, Some(1)),
+                (None, Some(2)),
+            ]
+        );
+        assert_eq!(messages[0].params["message"], "saving");
+        assert_eq!(
+            messages[1].params,
+            json!({ "slot": "test_publisher/greeter/greetings", "value": "hello" })
+        );
+        let mut messages = messages.into_iter().skip(2);
+        assert_eq!(messages.next().unwrap().into_result().unwrap(), "hello");
+        let error = messages
+            .next()
+            .unwrap()
+            .into_result()
+            .unwrap_err()
+            .into_host_error();
+        assert_eq!(error.code(), "METHOD_NOT_FOUND");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_crashed_runner_is_restarted() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("greeter");
+        test_utils::write_lua_tapplet(&dir, "greeter", "0.1.0");
+        // Aborts on its first start, then answers every call with a result
+        let runner = temp.path().join("runner.sh");
+        std::fs::write(
+            &runner,
+            format!(
+                "#!/bin/sh\nif [ ! -e {marker} ]; then touch {marker}; read line; kill -ABRT $$; fi\n\
+                 while read line; do\n  id=$(echo \"$line\" | sed 's/.*\"id\":\\([0-9]*\\).*/\\1/')\n  \
+                 echo \"{{\\\"jsonrpc\\\":\\\"2.0\\\",\\\"id\\\":$id,\\\"result\\\":\\\"restarted\\\"}}\"\ndone\n",
+                marker = temp.path().join("started").display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&runner, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut host = ProcessTappletHost::new(&dir, crate::reference_api::MemoryTappletApi::new())
+            .unwrap()
+            .with_runner(&runner);
+        let context = CallContext::user();
+        let err = host.run("greet", json!({}), &context).await.unwrap_err();
+        assert_eq!(err.code(), "PROCESS_CRASHED");
+        assert!(!host.is_running());
+
+        let result = host.run("greet", json!({}), &context).await.unwrap();
+        assert_eq!(result, "restarted");
+        assert!(host.is_running());
+    }
+}
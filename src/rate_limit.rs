@@ -0,0 +1,134 @@
+//! Limits on how often a tapplet may call host functions.
+//!
+//! A [`RateLimit`] is a token bucket: a tapplet may make `burst` host calls in
+//! quick succession and gets `calls_per_second` of them back every second. Set one
+//! with `with_rate_limit` on either host. Calls over the limit fail inside the
+//! tapplet, which can catch the error and back off; Lua calls that don't fail with
+//! [`HostError::RateLimited`], and WASI functions return `EAGAIN`.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::host::HostError;
+
+/// How often a tapplet may call host functions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Calls regained per second
+    pub calls_per_second: f64,
+    /// Calls that may be made at once, and the most that can be saved up
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(calls_per_second: f64, burst: u32) -> Self {
+        Self {
+            calls_per_second,
+            burst,
+        }
+    }
+}
+
+/// The bucket of one host, shared with its host functions. The default doesn't
+/// limit anything.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    bucket: Option<Arc<Mutex<Bucket>>>,
+}
+
+struct Bucket {
+    limit: RateLimit,
+    calls: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// A limiter with a full bucket
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            bucket: Some(Arc::new(Mutex::new(Bucket {
+                limit,
+                calls: limit.burst as f64,
+                refilled: Instant::now(),
+            }))),
+        }
+    }
+
+    /// Take a call to `function` from the bucket, failing with
+    /// [`HostError::RateLimited`] if there is none left
+    pub fn check(&self, function: &str) -> Result<(), HostError> {
+        self.check_at(function, Instant::now())
+    }
+
+    fn check_at(&self, function: &str, now: Instant) -> Result<(), HostError> {
+        let Some(bucket) = &self.bucket else {
+            return Ok(());
+        };
+        let mut bucket = bucket.lock().unwrap();
+        let limit = bucket.limit;
+        let elapsed = now.saturating_duration_since(bucket.refilled);
+        bucket.calls =
+            (bucket.calls + elapsed.as_secs_f64() * limit.calls_per_second).min(limit.burst as f64);
+        bucket.refilled = now;
+        if bucket.calls < 1.0 {
+            return Err(HostError::RateLimited(format!(
+                "{} called more than {} times per second",
+                function, limit.calls_per_second
+            )));
+        }
+        bucket.calls -= 1.0;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = self
+            .bucket
+            .as_ref()
+            .map(|bucket| bucket.lock().unwrap().limit);
+        f.debug_struct("RateLimiter")
+            .field("limit", &limit)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(RateLimit::new(2.0, 3));
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.check_at("minotari_get_balance", start).unwrap();
+        }
+        let err = limiter.check_at("minotari_get_balance", start).unwrap_err();
+        assert_eq!(err.code(), "RATE_LIMITED");
+        assert_eq!(
+            err.to_string(),
+            "Rate limited: minotari_get_balance called more than 2 times per second"
+        );
+
+        // Half a second gives one call back, and the bucket never holds more than the burst
+        let later = start + Duration::from_millis(500);
+        limiter.check_at("minotari_get_balance", later).unwrap();
+        assert!(limiter.check_at("minotari_get_balance", later).is_err());
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            limiter
+                .check_at("minotari_get_balance", much_later)
+                .unwrap();
+        }
+        assert!(
+            limiter
+                .check_at("minotari_get_balance", much_later)
+                .is_err()
+        );
+
+        assert!(RateLimiter::default().check("anything").is_ok());
+    }
+}
@@ -0,0 +1,307 @@
+//! Reference implementations of the host API for running and testing tapplets
+//! without a wallet.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::host::MinotariTappletApiV1;
+use crate::wallet::{
+    Balance, MinotariTappletApiV2, TransactionDirection, TransactionFilter, TransactionInfo,
+    TransactionStatus,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedViewKey {
+    pub viewkey: String,
+    pub birthday: u64,
+}
+
+/// Everything stored by the reference APIs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiState {
+    #[serde(default)]
+    pub slots: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub watched_viewkeys: Vec<WatchedViewKey>,
+    #[serde(default)]
+    pub balance: Balance,
+    /// Transactions, oldest first
+    #[serde(default)]
+    pub transactions: Vec<TransactionInfo>,
+}
+
+impl ApiState {
+    fn append_data(&mut self, slot: &str, value: &str) {
+        self.slots
+            .entry(slot.to_string())
+            .or_default()
+            .push(value.to_string());
+    }
+
+    fn load_data_entries(&self, slot: &str) -> Vec<String> {
+        self.slots.get(slot).cloned().unwrap_or_default()
+    }
+
+    fn add_watched_viewkey(&mut self, viewkey: &str, birthday: u64) {
+        if !self.watched_viewkeys.iter().any(|k| k.viewkey == viewkey) {
+            self.watched_viewkeys.push(WatchedViewKey {
+                viewkey: viewkey.to_string(),
+                birthday,
+            });
+        }
+    }
+
+    /// Record a completed outbound transaction, failing if the balance is too low
+    fn send_transaction(&mut self, destination: &str, amount: u64, fee: u64) -> Result<String> {
+        let total = amount.checked_add(fee).context("Amount overflow")?;
+        if total > self.balance.available {
+            bail!(
+                "Insufficient funds: {} available, {} required",
+                self.balance.available,
+                total
+            );
+        }
+        self.balance.available -= total;
+        let id = (self.transactions.len() + 1).to_string();
+        self.transactions.push(TransactionInfo {
+            id: id.clone(),
+            direction: TransactionDirection::Outbound,
+            status: TransactionStatus::Completed,
+            amount,
+            fee,
+            counterparty: destination.to_string(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        });
+        Ok(id)
+    }
+
+    fn get_transactions(&self, filter: &TransactionFilter) -> Vec<TransactionInfo> {
+        self.transactions
+            .iter()
+            .rev()
+            .filter(|transaction| filter.matches(transaction))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Keeps all data in memory, useful for unit tests
+#[derive(Debug, Default)]
+pub struct MemoryTappletApi {
+    state: Mutex<ApiState>,
+}
+
+impl MemoryTappletApi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start with the given wallet balance
+    pub fn with_balance(balance: Balance) -> Self {
+        Self {
+            state: Mutex::new(ApiState {
+                balance,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Snapshot of everything stored so far
+    pub fn state(&self) -> ApiState {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl MinotariTappletApiV1 for MemoryTappletApi {
+    async fn append_data(&self, slot: &str, value: &str) -> Result<(), anyhow::Error> {
+        self.state.lock().unwrap().append_data(slot, value);
+        Ok(())
+    }
+
+    async fn load_data_entries(&self, slot: &str) -> Result<Vec<String>, anyhow::Error> {
+        Ok(self.state.lock().unwrap().load_data_entries(slot))
+    }
+
+    async fn add_watched_viewkey(&self, viewkey: &str, birthday: u64) -> Result<(), anyhow::Error> {
+        self.state
+            .lock()
+            .unwrap()
+            .add_watched_viewkey(viewkey, birthday);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MinotariTappletApiV2 for MemoryTappletApi {
+    async fn get_balance(&self) -> Result<Balance, anyhow::Error> {
+        Ok(self.state.lock().unwrap().balance)
+    }
+
+    async fn send_transaction(
+        &self,
+        destination: &str,
+        amount: u64,
+        fee: u64,
+    ) -> Result<String, anyhow::Error> {
+        self.state
+            .lock()
+            .unwrap()
+            .send_transaction(destination, amount, fee)
+    }
+
+    async fn get_transactions(
+        &self,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionInfo>, anyhow::Error> {
+        Ok(self.state.lock().unwrap().get_transactions(filter))
+    }
+}
+
+/// Persists all data to a JSON file, so tapplet data survives restarts during development
+#[derive(Debug)]
+pub struct FileTappletApi {
+    path: PathBuf,
+    state: Mutex<ApiState>,
+}
+
+impl FileTappletApi {
+    /// Open the state file at `path`, starting empty if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read API state: {}", path.display()))?;
+            serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse API state: {}", path.display()))?
+        } else {
+            ApiState::default()
+        };
+        Ok(Self {
+            path,
+            state: Mutex::new(state),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn state(&self) -> ApiState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Apply a change to the state and write it to disk
+    fn update<R>(&self, f: impl FnOnce(&mut ApiState) -> Result<R>) -> Result<R> {
+        let mut state = self.state.lock().unwrap();
+        let result = f(&mut state)?;
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write to a temporary file first so a crash can't leave a truncated state file
+        let temp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_string_pretty(&*state)?)
+            .with_context(|| format!("Failed to write API state: {}", temp_path.display()))?;
+        std::fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("Failed to write API state: {}", self.path.display()))?;
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl MinotariTappletApiV1 for FileTappletApi {
+    async fn append_data(&self, slot: &str, value: &str) -> Result<(), anyhow::Error> {
+        self.update(|state| {
+            state.append_data(slot, value);
+            Ok(())
+        })
+    }
+
+    async fn load_data_entries(&self, slot: &str) -> Result<Vec<String>, anyhow::Error> {
+        Ok(self.state.lock().unwrap().load_data_entries(slot))
+    }
+
+    async fn add_watched_viewkey(&self, viewkey: &str, birthday: u64) -> Result<(), anyhow::Error> {
+        self.update(|state| {
+            state.add_watched_viewkey(viewkey, birthday);
+            Ok(())
+        })
+    }
+}
+
+#[async_trait]
+impl MinotariTappletApiV2 for FileTappletApi {
+    async fn get_balance(&self) -> Result<Balance, anyhow::Error> {
+        Ok(self.state.lock().unwrap().balance)
+    }
+
+    async fn send_transaction(
+        &self,
+        destination: &str,
+        amount: u64,
+        fee: u64,
+    ) -> Result<String, anyhow::Error> {
+        self.update(|state| state.send_transaction(destination, amount, fee))
+    }
+
+    async fn get_transactions(
+        &self,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionInfo>, anyhow::Error> {
+        Ok(self.state.lock().unwrap().get_transactions(filter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_api_wallet() {
+        let api = MemoryTappletApi::with_balance(Balance {
+            available: 100,
+            ..Default::default()
+        });
+        api.send_transaction("address", 60, 1).await.unwrap();
+        assert!(api.send_transaction("address", 60, 1).await.is_err());
+        assert_eq!(api.get_balance().await.unwrap().available, 39);
+        let transactions = api
+            .get_transactions(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(transactions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_file_api_persists_slots() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("state.json");
+
+        let api = FileTappletApi::open(&path).unwrap();
+        api.append_data("notes", "first").await.unwrap();
+        api.append_data("notes", "second").await.unwrap();
+        api.add_watched_viewkey("key", 10).await.unwrap();
+        api.add_watched_viewkey("key", 10).await.unwrap();
+
+        let reopened = FileTappletApi::open(&path).unwrap();
+        assert_eq!(
+            reopened.load_data_entries("notes").await.unwrap(),
+            vec!["first", "second"]
+        );
+        assert!(
+            reopened
+                .load_data_entries("other")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(reopened.state().watched_viewkeys.len(), 1);
+    }
+}
@@ -0,0 +1,246 @@
+//! Tapplets published more than once under the same qualified name
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::TappletManifest;
+use crate::trace;
+
+/// What a registry does with tapplets published more than once under the same
+/// name by the same publisher
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Fail loading and fetching with `DUPLICATE_TAPPLET`
+    Error,
+    /// Keep the copy with the newest version
+    KeepNewestVersion,
+    /// Keep the copy listed first in the registry index, or first by path
+    #[default]
+    KeepFirst,
+}
+
+/// One copy of a tapplet in a [`TappletConflict`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TappletCopy {
+    pub version: String,
+    pub dir: PathBuf,
+    /// Whether the registry lists this copy
+    pub kept: bool,
+}
+
+/// A tapplet name published from more than one directory, or more than once
+/// with the same version
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TappletConflict {
+    pub name: String,
+    pub publisher: String,
+    /// Every copy, in the order the registry lists them
+    pub copies: Vec<TappletCopy>,
+}
+
+impl fmt::Display for TappletConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let copies: Vec<_> = self
+            .copies
+            .iter()
+            .map(|copy| format!("{} in {}", copy.version, copy.dir.display()))
+            .collect();
+        write!(f, "{} is published as {}", self.name, copies.join(", "))
+    }
+}
+
+/// The directory a tapplet is published from: its own directory, or the parent
+/// of a `<name>/<version>` directory
+fn origin<'a>(manifest: &TappletManifest, dir: &'a Path) -> &'a Path {
+    match (dir.file_name(), dir.parent()) {
+        (Some(name), Some(parent)) if name == manifest.version.as_str() => parent,
+        _ => dir,
+    }
+}
+
+/// Find conflicting tapplets and drop the copies `policy` doesn't keep.
+///
+/// Several versions of a tapplet are only a conflict when they come from
+/// different directories, unless `by_directory` is false because the registry
+/// has no directory layout of its own. Same-named tapplets of different
+/// publishers never conflict.
+pub(super) fn resolve(
+    tapplets: Vec<(TappletManifest, PathBuf)>,
+    policy: DuplicatePolicy,
+    by_directory: bool,
+) -> (Vec<(TappletManifest, PathBuf)>, Vec<TappletConflict>) {
+    let mut names = Vec::new();
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, (tapplet, _)) in tapplets.iter().enumerate() {
+        let name = tapplet.qualified_name();
+        groups
+            .entry(name.clone())
+            .or_insert_with(|| {
+                names.push(name);
+                Vec::new()
+            })
+            .push(i);
+    }
+
+    let mut keep = vec![true; tapplets.len()];
+    let mut conflicts = Vec::new();
+    for name in names {
+        let members = &groups[&name];
+        let origin_of = |i: usize| {
+            let (tapplet, dir) = &tapplets[i];
+            by_directory.then(|| origin(tapplet, dir))
+        };
+        let one_origin = members
+            .iter()
+            .all(|&i| origin_of(i) == origin_of(members[0]));
+        let mut versions: Vec<_> = members.iter().map(|&i| &tapplets[i].0.version).collect();
+        versions.sort();
+        versions.dedup();
+        if one_origin && versions.len() == members.len() {
+            continue;
+        }
+
+        let winner = match policy {
+            DuplicatePolicy::Error => None,
+            DuplicatePolicy::KeepFirst => Some(members[0]),
+            DuplicatePolicy::KeepNewestVersion => {
+                let mut newest = members[0];
+                for &i in members {
+                    if tapplets[i].0.semver().ok() > tapplets[newest].0.semver().ok() {
+                        newest = i;
+                    }
+                }
+                Some(newest)
+            }
+        };
+        // Keep every version from the winner's directory, each once
+        let mut kept_versions = Vec::new();
+        for &i in members {
+            keep[i] = winner.is_some_and(|winner| origin_of(i) == origin_of(winner))
+                && !kept_versions.contains(&&tapplets[i].0.version);
+            if keep[i] {
+                kept_versions.push(&tapplets[i].0.version);
+            }
+        }
+
+        let conflict = TappletConflict {
+            name: tapplets[members[0]].0.name.clone(),
+            publisher: tapplets[members[0]].0.publisher.clone(),
+            copies: members
+                .iter()
+                .map(|&i| TappletCopy {
+                    version: tapplets[i].0.version.clone(),
+                    dir: tapplets[i].1.clone(),
+                    kept: keep[i],
+                })
+                .collect(),
+        };
+        if policy != DuplicatePolicy::Error {
+            trace::warning!("Duplicate tapplet: {}", conflict);
+        }
+        conflicts.push(conflict);
+    }
+
+    let tapplets = tapplets
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(tapplet, keep)| keep.then_some(tapplet))
+        .collect();
+    (tapplets, conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn test_resolve_duplicates() {
+        let tapplet = |name: &str, version: &str, dir: &str| {
+            let manifest =
+                TappletManifest::from_toml_str(&test_utils::manifest_toml(name, version)).unwrap();
+            (manifest, PathBuf::from(dir))
+        };
+        let tapplets = vec![
+            tapplet("notes", "0.1.0", "tapplets/notes/0.1.0"),
+            tapplet("wallet", "0.1.0", "tapplets/wallet"),
+            tapplet("notes", "0.2.0", "tapplets/notes/0.2.0"),
+            tapplet("wallet", "0.2.0", "tapplets/wallet-fork"),
+        ];
+        let kept = |policy, by_directory| {
+            let (tapplets, conflicts) = resolve(tapplets.clone(), policy, by_directory);
+            let kept: Vec<_> = tapplets
+                .iter()
+                .map(|(_, dir)| dir.to_str().unwrap().to_string())
+                .collect();
+            (kept, conflicts)
+        };
+
+        // Versions in one directory are not a conflict, forks in another are
+        let (first, conflicts) = kept(DuplicatePolicy::KeepFirst, true);
+        assert_eq!(
+            first,
+            [
+                "tapplets/notes/0.1.0",
+                "tapplets/wallet",
+                "tapplets/notes/0.2.0"
+            ]
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[0].to_string(),
+            "wallet is published as 0.1.0 in tapplets/wallet, 0.2.0 in tapplets/wallet-fork"
+        );
+        assert!(conflicts[0].copies[0].kept && !conflicts[0].copies[1].kept);
+
+        let (newest, _) = kept(DuplicatePolicy::KeepNewestVersion, true);
+        assert_eq!(
+            newest,
+            [
+                "tapplets/notes/0.1.0",
+                "tapplets/notes/0.2.0",
+                "tapplets/wallet-fork"
+            ]
+        );
+        let (none, conflicts) = kept(DuplicatePolicy::Error, true);
+        assert_eq!(none, ["tapplets/notes/0.1.0", "tapplets/notes/0.2.0"]);
+        assert!(conflicts[0].copies.iter().all(|copy| !copy.kept));
+
+        // Without a directory layout only repeated versions conflict
+        let (all, conflicts) = kept(DuplicatePolicy::KeepFirst, false);
+        assert_eq!(all.len(), 4);
+        assert!(conflicts.is_empty());
+        let mut tapplets = tapplets;
+        tapplets.push(tapplet("notes", "0.1.0", "elsewhere"));
+        let (kept, conflicts) = resolve(tapplets, DuplicatePolicy::KeepFirst, false);
+        assert_eq!(kept.len(), 4);
+        assert_eq!(conflicts[0].name, "notes");
+    }
+
+    #[test]
+    fn test_same_name_of_different_publishers() {
+        let tapplet = |publisher: &str, dir: &str| {
+            let toml =
+                test_utils::manifest_toml("wallet", "0.1.0").replace("test_publisher", publisher);
+            let manifest = TappletManifest::from_toml_str(&toml).unwrap();
+            (manifest, PathBuf::from(dir))
+        };
+        let tapplets = vec![
+            tapplet("alice", "tapplets/alice/wallet"),
+            tapplet("bob", "tapplets/bob/wallet"),
+        ];
+        for policy in [
+            DuplicatePolicy::Error,
+            DuplicatePolicy::KeepFirst,
+            DuplicatePolicy::KeepNewestVersion,
+        ] {
+            let (kept, conflicts) = resolve(tapplets.clone(), policy, true);
+            let kept: Vec<_> = kept.iter().map(|(t, _)| t.qualified_name()).collect();
+            assert_eq!(kept, ["alice/wallet", "bob/wallet"]);
+            assert!(conflicts.is_empty());
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! A machine-readable dump of a loaded registry, for store fronts and search indexes
+
+use std::collections::BTreeMap;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::TappletRegistry;
+use crate::TappletManifest;
+use crate::model::RuntimeKind;
+
+/// Version of the [`RegistryExport`] schema. Adding fields keeps the version;
+/// removing fields or changing what they mean bumps it.
+pub const EXPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Everything a registry lists, as written by [`TappletRegistry::export_json`].
+///
+/// The JSON has the fields of these structs, in snake case. Optional fields are
+/// `null` rather than left out, so consumers can rely on every key being present.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegistryExport {
+    /// [`EXPORT_SCHEMA_VERSION`] of the writer
+    pub schema_version: u32,
+    /// Name the registry was configured with
+    pub registry: String,
+    /// URL the registry is fetched from
+    pub url: String,
+    /// Commit or index revision the tapplets were loaded from
+    pub revision: Option<String>,
+    /// When the registry was last fetched, in seconds since the Unix epoch
+    pub fetched_at: Option<u64>,
+    /// Every tapplet, ordered by qualified name
+    pub tapplets: Vec<ExportedTapplet>,
+}
+
+/// One tapplet of a [`RegistryExport`] with all of its versions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedTapplet {
+    pub name: String,
+    pub publisher: String,
+    /// `publisher/name`, unique within the export
+    pub qualified_name: String,
+    /// Newest version that isn't yanked
+    pub latest: Option<String>,
+    /// Oldest first
+    pub versions: Vec<ExportedVersion>,
+}
+
+/// One published version of an [`ExportedTapplet`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedVersion {
+    pub version: String,
+    pub yanked: bool,
+    /// Declared in the manifest or detected from the tapplet's files
+    pub runtime: Option<RuntimeKind>,
+    /// The manifest as the registry serves it
+    pub manifest: serde_json::Value,
+}
+
+impl TappletRegistry {
+    /// Dump every tapplet and version the registry lists, yanked ones included.
+    ///
+    /// Fails if the registry hasn't been loaded.
+    pub fn export(&self) -> Result<RegistryExport> {
+        self.ensure_loaded()?;
+        let mut by_name: BTreeMap<String, Vec<&TappletManifest>> = BTreeMap::new();
+        for tapplet in &self.tapplets {
+            by_name
+                .entry(tapplet.qualified_name())
+                .or_default()
+                .push(tapplet);
+        }
+
+        let mut tapplets = Vec::new();
+        for (qualified_name, mut versions) in by_name {
+            versions.sort_by(|a, b| a.cmp_version(b));
+            let newest = versions[versions.len() - 1];
+            let (name, publisher) = (newest.name.clone(), newest.publisher.clone());
+            let latest = versions
+                .iter()
+                .rev()
+                .find(|tapplet| !tapplet.yanked)
+                .map(|tapplet| tapplet.version.clone());
+            let versions = versions
+                .into_iter()
+                .map(|tapplet| {
+                    Ok(ExportedVersion {
+                        version: tapplet.version.clone(),
+                        yanked: tapplet.yanked,
+                        runtime: self.runtime_of(tapplet),
+                        manifest: serde_json::to_value(tapplet).with_context(|| {
+                            format!("Failed to export {}", tapplet.canonical_name())
+                        })?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            tapplets.push(ExportedTapplet {
+                name,
+                publisher,
+                qualified_name,
+                latest,
+                versions,
+            });
+        }
+
+        Ok(RegistryExport {
+            schema_version: EXPORT_SCHEMA_VERSION,
+            registry: self.name.clone(),
+            url: self.git_url.clone(),
+            revision: self.current_revision.clone(),
+            fetched_at: self
+                .last_fetch
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|since_epoch| since_epoch.as_secs()),
+            tapplets,
+        })
+    }
+
+    /// [`Self::export`] as pretty-printed JSON
+    pub fn export_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(&self.export()?).context("Failed to serialize registry export")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::InMemoryRegistry;
+    use crate::test_utils;
+
+    #[test]
+    fn test_export_json() {
+        let mut memory = InMemoryRegistry::new("store");
+        for (name, version, yanked) in [
+            ("wallet", "0.2.0", true),
+            ("wallet", "0.1.0", false),
+            ("greeter", "1.0.0", false),
+        ] {
+            let mut manifest =
+                TappletManifest::from_toml_str(&test_utils::manifest_toml(name, version)).unwrap();
+            manifest.yanked = yanked;
+            memory.push(manifest);
+        }
+        let registry: TappletRegistry = memory.into();
+
+        let json = registry.export_json().unwrap();
+        let export: RegistryExport = serde_json::from_str(&json).unwrap();
+        assert_eq!(export.schema_version, EXPORT_SCHEMA_VERSION);
+        assert_eq!(export.registry, "store");
+        assert_eq!(export.fetched_at, None);
+        let names: Vec<_> = export
+            .tapplets
+            .iter()
+            .map(|tapplet| tapplet.qualified_name.as_str())
+            .collect();
+        assert_eq!(names, ["test_publisher/greeter", "test_publisher/wallet"]);
+
+        // Versions are oldest first, and the latest skips yanked ones
+        let wallet = &export.tapplets[1];
+        assert_eq!(wallet.latest.as_deref(), Some("0.1.0"));
+        let versions: Vec<_> = wallet
+            .versions
+            .iter()
+            .map(|version| (version.version.as_str(), version.yanked))
+            .collect();
+        assert_eq!(versions, [("0.1.0", false), ("0.2.0", true)]);
+        assert_eq!(wallet.versions[0].manifest["license"], "MIT");
+
+        // Optional fields are written as null
+        let raw: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(raw["revision"].is_null());
+        assert!(raw["tapplets"][0]["versions"][0]["runtime"].is_null());
+
+        let unloaded = TappletRegistry::new("store", "https://example.com", Default::default());
+        assert!(unloaded.export_json().is_err());
+    }
+}
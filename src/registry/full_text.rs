@@ -0,0 +1,266 @@
+//! Full-text search over a registry's manifests with tantivy, kept up to date as
+//! the registry is fetched
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use anyhow::{Context, Result};
+use tantivy::collector::TopDocs;
+use tantivy::query::QueryParser;
+use tantivy::schema::{Field, STORED, STRING, Schema, TEXT, Value};
+use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+
+use crate::TappletManifest;
+
+/// Smallest writer heap tantivy accepts
+const WRITER_HEAP_SIZE: usize = 15_000_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Fields {
+    /// Fully qualified name, the key each document is replaced by
+    key: Field,
+    name: Field,
+    friendly_name: Field,
+    description: Field,
+    publisher: Field,
+    /// Tags and categories
+    keywords: Field,
+}
+
+/// In-memory tantivy index of a registry's tapplets, yanked ones left out.
+///
+/// Clones share the index, and searching only needs `&self`, so any number of
+/// threads can search while the registry holds the one it updates.
+#[derive(Clone)]
+pub struct FullTextIndex {
+    index: Index,
+    reader: IndexReader,
+    fields: Fields,
+    /// Registry revision the index was last updated for
+    revision: Option<String>,
+    /// Fingerprint of each indexed manifest, by fully qualified name
+    indexed: HashMap<String, u64>,
+}
+
+impl FullTextIndex {
+    pub fn new() -> Result<Self> {
+        let mut schema = Schema::builder();
+        let fields = Fields {
+            key: schema.add_text_field("key", STRING | STORED),
+            name: schema.add_text_field("name", TEXT),
+            friendly_name: schema.add_text_field("friendly_name", TEXT),
+            description: schema.add_text_field("description", TEXT),
+            publisher: schema.add_text_field("publisher", TEXT),
+            keywords: schema.add_text_field("keywords", TEXT),
+        };
+        let index = Index::create_in_ram(schema.build());
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .context("Failed to open search index reader")?;
+        Ok(Self {
+            index,
+            reader,
+            fields,
+            revision: None,
+            indexed: HashMap::new(),
+        })
+    }
+
+    /// Registry revision the index was last updated for
+    pub fn revision(&self) -> Option<&str> {
+        self.revision.as_deref()
+    }
+
+    /// Number of indexed tapplet versions
+    pub fn len(&self) -> usize {
+        self.indexed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indexed.is_empty()
+    }
+
+    /// Bring the index in line with `tapplets`, loaded at `revision`.
+    ///
+    /// Nothing is done if the revision is the one already indexed. Otherwise only
+    /// tapplets that were added, changed or removed are reindexed. Returns how
+    /// many documents were added or removed.
+    pub fn update(
+        &mut self,
+        revision: Option<&str>,
+        tapplets: &[TappletManifest],
+    ) -> Result<usize> {
+        if revision.is_some() && revision == self.revision.as_deref() {
+            return Ok(0);
+        }
+        let current: HashMap<String, (u64, &TappletManifest)> = tapplets
+            .iter()
+            .filter(|tapplet| !tapplet.yanked)
+            .map(|tapplet| {
+                (
+                    tapplet.fully_qualified_name(),
+                    (fingerprint(tapplet), tapplet),
+                )
+            })
+            .collect();
+        let removed: Vec<_> = self
+            .indexed
+            .iter()
+            .filter(|(key, indexed)| current.get(*key).is_none_or(|(new, _)| new != *indexed))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let added: Vec<_> = current
+            .iter()
+            .filter(|(key, (new, _))| self.indexed.get(*key) != Some(new))
+            .collect();
+
+        let mut changes = 0;
+        if !removed.is_empty() || !added.is_empty() {
+            let mut writer: IndexWriter = self
+                .index
+                .writer_with_num_threads(1, WRITER_HEAP_SIZE)
+                .context("Failed to open search index writer")?;
+            for key in &removed {
+                writer.delete_term(Term::from_field_text(self.fields.key, key));
+                self.indexed.remove(key);
+            }
+            for (key, (print, tapplet)) in &added {
+                writer.add_document(self.document(key, tapplet))?;
+                self.indexed.insert((*key).clone(), *print);
+            }
+            writer.commit().context("Failed to commit search index")?;
+            self.reader
+                .reload()
+                .context("Failed to reload search index")?;
+            // A changed tapplet is removed and added again
+            changes = removed.len() + added.len();
+        }
+        self.revision = revision.map(str::to_string);
+        Ok(changes)
+    }
+
+    fn document(&self, key: &str, tapplet: &TappletManifest) -> TantivyDocument {
+        let mut document = TantivyDocument::default();
+        document.add_text(self.fields.key, key);
+        document.add_text(self.fields.name, &tapplet.name);
+        document.add_text(self.fields.friendly_name, &tapplet.friendly_name);
+        if let Some(description) = &tapplet.description {
+            document.add_text(self.fields.description, description);
+        }
+        document.add_text(self.fields.publisher, &tapplet.publisher);
+        for keyword in tapplet.tags.iter().chain(&tapplet.categories) {
+            document.add_text(self.fields.keywords, keyword);
+        }
+        document
+    }
+
+    /// Fully qualified names of the tapplets best matching `query`, best first.
+    ///
+    /// Words match fuzzily and as prefixes, so `walet` and `wal` both find
+    /// `wallet`, and every word must match. `"quoted words"` match as a phrase,
+    /// and `"quoted wo"*` as a phrase whose last word is a prefix. Matches in the
+    /// name rank above matches in the friendly name, which rank above the rest.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<String>> {
+        let Fields {
+            key,
+            name,
+            friendly_name,
+            description,
+            publisher,
+            keywords,
+        } = self.fields;
+        let text_fields = [name, friendly_name, description, publisher, keywords];
+        let mut parser = QueryParser::for_index(&self.index, text_fields.to_vec());
+        parser.set_conjunction_by_default();
+        parser.set_field_boost(name, 3.0);
+        parser.set_field_boost(friendly_name, 2.0);
+        for field in text_fields {
+            parser.set_field_fuzzy(field, true, 1, true);
+        }
+        // Search boxes get whatever users type, so unbalanced quotes and the
+        // like are dropped rather than failing the search
+        let (query, _) = parser.parse_query_lenient(query);
+
+        let searcher = self.reader.searcher();
+        let top = searcher
+            .search(&query, &TopDocs::with_limit(limit))
+            .context("Failed to search index")?;
+        let mut keys = Vec::with_capacity(top.len());
+        for (_, address) in top {
+            let document: TantivyDocument = searcher.doc(address)?;
+            if let Some(found) = document.get_first(key).and_then(|value| value.as_str()) {
+                keys.push(found.to_string());
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// Changes whenever anything in the manifest does
+fn fingerprint(tapplet: &TappletManifest) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(tapplet)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    fn manifest(name: &str, version: &str, description: &str) -> TappletManifest {
+        let mut manifest =
+            TappletManifest::from_toml_str(&test_utils::manifest_toml(name, version)).unwrap();
+        manifest.description = Some(description.to_string());
+        manifest
+    }
+
+    #[test]
+    fn test_full_text_index() {
+        let mut tapplets = vec![
+            manifest("wallet", "0.1.0", "Send and receive Tari"),
+            manifest(
+                "password_vault",
+                "1.0.0",
+                "Keeps your passwords in a safe place",
+            ),
+            manifest("greeter", "1.0.0", "Says hello to the wallet owner"),
+        ];
+        let mut index = FullTextIndex::new().unwrap();
+        assert_eq!(index.update(Some("a"), &tapplets).unwrap(), 3);
+
+        // Name matches rank first; typos, prefixes and phrases all match
+        let wallet = "test_publisher/wallet@0.1.0";
+        let greeter = "test_publisher/greeter@1.0.0";
+        let vault = "test_publisher/password_vault@1.0.0";
+        assert_eq!(index.search("wallet", 10).unwrap(), [wallet, greeter]);
+        assert_eq!(index.search("walet", 10).unwrap()[0], wallet);
+        assert_eq!(index.search("pass", 10).unwrap(), [vault]);
+        assert_eq!(index.search("\"safe place\"", 10).unwrap(), [vault]);
+        assert!(index.search("\"place safe\"", 10).unwrap().is_empty());
+        assert_eq!(index.search("\"in a sa\"*", 10).unwrap(), [vault]);
+        assert!(index.search("\"unbalanced", 10).is_ok());
+
+        // The same revision isn't indexed again, and a new one only reindexes
+        // what changed
+        assert_eq!(index.update(Some("a"), &[]).unwrap(), 0);
+        tapplets[2].yanked = true;
+        tapplets[0].description = Some("Holds your coins".to_string());
+        assert_eq!(index.update(Some("b"), &tapplets).unwrap(), 3);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.revision(), Some("b"));
+        assert!(index.search("hello", 10).unwrap().is_empty());
+        assert_eq!(index.search("coins", 10).unwrap(), [wallet]);
+
+        // Clones share the index, so other threads can search it
+        let shared = index.clone();
+        let found = std::thread::spawn(move || shared.search("vault", 10).unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(found, [vault]);
+    }
+}
@@ -0,0 +1,293 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use git2::Repository;
+use walkdir::WalkDir;
+
+use super::policy::FetchState;
+use crate::install::list_installed;
+use crate::lock::{LOCK_FILE_NAME, LockFile};
+use crate::manager::InstalledTapplet;
+
+/// Name of the build output directory removed by [`GcPolicy::remove_build_artifacts`]
+const BUILD_ARTIFACTS_DIR: &str = "target";
+
+/// What [`super::TappletRegistry::gc`] is allowed to remove from the cache.
+///
+/// Orphaned clones (interrupted clones and fetch state without a checkout) are
+/// always removed. The registry's own checkout is never removed.
+#[derive(Debug, Clone, Default)]
+pub struct GcPolicy {
+    /// Remove other clones that haven't been fetched for longer than this
+    pub max_age: Option<Duration>,
+    /// Remove the least recently fetched other clones until the cache fits in this many bytes
+    pub max_size: Option<u64>,
+    /// Remove `target/` directories left behind by WASM builds
+    pub remove_build_artifacts: bool,
+    /// Remove installs of tapplet versions older than the newest installed one,
+    /// as left behind by [`crate::install::InstallOptions::versioned_dirs`],
+    /// unless the cache's lock file pins them
+    pub remove_superseded_installs: bool,
+}
+
+impl GcPolicy {
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    pub fn with_build_artifacts_removed(mut self) -> Self {
+        self.remove_build_artifacts = true;
+        self
+    }
+
+    pub fn with_superseded_installs_removed(mut self) -> Self {
+        self.remove_superseded_installs = true;
+        self
+    }
+}
+
+/// Disk usage of a single cached repository clone
+#[derive(Debug, Clone)]
+pub struct RepoUsage {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub last_fetch: Option<SystemTime>,
+    /// The clone is unusable (e.g. an interrupted clone) or its checkout is gone
+    pub is_orphaned: bool,
+}
+
+/// Disk usage of a cache directory
+#[derive(Debug, Clone, Default)]
+pub struct CacheStats {
+    pub cache_directory: PathBuf,
+    /// Repository clones in the cache, largest first
+    pub repos: Vec<RepoUsage>,
+    /// Size of everything in the cache directory, including installed tapplets
+    pub total_bytes: u64,
+}
+
+impl CacheStats {
+    pub fn collect(cache_directory: &Path) -> Result<Self> {
+        let mut stats = CacheStats {
+            cache_directory: cache_directory.to_path_buf(),
+            ..Default::default()
+        };
+        if !cache_directory.exists() {
+            return Ok(stats);
+        }
+
+        let entries = std::fs::read_dir(cache_directory).with_context(|| {
+            format!(
+                "Failed to read cache directory: {}",
+                cache_directory.display()
+            )
+        })?;
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if let Some(repo_path) = fetch_state_repo_path(&path) {
+                if !repo_path.exists() {
+                    stats.repos.push(RepoUsage {
+                        size_bytes: disk_usage(&path),
+                        path: repo_path,
+                        last_fetch: None,
+                        is_orphaned: true,
+                    });
+                }
+                continue;
+            }
+            if !path.join(".git").exists() {
+                continue;
+            }
+            stats.repos.push(RepoUsage {
+                size_bytes: disk_usage(&path),
+                last_fetch: FetchState::read(&path)
+                    .ok()
+                    .and_then(|state| state.last_fetch()),
+                is_orphaned: !has_checkout(&path),
+                path,
+            });
+        }
+
+        stats
+            .repos
+            .sort_by_key(|repo| std::cmp::Reverse(repo.size_bytes));
+        stats.total_bytes = disk_usage(cache_directory);
+        Ok(stats)
+    }
+
+    pub fn repo(&self, path: &Path) -> Option<&RepoUsage> {
+        self.repos.iter().find(|repo| repo.path == path)
+    }
+}
+
+/// What a garbage collection removed
+#[derive(Debug, Clone, Default)]
+pub struct GcReport {
+    pub removed: Vec<PathBuf>,
+    pub freed_bytes: u64,
+}
+
+/// Garbage collect a cache directory, keeping the clone at `keep`
+pub(crate) fn collect_garbage(
+    cache_directory: &Path,
+    keep: &Path,
+    policy: &GcPolicy,
+) -> Result<GcReport> {
+    let stats = CacheStats::collect(cache_directory)?;
+    let mut report = GcReport::default();
+    let mut remaining = Vec::new();
+
+    for repo in stats.repos {
+        if repo.path == keep {
+            continue;
+        }
+        let expired = policy.max_age.is_some_and(|max_age| {
+            repo.last_fetch
+                .or_else(|| modified(&repo.path))
+                .and_then(|time| time.elapsed().ok())
+                .is_some_and(|age| age > max_age)
+        });
+        if repo.is_orphaned || expired {
+            remove_clone(&repo, &mut report)?;
+        } else {
+            remaining.push(repo);
+        }
+    }
+
+    if policy.remove_build_artifacts {
+        for dir in build_artifact_dirs(cache_directory) {
+            let size = disk_usage(&dir);
+            std::fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to remove build artifacts: {}", dir.display()))?;
+            report.freed_bytes += size;
+            report.removed.push(dir);
+        }
+    }
+
+    if policy.remove_superseded_installs {
+        for tapplet in superseded_installs(cache_directory)? {
+            let size = disk_usage(&tapplet.path);
+            std::fs::remove_dir_all(&tapplet.path).with_context(|| {
+                format!(
+                    "Failed to remove installed tapplet: {}",
+                    tapplet.path.display()
+                )
+            })?;
+            report.freed_bytes += size;
+            report.removed.push(tapplet.path);
+        }
+    }
+
+    if let Some(max_size) = policy.max_size {
+        let mut total = stats.total_bytes.saturating_sub(report.freed_bytes);
+        // Oldest first, clones that were never fetched count as oldest
+        remaining.sort_by_key(|repo| repo.last_fetch.or_else(|| modified(&repo.path)));
+        for repo in remaining {
+            if total <= max_size {
+                break;
+            }
+            total = total.saturating_sub(repo.size_bytes);
+            remove_clone(&repo, &mut report)?;
+        }
+    }
+
+    Ok(report)
+}
+
+fn remove_clone(repo: &RepoUsage, report: &mut GcReport) -> Result<()> {
+    if repo.path.exists() {
+        std::fs::remove_dir_all(&repo.path)
+            .with_context(|| format!("Failed to remove cached clone: {}", repo.path.display()))?;
+    }
+    let state_path = FetchState::path(&repo.path);
+    if state_path.exists() {
+        std::fs::remove_file(&state_path)
+            .with_context(|| format!("Failed to remove fetch state: {}", state_path.display()))?;
+    }
+    report.freed_bytes += repo.size_bytes;
+    report.removed.push(repo.path.clone());
+    Ok(())
+}
+
+/// Installs with a newer install of the same tapplet from the same publisher,
+/// except the version the lock file pins
+fn superseded_installs(cache_directory: &Path) -> Result<Vec<InstalledTapplet>> {
+    let lock = LockFile::load(&cache_directory.join(LOCK_FILE_NAME))?;
+    let installed = list_installed(cache_directory)?;
+    let is_superseded = |tapplet: &InstalledTapplet| {
+        let manifest = &tapplet.manifest;
+        let locked = lock
+            .get(&manifest.qualified_name())
+            .is_some_and(|locked| locked.version == manifest.version);
+        !locked
+            && installed.iter().any(|other| {
+                other.manifest.name_matches(&manifest.name)
+                    && other.manifest.publisher == manifest.publisher
+                    && other.manifest.cmp_version(manifest).is_gt()
+            })
+    };
+    Ok(installed
+        .iter()
+        .filter(|tapplet| is_superseded(tapplet))
+        .cloned()
+        .collect())
+}
+
+/// `target/` directories of cargo projects anywhere in the cache
+fn build_artifact_dirs(cache_directory: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut walker = WalkDir::new(cache_directory).into_iter();
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else { continue };
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+        if entry.file_name() == ".git" {
+            walker.skip_current_dir();
+        } else if entry.file_name() == BUILD_ARTIFACTS_DIR
+            && entry
+                .path()
+                .parent()
+                .is_some_and(|parent| parent.join("Cargo.toml").exists())
+        {
+            dirs.push(entry.path().to_path_buf());
+            walker.skip_current_dir();
+        }
+    }
+    dirs
+}
+
+/// Whether a clone has a commit checked out, i.e. it wasn't interrupted
+fn has_checkout(path: &Path) -> bool {
+    Repository::open(path)
+        .ok()
+        .is_some_and(|repo| repo.head().is_ok_and(|head| head.peel_to_commit().is_ok()))
+}
+
+/// The checkout a `<repo>.fetch.toml` state file belongs to
+fn fetch_state_repo_path(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_str()?;
+    let repo_name = file_name.strip_suffix(".fetch.toml")?;
+    Some(path.with_file_name(repo_name))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn disk_usage(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
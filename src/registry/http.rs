@@ -0,0 +1,669 @@
+//! Registries served over plain HTTP(S) instead of git.
+//!
+//! The registry URL points at a directory holding an `index.json` that lists every
+//! tapplet version with its `.tapplet` archive and the archive's SHA-256. When the
+//! registry has an [`IndexVerifier`], an `index.json.sig` next to the index must
+//! carry a signature over it that the verifier accepts. Fetching asks for the index
+//! with `If-None-Match`/`If-Modified-Since`, downloads the archives that aren't
+//! cached yet, resuming interrupted downloads, and unpacks each one so its tapplet
+//! can be installed like one from a git checkout.
+
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::index::{apply_markers, check_listed_as};
+use super::policy::FetchState;
+use super::progress::{FetchProgress, ProgressReporter};
+use super::{FetchResult, sanitize_repo_name};
+use crate::TappletManifest;
+use crate::checksum::{sha256_file, sha256_hex};
+use crate::error::TappletError;
+use crate::install::ExistingInstall;
+use crate::model::TappletDeprecation;
+use crate::package::{PACKAGE_EXTENSION, Package};
+use crate::trace;
+
+pub const INDEX_FILE_NAME: &str = "index.json";
+pub const SIGNATURE_FILE_NAME: &str = "index.json.sig";
+
+/// Largest index or signature that is downloaded
+const MAX_INDEX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Largest archive that is downloaded
+const MAX_ARCHIVE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Where downloads are kept until they are verified and unpacked
+const ARCHIVES_DIR: &str = "archives";
+
+/// Where archives are unpacked, one directory per archive hash
+const TAPPLETS_DIR: &str = "tapplets";
+
+/// Index of an HTTP registry, served as `index.json` at the registry URL
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HttpRegistryIndex {
+    #[serde(default)]
+    pub tapplets: Vec<HttpIndexEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HttpIndexEntry {
+    pub name: String,
+    pub version: String,
+    /// URL of the `.tapplet` archive, relative to the index
+    pub archive: String,
+    /// Hex encoded SHA-256 of the archive, 64 characters
+    pub sha256: String,
+    /// Yank the tapplet without republishing its archive
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub yanked: bool,
+    /// Deprecate the tapplet without republishing its archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<TappletDeprecation>,
+}
+
+impl HttpRegistryIndex {
+    /// Build an index of the `.tapplet` archives in `dir`, to be served from the
+    /// same directory.
+    ///
+    /// Publishers can use this to generate `index.json` for a static bucket.
+    pub fn build(dir: &Path) -> Result<Self> {
+        let mut tapplets = Vec::new();
+        let entries =
+            std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != PACKAGE_EXTENSION) {
+                continue;
+            }
+            let package = Package::read(&path)?;
+            tapplets.push(HttpIndexEntry {
+                name: package.manifest.name,
+                version: package.manifest.version,
+                archive: path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                sha256: sha256_file(&path)?,
+                yanked: false,
+                deprecated: None,
+            });
+        }
+        tapplets.sort_by(|a, b| a.name.cmp(&b.name).then(a.version.cmp(&b.version)));
+        Ok(Self { tapplets })
+    }
+
+    pub fn to_json_string(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Checks the signature published as `index.json.sig` against the raw bytes of `index.json`
+pub trait IndexVerifier: Send + Sync {
+    fn verify(&self, index: &[u8], signature: &[u8]) -> Result<()>;
+}
+
+impl<F> IndexVerifier for F
+where
+    F: Fn(&[u8], &[u8]) -> Result<()> + Send + Sync,
+{
+    fn verify(&self, index: &[u8], signature: &[u8]) -> Result<()> {
+        self(index, signature)
+    }
+}
+
+/// Blocking implementation of load for an HTTP registry
+pub(super) fn load_blocking(url: &str, cache_directory: &Path) -> Result<FetchResult> {
+    let _span = trace::span!("registry_load", url = url);
+    let repo_path = cache_directory.join(sanitize_repo_name(url));
+    let index_path = repo_path.join(INDEX_FILE_NAME);
+    if !index_path.exists() {
+        bail!(TappletError::RepositoryNotFound { path: repo_path });
+    }
+
+    let bytes = std::fs::read(&index_path)
+        .with_context(|| format!("Failed to read {}", index_path.display()))?;
+    let index = parse_index(&bytes)?;
+    let state = FetchState::read(&repo_path)?;
+    Ok(FetchResult {
+        tapplets: load_tapplets(&repo_path, &index),
+        last_fetch: state.last_fetch(),
+        source: state.source,
+        commit_hash: Some(sha256_hex(&bytes)),
+        repository_path: repo_path,
+        was_cloned: false,
+    })
+}
+
+/// Blocking implementation of fetch for an HTTP registry, fetching from
+/// `source_url` into the cache of `url`
+pub(super) fn fetch_blocking(
+    url: &str,
+    source_url: &str,
+    cache_directory: &Path,
+    verifier: Option<&dyn IndexVerifier>,
+    allow_unsigned_index: bool,
+    reporter: &dyn ProgressReporter,
+) -> Result<FetchResult> {
+    let _span = trace::span!("registry_fetch", url = source_url);
+    if verifier.is_none() && !allow_unsigned_index {
+        bail!(TappletError::InvalidIndexSignature(format!(
+            "registry {} has no index verifier and doesn't allow unsigned indexes",
+            url
+        )));
+    }
+    let base = base_url(source_url)?;
+    let repo_path = cache_directory.join(sanitize_repo_name(url));
+    std::fs::create_dir_all(&repo_path)
+        .with_context(|| format!("Failed to create {}", repo_path.display()))?;
+    let agent = ureq::agent();
+
+    let state = FetchState::read(&repo_path)?;
+    let index_path = repo_path.join(INDEX_FILE_NAME);
+    let index_url = base.join(INDEX_FILE_NAME)?;
+    let mut request = agent.request_url("GET", &index_url);
+    if index_path.exists() {
+        if let Some(etag) = &state.etag {
+            request = request.set("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &state.last_modified {
+            request = request.set("If-Modified-Since", last_modified);
+        }
+    }
+    let response = request
+        .call()
+        .with_context(|| format!("Failed to download {}", index_url))?;
+    let etag = response.header("ETag").map(str::to_string).or(state.etag);
+    let last_modified = response
+        .header("Last-Modified")
+        .map(str::to_string)
+        .or(state.last_modified);
+
+    let bytes = if response.status() == 304 {
+        std::fs::read(&index_path)
+            .with_context(|| format!("Failed to read {}", index_path.display()))?
+    } else {
+        let bytes = read_body(response, MAX_INDEX_SIZE)?;
+        if let Some(verifier) = verifier {
+            let signature_url = base.join(SIGNATURE_FILE_NAME)?;
+            let signature = agent
+                .request_url("GET", &signature_url)
+                .call()
+                .map_err(anyhow::Error::from)
+                .and_then(|response| read_body(response, MAX_INDEX_SIZE))
+                .with_context(|| format!("Failed to download {}", signature_url))?;
+            verifier
+                .verify(&bytes, &signature)
+                .map_err(|e| TappletError::InvalidIndexSignature(format!("{:#}", e)))?;
+        }
+        bytes
+    };
+    let index = parse_index(&bytes)?;
+
+    let total = index.tapplets.len();
+    let mut received_bytes = 0;
+    for (done, entry) in index.tapplets.iter().enumerate() {
+        reporter.report(FetchProgress::Receiving {
+            received_objects: done,
+            total_objects: total,
+            indexed_objects: done,
+            received_bytes,
+        });
+        match fetch_archive(&agent, &base, &repo_path, entry, &mut received_bytes) {
+            Ok(()) => {}
+            // A broken archive only takes its own entry out of the registry
+            Err(e) if e.downcast_ref::<TappletError>().is_some() => {
+                trace::warning!(
+                    "Skipping index entry {}@{}: {:#}",
+                    entry.name,
+                    entry.version,
+                    e
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    remove_unlisted(&repo_path, &index)?;
+
+    // Written last so an interrupted fetch asks for the whole index again
+    std::fs::write(&index_path, &bytes)
+        .with_context(|| format!("Failed to write {}", index_path.display()))?;
+    let last_fetch = FetchState::record_http_fetch(&repo_path, source_url, etag, last_modified)?;
+    let commit_hash = sha256_hex(&bytes);
+    let tapplets = load_tapplets(&repo_path, &index);
+    trace::info!(
+        "Fetched registry index {} with {} tapplets",
+        commit_hash,
+        tapplets.len()
+    );
+    reporter.report(FetchProgress::Done);
+
+    Ok(FetchResult {
+        repository_path: repo_path,
+        was_cloned: false,
+        commit_hash: Some(commit_hash),
+        tapplets,
+        last_fetch: Some(last_fetch),
+        source: Some(source_url.to_string()),
+    })
+}
+
+/// The registry URL as a directory, so relative archive URLs resolve inside it
+fn base_url(url: &str) -> Result<Url> {
+    let mut base = Url::parse(url).with_context(|| format!("Invalid registry URL: {}", url))?;
+    if !base.path().ends_with('/') {
+        let path = format!("{}/", base.path());
+        base.set_path(&path);
+    }
+    Ok(base)
+}
+
+/// Read an index or its signature, failing instead of truncating it if it is
+/// larger than `limit` bytes
+fn read_body(response: ureq::Response, limit: u64) -> Result<Vec<u8>> {
+    let url = response.get_url().to_string();
+    let mut bytes = Vec::new();
+    response
+        .into_reader()
+        .take(limit + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > limit {
+        bail!(
+            "{} is too large: registry index files are limited to {} bytes",
+            url,
+            limit
+        );
+    }
+    Ok(bytes)
+}
+
+/// Parse an index, leaving out entries whose hash isn't a SHA-256 in hex, as the
+/// hash names their files in the cache
+fn parse_index(bytes: &[u8]) -> Result<HttpRegistryIndex> {
+    let mut index: HttpRegistryIndex =
+        serde_json::from_slice(bytes).context("Failed to parse registry index")?;
+    index.tapplets.retain(|entry| {
+        let valid = entry.sha256.len() == 64 && entry.sha256.bytes().all(|b| b.is_ascii_hexdigit());
+        if !valid {
+            trace::warning!(
+                "Skipping index entry {}@{}: '{}' is not a SHA-256",
+                entry.name,
+                entry.version,
+                entry.sha256
+            );
+        }
+        valid
+    });
+    Ok(index)
+}
+
+fn tapplet_dir(repo_path: &Path, entry: &HttpIndexEntry) -> PathBuf {
+    repo_path
+        .join(TAPPLETS_DIR)
+        .join(entry.sha256.to_ascii_lowercase())
+}
+
+/// Download, verify and unpack the archive of an entry unless it is already unpacked
+fn fetch_archive(
+    agent: &ureq::Agent,
+    base: &Url,
+    repo_path: &Path,
+    entry: &HttpIndexEntry,
+    received_bytes: &mut usize,
+) -> Result<()> {
+    let dir = tapplet_dir(repo_path, entry);
+    if dir.exists() {
+        return Ok(());
+    }
+    let archive = download_archive(agent, base, repo_path, entry, received_bytes)?;
+    let package = Package::read(&archive)?;
+    if package.manifest.name != entry.name || package.manifest.version != entry.version {
+        bail!(TappletError::InvalidPackage(format!(
+            "{} contains {}@{}",
+            entry.archive, package.manifest.name, package.manifest.version
+        )));
+    }
+    package.verify()?;
+    package.unpack_to(&dir, ExistingInstall::None)?;
+    std::fs::remove_file(&archive)
+        .with_context(|| format!("Failed to remove {}", archive.display()))?;
+    Ok(())
+}
+
+/// Download an archive to `archives/<sha256>.tapplet`, continuing an earlier
+/// partial download with a range request when there is one
+fn download_archive(
+    agent: &ureq::Agent,
+    base: &Url,
+    repo_path: &Path,
+    entry: &HttpIndexEntry,
+    received_bytes: &mut usize,
+) -> Result<PathBuf> {
+    let url = base
+        .join(&entry.archive)
+        .with_context(|| format!("Invalid archive URL: {}", entry.archive))?;
+    let sha256 = entry.sha256.to_ascii_lowercase();
+    let archives = repo_path.join(ARCHIVES_DIR);
+    std::fs::create_dir_all(&archives)
+        .with_context(|| format!("Failed to create {}", archives.display()))?;
+    let partial = archives.join(format!("{}.{}.part", sha256, PACKAGE_EXTENSION));
+
+    let offset = partial.metadata().map(|meta| meta.len()).unwrap_or(0);
+    let request = agent.request_url("GET", &url);
+    let response = if offset > 0 {
+        match request.set("Range", &format!("bytes={}-", offset)).call() {
+            // The partial download is no prefix of what the server has, start over
+            Err(ureq::Error::Status(416, _)) => agent.request_url("GET", &url).call(),
+            response => response,
+        }
+    } else {
+        request.call()
+    }
+    .with_context(|| format!("Failed to download {}", url))?;
+
+    let resumed = response.status() == 206;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&partial)
+        .with_context(|| format!("Failed to open {}", partial.display()))?;
+    let offset = if resumed { offset } else { 0 };
+    let mut body = response
+        .into_reader()
+        .take((MAX_ARCHIVE_SIZE + 1).saturating_sub(offset));
+    let copied = std::io::copy(&mut body, &mut file)
+        .with_context(|| format!("Failed to download {}", url))?;
+    *received_bytes += copied as usize;
+    drop(file);
+    if offset + copied > MAX_ARCHIVE_SIZE {
+        std::fs::remove_file(&partial)
+            .with_context(|| format!("Failed to remove {}", partial.display()))?;
+        bail!(TappletError::InvalidPackage(format!(
+            "{} is larger than the limit of {} bytes",
+            entry.archive, MAX_ARCHIVE_SIZE
+        )));
+    }
+
+    let actual = sha256_file(&partial)?;
+    if actual != sha256 {
+        std::fs::remove_file(&partial)
+            .with_context(|| format!("Failed to remove {}", partial.display()))?;
+        bail!(TappletError::IntegrityMismatch {
+            file: entry.archive.clone(),
+            expected: sha256,
+            actual,
+        });
+    }
+    let archive = archives.join(format!("{}.{}", sha256, PACKAGE_EXTENSION));
+    std::fs::rename(&partial, &archive)
+        .with_context(|| format!("Failed to move download to {}", archive.display()))?;
+    Ok(archive)
+}
+
+/// Remove unpacked archives and partial downloads the index no longer lists
+fn remove_unlisted(repo_path: &Path, index: &HttpRegistryIndex) -> Result<()> {
+    let listed: HashSet<String> = index
+        .tapplets
+        .iter()
+        .map(|entry| entry.sha256.to_ascii_lowercase())
+        .collect();
+    for dir_name in [TAPPLETS_DIR, ARCHIVES_DIR] {
+        let Ok(entries) = std::fs::read_dir(repo_path.join(dir_name)) else {
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let sha256 = file_name.split('.').next().unwrap_or_default();
+            if listed.contains(sha256) {
+                continue;
+            }
+            let path = entry.path();
+            let removed = if path.is_dir() {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+            };
+            removed.with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Load the unpacked manifest of every entry in the index, skipping entries that
+/// weren't unpacked or don't match
+fn load_tapplets(repo_path: &Path, index: &HttpRegistryIndex) -> Vec<(TappletManifest, PathBuf)> {
+    let mut tapplets = Vec::new();
+    for entry in &index.tapplets {
+        let dir = tapplet_dir(repo_path, entry);
+        let loaded =
+            TappletManifest::from_file(dir.join("manifest.toml")).and_then(|mut manifest| {
+                check_listed_as(&manifest, &entry.name, &entry.version)?;
+                apply_markers(&mut manifest, entry.yanked, &entry.deprecated);
+                Ok(manifest)
+            });
+        match loaded {
+            Ok(manifest) => tapplets.push((manifest, dir)),
+            Err(e) => {
+                trace::warning!(
+                    "Skipping index entry {}@{}: {:#}",
+                    entry.name,
+                    entry.version,
+                    e
+                );
+            }
+        }
+    }
+    tapplets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TappletRegistry;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Files served by [`serve`], and the requests it received with their
+    /// conditional and range headers
+    #[derive(Default)]
+    struct Server {
+        files: HashMap<String, Vec<u8>>,
+        requests: Vec<String>,
+    }
+
+    /// Serve files over HTTP on a local port, with an ETag of their hash and
+    /// support for `bytes=N-` ranges
+    fn serve(server: Arc<Mutex<Server>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let path = request_line
+                    .split(' ')
+                    .nth(1)
+                    .unwrap_or_default()
+                    .to_string();
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let Some((name, value)) = line.trim_end().split_once(": ") else {
+                        break;
+                    };
+                    headers.insert(name.to_ascii_lowercase(), value.to_string());
+                }
+
+                let mut server = server.lock().unwrap();
+                let mut logged = path.clone();
+                for header in ["if-none-match", "range"] {
+                    if let Some(value) = headers.get(header) {
+                        logged.push_str(&format!(" {}={}", header, value));
+                    }
+                }
+                server.requests.push(logged);
+                let (status, body, etag) = match server.files.get(&path) {
+                    None => ("404 Not Found", Vec::new(), String::new()),
+                    Some(body) => {
+                        let etag = format!("\"{}\"", sha256_hex(body));
+                        let range = headers
+                            .get("range")
+                            .and_then(|range| range.strip_prefix("bytes="))
+                            .and_then(|range| range.strip_suffix('-'))
+                            .and_then(|offset| offset.parse::<usize>().ok());
+                        if headers.get("if-none-match") == Some(&etag) {
+                            ("304 Not Modified", Vec::new(), etag)
+                        } else if let Some(offset) = range {
+                            ("206 Partial Content", body[offset..].to_vec(), etag)
+                        } else {
+                            ("200 OK", body.clone(), etag)
+                        }
+                    }
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    etag,
+                    body.len()
+                );
+                let _ = stream.write_all(&body);
+            }
+        });
+        format!("http://{}/registry", address)
+    }
+
+    fn sign(index: &[u8]) -> Vec<u8> {
+        sha256_hex(index).into_bytes()
+    }
+
+    #[test]
+    fn test_index_larger_than_the_limit_is_refused() {
+        let body = "x".repeat(11);
+        let response = ureq::Response::new(200, "OK", &body).unwrap();
+        assert_eq!(read_body(response, 11).unwrap(), body.as_bytes());
+        let response = ureq::Response::new(200, "OK", &body).unwrap();
+        let err = read_body(response, 10).unwrap_err();
+        assert!(err.to_string().contains("too large"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_http_registry_fetch() {
+        let temp = tempfile::tempdir().unwrap();
+        let published = temp.path().join("published");
+        std::fs::create_dir_all(&published).unwrap();
+        for (name, version) in [("notes", "0.1.0"), ("wallet", "0.2.0")] {
+            let source = temp.path().join(name);
+            crate::test_utils::write_lua_tapplet(&source, name, version);
+            let archive = crate::package::build(&source).unwrap();
+            std::fs::copy(&archive, published.join(archive.file_name().unwrap())).unwrap();
+        }
+        let index = HttpRegistryIndex::build(&published).unwrap();
+        assert_eq!(index.tapplets[0].archive, "notes-0.1.0.tapplet");
+        let index_json = index.to_json_string().unwrap().into_bytes();
+
+        let server = Arc::new(Mutex::new(Server::default()));
+        for entry in &index.tapplets {
+            let bytes = std::fs::read(published.join(&entry.archive)).unwrap();
+            server
+                .lock()
+                .unwrap()
+                .files
+                .insert(format!("/registry/{}", entry.archive), bytes);
+        }
+        server.lock().unwrap().files.extend([
+            ("/registry/index.json".to_string(), index_json.clone()),
+            ("/registry/index.json.sig".to_string(), sign(&index_json)),
+        ]);
+        let url = serve(server.clone());
+
+        // An earlier fetch was interrupted halfway through the wallet archive
+        let cache = temp.path().join("cache");
+        let wallet = &index.tapplets[1];
+        let archives = cache.join(sanitize_repo_name(&url)).join(ARCHIVES_DIR);
+        std::fs::create_dir_all(&archives).unwrap();
+        let wallet_bytes = std::fs::read(published.join(&wallet.archive)).unwrap();
+        std::fs::write(
+            archives.join(format!("{}.tapplet.part", wallet.sha256)),
+            &wallet_bytes[..10],
+        )
+        .unwrap();
+
+        let verifier = |index: &[u8], signature: &[u8]| {
+            if signature != sign(index) {
+                bail!("signature doesn't match");
+            }
+            Ok(())
+        };
+        let mut registry = TappletRegistry::new_http("http", url.as_str(), cache.clone())
+            .with_index_verifier(Arc::new(verifier));
+        registry.fetch().await.unwrap();
+        assert_eq!(registry.tapplets.len(), 2);
+        let notes = registry.get_by_name("notes").unwrap();
+        assert!(registry.tapplet_dir(notes).join("notes.lua").is_file());
+        assert_eq!(registry.revision(), Some(&sha256_hex(&index_json)));
+        let requests = std::mem::take(&mut server.lock().unwrap().requests);
+        assert_eq!(
+            requests,
+            [
+                "/registry/index.json".to_string(),
+                "/registry/index.json.sig".to_string(),
+                "/registry/notes-0.1.0.tapplet".to_string(),
+                "/registry/wallet-0.2.0.tapplet range=bytes=10-".to_string(),
+            ]
+        );
+
+        // The index hasn't changed, so nothing but the index is requested again
+        registry.fetch().await.unwrap();
+        let requests = std::mem::take(&mut server.lock().unwrap().requests);
+        assert_eq!(
+            requests,
+            [format!(
+                "/registry/index.json if-none-match=\"{}\"",
+                sha256_hex(&index_json)
+            )]
+        );
+        assert_eq!(registry.tapplets.len(), 2);
+
+        // A new index with a bad signature is refused, and the cached one still loads
+        let mut changed = index.clone();
+        changed.tapplets.pop();
+        server.lock().unwrap().files.insert(
+            "/registry/index.json".to_string(),
+            changed.to_json_string().unwrap().into_bytes(),
+        );
+        let err = registry.fetch().await.unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_INDEX_SIGNATURE");
+        registry.load().await.unwrap();
+        assert_eq!(registry.tapplets.len(), 2);
+
+        // Unsigned indexes need to be allowed, and entries without a SHA-256 are skipped
+        let mut unsigned = index.clone();
+        unsigned.tapplets[1].sha256 = "../../escaped".to_string();
+        server.lock().unwrap().files.insert(
+            "/registry/index.json".to_string(),
+            unsigned.to_json_string().unwrap().into_bytes(),
+        );
+        let cache = temp.path().join("unsigned");
+        let mut registry = TappletRegistry::new_http("http", url.as_str(), cache.clone());
+        let err = registry.fetch().await.unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_INDEX_SIGNATURE");
+        let mut registry = registry.allow_unsigned_index();
+        registry.fetch().await.unwrap();
+        assert_eq!(registry.tapplets.len(), 1);
+        assert_eq!(registry.tapplets[0].name, "notes");
+    }
+}
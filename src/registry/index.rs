@@ -0,0 +1,298 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::TappletManifest;
+use crate::checksum::sha256_file;
+use crate::error::TappletError;
+use crate::model::{TappletDeprecation, find_manifest_file};
+
+pub const INDEX_TOML_FILE_NAME: &str = "index.toml";
+pub const INDEX_JSON_FILE_NAME: &str = "index.json";
+
+/// Index of all tapplets in a registry, stored as `index.toml` or `index.json`
+/// at the registry root.
+///
+/// When present it is used instead of walking the `tapplets/` tree, so only the
+/// listed manifests are read.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RegistryIndex {
+    #[serde(default)]
+    pub tapplets: Vec<RegistryIndexEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RegistryIndexEntry {
+    pub name: String,
+    pub version: String,
+    /// Directory containing the tapplet's manifest, relative to the registry root
+    pub path: String,
+    /// Hex encoded SHA-256 of the manifest file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Yank the tapplet without changing its manifest
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub yanked: bool,
+    /// Deprecate the tapplet without changing its manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<TappletDeprecation>,
+}
+
+/// Apply the yank and deprecation markers of an index entry to its manifest
+pub(super) fn apply_markers(
+    manifest: &mut TappletManifest,
+    yanked: bool,
+    deprecated: &Option<TappletDeprecation>,
+) {
+    manifest.yanked |= yanked;
+    if deprecated.is_some() {
+        manifest.deprecated = deprecated.clone();
+    }
+}
+
+impl RegistryIndex {
+    /// Read the index from a registry checkout, preferring `index.toml` over `index.json`.
+    ///
+    /// Returns `None` if the registry has no index.
+    pub fn from_repo(repo_path: &Path) -> Result<Option<Self>> {
+        let toml_path = repo_path.join(INDEX_TOML_FILE_NAME);
+        if toml_path.exists() {
+            let content = std::fs::read_to_string(&toml_path)?;
+            return Ok(Some(toml::from_str(&content).with_context(|| {
+                format!("Failed to parse registry index: {}", toml_path.display())
+            })?));
+        }
+
+        let json_path = repo_path.join(INDEX_JSON_FILE_NAME);
+        if json_path.exists() {
+            let content = std::fs::read_to_string(&json_path)?;
+            return Ok(Some(serde_json::from_str(&content).with_context(|| {
+                format!("Failed to parse registry index: {}", json_path.display())
+            })?));
+        }
+
+        Ok(None)
+    }
+
+    /// Build an index for a registry checkout by scanning `tapplets/` for manifests.
+    ///
+    /// Registry maintainers can use this to generate the index file.
+    pub fn build(repo_path: &Path) -> Result<Self> {
+        let mut tapplets = Vec::new();
+        for (manifest, dir) in super::walk_tapplets(repo_path)? {
+            let relative = dir.strip_prefix(repo_path).unwrap_or(&dir);
+            tapplets.push(RegistryIndexEntry {
+                name: manifest.name,
+                version: manifest.version,
+                path: relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                sha256: Some(sha256_file(&manifest_file(&dir)?)?),
+                yanked: false,
+                deprecated: None,
+            });
+        }
+        tapplets.sort_by(|a, b| a.name.cmp(&b.name).then(a.version.cmp(&b.version)));
+        Ok(Self { tapplets })
+    }
+
+    pub fn to_toml_string(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Load every manifest listed in the index.
+    ///
+    /// Entries whose manifest is missing, unparsable, doesn't match the listed
+    /// name/version or fails its hash check are skipped with a warning.
+    pub(crate) fn load_tapplets(&self, repo_path: &Path) -> Vec<(TappletManifest, PathBuf)> {
+        let mut tapplets = Vec::new();
+        for entry in &self.tapplets {
+            match entry.load(repo_path) {
+                Ok(loaded) => tapplets.push(loaded),
+                Err(e) => {
+                    crate::trace::warning!(
+                        "Skipping index entry {}@{}: {:#}",
+                        entry.name,
+                        entry.version,
+                        e
+                    );
+                }
+            }
+        }
+        tapplets
+    }
+}
+
+/// The manifest of the tapplet in `dir`, in whichever format it is written
+fn manifest_file(dir: &Path) -> Result<PathBuf> {
+    find_manifest_file(dir).ok_or_else(|| {
+        TappletError::ManifestNotFound {
+            path: dir.to_path_buf(),
+        }
+        .into()
+    })
+}
+
+/// Fail with [`TappletError::ManifestMismatch`] unless `manifest` is the tapplet
+/// an index lists as `name@version`
+pub(super) fn check_listed_as(manifest: &TappletManifest, name: &str, version: &str) -> Result<()> {
+    if manifest.name != name {
+        anyhow::bail!(TappletError::ManifestMismatch {
+            field: "name",
+            expected: name.to_string(),
+            actual: manifest.name.clone(),
+        });
+    }
+    if manifest.version != version {
+        anyhow::bail!(TappletError::ManifestMismatch {
+            field: "version",
+            expected: version.to_string(),
+            actual: manifest.version.clone(),
+        });
+    }
+    Ok(())
+}
+
+impl RegistryIndexEntry {
+    pub(super) fn load(&self, repo_path: &Path) -> Result<(TappletManifest, PathBuf)> {
+        let dir = self.dir(repo_path)?;
+        let manifest_path = manifest_file(&dir)?;
+        if let Some(expected) = &self.sha256 {
+            let actual = sha256_file(&manifest_path)?;
+            if !actual.eq_ignore_ascii_case(expected) {
+                anyhow::bail!(TappletError::IntegrityMismatch {
+                    file: manifest_path.display().to_string(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        let mut manifest = TappletManifest::from_file(&manifest_path)?;
+        check_listed_as(&manifest, &self.name, &self.version)?;
+        #[cfg(feature = "host-core")]
+        crate::local_folder_lua_tapplet::check_scripts(
+            &dir,
+            &crate::sandbox::SandboxOptions::default(),
+        )?;
+        apply_markers(&mut manifest, self.yanked, &self.deprecated);
+        Ok((manifest, dir))
+    }
+
+    /// The entry's directory in `repo_path`. Paths that are absolute, have `..`
+    /// in them or lead out of the checkout through a symlink are refused.
+    fn dir(&self, repo_path: &Path) -> Result<PathBuf> {
+        let relative = Path::new(&self.path);
+        let plain = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !plain || self.path.is_empty() {
+            anyhow::bail!("path '{}' is not inside the registry", self.path);
+        }
+        let dir = repo_path.join(relative);
+        let resolved = dir
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", dir.display()))?;
+        if !resolved.starts_with(repo_path.canonicalize()?) {
+            anyhow::bail!("path '{}' is not inside the registry", self.path);
+        }
+        Ok(dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn test_build_and_load_index() {
+        let temp = tempfile::tempdir().unwrap();
+        let repo = temp.path();
+        test_utils::write_lua_tapplet(&repo.join("tapplets").join("a"), "a", "0.1.0");
+        test_utils::write_lua_tapplet(&repo.join("tapplets").join("b"), "b", "0.2.0");
+
+        let index = RegistryIndex::build(repo).unwrap();
+        assert_eq!(index.tapplets.len(), 2);
+        assert_eq!(index.tapplets[0].path, "tapplets/a");
+        std::fs::write(
+            repo.join(INDEX_TOML_FILE_NAME),
+            index.to_toml_string().unwrap(),
+        )
+        .unwrap();
+
+        let index = RegistryIndex::from_repo(repo).unwrap().unwrap();
+        let loaded = index.load_tapplets(repo);
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].0.name, "b");
+        assert_eq!(loaded[1].1, repo.join("tapplets/b"));
+    }
+
+    #[test]
+    fn test_index_entry_with_bad_hash_is_skipped() {
+        let temp = tempfile::tempdir().unwrap();
+        let repo = temp.path();
+        test_utils::write_lua_tapplet(&repo.join("tapplets").join("a"), "a", "0.1.0");
+        std::fs::write(
+            repo.join(INDEX_JSON_FILE_NAME),
+            r#"{"tapplets": [{"name": "a", "version": "0.1.0", "path": "tapplets/a", "sha256": "00"}]}"#,
+        )
+        .unwrap();
+
+        let index = RegistryIndex::from_repo(repo).unwrap().unwrap();
+        assert!(index.load_tapplets(repo).is_empty());
+        let err = index.tapplets[0].load(repo).unwrap_err();
+        assert_eq!(crate::error_code(&err), "INTEGRITY_MISMATCH");
+
+        let mut entry = index.tapplets[0].clone();
+        entry.sha256 = None;
+        entry.version = "0.2.0".to_string();
+        let err = entry.load(repo).unwrap_err();
+        assert_eq!(crate::error_code(&err), "MANIFEST_MISMATCH");
+        assert_eq!(
+            err.to_string(),
+            "Manifest version '0.1.0' does not match expected '0.2.0'"
+        );
+    }
+
+    #[test]
+    fn test_index_entry_outside_registry_is_skipped() {
+        let temp = tempfile::tempdir().unwrap();
+        let repo = temp.path().join("registry");
+        test_utils::write_lua_tapplet(&repo.join("tapplets").join("a"), "a", "0.1.0");
+        test_utils::write_lua_tapplet(&temp.path().join("outside"), "a", "0.1.0");
+        let outside = temp.path().join("outside").display().to_string();
+
+        for path in ["../outside", outside.as_str(), "tapplets/../../outside", ""] {
+            let entry = RegistryIndexEntry {
+                name: "a".to_string(),
+                version: "0.1.0".to_string(),
+                path: path.to_string(),
+                sha256: None,
+                yanked: false,
+                deprecated: None,
+            };
+            let err = entry.load(&repo).unwrap_err();
+            assert!(
+                err.to_string().contains("not inside the registry"),
+                "{}",
+                err
+            );
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(temp.path().join("outside"), repo.join("link")).unwrap();
+            let entry = RegistryIndexEntry {
+                name: "a".to_string(),
+                version: "0.1.0".to_string(),
+                path: "link".to_string(),
+                sha256: None,
+                yanked: false,
+                deprecated: None,
+            };
+            assert!(entry.load(&repo).is_err());
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use super::{DuplicatePolicy, RegistrySource, TappletRegistry, conflict};
+use crate::TappletManifest;
+
+/// A registry whose tapplets are pushed directly instead of fetched, so tests of
+/// code working with registries don't need a git remote.
+///
+/// Convert it into a loaded [`TappletRegistry`] with `into()`. Fetching or loading
+/// that registry keeps the pushed tapplets. Tapplets pushed twice with the same
+/// name and version are resolved with [`DuplicatePolicy::KeepFirst`].
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryRegistry {
+    name: String,
+    tapplets: Vec<(TappletManifest, PathBuf)>,
+}
+
+impl InMemoryRegistry {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            tapplets: Vec::new(),
+        }
+    }
+
+    /// Add a tapplet without files, enough for lookups and dependency resolution
+    pub fn push(&mut self, manifest: TappletManifest) -> &mut Self {
+        self.push_with_dir(manifest, PathBuf::new())
+    }
+
+    /// Add a tapplet whose files are in `dir`, so it can also be installed
+    pub fn push_with_dir(&mut self, manifest: TappletManifest, dir: PathBuf) -> &mut Self {
+        self.tapplets.push((manifest, dir));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.tapplets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tapplets.is_empty()
+    }
+}
+
+impl From<InMemoryRegistry> for TappletRegistry {
+    fn from(memory: InMemoryRegistry) -> Self {
+        let url = format!("memory://{}", memory.name);
+        let mut registry = TappletRegistry {
+            source: RegistrySource::Memory,
+            ..TappletRegistry::new(memory.name, url, PathBuf::new())
+        };
+        let (tapplets, conflicts) =
+            conflict::resolve(memory.tapplets, DuplicatePolicy::KeepFirst, false);
+        registry.set_tapplets(tapplets);
+        registry.conflicts = conflicts;
+        registry.is_loaded = true;
+        registry
+    }
+}
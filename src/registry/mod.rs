@@ -0,0 +1,2175 @@
+mod conflict;
+mod export;
+#[cfg(feature = "search-index")]
+mod full_text;
+mod gc;
+#[cfg(feature = "http-registry")]
+mod http;
+mod index;
+mod memory;
+mod policy;
+mod progress;
+mod search;
+mod validate;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+pub use conflict::{DuplicatePolicy, TappletConflict, TappletCopy};
+pub use export::{EXPORT_SCHEMA_VERSION, ExportedTapplet, ExportedVersion, RegistryExport};
+#[cfg(feature = "search-index")]
+pub use full_text::FullTextIndex;
+pub use gc::{CacheStats, GcPolicy, GcReport, RepoUsage};
+#[cfg(feature = "http-registry")]
+pub use http::{HttpIndexEntry, HttpRegistryIndex, IndexVerifier};
+pub use index::{RegistryIndex, RegistryIndexEntry};
+pub use memory::InMemoryRegistry;
+pub use policy::FetchPolicy;
+pub use progress::{FetchProgress, NoProgress, ProgressReporter};
+pub use search::SearchQuery;
+pub use validate::{FindingKind, RegistryFinding, RegistryReport};
+
+use crate::TappletManifest;
+use crate::error::TappletError;
+use crate::local_folder_lua_tapplet::tapplet_dir_runtime;
+#[cfg(feature = "metrics")]
+use crate::metrics::{self, MetricsSink};
+use crate::model::{RuntimeKind, find_manifest_file, parse_version_req};
+use crate::resolver;
+use crate::trace;
+use crate::trust::{TrustPolicy, TrustReport};
+use anyhow::{Context, Result};
+use git2::{
+    AutotagOption, FetchOptions as Git2FetchOptions, RemoteCallbacks, Repository,
+    build::{CheckoutBuilder, RepoBuilder},
+};
+use policy::FetchState;
+
+/// Options controlling how a registry repository is cloned and updated
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    /// Only fetch the latest commit (`depth = 1`) instead of the full history
+    pub shallow: bool,
+    /// Only check out the `tapplets/` directory and the registry index
+    pub sparse: bool,
+}
+
+impl FetchOptions {
+    /// Shallow clone with a sparse checkout, the cheapest way to fetch a registry
+    pub fn minimal() -> Self {
+        Self {
+            shallow: true,
+            sparse: true,
+        }
+    }
+}
+
+/// Which revision of a git registry to check out
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum RegistryRef {
+    /// The `main` branch, or `master` if there is no `main`
+    #[default]
+    DefaultBranch,
+    /// The latest commit of a branch, e.g. a testnet's dev branch
+    Branch(String),
+    /// A release tag, for reproducible deployments
+    Tag(String),
+    /// A commit hash, full or abbreviated
+    Rev(String),
+}
+
+impl RegistryRef {
+    /// Tags and commits may be outside a shallow clone's history, so they are
+    /// always fetched in full
+    fn needs_history(&self) -> bool {
+        matches!(self, RegistryRef::Tag(_) | RegistryRef::Rev(_))
+    }
+}
+
+impl fmt::Display for RegistryRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryRef::DefaultBranch => write!(f, "default branch"),
+            RegistryRef::Branch(branch) => write!(f, "branch {}", branch),
+            RegistryRef::Tag(tag) => write!(f, "tag {}", tag),
+            RegistryRef::Rev(rev) => write!(f, "commit {}", rev),
+        }
+    }
+}
+
+/// How long to wait before trying the first mirror when the primary URL fails
+pub const DEFAULT_FAILOVER_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Where a registry is fetched from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RegistrySource {
+    Git,
+    #[cfg(feature = "http-registry")]
+    Http,
+    /// A plain directory laid out like a checkout
+    Local,
+    /// Tapplets pushed through [`InMemoryRegistry`]
+    Memory,
+}
+
+#[derive(Clone)]
+pub struct TappletRegistry {
+    pub name: String,
+    /// URL of the git repository, or of the directory serving an HTTP registry's index
+    pub git_url: String,
+    pub cache_directory: PathBuf,
+    pub current_revision: Option<String>,
+    pub tapplets: Vec<TappletManifest>,
+    /// Directory of each tapplet in the checkout, keyed by fully qualified name
+    tapplet_dirs: HashMap<String, PathBuf>,
+    source: RegistrySource,
+    registry_ref: RegistryRef,
+    /// Tried in order when fetching from `git_url` fails
+    mirrors: Vec<String>,
+    failover_backoff: Duration,
+    /// URL the last successful fetch came from
+    fetched_from: Option<String>,
+    duplicate_policy: DuplicatePolicy,
+    /// Tapplets published more than once, found by the last load or fetch
+    conflicts: Vec<TappletConflict>,
+    #[cfg(feature = "http-registry")]
+    index_verifier: Option<Arc<dyn IndexVerifier>>,
+    #[cfg(feature = "http-registry")]
+    allow_unsigned_index: bool,
+    fetch_options: FetchOptions,
+    fetch_policy: FetchPolicy,
+    last_fetch: Option<SystemTime>,
+    is_loaded: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<dyn MetricsSink>>,
+    /// Whether loads and fetches keep `search_index` up to date
+    #[cfg(feature = "search-index")]
+    full_text: bool,
+    #[cfg(feature = "search-index")]
+    search_index: Option<FullTextIndex>,
+}
+
+impl TappletRegistry {
+    pub fn new<S: AsRef<str>>(name: S, git_url: S, cache_directory: PathBuf) -> Self {
+        Self {
+            name: name.as_ref().to_string(),
+            git_url: git_url.as_ref().to_string(),
+            cache_directory,
+            current_revision: None,
+            tapplets: Vec::new(),
+            tapplet_dirs: HashMap::new(),
+            source: RegistrySource::Git,
+            registry_ref: RegistryRef::default(),
+            mirrors: Vec::new(),
+            failover_backoff: DEFAULT_FAILOVER_BACKOFF,
+            fetched_from: None,
+            duplicate_policy: DuplicatePolicy::default(),
+            conflicts: Vec::new(),
+            #[cfg(feature = "http-registry")]
+            index_verifier: None,
+            #[cfg(feature = "http-registry")]
+            allow_unsigned_index: false,
+            fetch_options: FetchOptions::default(),
+            fetch_policy: FetchPolicy::default(),
+            last_fetch: None,
+            is_loaded: false,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            #[cfg(feature = "search-index")]
+            full_text: false,
+            #[cfg(feature = "search-index")]
+            search_index: None,
+        }
+    }
+
+    /// A registry served over HTTP(S) from `url`, the directory holding its
+    /// `index.json` and `.tapplet` archives. See [`HttpRegistryIndex`].
+    #[cfg(feature = "http-registry")]
+    pub fn new_http<S: AsRef<str>>(name: S, url: S, cache_directory: PathBuf) -> Self {
+        Self {
+            source: RegistrySource::Http,
+            ..Self::new(name, url, cache_directory)
+        }
+    }
+
+    /// A registry read straight from `dir`, laid out like a registry checkout
+    /// (`tapplets/**/manifest.toml`, or an index), without git.
+    ///
+    /// `fetch()` and `load()` both re-read the directory. The registry is named
+    /// after the directory.
+    pub fn from_local_path<P: AsRef<Path>>(dir: P) -> Self {
+        let dir = dir.as_ref();
+        let name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| dir.display().to_string());
+        Self {
+            source: RegistrySource::Local,
+            ..Self::new(name, dir.display().to_string(), dir.to_path_buf())
+        }
+    }
+
+    /// Only accept an HTTP registry's index if `verifier` accepts the signature
+    /// served next to it as `index.json.sig`
+    #[cfg(feature = "http-registry")]
+    pub fn with_index_verifier(mut self, verifier: Arc<dyn IndexVerifier>) -> Self {
+        self.index_verifier = Some(verifier);
+        self
+    }
+
+    /// Accept an HTTP registry's index without a signature check. Without this or
+    /// an index verifier, fetching an HTTP registry fails.
+    #[cfg(feature = "http-registry")]
+    pub fn allow_unsigned_index(mut self) -> Self {
+        self.allow_unsigned_index = true;
+        self
+    }
+
+    /// Report how long each fetch takes, and whether it failed, to the given sink
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics_sink(mut self, sink: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = Some(sink);
+        self
+    }
+
+    /// Build a [`FullTextIndex`] on the next load or fetch, and update it on each
+    /// one after, for [`TappletRegistry::search_full_text`]
+    #[cfg(feature = "search-index")]
+    pub fn with_search_index(mut self) -> Self {
+        self.full_text = true;
+        self
+    }
+
+    /// The full-text index, once a load or fetch has built it
+    #[cfg(feature = "search-index")]
+    pub fn search_index(&self) -> Option<&FullTextIndex> {
+        self.search_index.as_ref()
+    }
+
+    /// Check out `registry_ref` instead of the default branch on subsequent fetches.
+    /// HTTP registries ignore it.
+    ///
+    /// Registries pinned to different refs need their own cache directories, as
+    /// registries with the same URL and cache directory share one checkout.
+    pub fn with_ref(mut self, registry_ref: RegistryRef) -> Self {
+        self.registry_ref = registry_ref;
+        self
+    }
+
+    pub fn registry_ref(&self) -> &RegistryRef {
+        &self.registry_ref
+    }
+
+    /// Fall back to these URLs, in order, when fetching from the primary URL fails.
+    ///
+    /// Mirrors share the primary URL's cache, so they must serve the same registry.
+    pub fn with_mirrors<I, S>(mut self, mirrors: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.mirrors = mirrors.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn mirrors(&self) -> &[String] {
+        &self.mirrors
+    }
+
+    /// Wait `backoff` before trying the first mirror, doubling the wait before each
+    /// further one. Defaults to [`DEFAULT_FAILOVER_BACKOFF`].
+    pub fn with_failover_backoff(mut self, backoff: Duration) -> Self {
+        self.failover_backoff = backoff;
+        self
+    }
+
+    /// Use the given options for subsequent `fetch()` calls. HTTP registries ignore them.
+    /// Set what loading and fetching do with tapplets published more than once
+    pub fn with_duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    /// Tapplets published more than once, found by the last load or fetch. Under
+    /// [`DuplicatePolicy::Error`] these are kept after the load or fetch fails.
+    pub fn conflicts(&self) -> &[TappletConflict] {
+        &self.conflicts
+    }
+
+    pub fn with_fetch_options(mut self, fetch_options: FetchOptions) -> Self {
+        self.fetch_options = fetch_options;
+        self
+    }
+
+    pub fn fetch_options(&self) -> &FetchOptions {
+        &self.fetch_options
+    }
+
+    /// Use the given policy for subsequent `refresh()` calls
+    pub fn with_fetch_policy(mut self, fetch_policy: FetchPolicy) -> Self {
+        self.fetch_policy = fetch_policy;
+        self
+    }
+
+    pub fn fetch_policy(&self) -> FetchPolicy {
+        self.fetch_policy
+    }
+
+    /// When the cached checkout was last successfully fetched from the remote
+    pub fn last_fetch(&self) -> Option<SystemTime> {
+        self.last_fetch
+    }
+
+    /// URL the cached copy was last fetched from, the primary URL or one of the mirrors
+    pub fn fetched_from(&self) -> Option<&str> {
+        self.fetched_from.as_deref()
+    }
+
+    pub fn revision(&self) -> Option<&String> {
+        self.current_revision.as_ref()
+    }
+
+    /// Whether tapplets have been loaded by `fetch()` or `load()`
+    pub fn is_loaded(&self) -> bool {
+        self.is_loaded
+    }
+
+    /// Disk usage of the cache directory and the repository clones in it
+    pub fn cache_stats(&self) -> Result<CacheStats> {
+        CacheStats::collect(&self.cache_directory)
+    }
+
+    /// Remove orphaned clones, and stale clones, build artifacts and superseded
+    /// installs allowed by the policy.
+    ///
+    /// The cache directory may be shared by several registries, so only this
+    /// registry's own checkout is guaranteed to be kept. Local and in-memory
+    /// registries have no cache, so nothing is removed.
+    pub fn gc(&self, policy: &GcPolicy) -> Result<GcReport> {
+        if matches!(self.source, RegistrySource::Local | RegistrySource::Memory) {
+            return Ok(GcReport::default());
+        }
+        let repo_path = self.cache_directory.join(sanitize_repo_name(&self.git_url));
+        gc::collect_garbage(&self.cache_directory, &repo_path, policy)
+    }
+
+    /// Load tapplets from an already-fetched repository in the cache directory
+    /// without performing a fetch operation.
+    ///
+    /// This is useful when you want to read the cached data without updating it.
+    /// Returns an error if the repository hasn't been fetched yet.
+    pub async fn load(&mut self) -> Result<()> {
+        if self.source == RegistrySource::Memory {
+            return self.update_search_index();
+        }
+        let git_url = self.git_url.clone();
+        let cache_directory = self.cache_directory.clone();
+        let source = self.source;
+
+        let result = tokio::task::spawn_blocking(move || match source {
+            RegistrySource::Git => Self::load_blocking(&git_url, &cache_directory),
+            #[cfg(feature = "http-registry")]
+            RegistrySource::Http => http::load_blocking(&git_url, &cache_directory),
+            RegistrySource::Local => Self::load_local_blocking(&cache_directory),
+            RegistrySource::Memory => unreachable!("in-memory registries have nothing to load"),
+        })
+        .await
+        .context("Failed to spawn blocking task")??;
+
+        self.set_result(result)
+    }
+
+    /// Bring the registry up to date according to its [`FetchPolicy`].
+    ///
+    /// Unlike `fetch()` and `load()`, callers don't need to know whether the
+    /// network is available or the cache is fresh.
+    pub async fn refresh(&mut self) -> Result<()> {
+        match self.fetch_policy {
+            FetchPolicy::AlwaysFetch => self.fetch().await,
+            FetchPolicy::OfflineOnly => self.load().await,
+            FetchPolicy::PreferCache { max_age } => {
+                let cached = self.load().await;
+                let fresh = cached.is_ok()
+                    && self
+                        .last_fetch
+                        .and_then(|fetched| fetched.elapsed().ok())
+                        .is_some_and(|age| age <= max_age);
+                if fresh {
+                    return Ok(());
+                }
+                match self.fetch().await {
+                    Ok(()) => Ok(()),
+                    Err(e) if cached.is_ok() => {
+                        trace::warning!(
+                            "Failed to fetch registry {}, using cached copy: {:#}",
+                            self.name,
+                            e
+                        );
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Fetch or update the repository from the remote and load tapplets.
+    ///
+    /// This will clone the repository if it doesn't exist, or pull updates if it does.
+    pub async fn fetch(&mut self) -> Result<()> {
+        self.fetch_with_progress(Arc::new(NoProgress)).await
+    }
+
+    /// Fetch like [`TappletRegistry::fetch`], reporting clone/fetch progress to `reporter`.
+    ///
+    /// If the primary URL fails, each mirror is tried in turn, and the error of the
+    /// last one is returned if they all fail.
+    pub async fn fetch_with_progress(&mut self, reporter: Arc<dyn ProgressReporter>) -> Result<()> {
+        if self.source == RegistrySource::Memory {
+            return self.update_search_index();
+        }
+        #[cfg(feature = "metrics")]
+        let started = std::time::Instant::now();
+        let mut result = self.fetch_from(&self.git_url, reporter.clone()).await;
+        let mut backoff = self.failover_backoff;
+        for mirror in &self.mirrors {
+            let Err(e) = &result else { break };
+            trace::warning!(
+                "Failed to fetch registry {}, trying mirror {} in {:?}: {:#}",
+                self.name,
+                mirror,
+                backoff,
+                e
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+            result = self.fetch_from(mirror, reporter.clone()).await;
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(sink) = &self.metrics {
+            let status = if result.is_ok() { "ok" } else { "error" };
+            sink.observe_histogram(
+                metrics::REGISTRY_FETCH_DURATION_SECONDS,
+                &[("registry", self.name.as_str()), ("status", status)],
+                started.elapsed().as_secs_f64(),
+            );
+        }
+        self.set_result(result?)
+    }
+
+    /// Fetch from one source URL into the cache of the primary URL
+    async fn fetch_from(
+        &self,
+        source_url: &str,
+        reporter: Arc<dyn ProgressReporter>,
+    ) -> Result<FetchResult> {
+        // Use tokio to run the blocking git operations in a separate thread
+        let git_url = self.git_url.clone();
+        let source_url = source_url.to_string();
+        let cache_directory = self.cache_directory.clone();
+        let fetch_options = self.fetch_options.clone();
+        let registry_ref = self.registry_ref.clone();
+        let source = self.source;
+        #[cfg(feature = "http-registry")]
+        let index_verifier = self.index_verifier.clone();
+        #[cfg(feature = "http-registry")]
+        let allow_unsigned_index = self.allow_unsigned_index;
+
+        tokio::task::spawn_blocking(move || match source {
+            RegistrySource::Git => Self::fetch_blocking(
+                &git_url,
+                &source_url,
+                &cache_directory,
+                &fetch_options,
+                &registry_ref,
+                &*reporter,
+            ),
+            #[cfg(feature = "http-registry")]
+            RegistrySource::Http => http::fetch_blocking(
+                &git_url,
+                &source_url,
+                &cache_directory,
+                index_verifier.as_deref(),
+                allow_unsigned_index,
+                &*reporter,
+            ),
+            RegistrySource::Local => Self::load_local_blocking(&cache_directory),
+            RegistrySource::Memory => unreachable!("in-memory registries have nothing to fetch"),
+        })
+        .await
+        .context("Failed to spawn blocking task")?
+    }
+
+    /// Update the registry with fetched or loaded data
+    fn set_result(&mut self, result: FetchResult) -> Result<()> {
+        let by_directory = matches!(self.source, RegistrySource::Git | RegistrySource::Local);
+        let (tapplets, conflicts) =
+            conflict::resolve(result.tapplets, self.duplicate_policy, by_directory);
+        self.conflicts = conflicts;
+        if self.duplicate_policy == DuplicatePolicy::Error && !self.conflicts.is_empty() {
+            anyhow::bail!(TappletError::DuplicateTapplet {
+                conflicts: self.conflicts.clone(),
+            });
+        }
+        self.current_revision = result.commit_hash;
+        self.last_fetch = result.last_fetch;
+        self.fetched_from = result.source;
+        self.set_tapplets(tapplets);
+        self.is_loaded = true;
+        self.update_search_index()
+    }
+
+    /// Reindex what changed since the last load or fetch, if full-text search is on
+    fn update_search_index(&mut self) -> Result<()> {
+        #[cfg(feature = "search-index")]
+        if self.full_text {
+            let index = match &mut self.search_index {
+                Some(index) => index,
+                None => self.search_index.insert(FullTextIndex::new()?),
+            };
+            index.update(self.current_revision.as_deref(), &self.tapplets)?;
+        }
+        Ok(())
+    }
+
+    fn set_tapplets(&mut self, tapplets: Vec<(TappletManifest, PathBuf)>) {
+        self.tapplet_dirs = tapplets
+            .iter()
+            .map(|(tapplet, dir)| (tapplet.fully_qualified_name(), dir.clone()))
+            .collect();
+        self.tapplets = tapplets.into_iter().map(|(tapplet, _)| tapplet).collect();
+    }
+
+    fn ensure_loaded(&self) -> Result<()> {
+        if !self.is_loaded {
+            anyhow::bail!(TappletError::RegistryNotLoaded);
+        }
+        Ok(())
+    }
+
+    /// Directory of a loaded tapplet inside the cached checkout
+    pub(crate) fn tapplet_dir(&self, tapplet: &TappletManifest) -> PathBuf {
+        self.tapplet_dirs
+            .get(&tapplet.fully_qualified_name())
+            .cloned()
+            .unwrap_or_else(|| {
+                self.cache_directory
+                    .join(sanitize_repo_name(&self.git_url))
+                    .join("tapplets")
+                    .join(&tapplet.name)
+            })
+    }
+
+    /// Blocking implementation of load for use with tokio::spawn_blocking
+    fn load_blocking(git_url: &str, cache_directory: &Path) -> Result<FetchResult> {
+        let _span = trace::span!("registry_load", url = git_url);
+        let repo_path = cache_directory.join(sanitize_repo_name(git_url));
+
+        // Check if the repository exists
+        if !repo_path.exists() {
+            anyhow::bail!(TappletError::RepositoryNotFound { path: repo_path });
+        }
+
+        // Open the repository
+        let repository =
+            Repository::open(&repo_path).context("Failed to open cached repository")?;
+
+        // Get the current commit hash
+        let head = repository.head().context("Failed to get HEAD reference")?;
+        let commit = head
+            .peel_to_commit()
+            .context("Failed to peel HEAD to commit")?;
+        let commit_hash = commit.id().to_string();
+
+        // Parse all tapplet configurations from the repository
+        let tapplets = parse_tapplets_from_repo(&repo_path)
+            .context("Failed to parse tapplet configurations")?;
+        let state = FetchState::read(&repo_path)?;
+
+        Ok(FetchResult {
+            repository_path: repo_path,
+            was_cloned: false,
+            commit_hash: Some(commit_hash),
+            tapplets,
+            last_fetch: state.last_fetch(),
+            source: state.source,
+        })
+    }
+
+    /// Blocking implementation of load and fetch for a registry in a plain directory
+    fn load_local_blocking(dir: &Path) -> Result<FetchResult> {
+        let _span = trace::span!("registry_load", url = %dir.display());
+        if !dir.exists() {
+            anyhow::bail!(TappletError::RepositoryNotFound {
+                path: dir.to_path_buf()
+            });
+        }
+        let tapplets =
+            parse_tapplets_from_repo(dir).context("Failed to parse tapplet configurations")?;
+        Ok(FetchResult {
+            repository_path: dir.to_path_buf(),
+            was_cloned: false,
+            commit_hash: None,
+            tapplets,
+            last_fetch: None,
+            source: None,
+        })
+    }
+
+    /// Blocking implementation of fetch for use with tokio::spawn_blocking, fetching
+    /// from `source_url` into the cache of `git_url`
+    fn fetch_blocking(
+        git_url: &str,
+        source_url: &str,
+        cache_directory: &Path,
+        options: &FetchOptions,
+        registry_ref: &RegistryRef,
+        reporter: &dyn ProgressReporter,
+    ) -> Result<FetchResult> {
+        let _span = trace::span!("registry_fetch", url = source_url);
+        let repo_path = cache_directory.join(sanitize_repo_name(git_url));
+        let mut options = options.clone();
+        if registry_ref.needs_history() {
+            options.shallow = false;
+        }
+        let options = &options;
+
+        // Ensure cache directory exists
+        if !cache_directory.exists() {
+            std::fs::create_dir_all(cache_directory).context("Failed to create cache directory")?;
+        }
+
+        let repository;
+        let was_cloned;
+
+        // Check if the repository already exists
+        if repo_path.exists() {
+            // Repository exists, try to open and pull
+            repository =
+                Repository::open(&repo_path).context("Failed to open existing repository")?;
+            fetch_updates_from(&repository, source_url, options, reporter)
+                .with_context(|| format!("Failed to fetch updates from {}", source_url))?;
+            was_cloned = false;
+        } else {
+            // Clone the repository
+            repository = clone_repository(source_url, &repo_path, options, reporter)
+                .with_context(|| format!("Failed to clone repository from {}", source_url))?;
+            was_cloned = true;
+        }
+
+        checkout_ref(&repository, registry_ref, options)
+            .with_context(|| format!("Failed to checkout {}", registry_ref))?;
+
+        // Get the current commit hash
+        let head = repository.head().context("Failed to get HEAD reference")?;
+        let commit = head
+            .peel_to_commit()
+            .context("Failed to peel HEAD to commit")?;
+        let commit_hash = commit.id().to_string();
+
+        // Parse all tapplet configurations from the repository
+        let tapplets = parse_tapplets_from_repo(&repo_path)
+            .context("Failed to parse tapplet configurations")?;
+        let last_fetch = FetchState::record_fetch(&repo_path, source_url)?;
+        trace::info!(
+            "Fetched registry at {} with {} tapplets",
+            commit_hash,
+            tapplets.len()
+        );
+        reporter.report(FetchProgress::Done);
+
+        Ok(FetchResult {
+            repository_path: repo_path,
+            was_cloned,
+            commit_hash: Some(commit_hash),
+            tapplets,
+            last_fetch: Some(last_fetch),
+            source: Some(source_url.to_string()),
+        })
+    }
+
+    /// Tapplets matching `query`, most relevant first. Pass a `&str` to search
+    /// by text alone, or a [`SearchQuery`] to filter and paginate. Yanked
+    /// tapplets are left out unless the query includes them.
+    pub fn search(&self, query: impl Into<SearchQuery>) -> Result<Vec<&TappletManifest>> {
+        self.search_where(&query.into(), |_| true)
+    }
+
+    /// Tapplets matching `query` in the full-text index, best first, at most
+    /// `limit` of them. See [`FullTextIndex::search`] for the query syntax.
+    /// Yanked tapplets are never found.
+    ///
+    /// Fails unless the registry was loaded or fetched after
+    /// [`TappletRegistry::with_search_index`].
+    #[cfg(feature = "search-index")]
+    pub fn search_full_text(&self, query: &str, limit: usize) -> Result<Vec<&TappletManifest>> {
+        self.ensure_loaded()?;
+        let Some(index) = &self.search_index else {
+            anyhow::bail!(
+                "Registry {} has no full-text index, enable it with with_search_index()",
+                self.name
+            );
+        };
+        let ranks: HashMap<_, _> = index
+            .search(query, limit)?
+            .into_iter()
+            .enumerate()
+            .map(|(rank, key)| (key, rank))
+            .collect();
+        let mut found: Vec<_> = self
+            .tapplets
+            .iter()
+            .filter_map(|tapplet| Some((ranks.get(&tapplet.fully_qualified_name())?, tapplet)))
+            .collect();
+        found.sort_by_key(|(rank, _)| **rank);
+        Ok(found.into_iter().map(|(_, tapplet)| tapplet).collect())
+    }
+
+    /// Search, skipping tapplets `keep` rejects before paginating
+    fn search_where(
+        &self,
+        query: &SearchQuery,
+        keep: impl Fn(&TappletManifest) -> bool,
+    ) -> Result<Vec<&TappletManifest>> {
+        self.ensure_loaded()?;
+        let mut found: Vec<_> = self
+            .tapplets
+            .iter()
+            .filter(|tapplet| query.filters(tapplet) && keep(tapplet))
+            .filter(|tapplet| {
+                query
+                    .runtime
+                    .is_none_or(|kind| self.runtime_of(tapplet) == Some(kind))
+            })
+            .filter_map(|tapplet| Some((query.relevance(tapplet)?, tapplet)))
+            .collect();
+        found.sort_by(|(a_relevance, a), (b_relevance, b)| {
+            a_relevance
+                .cmp(b_relevance)
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| b.cmp_version(a))
+        });
+        Ok(found
+            .into_iter()
+            .map(|(_, tapplet)| tapplet)
+            .skip(query.offset)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    /// The runtime a tapplet declares, or else the one detected from its files
+    fn runtime_of(&self, tapplet: &TappletManifest) -> Option<RuntimeKind> {
+        match &tapplet.runtime {
+            Some(runtime) => Some(runtime.kind),
+            None => tapplet_dir_runtime(&self.tapplet_dir(tapplet)).ok(),
+        }
+    }
+
+    /// Look up a tapplet by exact name, returning its newest version that isn't a
+    /// prerelease.
+    ///
+    /// Returns `None` if the registry hasn't been loaded, if the tapplet only has
+    /// prereleases, or if `name` is a bare name several publishers' tapplets have.
+    pub fn get_by_name(&self, name: &str) -> Option<&TappletManifest> {
+        let name = self.qualify(name).ok()??;
+        self.tapplets
+            .iter()
+            .filter(|tapplet| tapplet.name_matches(&name) && !tapplet.is_prerelease())
+            .max_by(|a, b| a.cmp_version(b))
+    }
+
+    /// Look up a specific tapplet version by its canonical `name@version`, or its
+    /// fully qualified `publisher/name@version`. Like [`Self::get_by_name`], a
+    /// bare name several publishers' tapplets have matches nothing.
+    pub fn get_by_canonical_name(&self, canonical_name: &str) -> Option<&TappletManifest> {
+        let (name, version) = canonical_name.rsplit_once('@')?;
+        let name = self.qualify(name).ok()??;
+        self.tapplets
+            .iter()
+            .find(|tapplet| tapplet.name_matches(&name) && tapplet.version == version)
+    }
+
+    /// The publishers of tapplets called `name`, sorted
+    pub fn publishers_of(&self, name: &str) -> Vec<&str> {
+        let mut publishers: Vec<_> = self
+            .tapplets
+            .iter()
+            .filter(|tapplet| tapplet.name_matches(name))
+            .map(|tapplet| tapplet.publisher.as_str())
+            .collect();
+        publishers.sort();
+        publishers.dedup();
+        publishers
+    }
+
+    /// The qualified `publisher/name` of the tapplet `name` refers to: `name`
+    /// itself if it is qualified, otherwise the tapplet of the only publisher
+    /// with a tapplet of that name. `None` if there is no such tapplet.
+    ///
+    /// Fails with `AMBIGUOUS_NAME` if several publishers have a tapplet of that name.
+    pub fn qualify(&self, name: &str) -> Result<Option<String>> {
+        match self.publishers_of(name).as_slice() {
+            [] => Ok(None),
+            [publisher] => {
+                let name = name.split_once('/').map_or(name, |(_, name)| name);
+                Ok(Some(format!("{}/{}", publisher, name.replace('-', "_"))))
+            }
+            publishers => Err(TappletError::AmbiguousName {
+                name: name.to_string(),
+                publishers: publishers.iter().map(|p| p.to_string()).collect(),
+            }
+            .into()),
+        }
+    }
+
+    /// Whether any version of a tapplet with this exact name is in the registry
+    pub fn contains(&self, name: &str) -> bool {
+        self.tapplets
+            .iter()
+            .any(|tapplet| tapplet.name_matches(name))
+    }
+
+    /// All published versions of a tapplet, ordered from oldest to newest.
+    /// `name` is a qualified `publisher/name`, or a bare name only one
+    /// publisher's tapplets have, see [`Self::qualify`].
+    ///
+    /// Versions that are not valid semver sort before all valid ones.
+    pub fn versions_of(&self, name: &str) -> Result<Vec<&TappletManifest>> {
+        self.ensure_loaded()?;
+        let Some(name) = self.qualify(name)? else {
+            return Ok(Vec::new());
+        };
+        let mut versions: Vec<_> = self
+            .tapplets
+            .iter()
+            .filter(|tapplet| tapplet.name_matches(&name))
+            .collect();
+        versions.sort_by(|a, b| a.cmp_version(b));
+        Ok(versions)
+    }
+
+    /// The newest version of a tapplet that isn't a prerelease
+    pub fn latest(&self, name: &str) -> Result<Option<&TappletManifest>> {
+        Ok(self
+            .versions_of(name)?
+            .into_iter()
+            .rfind(|tapplet| !tapplet.is_prerelease()))
+    }
+
+    /// The newest version of a tapplet, prereleases included
+    pub fn latest_with_prereleases(&self, name: &str) -> Result<Option<&TappletManifest>> {
+        Ok(self.versions_of(name)?.pop())
+    }
+
+    /// The newest version of a tapplet matching a semver requirement such as `^1.2` or `>=0.3, <0.5`
+    pub fn get(&self, name: &str, version_req: &str) -> Result<Option<&TappletManifest>> {
+        let req = parse_version_req(version_req)?;
+        Ok(self
+            .versions_of(name)?
+            .into_iter()
+            .rev()
+            .find(|tapplet| tapplet.satisfies(&req)))
+    }
+
+    /// The newest version of a tapplet matching `version_req` and the versions of its
+    /// dependencies, transitively, in install order. See [`crate::resolver::resolve`].
+    pub fn resolve_dependencies(
+        &self,
+        name: &str,
+        version_req: &str,
+    ) -> Result<Vec<TappletManifest>> {
+        Ok(resolver::resolve(self, &[], name, version_req)?
+            .into_iter()
+            .map(|resolved| resolved.manifest)
+            .collect())
+    }
+
+    /// Search like [`TappletRegistry::search`], excluding tapplets the policy doesn't trust
+    pub fn search_trusted(
+        &self,
+        query: impl Into<SearchQuery>,
+        policy: &TrustPolicy,
+    ) -> Result<Vec<&TappletManifest>> {
+        self.search_where(&query.into(), |tapplet| policy.is_trusted(tapplet))
+    }
+
+    /// Every category tapplets are listed under, sorted, with how many tapplets
+    /// are listed under each. Counts the newest version of each tapplet that
+    /// isn't yanked, and ignores case, using the first spelling found.
+    pub fn categories(&self) -> Result<Vec<(&str, usize)>> {
+        let mut categories: Vec<(&str, usize)> = Vec::new();
+        for tapplet in self.listed()? {
+            for category in &tapplet.categories {
+                match categories
+                    .iter_mut()
+                    .find(|(listed, _)| listed.eq_ignore_ascii_case(category))
+                {
+                    Some((_, count)) => *count += 1,
+                    None => categories.push((category, 1)),
+                }
+            }
+        }
+        categories.sort_by_key(|(category, _)| category.to_lowercase());
+        Ok(categories)
+    }
+
+    /// The newest version of every tapplet listed under `category`, ignoring
+    /// case, sorted by name. Yanked versions are left out.
+    pub fn by_category(&self, category: &str) -> Result<Vec<&TappletManifest>> {
+        Ok(self
+            .listed()?
+            .into_iter()
+            .filter(|tapplet| tapplet.in_category(category))
+            .collect())
+    }
+
+    /// The newest version of every tapplet that isn't yanked, sorted by name.
+    /// Same-named tapplets of different publishers are listed separately.
+    fn listed(&self) -> Result<Vec<&TappletManifest>> {
+        self.ensure_loaded()?;
+        let mut newest: HashMap<String, &TappletManifest> = HashMap::new();
+        for tapplet in self.tapplets.iter().filter(|tapplet| !tapplet.yanked) {
+            newest
+                .entry(tapplet.qualified_name())
+                .and_modify(|listed| {
+                    if tapplet.cmp_version(listed).is_gt() {
+                        *listed = tapplet;
+                    }
+                })
+                .or_insert(tapplet);
+        }
+        let mut listed: Vec<_> = newest.into_values().collect();
+        listed.sort_by(|a, b| (&a.name, &a.publisher).cmp(&(&b.name, &b.publisher)));
+        Ok(listed)
+    }
+
+    /// Check every tapplet in the registry against a trust policy
+    pub fn trust_report(&self, policy: &TrustPolicy) -> Result<TrustReport> {
+        self.ensure_loaded()?;
+        Ok(policy.report(&self.tapplets))
+    }
+
+    /// Lint every manifest in the registry, reporting schema errors, bad public
+    /// keys, duplicate names, tapplets in the wrong directory and missing or
+    /// modified artifacts.
+    ///
+    /// Unlike loading, this looks at every manifest in the checkout,
+    /// including the ones loading skips, so registry maintainers can run it
+    /// before merging changes.
+    pub fn validate(&self) -> Result<RegistryReport> {
+        self.validate_with_policy(&TrustPolicy::default())
+    }
+
+    /// Like [`Self::validate`], also reporting manifests whose signature or
+    /// publisher the trust policy rejects
+    pub fn validate_with_policy(&self, policy: &TrustPolicy) -> Result<RegistryReport> {
+        self.ensure_loaded()?;
+        let report = match self.source {
+            RegistrySource::Git => RegistryReport::for_checkout(
+                &self.cache_directory.join(sanitize_repo_name(&self.git_url)),
+                policy,
+            ),
+            RegistrySource::Local => RegistryReport::for_checkout(&self.cache_directory, policy),
+            #[cfg(feature = "http-registry")]
+            RegistrySource::Http => {
+                RegistryReport::for_loaded(self.tapplets_and_dirs()?, &self.conflicts, policy)
+            }
+            RegistrySource::Memory => {
+                RegistryReport::for_loaded(self.tapplets_and_dirs()?, &self.conflicts, policy)
+            }
+        };
+        Ok(report)
+    }
+
+    pub fn tapplets_and_dirs(&self) -> Result<Vec<(&TappletManifest, PathBuf)>> {
+        self.ensure_loaded()?;
+        let mut results = Vec::new();
+        for tapplet in &self.tapplets {
+            results.push((tapplet, self.tapplet_dir(tapplet)));
+        }
+        Ok(results)
+    }
+
+    pub fn find_tapplet_and_dir_by_pub_key(
+        &self,
+        public_key: &str,
+    ) -> Result<Option<(&TappletManifest, PathBuf)>> {
+        self.ensure_loaded()?;
+        Ok(self
+            .tapplets
+            .iter()
+            .find(|tapplet| tapplet.public_key == public_key)
+            .map(|tapplet| {
+                let dir = self
+                    .tapplet_dirs
+                    .get(&tapplet.fully_qualified_name())
+                    .cloned()
+                    .unwrap_or_else(|| {
+                        self.cache_directory
+                            .join(sanitize_repo_name(&self.git_url))
+                            .join("tapplets")
+                            .join(&tapplet.public_key)
+                    });
+                (tapplet, dir)
+            }))
+    }
+}
+
+struct FetchResult {
+    #[allow(dead_code)]
+    repository_path: PathBuf,
+    #[allow(dead_code)]
+    was_cloned: bool,
+    /// `None` for registries read from a plain directory
+    commit_hash: Option<String>,
+    tapplets: Vec<(TappletManifest, PathBuf)>,
+    last_fetch: Option<SystemTime>,
+    source: Option<String>,
+}
+
+/// Clone a repository from a URL to a local path.
+///
+/// A shallow clone falls back to a full clone if the transport doesn't support it.
+pub(crate) fn clone_repository(
+    url: &str,
+    path: &Path,
+    options: &FetchOptions,
+    reporter: &dyn ProgressReporter,
+) -> Result<Repository> {
+    if options.shallow {
+        match clone_repository_with_depth(url, path, options, reporter, 1) {
+            Ok(repo) => return Ok(repo),
+            Err(e) => {
+                trace::warning!(
+                    "Shallow clone of {} failed ({}), falling back to a full clone",
+                    url,
+                    e
+                );
+                if path.exists() {
+                    std::fs::remove_dir_all(path)
+                        .context("Failed to clean up partial shallow clone")?;
+                }
+            }
+        }
+    }
+    clone_repository_with_depth(url, path, options, reporter, 0)
+}
+
+fn clone_repository_with_depth(
+    url: &str,
+    path: &Path,
+    options: &FetchOptions,
+    reporter: &dyn ProgressReporter,
+    depth: i32,
+) -> Result<Repository> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.transfer_progress(|stats| {
+        if let Some(progress) = FetchProgress::from_git(&stats) {
+            reporter.report(progress);
+        }
+        true
+    });
+
+    let mut fetch_options = Git2FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks);
+    fetch_options.depth(depth);
+
+    let mut builder = RepoBuilder::new();
+    builder.fetch_options(fetch_options);
+    builder.with_checkout(checkout_builder(options));
+
+    Ok(builder.clone(url, path)?)
+}
+
+/// Fetch updates from the remote repository
+pub(crate) fn fetch_updates(
+    repo: &Repository,
+    options: &FetchOptions,
+    reporter: &dyn ProgressReporter,
+) -> Result<()> {
+    let mut remote = repo
+        .find_remote("origin")
+        .or_else(|_| repo.remote_anonymous("origin"))?;
+    fetch_and_fast_forward(repo, &mut remote, options, reporter)
+}
+
+/// Fetch updates like [`fetch_updates`], from `url` instead of the `origin` remote
+fn fetch_updates_from(
+    repo: &Repository,
+    url: &str,
+    options: &FetchOptions,
+    reporter: &dyn ProgressReporter,
+) -> Result<()> {
+    let mut remote = repo.remote_anonymous(url)?;
+    fetch_and_fast_forward(repo, &mut remote, options, reporter)
+}
+
+fn fetch_and_fast_forward(
+    repo: &Repository,
+    remote: &mut git2::Remote<'_>,
+    options: &FetchOptions,
+    reporter: &dyn ProgressReporter,
+) -> Result<()> {
+    if options.shallow {
+        if let Err(e) = fetch_remote(remote, reporter, 1) {
+            trace::warning!("Shallow fetch failed ({}), falling back to a full fetch", e);
+            fetch_remote(remote, reporter, 0)?;
+        }
+    } else {
+        fetch_remote(remote, reporter, 0)?;
+    }
+    // Merge or fast-forward if possible
+    let fetch_head = repo.find_reference("FETCH_HEAD")?;
+    let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
+    let analysis = repo.merge_analysis(&[&fetch_commit])?;
+
+    if analysis.0.is_up_to_date() {
+        Ok(())
+    } else if analysis.0.is_fast_forward() {
+        let mut reference = repo
+            .find_reference("refs/heads/main")
+            .or_else(|_| repo.find_reference("refs/heads/master"))?;
+        let reference = reference.set_target(fetch_commit.id(), "Fast-Forward")?;
+        repo.set_head(reference.name().context("Branch name is not valid UTF-8")?)?;
+        repo.checkout_head(Some(checkout_builder(options).force()))?;
+        Ok(())
+    } else {
+        // Could not fast-forward, might need manual merge
+        Ok(())
+    }
+}
+
+/// Fetch all branches from a remote, limited to `depth` commits when non-zero
+fn fetch_remote(
+    remote: &mut git2::Remote<'_>,
+    reporter: &dyn ProgressReporter,
+    depth: i32,
+) -> Result<()> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.transfer_progress(|stats| {
+        if let Some(progress) = FetchProgress::from_git(&stats) {
+            reporter.report(progress);
+        }
+        true
+    });
+
+    let mut fetch_options = Git2FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks);
+    fetch_options.download_tags(AutotagOption::All);
+    fetch_options.depth(depth);
+
+    remote.fetch(
+        &["refs/heads/*:refs/remotes/origin/*"],
+        Some(&mut fetch_options),
+        None,
+    )?;
+    Ok(())
+}
+
+/// Checkout the default branch, or the pinned branch, tag or commit with a detached HEAD
+fn checkout_ref(
+    repo: &Repository,
+    registry_ref: &RegistryRef,
+    options: &FetchOptions,
+) -> Result<()> {
+    let spec = match registry_ref {
+        RegistryRef::DefaultBranch => return checkout_default_branch(repo, options),
+        // The remote branch, so an updated checkout sees new commits
+        RegistryRef::Branch(branch) => format!("refs/remotes/origin/{}", branch),
+        RegistryRef::Tag(tag) => format!("refs/tags/{}", tag),
+        RegistryRef::Rev(rev) => rev.clone(),
+    };
+    let commit = repo
+        .revparse_single(&spec)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| TappletError::RegistryRefNotFound(registry_ref.to_string()))?;
+    repo.checkout_tree(commit.as_object(), Some(checkout_builder(options).force()))?;
+    repo.set_head_detached(commit.id())?;
+    Ok(())
+}
+
+/// Checkout the default branch (main or master)
+fn checkout_default_branch(repo: &Repository, options: &FetchOptions) -> Result<()> {
+    // Try main first, then master
+    let branch_name = if repo.find_reference("refs/heads/main").is_ok() {
+        "refs/heads/main"
+    } else {
+        "refs/heads/master"
+    };
+
+    let obj = repo.revparse_single(branch_name)?;
+    repo.checkout_tree(&obj, Some(&mut checkout_builder(options)))?;
+    repo.set_head(branch_name)?;
+
+    Ok(())
+}
+
+/// Checkout settings for a registry, limited to the tapplets and index for sparse checkouts
+fn checkout_builder(options: &FetchOptions) -> CheckoutBuilder<'static> {
+    let mut builder = CheckoutBuilder::new();
+    if options.sparse {
+        builder
+            .path("tapplets/*")
+            .path(index::INDEX_TOML_FILE_NAME)
+            .path(index::INDEX_JSON_FILE_NAME);
+    }
+    builder
+}
+
+/// Parse all tapplet configurations from a repository, along with their directories.
+///
+/// Uses the registry index if the repository has one, otherwise walks `tapplets/`.
+fn parse_tapplets_from_repo(repo_path: &Path) -> Result<Vec<(TappletManifest, PathBuf)>> {
+    match RegistryIndex::from_repo(repo_path)? {
+        Some(index) => Ok(index.load_tapplets(repo_path)),
+        None => walk_tapplets(repo_path),
+    }
+}
+
+/// Walk the `tapplets/` directory of a repository and parse every manifest, in
+/// any of the [`crate::model::MANIFEST_FILE_NAMES`] formats
+fn walk_tapplets(repo_path: &Path) -> Result<Vec<(TappletManifest, PathBuf)>> {
+    let mut tapplets = Vec::new();
+
+    // Walk through the repository looking for .toml files
+    for entry in walkdir::WalkDir::new(repo_path.join("tapplets"))
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+
+        // Skip .git directory
+        if path.components().any(|c| c.as_os_str() == ".git") {
+            continue;
+        }
+
+        if is_tapplet_manifest(path) {
+            match TappletManifest::from_file(path) {
+                Ok(config) => {
+                    let dir = path.parent().unwrap_or(repo_path).to_path_buf();
+                    // Lua scripts that don't compile or escape the default sandbox
+                    // never make it into the registry
+                    #[cfg(feature = "host-core")]
+                    if let Err(e) = crate::local_folder_lua_tapplet::check_scripts(
+                        &dir,
+                        &crate::sandbox::SandboxOptions::default(),
+                    ) {
+                        trace::warning!("Skipping {}: {:#}", dir.display(), e);
+                        continue;
+                    }
+                    tapplets.push((config, dir));
+                }
+                Err(e) => {
+                    trace::warning!("Failed to parse {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    Ok(tapplets)
+}
+
+/// Whether `path` is the manifest of its directory: the file [`find_manifest_file`]
+/// picks, so a directory with both `manifest.toml` and `manifest.json` is read once
+pub(super) fn is_tapplet_manifest(path: &Path) -> bool {
+    path.parent().and_then(find_manifest_file).as_deref() == Some(path)
+}
+
+/// Sanitize a repository URL to create a safe directory name
+pub(crate) fn sanitize_repo_name(url: &str) -> String {
+    // Remove protocol prefix
+    let without_protocol = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .or_else(|| url.strip_prefix("git://"))
+        .or_else(|| url.strip_prefix("ssh://"))
+        .unwrap_or(url);
+
+    // Remove .git suffix
+    let without_suffix = without_protocol
+        .strip_suffix(".git")
+        .unwrap_or(without_protocol);
+
+    // Replace invalid characters with underscores
+    without_suffix
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{RuntimeConfig, TappletDeprecation};
+    use crate::test_utils;
+
+    fn loaded_registry(tapplets: &[(&str, &str)]) -> TappletRegistry {
+        let tapplets: Vec<_> = tapplets
+            .iter()
+            .map(|(name, version)| (*name, *version, &[][..]))
+            .collect();
+        registry_with_dependencies(&tapplets)
+    }
+
+    /// Name, version and `(dependency, requirement)` pairs of each tapplet
+    type TestTapplet<'a> = (&'a str, &'a str, &'a [(&'a str, &'a str)]);
+
+    fn registry_with_dependencies(tapplets: &[TestTapplet]) -> TappletRegistry {
+        let mut registry =
+            TappletRegistry::new("test", "https://example.com/registry", PathBuf::new());
+        registry.set_tapplets(
+            tapplets
+                .iter()
+                .map(|(name, version, dependencies)| {
+                    let mut manifest =
+                        TappletManifest::from_toml_str(&test_utils::manifest_toml(name, version))
+                            .unwrap();
+                    for (dependency, req) in *dependencies {
+                        manifest
+                            .dependencies
+                            .insert(dependency.to_string(), req.to_string());
+                    }
+                    (
+                        manifest,
+                        PathBuf::from(format!("tapplets/{}/{}", name, version)),
+                    )
+                })
+                .collect(),
+        );
+        registry.is_loaded = true;
+        registry
+    }
+
+    #[test]
+    fn test_versions_of_latest_and_get() {
+        let registry = loaded_registry(&[
+            ("wallet", "1.10.0"),
+            ("wallet", "1.2.0"),
+            ("wallet", "2.0.0-beta.1"),
+            ("other", "0.1.0"),
+        ]);
+
+        let versions: Vec<_> = registry
+            .versions_of("wallet")
+            .unwrap()
+            .iter()
+            .map(|t| t.version.as_str())
+            .collect();
+        assert_eq!(versions, ["1.2.0", "1.10.0", "2.0.0-beta.1"]);
+
+        assert_eq!(
+            registry.latest("wallet").unwrap().unwrap().version,
+            "1.10.0"
+        );
+        assert_eq!(registry.get_by_name("wallet").unwrap().version, "1.10.0");
+        assert_eq!(
+            registry
+                .latest_with_prereleases("wallet")
+                .unwrap()
+                .unwrap()
+                .version,
+            "2.0.0-beta.1"
+        );
+        assert_eq!(
+            registry.get("wallet", "^1").unwrap().unwrap().version,
+            "1.10.0"
+        );
+        assert_eq!(
+            registry.get("wallet", "<1.5").unwrap().unwrap().version,
+            "1.2.0"
+        );
+        assert!(registry.get("wallet", "^3").unwrap().is_none());
+        assert!(registry.get("wallet", "not a req").is_err());
+        assert!(registry.latest("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_resolve_dependencies() {
+        let registry = registry_with_dependencies(&[
+            ("app", "1.0.0", &[("wallet", "^1"), ("contacts", ">=0.2")]),
+            ("contacts", "0.3.0", &[("wallet", ">=1.1")]),
+            ("wallet", "1.0.0", &[]),
+            ("wallet", "1.4.0", &[]),
+            ("wallet", "2.0.0", &[]),
+            ("cyclic-a", "1.0.0", &[("cyclic_b", "*")]),
+            ("cyclic_b", "1.0.0", &[("cyclic-a", "*")]),
+            ("picky", "1.0.0", &[("wallet", "^2"), ("app", "*")]),
+            ("broken", "1.0.0", &[("missing", "*")]),
+        ]);
+
+        let order: Vec<_> = registry
+            .resolve_dependencies("app", "*")
+            .unwrap()
+            .iter()
+            .map(|tapplet| tapplet.canonical_name())
+            .collect();
+        assert_eq!(order, ["wallet@1.4.0", "contacts@0.3.0", "app@1.0.0"]);
+
+        let err = registry.resolve_dependencies("cyclic-a", "*").unwrap_err();
+        assert_eq!(crate::error_code(&err), "DEPENDENCY_CYCLE");
+        assert_eq!(
+            err.to_string(),
+            "Dependency cycle: test_publisher/cyclic_a -> test_publisher/cyclic_b -> \
+             test_publisher/cyclic_a"
+        );
+
+        let err = registry.resolve_dependencies("picky", "*").unwrap_err();
+        assert_eq!(crate::error_code(&err), "DEPENDENCY_CONFLICT");
+        assert_eq!(
+            err.to_string(),
+            "No version of 'wallet' satisfies all requirements: \
+             contacts requires >=1.1, app requires ^1, picky requires ^2"
+        );
+
+        let err = registry.resolve_dependencies("broken", "*").unwrap_err();
+        assert_eq!(crate::error_code(&err), "TAPPLET_NOT_FOUND");
+        let err = registry.resolve_dependencies("app", "^2").unwrap_err();
+        assert_eq!(crate::error_code(&err), "DEPENDENCY_CONFLICT");
+    }
+
+    #[test]
+    fn test_exact_lookups() {
+        let registry = loaded_registry(&[
+            ("password-manager", "0.1.0"),
+            ("password-manager", "0.2.0"),
+            ("password-manager-pro", "1.0.0"),
+        ]);
+
+        assert_eq!(
+            registry.get_by_name("password_manager").unwrap().version,
+            "0.2.0"
+        );
+        assert!(registry.get_by_name("password").is_none());
+        assert_eq!(
+            registry
+                .get_by_canonical_name("password_manager@0.1.0")
+                .unwrap()
+                .version,
+            "0.1.0"
+        );
+        assert!(
+            registry
+                .get_by_canonical_name("password_manager@9.9.9")
+                .is_none()
+        );
+        assert!(registry.get_by_canonical_name("password_manager").is_none());
+        assert!(
+            registry
+                .get_by_canonical_name("test_publisher/password_manager@0.1.0")
+                .is_some()
+        );
+        assert!(
+            registry
+                .get_by_canonical_name("other_publisher/password_manager@0.1.0")
+                .is_none()
+        );
+        assert!(registry.contains("password-manager-pro"));
+        assert!(!registry.contains("manager"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shallow_sparse_fetch() {
+        let temp = tempfile::tempdir().unwrap();
+        let remote = temp.path().join("remote");
+        test_utils::init_registry_repo(&remote, &[("wallet", "0.1.0")]);
+        std::fs::write(remote.join("README.md"), "registry docs").unwrap();
+        test_utils::commit_all(&remote);
+
+        let cache = temp.path().join("cache");
+        let mut registry = TappletRegistry::new("test", remote.to_str().unwrap(), cache.clone())
+            .with_fetch_options(FetchOptions::minimal());
+        registry.fetch().await.unwrap();
+        assert_eq!(registry.tapplets.len(), 1);
+
+        let checkout = cache.join(sanitize_repo_name(remote.to_str().unwrap()));
+        assert!(checkout.join("tapplets/wallet/manifest.toml").exists());
+        assert!(!checkout.join("README.md").exists());
+
+        // Updating an existing sparse checkout keeps it sparse
+        registry.fetch().await.unwrap();
+        assert!(!checkout.join("README.md").exists());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fetch_reports_progress() {
+        let temp = tempfile::tempdir().unwrap();
+        let remote = temp.path().join("remote");
+        test_utils::init_registry_repo(&remote, &[("wallet", "0.1.0")]);
+
+        // A file:// url goes through the transport so transfer progress is reported
+        let url = format!("file://{}", remote.display());
+        let mut registry = TappletRegistry::new("test", url.as_str(), temp.path().join("cache"));
+        let updates = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = updates.clone();
+        registry
+            .fetch_with_progress(Arc::new(move |progress| {
+                recorded.lock().unwrap().push(progress)
+            }))
+            .await
+            .unwrap();
+
+        let updates = updates.lock().unwrap().clone();
+        assert!(updates.len() > 1);
+        assert!(
+            updates
+                .iter()
+                .any(|p| matches!(p, FetchProgress::Receiving { .. }))
+        );
+        assert_eq!(updates.last(), Some(&FetchProgress::Done));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fetch_policies() {
+        let temp = tempfile::tempdir().unwrap();
+        let remote = temp.path().join("remote");
+        test_utils::init_registry_repo(&remote, &[("wallet", "0.1.0")]);
+        let cache = temp.path().join("cache");
+        let registry = |policy| {
+            TappletRegistry::new("test", remote.to_str().unwrap(), cache.clone())
+                .with_fetch_policy(policy)
+        };
+
+        // Nothing cached yet
+        assert!(registry(FetchPolicy::OfflineOnly).refresh().await.is_err());
+
+        let mut online = registry(FetchPolicy::AlwaysFetch);
+        online.refresh().await.unwrap();
+        assert!(online.last_fetch().is_some());
+
+        // The fetch time survives a restart
+        let mut offline = registry(FetchPolicy::OfflineOnly);
+        offline.refresh().await.unwrap();
+        assert_eq!(offline.tapplets.len(), 1);
+        assert_eq!(offline.last_fetch(), online.last_fetch());
+
+        // With the remote gone, a stale cache is still served
+        std::fs::remove_dir_all(&remote).unwrap();
+        let mut stale = registry(FetchPolicy::PreferCache {
+            max_age: std::time::Duration::ZERO,
+        });
+        stale.refresh().await.unwrap();
+        assert_eq!(stale.tapplets.len(), 1);
+        assert!(registry(FetchPolicy::AlwaysFetch).refresh().await.is_err());
+    }
+
+    #[test]
+    fn test_json_and_yaml_manifests_are_ingested() {
+        let temp = tempfile::tempdir().unwrap();
+        let repo = temp.path();
+        let wallet = repo.join("tapplets/wallet");
+        test_utils::write_lua_tapplet(&wallet, "wallet", "0.1.0");
+        let notes = repo.join("tapplets/notes");
+        test_utils::write_lua_tapplet(&notes, "notes", "0.1.0");
+        let manifest = TappletManifest::from_file(notes.join("manifest.toml")).unwrap();
+        std::fs::remove_file(notes.join("manifest.toml")).unwrap();
+        std::fs::write(
+            notes.join("manifest.json"),
+            serde_json::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+        // manifest.toml is preferred, so the wallet is only read once
+        let manifest = TappletManifest::from_file(wallet.join("manifest.toml")).unwrap();
+        std::fs::write(
+            wallet.join("manifest.yaml"),
+            serde_yaml::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+
+        let walked: Vec<_> = walk_tapplets(repo)
+            .unwrap()
+            .into_iter()
+            .map(|(manifest, dir)| (manifest.name, dir))
+            .collect();
+        assert_eq!(
+            walked,
+            [("notes".to_string(), notes), ("wallet".to_string(), wallet)]
+        );
+
+        let index = RegistryIndex::build(repo).unwrap();
+        assert_eq!(index.load_tapplets(repo).len(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_local_and_in_memory_registries() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("local");
+        test_utils::write_lua_tapplet(&dir.join("tapplets/wallet"), "wallet", "0.1.0");
+        std::fs::create_dir_all(dir.join("tapplets/wallet/target")).unwrap();
+
+        let mut local = TappletRegistry::from_local_path(&dir);
+        assert_eq!(local.name, "local");
+        local.load().await.unwrap();
+        assert_eq!(local.revision(), None);
+        let wallet = local.get_by_name("wallet").unwrap();
+        assert_eq!(local.tapplet_dir(wallet), dir.join("tapplets/wallet"));
+        // There is no cache to clean up, so nothing in the directory is touched
+        let report = local
+            .gc(&GcPolicy::default().with_build_artifacts_removed())
+            .unwrap();
+        assert!(report.removed.is_empty());
+        assert!(dir.join("tapplets/wallet/target").exists());
+        test_utils::write_lua_tapplet(&dir.join("tapplets/notes"), "notes", "0.1.0");
+        local.fetch().await.unwrap();
+        assert_eq!(local.tapplets.len(), 2);
+
+        let mut memory = InMemoryRegistry::new("memory");
+        let manifest = |name, version| {
+            TappletManifest::from_toml_str(&test_utils::manifest_toml(name, version)).unwrap()
+        };
+        let mut wallet = manifest("wallet", "0.2.0");
+        wallet
+            .dependencies
+            .insert("notes".to_string(), "^0.1".to_string());
+        memory
+            .push(wallet)
+            .push(manifest("notes", "0.1.0"))
+            .push_with_dir(manifest("notes", "0.1.1"), dir.join("tapplets/notes"));
+        let mut registry = TappletRegistry::from(memory);
+        assert!(registry.is_loaded());
+        registry.refresh().await.unwrap();
+        let resolved: Vec<_> = registry
+            .resolve_dependencies("wallet", "*")
+            .unwrap()
+            .into_iter()
+            .map(|tapplet| tapplet.canonical_name())
+            .collect();
+        assert_eq!(resolved, ["notes@0.1.1", "wallet@0.2.0"]);
+        let notes = registry.latest("notes").unwrap().unwrap();
+        assert_eq!(registry.tapplet_dir(notes), dir.join("tapplets/notes"));
+    }
+
+    #[test]
+    fn test_search_query() {
+        let manifest = |name: &str| {
+            TappletManifest::from_toml_str(&test_utils::manifest_toml(name, "0.1.0")).unwrap()
+        };
+        let mut notes = manifest("notes");
+        notes.description = Some("Notes that sync with your wallet".to_string());
+        let mut chat = manifest("chat");
+        chat.friendly_name = "Wallet chat".to_string();
+        chat.runtime = Some(RuntimeConfig {
+            kind: RuntimeKind::Wasm,
+            entrypoint: None,
+        });
+        let mut ledger = manifest("ledger");
+        ledger.publisher = "wallet_labs".to_string();
+        ledger.license = Some("GPL-3.0-only".to_string());
+        let mut memory = InMemoryRegistry::new("memory");
+        memory
+            .push(ledger)
+            .push(notes)
+            .push(manifest("wallet_backup"))
+            .push(chat)
+            .push(manifest("wallet"))
+            .push(manifest("games"));
+        let registry = TappletRegistry::from(memory);
+        let names = |query: SearchQuery| -> Vec<String> {
+            registry
+                .search(query)
+                .unwrap()
+                .into_iter()
+                .map(|tapplet| tapplet.name.clone())
+                .collect()
+        };
+
+        assert_eq!(
+            names("WALLET".into()),
+            ["wallet", "wallet_backup", "chat", "notes", "ledger"]
+        );
+        assert_eq!(
+            names(SearchQuery::new("wallet").with_offset(1).with_limit(2)),
+            ["wallet_backup", "chat"]
+        );
+        assert_eq!(
+            names(SearchQuery::new("").with_publisher("wallet_labs")),
+            ["ledger"]
+        );
+        assert_eq!(
+            names(SearchQuery::default().with_runtime(RuntimeKind::Wasm)),
+            ["chat"]
+        );
+        assert_eq!(
+            names(SearchQuery::default().with_license("gpl-3.0-only")),
+            ["ledger"]
+        );
+    }
+
+    #[cfg(feature = "search-index")]
+    #[tokio::test]
+    async fn test_search_full_text() {
+        let mut memory = InMemoryRegistry::new("memory");
+        for name in ["wallet", "wallet_backup", "games"] {
+            memory.push(
+                TappletManifest::from_toml_str(&test_utils::manifest_toml(name, "0.1.0")).unwrap(),
+            );
+        }
+        let mut registry = TappletRegistry::from(memory).with_search_index();
+        assert!(registry.search_full_text("wallet", 10).is_err());
+
+        registry.fetch().await.unwrap();
+        assert_eq!(registry.search_index().unwrap().len(), 3);
+        let names: Vec<_> = registry
+            .search_full_text("walet backup", 10)
+            .unwrap()
+            .into_iter()
+            .map(|tapplet| tapplet.name.as_str())
+            .collect();
+        assert_eq!(names, ["wallet_backup"]);
+    }
+
+    #[test]
+    fn test_browse_categories() {
+        let manifest = |name: &str, version: &str, categories: &[&str]| {
+            let mut manifest =
+                TappletManifest::from_toml_str(&test_utils::manifest_toml(name, version)).unwrap();
+            manifest.categories = categories.iter().map(|c| c.to_string()).collect();
+            manifest
+        };
+        let mut yanked = manifest("chat", "0.3.0", &["Games"]);
+        yanked.yanked = true;
+        let mut tagged = manifest("notes", "0.1.0", &["Productivity"]);
+        tagged.tags = vec!["sync".to_string()];
+        let mut memory = InMemoryRegistry::new("memory");
+        memory
+            .push(manifest("wallet", "0.1.0", &["Games"]))
+            .push(manifest("wallet", "0.2.0", &["Finance"]))
+            .push(manifest("chat", "0.2.0", &["Social", "productivity"]))
+            .push(yanked)
+            .push(tagged);
+        let registry = TappletRegistry::from(memory);
+
+        assert_eq!(
+            registry.categories().unwrap(),
+            [("Finance", 1), ("productivity", 2), ("Social", 1)]
+        );
+        let names: Vec<_> = registry
+            .by_category("PRODUCTIVITY")
+            .unwrap()
+            .into_iter()
+            .map(|tapplet| tapplet.canonical_name())
+            .collect();
+        assert_eq!(names, ["chat@0.2.0", "notes@0.1.0"]);
+        assert!(registry.by_category("Games").unwrap().is_empty());
+
+        let found = registry
+            .search(
+                SearchQuery::default()
+                    .with_category("social")
+                    .with_tag("SYNC"),
+            )
+            .unwrap();
+        assert!(found.is_empty());
+        let found = registry
+            .search(SearchQuery::default().with_tag("SYNC"))
+            .unwrap();
+        assert_eq!(found[0].name, "notes");
+    }
+
+    #[test]
+    fn test_same_name_of_different_publishers() {
+        let manifest = |publisher: &str, name: &str, version: &str| {
+            let toml =
+                test_utils::manifest_toml(name, version).replace("test_publisher", publisher);
+            let mut manifest = TappletManifest::from_toml_str(&toml).unwrap();
+            manifest.categories = vec!["Finance".to_string()];
+            manifest
+        };
+        let mut app = manifest("carol", "app", "1.0.0");
+        app.dependencies
+            .insert("bob/wallet".to_string(), "*".to_string());
+        let mut ambiguous = manifest("carol", "ambiguous", "1.0.0");
+        ambiguous
+            .dependencies
+            .insert("wallet".to_string(), "*".to_string());
+        let mut memory = InMemoryRegistry::new("memory");
+        memory
+            .push(manifest("alice", "wallet", "0.2.0"))
+            .push(manifest("bob", "wallet", "0.1.0"))
+            .push(app)
+            .push(ambiguous);
+        let registry = TappletRegistry::from(memory);
+
+        assert!(registry.conflicts().is_empty());
+        let err = registry.versions_of("wallet").unwrap_err();
+        assert_eq!(crate::error_code(&err), "AMBIGUOUS_NAME");
+        assert!(registry.get_by_name("wallet").is_none());
+        assert!(registry.get_by_canonical_name("wallet@0.1.0").is_none());
+        assert_eq!(
+            registry.latest("bob/wallet").unwrap().unwrap().publisher,
+            "bob"
+        );
+        assert_eq!(registry.get_by_name("app").unwrap().publisher, "carol");
+
+        let order: Vec<_> = registry
+            .resolve_dependencies("app", "*")
+            .unwrap()
+            .iter()
+            .map(|tapplet| tapplet.fully_qualified_name())
+            .collect();
+        assert_eq!(order, ["bob/wallet@0.1.0", "carol/app@1.0.0"]);
+        let err = registry.resolve_dependencies("ambiguous", "*").unwrap_err();
+        assert_eq!(crate::error_code(&err), "AMBIGUOUS_NAME");
+
+        let listed: Vec<_> = registry
+            .by_category("finance")
+            .unwrap()
+            .into_iter()
+            .map(|tapplet| tapplet.qualified_name())
+            .collect();
+        assert_eq!(
+            listed,
+            ["carol/ambiguous", "carol/app", "alice/wallet", "bob/wallet"]
+        );
+        let report = registry.validate().unwrap();
+        assert_eq!(report.of_kind(FindingKind::DuplicateName).count(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_yanked_and_deprecated_tapplets() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("registry");
+        for (name, version) in [("wallet", "0.1.0"), ("wallet", "0.2.0"), ("notes", "0.1.0")] {
+            test_utils::write_lua_tapplet(
+                &dir.join("tapplets").join(name).join(version),
+                name,
+                version,
+            );
+        }
+        let mut index = RegistryIndex::build(&dir).unwrap();
+        index.tapplets[2].yanked = true;
+        index.tapplets[0].deprecated = Some(TappletDeprecation {
+            since: Some("0.1.0".to_string()),
+            reason: None,
+            replacement: Some("wallet".to_string()),
+        });
+        std::fs::write(dir.join("index.toml"), index.to_toml_string().unwrap()).unwrap();
+
+        let mut registry = TappletRegistry::from_local_path(&dir);
+        registry.load().await.unwrap();
+        let found: Vec<_> = registry
+            .search("")
+            .unwrap()
+            .into_iter()
+            .map(|tapplet| tapplet.canonical_name())
+            .collect();
+        assert_eq!(found, ["notes@0.1.0", "wallet@0.1.0"]);
+        let all = registry
+            .search(SearchQuery::default().with_yanked_included())
+            .unwrap();
+        assert_eq!(all.len(), 3);
+        // Yanked versions can still be looked up, but aren't resolved
+        assert!(
+            registry
+                .get_by_canonical_name("wallet@0.2.0")
+                .unwrap()
+                .yanked
+        );
+        let resolved = registry.resolve_dependencies("wallet", "*").unwrap();
+        assert_eq!(resolved[0].version, "0.1.0");
+        let notes = registry.get_by_name("notes").unwrap();
+        assert_eq!(
+            notes.deprecated.as_ref().unwrap().to_string(),
+            "deprecated since 0.1.0, use wallet instead"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_validate_registry() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("registry");
+        let key = "ab".repeat(32);
+        let write = |path: &str, name: &str, extra: &str| {
+            test_utils::write_lua_tapplet(&dir.join(path), name, "0.1.0");
+            let manifest =
+                test_utils::manifest_toml(name, "0.1.0").replace("test_public_key", &key) + extra;
+            std::fs::write(dir.join(path).join("manifest.toml"), manifest).unwrap();
+        };
+        write("tapplets/wallet", "wallet", "");
+        write("tapplets/notes/0.1.0", "notes", "");
+        write("tapplets/wallet-copy", "wallet", "");
+        write(
+            "tapplets/chat",
+            "chat",
+            &format!("\n[artifacts]\n\"missing.lua\" = \"{}\"\n", "00".repeat(32)),
+        );
+        test_utils::write_lua_tapplet(&dir.join("tapplets/keyless"), "keyless", "0.1.0");
+        std::fs::create_dir_all(dir.join("tapplets/broken")).unwrap();
+        std::fs::write(dir.join("tapplets/broken/manifest.toml"), "name = ").unwrap();
+
+        let mut registry = TappletRegistry::from_local_path(&dir);
+        assert!(registry.validate().is_err());
+        registry.load().await.unwrap();
+        let report = registry.validate().unwrap();
+        assert_eq!(report.checked, 6);
+        let kinds: Vec<_> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.kind, finding.path.to_str().unwrap()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (
+                    FindingKind::InvalidManifest,
+                    "tapplets/broken/manifest.toml"
+                ),
+                (FindingKind::MissingArtifact, "tapplets/chat/manifest.toml"),
+                (FindingKind::BadPublicKey, "tapplets/keyless/manifest.toml"),
+                (
+                    FindingKind::NameMismatch,
+                    "tapplets/wallet-copy/manifest.toml"
+                ),
+                (
+                    FindingKind::DuplicateName,
+                    "tapplets/wallet-copy/manifest.toml"
+                ),
+            ]
+        );
+        let bad_key = report.of_kind(FindingKind::BadPublicKey).next().unwrap();
+        assert_eq!(bad_key.field.as_deref(), Some("public_key"));
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["findings"][0]["kind"], "invalid_manifest");
+
+        // Every manifest that parses is also checked against the trust policy
+        let report = registry
+            .validate_with_policy(&TrustPolicy::new().allow_publisher("someone_else"))
+            .unwrap();
+        assert_eq!(report.of_kind(FindingKind::Untrusted).count(), 5);
+
+        // Loading drops the copy of wallet, or fails under the error policy
+        assert_eq!(registry.conflicts().len(), 1);
+        assert_eq!(registry.versions_of("wallet").unwrap().len(), 1);
+        let mut strict =
+            TappletRegistry::from_local_path(&dir).with_duplicate_policy(DuplicatePolicy::Error);
+        let err = strict.load().await.unwrap_err();
+        assert_eq!(crate::error_code(&err), "DUPLICATE_TAPPLET");
+        assert!(!strict.is_loaded());
+        assert_eq!(strict.conflicts()[0].copies.len(), 2);
+
+        let mut memory = InMemoryRegistry::new("memory");
+        let manifest =
+            TappletManifest::from_toml_str(&test_utils::manifest_toml("notes", "0.1.0")).unwrap();
+        memory.push(manifest.clone()).push(manifest);
+        let report = TappletRegistry::from(memory).validate().unwrap();
+        let kinds: Vec<_> = report.findings.iter().map(|finding| finding.kind).collect();
+        assert_eq!(
+            kinds,
+            [FindingKind::BadPublicKey, FindingKind::DuplicateName]
+        );
+        assert_eq!(report.checked, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pinned_refs() {
+        let temp = tempfile::tempdir().unwrap();
+        let remote = temp.path().join("remote");
+        let first = test_utils::init_registry_repo(&remote, &[("wallet", "0.1.0")]);
+        let repo = Repository::open(&remote).unwrap();
+        let first_commit = repo.find_object(first.parse().unwrap(), None).unwrap();
+        repo.tag_lightweight("v1", &first_commit, false).unwrap();
+        repo.branch("dev", &first_commit.peel_to_commit().unwrap(), false)
+            .unwrap();
+        test_utils::write_lua_tapplet(&remote.join("tapplets/notes"), "notes", "0.2.0");
+        let second = test_utils::commit_all(&remote);
+
+        let fetch = |name: &str, registry_ref| {
+            let mut registry =
+                TappletRegistry::new("test", remote.to_str().unwrap(), temp.path().join(name))
+                    .with_ref(registry_ref);
+            async move { registry.fetch().await.map(|()| registry) }
+        };
+        let registry = fetch("default", RegistryRef::DefaultBranch).await.unwrap();
+        assert_eq!(registry.revision(), Some(&second));
+        assert_eq!(registry.tapplets.len(), 2);
+        let registry = fetch("tag", RegistryRef::Tag("v1".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(registry.revision(), Some(&first));
+        assert_eq!(registry.tapplets.len(), 1);
+        let registry = fetch("rev", RegistryRef::Rev(first[..8].to_string()))
+            .await
+            .unwrap();
+        assert_eq!(registry.revision(), Some(&first));
+
+        // A pinned branch follows new commits
+        let mut registry = fetch("branch", RegistryRef::Branch("dev".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(registry.revision(), Some(&first));
+        repo.reference("refs/heads/dev", second.parse().unwrap(), true, "advance")
+            .unwrap();
+        registry.fetch().await.unwrap();
+        assert_eq!(registry.revision(), Some(&second));
+
+        let err = fetch("missing", RegistryRef::Tag("v2".to_string()))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(crate::error_code(&err), "REGISTRY_REF_NOT_FOUND");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fetch_fails_over_to_mirrors() {
+        let temp = tempfile::tempdir().unwrap();
+        let url = |name: &str| temp.path().join(name).to_str().unwrap().to_string();
+        test_utils::init_registry_repo(&temp.path().join("mirror"), &[("wallet", "0.1.0")]);
+        let cache = temp.path().join("cache");
+        let registry = || {
+            TappletRegistry::new(url("primary"), url("primary"), cache.clone())
+                .with_mirrors([url("down"), url("mirror")])
+                .with_failover_backoff(Duration::ZERO)
+        };
+
+        let mut fetched = registry();
+        fetched.fetch().await.unwrap();
+        assert_eq!(fetched.tapplets.len(), 1);
+        assert_eq!(fetched.fetched_from(), Some(url("mirror").as_str()));
+        // The mirror fills the primary URL's cache
+        assert!(cache.join(sanitize_repo_name(&url("primary"))).exists());
+
+        // Updating an existing clone falls back too
+        test_utils::write_lua_tapplet(&temp.path().join("mirror/tapplets/notes"), "notes", "0.2.0");
+        test_utils::commit_all(&temp.path().join("mirror"));
+        fetched.fetch().await.unwrap();
+        assert_eq!(fetched.tapplets.len(), 2);
+
+        let mut loaded = registry();
+        loaded.load().await.unwrap();
+        assert_eq!(loaded.fetched_from(), Some(url("mirror").as_str()));
+
+        std::fs::remove_dir_all(temp.path().join("mirror")).unwrap();
+        let err = registry().fetch().await.unwrap_err();
+        assert!(format!("{:#}", err).contains(&url("mirror")));
+    }
+
+    #[test]
+    fn test_versioned_directory_layout() {
+        let temp = tempfile::tempdir().unwrap();
+        let repo = temp.path();
+        for version in ["0.1.0", "0.2.0"] {
+            test_utils::write_lua_tapplet(
+                &repo.join("tapplets").join("wallet").join(version),
+                "wallet",
+                version,
+            );
+        }
+
+        let mut registry = loaded_registry(&[]);
+        registry.set_tapplets(walk_tapplets(repo).unwrap());
+        let dirs = registry.tapplets_and_dirs().unwrap();
+        assert_eq!(dirs.len(), 2);
+        let latest = registry.latest("wallet").unwrap().unwrap();
+        assert_eq!(
+            registry.tapplet_dir(latest),
+            repo.join("tapplets").join("wallet").join("0.2.0")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gc_and_cache_stats() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache = temp.path().join("cache");
+        let registry_for = |name: &str| {
+            let remote = temp.path().join(name);
+            test_utils::init_registry_repo(&remote, &[("wallet", "0.1.0")]);
+            TappletRegistry::new(name, remote.to_str().unwrap(), cache.clone())
+        };
+        let mut registry = registry_for("own");
+        registry.fetch().await.unwrap();
+        let mut other = registry_for("other");
+        other.fetch().await.unwrap();
+
+        // An interrupted clone, fetch state left behind by a deleted clone and build output
+        Repository::init(cache.join("interrupted")).unwrap();
+        std::fs::write(cache.join("deleted.fetch.toml"), "").unwrap();
+        let crate_dir = cache.join("sources").join("wasm_tapplet");
+        std::fs::create_dir_all(crate_dir.join("target")).unwrap();
+        std::fs::write(crate_dir.join("Cargo.toml"), "").unwrap();
+        std::fs::write(crate_dir.join("target").join("out.wasm"), vec![0; 1024]).unwrap();
+
+        let stats = registry.cache_stats().unwrap();
+        assert_eq!(stats.repos.len(), 4);
+        assert_eq!(stats.repos.iter().filter(|r| r.is_orphaned).count(), 2);
+        assert!(stats.total_bytes >= 1024);
+
+        let report = registry.gc(&GcPolicy::default()).unwrap();
+        assert_eq!(report.removed.len(), 2);
+        assert!(!cache.join("interrupted").exists());
+        assert!(crate_dir.join("target").exists());
+
+        let report = registry
+            .gc(&GcPolicy::default().with_build_artifacts_removed())
+            .unwrap();
+        assert_eq!(report.removed, vec![crate_dir.join("target")]);
+        assert!(report.freed_bytes >= 1024);
+
+        // Size limits evict other clones but never the registry's own checkout
+        let report = registry.gc(&GcPolicy::default().with_max_size(0)).unwrap();
+        assert_eq!(report.removed.len(), 1);
+        assert!(registry.load().await.is_ok());
+        assert!(other.load().await.is_err());
+    }
+
+    #[test]
+    fn test_gc_removes_superseded_installs() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache = temp.path().to_path_buf();
+        for (name, version) in [
+            ("wallet", "0.1.0"),
+            ("wallet", "0.2.0"),
+            ("wallet", "0.3.0"),
+            ("notes", "0.1.0"),
+            ("notes", "0.2.0"),
+        ] {
+            let dir = cache.join(format!("{}@{}", name, version));
+            test_utils::write_lua_tapplet(&dir, name, version);
+        }
+        std::fs::write(
+            cache.join(crate::lock::LOCK_FILE_NAME),
+            r#"
+[[tapplets]]
+name = "wallet"
+version = "0.1.0"
+artifact_sha256 = "00"
+
+[tapplets.source]
+kind = "local_lua"
+path = "wallet"
+"#,
+        )
+        .unwrap();
+        let registry = TappletRegistry::new("own", "https://example.com/own.git", cache.clone());
+
+        let report = registry.gc(&GcPolicy::default()).unwrap();
+        assert!(report.removed.is_empty());
+
+        let report = registry
+            .gc(&GcPolicy::default().with_superseded_installs_removed())
+            .unwrap();
+        let mut removed = report.removed.clone();
+        removed.sort();
+        assert_eq!(
+            removed,
+            [cache.join("notes@0.1.0"), cache.join("wallet@0.2.0")]
+        );
+        assert!(report.freed_bytes > 0);
+        // The locked version and the newest version of every tapplet are kept
+        for kept in ["wallet@0.1.0", "wallet@0.3.0", "notes@0.2.0"] {
+            assert!(cache.join(kept).exists(), "{}", kept);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// How [`super::TappletRegistry::refresh`] chooses between the network and the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FetchPolicy {
+    /// Always fetch from the remote, failing if the remote is unreachable
+    #[default]
+    AlwaysFetch,
+    /// Use the cached checkout while it is younger than `max_age`, otherwise fetch.
+    ///
+    /// If fetching fails, a stale cache is served instead of returning an error.
+    PreferCache { max_age: Duration },
+    /// Never touch the network, only read the cached checkout
+    OfflineOnly,
+}
+
+/// Bookkeeping stored next to a cached registry checkout
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub(crate) struct FetchState {
+    /// Seconds since the unix epoch of the last successful fetch
+    pub last_fetch_unix: Option<u64>,
+    /// URL the last successful fetch came from, the primary URL or a mirror
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// `ETag` of an HTTP registry's index, sent back as `If-None-Match`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// `Last-Modified` of an HTTP registry's index, sent back as `If-Modified-Since`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl FetchState {
+    /// Path of the state file for a checkout, kept outside the repository itself
+    pub fn path(repo_path: &Path) -> PathBuf {
+        let mut file_name = repo_path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".fetch.toml");
+        repo_path.with_file_name(file_name)
+    }
+
+    pub fn read(repo_path: &Path) -> Result<Self> {
+        let path = Self::path(repo_path);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse fetch state: {}", path.display()))
+    }
+
+    /// Record a successful fetch at the current time, returning the recorded time
+    pub fn record_fetch(repo_path: &Path, source: &str) -> Result<SystemTime> {
+        FetchState {
+            source: Some(source.to_string()),
+            ..Default::default()
+        }
+        .write(repo_path)
+    }
+
+    /// Record a successful fetch of an HTTP registry along with the validators of its index
+    #[cfg(feature = "http-registry")]
+    pub fn record_http_fetch(
+        repo_path: &Path,
+        source: &str,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    ) -> Result<SystemTime> {
+        FetchState {
+            source: Some(source.to_string()),
+            etag,
+            last_modified,
+            ..Default::default()
+        }
+        .write(repo_path)
+    }
+
+    fn write(mut self, repo_path: &Path) -> Result<SystemTime> {
+        self.last_fetch_unix = Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+        let path = Self::path(repo_path);
+        std::fs::write(&path, toml::to_string(&self)?)
+            .with_context(|| format!("Failed to write fetch state: {}", path.display()))?;
+        Ok(self.last_fetch().unwrap_or(UNIX_EPOCH))
+    }
+
+    pub fn last_fetch(&self) -> Option<SystemTime> {
+        self.last_fetch_unix
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+}
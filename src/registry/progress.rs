@@ -0,0 +1,64 @@
+use git2::Progress;
+
+/// Progress of a registry clone or fetch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchProgress {
+    /// Objects are being downloaded from the remote
+    Receiving {
+        received_objects: usize,
+        total_objects: usize,
+        indexed_objects: usize,
+        received_bytes: usize,
+    },
+    /// All objects are downloaded and deltas are being resolved
+    ResolvingDeltas {
+        indexed_deltas: usize,
+        total_deltas: usize,
+    },
+    /// The repository is up to date and checked out
+    Done,
+}
+
+impl FetchProgress {
+    pub(crate) fn from_git(stats: &Progress<'_>) -> Option<Self> {
+        if stats.total_objects() > 0 && stats.received_objects() == stats.total_objects() {
+            Some(FetchProgress::ResolvingDeltas {
+                indexed_deltas: stats.indexed_deltas(),
+                total_deltas: stats.total_deltas(),
+            })
+        } else if stats.total_objects() > 0 {
+            Some(FetchProgress::Receiving {
+                received_objects: stats.received_objects(),
+                total_objects: stats.total_objects(),
+                indexed_objects: stats.indexed_objects(),
+                received_bytes: stats.received_bytes(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Receives progress updates while a registry is cloned or fetched.
+///
+/// Called from the blocking git thread, so implementations should be cheap
+/// and forward the update (e.g. over a channel) rather than do work inline.
+pub trait ProgressReporter: Send + Sync {
+    fn report(&self, progress: FetchProgress);
+}
+
+impl<F> ProgressReporter for F
+where
+    F: Fn(FetchProgress) + Send + Sync,
+{
+    fn report(&self, progress: FetchProgress) {
+        self(progress)
+    }
+}
+
+/// A reporter that ignores all progress updates
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {
+    fn report(&self, _progress: FetchProgress) {}
+}
@@ -0,0 +1,138 @@
+use crate::TappletManifest;
+use crate::model::RuntimeKind;
+
+/// What [`super::TappletRegistry::search`] looks for.
+///
+/// A plain `&str` converts into a query that only matches text. Results are
+/// ordered by relevance: a match in the name ranks above one in the friendly
+/// name, which ranks above one in the description or publisher.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    /// Looked for in names, friendly names, descriptions and publishers, ignoring
+    /// case. Empty matches every tapplet.
+    pub text: String,
+    /// Only tapplets from this publisher
+    pub publisher: Option<String>,
+    /// Only tapplets for this runtime, declared or detected from their files
+    pub runtime: Option<RuntimeKind>,
+    /// Only tapplets listed under this category, ignoring case
+    pub category: Option<String>,
+    /// Only tapplets with this tag, ignoring case
+    pub tag: Option<String>,
+    /// Only tapplets usable under this SPDX license, ignoring case
+    pub license: Option<String>,
+    /// Also list yanked tapplets
+    pub include_yanked: bool,
+    /// Number of results to skip
+    pub offset: usize,
+    /// Most results to return
+    pub limit: Option<usize>,
+}
+
+/// How well a tapplet matches the query text, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum Relevance {
+    ExactName,
+    Name,
+    FriendlyName,
+    Description,
+    Publisher,
+}
+
+impl SearchQuery {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_publisher(mut self, publisher: impl Into<String>) -> Self {
+        self.publisher = Some(publisher.into());
+        self
+    }
+
+    pub fn with_runtime(mut self, runtime: RuntimeKind) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    pub fn with_category(mut self, category: impl Into<String>) -> Self {
+        self.category = Some(category.into());
+        self
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    pub fn with_license(mut self, license: impl Into<String>) -> Self {
+        self.license = Some(license.into());
+        self
+    }
+
+    pub fn with_yanked_included(mut self) -> Self {
+        self.include_yanked = true;
+        self
+    }
+
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Whether a tapplet passes the filters, leaving the text and runtime aside
+    pub(super) fn filters(&self, tapplet: &TappletManifest) -> bool {
+        (self.include_yanked || !tapplet.yanked)
+            && self
+                .publisher
+                .as_ref()
+                .is_none_or(|publisher| *publisher == tapplet.publisher)
+            && self
+                .category
+                .as_ref()
+                .is_none_or(|category| tapplet.in_category(category))
+            && self.tag.as_ref().is_none_or(|tag| tapplet.has_tag(tag))
+            && self
+                .license
+                .as_ref()
+                .is_none_or(|license| tapplet.licensed_under([license.as_str()]))
+    }
+
+    /// How well the tapplet matches the text, `None` if it doesn't
+    pub(super) fn relevance(&self, tapplet: &TappletManifest) -> Option<Relevance> {
+        let text = self.text.to_lowercase();
+        let contains = |field: &str| field.to_lowercase().contains(&text);
+        if tapplet.name_matches(&text) || tapplet.name.eq_ignore_ascii_case(&text) {
+            Some(Relevance::ExactName)
+        } else if contains(&tapplet.name) {
+            Some(Relevance::Name)
+        } else if contains(&tapplet.friendly_name) {
+            Some(Relevance::FriendlyName)
+        } else if tapplet.description.as_deref().is_some_and(contains) {
+            Some(Relevance::Description)
+        } else if contains(&tapplet.publisher) {
+            Some(Relevance::Publisher)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<&str> for SearchQuery {
+    fn from(text: &str) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<String> for SearchQuery {
+    fn from(text: String) -> Self {
+        Self::new(text)
+    }
+}
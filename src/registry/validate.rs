@@ -0,0 +1,319 @@
+//! Lint checks over a whole registry, for registry maintainers' pre-merge gates
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use super::{RegistryIndex, TappletConflict};
+use crate::TappletManifest;
+use crate::error::TappletError;
+use crate::trust::{TrustPolicy, TrustViolation};
+
+/// What kind of problem a [`RegistryFinding`] reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind {
+    /// The manifest doesn't parse, or fails the strict manifest checks
+    InvalidManifest,
+    /// The public key is not 64 hex characters
+    BadPublicKey,
+    /// Another manifest in the registry has the same name and version
+    DuplicateName,
+    /// The manifest's directory is named after another tapplet or version
+    NameMismatch,
+    /// A file listed in `[artifacts]` or `[assets]`, or the runtime entrypoint,
+    /// doesn't exist
+    MissingArtifact,
+    /// A file listed in `[artifacts]` doesn't match its hash
+    ArtifactMismatch,
+    /// A file listed in `[assets]` is too large or not the image it claims to be
+    InvalidAsset,
+    /// A Lua script fails the sandbox check, so the registry won't list the tapplet
+    InvalidScript,
+    /// An entry of the registry index doesn't load
+    InvalidIndexEntry,
+    /// The trust policy's signature verifier rejects the manifest
+    InvalidSignature,
+    /// The trust policy doesn't trust the publisher or key
+    Untrusted,
+}
+
+/// One problem found by [`super::TappletRegistry::validate`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RegistryFinding {
+    pub kind: FindingKind,
+    /// Manifest or index the finding is about, relative to the registry root
+    pub path: PathBuf,
+    /// Dotted path of the manifest field, if the finding is about one field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for RegistryFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(field) = &self.field {
+            write!(f, " ({})", field)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Everything [`super::TappletRegistry::validate`] found, in the order the
+/// manifests were checked
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RegistryReport {
+    /// Number of manifests checked
+    pub checked: usize,
+    pub findings: Vec<RegistryFinding>,
+}
+
+impl RegistryReport {
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Findings of one kind
+    pub fn of_kind(&self, kind: FindingKind) -> impl Iterator<Item = &RegistryFinding> {
+        self.findings
+            .iter()
+            .filter(move |finding| finding.kind == kind)
+    }
+
+    fn add(&mut self, kind: FindingKind, path: &Path, message: impl Into<String>) {
+        self.findings.push(RegistryFinding {
+            kind,
+            path: path.to_path_buf(),
+            field: None,
+            message: message.into(),
+        });
+    }
+
+    /// Check every manifest below `tapplets/` in a checkout, plus its index
+    pub(super) fn for_checkout(root: &Path, policy: &TrustPolicy) -> Self {
+        let mut report = Self::default();
+        let mut seen = HashMap::new();
+        let mut manifests: Vec<_> = walkdir::WalkDir::new(root.join("tapplets"))
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| super::is_tapplet_manifest(entry.path()))
+            .map(|entry| entry.into_path())
+            .collect();
+        manifests.sort();
+        for manifest_path in manifests {
+            let relative = manifest_path.strip_prefix(root).unwrap_or(&manifest_path);
+            let dir = manifest_path.parent().unwrap_or(root);
+            if let Some(manifest) = report.parse(&manifest_path, relative) {
+                report.check_dir_name(&manifest, dir, relative);
+                report.check(&manifest, Some(dir), relative, policy, &mut seen);
+            }
+        }
+        report.check_index(root);
+        report
+    }
+
+    /// Check manifests that were loaded without their files on disk being
+    /// walked, e.g. from an HTTP registry or an in-memory one. Duplicates were
+    /// already dropped while loading, so they are reported from `conflicts`.
+    pub(super) fn for_loaded<'a>(
+        tapplets: impl IntoIterator<Item = (&'a TappletManifest, PathBuf)>,
+        conflicts: &[TappletConflict],
+        policy: &TrustPolicy,
+    ) -> Self {
+        let mut report = Self::default();
+        let mut seen = HashMap::new();
+        for (manifest, dir) in tapplets {
+            report.checked += 1;
+            let path = PathBuf::from(manifest.canonical_name());
+            for issue in manifest.validate() {
+                report.add_issue(&path, issue.field, issue.message);
+            }
+            let dir = (!dir.as_os_str().is_empty()).then_some(dir.as_path());
+            report.check(manifest, dir, &path, policy, &mut seen);
+        }
+        for conflict in conflicts {
+            for copy in conflict.copies.iter().filter(|copy| !copy.kept) {
+                report.checked += 1;
+                report.add(
+                    FindingKind::DuplicateName,
+                    Path::new(&format!(
+                        "{}/{}@{}",
+                        conflict.publisher, conflict.name, copy.version
+                    )),
+                    conflict.to_string(),
+                );
+            }
+        }
+        report
+    }
+
+    /// Parse a manifest strictly, falling back to the lenient parser so the
+    /// other checks still run when only the strict checks fail
+    fn parse(&mut self, manifest_path: &Path, relative: &Path) -> Option<TappletManifest> {
+        self.checked += 1;
+        let error = match TappletManifest::from_file_strict(manifest_path) {
+            Ok(manifest) => return Some(manifest),
+            Err(error) => error,
+        };
+        match error.downcast_ref::<TappletError>() {
+            Some(TappletError::ManifestIssues { issues }) => {
+                for issue in issues {
+                    self.add_issue(relative, issue.field.clone(), issue.message.clone());
+                }
+            }
+            _ => self.add(
+                FindingKind::InvalidManifest,
+                relative,
+                format!("{:#}", error),
+            ),
+        }
+        TappletManifest::from_file(manifest_path).ok()
+    }
+
+    fn add_issue(&mut self, path: &Path, field: String, message: String) {
+        let kind = if field == "public_key" {
+            FindingKind::BadPublicKey
+        } else {
+            FindingKind::InvalidManifest
+        };
+        self.findings.push(RegistryFinding {
+            kind,
+            path: path.to_path_buf(),
+            field: (!field.is_empty()).then_some(field),
+            message,
+        });
+    }
+
+    /// Tapplets live in `tapplets/<name>` or `tapplets/<name>/<version>`
+    fn check_dir_name(&mut self, manifest: &TappletManifest, dir: &Path, relative: &Path) {
+        let name_of = |dir: &Path| {
+            dir.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let dir_name = name_of(dir);
+        let matches = manifest.name_matches(&dir_name)
+            || (dir_name == manifest.version
+                && dir
+                    .parent()
+                    .is_some_and(|parent| manifest.name_matches(&name_of(parent))));
+        if !matches {
+            self.add(
+                FindingKind::NameMismatch,
+                relative,
+                format!(
+                    "{} is in directory '{}', expected tapplets/{} or tapplets/{}/{}",
+                    manifest.canonical_name(),
+                    dir_name,
+                    manifest.name,
+                    manifest.name,
+                    manifest.version
+                ),
+            );
+        }
+    }
+
+    /// Checks shared by both kinds of registries
+    fn check(
+        &mut self,
+        manifest: &TappletManifest,
+        dir: Option<&Path>,
+        path: &Path,
+        policy: &TrustPolicy,
+        seen: &mut HashMap<String, PathBuf>,
+    ) {
+        let key = format!(
+            "{}/{}@{}",
+            manifest.publisher,
+            manifest.name.to_lowercase().replace('-', "_"),
+            manifest.version
+        );
+        if let Some(first) = seen.get(&key) {
+            self.add(
+                FindingKind::DuplicateName,
+                path,
+                format!(
+                    "{} is also published at {}",
+                    manifest.fully_qualified_name(),
+                    first.display()
+                ),
+            );
+        } else {
+            seen.insert(key, path.to_path_buf());
+        }
+
+        if let Some(dir) = dir {
+            self.check_files(manifest, dir, path);
+        }
+
+        for violation in policy.check(manifest) {
+            let kind = match violation {
+                TrustViolation::InvalidSignature(_) => FindingKind::InvalidSignature,
+                _ => FindingKind::Untrusted,
+            };
+            self.add(kind, path, violation.to_string());
+        }
+    }
+
+    fn check_files(&mut self, manifest: &TappletManifest, dir: &Path, path: &Path) {
+        if let Err(e) = manifest.verify_artifacts(dir) {
+            let kind = match e.downcast_ref::<TappletError>() {
+                Some(TappletError::IntegrityMismatch { .. }) => FindingKind::ArtifactMismatch,
+                Some(TappletError::ArtifactNotFound(_)) => FindingKind::MissingArtifact,
+                _ => FindingKind::InvalidManifest,
+            };
+            self.add(kind, path, format!("{:#}", e));
+        }
+        if let Err(e) = manifest.verify_assets(dir) {
+            let kind = match e.downcast_ref::<TappletError>() {
+                Some(TappletError::ArtifactNotFound(_)) => FindingKind::MissingArtifact,
+                _ => FindingKind::InvalidAsset,
+            };
+            self.add(kind, path, format!("{:#}", e));
+        }
+        if let Some(runtime) = &manifest.runtime
+            && let Ok(entrypoint) = runtime.entrypoint_path(dir)
+            && !entrypoint.is_file()
+        {
+            self.add(
+                FindingKind::MissingArtifact,
+                path,
+                format!("entrypoint {} does not exist", runtime.entrypoint()),
+            );
+        }
+        #[cfg(feature = "host-core")]
+        if let Err(e) = crate::local_folder_lua_tapplet::check_scripts(
+            dir,
+            &crate::sandbox::SandboxOptions::default(),
+        ) {
+            self.add(FindingKind::InvalidScript, path, format!("{:#}", e));
+        }
+    }
+
+    fn check_index(&mut self, root: &Path) {
+        let index = match RegistryIndex::from_repo(root) {
+            Ok(Some(index)) => index,
+            Ok(None) => return,
+            Err(e) => {
+                self.add(
+                    FindingKind::InvalidIndexEntry,
+                    Path::new(""),
+                    format!("{:#}", e),
+                );
+                return;
+            }
+        };
+        for entry in &index.tapplets {
+            if let Err(e) = entry.load(root) {
+                self.add(
+                    FindingKind::InvalidIndexEntry,
+                    Path::new(&entry.path),
+                    format!("index entry {}@{}: {:#}", entry.name, entry.version, e),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+
+use anyhow::{Result, bail};
+use tokio::task::JoinSet;
+
+use crate::TappletManifest;
+use crate::error::TappletError;
+use crate::registry::TappletRegistry;
+
+/// A tapplet found in one of the managed registries
+#[derive(Debug, Clone)]
+pub struct RegistryMatch<'a> {
+    /// Name of the registry the tapplet came from
+    pub registry: &'a str,
+    /// Priority of that registry, higher wins
+    pub priority: i32,
+    pub tapplet: &'a TappletManifest,
+}
+
+struct RegistryEntry {
+    registry: TappletRegistry,
+    priority: i32,
+}
+
+/// Holds several registries (e.g. an official one plus community/dev ones) and
+/// resolves tapplets across all of them.
+///
+/// When the same tapplet name is published in more than one registry, the
+/// registry with the highest priority overlays the others. Registries with
+/// equal priority are resolved in the order they were added.
+#[derive(Default)]
+pub struct RegistryManager {
+    registries: Vec<RegistryEntry>,
+}
+
+impl RegistryManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a registry with the given priority, replacing any registry with the same name
+    pub fn add_registry(&mut self, registry: TappletRegistry, priority: i32) {
+        self.registries
+            .retain(|entry| entry.registry.name != registry.name);
+        self.registries.push(RegistryEntry { registry, priority });
+        // Stable sort keeps insertion order between equal priorities
+        self.registries
+            .sort_by_key(|entry| std::cmp::Reverse(entry.priority));
+    }
+
+    /// Remove a registry by name, returning it if it was present
+    pub fn remove_registry(&mut self, name: &str) -> Option<TappletRegistry> {
+        let index = self
+            .registries
+            .iter()
+            .position(|entry| entry.registry.name == name)?;
+        Some(self.registries.remove(index).registry)
+    }
+
+    pub fn registry(&self, name: &str) -> Option<&TappletRegistry> {
+        self.registries
+            .iter()
+            .find(|entry| entry.registry.name == name)
+            .map(|entry| &entry.registry)
+    }
+
+    /// Registries in resolution order (highest priority first)
+    pub fn registries(&self) -> impl Iterator<Item = &TappletRegistry> {
+        self.registries.iter().map(|entry| &entry.registry)
+    }
+
+    /// Refresh all registries concurrently, each according to its own
+    /// [`FetchPolicy`](crate::registry::FetchPolicy).
+    ///
+    /// A failing registry doesn't prevent the others from being fetched; the
+    /// result for each registry is returned by name in resolution order. Copies of
+    /// the registries are fetched, so if this is dropped midway or a fetch panics
+    /// the registries are left as they were.
+    pub async fn fetch_all(&mut self) -> Vec<(String, Result<()>)> {
+        let mut tasks = JoinSet::new();
+        for (index, entry) in self.registries.iter().enumerate() {
+            let mut registry = entry.registry.clone();
+            tasks.spawn(async move {
+                let result = registry.refresh().await;
+                (index, registry, result)
+            });
+        }
+
+        let mut fetched = Vec::with_capacity(tasks.len());
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok(result) => fetched.push(result),
+                Err(err) => std::panic::resume_unwind(err.into_panic()),
+            }
+        }
+        fetched.sort_by_key(|(index, _, _)| *index);
+
+        let mut results = Vec::with_capacity(fetched.len());
+        for (index, registry, result) in fetched {
+            results.push((registry.name.clone(), result));
+            self.registries[index].registry = registry;
+        }
+        results
+    }
+
+    /// Search all loaded registries, keeping only the highest priority match per tapplet name
+    pub fn search(&self, query: &str) -> Result<Vec<RegistryMatch<'_>>> {
+        let mut seen = HashSet::new();
+        Ok(self
+            .search_all(query)?
+            .into_iter()
+            .filter(|found| seen.insert(normalized_name(&found.tapplet.name)))
+            .collect())
+    }
+
+    /// Search all loaded registries, returning every match with its provenance
+    pub fn search_all(&self, query: &str) -> Result<Vec<RegistryMatch<'_>>> {
+        let mut results = Vec::new();
+        for entry in self.loaded_entries()? {
+            for tapplet in entry.registry.search(query)? {
+                results.push(RegistryMatch {
+                    registry: &entry.registry.name,
+                    priority: entry.priority,
+                    tapplet,
+                });
+            }
+        }
+        Ok(results)
+    }
+
+    /// Resolve the latest version of a tapplet from the highest priority registry that has it
+    pub fn get_tapplet(&self, name: &str) -> Result<Option<RegistryMatch<'_>>> {
+        for entry in self.loaded_entries()? {
+            if let Some(tapplet) = entry.registry.latest(name)? {
+                return Ok(Some(RegistryMatch {
+                    registry: &entry.registry.name,
+                    priority: entry.priority,
+                    tapplet,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    fn loaded_entries(&self) -> Result<impl Iterator<Item = &RegistryEntry>> {
+        if !self
+            .registries
+            .iter()
+            .any(|entry| entry.registry.is_loaded())
+        {
+            bail!(TappletError::RegistryNotLoaded);
+        }
+        Ok(self
+            .registries
+            .iter()
+            .filter(|entry| entry.registry.is_loaded()))
+    }
+}
+
+fn normalized_name(name: &str) -> String {
+    name.replace("-", "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fetch_all_and_overlay_by_priority() {
+        let temp = tempfile::tempdir().unwrap();
+        let official = temp.path().join("official");
+        let community = temp.path().join("community");
+        test_utils::init_registry_repo(&official, &[("wallet-tools", "1.0.0")]);
+        test_utils::init_registry_repo(
+            &community,
+            &[("wallet-tools", "2.0.0"), ("price-alert", "0.1.0")],
+        );
+
+        let cache = temp.path().join("cache");
+        let mut manager = RegistryManager::new();
+        manager.add_registry(
+            TappletRegistry::new("community", community.to_str().unwrap(), cache.clone()),
+            0,
+        );
+        manager.add_registry(
+            TappletRegistry::new("official", official.to_str().unwrap(), cache.clone()),
+            10,
+        );
+        assert!(manager.search("wallet").is_err());
+
+        let results = manager.fetch_all().await;
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(results[0].0, "official");
+
+        let found = manager.get_tapplet("wallet_tools").unwrap().unwrap();
+        assert_eq!(found.registry, "official");
+        assert_eq!(found.tapplet.version, "1.0.0");
+
+        let overlaid = manager.search("wallet").unwrap();
+        assert_eq!(overlaid.len(), 1);
+        assert_eq!(overlaid[0].registry, "official");
+        assert_eq!(manager.search_all("wallet").unwrap().len(), 2);
+
+        let community_only = manager.get_tapplet("price-alert").unwrap().unwrap();
+        assert_eq!(community_only.registry, "community");
+
+        // Dropping a fetch midway keeps the registries as they were
+        let dropped = tokio::time::timeout(std::time::Duration::ZERO, manager.fetch_all());
+        assert!(dropped.await.is_err());
+        assert_eq!(manager.registries().count(), 2);
+        assert!(manager.get_tapplet("price-alert").unwrap().is_some());
+    }
+}
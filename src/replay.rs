@@ -0,0 +1,577 @@
+//! Recording tapplet calls with their host API interactions, and replaying them.
+//!
+//! A [`Recorder`] wraps a host API so every call the tapplet makes into it is
+//! captured with its response. [`Recorder::record`] runs a method and returns a
+//! [`CallRecording`] of the arguments, the interactions and the result, which can
+//! be saved as JSON. A [`Replayer`] is a host API that answers from a recording
+//! instead, so [`Replayer::replay`] re-executes the tapplet exactly as it ran,
+//! e.g. to reproduce a bug or as a regression test:
+//!
+//! ```rust,ignore
+//! let recorder = Recorder::new();
+//! let host = LuaTappletHost::new_shared(config.clone(), "tapplet.lua", Arc::new(recorder.wrap(api)))?;
+//! let (_, recording) = recorder.record(&mut host, "send", args, &ctx).await;
+//! recording.save("send.recording.json")?;
+//!
+//! let replayer = Replayer::new();
+//! let mut host = LuaTappletHost::new_shared(config, "tapplet.lua", Arc::new(replayer.clone()))?;
+//! let report = replayer.replay(&mut host, &CallRecording::load("send.recording.json")?, &ctx).await;
+//! assert!(report.is_faithful(), "{:?}", report.divergence);
+//! ```
+//!
+//! HTTP requests and chain queries are host API interactions too. Wrap the
+//! [`HttpClient`] of a host's `NetworkAccess` and its [`MinotariChainApi`] with
+//! the same recorder, and give a replaying host the replayer as both, to record
+//! and replay them; this is how WASM tapplets, which don't call the wallet API,
+//! get recordings with interactions:
+//!
+//! ```rust,ignore
+//! let host = WasmTappletHost::new(config, "tapplet.wasm")?
+//!     .with_network_access(NetworkAccess::new(Arc::new(recorder.wrap(client))))
+//!     .with_chain_api(Arc::new(recorder.wrap(chain)));
+//! ```
+//!
+//! Time and random bytes aren't recorded. Tapplets that use them only replay
+//! faithfully on hosts with the same deterministic
+//! [`AmbientMode`](crate::ambient::AmbientMode) as the recording one.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::call_context::CallContext;
+use crate::chain::{BlockHeader, KernelInfo, MinotariChainApi};
+use crate::host::{HostError, MinotariTappletApiV1, TappletRunner};
+use crate::network::{HttpClient, HttpLimits, HttpMethod, HttpRequest, HttpResponse};
+use crate::wallet::{Balance, MinotariTappletApiV2, TransactionFilter, TransactionInfo};
+
+/// One call from a tapplet into the host API and what it returned
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostInteraction {
+    /// The API method, e.g. `append_data`
+    pub function: String,
+    pub args: Value,
+    /// The returned value, or the error message
+    pub response: Result<Value, String>,
+}
+
+/// The error a recorded call failed with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedError {
+    pub code: String,
+    pub message: String,
+}
+
+impl From<&HostError> for RecordedError {
+    fn from(err: &HostError) -> Self {
+        Self {
+            code: err.code().to_string(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// A tapplet method call with every host API interaction it made, in order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallRecording {
+    pub tapplet: String,
+    pub method: String,
+    pub args: Value,
+    pub interactions: Vec<HostInteraction>,
+    pub result: Result<Value, RecordedError>,
+}
+
+impl CallRecording {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize recording")?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write recording: {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read recording: {}", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse recording: {}", path.display()))
+    }
+}
+
+/// Captures the host API interactions of tapplet calls. Clones share what they capture.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    interactions: Arc<Mutex<Vec<HostInteraction>>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wrap `api` so that calls into it are captured by this recorder
+    pub fn wrap<T>(&self, api: T) -> RecordingApi<T> {
+        RecordingApi {
+            api,
+            recorder: self.clone(),
+        }
+    }
+
+    /// Call `method` on `runner`, whose API is wrapped by this recorder, and
+    /// record the call
+    pub async fn record<R: TappletRunner + ?Sized>(
+        &self,
+        runner: &mut R,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> (Result<Value, HostError>, CallRecording) {
+        self.interactions.lock().unwrap().clear();
+        let result = runner.call(method, args.clone(), context).await;
+        let recording = CallRecording {
+            tapplet: runner.manifest().name.clone(),
+            method: method.to_string(),
+            args,
+            interactions: std::mem::take(&mut *self.interactions.lock().unwrap()),
+            result: result.as_ref().map(Value::clone).map_err(Into::into),
+        };
+        (result, recording)
+    }
+
+    fn capture<T: Serialize, E: std::fmt::Display>(
+        &self,
+        function: &str,
+        args: Value,
+        response: &Result<T, E>,
+    ) {
+        let response = match response {
+            Ok(value) => serde_json::to_value(value).map_err(|e| e.to_string()),
+            Err(e) => Err(format!("{:#}", e)),
+        };
+        self.interactions.lock().unwrap().push(HostInteraction {
+            function: function.to_string(),
+            args,
+            response,
+        });
+    }
+}
+
+/// A host API whose calls are captured by a [`Recorder`], see [`Recorder::wrap`]
+pub struct RecordingApi<T> {
+    api: T,
+    recorder: Recorder,
+}
+
+#[async_trait]
+impl<T: MinotariTappletApiV1> MinotariTappletApiV1 for RecordingApi<T> {
+    async fn append_data(&self, slot: &str, value: &str) -> Result<(), anyhow::Error> {
+        let result = self.api.append_data(slot, value).await;
+        let args = json!({ "slot": slot, "value": value });
+        self.recorder.capture("append_data", args, &result);
+        result
+    }
+
+    async fn load_data_entries(&self, slot: &str) -> Result<Vec<String>, anyhow::Error> {
+        let result = self.api.load_data_entries(slot).await;
+        let args = json!({ "slot": slot });
+        self.recorder.capture("load_data_entries", args, &result);
+        result
+    }
+
+    async fn add_watched_viewkey(&self, viewkey: &str, birthday: u64) -> Result<(), anyhow::Error> {
+        let result = self.api.add_watched_viewkey(viewkey, birthday).await;
+        let args = json!({ "viewkey": viewkey, "birthday": birthday });
+        self.recorder.capture("add_watched_viewkey", args, &result);
+        result
+    }
+}
+
+#[async_trait]
+impl<T: MinotariTappletApiV2> MinotariTappletApiV2 for RecordingApi<T> {
+    async fn get_balance(&self) -> Result<Balance, anyhow::Error> {
+        let result = self.api.get_balance().await;
+        self.recorder.capture("get_balance", json!({}), &result);
+        result
+    }
+
+    async fn send_transaction(
+        &self,
+        destination: &str,
+        amount: u64,
+        fee: u64,
+    ) -> Result<String, anyhow::Error> {
+        let result = self.api.send_transaction(destination, amount, fee).await;
+        let args = json!({ "destination": destination, "amount": amount, "fee": fee });
+        self.recorder.capture("send_transaction", args, &result);
+        result
+    }
+
+    async fn get_transactions(
+        &self,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionInfo>, anyhow::Error> {
+        let result = self.api.get_transactions(filter).await;
+        let args = json!({ "filter": filter });
+        self.recorder.capture("get_transactions", args, &result);
+        result
+    }
+}
+
+impl<T: HttpClient> HttpClient for RecordingApi<T> {
+    fn send(&self, request: &HttpRequest, limits: &HttpLimits) -> Result<HttpResponse, String> {
+        let result = self.api.send(request, limits);
+        let (function, args) = http_interaction(request);
+        self.recorder.capture(function, args, &result);
+        result
+    }
+}
+
+#[async_trait]
+impl<T: MinotariChainApi> MinotariChainApi for RecordingApi<T> {
+    async fn get_tip_height(&self) -> Result<u64, anyhow::Error> {
+        let result = self.api.get_tip_height().await;
+        self.recorder.capture("get_tip_height", json!({}), &result);
+        result
+    }
+
+    async fn get_block_header(&self, height: u64) -> Result<Option<BlockHeader>, anyhow::Error> {
+        let result = self.api.get_block_header(height).await;
+        let args = json!({ "height": height });
+        self.recorder.capture("get_block_header", args, &result);
+        result
+    }
+
+    async fn get_kernel(&self, excess: &str) -> Result<Option<KernelInfo>, anyhow::Error> {
+        let result = self.api.get_kernel(excess).await;
+        let args = json!({ "excess": excess });
+        self.recorder.capture("get_kernel", args, &result);
+        result
+    }
+}
+
+/// The function and arguments an HTTP request is recorded as
+fn http_interaction(request: &HttpRequest) -> (&'static str, Value) {
+    match request.method {
+        HttpMethod::Get => ("http_get", json!({ "url": request.url })),
+        HttpMethod::Post => (
+            "http_post",
+            json!({
+                "url": request.url,
+                "body": request.body,
+                "content_type": request.content_type,
+            }),
+        ),
+    }
+}
+
+/// What happened when a recording was replayed
+#[derive(Debug)]
+pub struct ReplayReport {
+    pub result: Result<Value, HostError>,
+    /// The result is the recorded one
+    pub result_matches: bool,
+    /// The first host API call that wasn't the next recorded interaction
+    pub divergence: Option<String>,
+    /// Recorded interactions the replay never made
+    pub unused: Vec<HostInteraction>,
+}
+
+impl ReplayReport {
+    /// The replay made exactly the recorded interactions and returned the recorded result
+    pub fn is_faithful(&self) -> bool {
+        self.result_matches && self.divergence.is_none() && self.unused.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct ReplayState {
+    pending: VecDeque<HostInteraction>,
+    divergence: Option<String>,
+}
+
+/// A host API that answers with the responses of a recording, see [`Replayer::replay`].
+/// Clones share the recording being replayed.
+#[derive(Clone, Default)]
+pub struct Replayer {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl Replayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call the recorded method on `runner`, whose API is this replayer, answering
+    /// its host API calls from `recording`
+    pub async fn replay<R: TappletRunner + ?Sized>(
+        &self,
+        runner: &mut R,
+        recording: &CallRecording,
+        context: &CallContext,
+    ) -> ReplayReport {
+        *self.state.lock().unwrap() = ReplayState {
+            pending: recording.interactions.iter().cloned().collect(),
+            divergence: None,
+        };
+        let result = runner
+            .call(&recording.method, recording.args.clone(), context)
+            .await;
+        let state = std::mem::take(&mut *self.state.lock().unwrap());
+        let result_matches = match (&result, &recording.result) {
+            (Ok(value), Ok(recorded)) => value == recorded,
+            (Err(e), Err(recorded)) => RecordedError::from(e) == *recorded,
+            _ => false,
+        };
+        ReplayReport {
+            result,
+            result_matches,
+            divergence: state.divergence,
+            unused: state.pending.into(),
+        }
+    }
+
+    /// Answer a call with the next recorded interaction, if it is the same call
+    fn respond<T: DeserializeOwned>(&self, function: &str, args: Value) -> Result<T> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(divergence) = &state.divergence {
+            return Err(anyhow!("replay diverged: {}", divergence));
+        }
+        let next = state.pending.front();
+        if next.is_none_or(|next| next.function != function || next.args != args) {
+            let expected = next.map_or("no more calls".to_string(), |next| {
+                format!("{}({})", next.function, next.args)
+            });
+            let divergence = format!("expected {}, got {}({})", expected, function, args);
+            state.divergence = Some(divergence.clone());
+            return Err(anyhow!("replay diverged: {}", divergence));
+        }
+        let interaction = state.pending.pop_front().unwrap();
+        match interaction.response {
+            Ok(value) => serde_json::from_value(value)
+                .with_context(|| format!("Invalid recorded response for {}", function)),
+            Err(message) => Err(anyhow!(message)),
+        }
+    }
+}
+
+#[async_trait]
+impl MinotariTappletApiV1 for Replayer {
+    async fn append_data(&self, slot: &str, value: &str) -> Result<(), anyhow::Error> {
+        self.respond("append_data", json!({ "slot": slot, "value": value }))
+    }
+
+    async fn load_data_entries(&self, slot: &str) -> Result<Vec<String>, anyhow::Error> {
+        self.respond("load_data_entries", json!({ "slot": slot }))
+    }
+
+    async fn add_watched_viewkey(&self, viewkey: &str, birthday: u64) -> Result<(), anyhow::Error> {
+        let args = json!({ "viewkey": viewkey, "birthday": birthday });
+        self.respond("add_watched_viewkey", args)
+    }
+}
+
+#[async_trait]
+impl MinotariTappletApiV2 for Replayer {
+    async fn get_balance(&self) -> Result<Balance, anyhow::Error> {
+        self.respond("get_balance", json!({}))
+    }
+
+    async fn send_transaction(
+        &self,
+        destination: &str,
+        amount: u64,
+        fee: u64,
+    ) -> Result<String, anyhow::Error> {
+        let args = json!({ "destination": destination, "amount": amount, "fee": fee });
+        self.respond("send_transaction", args)
+    }
+
+    async fn get_transactions(
+        &self,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionInfo>, anyhow::Error> {
+        self.respond("get_transactions", json!({ "filter": filter }))
+    }
+}
+
+impl HttpClient for Replayer {
+    fn send(&self, request: &HttpRequest, _: &HttpLimits) -> Result<HttpResponse, String> {
+        let (function, args) = http_interaction(request);
+        self.respond(function, args).map_err(|e| format!("{:#}", e))
+    }
+}
+
+#[async_trait]
+impl MinotariChainApi for Replayer {
+    async fn get_tip_height(&self) -> Result<u64, anyhow::Error> {
+        self.respond("get_tip_height", json!({}))
+    }
+
+    async fn get_block_header(&self, height: u64) -> Result<Option<BlockHeader>, anyhow::Error> {
+        self.respond("get_block_header", json!({ "height": height }))
+    }
+
+    async fn get_kernel(&self, excess: &str) -> Result<Option<KernelInfo>, anyhow::Error> {
+        self.respond("get_kernel", json!({ "excess": excess }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TappletManifest;
+    use crate::host::LuaTappletHost;
+    use crate::network::NetworkAccess;
+    use crate::reference_api::MemoryTappletApi;
+    use crate::wallet::PERMISSION_SEND_TRANSACTION;
+
+    const CODE: &str = r#"
+        function pay(args)
+            local tx = minotari_send_transaction(args.to, args.amount, 1)
+            minotari_append_data("payments", tx)
+            return tx .. " of " .. #minotari_load_data_entries("payments")
+        end
+    "#;
+
+    fn manifest() -> TappletManifest {
+        manifest_with("")
+    }
+
+    fn manifest_with(extra: &str) -> TappletManifest {
+        let toml = crate::test_utils::manifest_toml("payer", "0.1.0")
+            .replace(r#"methods = ["greet"]"#, r#"methods = ["pay"]"#)
+            .replace("[api.greet]", "[api.pay]")
+            .replace("[api.greet.returns]", "[api.pay.returns]");
+        TappletManifest::from_toml_str(&(toml + extra)).unwrap()
+    }
+
+    struct Ticker;
+
+    impl HttpClient for Ticker {
+        fn send(&self, _: &HttpRequest, _: &HttpLimits) -> Result<HttpResponse, String> {
+            Ok(HttpResponse {
+                status: 200,
+                body: "42".to_string(),
+            })
+        }
+    }
+
+    struct Tip;
+
+    #[async_trait]
+    impl MinotariChainApi for Tip {
+        async fn get_tip_height(&self) -> Result<u64, anyhow::Error> {
+            Ok(7)
+        }
+
+        async fn get_block_header(&self, _: u64) -> Result<Option<BlockHeader>, anyhow::Error> {
+            Ok(None)
+        }
+
+        async fn get_kernel(&self, _: &str) -> Result<Option<KernelInfo>, anyhow::Error> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_record_and_replay() {
+        let ctx = CallContext::user().with_permission(PERMISSION_SEND_TRANSACTION);
+        let args = json!({ "to": "alice", "amount": 5 });
+        let api = MemoryTappletApi::with_balance(Balance {
+            available: 100,
+            ..Default::default()
+        });
+        let recorder = Recorder::new();
+        let mut host =
+            LuaTappletHost::from_string_shared(manifest(), CODE, Arc::new(recorder.wrap(api)))
+                .unwrap()
+                .with_api_v2();
+        let (result, recording) = recorder.record(&mut host, "pay", args.clone(), &ctx).await;
+        let payment = result.unwrap();
+        let functions: Vec<_> = recording
+            .interactions
+            .iter()
+            .map(|interaction| interaction.function.as_str())
+            .collect();
+        assert_eq!(
+            functions,
+            ["send_transaction", "append_data", "load_data_entries"]
+        );
+
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("pay.json");
+        recording.save(&path).unwrap();
+        let recording = CallRecording::load(&path).unwrap();
+
+        let replayer = Replayer::new();
+        let mut host =
+            LuaTappletHost::from_string_shared(manifest(), CODE, Arc::new(replayer.clone()))
+                .unwrap()
+                .with_api_v2();
+        let report = replayer.replay(&mut host, &recording, &ctx).await;
+        assert!(report.is_faithful(), "{:?}", report);
+        assert_eq!(report.result.unwrap(), payment);
+
+        // A tapplet that calls the API differently diverges from the recording
+        let changed = CODE.replace("args.amount, 1", "args.amount, 2");
+        let mut host =
+            LuaTappletHost::from_string_shared(manifest(), &changed, Arc::new(replayer.clone()))
+                .unwrap()
+                .with_api_v2();
+        let report = replayer.replay(&mut host, &recording, &ctx).await;
+        assert!(!report.is_faithful());
+        assert!(
+            report
+                .divergence
+                .unwrap()
+                .starts_with("expected send_transaction")
+        );
+        assert_eq!(report.unused.len(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_record_and_replay_network_and_chain() {
+        let code = r#"
+            function pay()
+                local price = minotari_http_get("https://api.example.com/price")
+                return price.body .. "@" .. minotari_get_tip_height()
+            end
+        "#;
+        let config = manifest_with(
+            "[permissions.network]\nallowed_hosts = [\"api.example.com\"]\n\
+             [permissions.chain]\nread = true\n",
+        );
+        let ctx = CallContext::user();
+        let recorder = Recorder::new();
+        let mut host = LuaTappletHost::from_string_shared(
+            config.clone(),
+            code,
+            Arc::new(recorder.wrap(MemoryTappletApi::new())),
+        )
+        .unwrap()
+        .with_network_access(NetworkAccess::new(Arc::new(recorder.wrap(Ticker))))
+        .with_chain_api(Arc::new(recorder.wrap(Tip)));
+        let (result, recording) = recorder.record(&mut host, "pay", Value::Null, &ctx).await;
+        assert_eq!(result.unwrap(), "42@7");
+        let functions: Vec<_> = recording
+            .interactions
+            .iter()
+            .map(|interaction| interaction.function.as_str())
+            .collect();
+        assert_eq!(functions, ["http_get", "get_tip_height"]);
+
+        let replayer = Replayer::new();
+        let mut host = LuaTappletHost::from_string_shared(config, code, Arc::new(replayer.clone()))
+            .unwrap()
+            .with_network_access(NetworkAccess::new(Arc::new(replayer.clone())))
+            .with_chain_api(Arc::new(replayer.clone()));
+        let report = replayer.replay(&mut host, &recording, &ctx).await;
+        assert!(report.is_faithful(), "{:?}", report);
+    }
+}
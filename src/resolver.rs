@@ -0,0 +1,216 @@
+//! Resolving tapplet dependencies into an install order
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+
+use crate::error::TappletError;
+use crate::model::{TappletManifest, VersionReq, parse_version_req};
+use crate::registry::TappletRegistry;
+
+/// A tapplet chosen by [`resolve`]
+#[derive(Debug, Clone)]
+pub struct ResolvedTapplet {
+    pub manifest: TappletManifest,
+    /// Whether the chosen version is the one already installed
+    pub installed: bool,
+}
+
+/// Choose a version of `name` and of everything it depends on, transitively.
+///
+/// `name` and the names of dependencies are either qualified, `publisher/name`,
+/// or bare names that only one publisher's tapplets have among the installed and
+/// registry tapplets. A bare name several publishers' tapplets have fails with
+/// `AMBIGUOUS_NAME`, instead of whichever comes first being chosen.
+///
+/// Installed tapplets are kept if they satisfy every requirement on them, otherwise
+/// the newest version in the registry satisfying them all is chosen. Yanked
+/// versions are never chosen from the registry. When a
+/// requirement turns up that an already chosen version doesn't satisfy, resolution
+/// starts over taking it into account, so requirements are never dropped even if
+/// the dependent that made them ends up not being chosen.
+///
+/// Returns the tapplets in install order, dependencies before their dependents.
+pub fn resolve(
+    registry: &TappletRegistry,
+    installed: &[TappletManifest],
+    name: &str,
+    version_req: &str,
+) -> Result<Vec<ResolvedTapplet>> {
+    let version_req = parse_version_req(version_req)?;
+    let mut requirements = BTreeMap::new();
+    loop {
+        let mut resolution = Resolution {
+            registry,
+            installed,
+            chosen: BTreeMap::new(),
+            requirements,
+            stack: Vec::new(),
+            order: Vec::new(),
+        };
+        match resolution.visit(name, &version_req, None) {
+            Ok(()) => {
+                return Ok(resolution
+                    .order
+                    .iter()
+                    .map(|key| resolution.chosen[key].clone())
+                    .collect());
+            }
+            // Each retry knows at least one more requirement, so this terminates
+            Err(Visit::Retry) => requirements = resolution.requirements,
+            Err(Visit::Failed(e)) => return Err(e),
+        }
+    }
+}
+
+enum Visit {
+    /// A chosen version turned out not to satisfy a requirement found later
+    Retry,
+    Failed(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for Visit {
+    fn from(e: E) -> Self {
+        Visit::Failed(e.into())
+    }
+}
+
+struct Resolution<'a> {
+    registry: &'a TappletRegistry,
+    installed: &'a [TappletManifest],
+    chosen: BTreeMap<String, ResolvedTapplet>,
+    /// Every requirement seen for a tapplet, with the name of the tapplet requiring it
+    requirements: BTreeMap<String, Vec<(Option<String>, VersionReq)>>,
+    /// Tapplets whose dependencies are being resolved, to detect cycles
+    stack: Vec<String>,
+    order: Vec<String>,
+}
+
+impl Resolution<'_> {
+    fn visit(
+        &mut self,
+        name: &str,
+        req: &VersionReq,
+        required_by: Option<&str>,
+    ) -> Result<(), Visit> {
+        // Keyed by qualified name, where `my-tapplet` and `my_tapplet` are the same tapplet
+        let key = self.qualify(name)?;
+        if let Some(start) = self.stack.iter().position(|visiting| *visiting == key) {
+            let mut cycle = self.stack[start..].to_vec();
+            cycle.push(key);
+            return Err(TappletError::DependencyCycle { cycle }.into());
+        }
+        let requirement = (required_by.map(str::to_string), req.clone());
+        let requirements = self.requirements.entry(key.clone()).or_default();
+        if !requirements.contains(&requirement) {
+            requirements.push(requirement);
+        }
+
+        if let Some(chosen) = self.chosen.get(&key) {
+            if !chosen.manifest.satisfies(req) {
+                return Err(Visit::Retry);
+            }
+            return Ok(());
+        }
+
+        let satisfies_all = |tapplet: &TappletManifest| {
+            tapplet.name_matches(&key)
+                && self.requirements[&key]
+                    .iter()
+                    .all(|(_, req)| tapplet.satisfies(req))
+        };
+        let chosen = match self.installed.iter().find(|tapplet| satisfies_all(tapplet)) {
+            Some(manifest) => ResolvedTapplet {
+                manifest: manifest.clone(),
+                installed: true,
+            },
+            None => {
+                let manifest = self
+                    .registry
+                    .versions_of(&key)?
+                    .into_iter()
+                    .rev()
+                    .find(|tapplet| !tapplet.yanked && satisfies_all(tapplet));
+                match manifest {
+                    Some(manifest) => ResolvedTapplet {
+                        manifest: manifest.clone(),
+                        installed: false,
+                    },
+                    None if self.registry.contains(&key) => {
+                        return Err(self.conflict(name, &key).into());
+                    }
+                    None => {
+                        let err = anyhow::Error::from(TappletError::TappletNotFound {
+                            name: name.to_string(),
+                        });
+                        return Err(err
+                            .context(match required_by {
+                                Some(dependent) => format!("Dependency of '{}'", dependent),
+                                None => format!("Not in registry '{}'", self.registry.name),
+                            })
+                            .into());
+                    }
+                }
+            }
+        };
+
+        let dependencies = chosen.manifest.dependencies.clone();
+        let dependent = chosen.manifest.name.clone();
+        self.chosen.insert(key.clone(), chosen);
+        self.stack.push(key.clone());
+        for (dependency, dependency_req) in &dependencies {
+            let dependency_req = parse_version_req(dependency_req)
+                .with_context(|| format!("Invalid dependency of '{}'", dependent))?;
+            self.visit(dependency, &dependency_req, Some(&dependent))?;
+        }
+        self.stack.pop();
+        self.order.push(key);
+        Ok(())
+    }
+
+    /// The qualified name of the tapplet `name` refers to, with `-` in the name
+    /// replaced by `_`, or the bare name if no tapplet has it
+    fn qualify(&self, name: &str) -> Result<String> {
+        let (publisher, bare) = match name.split_once('/') {
+            Some((publisher, bare)) => (Some(publisher), bare),
+            None => (None, name),
+        };
+        let bare = bare.replace('-', "_");
+        if let Some(publisher) = publisher {
+            return Ok(format!("{}/{}", publisher, bare));
+        }
+
+        let mut publishers: Vec<_> = self
+            .installed
+            .iter()
+            .filter(|tapplet| tapplet.name_matches(name))
+            .map(|tapplet| tapplet.publisher.as_str())
+            .chain(self.registry.publishers_of(name))
+            .collect();
+        publishers.sort();
+        publishers.dedup();
+        match publishers.as_slice() {
+            [] => Ok(bare),
+            [publisher] => Ok(format!("{}/{}", publisher, bare)),
+            publishers => Err(TappletError::AmbiguousName {
+                name: name.to_string(),
+                publishers: publishers.iter().map(|p| p.to_string()).collect(),
+            }
+            .into()),
+        }
+    }
+
+    fn conflict(&self, name: &str, key: &str) -> TappletError {
+        let requirements = self.requirements[key]
+            .iter()
+            .map(|(required_by, req)| match required_by {
+                Some(dependent) => format!("{} requires {}", dependent, req),
+                None => format!("{} was requested", req),
+            })
+            .collect();
+        TappletError::DependencyConflict {
+            name: name.to_string(),
+            requirements,
+        }
+    }
+}
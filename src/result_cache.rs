@@ -0,0 +1,260 @@
+//! Memoized results of pure tapplet methods.
+//!
+//! Methods marked `pure = true` in the manifest promise that their result only
+//! depends on their arguments. Hosts given a [`ResultCache`] with
+//! `with_result_cache` answer repeated calls of those methods with the same
+//! arguments from the cache, without running the tapplet. Calls are still
+//! checked against the method's permissions first, and failed calls aren't
+//! cached.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::TappletManifest;
+
+/// Tapplet name and version, method and arguments of a call
+type Key = (String, String, String, String);
+
+struct Entry {
+    value: Value,
+    stored: Instant,
+    /// Position in [`State::recency`]
+    used: u64,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<Key, Entry>,
+    /// Keys by when they were last used, oldest first
+    recency: BTreeMap<u64, Key>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl State {
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used);
+        }
+    }
+}
+
+/// How often a [`ResultCache`] could answer a call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// LRU cache of pure method results, optionally expiring them after a while.
+/// Clones share the cached results, so one cache can serve many hosts.
+#[derive(Clone)]
+pub struct ResultCache {
+    capacity: usize,
+    ttl: Option<Duration>,
+    state: Arc<Mutex<State>>,
+}
+
+impl ResultCache {
+    /// Keep the results of up to `capacity` calls, dropping the least recently
+    /// used ones first
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ttl: None,
+            state: Arc::default(),
+        }
+    }
+
+    /// Run the method again once its cached result is older than `ttl`
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            entries: state.entries.len(),
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.recency.clear();
+    }
+
+    /// Drop the results of every version of a tapplet, e.g. because its code
+    /// changed. Hosts do this when they are reloaded. `tapplet` is a
+    /// [qualified name](TappletManifest::qualified_name), or a name to drop the
+    /// tapplets of that name of every publisher.
+    pub fn invalidate(&self, tapplet: &str) {
+        let mut state = self.state.lock().unwrap();
+        let keys: Vec<_> = state
+            .entries
+            .keys()
+            .filter(|(cached, _, _, _)| is_tapplet(cached, tapplet))
+            .cloned()
+            .collect();
+        for key in &keys {
+            state.remove(key);
+        }
+    }
+
+    /// The cached result of a call of `method` of `tapplet` with `args`
+    pub(crate) fn get(
+        &self,
+        tapplet: &TappletManifest,
+        method: &str,
+        args: &Value,
+    ) -> Option<Value> {
+        self.get_at(&key(tapplet, method, args), Instant::now())
+    }
+
+    fn get_at(&self, key: &Key, now: Instant) -> Option<Value> {
+        let mut state = self.state.lock().unwrap();
+        let expired = match (state.entries.get(key), self.ttl) {
+            (None, _) => {
+                state.misses += 1;
+                return None;
+            }
+            (Some(entry), Some(ttl)) => now.saturating_duration_since(entry.stored) >= ttl,
+            (Some(_), None) => false,
+        };
+        if expired {
+            state.remove(key);
+            state.misses += 1;
+            return None;
+        }
+        state.hits += 1;
+        state.clock += 1;
+        let used = state.clock;
+        let entry = state.entries.get_mut(key)?;
+        let previous = std::mem::replace(&mut entry.used, used);
+        let value = entry.value.clone();
+        state.recency.remove(&previous);
+        state.recency.insert(used, key.clone());
+        Some(value)
+    }
+
+    /// Remember the result of a call
+    pub(crate) fn insert(
+        &self,
+        tapplet: &TappletManifest,
+        method: &str,
+        args: &Value,
+        value: &Value,
+    ) {
+        self.insert_at(key(tapplet, method, args), value.clone(), Instant::now());
+    }
+
+    fn insert_at(&self, key: Key, value: Value, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.remove(&key);
+        while state.entries.len() >= self.capacity {
+            let Some((_, oldest)) = state.recency.pop_first() else {
+                break;
+            };
+            state.entries.remove(&oldest);
+        }
+        state.clock += 1;
+        let used = state.clock;
+        state.recency.insert(used, key.clone());
+        state.entries.insert(
+            key,
+            Entry {
+                value,
+                stored: now,
+                used,
+            },
+        );
+    }
+}
+
+impl std::fmt::Debug for ResultCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResultCache")
+            .field("capacity", &self.capacity)
+            .field("ttl", &self.ttl)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// Whether `qualified`, a qualified name, is `tapplet`'s, given by qualified
+/// name or by name
+pub(crate) fn is_tapplet(qualified: &str, tapplet: &str) -> bool {
+    qualified == tapplet
+        || qualified
+            .split_once('/')
+            .is_some_and(|(_, name)| name == tapplet.replace("-", "_"))
+}
+
+fn key(tapplet: &TappletManifest, method: &str, args: &Value) -> Key {
+    // Object keys serialize sorted, so equal arguments give equal keys
+    (
+        tapplet.qualified_name(),
+        tapplet.version.clone(),
+        method.to_string(),
+        args.to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_lru_and_ttl() {
+        let cache = ResultCache::new(2).with_ttl(Duration::from_secs(60));
+        let toml = crate::test_utils::manifest_toml("math", "0.1.0");
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let start = Instant::now();
+        let call = |n: u64| key(&config, "square", &json!({ "n": n }));
+
+        cache.insert_at(call(1), json!(1), start);
+        cache.insert_at(call(2), json!(4), start);
+        // Using 1 makes 2 the least recently used, so it goes first
+        assert_eq!(cache.get_at(&call(1), start), Some(json!(1)));
+        cache.insert_at(call(3), json!(9), start);
+        assert_eq!(cache.get_at(&call(2), start), None);
+        assert_eq!(cache.get_at(&call(3), start), Some(json!(9)));
+
+        // Results expire after the TTL
+        let later = start + Duration::from_secs(61);
+        assert_eq!(cache.get_at(&call(1), later), None);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 2,
+                entries: 1
+            }
+        );
+
+        // Another publisher's tapplet of the same name has its own results
+        let mut other = config.clone();
+        other.publisher = "other_publisher".to_string();
+        let args = json!({ "n": 3 });
+        assert_eq!(cache.get_at(&key(&other, "square", &args), start), None);
+        cache.insert_at(key(&other, "square", &args), json!(-9), start);
+        cache.invalidate("other_publisher/math");
+        assert_eq!(cache.get_at(&call(3), start), Some(json!(9)));
+
+        cache.invalidate("math");
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
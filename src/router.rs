@@ -0,0 +1,378 @@
+//! Calls from one running tapplet to another.
+//!
+//! Hosts registered with a [`TappletRouter`] can call each other through the
+//! `minotari_call_tapplet(name, method, args_json)` host function, once given the
+//! router with [`crate::host::LuaTappletHost::with_router`]. A tapplet may only call
+//! the tapplets listed in its manifest's `[dependencies]`, in a version satisfying
+//! the requirement, and the callee sees the call as coming from
+//! [`crate::call_context::Caller::Tapplet`].
+//!
+//! A tapplet that is already handling a call can't be called again until that call
+//! returns, and chains of calls are limited to [`DEFAULT_MAX_CALL_DEPTH`] tapplets.
+//!
+//! Only Lua tapplets can make calls. WASM hosts can be registered and called, but
+//! a module importing `minotari_call_tapplet` fails to load with
+//! [`HostError::Unsupported`]: the router lives on one thread, while WASM hosts
+//! and their host functions may be moved between threads.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::{Rc, Weak};
+
+use serde_json::Value;
+
+use crate::call_context::CallContext;
+use crate::host::{HostError, TappletRunner};
+use crate::model::{TappletManifest, parse_version_req};
+
+/// How many tapplets a chain of calls may pass through, including the first
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 8;
+
+/// Routes calls between registered tapplet hosts.
+///
+/// Cloning a router gives another handle to the same set of tapplets.
+#[derive(Clone)]
+pub struct TappletRouter {
+    state: Rc<RouterState>,
+}
+
+/// A router handle held by a host, weak so that hosts registered with the router
+/// don't keep it alive
+#[derive(Clone)]
+pub(crate) struct RouterHandle {
+    state: Weak<RouterState>,
+}
+
+struct RouterState {
+    /// Hosts by name with `-` replaced by `_`. A host is taken out while it handles a call.
+    tapplets: RefCell<BTreeMap<String, Option<Box<dyn TappletRunner>>>>,
+    manifests: RefCell<BTreeMap<String, TappletManifest>>,
+    /// Tapplets currently handling a call, outermost first
+    stack: RefCell<Vec<String>>,
+    max_depth: usize,
+}
+
+impl Default for TappletRouter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TappletRouter {
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RouterState {
+                tapplets: RefCell::default(),
+                manifests: RefCell::default(),
+                stack: RefCell::default(),
+                max_depth: DEFAULT_MAX_CALL_DEPTH,
+            }),
+        }
+    }
+
+    /// Limit chains of calls to `max_depth` tapplets. Must be set before hosts are
+    /// given the router.
+    pub fn with_max_depth(self, max_depth: usize) -> Self {
+        let state = Rc::try_unwrap(self.state)
+            .ok()
+            .expect("max depth is set before the router is shared");
+        Self {
+            state: Rc::new(RouterState { max_depth, ..state }),
+        }
+    }
+
+    /// Add a host, replacing any registered under the same name
+    pub fn register(&self, host: impl TappletRunner + 'static) {
+        let manifest = host.manifest().clone();
+        let key = key(&manifest.name);
+        self.state
+            .tapplets
+            .borrow_mut()
+            .insert(key.clone(), Some(Box::new(host)));
+        self.state.manifests.borrow_mut().insert(key, manifest);
+    }
+
+    /// Names of the registered tapplets
+    pub fn tapplets(&self) -> Vec<String> {
+        self.state
+            .manifests
+            .borrow()
+            .values()
+            .map(|manifest| manifest.name.clone())
+            .collect()
+    }
+
+    /// Call a method of a registered tapplet from outside any tapplet
+    pub async fn call(
+        &self,
+        name: &str,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError> {
+        self.state.call(name, method, args, context).await
+    }
+
+    pub(crate) fn handle(&self) -> RouterHandle {
+        RouterHandle {
+            state: Rc::downgrade(&self.state),
+        }
+    }
+}
+
+impl RouterHandle {
+    /// Call `name` on behalf of the tapplet `caller`, which must depend on it
+    pub(crate) async fn call_from(
+        &self,
+        caller: &TappletManifest,
+        name: &str,
+        method: &str,
+        args: Value,
+    ) -> Result<Value, HostError> {
+        let state = self.state.upgrade().ok_or_else(|| {
+            HostError::ExecutionError("the tapplet router has been dropped".to_string())
+        })?;
+        let Some((_, requirement)) = caller
+            .dependencies
+            .iter()
+            .find(|(dependency, _)| key(dependency) == key(name))
+        else {
+            return Err(HostError::PermissionDenied(format!(
+                "{} can't call {} without declaring it as a dependency",
+                caller.name, name
+            )));
+        };
+        if let Some(manifest) = state.manifests.borrow().get(&key(name)) {
+            let requirement = parse_version_req(requirement)
+                .map_err(|e| HostError::PermissionDenied(format!("{:#}", e)))?;
+            if !manifest.satisfies(&requirement) {
+                return Err(HostError::PermissionDenied(format!(
+                    "{} requires {} {}, but version {} is loaded",
+                    caller.name, name, requirement, manifest.version
+                )));
+            }
+        }
+        let context = CallContext::tapplet(&caller.name);
+        state.call(name, method, args, &context).await
+    }
+}
+
+impl RouterState {
+    async fn call(
+        &self,
+        name: &str,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<Value, HostError> {
+        let key = key(name);
+        if self.stack.borrow().contains(&key) {
+            return Err(HostError::ReentrantCall(format!(
+                "{} is already handling a call",
+                name
+            )));
+        }
+        if self.stack.borrow().len() >= self.max_depth {
+            return Err(HostError::CallDepthExceeded(format!(
+                "calling {} would exceed {} nested tapplet calls",
+                name, self.max_depth
+            )));
+        }
+        let host = self
+            .tapplets
+            .borrow_mut()
+            .get_mut(&key)
+            .ok_or_else(|| HostError::TappletNotFound(name.to_string()))?
+            .take()
+            .ok_or_else(|| HostError::ReentrantCall(format!("{} is busy", name)))?;
+
+        self.stack.borrow_mut().push(key.clone());
+        let mut running = Running {
+            state: self,
+            key,
+            host: Some(host),
+        };
+        let host = running.host.as_mut().expect("the host is put back on drop");
+        host.call(method, args, context).await
+    }
+}
+
+/// A host taken out to handle a call. Dropping it, when the call returns or its
+/// future is dropped or panics, puts the host back and takes it off the stack.
+struct Running<'a> {
+    state: &'a RouterState,
+    key: String,
+    host: Option<Box<dyn TappletRunner>>,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let mut stack = self.state.stack.borrow_mut();
+        if let Some(position) = stack.iter().rposition(|key| *key == self.key) {
+            stack.remove(position);
+        }
+        // The host may have been replaced while it was running
+        if let Some(host) = self.host.take() {
+            self.state
+                .tapplets
+                .borrow_mut()
+                .entry(self.key.clone())
+                .or_default()
+                .get_or_insert(host);
+        }
+    }
+}
+
+/// `my-tapplet` and `my_tapplet` are the same tapplet
+fn key(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::host::{LuaTappletHost, WasmTappletHost};
+    use crate::model::{MethodDefinition, ParamType};
+    use crate::reference_api::MemoryTappletApi;
+
+    /// A tapplet whose `greet` calls `greet` on the tapplet named by its `target`
+    /// argument, or greets `name` if there is none
+    fn register(router: &TappletRouter, name: &str, dependencies: &[(&str, &str)]) {
+        let mut config =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml(name, "1.0.0"))
+                .unwrap();
+        for (dependency, req) in dependencies {
+            config
+                .dependencies
+                .insert(dependency.to_string(), req.to_string());
+        }
+        let code = r#"
+            function greet(args)
+                if args.target then
+                    return minotari_call_tapplet(args.target, "greet", args.forward or "{}")
+                end
+                return "Hello from " .. args.name
+            end
+        "#;
+        let api = Arc::new(MemoryTappletApi::new());
+        let host = LuaTappletHost::from_string_shared(config, code, api)
+            .unwrap()
+            .with_router(router);
+        router.register(host);
+    }
+
+    async fn greet(router: &TappletRouter, name: &str, args: Value) -> Result<Value, HostError> {
+        router.call(name, "greet", args, &CallContext::user()).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_call_between_tapplets() {
+        let router = TappletRouter::new();
+        register(&router, "contacts", &[]);
+        register(
+            &router,
+            "app",
+            &[("contacts", "^1"), ("echo", "*"), ("wallet", "*")],
+        );
+        register(&router, "outdated", &[("contacts", "^2")]);
+        register(&router, "echo", &[("app", "*")]);
+        assert_eq!(router.tapplets(), ["app", "contacts", "echo", "outdated"]);
+
+        let args = json!({"target": "contacts", "forward": r#"{"name": "app"}"#});
+        assert_eq!(
+            greet(&router, "app", args.clone()).await.unwrap(),
+            "Hello from app"
+        );
+
+        // Only declared dependencies, in a version satisfying the requirement
+        let err = greet(&router, "outdated", args).await.unwrap_err();
+        assert_eq!(err.code(), "PERMISSION_DENIED");
+        let args = json!({"target": "app"});
+        let err = greet(&router, "contacts", args).await.unwrap_err();
+        assert_eq!(err.code(), "PERMISSION_DENIED");
+        let err = greet(&router, "app", json!({"target": "wallet"}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "TAPPLET_NOT_FOUND");
+
+        // echo -> app -> echo
+        let forward = json!({"target": "echo"}).to_string();
+        let args = json!({"target": "app", "forward": forward});
+        let err = greet(&router, "echo", args).await.unwrap_err();
+        assert_eq!(err.code(), "REENTRANT_CALL");
+
+        // Hosts are back in place after failed calls
+        let args = json!({"target": "contacts", "forward": r#"{"name": "app"}"#});
+        assert!(greet(&router, "app", args.clone()).await.is_ok());
+
+        let router = TappletRouter::new().with_max_depth(1);
+        register(&router, "contacts", &[]);
+        register(&router, "app", &[("contacts", "^1")]);
+        let err = greet(&router, "app", args).await.unwrap_err();
+        assert_eq!(err.code(), "CALL_DEPTH_EXCEEDED");
+    }
+
+    /// A tapplet whose `stall` method never returns
+    struct Staller(TappletManifest);
+
+    #[async_trait::async_trait(?Send)]
+    impl TappletRunner for Staller {
+        fn manifest(&self) -> &TappletManifest {
+            &self.0
+        }
+
+        async fn call(
+            &mut self,
+            method: &str,
+            _: Value,
+            _: &CallContext,
+        ) -> Result<Value, HostError> {
+            if method == "stall" {
+                std::future::pending::<()>().await;
+            }
+            Ok(Value::from(method))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dropped_call_puts_host_back() {
+        let router = TappletRouter::new();
+        let manifest =
+            TappletManifest::from_toml_str(&crate::test_utils::manifest_toml("slow", "1.0.0"))
+                .unwrap();
+        router.register(Staller(manifest));
+        let context = CallContext::user();
+
+        let call = router.call("slow", "stall", Value::Null, &context);
+        let timeout = tokio::time::timeout(std::time::Duration::from_millis(10), call);
+        assert!(timeout.await.is_err());
+
+        assert!(router.state.stack.borrow().is_empty());
+        let result = router.call("slow", "ping", Value::Null, &context);
+        assert_eq!(result.await.unwrap(), "ping");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_wasm_tapplets() {
+        let router = TappletRouter::new();
+        let config = TappletManifest::builder("counter", "1.0.0")
+            .with_method("greet", MethodDefinition::new("", ParamType::Any, ""))
+            .build_unchecked();
+        let wat = r#"(module (func (export "greet") (result i32) (i32.const 1)))"#;
+        router.register(WasmTappletHost::from_wat(config.clone(), wat).unwrap());
+        register(&router, "app", &[("counter", "*")]);
+
+        // WASM tapplets can be called, but can't call others
+        let args = json!({"target": "counter", "forward": "[]"});
+        assert_eq!(greet(&router, "app", args).await.unwrap(), 1);
+        let caller = r#"(module
+            (import "minotari" "minotari_call_tapplet"
+              (func (param i32 i32 i32 i32 i32 i32) (result i32))))"#;
+        let err = WasmTappletHost::from_wat(config, caller).err().unwrap();
+        assert_eq!(err.code(), "UNSUPPORTED");
+    }
+}
@@ -0,0 +1,509 @@
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+
+use mlua::{Lua, Table, Value};
+
+/// Globals removed from every Lua tapplet environment unless explicitly allowed.
+///
+/// Luau already ships without `io` and with a reduced `os`, but tapplets should
+/// not depend on the host's Lua build for this, and `loadstring`/`load` would
+/// let a script run code that was never part of the reviewed tapplet. The
+/// built-in `require` searches `LUA_PATH` and the working directory, so it is
+/// replaced by one limited to the module root, see [`SandboxOptions::with_module_root`].
+pub const DENIED_GLOBALS: &[&str] = &[
+    "os",
+    "io",
+    "debug",
+    "loadstring",
+    "load",
+    "dofile",
+    "loadfile",
+    "getfenv",
+    "setfenv",
+    "require",
+    "package",
+];
+
+/// How a Lua tapplet's environment is restricted
+#[derive(Debug, Clone, Default)]
+pub struct SandboxOptions {
+    allowed: BTreeSet<String>,
+    module_root: Option<PathBuf>,
+    deterministic: bool,
+}
+
+impl SandboxOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep one of the [`DENIED_GLOBALS`] available to the script, e.g. `os` for `os.time`
+    pub fn with_allowed_global<S: Into<String>>(mut self, name: S) -> Self {
+        self.allowed.insert(name.into());
+        self
+    }
+
+    /// Let the script `require` Lua modules from this directory, and nowhere else.
+    ///
+    /// `require("utils.format")` loads `utils/format.lua` (or `.luau`) relative to
+    /// the root. Hosts created from a script file use the script's directory.
+    pub fn with_module_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.module_root = Some(root.into());
+        self
+    }
+
+    /// Make the script behave the same in every host: `os` is removed even if
+    /// allowed, so it can't read the clock, `math.random` is seeded with 0
+    /// when the script is loaded, and `minotari_now` and `minotari_random_bytes`
+    /// are deterministic
+    pub fn with_deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    pub fn module_root(&self) -> Option<&Path> {
+        self.module_root.as_deref()
+    }
+
+    pub fn is_allowed(&self, name: &str) -> bool {
+        self.allowed.contains(name) && !(self.deterministic && name == "os")
+    }
+
+    /// Globals that will be removed from the environment
+    pub fn denied_globals(&self) -> impl Iterator<Item = &'static str> + '_ {
+        DENIED_GLOBALS
+            .iter()
+            .copied()
+            .filter(|name| !self.is_allowed(name))
+    }
+
+    /// Remove denied globals and enable Luau's sandbox mode.
+    ///
+    /// Must run before any tapplet code is loaded: once sandboxed the original
+    /// globals are read-only and can no longer be removed.
+    pub(crate) fn apply(&self, lua: &Lua) -> mlua::Result<()> {
+        let globals = lua.globals();
+        for name in self.denied_globals() {
+            globals.raw_set(name, Value::Nil)?;
+        }
+        if self.deterministic {
+            let math: Table = globals.get("math")?;
+            math.get::<mlua::Function>("randomseed")?.call::<()>(0)?;
+        }
+        lua.sandbox(true)?;
+        if let Some(root) = &self.module_root {
+            install_require(lua, root)?;
+        }
+        Ok(())
+    }
+}
+
+/// A problem [`check_script`] found in a Lua script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptIssue {
+    /// 1-based line of the problem, if known
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScriptIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Compile `code` in a throwaway sandbox without running it, and report syntax
+/// errors and references to globals that `options` denies.
+///
+/// Globals are found by scanning the source, so only direct references like
+/// `os.time()` are caught; anything subtler still fails when the script runs.
+/// `require` is only denied if `options` has no module root.
+pub fn check_script(code: &str, options: &SandboxOptions) -> Vec<ScriptIssue> {
+    let mut issues = Vec::new();
+    let lua = Lua::new();
+    // The module root is only needed to run `require`, not to compile
+    let compile_options = SandboxOptions {
+        allowed: options.allowed.clone(),
+        module_root: None,
+        deterministic: options.deterministic,
+    };
+    let compiled = compile_options
+        .apply(&lua)
+        .and_then(|()| lua.load(code).set_name("=script").into_function());
+    if let Err(e) = compiled {
+        issues.push(syntax_issue(&e));
+    }
+
+    let denied: Vec<_> = options
+        .denied_globals()
+        .filter(|name| *name != "require" || options.module_root.is_none())
+        .collect();
+    issues.extend(denied_global_references(code, &denied));
+    issues
+}
+
+/// Luau prefixes syntax errors with `<chunk name>:<line>: `
+fn syntax_issue(err: &mlua::Error) -> ScriptIssue {
+    let message = match err {
+        mlua::Error::SyntaxError { message, .. } => message.clone(),
+        other => other.to_string(),
+    };
+    if let Some(rest) = message.strip_prefix("script:")
+        && let Some((line, message)) = rest.split_once(": ")
+        && let Ok(line) = line.parse()
+    {
+        return ScriptIssue {
+            line: Some(line),
+            message: message.to_string(),
+        };
+    }
+    ScriptIssue {
+        line: None,
+        message,
+    }
+}
+
+/// Find identifiers in `denied` that are used as globals, skipping comments,
+/// strings, fields (`t.os`), methods, table keys (`{ debug = true }`) and names
+/// declared `local`, as loop variables or as function parameters in an
+/// enclosing block
+fn denied_global_references(code: &str, denied: &[&str]) -> Vec<ScriptIssue> {
+    let bytes = code.as_bytes();
+    let mut issues = Vec::new();
+    // The locals of each open block, innermost last
+    let mut scopes = vec![BTreeSet::new()];
+    let mut line = 1;
+    // The last character before the current token that isn't part of a name or value
+    let mut previous = 0u8;
+    // Inside `local a, b` or `for a, b`
+    let mut declaring = false;
+    // After `for` or `while`, whose `do` doesn't open another block
+    let mut loop_header = false;
+    // After `function`, until the `(` that opens its parameters and body
+    let mut function_header = false;
+    // Inside the parameter list of a function
+    let mut parameters = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = match long_bracket_level(bytes, i + 2) {
+                    Some(level) => skip_long_bracket(bytes, i + 2, level),
+                    None => bytes[i..]
+                        .iter()
+                        .position(|&b| b == b'\n')
+                        .map_or(bytes.len(), |end| i + end),
+                };
+            }
+            b'[' if long_bracket_level(bytes, i).is_some() => {
+                i = skip_long_bracket(bytes, i, long_bracket_level(bytes, i).unwrap_or(0));
+                previous = b'"';
+            }
+            quote @ (b'"' | b'\'' | b'`') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i = (i + 1).min(bytes.len());
+                previous = b'"';
+            }
+            b if b == b'_' || b.is_ascii_alphanumeric() => {
+                while i < bytes.len() && (bytes[i] == b'_' || bytes[i].is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+                let word = &code[start..i];
+                let next = bytes[i..].iter().position(|b| !b.is_ascii_whitespace());
+                let next = |offset: usize| next.and_then(|n| bytes.get(i + n + offset)).copied();
+                if b.is_ascii_digit() {
+                    // Numbers like `0x1F` or `1e10`, and `1.5`
+                    while i < bytes.len() && bytes[i] == b'.' {
+                        i += 1;
+                        while i < bytes.len() && bytes[i].is_ascii_alphanumeric() {
+                            i += 1;
+                        }
+                    }
+                } else if word == "local" {
+                    declaring = true;
+                } else if word == "function" {
+                    function_header = true;
+                } else if parameters {
+                    scopes.last_mut().unwrap().insert(word);
+                } else if declaring {
+                    scopes.last_mut().unwrap().insert(word);
+                    declaring = next(0) == Some(b',');
+                } else {
+                    match word {
+                        "do" if loop_header => loop_header = false,
+                        "do" | "then" | "repeat" => scopes.push(BTreeSet::new()),
+                        "for" | "while" => {
+                            scopes.push(BTreeSet::new());
+                            loop_header = true;
+                            declaring = word == "for";
+                        }
+                        "else" => {
+                            close_scope(&mut scopes);
+                            scopes.push(BTreeSet::new());
+                        }
+                        "elseif" | "end" | "until" => close_scope(&mut scopes),
+                        _ if denied.contains(&word)
+                            && !scopes.iter().any(|locals| locals.contains(word))
+                            && !is_field_or_key(previous, next(0), next(1)) =>
+                        {
+                            issues.push(ScriptIssue {
+                                line: Some(line),
+                                message: format!("uses the denied global '{}'", word),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                previous = b'a';
+            }
+            b if b.is_ascii_whitespace() => i += 1,
+            b => {
+                if b == b'(' && function_header {
+                    scopes.push(BTreeSet::new());
+                    function_header = false;
+                    parameters = true;
+                } else if b == b')' {
+                    parameters = false;
+                }
+                previous = b;
+                i += 1;
+            }
+        }
+        line += bytes[start..i].iter().filter(|&&b| b == b'\n').count();
+    }
+    issues
+}
+
+/// Leave a block, keeping the chunk's own scope when `end`s don't match up
+fn close_scope(scopes: &mut Vec<BTreeSet<&str>>) {
+    if scopes.len() > 1 {
+        scopes.pop();
+    }
+}
+
+/// Whether a name is a field or method (`t.os`, `t:os()`) or a table key (`{ os = 1 }`),
+/// from the character before it and the first two non-whitespace characters after it
+fn is_field_or_key(previous: u8, next: Option<u8>, after_next: Option<u8>) -> bool {
+    matches!(previous, b'.' | b':')
+        || (matches!(previous, b'{' | b',' | b';')
+            && next == Some(b'=')
+            && after_next != Some(b'='))
+}
+
+/// The level of the long bracket (`[[`, `[==[`, ...) opening at `i`, if there is one
+fn long_bracket_level(bytes: &[u8], i: usize) -> Option<usize> {
+    if bytes.get(i) != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[i + 1..].iter().take_while(|&&b| b == b'=').count();
+    (bytes.get(i + 1 + level) == Some(&b'[')).then_some(level)
+}
+
+/// The index just past the long string or comment opening at `i`
+fn skip_long_bracket(bytes: &[u8], i: usize, level: usize) -> usize {
+    let close = format!("]{}]", "=".repeat(level));
+    let body = i + level + 2;
+    bytes[body..]
+        .windows(close.len())
+        .position(|window| window == close.as_bytes())
+        .map_or(bytes.len(), |end| body + end + close.len())
+}
+
+/// Replace `require` with one that only loads modules below `root`
+fn install_require(lua: &Lua, root: &Path) -> mlua::Result<()> {
+    let root = root.canonicalize().map_err(|e| {
+        mlua::Error::runtime(format!("Invalid module root {}: {}", root.display(), e))
+    })?;
+    // Module name -> result, or `false` while the module is still loading
+    let loaded: Table = lua.create_table()?;
+    let require = lua.create_function(move |lua, name: String| {
+        match loaded.raw_get::<Value>(name.as_str())? {
+            Value::Nil => {}
+            Value::Boolean(false) => {
+                return Err(mlua::Error::runtime(format!(
+                    "circular require of module '{}'",
+                    name
+                )));
+            }
+            module => return Ok(module),
+        }
+        let path = resolve_module(&root, &name)?;
+        let code = std::fs::read_to_string(&path).map_err(|e| {
+            mlua::Error::runtime(format!("failed to read module '{}': {}", name, e))
+        })?;
+
+        loaded.raw_set(name.as_str(), false)?;
+        let result = lua
+            .load(code)
+            .set_name(format!(
+                "@{}",
+                path.strip_prefix(&root).unwrap_or(&path).display()
+            ))
+            .call::<Value>(name.as_str());
+        let module = match result {
+            Ok(Value::Nil) => Value::Boolean(true),
+            Ok(module) => module,
+            Err(e) => {
+                loaded.raw_set(name.as_str(), Value::Nil)?;
+                return Err(e);
+            }
+        };
+        loaded.raw_set(name.as_str(), &module)?;
+        Ok(module)
+    })?;
+    lua.globals().set("require", require)
+}
+
+/// Find the file for a module name like `utils.format`, refusing anything outside `root`
+fn resolve_module(root: &Path, name: &str) -> mlua::Result<PathBuf> {
+    let not_found = || mlua::Error::runtime(format!("module '{}' not found", name));
+    let relative: PathBuf = name.split('.').collect();
+    let is_plain = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if name.is_empty() || !is_plain || name.split('.').any(str::is_empty) {
+        return Err(not_found());
+    }
+    for extension in ["luau", "lua"] {
+        let Ok(path) = root
+            .join(&relative)
+            .with_extension(extension)
+            .canonicalize()
+        else {
+            continue;
+        };
+        // Symlinks could otherwise point outside the tapplet directory
+        if path.starts_with(root) && path.is_file() {
+            return Ok(path);
+        }
+    }
+    Err(not_found())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowlist() {
+        let options = SandboxOptions::new().with_allowed_global("os");
+        assert!(options.denied_globals().all(|name| name != "os"));
+        assert!(options.denied_globals().any(|name| name == "io"));
+
+        let lua = Lua::new();
+        options.apply(&lua).unwrap();
+        let os: Value = lua.globals().get("os").unwrap();
+        let loadstring: Value = lua.globals().get("loadstring").unwrap();
+        assert!(!os.is_nil());
+        assert!(loadstring.is_nil());
+    }
+
+    #[test]
+    fn test_require_is_limited_to_module_root() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("tapplet");
+        std::fs::create_dir_all(root.join("utils")).unwrap();
+        std::fs::write(
+            root.join("utils/format.lua"),
+            "return { greet = function(name) return 'Hello, ' .. name end }",
+        )
+        .unwrap();
+        std::fs::write(root.join("loop.lua"), "return require('loop')").unwrap();
+        std::fs::write(temp.path().join("outside.lua"), "return 1").unwrap();
+
+        let lua = Lua::new();
+        SandboxOptions::new()
+            .with_module_root(&root)
+            .apply(&lua)
+            .unwrap();
+        let greeting: String = lua
+            .load("return require('utils.format').greet('Alice')")
+            .eval()
+            .unwrap();
+        assert_eq!(greeting, "Hello, Alice");
+        // Modules are only loaded once
+        let same: bool = lua
+            .load("return require('utils.format') == require('utils.format')")
+            .eval()
+            .unwrap();
+        assert!(same);
+
+        for name in ["../outside", "..outside", "/etc/passwd", "missing", "loop"] {
+            let code = format!("return require({:?})", name);
+            assert!(lua.load(&code).exec().is_err(), "{} should not load", name);
+        }
+
+        // Without a module root there is no require at all
+        let lua = Lua::new();
+        SandboxOptions::new().apply(&lua).unwrap();
+        assert!(lua.load("return require('utils.format')").exec().is_err());
+    }
+
+    #[test]
+    fn test_check_script() {
+        let options = SandboxOptions::new();
+        let clean = r#"
+-- os.exit() in a comment
+--[==[ io.open() ]==]
+local debug = true
+local config = { os = "linux", io = 1 }
+function greet(t) return "os: " .. config.os .. t:os() .. [[ require('x') ]] end
+"#;
+        assert_eq!(check_script(clean, &options), []);
+
+        let issues = check_script("function f()\n  return os.time()\nend", &options);
+        assert_eq!(
+            issues,
+            [ScriptIssue {
+                line: Some(2),
+                message: "uses the denied global 'os'".to_string()
+            }]
+        );
+        assert!(
+            check_script(
+                "return os.time()",
+                &options.clone().with_allowed_global("os")
+            )
+            .is_empty()
+        );
+
+        // Locals only hide globals in their own block
+        let code = r#"
+for _, io in ipairs(streams) do io.write("x") end
+local function log(debug) return debug end
+if ready then
+  local os = {}
+elseif os.time() > 0 then
+  local require = nil
+else
+  do local debug = 1 end
+  return debug
+end
+"#;
+        let lines: Vec<_> = check_script(code, &options)
+            .into_iter()
+            .map(|issue| issue.line)
+            .collect();
+        assert_eq!(lines, [Some(6), Some(10)]);
+
+        let issues = check_script("function f(\n  return 1\nend", &options);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, Some(2));
+
+        // `require` is allowed once there is a module root to load from
+        let code = "local m = require('utils')";
+        assert_eq!(check_script(code, &options).len(), 1);
+        assert!(check_script(code, &options.with_module_root("/tmp")).is_empty());
+    }
+}
@@ -0,0 +1,453 @@
+//! Periodic execution of tapplet methods declared in the manifest's `[schedule]` section:
+//!
+//! ```toml
+//! [schedule.check_prices]
+//! method = "check_prices"
+//! interval_secs = 60
+//! jitter_secs = 5
+//!
+//! [schedule.daily_report]
+//! method = "report"
+//! cron = "0 8 * * *"
+//! ```
+//!
+//! [`TappletScheduler`] decides what is due; hosts that drive tapplets themselves
+//! call [`TappletScheduler::take_due`] and [`TappletScheduler::finish`], others can
+//! hand their tapplets to [`TappletScheduler::run`].
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, TimeDelta, Utc};
+use rand::Rng;
+use serde_json::Value;
+
+use crate::call_context::CallContext;
+use crate::error::TappletError;
+use crate::host::{HostError, TappletRunner};
+use crate::model::{ScheduledTask, TappletManifest};
+
+enum Trigger {
+    Interval(TimeDelta),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Trigger {
+    fn from_task(name: &str, task: &ScheduledTask) -> Result<Self, TappletError> {
+        let invalid = |reason: String| {
+            TappletError::InvalidManifest(format!("schedule '{}' {}", name, reason))
+        };
+        if seconds(task.jitter_secs).is_none() {
+            return Err(invalid(format!(
+                "has a jitter of {} seconds, which is out of range",
+                task.jitter_secs
+            )));
+        }
+        match (task.interval_secs, &task.cron) {
+            (Some(0), None) => Err(invalid("has an interval of 0 seconds".to_string())),
+            (Some(secs), None) => seconds(secs).map(Trigger::Interval).ok_or_else(|| {
+                invalid(format!(
+                    "has an interval of {} seconds, which is out of range",
+                    secs
+                ))
+            }),
+            (None, Some(expression)) => {
+                // The cron crate wants a seconds field, standard expressions don't have one
+                let expression = if expression.split_whitespace().count() == 5 {
+                    format!("0 {}", expression)
+                } else {
+                    expression.clone()
+                };
+                cron::Schedule::from_str(&expression)
+                    .map(|schedule| Trigger::Cron(Box::new(schedule)))
+                    .map_err(|e| invalid(format!("has an invalid cron expression: {}", e)))
+            }
+            _ => Err(invalid(
+                "must set exactly one of interval_secs and cron".to_string(),
+            )),
+        }
+    }
+
+    /// `None` once there are no more runs, or the next one is past the dates
+    /// `DateTime` can hold
+    fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Trigger::Interval(interval) => time.checked_add_signed(*interval),
+            Trigger::Cron(schedule) => schedule.after(&time).next(),
+        }
+    }
+}
+
+/// `secs` as a `TimeDelta`, if it is in range
+fn seconds(secs: u64) -> Option<TimeDelta> {
+    i64::try_from(secs).ok().and_then(TimeDelta::try_seconds)
+}
+
+struct Job {
+    id: u64,
+    /// Qualified name of the tapplet
+    tapplet: String,
+    task_name: String,
+    task: ScheduledTask,
+    trigger: Trigger,
+    /// `None` once a cron schedule has no more runs
+    next_run: Option<DateTime<Utc>>,
+    failures: u32,
+}
+
+impl Job {
+    fn schedule_after(&mut self, time: DateTime<Utc>) {
+        let jitter_secs = self.task.jitter_secs;
+        self.next_run = self.trigger.next_after(time).map(|next| {
+            if jitter_secs == 0 {
+                return next;
+            }
+            // Checked by `Trigger::from_task`, but a jitter out of range is skipped
+            // rather than panicking
+            jitter_secs
+                .checked_mul(1000)
+                .and_then(|max_ms| i64::try_from(max_ms).ok())
+                .map(|max_ms| rand::thread_rng().gen_range(0..=max_ms))
+                .and_then(TimeDelta::try_milliseconds)
+                .and_then(|jitter| next.checked_add_signed(jitter))
+                .unwrap_or(next)
+        });
+    }
+}
+
+/// A scheduled method call that is due, returned by [`TappletScheduler::take_due`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledRun {
+    job: u64,
+    /// Qualified name of the tapplet, see [`TappletManifest::qualified_name`]
+    pub tapplet: String,
+    /// Name of the task in the manifest's `[schedule]` section
+    pub task: String,
+    pub method: String,
+    pub args: Value,
+}
+
+/// The result of a scheduled run made by [`TappletScheduler::run_pending`]
+#[derive(Debug)]
+pub struct ScheduledOutcome {
+    pub run: ScheduledRun,
+    pub result: Result<Value, HostError>,
+}
+
+/// Runs tapplet methods on the intervals and cron expressions from their manifests
+pub struct TappletScheduler {
+    jobs: Vec<Job>,
+    next_job_id: u64,
+    max_concurrency: usize,
+    backoff_base: Duration,
+    backoff_max: Duration,
+    running: HashMap<String, usize>,
+}
+
+impl Default for TappletScheduler {
+    fn default() -> Self {
+        Self {
+            jobs: Vec::new(),
+            next_job_id: 0,
+            max_concurrency: 1,
+            backoff_base: Duration::from_secs(30),
+            backoff_max: Duration::from_secs(60 * 60),
+            running: HashMap::new(),
+        }
+    }
+}
+
+impl TappletScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many scheduled runs of one tapplet may be in progress at once, 1 by default.
+    /// Due runs beyond the limit wait until an earlier run finishes.
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    /// After a failed run, wait `base` before the next attempt, doubling with each
+    /// further failure up to `max`. Defaults to 30 seconds and one hour.
+    pub fn with_failure_backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.backoff_base = base;
+        self.backoff_max = max;
+        self
+    }
+
+    /// Schedule the tasks from a tapplet's manifest, with their first runs after `now`.
+    ///
+    /// Fails without scheduling anything if a task is invalid or calls a method
+    /// that is not in `api.methods`.
+    pub fn add_tapplet(&mut self, manifest: &TappletManifest, now: DateTime<Utc>) -> Result<()> {
+        let tapplet = manifest.qualified_name();
+        let mut jobs = Vec::new();
+        for (name, task) in &manifest.schedule {
+            if !manifest.api.methods.contains(&task.method) {
+                return Err(TappletError::InvalidManifest(format!(
+                    "schedule '{}' calls undeclared method '{}'",
+                    name, task.method
+                ))
+                .into());
+            }
+            let mut job = Job {
+                id: self.next_job_id + jobs.len() as u64,
+                tapplet: tapplet.clone(),
+                task_name: name.clone(),
+                task: task.clone(),
+                trigger: Trigger::from_task(name, task)?,
+                next_run: None,
+                failures: 0,
+            };
+            job.schedule_after(now);
+            jobs.push(job);
+        }
+        self.remove_tapplet(&tapplet);
+        self.next_job_id += jobs.len() as u64;
+        self.jobs.extend(jobs);
+        Ok(())
+    }
+
+    /// Stop scheduling the tasks of the tapplet with this qualified name. Runs
+    /// already taken can still be finished.
+    pub fn remove_tapplet(&mut self, qualified_name: &str) {
+        self.jobs.retain(|job| job.tapplet != qualified_name);
+    }
+
+    /// When the next run is due, if anything is scheduled
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        self.jobs.iter().filter_map(|job| job.next_run).min()
+    }
+
+    /// Runs that are due at `now`, within each tapplet's concurrency limit.
+    ///
+    /// Runs missed while the host was busy are not caught up: each task runs at
+    /// most once per call and is then scheduled after `now`.
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<ScheduledRun> {
+        let mut due = Vec::new();
+        for job in &mut self.jobs {
+            if job.next_run.is_none_or(|next| next > now) {
+                continue;
+            }
+            let running = self.running.entry(job.tapplet.clone()).or_default();
+            if *running >= self.max_concurrency {
+                continue;
+            }
+            *running += 1;
+            job.schedule_after(now);
+            due.push(ScheduledRun {
+                job: job.id,
+                tapplet: job.tapplet.clone(),
+                task: job.task_name.clone(),
+                method: job.task.method.clone(),
+                args: job.task.args.clone(),
+            });
+        }
+        due
+    }
+
+    /// Record the result of a run returned by [`TappletScheduler::take_due`].
+    ///
+    /// Failures push the task's next run back by the failure backoff.
+    pub fn finish(&mut self, run: &ScheduledRun, succeeded: bool, now: DateTime<Utc>) {
+        if let Some(running) = self.running.get_mut(&run.tapplet) {
+            *running = running.saturating_sub(1);
+        }
+        let (base, max) = (self.backoff_base, self.backoff_max);
+        // The tapplet may have been removed while the run was in progress
+        let Some(job) = self.jobs.iter_mut().find(|job| job.id == run.job) else {
+            return;
+        };
+        if succeeded {
+            job.failures = 0;
+            return;
+        }
+        let backoff = base.saturating_mul(1 << job.failures.min(31)).min(max);
+        job.failures = job.failures.saturating_add(1);
+        let retry_at = TimeDelta::from_std(backoff)
+            .ok()
+            .and_then(|backoff| now.checked_add_signed(backoff))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        if let Some(next) = &mut job.next_run
+            && *next < retry_at
+        {
+            *next = retry_at;
+        }
+    }
+
+    /// Call every due method on the matching tapplet, one after the other, as
+    /// [`crate::call_context::Caller::System`]. Runs for tapplets missing from
+    /// `tapplets` fail with [`HostError::MethodNotFound`].
+    pub async fn run_pending(
+        &mut self,
+        tapplets: &mut [Box<dyn TappletRunner>],
+        now: DateTime<Utc>,
+    ) -> Vec<ScheduledOutcome> {
+        let context = CallContext::system();
+        let mut outcomes = Vec::new();
+        for run in self.take_due(now) {
+            let target = tapplets
+                .iter_mut()
+                .find(|tapplet| tapplet.manifest().qualified_name() == run.tapplet);
+            let result = match target {
+                Some(target) => target.call(&run.method, run.args.clone(), &context).await,
+                None => Err(HostError::MethodNotFound(format!(
+                    "{}.{}",
+                    run.tapplet, run.method
+                ))),
+            };
+            self.finish(&run, result.is_ok(), Utc::now());
+            outcomes.push(ScheduledOutcome { run, result });
+        }
+        outcomes
+    }
+
+    /// Schedule `tapplets` and run their tasks until none are left, passing each
+    /// outcome to `on_outcome`
+    pub async fn run(
+        mut self,
+        mut tapplets: Vec<Box<dyn TappletRunner>>,
+        mut on_outcome: impl FnMut(ScheduledOutcome),
+    ) -> Result<()> {
+        for tapplet in &tapplets {
+            self.add_tapplet(tapplet.manifest(), Utc::now())?;
+        }
+        while let Some(next) = self.next_run() {
+            if let Ok(wait) = (next - Utc::now()).to_std() {
+                tokio::time::sleep(wait).await;
+            }
+            for outcome in self.run_pending(&mut tapplets, Utc::now()).await {
+                on_outcome(outcome);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(schedule: &str) -> TappletManifest {
+        let toml = crate::test_utils::manifest_toml("alerts", "0.1.0") + schedule;
+        TappletManifest::from_toml_str(&toml).unwrap()
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn test_interval_backoff_and_concurrency() {
+        let mut scheduler = TappletScheduler::new()
+            .with_failure_backoff(Duration::from_secs(100), Duration::from_secs(250));
+        let manifest = manifest("[schedule.poll]\nmethod = \"greet\"\ninterval_secs = 60\n");
+        scheduler.add_tapplet(&manifest, at(0)).unwrap();
+        assert_eq!(scheduler.next_run(), Some(at(60)));
+        assert!(scheduler.take_due(at(59)).is_empty());
+
+        let run = scheduler.take_due(at(60)).pop().unwrap();
+        assert_eq!((run.task.as_str(), run.method.as_str()), ("poll", "greet"));
+        // Still running, so the next interval has to wait
+        assert!(scheduler.take_due(at(200)).is_empty());
+
+        scheduler.finish(&run, false, at(200));
+        assert_eq!(scheduler.next_run(), Some(at(300)));
+        let run = scheduler.take_due(at(300)).pop().unwrap();
+        scheduler.finish(&run, false, at(300));
+        assert_eq!(scheduler.next_run(), Some(at(500)));
+        let run = scheduler.take_due(at(500)).pop().unwrap();
+        scheduler.finish(&run, false, at(500));
+        assert_eq!(scheduler.next_run(), Some(at(750)));
+
+        let run = scheduler.take_due(at(750)).pop().unwrap();
+        scheduler.finish(&run, true, at(750));
+        assert_eq!(scheduler.next_run(), Some(at(810)));
+    }
+
+    #[test]
+    fn test_cron_and_invalid_schedules() {
+        let mut scheduler = TappletScheduler::new();
+        let daily = manifest("[schedule.report]\nmethod = \"greet\"\ncron = \"0 8 * * *\"\n");
+        scheduler.add_tapplet(&daily, at(0)).unwrap();
+        let next = scheduler.next_run().unwrap();
+        assert_eq!(next.format("%H:%M:%S").to_string(), "08:00:00");
+        assert!(next > at(0) && next <= at(24 * 60 * 60));
+
+        for schedule in [
+            "[schedule.a]\nmethod = \"greet\"\n",
+            "[schedule.a]\nmethod = \"greet\"\ninterval_secs = 60\ncron = \"* * * * *\"\n",
+            "[schedule.a]\nmethod = \"greet\"\ncron = \"every minute\"\n",
+            "[schedule.a]\nmethod = \"missing\"\ninterval_secs = 60\n",
+        ] {
+            assert!(scheduler.add_tapplet(&manifest(schedule), at(0)).is_err());
+        }
+        // The failed additions left the existing schedule alone
+        assert_eq!(scheduler.next_run(), Some(next));
+    }
+
+    #[test]
+    fn test_out_of_range_interval() {
+        let mut scheduler = TappletScheduler::new();
+        let schedule = format!(
+            "[schedule.a]\nmethod = \"greet\"\ninterval_secs = {}\n",
+            i64::MAX
+        );
+        let err = scheduler
+            .add_tapplet(&manifest(&schedule), at(0))
+            .unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_MANIFEST");
+
+        // In range, but past the last date `DateTime` holds, so it never runs
+        let schedule = format!(
+            "[schedule.a]\nmethod = \"greet\"\ninterval_secs = {}\n",
+            i64::MAX / 1000
+        );
+        scheduler.add_tapplet(&manifest(&schedule), at(0)).unwrap();
+        assert_eq!(scheduler.next_run(), None);
+    }
+
+    #[test]
+    fn test_out_of_range_jitter() {
+        let mut scheduler = TappletScheduler::new();
+        let schedule = format!(
+            "[schedule.a]\nmethod = \"greet\"\ninterval_secs = 60\njitter_secs = {}\n",
+            i64::MAX
+        );
+        let err = scheduler
+            .add_tapplet(&manifest(&schedule), at(0))
+            .unwrap_err();
+        assert_eq!(crate::error_code(&err), "INVALID_MANIFEST");
+        assert_eq!(scheduler.next_run(), None);
+
+        let schedule = format!(
+            "[schedule.a]\nmethod = \"greet\"\ninterval_secs = 60\njitter_secs = {}\n",
+            i64::MAX / 1000
+        );
+        scheduler.add_tapplet(&manifest(&schedule), at(0)).unwrap();
+        assert!(scheduler.next_run().unwrap() >= at(60));
+    }
+
+    #[test]
+    fn test_remove_tapplet() {
+        let mut scheduler = TappletScheduler::new();
+        let manifest = manifest("[schedule.poll]\nmethod = \"greet\"\ninterval_secs = 60\n");
+        scheduler.add_tapplet(&manifest, at(0)).unwrap();
+        let run = scheduler.take_due(at(60)).pop().unwrap();
+        assert_eq!(run.tapplet, manifest.qualified_name());
+
+        scheduler.remove_tapplet(&manifest.qualified_name());
+        assert!(scheduler.jobs.is_empty());
+        assert_eq!(scheduler.next_run(), None);
+        // The run taken before the removal can still finish
+        scheduler.finish(&run, false, at(70));
+
+        // Adding the tapplet back starts it afresh, within the concurrency limit
+        scheduler.add_tapplet(&manifest, at(100)).unwrap();
+        assert_eq!(scheduler.take_due(at(160)).len(), 1);
+    }
+}
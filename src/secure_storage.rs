@@ -0,0 +1,115 @@
+//! Encryption at rest for tapplet data slots.
+//!
+//! Values are encrypted with XChaCha20-Poly1305 before they reach
+//! [`MinotariTappletApiV1::append_data`], so the wallet only ever stores
+//! ciphertext. The slot name is bound into each entry, so an entry copied to
+//! another slot fails to decrypt.
+//!
+//! [`MinotariTappletApiV1::append_data`]: crate::host::MinotariTappletApiV1::append_data
+
+use std::fmt;
+
+use anyhow::{Context, Result, anyhow, bail};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+/// Prefix of every encrypted entry, so the format can change later
+const ENTRY_PREFIX: &str = "enc1:";
+const NONCE_LEN: usize = 24;
+
+/// Key used to encrypt one tapplet's data slots
+#[derive(Clone)]
+pub struct StorageKey([u8; 32]);
+
+impl StorageKey {
+    /// Derive a tapplet's key from a wallet secret.
+    ///
+    /// Each tapplet gets its own key, so one tapplet can't decrypt another's data
+    /// even if it reads the same slots.
+    pub fn derive(wallet_secret: &[u8], tapplet: &str) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(Some(b"tari-tapplet-storage"), wallet_secret);
+        let mut key = [0u8; 32];
+        hkdf.expand(tapplet.as_bytes(), &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self(key)
+    }
+
+    pub fn from_bytes(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    /// Encrypt a value for `slot`, returning a string suitable for `append_data`
+    pub fn encrypt(&self, slot: &str, value: &str) -> Result<String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher()
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: slot.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to encrypt entry for slot {}", slot))?;
+        Ok(format!(
+            "{}{}{}",
+            ENTRY_PREFIX,
+            hex::encode(nonce),
+            hex::encode(ciphertext)
+        ))
+    }
+
+    /// Decrypt an entry previously returned by [`StorageKey::encrypt`] for the same slot
+    pub fn decrypt(&self, slot: &str, entry: &str) -> Result<String> {
+        let Some(encoded) = entry.strip_prefix(ENTRY_PREFIX) else {
+            bail!("Entry in slot {} is not encrypted", slot);
+        };
+        let bytes = hex::decode(encoded).context("Encrypted entry is not valid hex")?;
+        if bytes.len() < NONCE_LEN {
+            bail!("Encrypted entry in slot {} is truncated", slot);
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher()
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: slot.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to decrypt entry in slot {}", slot))?;
+        String::from_utf8(plaintext).context("Decrypted entry is not valid UTF-8")
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+impl fmt::Debug for StorageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StorageKey(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let key = StorageKey::derive(b"wallet secret", "password-manager");
+        let entry = key.encrypt("passwords", "hunter2").unwrap();
+        assert!(!entry.contains("hunter2"));
+        assert_eq!(key.decrypt("passwords", &entry).unwrap(), "hunter2");
+
+        // Bound to the slot, the tapplet and the wallet
+        assert!(key.decrypt("notes", &entry).is_err());
+        let other_tapplet = StorageKey::derive(b"wallet secret", "other");
+        assert!(other_tapplet.decrypt("passwords", &entry).is_err());
+        assert!(key.decrypt("passwords", "hunter2").is_err());
+    }
+}
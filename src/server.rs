@@ -0,0 +1,619 @@
+//! JSON-RPC access to loaded tapplets over HTTP and WebSocket.
+//!
+//! [`TappletRpcServer`] exposes three methods:
+//!
+//! * `tapplet.list()` - name, publisher, version and methods of every loaded tapplet
+//! * `tapplet.manifest(name)` - a tapplet's full manifest
+//! * `tapplet.call(name, method, params)` - call a tapplet method
+//!
+//! `name` is either a tapplet's qualified name, `publisher/name`, or its bare
+//! name if only one loaded tapplet has that name.
+//!
+//! Parameters can be passed by position or by name. Failed calls return a
+//! JSON-RPC error whose `data.code` is the library's error code, e.g.
+//! `PERMISSION_DENIED`.
+//!
+//! Calls run as [`Caller::Rpc`] without permissions unless the server is given
+//! another context. The server only binds to loopback addresses unless
+//! [`TappletRpcServer::allow_remote`] is set, and refuses requests from browser
+//! pages, which carry an `Origin` header, unless their origin is allowed with
+//! [`TappletRpcServer::with_allowed_origin`].
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use anyhow::{Context, Result, bail};
+use jsonrpsee::core::BoxError;
+use jsonrpsee::server::{HttpBody, HttpRequest, HttpResponse, Server, ServerHandle};
+use jsonrpsee::types::ErrorObjectOwned;
+use jsonrpsee::types::error::INVALID_PARAMS_CODE;
+use jsonrpsee::{RpcModule, types::Params};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::sync::{mpsc, oneshot};
+use tower::{Layer, Service};
+
+use crate::call_context::{CallContext, Caller};
+use crate::error::TappletError;
+use crate::host::{HostError, MinotariTappletApiV1, TappletRunner};
+use crate::host_options::HostOptions;
+use crate::manager::TappletManager;
+use crate::model::TappletManifest;
+
+/// JSON-RPC error code for failures reported by the library, with details in `data.code`
+pub const TAPPLET_ERROR_CODE: i32 = -32000;
+
+/// Number of calls that can wait for the tapplet thread before callers are slowed down
+const CALL_QUEUE_SIZE: usize = 64;
+
+type LoadedTapplets = Vec<Box<dyn TappletRunner>>;
+type Loader = Box<dyn FnOnce() -> Result<LoadedTapplets> + Send>;
+
+/// Summary of a loaded tapplet returned by `tapplet.list`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TappletInfo {
+    pub name: String,
+    pub publisher: String,
+    pub version: String,
+    pub friendly_name: String,
+    pub methods: Vec<String>,
+}
+
+impl From<&TappletManifest> for TappletInfo {
+    fn from(manifest: &TappletManifest) -> Self {
+        Self {
+            name: manifest.name.clone(),
+            publisher: manifest.publisher.clone(),
+            version: manifest.version.clone(),
+            friendly_name: manifest.friendly_name.clone(),
+            methods: manifest.api.methods.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ManifestParams {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct CallParams {
+    name: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+struct CallRequest {
+    /// Qualified name of the tapplet to call
+    tapplet: String,
+    params: CallParams,
+    reply: oneshot::Sender<Result<Value, HostError>>,
+}
+
+struct ServerState {
+    /// Manifests of the loaded tapplets by qualified name
+    manifests: BTreeMap<String, TappletManifest>,
+    calls: mpsc::Sender<CallRequest>,
+}
+
+/// Serves tapplet methods over JSON-RPC.
+///
+/// Tapplet hosts can't be moved between threads, so they are created by a loader
+/// on a dedicated thread and calls are executed there one at a time.
+pub struct TappletRpcServer {
+    loader: Loader,
+    context: CallContext,
+    /// Origins of browser pages that may call the server
+    allowed_origins: Vec<String>,
+    allow_remote: bool,
+}
+
+impl TappletRpcServer {
+    /// Serve the tapplets returned by `loader`, which runs on the tapplet thread
+    pub fn new(loader: impl FnOnce() -> Result<LoadedTapplets> + Send + 'static) -> Self {
+        Self {
+            loader: Box::new(loader),
+            context: CallContext::rpc(),
+            allowed_origins: Vec::new(),
+            allow_remote: false,
+        }
+    }
+
+    /// Serve every tapplet installed in `manager`, including same-named tapplets
+    /// of different publishers, with hosts limited by [`HostOptions::default`],
+    /// so a runaway call times out instead of holding up every later one
+    pub fn from_manager<T>(manager: TappletManager, api: T) -> Self
+    where
+        T: MinotariTappletApiV1 + Clone + 'static,
+    {
+        Self::from_manager_with_options(manager, api, HostOptions::default())
+    }
+
+    /// [`Self::from_manager`] with the limits, sandbox and hooks of `options`
+    pub fn from_manager_with_options<T>(
+        manager: TappletManager,
+        api: T,
+        options: HostOptions,
+    ) -> Self
+    where
+        T: MinotariTappletApiV1 + Clone + 'static,
+    {
+        Self::new(move || {
+            manager
+                .list_installed()?
+                .into_iter()
+                .map(|tapplet| {
+                    let host = manager.build_host(tapplet, Arc::new(api.clone()), &options)?;
+                    Ok(Box::new(host) as Box<dyn TappletRunner>)
+                })
+                .collect()
+        })
+    }
+
+    /// Context of every `tapplet.call`, [`CallContext::rpc`] without permissions
+    /// by default, so methods that are `user_only` can't be called.
+    ///
+    /// Anyone who can reach the server gets this caller and its permissions, so
+    /// only pass [`CallContext::user`] or grant permissions when untrusted callers
+    /// can't reach the server.
+    pub fn with_call_context(mut self, context: CallContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Accept requests from browser pages served from `origin`, e.g.
+    /// `http://localhost:3000`. Requests without an `Origin` header, such as
+    /// those of non-browser clients, are always accepted.
+    pub fn with_allowed_origin(mut self, origin: impl Into<String>) -> Self {
+        self.allowed_origins.push(origin.into());
+        self
+    }
+
+    /// Let [`Self::start`] bind to addresses other machines can reach
+    pub fn allow_remote(mut self) -> Self {
+        self.allow_remote = true;
+        self
+    }
+
+    /// Load the tapplets and start serving on `addr`, which must be a loopback
+    /// address unless remote access is allowed. Use port 0 to pick a free port.
+    pub async fn start(self, addr: SocketAddr) -> Result<RpcServerHandle> {
+        if !addr.ip().is_loopback() && !self.allow_remote {
+            bail!(
+                "{} is not a loopback address; call allow_remote() to serve tapplets on it",
+                addr
+            );
+        }
+        if self.context.caller == Caller::User {
+            crate::trace::warning!(
+                "Serving tapplets on {} with the privileges of the user",
+                addr
+            );
+        }
+        let (calls_tx, calls_rx) = mpsc::channel(CALL_QUEUE_SIZE);
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let Self {
+            loader,
+            context,
+            allowed_origins,
+            ..
+        } = self;
+        std::thread::Builder::new()
+            .name("tapplet-rpc".to_string())
+            .spawn(move || run_tapplets(loader, context, calls_rx, ready_tx))?;
+        let manifests = ready_rx
+            .await
+            .context("Tapplet thread stopped while loading tapplets")??;
+
+        let mut module = RpcModule::new(ServerState {
+            manifests,
+            calls: calls_tx,
+        });
+        module.register_method("tapplet.list", |_, state, _| {
+            state
+                .manifests
+                .values()
+                .map(TappletInfo::from)
+                .collect::<Vec<_>>()
+        })?;
+        module.register_method("tapplet.manifest", |params, state, _| {
+            let ManifestParams { name } = parse_params(&params)?;
+            let manifest = state.find(&name)?;
+            serde_json::to_value(manifest).map_err(|e| internal_error(e.to_string()))
+        })?;
+        module.register_async_method("tapplet.call", |params, state, _| async move {
+            let params: CallParams = parse_params(&params)?;
+            state.call(params).await
+        })?;
+
+        let server = Server::builder()
+            .set_http_middleware(
+                tower::ServiceBuilder::new().layer(OriginFilterLayer(Arc::new(allowed_origins))),
+            )
+            .build(addr)
+            .await?;
+        let local_addr = server.local_addr()?;
+        Ok(RpcServerHandle {
+            local_addr,
+            handle: server.start(module),
+        })
+    }
+}
+
+impl ServerState {
+    /// The tapplet `name` refers to, by qualified name or by a bare name that
+    /// only one loaded tapplet has
+    fn find(&self, name: &str) -> Result<&TappletManifest, ErrorObjectOwned> {
+        let mut matches = self
+            .manifests
+            .values()
+            .filter(|manifest| manifest.name_matches(name));
+        match (matches.next(), matches.next()) {
+            (Some(manifest), None) => Ok(manifest),
+            (Some(_), Some(_)) => Err(ErrorObjectOwned::owned(
+                INVALID_PARAMS_CODE,
+                format!(
+                    "Several publishers' tapplets are called '{}', use publisher/name",
+                    name
+                ),
+                None::<()>,
+            )),
+            (None, _) => Err(library_error(&TappletError::TappletNotFound {
+                name: name.to_string(),
+            })),
+        }
+    }
+
+    async fn call(&self, params: CallParams) -> Result<Value, ErrorObjectOwned> {
+        let tapplet = self.find(&params.name)?.qualified_name();
+        let (reply, result) = oneshot::channel();
+        self.calls
+            .send(CallRequest {
+                tapplet,
+                params,
+                reply,
+            })
+            .await
+            .map_err(|_| internal_error("Tapplet thread stopped".to_string()))?;
+        let result = result
+            .await
+            .map_err(|_| internal_error("Tapplet thread stopped".to_string()))?;
+        result.map_err(|err| match err {
+            HostError::InvalidArguments(_) => ErrorObjectOwned::owned(
+                INVALID_PARAMS_CODE,
+                err.to_string(),
+                Some(json!({ "code": err.code() })),
+            ),
+            err => ErrorObjectOwned::owned(
+                TAPPLET_ERROR_CODE,
+                err.to_string(),
+                Some(json!({ "code": err.code() })),
+            ),
+        })
+    }
+}
+
+/// Create the tapplets and execute calls until the server is stopped
+fn run_tapplets(
+    loader: Loader,
+    context: CallContext,
+    mut calls: mpsc::Receiver<CallRequest>,
+    ready: oneshot::Sender<Result<BTreeMap<String, TappletManifest>>>,
+) {
+    // Host API calls from Lua block in place, which needs a multi-threaded runtime
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            let _ = ready.send(Err(e.into()));
+            return;
+        }
+    };
+    let mut tapplets = match loader() {
+        Ok(tapplets) => tapplets,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+    let manifests = tapplets
+        .iter()
+        .map(|tapplet| {
+            (
+                tapplet.manifest().qualified_name(),
+                tapplet.manifest().clone(),
+            )
+        })
+        .collect();
+    if ready.send(Ok(manifests)).is_err() {
+        return;
+    }
+
+    runtime.block_on(async {
+        while let Some(CallRequest {
+            tapplet,
+            params,
+            reply,
+        }) = calls.recv().await
+        {
+            let target = tapplets
+                .iter_mut()
+                .find(|target| target.manifest().qualified_name() == tapplet);
+            let result = match target {
+                Some(target) => target.call(&params.method, params.params, &context).await,
+                None => Err(HostError::MethodNotFound(format!(
+                    "{}.{}",
+                    params.name, params.method
+                ))),
+            };
+            let _ = reply.send(result);
+        }
+    });
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: &Params) -> Result<T, ErrorObjectOwned> {
+    params.parse()
+}
+
+fn library_error(err: &TappletError) -> ErrorObjectOwned {
+    ErrorObjectOwned::owned(
+        TAPPLET_ERROR_CODE,
+        err.to_string(),
+        Some(json!({ "code": err.code() })),
+    )
+}
+
+fn internal_error(message: String) -> ErrorObjectOwned {
+    ErrorObjectOwned::owned(
+        jsonrpsee::types::error::INTERNAL_ERROR_CODE,
+        message,
+        None::<()>,
+    )
+}
+
+/// Refuses requests whose `Origin` header isn't one of the allowed origins,
+/// including WebSocket upgrades, which browsers don't subject to CORS
+#[derive(Clone)]
+struct OriginFilterLayer(Arc<Vec<String>>);
+
+impl<S> Layer<S> for OriginFilterLayer {
+    type Service = OriginFilter<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        OriginFilter {
+            inner,
+            allowed: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct OriginFilter<S> {
+    inner: S,
+    allowed: Arc<Vec<String>>,
+}
+
+impl<S, B> Service<HttpRequest<B>> for OriginFilter<S>
+where
+    S: Service<HttpRequest<B>, Response = HttpResponse<HttpBody>>,
+    S::Error: Into<BoxError> + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: HttpRequest<B>) -> Self::Future {
+        let allowed = match request.headers().get("origin") {
+            None => true,
+            Some(origin) => self
+                .allowed
+                .iter()
+                .any(|allowed| allowed.as_bytes() == origin.as_bytes()),
+        };
+        if !allowed {
+            return Box::pin(async { Ok(jsonrpsee::server::http::response::denied()) });
+        }
+        let response = self.inner.call(request);
+        Box::pin(async move { response.await.map_err(Into::into) })
+    }
+}
+
+/// A running [`TappletRpcServer`]
+pub struct RpcServerHandle {
+    local_addr: SocketAddr,
+    handle: ServerHandle,
+}
+
+impl RpcServerHandle {
+    /// Address the server is listening on, for both HTTP and WebSocket
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting requests and wait for the server to shut down
+    pub async fn stop(self) {
+        // Fails only if the server already stopped
+        let _ = self.handle.stop();
+        self.handle.stopped().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use jsonrpsee::core::client::ClientT;
+    use jsonrpsee::http_client::{HeaderMap, HeaderValue, HttpClientBuilder};
+    use jsonrpsee::rpc_params;
+
+    use super::*;
+    use crate::host::LuaTappletHost;
+    use crate::reference_api::MemoryTappletApi;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_list_manifest_and_call() {
+        let server = TappletRpcServer::new(|| {
+            let toml = crate::test_utils::manifest_toml("greeter", "0.1.0");
+            let config = TappletManifest::from_toml_str(&toml)?;
+            let code = "function greet(args) return 'Hello, ' .. args.name end";
+            let host = LuaTappletHost::from_string_shared(
+                config,
+                code,
+                Arc::new(MemoryTappletApi::new()),
+            )?;
+            Ok(vec![Box::new(host) as Box<dyn TappletRunner>])
+        });
+        let handle = server.start("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://{}", handle.local_addr()))
+            .unwrap();
+
+        let list: Vec<TappletInfo> = client.request("tapplet.list", rpc_params![]).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].methods, vec!["greet"]);
+
+        let manifest: Value = client
+            .request("tapplet.manifest", rpc_params!["greeter"])
+            .await
+            .unwrap();
+        assert_eq!(manifest["version"], "0.1.0");
+
+        let greeting: String = client
+            .request(
+                "tapplet.call",
+                rpc_params!["greeter", "greet", json!({"name": "Alice"})],
+            )
+            .await
+            .unwrap();
+        assert_eq!(greeting, "Hello, Alice");
+
+        let err = client
+            .request::<Value, _>("tapplet.call", rpc_params!["missing", "greet"])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("TAPPLET_NOT_FOUND"), "{}", err);
+
+        // Browser pages need their origin allowed
+        let mut headers = HeaderMap::new();
+        headers.insert("Origin", HeaderValue::from_static("https://evil.example"));
+        let browser = HttpClientBuilder::default()
+            .set_headers(headers)
+            .build(format!("http://{}", handle.local_addr()))
+            .unwrap();
+        let err = browser
+            .request::<Value, _>("tapplet.list", rpc_params![])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("403"), "{}", err);
+
+        handle.stop().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tapplets_of_several_publishers() {
+        let server = TappletRpcServer::new(|| {
+            ["tari_labs", "acme"]
+                .into_iter()
+                .map(|publisher| {
+                    let toml = crate::test_utils::manifest_toml("wallet", "0.1.0")
+                        .replace("test_publisher", publisher);
+                    let config = TappletManifest::from_toml_str(&toml)?;
+                    let code = format!("function greet() return '{}' end", publisher);
+                    let host = LuaTappletHost::from_string_shared(
+                        config,
+                        &code,
+                        Arc::new(MemoryTappletApi::new()),
+                    )?;
+                    Ok(Box::new(host) as Box<dyn TappletRunner>)
+                })
+                .collect()
+        });
+        let handle = server.start("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://{}", handle.local_addr()))
+            .unwrap();
+
+        let list: Vec<TappletInfo> = client.request("tapplet.list", rpc_params![]).await.unwrap();
+        assert_eq!(list.len(), 2);
+        for publisher in ["tari_labs", "acme"] {
+            let greeting: String = client
+                .request(
+                    "tapplet.call",
+                    rpc_params![format!("{}/wallet", publisher), "greet"],
+                )
+                .await
+                .unwrap();
+            assert_eq!(greeting, publisher);
+        }
+        let err = client
+            .request::<Value, _>("tapplet.call", rpc_params!["wallet", "greet"])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("publisher/name"), "{}", err);
+
+        handle.stop().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_runaway_calls_from_manager_time_out() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        crate::test_utils::write_lua_tapplet(&source, "spinner", "0.1.0");
+        std::fs::write(
+            source.join("main.lua"),
+            "function greet() while true do end end",
+        )
+        .unwrap();
+        let manager = TappletManager::new(temp.path().join("cache"));
+        manager
+            .install(crate::manager::TappletSource::LocalLua { path: source })
+            .unwrap();
+
+        let options = HostOptions::default().with_timeout(std::time::Duration::from_millis(200));
+        let server = TappletRpcServer::from_manager_with_options(
+            manager,
+            Arc::new(MemoryTappletApi::new()),
+            options,
+        )
+        .with_call_context(CallContext::user());
+        let handle = server.start("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://{}", handle.local_addr()))
+            .unwrap();
+
+        // The tapplet thread is free again for the next call
+        for _ in 0..2 {
+            let err = client
+                .request::<Value, _>("tapplet.call", rpc_params!["spinner", "greet"])
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("TIMEOUT"), "{}", err);
+        }
+
+        handle.stop().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_remote_addresses_need_to_be_allowed() {
+        let server = TappletRpcServer::new(|| Ok(Vec::new()));
+        let err = server
+            .start("0.0.0.0:0".parse().unwrap())
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("allow_remote"), "{}", err);
+    }
+}
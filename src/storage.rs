@@ -0,0 +1,283 @@
+//! Per-tapplet view of the host API's data slots.
+//!
+//! Every slot a tapplet uses is prefixed with the tapplet's qualified name,
+//! `publisher/name`, so two tapplets using the same slot name, even tapplets of
+//! the same name from different publishers, can't read or overwrite each
+//! other's data, and
+//! appends are checked against the tapplet's [`StorageQuota`].
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+use anyhow::Result;
+
+use crate::events::{EventSender, TappletEvent};
+use crate::host::{HostError, MinotariTappletApiV1};
+
+/// Limits on how much a tapplet may store in each of its slots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StorageQuota {
+    pub max_entries: Option<usize>,
+    /// Total size of the stored entries, after encryption for encrypted slots
+    pub max_bytes: Option<usize>,
+}
+
+impl StorageQuota {
+    /// No limits
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.max_entries.is_none() && self.max_bytes.is_none()
+    }
+
+    /// Fail if appending `value` to a slot holding `entries` would exceed the quota
+    pub fn check(&self, slot: &str, entries: &[String], value: &str) -> Result<(), HostError> {
+        if let Some(max_entries) = self.max_entries
+            && entries.len() >= max_entries
+        {
+            return Err(HostError::StorageQuotaExceeded(format!(
+                "slot {} already holds the maximum of {} entries",
+                slot, max_entries
+            )));
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            let used: usize = entries.iter().map(String::len).sum();
+            if used + value.len() > max_bytes {
+                return Err(HostError::StorageQuotaExceeded(format!(
+                    "slot {} would hold {} bytes, the maximum is {}",
+                    slot,
+                    used + value.len(),
+                    max_bytes
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The name a tapplet's slot is stored under in the host API. `tapplet` is the
+/// tapplet's [qualified name](crate::TappletManifest::qualified_name), so
+/// tapplets of the same name from different publishers don't share slots.
+pub fn namespaced_slot(tapplet: &str, slot: &str) -> String {
+    format!("{}/{}", tapplet, slot)
+}
+
+/// The lock appends to a namespaced slot hold while its quota is checked, shared
+/// by every host so two appends can't both pass the check on the same entries
+fn slot_lock(slot: &str) -> Arc<tokio::sync::Mutex<()>> {
+    static LOCKS: OnceLock<Mutex<HashMap<String, Weak<tokio::sync::Mutex<()>>>>> = OnceLock::new();
+    let mut locks = LOCKS.get_or_init(Default::default).lock().unwrap();
+    if let Some(lock) = locks.get(slot).and_then(Weak::upgrade) {
+        return lock;
+    }
+    locks.retain(|_, lock| lock.strong_count() > 0);
+    let lock = Arc::new(tokio::sync::Mutex::new(()));
+    locks.insert(slot.to_string(), Arc::downgrade(&lock));
+    lock
+}
+
+/// Appends held back until they are committed, so a batch of calls either
+/// writes everything or nothing
+#[derive(Debug, Default)]
+pub(crate) struct StagedWrites {
+    /// The appends, in order
+    appends: Mutex<Vec<StagedAppend>>,
+}
+
+#[derive(Debug)]
+struct StagedAppend {
+    /// Namespaced slot
+    slot: String,
+    value: String,
+    /// Sent once the append is made
+    changed: Option<(EventSender, TappletEvent)>,
+}
+
+impl StagedWrites {
+    fn entries(&self, slot: &str) -> Vec<String> {
+        let appends = self.appends.lock().unwrap();
+        appends
+            .iter()
+            .filter(|append| append.slot == slot)
+            .map(|append| append.value.clone())
+            .collect()
+    }
+
+    /// Make the staged appends through `api`. The host API has no transactions,
+    /// so if an append fails the ones before it stay written.
+    pub async fn commit<T: MinotariTappletApiV1 + ?Sized>(&self, api: &T) -> Result<usize> {
+        let appends = std::mem::take(&mut *self.appends.lock().unwrap());
+        for append in &appends {
+            api.append_data(&append.slot, &append.value).await?;
+            if let Some((events, event)) = &append.changed {
+                let _ = events.send(event.clone());
+            }
+        }
+        Ok(appends.len())
+    }
+}
+
+/// Data slots of a single tapplet
+pub(crate) struct TappletStorage<T: ?Sized> {
+    api: Arc<T>,
+    tapplet: String,
+    quota: StorageQuota,
+    /// Where appends go instead of the API while a batch is staged
+    staged: Option<Arc<StagedWrites>>,
+    /// Where `slot_changed` events for appends go
+    events: Option<EventSender>,
+}
+
+impl<T: MinotariTappletApiV1 + ?Sized> TappletStorage<T> {
+    pub fn new(api: Arc<T>, tapplet: &str, quota: StorageQuota) -> Self {
+        Self {
+            api,
+            tapplet: tapplet.to_string(),
+            quota,
+            staged: None,
+            events: None,
+        }
+    }
+
+    /// Raise a [`TappletEvent::SlotChanged`] on `events` for every append once it
+    /// is made
+    pub fn with_events(mut self, events: Option<EventSender>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Stage appends in `staged` rather than making them. Loads see the staged
+    /// entries after the ones already stored.
+    pub fn with_staged(mut self, staged: Option<Arc<StagedWrites>>) -> Self {
+        self.staged = staged;
+        self
+    }
+
+    pub async fn append(&self, name: &str, value: &str) -> Result<()> {
+        let slot = namespaced_slot(&self.tapplet, name);
+        let lock = slot_lock(&slot);
+        let _guard = if self.quota.is_unlimited() {
+            None
+        } else {
+            let guard = lock.lock().await;
+            let entries = self.load_namespaced(&slot).await?;
+            self.quota.check(&slot, &entries, value)?;
+            Some(guard)
+        };
+        let changed = self.events.clone().map(|events| {
+            let event = TappletEvent::SlotChanged {
+                tapplet: self.tapplet.clone(),
+                slot: name.to_string(),
+            };
+            (events, event)
+        });
+        match &self.staged {
+            Some(staged) => {
+                staged.appends.lock().unwrap().push(StagedAppend {
+                    slot,
+                    value: value.to_string(),
+                    changed,
+                });
+            }
+            None => {
+                self.api.append_data(&slot, value).await?;
+                // The bus may be gone, leaving nobody to tell
+                if let Some((events, event)) = changed {
+                    let _ = events.send(event);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn load(&self, slot: &str) -> Result<Vec<String>> {
+        self.load_namespaced(&namespaced_slot(&self.tapplet, slot))
+            .await
+    }
+
+    async fn load_namespaced(&self, slot: &str) -> Result<Vec<String>> {
+        let mut entries = self.api.load_data_entries(slot).await?;
+        if let Some(staged) = &self.staged {
+            entries.extend(staged.entries(slot));
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reference_api::MemoryTappletApi;
+
+    #[tokio::test]
+    async fn test_slots_are_namespaced_and_limited() {
+        let api = Arc::new(MemoryTappletApi::new());
+        let quota = StorageQuota::unlimited()
+            .with_max_entries(2)
+            .with_max_bytes(8);
+        let first = TappletStorage::new(api.clone(), "first", quota);
+        let second = TappletStorage::new(api.clone(), "second", quota);
+
+        first.append("notes", "abc").await.unwrap();
+        assert!(second.load("notes").await.unwrap().is_empty());
+        assert_eq!(api.state().slots["first/notes"], vec!["abc"]);
+
+        let err = first.append("notes", "too long").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<HostError>(),
+            Some(HostError::StorageQuotaExceeded(_))
+        ));
+        first.append("notes", "def").await.unwrap();
+        assert!(first.append("notes", "g").await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_appends_respect_the_quota() {
+        let api = Arc::new(MemoryTappletApi::new());
+        let quota = StorageQuota::unlimited().with_max_entries(5);
+        let appends: Vec<_> = (0..20)
+            .map(|i| {
+                let storage = TappletStorage::new(api.clone(), "notes", quota);
+                tokio::spawn(async move { storage.append("drafts", &i.to_string()).await })
+            })
+            .collect();
+        let mut written = 0;
+        for append in appends {
+            written += append.await.unwrap().is_ok() as usize;
+        }
+        assert_eq!(written, 5);
+        assert_eq!(api.state().slots["notes/drafts"].len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_staged_appends() {
+        let api = Arc::new(MemoryTappletApi::new());
+        let staged = Arc::new(StagedWrites::default());
+        let quota = StorageQuota::unlimited().with_max_entries(2);
+        let storage =
+            TappletStorage::new(api.clone(), "notes", quota).with_staged(Some(staged.clone()));
+
+        storage.append("drafts", "one").await.unwrap();
+        storage.append("drafts", "two").await.unwrap();
+        // Staged entries are read back and count against the quota
+        assert_eq!(storage.load("drafts").await.unwrap(), ["one", "two"]);
+        assert!(storage.append("drafts", "three").await.is_err());
+        assert!(api.state().slots.is_empty());
+
+        assert_eq!(staged.commit(api.as_ref()).await.unwrap(), 2);
+        assert_eq!(api.state().slots["notes/drafts"], ["one", "two"]);
+        assert_eq!(staged.commit(api.as_ref()).await.unwrap(), 0);
+    }
+}
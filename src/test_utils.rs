@@ -0,0 +1,67 @@
+//! Shared fixtures for unit tests
+
+use std::path::Path;
+
+use git2::Repository;
+
+/// A minimal valid manifest for a tapplet with a single `greet` method
+pub fn manifest_toml(name: &str, version: &str) -> String {
+    format!(
+        r#"
+name = "{name}"
+version = "{version}"
+friendly_name = "{name} tapplet"
+description = "Test tapplet {name}"
+publisher = "test_publisher"
+public_key = "test_public_key"
+license = "MIT"
+
+[api]
+methods = ["greet"]
+
+[api.greet]
+description = "Returns a greeting message."
+
+[api.greet.returns]
+type = "string"
+description = "A greeting message."
+
+[sigs]
+todo = "add sigs here"
+"#
+    )
+}
+
+/// Write a Lua tapplet (manifest plus script) into `dir`
+pub fn write_lua_tapplet(dir: &Path, name: &str, version: &str) {
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(dir.join("manifest.toml"), manifest_toml(name, version)).unwrap();
+    std::fs::write(dir.join("main.lua"), "function greet() return 'hello' end").unwrap();
+}
+
+/// Initialise a git repository in `dir` and commit everything in it, returning the commit id
+pub fn commit_all(dir: &Path) -> String {
+    let repo = Repository::open(dir)
+        .or_else(|_| Repository::init(dir))
+        .unwrap();
+    let mut index = repo.index().unwrap();
+    index
+        .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let sig = git2::Signature::now("test", "test@example.com").unwrap();
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let parents: Vec<_> = parent.iter().collect();
+    repo.commit(Some("HEAD"), &sig, &sig, "commit", &tree, &parents)
+        .unwrap()
+        .to_string()
+}
+
+/// Create a registry repository in `dir` containing Lua tapplets with the given names
+pub fn init_registry_repo(dir: &Path, tapplets: &[(&str, &str)]) -> String {
+    for (name, version) in tapplets {
+        write_lua_tapplet(&dir.join("tapplets").join(name), name, version);
+    }
+    commit_all(dir)
+}
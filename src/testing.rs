@@ -0,0 +1,236 @@
+//! Run the tests declared in a tapplet manifest against the reference host API.
+//!
+//! ```toml
+//! [tests.greets_alice]
+//! method = "greet"
+//! args = { name = "Alice" }
+//! expected = "Hello, Alice"
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::call_context::CallContext;
+use crate::error::TappletError;
+use crate::host::{HostError, LuaTappletHost, WasmTappletHost};
+use crate::model::{RuntimeKind, TappletManifest, TappletTest, find_manifest_file};
+use crate::reference_api::MemoryTappletApi;
+
+/// Where the harness loads the tapplet code from
+#[derive(Debug, Clone)]
+enum TestSource {
+    Wasm(PathBuf),
+    Lua(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestOutcome {
+    Passed,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    pub outcome: TestOutcome,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TestReport {
+    pub results: Vec<TestResult>,
+}
+
+impl TestReport {
+    pub fn is_success(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &TestResult> {
+        self.results
+            .iter()
+            .filter(|result| result.outcome != TestOutcome::Passed)
+    }
+}
+
+/// Loads a tapplet and runs its manifest-declared tests.
+///
+/// Every test gets a fresh host backed by an empty [`MemoryTappletApi`], so
+/// tests can't affect each other through stored data.
+pub struct TappletTestHarness {
+    manifest: TappletManifest,
+    source: TestSource,
+}
+
+impl TappletTestHarness {
+    /// Load a tapplet from a source or installed directory containing a manifest
+    /// and the entrypoint its `[runtime]` declares, or else `<name>.wasm` or a Lua script
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let (manifest, kind, path) = locate_entrypoint(dir.as_ref())?;
+        let source = match kind {
+            RuntimeKind::Wasm => TestSource::Wasm(path),
+            RuntimeKind::Lua => TestSource::Lua(path),
+        };
+        Ok(Self { manifest, source })
+    }
+
+    pub fn manifest(&self) -> &TappletManifest {
+        &self.manifest
+    }
+
+    /// Run a single test, returning whether it passed and why not
+    pub async fn run_test(&self, test: &TappletTest) -> TestOutcome {
+        let mut context = CallContext::user();
+        for permission in &test.permissions {
+            context = context.with_permission(permission.clone());
+        }
+
+        let result = match self.call(&test.method, test.args.clone(), &context).await {
+            Ok(result) => result,
+            Err(e) => return TestOutcome::Failed(format!("{:#}", e)),
+        };
+
+        match (result, &test.expected_error) {
+            (Ok(actual), None) => match &test.expected {
+                Some(expected) if expected != &actual => {
+                    TestOutcome::Failed(format!("expected {}, got {}", expected, actual))
+                }
+                _ => TestOutcome::Passed,
+            },
+            (Ok(actual), Some(code)) => {
+                TestOutcome::Failed(format!("expected error {}, got {}", code, actual))
+            }
+            (Err(e), Some(code)) if e.code() == code => TestOutcome::Passed,
+            (Err(e), _) => TestOutcome::Failed(format!("{}: {}", e.code(), e)),
+        }
+    }
+
+    /// Run every test declared in the manifest, in name order
+    pub async fn run_all(&self) -> TestReport {
+        let mut report = TestReport::default();
+        for (name, test) in &self.manifest.tests {
+            report.results.push(TestResult {
+                name: name.clone(),
+                outcome: self.run_test(test).await,
+            });
+        }
+        report
+    }
+
+    /// Call a method on a fresh host. The outer error is a harness failure, the
+    /// inner one the tapplet's result.
+    async fn call(
+        &self,
+        method: &str,
+        args: Value,
+        context: &CallContext,
+    ) -> Result<Result<Value, HostError>> {
+        match &self.source {
+            TestSource::Wasm(path) => {
+                let mut host = WasmTappletHost::new(self.manifest.clone(), path)
+                    .context("Failed to load WASM tapplet")?;
+                Ok(host.run(method, args, context))
+            }
+            TestSource::Lua(path) => {
+                let host = LuaTappletHost::new_shared(
+                    self.manifest.clone(),
+                    path,
+                    Arc::new(MemoryTappletApi::new()),
+                )
+                .context("Failed to load Lua tapplet")?
+                .with_api_v2();
+                Ok(host.run(method, args, context).await)
+            }
+        }
+    }
+}
+
+/// The manifest, runtime and code of a tapplet in a source or installed directory
+pub(crate) fn locate_entrypoint(dir: &Path) -> Result<(TappletManifest, RuntimeKind, PathBuf)> {
+    let Some(manifest_file) = find_manifest_file(dir) else {
+        return Err(TappletError::ManifestNotFound {
+            path: dir.to_path_buf(),
+        }
+        .into());
+    };
+    let manifest = TappletManifest::from_file(&manifest_file)?;
+
+    let wasm_path = dir.join(format!("{}.wasm", manifest.name));
+    let (kind, path) = match &manifest.runtime {
+        Some(runtime) => {
+            // Installed tapplets keep their entrypoint as `<name>.<ext>`
+            let installed = dir.join(format!("{}.{}", manifest.name, runtime.kind.extension()));
+            let path = if installed.exists() {
+                installed
+            } else {
+                runtime.entrypoint_path(dir)?
+            };
+            (runtime.kind, path)
+        }
+        None if wasm_path.exists() => (RuntimeKind::Wasm, wasm_path),
+        None => (RuntimeKind::Lua, find_lua_script(dir, &manifest.name)?),
+    };
+    Ok((manifest, kind, path))
+}
+
+/// `<name>.lua` for installed tapplets, otherwise the first Lua file in the directory
+fn find_lua_script(dir: &Path, name: &str) -> Result<PathBuf> {
+    let installed = dir.join(format!("{}.lua", name));
+    if installed.exists() {
+        return Ok(installed);
+    }
+    let mut scripts: Vec<_> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read tapplet directory: {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lua"))
+        .collect();
+    scripts.sort();
+    scripts.into_iter().next().ok_or_else(|| {
+        TappletError::ArtifactNotFound(format!(
+            "no WASM or Lua file for tapplet '{}' in {}",
+            name,
+            dir.display()
+        ))
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run_manifest_tests() {
+        let temp = tempfile::tempdir().unwrap();
+        let manifest = crate::test_utils::manifest_toml("greeter", "0.1.0")
+            + r#"
+[tests.greets]
+method = "greet"
+expected = "hello"
+
+[tests.wrong_result]
+method = "greet"
+expected = "goodbye"
+
+[tests.unknown_method]
+method = "missing"
+expected_error = "METHOD_NOT_FOUND"
+"#;
+        std::fs::write(temp.path().join("manifest.toml"), manifest).unwrap();
+        std::fs::write(
+            temp.path().join("main.lua"),
+            "function greet() return 'hello' end",
+        )
+        .unwrap();
+
+        let harness = TappletTestHarness::load(temp.path()).unwrap();
+        let report = harness.run_all().await;
+        assert_eq!(report.results.len(), 3);
+        let failures: Vec<_> = report.failures().map(|r| r.name.as_str()).collect();
+        assert_eq!(failures, vec!["wrong_result"]);
+        assert!(!report.is_success());
+    }
+}
@@ -0,0 +1,94 @@
+//! Logging through [`tracing`](https://docs.rs/tracing) when the `tracing` feature is enabled.
+//!
+//! Without the feature, warnings are written to stderr as before and spans and
+//! informational events are dropped.
+
+/// Something went wrong but the operation carried on, e.g. a manifest was skipped
+macro_rules! warning {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::warn!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        eprintln!("Warning: {}", format_args!($($arg)+));
+    }};
+}
+
+/// A step worth knowing about that isn't a problem
+macro_rules! info {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::info!($($arg)+);
+    }};
+}
+
+/// Enter an info-level span until the returned guard is dropped
+macro_rules! span {
+    ($name:literal $(, $($field:tt)+)?) => {{
+        #[cfg(feature = "tracing")]
+        let guard = ::tracing::info_span!($name $(, $($field)+)?).entered();
+        #[cfg(not(feature = "tracing"))]
+        let guard = $crate::trace::NoSpan;
+        guard
+    }};
+}
+
+pub(crate) use {info, span, warning};
+
+/// Stands in for an entered span when the `tracing` feature is disabled
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
+
+/// Warn that a tapplet method marked deprecated in its manifest was called, with
+/// the tapplet, method, `since` and `use_instead` as fields
+#[cfg(feature = "host-core")]
+pub(crate) fn deprecated_call(
+    tapplet: &str,
+    method: &str,
+    deprecation: &crate::model::MethodDeprecation,
+) {
+    #[cfg(feature = "tracing")]
+    ::tracing::warn!(
+        tapplet,
+        method,
+        since = deprecation.since.as_deref(),
+        use_instead = deprecation.use_instead.as_deref(),
+        "call to deprecated method: {}",
+        deprecation
+    );
+    #[cfg(not(feature = "tracing"))]
+    eprintln!("Warning: {}.{} is {}", tapplet, method, deprecation);
+}
+
+/// Run a tapplet method call in a `tapplet_call` span with the tapplet and method
+/// names, and log how long it took and, if it failed, its error code
+#[cfg(feature = "host-core")]
+pub(crate) fn call<T>(
+    tapplet: &str,
+    method: &str,
+    call: impl FnOnce() -> Result<T, crate::host::HostError>,
+) -> Result<T, crate::host::HostError> {
+    #[cfg(feature = "tracing")]
+    {
+        let span = ::tracing::info_span!(
+            "tapplet_call",
+            tapplet,
+            method,
+            duration_ms = ::tracing::field::Empty
+        );
+        let _guard = span.enter();
+        let started = std::time::Instant::now();
+        let result = call();
+        let duration_ms = started.elapsed().as_millis() as u64;
+        span.record("duration_ms", duration_ms);
+        match &result {
+            Ok(_) => ::tracing::debug!(duration_ms, "call finished"),
+            Err(e) => ::tracing::warn!(duration_ms, code = e.code(), error = %e, "call failed"),
+        }
+        result
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (tapplet, method);
+        call()
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::TappletManifest;
+use crate::error::TappletError;
+
+/// Verifies the signatures attached to a tapplet manifest
+pub trait SignatureVerifier: Send + Sync {
+    fn verify(&self, manifest: &TappletManifest) -> Result<()>;
+}
+
+/// Why a tapplet is not trusted by a [`TrustPolicy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrustViolation {
+    UntrustedPublisher { publisher: String },
+    UntrustedPublicKey { public_key: String },
+    PinnedKeyMismatch { expected: String, actual: String },
+    InvalidSignature(String),
+    DisallowedLicense { license: Option<String> },
+}
+
+impl fmt::Display for TrustViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrustViolation::UntrustedPublisher { publisher } => {
+                write!(f, "publisher {} is not trusted", publisher)
+            }
+            TrustViolation::UntrustedPublicKey { public_key } => {
+                write!(f, "public key {} is not trusted", public_key)
+            }
+            TrustViolation::PinnedKeyMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "public key {} does not match pinned key {}",
+                    actual, expected
+                )
+            }
+            TrustViolation::InvalidSignature(msg) => write!(f, "invalid signature: {}", msg),
+            TrustViolation::DisallowedLicense { license: None } => {
+                write!(f, "no license is declared")
+            }
+            TrustViolation::DisallowedLicense {
+                license: Some(license),
+            } => write!(f, "license {} is not allowed", license),
+        }
+    }
+}
+
+/// Decides which tapplets may be listed and installed.
+///
+/// An empty allowlist allows everything, so the default policy trusts all
+/// tapplets. Pinned keys are checked per tapplet name regardless of the
+/// allowlists.
+#[derive(Clone, Default)]
+pub struct TrustPolicy {
+    allowed_publishers: HashSet<String>,
+    allowed_public_keys: HashSet<String>,
+    pinned_keys: HashMap<String, String>,
+    allowed_licenses: HashSet<String>,
+    verifier: Option<Arc<dyn SignatureVerifier>>,
+}
+
+impl TrustPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only trust tapplets from this publisher (and any other allowed publishers)
+    pub fn allow_publisher<S: Into<String>>(mut self, publisher: S) -> Self {
+        self.allowed_publishers.insert(publisher.into());
+        self
+    }
+
+    /// Only trust tapplets signed with this public key (and any other allowed keys)
+    pub fn allow_public_key<S: Into<String>>(mut self, public_key: S) -> Self {
+        self.allowed_public_keys.insert(public_key.into());
+        self
+    }
+
+    /// Require the tapplet with this name to always use the given public key
+    pub fn pin_key<S: Into<String>>(mut self, tapplet_name: S, public_key: S) -> Self {
+        self.pinned_keys
+            .insert(tapplet_name.into().replace("-", "_"), public_key.into());
+        self
+    }
+
+    /// Only trust tapplets usable under this SPDX license (and any other allowed
+    /// licenses), see [`TappletManifest::licensed_under`]
+    pub fn allow_license<S: Into<String>>(mut self, license: S) -> Self {
+        self.allowed_licenses.insert(license.into());
+        self
+    }
+
+    /// Verify manifest signatures with the given verifier
+    pub fn with_verifier(mut self, verifier: Arc<dyn SignatureVerifier>) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    /// All the reasons a tapplet is not trusted, empty if it is trusted
+    pub fn check(&self, manifest: &TappletManifest) -> Vec<TrustViolation> {
+        let mut violations = Vec::new();
+
+        if !self.allowed_publishers.is_empty()
+            && !self.allowed_publishers.contains(&manifest.publisher)
+        {
+            violations.push(TrustViolation::UntrustedPublisher {
+                publisher: manifest.publisher.clone(),
+            });
+        }
+
+        if !self.allowed_public_keys.is_empty()
+            && !self.allowed_public_keys.contains(&manifest.public_key)
+        {
+            violations.push(TrustViolation::UntrustedPublicKey {
+                public_key: manifest.public_key.clone(),
+            });
+        }
+
+        if let Some(expected) = self.pinned_keys.get(&manifest.name.replace("-", "_"))
+            && expected != &manifest.public_key
+        {
+            violations.push(TrustViolation::PinnedKeyMismatch {
+                expected: expected.clone(),
+                actual: manifest.public_key.clone(),
+            });
+        }
+
+        if !self.allowed_licenses.is_empty()
+            && !manifest.licensed_under(self.allowed_licenses.iter().map(String::as_str))
+        {
+            violations.push(TrustViolation::DisallowedLicense {
+                license: manifest.license.clone(),
+            });
+        }
+
+        if let Some(verifier) = &self.verifier
+            && let Err(e) = verifier.verify(manifest)
+        {
+            violations.push(TrustViolation::InvalidSignature(e.to_string()));
+        }
+
+        violations
+    }
+
+    pub fn is_trusted(&self, manifest: &TappletManifest) -> bool {
+        self.check(manifest).is_empty()
+    }
+
+    /// Fail with all violations if a tapplet is not trusted
+    pub fn ensure_trusted(&self, manifest: &TappletManifest) -> Result<()> {
+        let violations = self.check(manifest);
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(TappletError::Untrusted {
+            name: manifest.canonical_name(),
+            violations,
+        }
+        .into())
+    }
+
+    /// Check a set of tapplets and report which are trusted and why the others are not
+    pub fn report<'a>(
+        &self,
+        tapplets: impl IntoIterator<Item = &'a TappletManifest>,
+    ) -> TrustReport {
+        let mut report = TrustReport::default();
+        for tapplet in tapplets {
+            let violations = self.check(tapplet);
+            if violations.is_empty() {
+                report.trusted.push(tapplet.canonical_name());
+            } else {
+                report
+                    .violations
+                    .push((tapplet.canonical_name(), violations));
+            }
+        }
+        report
+    }
+}
+
+/// Result of checking a set of tapplets against a [`TrustPolicy`]
+#[derive(Debug, Clone, Default)]
+pub struct TrustReport {
+    /// Canonical names of trusted tapplets
+    pub trusted: Vec<String>,
+    /// Canonical names of untrusted tapplets with their violations
+    pub violations: Vec<(String, Vec<TrustViolation>)>,
+}
+
+impl TrustReport {
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    fn manifest(name: &str) -> TappletManifest {
+        TappletManifest::from_toml_str(&test_utils::manifest_toml(name, "0.1.0")).unwrap()
+    }
+
+    struct RejectAll;
+
+    impl SignatureVerifier for RejectAll {
+        fn verify(&self, _manifest: &TappletManifest) -> Result<()> {
+            anyhow::bail!("no signature")
+        }
+    }
+
+    #[test]
+    fn test_default_policy_trusts_everything() {
+        assert!(TrustPolicy::default().is_trusted(&manifest("wallet")));
+    }
+
+    #[test]
+    fn test_allowlists_and_pinning() {
+        let policy = TrustPolicy::new()
+            .allow_publisher("official")
+            .pin_key("price-alert", "pinned_key");
+        let tapplet = manifest("price_alert");
+
+        assert_eq!(
+            policy.check(&tapplet),
+            vec![
+                TrustViolation::UntrustedPublisher {
+                    publisher: "test_publisher".to_string()
+                },
+                TrustViolation::PinnedKeyMismatch {
+                    expected: "pinned_key".to_string(),
+                    actual: "test_public_key".to_string()
+                },
+            ]
+        );
+
+        let policy = TrustPolicy::new()
+            .allow_publisher("test_publisher")
+            .allow_public_key("test_public_key");
+        assert!(policy.ensure_trusted(&tapplet).is_ok());
+    }
+
+    #[test]
+    fn test_allowed_licenses() {
+        let policy = TrustPolicy::new()
+            .allow_license("apache-2.0")
+            .allow_license("MIT");
+        let mut tapplet = manifest("wallet");
+        assert!(policy.is_trusted(&tapplet));
+        tapplet.license = Some("GPL-3.0-only OR Apache-2.0".to_string());
+        assert!(policy.is_trusted(&tapplet));
+        tapplet.license = Some("MIT AND GPL-3.0-only".to_string());
+        assert_eq!(
+            policy.check(&tapplet),
+            vec![TrustViolation::DisallowedLicense {
+                license: Some("MIT AND GPL-3.0-only".to_string())
+            }]
+        );
+        tapplet.license = None;
+        let err = policy.ensure_trusted(&tapplet).unwrap_err();
+        assert!(err.to_string().contains("no license is declared"));
+    }
+
+    #[test]
+    fn test_signature_verifier_and_report() {
+        let policy = TrustPolicy::new().with_verifier(Arc::new(RejectAll));
+        let tapplets = [manifest("a"), manifest("b")];
+        let report = policy.report(&tapplets);
+        assert!(!report.is_clean());
+        assert!(report.trusted.is_empty());
+        assert_eq!(report.violations.len(), 2);
+        let err = policy.ensure_trusted(&tapplets[0]).unwrap_err();
+        assert_eq!(crate::error::error_code(&err), "SIGNATURE_INVALID");
+    }
+}
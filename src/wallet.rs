@@ -0,0 +1,227 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::call_context::CallContext;
+use crate::chain::block_on;
+use crate::host::{HostError, MinotariTappletApiV1};
+
+/// Permission required to call `minotari_get_balance`
+pub const PERMISSION_READ_BALANCE: &str = "wallet:read_balance";
+/// Permission required to call `minotari_send_transaction`
+pub const PERMISSION_SEND_TRANSACTION: &str = "wallet:send_transaction";
+/// Permission required to call `minotari_get_transactions`
+pub const PERMISSION_READ_TRANSACTIONS: &str = "wallet:read_transactions";
+
+/// Wallet balance in microMinotari
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    pub available: u64,
+    pub pending_incoming: u64,
+    pub pending_outgoing: u64,
+    pub timelocked: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionDirection {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    Pending,
+    Completed,
+    Cancelled,
+}
+
+impl TransactionDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionDirection::Inbound => "inbound",
+            TransactionDirection::Outbound => "outbound",
+        }
+    }
+}
+
+impl TransactionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionStatus::Pending => "pending",
+            TransactionStatus::Completed => "completed",
+            TransactionStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionInfo {
+    pub id: String,
+    pub direction: TransactionDirection,
+    pub status: TransactionStatus,
+    /// Amount in microMinotari
+    pub amount: u64,
+    /// Fee in microMinotari
+    pub fee: u64,
+    /// Destination address for outbound, source address for inbound transactions
+    pub counterparty: String,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+}
+
+/// Which transactions `get_transactions` should return
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionFilter {
+    #[serde(default)]
+    pub direction: Option<TransactionDirection>,
+    #[serde(default)]
+    pub status: Option<TransactionStatus>,
+    /// Return at most this many transactions, newest first
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl TransactionFilter {
+    pub fn matches(&self, transaction: &TransactionInfo) -> bool {
+        self.direction.is_none_or(|d| d == transaction.direction)
+            && self.status.is_none_or(|s| s == transaction.status)
+    }
+}
+
+/// Wallet surface on top of the storage API.
+///
+/// Exposed to tapplets as `minotari_get_balance`, `minotari_send_transaction`
+/// and `minotari_get_transactions` once enabled with
+/// [`crate::host::LuaTappletHost::with_api_v2`] or given to a WASM host with
+/// [`crate::host::WasmTappletHost::with_wallet_api`]. Each function requires its
+/// `PERMISSION_*` permission in the call context.
+#[async_trait]
+pub trait MinotariTappletApiV2: MinotariTappletApiV1 {
+    async fn get_balance(&self) -> Result<Balance, anyhow::Error>;
+    /// Send `amount` microMinotari to `destination`, returning the transaction id
+    async fn send_transaction(
+        &self,
+        destination: &str,
+        amount: u64,
+        fee: u64,
+    ) -> Result<String, anyhow::Error>;
+    async fn get_transactions(
+        &self,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionInfo>, anyhow::Error>;
+}
+
+#[async_trait]
+impl<T: MinotariTappletApiV2 + ?Sized> MinotariTappletApiV2 for Arc<T> {
+    async fn get_balance(&self) -> Result<Balance, anyhow::Error> {
+        (**self).get_balance().await
+    }
+
+    async fn send_transaction(
+        &self,
+        destination: &str,
+        amount: u64,
+        fee: u64,
+    ) -> Result<String, anyhow::Error> {
+        (**self).send_transaction(destination, amount, fee).await
+    }
+
+    async fn get_transactions(
+        &self,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionInfo>, anyhow::Error> {
+        (**self).get_transactions(filter).await
+    }
+}
+
+/// The wallet API of one WASM host and the context of the call it is running,
+/// shared with its host functions
+#[derive(Clone, Default)]
+pub(crate) struct GuestWallet {
+    api: Arc<Mutex<Option<Arc<dyn MinotariTappletApiV2>>>>,
+    context: Arc<Mutex<Option<CallContext>>>,
+}
+
+impl GuestWallet {
+    pub fn set_api(&self, api: Option<Arc<dyn MinotariTappletApiV2>>) {
+        *self.api.lock().unwrap() = api;
+    }
+
+    /// Check the permissions of wallet calls against `context` until it is unset
+    pub fn set_context(&self, context: Option<CallContext>) {
+        *self.context.lock().unwrap() = context;
+    }
+
+    pub fn balance(&self) -> Result<Balance, HostError> {
+        let api = self.api(PERMISSION_READ_BALANCE)?;
+        block_on(async move { api.get_balance().await })
+    }
+
+    pub fn send_transaction(
+        &self,
+        destination: &str,
+        amount: u64,
+        fee: u64,
+    ) -> Result<String, HostError> {
+        let api = self.api(PERMISSION_SEND_TRANSACTION)?;
+        let destination = destination.to_string();
+        block_on(async move { api.send_transaction(&destination, amount, fee).await })
+    }
+
+    pub fn transactions(
+        &self,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionInfo>, HostError> {
+        let api = self.api(PERMISSION_READ_TRANSACTIONS)?;
+        let filter = filter.clone();
+        block_on(async move { api.get_transactions(&filter).await })
+    }
+
+    /// The API, if the running call has `permission`
+    fn api(&self, permission: &str) -> Result<Arc<dyn MinotariTappletApiV2>, HostError> {
+        match &*self.context.lock().unwrap() {
+            Some(context) => context.require_permission(permission)?,
+            None => {
+                return Err(HostError::PermissionDenied(format!(
+                    "{} needs a call context",
+                    permission
+                )));
+            }
+        }
+        self.api.lock().unwrap().clone().ok_or_else(|| {
+            HostError::PermissionDenied("the wallet API is not enabled in this host".to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transaction_filter() {
+        let transaction = TransactionInfo {
+            id: "1".to_string(),
+            direction: TransactionDirection::Outbound,
+            status: TransactionStatus::Completed,
+            amount: 5_000_000_000,
+            fee: 25,
+            counterparty: "address".to_string(),
+            timestamp: 0,
+        };
+        assert!(TransactionFilter::default().matches(&transaction));
+        let filter: TransactionFilter =
+            serde_json::from_str(r#"{"direction": "outbound", "status": "pending"}"#).unwrap();
+        assert!(!filter.matches(&transaction));
+        assert!(
+            TransactionFilter {
+                status: Some(TransactionStatus::Completed),
+                ..filter
+            }
+            .matches(&transaction)
+        );
+    }
+}
@@ -0,0 +1,1035 @@
+//! An opt-in, fully virtual WASI environment for WASM tapplets.
+//!
+//! Modules built with a `wasm32-wasip1` toolchain (Rust std, TinyGo, AssemblyScript's
+//! WASI shim) import `wasi_snapshot_preview1` functions for their file, clock and random
+//! number access. A host created with [`crate::host::WasmTappletHost::new_with_wasi`]
+//! provides them from [`WasiOptions`] instead of the real system, with either engine:
+//!
+//! - files live in a [`VirtualFs`] preopened as `/`, which the embedder can fill
+//!   before and read after calls
+//! - stdout and stderr go to a [`LogSink`] as `info` and `warn` records
+//! - clocks return the time set with [`WasiOptions::with_clock`], and random bytes come
+//!   from a generator seeded with [`WasiOptions::with_random_seed`], so calls are
+//!   reproducible
+//! - sockets, directories and every other function fail with `ENOSYS`
+//! - calls over the host's rate limit, if it has one, fail with `EAGAIN`
+//!
+//! ```rust,ignore
+//! let wasi = WasiOptions::new()
+//!     .with_file("config.json", br#"{"fee": 5}"#.to_vec())
+//!     .with_log_sink(log_sink);
+//! let mut host = WasmTappletHost::new_with_wasi(config, "tapplet.wasm", wasi)?;
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::log_sink::{LogLevel, LogRecord, LogSink};
+use crate::rate_limit::RateLimiter;
+
+/// The import module WASI preview 1 functions are taken from
+pub const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+/// Time reported to the tapplet by `clock_time_get`, in nanoseconds since the Unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WasiClock {
+    /// Every read returns the same time
+    Fixed(u64),
+    /// The first read returns `start`, and each following read `step` nanoseconds later
+    Ticking { start: u64, step: u64 },
+}
+
+impl Default for WasiClock {
+    fn default() -> Self {
+        WasiClock::Fixed(0)
+    }
+}
+
+/// Files a WASI tapplet can see, by path relative to `/`.
+///
+/// Clones share the same files, so keep one to inspect what the tapplet wrote.
+/// There are no directories: `a/b.txt` is simply a file with a slash in its name.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualFs {
+    files: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+}
+
+impl VirtualFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create or replace a file
+    pub fn insert(&self, path: &str, contents: Vec<u8>) {
+        if let Some(path) = normalize_path(path) {
+            self.files.lock().unwrap().insert(path, contents);
+        }
+    }
+
+    pub fn read(&self, path: &str) -> Option<Vec<u8>> {
+        let path = normalize_path(path)?;
+        self.files.lock().unwrap().get(&path).cloned()
+    }
+
+    pub fn remove(&self, path: &str) -> Option<Vec<u8>> {
+        let path = normalize_path(path)?;
+        self.files.lock().unwrap().remove(&path)
+    }
+
+    /// Paths of all files, sorted
+    pub fn paths(&self) -> Vec<String> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+
+    fn len(&self, path: &str) -> Option<u64> {
+        let files = self.files.lock().unwrap();
+        files.get(path).map(|contents| contents.len() as u64)
+    }
+
+    fn read_at(&self, path: &str, position: u64, len: usize) -> Option<Vec<u8>> {
+        let files = self.files.lock().unwrap();
+        let contents = files.get(path)?;
+        let start = (position as usize).min(contents.len());
+        let end = start.saturating_add(len).min(contents.len());
+        Some(contents[start..end].to_vec())
+    }
+
+    fn write_at(&self, path: &str, position: u64, data: &[u8]) -> Option<()> {
+        let mut files = self.files.lock().unwrap();
+        let contents = files.get_mut(path)?;
+        let start = position as usize;
+        let end = start + data.len();
+        if contents.len() < end {
+            contents.resize(end, 0);
+        }
+        contents[start..end].copy_from_slice(data);
+        Some(())
+    }
+}
+
+/// Strip `/` and `.` components, or `None` if the path escapes the root with `..`
+fn normalize_path(path: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            part => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// What a WASI tapplet sees of the outside world
+#[derive(Clone, Default)]
+pub struct WasiOptions {
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    fs: VirtualFs,
+    clock: WasiClock,
+    random_seed: u64,
+    log: Option<Arc<dyn LogSink>>,
+}
+
+impl WasiOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a command line argument after the program name, which is the tapplet name
+    pub fn with_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Set an environment variable. The environment is empty by default.
+    pub fn with_env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Add a file to the virtual filesystem
+    pub fn with_file(self, path: &str, contents: Vec<u8>) -> Self {
+        self.fs.insert(path, contents);
+        self
+    }
+
+    /// Use an existing filesystem, e.g. to share files between tapplets
+    pub fn with_filesystem(mut self, fs: VirtualFs) -> Self {
+        self.fs = fs;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: WasiClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Seed of the generator behind `random_get`. Hosts with the same seed see
+    /// the same bytes.
+    pub fn with_random_seed(mut self, seed: u64) -> Self {
+        self.random_seed = seed;
+        self
+    }
+
+    /// Send lines written to stdout and stderr to `sink`. Without a sink they are
+    /// discarded.
+    pub fn with_log_sink(mut self, sink: Arc<dyn LogSink>) -> Self {
+        self.log = Some(sink);
+        self
+    }
+
+    pub fn filesystem(&self) -> &VirtualFs {
+        &self.fs
+    }
+}
+
+// Error numbers from the WASI preview 1 specification
+const SUCCESS: i32 = 0;
+const EAGAIN: i32 = 6;
+const EBADF: i32 = 8;
+const EEXIST: i32 = 20;
+pub(crate) const EFAULT: i32 = 21;
+const EINVAL: i32 = 28;
+const EISDIR: i32 = 31;
+const ENOENT: i32 = 44;
+const ENOSYS: i32 = 52;
+const ENOTDIR: i32 = 54;
+const ESPIPE: i32 = 70;
+const ENOTCAPABLE: i32 = 76;
+
+const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+const FILETYPE_DIRECTORY: u8 = 3;
+const FILETYPE_REGULAR_FILE: u8 = 4;
+
+const OFLAGS_CREAT: i32 = 1;
+const OFLAGS_DIRECTORY: i32 = 2;
+const OFLAGS_EXCL: i32 = 4;
+const OFLAGS_TRUNC: i32 = 8;
+const FDFLAGS_APPEND: i32 = 1;
+
+const ALL_RIGHTS: u64 = (1 << 30) - 1;
+const RIGHT_FD_SEEK: u64 = 1 << 2;
+const RIGHT_FD_TELL: u64 = 1 << 5;
+
+/// The file descriptor of the `/` preopen
+const ROOT_FD: u32 = 3;
+
+/// Largest buffer a module may pass to a WASI function, in bytes
+const MAX_GUEST_BUFFER: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+enum Descriptor {
+    Stdin,
+    Stdout,
+    Stderr,
+    Root,
+    File {
+        path: String,
+        position: u64,
+        append: bool,
+    },
+}
+
+impl Descriptor {
+    fn filetype(&self) -> u8 {
+        match self {
+            Descriptor::Stdin | Descriptor::Stdout | Descriptor::Stderr => {
+                FILETYPE_CHARACTER_DEVICE
+            }
+            Descriptor::Root => FILETYPE_DIRECTORY,
+            Descriptor::File { .. } => FILETYPE_REGULAR_FILE,
+        }
+    }
+}
+
+/// State of one instance's WASI functions
+pub(crate) struct WasiEnv {
+    tapplet: String,
+    args: Vec<Vec<u8>>,
+    env: Vec<Vec<u8>>,
+    fs: VirtualFs,
+    clock: WasiClock,
+    clock_reads: u64,
+    random_state: u64,
+    log: Option<Arc<dyn LogSink>>,
+    fds: BTreeMap<u32, Descriptor>,
+    next_fd: u32,
+    limiter: RateLimiter,
+}
+
+impl WasiEnv {
+    pub fn new(options: &WasiOptions, tapplet: &str) -> Self {
+        let nul_terminated = |s: String| {
+            let mut bytes = s.into_bytes();
+            bytes.push(0);
+            bytes
+        };
+        let args = std::iter::once(tapplet.to_string())
+            .chain(options.args.iter().cloned())
+            .map(nul_terminated)
+            .collect();
+        let env = options
+            .env
+            .iter()
+            .map(|(key, value)| nul_terminated(format!("{}={}", key, value)))
+            .collect();
+        let fds = BTreeMap::from([
+            (0, Descriptor::Stdin),
+            (1, Descriptor::Stdout),
+            (2, Descriptor::Stderr),
+            (ROOT_FD, Descriptor::Root),
+        ]);
+        Self {
+            tapplet: tapplet.to_string(),
+            args,
+            env,
+            fs: options.fs.clone(),
+            clock: options.clock,
+            clock_reads: 0,
+            random_state: options.random_seed,
+            log: options.log.clone(),
+            fds,
+            next_fd: ROOT_FD + 1,
+            limiter: RateLimiter::default(),
+        }
+    }
+
+    /// Limit how often the module may call WASI functions
+    pub fn set_rate_limiter(&mut self, limiter: RateLimiter) {
+        self.limiter = limiter;
+    }
+
+    fn now(&mut self) -> u64 {
+        let reads = self.clock_reads;
+        self.clock_reads += 1;
+        match self.clock {
+            WasiClock::Fixed(time) => time,
+            WasiClock::Ticking { start, step } => start.wrapping_add(step.wrapping_mul(reads)),
+        }
+    }
+
+    /// The next value of a splitmix64 generator
+    fn next_random(&mut self) -> u64 {
+        self.random_state = self.random_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.random_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn log(&self, level: LogLevel, output: &[u8]) {
+        let Some(sink) = &self.log else {
+            return;
+        };
+        for line in String::from_utf8_lossy(output).lines() {
+            sink.log(LogRecord {
+                tapplet: self.tapplet.clone(),
+                level,
+                message: line.to_string(),
+            });
+        }
+    }
+
+    fn descriptor(&self, fd: i32) -> Result<Descriptor, i32> {
+        self.fds.get(&(fd as u32)).cloned().ok_or(EBADF)
+    }
+
+    /// Resolve `path` relative to the directory `fd`, which must be the root
+    fn resolve(&self, fd: i32, path: &[u8]) -> Result<String, i32> {
+        if !matches!(self.descriptor(fd)?, Descriptor::Root) {
+            return Err(ENOTDIR);
+        }
+        let path = std::str::from_utf8(path).map_err(|_| EINVAL)?;
+        normalize_path(path).ok_or(ENOTCAPABLE)
+    }
+
+    fn set_position(&mut self, fd: i32, new_position: u64) {
+        if let Some(Descriptor::File { position, .. }) = self.fds.get_mut(&(fd as u32)) {
+            *position = new_position;
+        }
+    }
+}
+
+/// Linear memory of the instance a WASI or host function was called from, as each
+/// engine exposes it
+pub trait GuestMemory {
+    /// Fill `buf` from `offset`, returning false if that's out of bounds
+    fn read(&self, offset: u64, buf: &mut [u8]) -> bool;
+    /// Copy `data` to `offset`, returning false if that's out of bounds
+    fn write(&mut self, offset: u64, data: &[u8]) -> bool;
+    /// Size of the memory in bytes, to check lengths from the guest against
+    /// before allocating for them
+    fn size(&self) -> u64;
+}
+
+impl GuestMemory for &mut [u8] {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> bool {
+        let Some(source) = usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.get(start..start.checked_add(buf.len())?))
+        else {
+            return false;
+        };
+        buf.copy_from_slice(source);
+        true
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> bool {
+        let Some(target) = usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.get_mut(start..start.checked_add(data.len())?))
+        else {
+            return false;
+        };
+        target.copy_from_slice(data);
+        true
+    }
+
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+/// Reads and writes guest memory, failing with `EFAULT` outside of it
+pub(crate) struct Guest<'a>(&'a mut dyn GuestMemory);
+
+impl<'a> Guest<'a> {
+    pub fn new(memory: &'a mut dyn GuestMemory) -> Self {
+        Self(memory)
+    }
+
+    /// Check a buffer the module passed before allocating for it
+    fn check(&self, ptr: i32, len: i32) -> Result<usize, i32> {
+        let (ptr, len) = (ptr as u32 as u64, len as u32 as u64);
+        if len > MAX_GUEST_BUFFER as u64 {
+            return Err(EINVAL);
+        }
+        if ptr + len > self.0.size() {
+            return Err(EFAULT);
+        }
+        Ok(len as usize)
+    }
+
+    fn read(&self, ptr: i32, len: i32) -> Result<Vec<u8>, i32> {
+        let mut buf = vec![0; self.check(ptr, len)?];
+        if !self.0.read(ptr as u32 as u64, &mut buf) {
+            return Err(EFAULT);
+        }
+        Ok(buf)
+    }
+
+    fn read_u32(&self, ptr: i32) -> Result<u32, i32> {
+        let bytes = self.read(ptr, 4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn write(&mut self, ptr: i32, data: &[u8]) -> Result<(), i32> {
+        if !self.0.write(ptr as u32 as u64, data) {
+            return Err(EFAULT);
+        }
+        Ok(())
+    }
+
+    fn write_u32(&mut self, ptr: i32, value: u32) -> Result<(), i32> {
+        self.write(ptr, &value.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, ptr: i32, value: u64) -> Result<(), i32> {
+        self.write(ptr, &value.to_le_bytes())
+    }
+
+    /// The buffers of an iovec array as `(ptr, len)` pairs
+    fn iovecs(&self, iovs: i32, iovs_len: i32) -> Result<Vec<(i32, i32)>, i32> {
+        (0..iovs_len)
+            .map(|i| {
+                let iov = iovs.wrapping_add(i * 8);
+                Ok((
+                    self.read_u32(iov)? as i32,
+                    self.read_u32(iov.wrapping_add(4))? as i32,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Fail with `EAGAIN` if the module is calling WASI functions faster than its
+/// rate limit allows
+pub(crate) fn limit(state: &WasiEnv, function: &str) -> Result<(), i32> {
+    state.limiter.check(function).map_err(|_| EAGAIN)
+}
+
+/// The error number a WASI function returns for `result`
+pub(crate) fn errno(result: Result<(), i32>) -> i32 {
+    match result {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+/// Calls `$define!(name(arg: type, ...))` for every WASI function with a virtual
+/// implementation in this module, which engines wrap as host functions taking the
+/// same arguments. `proc_exit`, which ends the call instead of returning, is left
+/// to each engine.
+macro_rules! wasi_functions {
+    ($define:ident) => {
+        $define!(args_get(ptrs: i32, buf: i32));
+        $define!(args_sizes_get(count: i32, size: i32));
+        $define!(environ_get(ptrs: i32, buf: i32));
+        $define!(environ_sizes_get(count: i32, size: i32));
+        $define!(clock_res_get(id: i32, resolution: i32));
+        $define!(clock_time_get(id: i32, precision: i64, time: i32));
+        $define!(random_get(buf: i32, len: i32));
+        $define!(fd_write(fd: i32, iovs: i32, iovs_len: i32, written: i32));
+        $define!(fd_read(fd: i32, iovs: i32, iovs_len: i32, read: i32));
+        $define!(fd_seek(fd: i32, offset: i64, whence: i32, result: i32));
+        $define!(fd_tell(fd: i32, result: i32));
+        $define!(fd_close(fd: i32));
+        $define!(fd_sync(fd: i32));
+        $define!(fd_datasync(fd: i32));
+        $define!(fd_fdstat_get(fd: i32, stat: i32));
+        $define!(fd_fdstat_set_flags(fd: i32, flags: i32));
+        $define!(fd_prestat_get(fd: i32, prestat: i32));
+        $define!(fd_prestat_dir_name(fd: i32, path: i32, len: i32));
+        $define!(fd_filestat_get(fd: i32, stat: i32));
+        $define!(path_filestat_get(fd: i32, flags: i32, path: i32, path_len: i32, stat: i32));
+        $define!(path_open(
+            fd: i32,
+            dirflags: i32,
+            path: i32,
+            path_len: i32,
+            oflags: i32,
+            rights_base: i64,
+            rights_inheriting: i64,
+            fdflags: i32,
+            opened: i32
+        ));
+        $define!(path_unlink_file(fd: i32, path: i32, path_len: i32));
+        $define!(sched_yield());
+    };
+}
+
+pub(crate) use wasi_functions;
+
+/// Whether the virtual environment implements the WASI function `name`; engines
+/// stub out the others with [`unsupported`]
+pub(crate) fn is_provided(name: &str) -> bool {
+    macro_rules! matches_name {
+        ($function:ident($($arg:ident: $ty:ty),*)) => {
+            if name == stringify!($function) {
+                return true;
+            }
+        };
+    }
+    wasi_functions!(matches_name);
+    name == "proc_exit"
+}
+
+/// What a WASI function without a virtual implementation returns
+pub(crate) const UNSUPPORTED: i32 = ENOSYS;
+
+/// The error `proc_exit` ends the call with
+pub(crate) fn exit_message(code: i32) -> String {
+    format!("tapplet exited with code {}", code)
+}
+
+fn sizes(items: &[Vec<u8>], guest: &mut Guest, count_ptr: i32, size_ptr: i32) -> Result<(), i32> {
+    guest.write_u32(count_ptr, items.len() as u32)?;
+    guest.write_u32(size_ptr, items.iter().map(Vec::len).sum::<usize>() as u32)
+}
+
+fn strings(items: &[Vec<u8>], guest: &mut Guest, ptrs: i32, buf: i32) -> Result<(), i32> {
+    let mut offset = buf;
+    for (i, item) in items.iter().enumerate() {
+        guest.write_u32(ptrs.wrapping_add(i as i32 * 4), offset as u32)?;
+        guest.write(offset, item)?;
+        offset = offset.wrapping_add(item.len() as i32);
+    }
+    Ok(())
+}
+
+pub(crate) fn args_sizes_get(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    count: i32,
+    size: i32,
+) -> Result<(), i32> {
+    sizes(&state.args, guest, count, size)
+}
+
+pub(crate) fn args_get(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    ptrs: i32,
+    buf: i32,
+) -> Result<(), i32> {
+    strings(&state.args, guest, ptrs, buf)
+}
+
+pub(crate) fn environ_sizes_get(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    count: i32,
+    size: i32,
+) -> Result<(), i32> {
+    sizes(&state.env, guest, count, size)
+}
+
+pub(crate) fn environ_get(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    ptrs: i32,
+    buf: i32,
+) -> Result<(), i32> {
+    strings(&state.env, guest, ptrs, buf)
+}
+
+pub(crate) fn clock_res_get(
+    _state: &mut WasiEnv,
+    guest: &mut Guest,
+    id: i32,
+    resolution: i32,
+) -> Result<(), i32> {
+    if !(0..=3).contains(&id) {
+        return Err(EINVAL);
+    }
+    guest.write_u64(resolution, 1)
+}
+
+pub(crate) fn clock_time_get(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    id: i32,
+    _precision: i64,
+    time: i32,
+) -> Result<(), i32> {
+    if !(0..=3).contains(&id) {
+        return Err(EINVAL);
+    }
+    guest.write_u64(time, state.now())
+}
+
+pub(crate) fn random_get(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    buf: i32,
+    len: i32,
+) -> Result<(), i32> {
+    let len = guest.check(buf, len)?;
+    let mut bytes = Vec::with_capacity(len + 8);
+    while bytes.len() < len {
+        bytes.extend_from_slice(&state.next_random().to_le_bytes());
+    }
+    bytes.truncate(len);
+    guest.write(buf, &bytes)
+}
+
+pub(crate) fn fd_write(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    fd: i32,
+    iovs: i32,
+    iovs_len: i32,
+    written: i32,
+) -> Result<(), i32> {
+    let mut data = Vec::new();
+    for (ptr, len) in guest.iovecs(iovs, iovs_len)? {
+        data.extend(guest.read(ptr, len)?);
+        if data.len() > MAX_GUEST_BUFFER {
+            return Err(EINVAL);
+        }
+    }
+    match state.descriptor(fd)? {
+        Descriptor::Stdout => state.log(LogLevel::Info, &data),
+        Descriptor::Stderr => state.log(LogLevel::Warn, &data),
+        Descriptor::Stdin | Descriptor::Root => return Err(EBADF),
+        Descriptor::File {
+            path,
+            position,
+            append,
+        } => {
+            let position = if append {
+                state.fs.len(&path).ok_or(ENOENT)?
+            } else {
+                position
+            };
+            state.fs.write_at(&path, position, &data).ok_or(ENOENT)?;
+            state.set_position(fd, position + data.len() as u64);
+        }
+    }
+    guest.write_u32(written, data.len() as u32)
+}
+
+pub(crate) fn fd_read(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    fd: i32,
+    iovs: i32,
+    iovs_len: i32,
+    read: i32,
+) -> Result<(), i32> {
+    let mut total = 0;
+    match state.descriptor(fd)? {
+        Descriptor::Stdin => {}
+        Descriptor::File { path, position, .. } => {
+            let mut position = position;
+            for (ptr, len) in guest.iovecs(iovs, iovs_len)? {
+                let data = state
+                    .fs
+                    .read_at(&path, position, len as u32 as usize)
+                    .ok_or(ENOENT)?;
+                guest.write(ptr, &data)?;
+                position += data.len() as u64;
+                total += data.len();
+                if data.len() < len as u32 as usize {
+                    break;
+                }
+            }
+            state.set_position(fd, position);
+        }
+        Descriptor::Root => return Err(EISDIR),
+        Descriptor::Stdout | Descriptor::Stderr => return Err(EBADF),
+    }
+    guest.write_u32(read, total as u32)
+}
+
+pub(crate) fn fd_seek(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    fd: i32,
+    offset: i64,
+    whence: i32,
+    result: i32,
+) -> Result<(), i32> {
+    let Descriptor::File { path, position, .. } = state.descriptor(fd)? else {
+        return Err(ESPIPE);
+    };
+    let base = match whence {
+        0 => 0,
+        1 => position,
+        2 => state.fs.len(&path).ok_or(ENOENT)?,
+        _ => return Err(EINVAL),
+    };
+    let new_position = base.checked_add_signed(offset).ok_or(EINVAL)?;
+    state.set_position(fd, new_position);
+    guest.write_u64(result, new_position)
+}
+
+pub(crate) fn fd_tell(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    fd: i32,
+    result: i32,
+) -> Result<(), i32> {
+    match state.descriptor(fd)? {
+        Descriptor::File { position, .. } => guest.write_u64(result, position),
+        _ => Err(ESPIPE),
+    }
+}
+
+pub(crate) fn fd_close(state: &mut WasiEnv, _guest: &mut Guest, fd: i32) -> Result<(), i32> {
+    state.fds.remove(&(fd as u32)).map(|_| ()).ok_or(EBADF)
+}
+
+pub(crate) fn fd_sync(state: &mut WasiEnv, _guest: &mut Guest, fd: i32) -> Result<(), i32> {
+    state.descriptor(fd).map(|_| ())
+}
+
+pub(crate) fn fd_datasync(state: &mut WasiEnv, guest: &mut Guest, fd: i32) -> Result<(), i32> {
+    fd_sync(state, guest, fd)
+}
+
+pub(crate) fn fd_fdstat_get(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    fd: i32,
+    stat: i32,
+) -> Result<(), i32> {
+    let descriptor = state.descriptor(fd)?;
+    let (flags, rights) = match &descriptor {
+        Descriptor::File { append, .. } => (*append as u16, ALL_RIGHTS),
+        Descriptor::Root => (0, ALL_RIGHTS),
+        // Streams can't seek, which is how libc's isatty recognizes them
+        _ => (0, ALL_RIGHTS & !(RIGHT_FD_SEEK | RIGHT_FD_TELL)),
+    };
+    let mut buf = [0u8; 24];
+    buf[0] = descriptor.filetype();
+    buf[2..4].copy_from_slice(&flags.to_le_bytes());
+    buf[8..16].copy_from_slice(&rights.to_le_bytes());
+    buf[16..24].copy_from_slice(&ALL_RIGHTS.to_le_bytes());
+    guest.write(stat, &buf)
+}
+
+pub(crate) fn fd_fdstat_set_flags(
+    state: &mut WasiEnv,
+    _guest: &mut Guest,
+    fd: i32,
+    flags: i32,
+) -> Result<(), i32> {
+    state.descriptor(fd)?;
+    if let Some(Descriptor::File { append, .. }) = state.fds.get_mut(&(fd as u32)) {
+        *append = flags & FDFLAGS_APPEND != 0;
+    }
+    Ok(())
+}
+
+pub(crate) fn fd_prestat_get(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    fd: i32,
+    prestat: i32,
+) -> Result<(), i32> {
+    if fd as u32 != ROOT_FD || !state.fds.contains_key(&ROOT_FD) {
+        return Err(EBADF);
+    }
+    // A directory preopen whose name is "/"
+    guest.write_u32(prestat, 0)?;
+    guest.write_u32(prestat.wrapping_add(4), 1)
+}
+
+pub(crate) fn fd_prestat_dir_name(
+    _state: &mut WasiEnv,
+    guest: &mut Guest,
+    fd: i32,
+    path: i32,
+    len: i32,
+) -> Result<(), i32> {
+    if fd as u32 != ROOT_FD {
+        return Err(EBADF);
+    }
+    if len < 1 {
+        return Err(EINVAL);
+    }
+    guest.write(path, b"/")
+}
+
+/// Write a `filestat` for a file of type `filetype` and `size` bytes. Timestamps are 0.
+fn write_filestat(guest: &mut Guest, ptr: i32, filetype: u8, size: u64) -> Result<(), i32> {
+    let mut buf = [0u8; 64];
+    buf[16] = filetype;
+    buf[24..32].copy_from_slice(&1u64.to_le_bytes());
+    buf[32..40].copy_from_slice(&size.to_le_bytes());
+    guest.write(ptr, &buf)
+}
+
+pub(crate) fn fd_filestat_get(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    fd: i32,
+    stat: i32,
+) -> Result<(), i32> {
+    let descriptor = state.descriptor(fd)?;
+    let size = match &descriptor {
+        Descriptor::File { path, .. } => state.fs.len(path).ok_or(ENOENT)?,
+        _ => 0,
+    };
+    write_filestat(guest, stat, descriptor.filetype(), size)
+}
+
+pub(crate) fn path_filestat_get(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    fd: i32,
+    _flags: i32,
+    path: i32,
+    path_len: i32,
+    stat: i32,
+) -> Result<(), i32> {
+    let path = state.resolve(fd, &guest.read(path, path_len)?)?;
+    if path.is_empty() {
+        return write_filestat(guest, stat, FILETYPE_DIRECTORY, 0);
+    }
+    let size = state.fs.len(&path).ok_or(ENOENT)?;
+    write_filestat(guest, stat, FILETYPE_REGULAR_FILE, size)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn path_open(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    fd: i32,
+    _dirflags: i32,
+    path: i32,
+    path_len: i32,
+    oflags: i32,
+    _rights_base: i64,
+    _rights_inheriting: i64,
+    fdflags: i32,
+    opened: i32,
+) -> Result<(), i32> {
+    let path = state.resolve(fd, &guest.read(path, path_len)?)?;
+    let descriptor = if path.is_empty() {
+        Descriptor::Root
+    } else {
+        let exists = state.fs.len(&path).is_some();
+        if oflags & OFLAGS_DIRECTORY != 0 {
+            return Err(if exists { ENOTDIR } else { ENOENT });
+        }
+        if exists && oflags & OFLAGS_EXCL != 0 {
+            return Err(EEXIST);
+        }
+        if !exists && oflags & OFLAGS_CREAT == 0 {
+            return Err(ENOENT);
+        }
+        if !exists || oflags & OFLAGS_TRUNC != 0 {
+            state.fs.insert(&path, Vec::new());
+        }
+        Descriptor::File {
+            path,
+            position: 0,
+            append: fdflags & FDFLAGS_APPEND != 0,
+        }
+    };
+    let new_fd = state.next_fd;
+    state.next_fd += 1;
+    state.fds.insert(new_fd, descriptor);
+    guest.write_u32(opened, new_fd)
+}
+
+pub(crate) fn path_unlink_file(
+    state: &mut WasiEnv,
+    guest: &mut Guest,
+    fd: i32,
+    path: i32,
+    path_len: i32,
+) -> Result<(), i32> {
+    let path = state.resolve(fd, &guest.read(path, path_len)?)?;
+    state.fs.remove(&path).map(|_| ()).ok_or(ENOENT)
+}
+
+pub(crate) fn sched_yield(_state: &mut WasiEnv, _guest: &mut Guest) -> Result<(), i32> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call_context::CallContext;
+    use crate::host::WasmTappletHost;
+    use crate::log_sink::MemoryLogSink;
+    use crate::model::TappletManifest;
+    use serde_json::json;
+
+    const WASI_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "random_get" (func $random_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "sock_accept" (func $sock_accept (param i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 100) "hello from wasi\n")
+        (data (i32.const 200) "config.txt")
+        (data (i32.const 220) "out.txt")
+        (global $initialized (mut i32) (i32.const 0))
+        (func (export "_initialize") (global.set $initialized (i32.const 1)))
+        (func (export "initialized") (result i32) (global.get $initialized))
+        (func (export "greet") (result i32)
+            (i32.store (i32.const 0) (i32.const 100))
+            (i32.store (i32.const 4) (i32.const 16))
+            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+        (func (export "now") (result i64)
+            (drop (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 16)))
+            (i64.load (i32.const 16)))
+        (func (export "random") (result i64)
+            (drop (call $random_get (i32.const 16) (i32.const 8)))
+            (i64.load (i32.const 16)))
+        (func (export "read_config") (result i32)
+            (drop (call $path_open (i32.const 3) (i32.const 0) (i32.const 200) (i32.const 10)
+                (i32.const 0) (i64.const 0) (i64.const 0) (i32.const 0) (i32.const 24)))
+            (i32.store (i32.const 0) (i32.const 300))
+            (i32.store (i32.const 4) (i32.const 10))
+            (drop (call $fd_read (i32.load (i32.const 24)) (i32.const 0) (i32.const 1) (i32.const 8)))
+            (i32.load8_u (i32.const 300)))
+        (func (export "write_output") (result i32)
+            (drop (call $path_open (i32.const 3) (i32.const 0) (i32.const 220) (i32.const 7)
+                (i32.const 1) (i64.const 0) (i64.const 0) (i32.const 0) (i32.const 24)))
+            (i32.store (i32.const 0) (i32.const 100))
+            (i32.store (i32.const 4) (i32.const 5))
+            (call $fd_write (i32.load (i32.const 24)) (i32.const 0) (i32.const 1) (i32.const 8)))
+        (func (export "escape") (result i32)
+            (call $path_open (i32.const 3) (i32.const 0) (i32.const 228) (i32.const 9)
+                (i32.const 1) (i64.const 0) (i64.const 0) (i32.const 0) (i32.const 24)))
+        (data (i32.const 228) "../x.txt")
+        (func (export "accept") (result i32)
+            (call $sock_accept (i32.const 3) (i32.const 0) (i32.const 0)))
+        (func (export "overlong") (result i32)
+            (i32.store (i32.const 0) (i32.const 100))
+            (i32.store (i32.const 4) (i32.const -1))
+            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+        (func (export "past_memory") (result i32)
+            (i32.store (i32.const 0) (i32.const 100))
+            (i32.store (i32.const 4) (i32.const 65536))
+            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    )"#;
+
+    fn host(wasi: WasiOptions) -> WasmTappletHost {
+        let toml = crate::test_utils::manifest_toml("wasi", "0.1.0").replace(
+            r#"methods = ["greet"]"#,
+            r#"methods = ["initialized", "greet", "now", "random", "read_config", "write_output", "escape", "accept", "overlong", "past_memory"]"#,
+        );
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        WasmTappletHost::from_bytes_with_wasi(config, WASI_WAT.as_bytes(), wasi).unwrap()
+    }
+
+    fn call(host: &mut WasmTappletHost, method: &str) -> serde_json::Value {
+        host.run(method, json!([]), &CallContext::user()).unwrap()
+    }
+
+    #[test]
+    fn test_wasi_virtual_environment() {
+        let sink = Arc::new(MemoryLogSink::new());
+        let wasi = WasiOptions::new()
+            .with_file("/config.txt", b"{\"fee\": 5}".to_vec())
+            .with_clock(WasiClock::Ticking {
+                start: 1_000,
+                step: 10,
+            })
+            .with_random_seed(7)
+            .with_log_sink(sink.clone());
+        let fs = wasi.filesystem().clone();
+        let mut host = host(wasi.clone());
+
+        assert_eq!(call(&mut host, "initialized"), 1);
+        assert_eq!(call(&mut host, "greet"), SUCCESS);
+        let records = sink.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].level, LogLevel::Info);
+        assert_eq!(records[0].message, "hello from wasi");
+
+        assert_eq!(call(&mut host, "now"), 1_000);
+        assert_eq!(call(&mut host, "now"), 1_010);
+        let random = call(&mut host, "random");
+        assert_ne!(call(&mut host, "random"), random);
+        assert_eq!(call(&mut self::host(wasi), "random"), random);
+
+        assert_eq!(call(&mut host, "read_config"), b'{');
+        assert_eq!(call(&mut host, "write_output"), SUCCESS);
+        assert_eq!(fs.read("out.txt").unwrap(), b"hello");
+        assert_eq!(call(&mut host, "escape"), ENOTCAPABLE);
+        assert_eq!(call(&mut host, "accept"), ENOSYS);
+        // Buffers are checked before anything is allocated for them
+        assert_eq!(call(&mut host, "overlong"), EINVAL);
+        assert_eq!(call(&mut host, "past_memory"), EFAULT);
+    }
+
+    #[test]
+    fn test_wasi_rate_limit() {
+        use crate::rate_limit::RateLimit;
+
+        let mut host = host(WasiOptions::new()).with_rate_limit(RateLimit::new(0.001, 2));
+        assert_eq!(call(&mut host, "greet"), SUCCESS);
+        assert_eq!(call(&mut host, "greet"), SUCCESS);
+        assert_eq!(call(&mut host, "greet"), EAGAIN);
+    }
+
+    #[test]
+    fn test_wasi_module_needs_wasi_options() {
+        let toml = crate::test_utils::manifest_toml("wasi", "0.1.0");
+        let config = TappletManifest::from_toml_str(&toml).unwrap();
+        let err = WasmTappletHost::from_bytes(config, WASI_WAT.as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), "WASM_INSTANTIATION_ERROR");
+    }
+}
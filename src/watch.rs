@@ -0,0 +1,118 @@
+//! Reinstalling tapplets from their source directory while they are developed.
+//!
+//! [`crate::local_folder_tapplet::LocalFolderTapplet::watch`] and
+//! [`crate::local_folder_lua_tapplet::LocalFolderLuaTapplet::watch`] return a
+//! [`TappletWatcher`] that reinstalls the tapplet whenever a file in its source
+//! directory changes. Hosts can then swap in the new code with `reload`:
+//!
+//! ```rust,ignore
+//! let mut watcher = tapplet.watch(cache_directory);
+//! while let Some(report) = watcher.next().await {
+//!     host.reload(report?.installed_path.join("my_tapplet.lua"))?;
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use walkdir::WalkDir;
+
+use crate::install::InstallReport;
+
+/// How often the source directory is checked for changes
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Reinstalls a tapplet when its sources change, until it is dropped
+pub struct TappletWatcher {
+    installs: mpsc::UnboundedReceiver<Result<InstallReport>>,
+    task: JoinHandle<()>,
+}
+
+impl TappletWatcher {
+    /// Install the tapplet with `install` now and every time a file below
+    /// `source_dir` changes. Must be called from within a tokio runtime.
+    pub(crate) fn spawn<F>(source_dir: PathBuf, poll_interval: Duration, install: F) -> Self
+    where
+        F: Fn() -> Result<InstallReport> + Send + Sync + 'static,
+    {
+        let (sender, installs) = mpsc::unbounded_channel();
+        let install = std::sync::Arc::new(install);
+        let task = tokio::spawn(async move {
+            let mut snapshot = None;
+            loop {
+                let dir = source_dir.clone();
+                let current = tokio::task::spawn_blocking(move || snapshot_dir(&dir)).await;
+                let current = match current {
+                    Ok(Ok(current)) => Some(current),
+                    Ok(Err(e)) => {
+                        if sender.send(Err(e)).is_err() {
+                            return;
+                        }
+                        None
+                    }
+                    Err(e) => {
+                        let _ = sender.send(Err(e).context("Failed to spawn blocking task"));
+                        return;
+                    }
+                };
+                if current.is_some() && current != snapshot {
+                    snapshot = current;
+                    let install = install.clone();
+                    let report = tokio::task::spawn_blocking(move || install())
+                        .await
+                        .context("Failed to spawn blocking task")
+                        .and_then(|report| report);
+                    if sender.send(report).is_err() {
+                        return;
+                    }
+                }
+                tokio::time::sleep(poll_interval).await;
+            }
+        });
+        Self { installs, task }
+    }
+
+    /// Wait for the next install: the first one right after the watcher starts, then
+    /// one for each change. An error means that install failed, e.g. because the
+    /// code doesn't compile; the watcher keeps going and retries on the next change.
+    pub async fn next(&mut self) -> Option<Result<InstallReport>> {
+        self.installs.recv().await
+    }
+}
+
+impl Drop for TappletWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Size and modification time of every file below `dir`, skipping hidden files
+/// and the `target` directory cargo builds into
+fn snapshot_dir(dir: &Path) -> Result<BTreeMap<PathBuf, (u64, SystemTime)>> {
+    let walker = WalkDir::new(dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            !(name.starts_with('.') || entry.depth() == 1 && name == "target")
+        });
+    let mut snapshot = BTreeMap::new();
+    for entry in walker {
+        let entry =
+            entry.with_context(|| format!("Failed to read source directory: {}", dir.display()))?;
+        if entry.file_type().is_file() {
+            let metadata = entry
+                .metadata()
+                .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+            snapshot.insert(
+                entry.path().to_path_buf(),
+                (metadata.len(), metadata.modified()?),
+            );
+        }
+    }
+    Ok(snapshot)
+}
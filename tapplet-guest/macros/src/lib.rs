@@ -0,0 +1,114 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{FnArg, Ident, ItemFn, Pat, ReturnType, Type, parse_macro_input};
+
+/// Export a function as a tapplet method.
+///
+/// The method's JSON arguments are deserialized by parameter name, matching the
+/// `params` declared for the method in the manifest. The return value is
+/// serialized to JSON. A function returning a `Result` reports its `Err` (any
+/// `Display` type) to the host as a failed call.
+///
+/// ```ignore
+/// use tari_tapplet_guest::tapplet_method;
+///
+/// #[tapplet_method]
+/// fn greet(name: String) -> String {
+///     format!("Hello, {}", name)
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn tapplet_method(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(
+            Span::call_site(),
+            "#[tapplet_method] does not take arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let function = parse_macro_input!(item as ItemFn);
+    match expand(&function) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(function: &ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let signature = &function.sig;
+    if let Some(token) = &signature.asyncness {
+        return Err(syn::Error::new_spanned(
+            token,
+            "tapplet methods can't be async",
+        ));
+    }
+    if !signature.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &signature.generics,
+            "tapplet methods can't be generic",
+        ));
+    }
+
+    let mut names = Vec::new();
+    let mut types = Vec::new();
+    for input in &signature.inputs {
+        let FnArg::Typed(arg) = input else {
+            return Err(syn::Error::new_spanned(
+                input,
+                "tapplet methods can't take self",
+            ));
+        };
+        let Pat::Ident(pat) = &*arg.pat else {
+            return Err(syn::Error::new_spanned(
+                &arg.pat,
+                "tapplet method parameters must be plain names",
+            ));
+        };
+        names.push(pat.ident.clone());
+        types.push((*arg.ty).clone());
+    }
+
+    let name = &signature.ident;
+    let export_name = name.to_string();
+    let export = format_ident!("__tapplet_export_{}", name);
+    let args = Ident::new("__TappletArgs", Span::call_site());
+    let call = if returns_result(&signature.output) {
+        quote! { #name(#(_args.#names),*).map_err(|e| e.to_string()) }
+    } else {
+        quote! { Ok::<_, String>(#name(#(_args.#names),*)) }
+    };
+
+    Ok(quote! {
+        #function
+
+        #[doc(hidden)]
+        #[unsafe(export_name = #export_name)]
+        pub unsafe extern "C" fn #export(ptr: *mut u8, len: usize) -> *mut u8 {
+            #[derive(::tari_tapplet_guest::__private::serde::Deserialize)]
+            #[serde(crate = "::tari_tapplet_guest::__private::serde")]
+            struct #args {
+                #(#names: #types,)*
+            }
+
+            // SAFETY: the host passes a buffer it allocated with `tapplet_alloc`
+            unsafe {
+                ::tari_tapplet_guest::__private::call(ptr, len, |_args: #args| #call)
+            }
+        }
+    })
+}
+
+/// Whether a return type is spelled `Result<..>`, e.g. `anyhow::Result<T>` or `Result<T, String>`
+fn returns_result(output: &ReturnType) -> bool {
+    let ReturnType::Type(_, ty) = output else {
+        return false;
+    };
+    let Type::Path(path) = &**ty else {
+        return false;
+    };
+    path.path
+        .segments
+        .last()
+        .is_some_and(|segment| segment.ident == "Result")
+}
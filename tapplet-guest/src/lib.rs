@@ -0,0 +1,867 @@
+//! Write WASM tapplets in Rust.
+//!
+//! Annotate the functions listed in the manifest's `api.methods` with
+//! [`tapplet_method`] and build the crate as a `cdylib` for `wasm32-unknown-unknown`:
+//!
+//! ```ignore
+//! use tari_tapplet_guest::tapplet_method;
+//!
+//! #[tapplet_method]
+//! fn greet(name: String) -> String {
+//!     format!("Hello, {}", name)
+//! }
+//! ```
+//!
+//! # Calling convention
+//!
+//! This is the ABI `WasmTappletHost` in `tari-tapplet-lib` uses for modules that
+//! export [`ALLOC_EXPORT`]:
+//!
+//! 1. The host serializes the call's arguments to JSON, allocates a buffer of that
+//!    length with `tapplet_alloc(len) -> ptr` and copies the JSON into it.
+//! 2. The host calls the method export `method(ptr, len) -> result_ptr`. The guest
+//!    takes ownership of the argument buffer.
+//! 3. `result_ptr` points to a little-endian `u32` length followed by that many
+//!    bytes of JSON, either `{"ok": <result>}` or `{"err": "<message>"}`.
+//! 4. The host copies the result out and frees it with
+//!    `tapplet_dealloc(result_ptr, 4 + length)`.
+//!
+//! A method may instead hand its result over in chunks by calling [`stream`] and
+//! returning `()`. The host then calls `tapplet_next_chunk() -> result_ptr` until
+//! it returns `{"done": true}`; the other chunks are returned like results and
+//! freed the same way.
+//!
+//! Before the first call, the host calls `tapplet_init() -> result_ptr` if the
+//! module exports it, see [`tapplet_init!`]. An `err` result is returned by the
+//! host as an execution error.
+//!
+//! When a method panics, the call traps. The host then calls
+//! `tapplet_last_panic() -> result_ptr`, which returns the panic message and
+//! location as `{"ok": "<message>"}`, or `{"ok": null}` if the trap wasn't a
+//! panic, so the error the host reports says what went wrong.
+//!
+//! Params declared as `bytes` arrive as base64 strings; take them as [`Bytes`],
+//! and return [`Bytes`] for results declared as `bytes`.
+//!
+//! # Host functions
+//!
+//! The guest calls back into the host through functions it imports from
+//! [`HOST_MODULE`]:
+//!
+//! - [`log`] imports `minotari_log(level, ptr, len)`, which logs the UTF-8
+//!   message at `ptr` with the tapplet's name attached
+//! - [`now`] imports `minotari_now() -> i64`, milliseconds since the Unix epoch
+//! - [`random_bytes`] imports `minotari_random_bytes(ptr, len)`, which fills the
+//!   buffer at `ptr`
+//! - [`http_get`] and [`http_post`] import `minotari_http_get` and
+//!   `minotari_http_post`, which return the length of a JSON result the guest
+//!   then copies out with `minotari_take_result(ptr, len)`
+//! - [`get_tip_height`], [`get_block_header`] and [`get_kernel`] import the
+//!   chain queries `minotari_get_tip_height`, `minotari_get_block_header` and
+//!   `minotari_get_kernel`, whose results are copied out the same way
+//! - [`get_balance`], [`send_transaction`] and [`get_transactions`] import the
+//!   wallet functions `minotari_get_balance`, `minotari_send_transaction` and
+//!   `minotari_get_transactions`, also copied out the same way
+//!
+//! Time and random bytes are real or deterministic depending on how the host is
+//! configured, so a tapplet never needs the OS for them. HTTP requests only
+//! reach the hosts listed in the manifest's `[permissions.network]`, and only if
+//! the host was given network access. Chain queries need
+//! `[permissions.chain] read = true` and a host with a chain API. Wallet
+//! functions need a host with a wallet API and the `wallet:*` permission of each
+//! function in the context of the call.
+
+// Lets the code generated by `#[tapplet_method]` refer to this crate by name in its own tests
+extern crate self as tari_tapplet_guest;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Once;
+
+use base64::Engine;
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+pub use tari_tapplet_guest_macros::tapplet_method;
+
+/// Export the host uses to allocate argument buffers in guest memory
+pub const ALLOC_EXPORT: &str = "tapplet_alloc";
+/// Export the host uses to free result buffers
+pub const DEALLOC_EXPORT: &str = "tapplet_dealloc";
+/// Size of the length prefix of a result buffer
+pub const LENGTH_PREFIX_SIZE: usize = 4;
+/// Export the host pulls the chunks of a streamed result from
+pub const NEXT_CHUNK_EXPORT: &str = "tapplet_next_chunk";
+/// Export the host reads the message of a panic from after a call trapped
+pub const LAST_PANIC_EXPORT: &str = "tapplet_last_panic";
+/// Export the host runs once after instantiating the module
+pub const INIT_EXPORT: &str = "tapplet_init";
+/// Module the host functions are imported from
+pub const HOST_MODULE: &str = "minotari";
+
+type Chunks = Box<dyn Iterator<Item = Result<serde_json::Value, String>>>;
+
+thread_local! {
+    /// Chunks of the last method that called [`stream`] and weren't pulled yet
+    static STREAM: RefCell<Option<Chunks>> = const { RefCell::new(None) };
+    /// Message of the last panic, until the host reads it
+    static LAST_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Record panic messages for [`tapplet_last_panic`], keeping the hook that was
+/// there before, which prints them where there is somewhere to print to
+fn record_panics() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            LAST_PANIC.set(Some(info.to_string()));
+            previous(info);
+        }));
+    });
+}
+
+/// Return the message of the last panic, if the host hasn't read it yet
+#[unsafe(no_mangle)]
+pub extern "C" fn tapplet_last_panic() -> *mut u8 {
+    let message = LAST_PANIC.take();
+    __private::result_buffer(&serde_json::json!({ "ok": message }))
+}
+
+/// Export a function as [`INIT_EXPORT`], which the host runs once before the
+/// first method call, e.g. to build lookup tables ahead of the first user
+/// interaction. The function takes no arguments and returns `()` or
+/// `Result<(), String>`.
+///
+/// ```ignore
+/// fn setup() -> Result<(), String> {
+///     WORDS.with(|words| words.set(load_word_list()?));
+///     Ok(())
+/// }
+///
+/// tari_tapplet_guest::tapplet_init!(setup);
+/// ```
+#[macro_export]
+macro_rules! tapplet_init {
+    ($init:path) => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn tapplet_init() -> *mut u8 {
+            $crate::__private::init(|| $crate::__private::InitResult::into_result($init()))
+        }
+    };
+}
+
+/// Severity of a [`log`] message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug = 0,
+    Info = 1,
+    Warn = 2,
+    Error = 3,
+}
+
+/// Log `message` through the host, which drops it if it is below the level the
+/// tapplet's host logs at. Outside of WASM, e.g. in the tapplet's own tests,
+/// messages go to stderr.
+///
+/// ```ignore
+/// tari_tapplet_guest::log(LogLevel::Warn, "fee estimate unavailable, using the default");
+/// ```
+pub fn log(level: LogLevel, message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        #[link(wasm_import_module = "minotari")]
+        unsafe extern "C" {
+            fn minotari_log(level: i32, ptr: *const u8, len: usize);
+        }
+        // SAFETY: the host only reads `len` bytes from `ptr`
+        unsafe { minotari_log(level as i32, message.as_ptr(), message.len()) };
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("[{:?}] {}", level, message);
+}
+
+/// Milliseconds since the Unix epoch, from the host's clock
+pub fn now() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        #[link(wasm_import_module = "minotari")]
+        unsafe extern "C" {
+            fn minotari_now() -> i64;
+        }
+        // SAFETY: the import takes no arguments
+        unsafe { minotari_now() as u64 }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// `len` random bytes from the host. Hosts refuse more than 64 KiB at once.
+/// Outside of WASM they come from std's hash seeds, which are fine for tests but
+/// not for keys.
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    #[cfg(target_arch = "wasm32")]
+    {
+        #[link(wasm_import_module = "minotari")]
+        unsafe extern "C" {
+            fn minotari_random_bytes(ptr: *mut u8, len: usize);
+        }
+        // SAFETY: the host writes exactly `len` bytes to `ptr`
+        unsafe { minotari_random_bytes(bytes.as_mut_ptr(), len) };
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use std::hash::BuildHasher;
+        let seed = std::collections::hash_map::RandomState::new();
+        for (i, chunk) in bytes.chunks_mut(8).enumerate() {
+            chunk.copy_from_slice(&seed.hash_one(i).to_le_bytes()[..chunk.len()]);
+        }
+    }
+    bytes
+}
+
+/// A response to [`http_get`] or [`http_post`], whatever its status
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// GET `url`, which must be an `https` URL of a host the manifest allows.
+/// Outside of WASM there is no host to send it, so it fails.
+///
+/// ```ignore
+/// let price = tari_tapplet_guest::http_get("https://api.example.com/price")?;
+/// ```
+pub fn http_get(url: &str) -> Result<HttpResponse, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        #[link(wasm_import_module = "minotari")]
+        unsafe extern "C" {
+            fn minotari_http_get(url_ptr: *const u8, url_len: usize) -> usize;
+        }
+        // SAFETY: the host only reads `url_len` bytes from `url_ptr`
+        let len = unsafe { minotari_http_get(url.as_ptr(), url.len()) };
+        take_host_result(len)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    Err(format!("cannot GET {} outside of a host", url))
+}
+
+/// POST `body` to `url` with `content_type`, `application/json` if empty. Like
+/// [`http_get`], only allowed hosts can be reached.
+pub fn http_post(url: &str, body: &str, content_type: &str) -> Result<HttpResponse, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        #[link(wasm_import_module = "minotari")]
+        unsafe extern "C" {
+            fn minotari_http_post(
+                url_ptr: *const u8,
+                url_len: usize,
+                body_ptr: *const u8,
+                body_len: usize,
+                type_ptr: *const u8,
+                type_len: usize,
+            ) -> usize;
+        }
+        // SAFETY: the host only reads the given number of bytes from each pointer
+        let len = unsafe {
+            minotari_http_post(
+                url.as_ptr(),
+                url.len(),
+                body.as_ptr(),
+                body.len(),
+                content_type.as_ptr(),
+                content_type.len(),
+            )
+        };
+        take_host_result(len)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = (body, content_type);
+        Err(format!("cannot POST to {} outside of a host", url))
+    }
+}
+
+/// Header of a block on the main chain, from [`get_block_header`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub height: u64,
+    pub hash: String,
+    pub prev_hash: String,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub output_mr: String,
+    pub kernel_mr: String,
+}
+
+/// A mined transaction kernel, from [`get_kernel`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KernelInfo {
+    pub excess: String,
+    /// Fee in microMinotari
+    pub fee: u64,
+    pub lock_height: u64,
+    pub block_height: u64,
+}
+
+/// Height of the tip of the chain. Outside of WASM there is no chain to ask,
+/// so this and the other chain queries fail.
+pub fn get_tip_height() -> Result<u64, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        #[link(wasm_import_module = "minotari")]
+        unsafe extern "C" {
+            fn minotari_get_tip_height() -> usize;
+        }
+        // SAFETY: the import takes no arguments
+        let len = unsafe { minotari_get_tip_height() };
+        take_host_result(len)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    Err("cannot query the chain outside of a host".to_string())
+}
+
+/// The header at `height`, `None` if the chain isn't that long
+pub fn get_block_header(height: u64) -> Result<Option<BlockHeader>, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        #[link(wasm_import_module = "minotari")]
+        unsafe extern "C" {
+            fn minotari_get_block_header(height: i64) -> usize;
+        }
+        // SAFETY: the import only takes a number
+        let len = unsafe { minotari_get_block_header(height as i64) };
+        take_host_result(len)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    Err(format!("cannot get block {} outside of a host", height))
+}
+
+/// The kernel with the hex `excess`, `None` if it hasn't been mined
+pub fn get_kernel(excess: &str) -> Result<Option<KernelInfo>, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        #[link(wasm_import_module = "minotari")]
+        unsafe extern "C" {
+            fn minotari_get_kernel(excess_ptr: *const u8, excess_len: usize) -> usize;
+        }
+        // SAFETY: the host only reads `excess_len` bytes from `excess_ptr`
+        let len = unsafe { minotari_get_kernel(excess.as_ptr(), excess.len()) };
+        take_host_result(len)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    Err(format!("cannot get kernel {} outside of a host", excess))
+}
+
+/// Wallet balance in microMinotari, from [`get_balance`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    pub available: u64,
+    pub pending_incoming: u64,
+    pub pending_outgoing: u64,
+    pub timelocked: u64,
+}
+
+/// A wallet transaction, from [`get_transactions`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionInfo {
+    pub id: String,
+    /// `inbound` or `outbound`
+    pub direction: String,
+    /// `pending`, `completed` or `cancelled`
+    pub status: String,
+    /// Amount in microMinotari
+    pub amount: u64,
+    /// Fee in microMinotari
+    pub fee: u64,
+    pub counterparty: String,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+}
+
+/// Which transactions [`get_transactions`] returns, all of them by default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionFilter {
+    /// `inbound` or `outbound`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<String>,
+    /// `pending`, `completed` or `cancelled`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// Return at most this many transactions, newest first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+/// Balance of the wallet. Outside of WASM there is no wallet to ask, so this
+/// and the other wallet functions fail.
+pub fn get_balance() -> Result<Balance, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        #[link(wasm_import_module = "minotari")]
+        unsafe extern "C" {
+            fn minotari_get_balance() -> usize;
+        }
+        // SAFETY: the import takes no arguments
+        let len = unsafe { minotari_get_balance() };
+        take_host_result(len)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    Err("cannot get the balance outside of a host".to_string())
+}
+
+/// Send `amount` microMinotari to `destination`, returning the transaction id
+pub fn send_transaction(destination: &str, amount: u64, fee: u64) -> Result<String, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        #[link(wasm_import_module = "minotari")]
+        unsafe extern "C" {
+            fn minotari_send_transaction(
+                destination_ptr: *const u8,
+                destination_len: usize,
+                amount: i64,
+                fee: i64,
+            ) -> usize;
+        }
+        // SAFETY: the host only reads `destination_len` bytes from `destination_ptr`
+        let len = unsafe {
+            minotari_send_transaction(
+                destination.as_ptr(),
+                destination.len(),
+                amount as i64,
+                fee as i64,
+            )
+        };
+        take_host_result(len)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = (amount, fee);
+        Err(format!("cannot send to {} outside of a host", destination))
+    }
+}
+
+/// The wallet's transactions that `filter` matches
+pub fn get_transactions(filter: &TransactionFilter) -> Result<Vec<TransactionInfo>, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        #[link(wasm_import_module = "minotari")]
+        unsafe extern "C" {
+            fn minotari_get_transactions(filter_ptr: *const u8, filter_len: usize) -> usize;
+        }
+        let filter = serde_json::to_string(filter).map_err(|e| e.to_string())?;
+        // SAFETY: the host only reads `filter_len` bytes from `filter_ptr`
+        let len = unsafe { minotari_get_transactions(filter.as_ptr(), filter.len()) };
+        take_host_result(len)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = filter;
+        Err("cannot get transactions outside of a host".to_string())
+    }
+}
+
+/// Result of a host function, as the host hands it over
+#[cfg(target_arch = "wasm32")]
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum HostResult<T> {
+    Ok(T),
+    Err { message: String },
+}
+
+/// Copy out the `len` bytes of JSON result the last host function left behind
+#[cfg(target_arch = "wasm32")]
+fn take_host_result<T: de::DeserializeOwned>(len: usize) -> Result<T, String> {
+    #[link(wasm_import_module = "minotari")]
+    unsafe extern "C" {
+        fn minotari_take_result(ptr: *mut u8, len: usize);
+    }
+    let mut result = vec![0; len];
+    // SAFETY: the host writes at most `len` bytes to the buffer
+    unsafe { minotari_take_result(result.as_mut_ptr(), len) };
+    match serde_json::from_slice(&result) {
+        Ok(HostResult::Ok(value)) => Ok(value),
+        Ok(HostResult::Err { message }) => Err(message),
+        Err(e) => Err(format!("invalid result from the host: {}", e)),
+    }
+}
+
+/// Hand the result of the running method over one chunk at a time, e.g. the
+/// entries of a large list, rather than as one JSON value. The method should
+/// return `()`; the host pulls the chunks once it has returned.
+///
+/// ```ignore
+/// #[tapplet_method]
+/// fn entries(prefix: String) {
+///     tari_tapplet_guest::stream(load_entries().filter(move |entry| entry.starts_with(&prefix)));
+/// }
+/// ```
+pub fn stream<I>(chunks: I)
+where
+    I: IntoIterator,
+    I::IntoIter: 'static,
+    I::Item: Serialize,
+{
+    let chunks = chunks.into_iter().map(|chunk| {
+        serde_json::to_value(chunk).map_err(|e| format!("failed to serialize chunk: {}", e))
+    });
+    STREAM.with_borrow_mut(|stream| *stream = Some(Box::new(chunks)));
+}
+
+/// Return the next chunk of the streamed result, `{"done": true}` once there are
+/// no more
+#[unsafe(no_mangle)]
+pub extern "C" fn tapplet_next_chunk() -> *mut u8 {
+    let chunk = STREAM.with_borrow_mut(|stream| {
+        let chunk = stream.as_mut().and_then(Iterator::next);
+        if chunk.is_none() {
+            *stream = None;
+        }
+        chunk
+    });
+    let response = match chunk {
+        Some(Ok(value)) => serde_json::json!({ "ok": value }),
+        Some(Err(message)) => serde_json::json!({ "err": message }),
+        None => serde_json::json!({ "done": true }),
+    };
+    __private::result_buffer(&response)
+}
+
+/// Allocate `len` bytes for the host to write into
+#[unsafe(no_mangle)]
+pub extern "C" fn tapplet_alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()).cast()
+}
+
+/// Free a buffer returned by [`tapplet_alloc`] or by a tapplet method
+///
+/// # Safety
+/// `ptr` and `len` must describe a buffer allocated by this module that has not
+/// been freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tapplet_dealloc(ptr: *mut u8, len: usize) {
+    drop(unsafe { take_buffer(ptr, len) });
+}
+
+unsafe fn take_buffer(ptr: *mut u8, len: usize) -> Box<[u8]> {
+    unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) }
+}
+
+const BASE64_CONFIG: GeneralPurposeConfig =
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+const BASE64: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, BASE64_CONFIG);
+const BASE64_URL_SAFE: GeneralPurpose = GeneralPurpose::new(&alphabet::URL_SAFE, BASE64_CONFIG);
+
+/// A `bytes` param or result: a base64 string in JSON, bytes in the guest.
+///
+/// Also accepts an array of byte values, for callers that don't go through a host.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(bytes: &[u8]) -> Self {
+        Self(bytes.to_vec())
+    }
+}
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Bytes;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a base64 string or an array of byte values")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Bytes, E> {
+                let engine = if s.contains(['-', '_']) {
+                    &BASE64_URL_SAFE
+                } else {
+                    &BASE64
+                };
+                engine.decode(s).map(Bytes).map_err(E::custom)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Bytes, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element::<u8>()? {
+                    bytes.push(byte);
+                }
+                Ok(Bytes(bytes))
+            }
+        }
+
+        deserializer.deserialize_any(BytesVisitor)
+    }
+}
+
+#[doc(hidden)]
+pub mod __private {
+    use serde::Serialize;
+    use serde::de::DeserializeOwned;
+    use serde_json::{Value, json};
+
+    pub use serde;
+
+    use super::{LENGTH_PREFIX_SIZE, take_buffer};
+
+    /// Run a tapplet method on the JSON arguments in `ptr`, returning the result buffer
+    ///
+    /// # Safety
+    /// `ptr` and `len` must describe a buffer allocated with `tapplet_alloc`.
+    pub unsafe fn call<A, R>(
+        ptr: *mut u8,
+        len: usize,
+        method: impl FnOnce(A) -> Result<R, String>,
+    ) -> *mut u8
+    where
+        A: DeserializeOwned,
+        R: Serialize,
+    {
+        let input = unsafe { take_buffer(ptr, len) };
+        super::record_panics();
+        // Chunks a previous method left unpulled aren't this method's
+        super::STREAM.with_borrow_mut(|stream| *stream = None);
+        super::LAST_PANIC.set(None);
+        let response = match parse_args(&input) {
+            Ok(args) => match method(args).map(serde_json::to_value) {
+                Ok(Ok(value)) => json!({ "ok": value }),
+                Ok(Err(e)) => json!({ "err": format!("failed to serialize result: {}", e) }),
+                Err(message) => json!({ "err": message }),
+            },
+            Err(e) => json!({ "err": format!("invalid arguments: {}", e) }),
+        };
+        drop(input);
+        result_buffer(&response)
+    }
+
+    /// Run a tapplet's init function, returning the result buffer
+    pub fn init(init: impl FnOnce() -> Result<(), String>) -> *mut u8 {
+        super::record_panics();
+        super::LAST_PANIC.set(None);
+        let response = match init() {
+            Ok(()) => json!({ "ok": null }),
+            Err(message) => json!({ "err": message }),
+        };
+        result_buffer(&response)
+    }
+
+    /// What init functions may return
+    pub trait InitResult {
+        fn into_result(self) -> Result<(), String>;
+    }
+
+    impl InitResult for () {
+        fn into_result(self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    impl InitResult for Result<(), String> {
+        fn into_result(self) -> Result<(), String> {
+            self
+        }
+    }
+
+    fn parse_args<A: DeserializeOwned>(input: &[u8]) -> serde_json::Result<A> {
+        // Methods without params may be called with `null` or no arguments at all
+        let value: Value = if input.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(input)?
+        };
+        let value = if value.is_null() { json!({}) } else { value };
+        serde_json::from_value(value)
+    }
+
+    pub(crate) fn result_buffer(response: &Value) -> *mut u8 {
+        let json = serde_json::to_vec(response).expect("JSON values always serialize");
+        let mut buffer = Vec::with_capacity(LENGTH_PREFIX_SIZE + json.len());
+        buffer.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&json);
+        Box::into_raw(buffer.into_boxed_slice()).cast()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    #[tapplet_method]
+    fn greet(name: String, times: u32) -> String {
+        vec![format!("Hello, {}", name); times as usize].join(" ")
+    }
+
+    #[tapplet_method]
+    fn divide(a: i64, b: i64) -> Result<i64, String> {
+        a.checked_div(b)
+            .ok_or_else(|| "division by zero".to_string())
+    }
+
+    #[tapplet_method]
+    fn version() -> &'static str {
+        "0.1.0"
+    }
+
+    #[tapplet_method]
+    fn reverse(data: Bytes) -> Bytes {
+        data.iter().rev().copied().collect::<Vec<_>>().into()
+    }
+
+    #[tapplet_method]
+    fn countdown(from: u32) {
+        stream((1..=from).rev());
+    }
+
+    /// Call an export the way the host does
+    fn invoke(export: unsafe extern "C" fn(*mut u8, usize) -> *mut u8, args: &str) -> Value {
+        let ptr = tapplet_alloc(args.len());
+        unsafe {
+            std::ptr::copy_nonoverlapping(args.as_ptr(), ptr, args.len());
+            take_result(export(ptr, args.len()))
+        }
+    }
+
+    unsafe fn take_result(result: *mut u8) -> Value {
+        unsafe {
+            let len = u32::from_le_bytes(*result.cast::<[u8; 4]>()) as usize;
+            let json = std::slice::from_raw_parts(result.add(LENGTH_PREFIX_SIZE), len);
+            let value = serde_json::from_slice(json).unwrap();
+            tapplet_dealloc(result, LENGTH_PREFIX_SIZE + len);
+            value
+        }
+    }
+
+    #[test]
+    fn test_exported_methods() {
+        assert_eq!(
+            invoke(__tapplet_export_greet, r#"{"name": "Alice", "times": 2}"#),
+            json!({"ok": "Hello, Alice Hello, Alice"})
+        );
+        assert_eq!(
+            invoke(__tapplet_export_divide, r#"{"a": 7, "b": 2}"#),
+            json!({"ok": 3})
+        );
+        assert_eq!(
+            invoke(__tapplet_export_divide, r#"{"a": 7, "b": 0}"#),
+            json!({"err": "division by zero"})
+        );
+        assert_eq!(
+            invoke(__tapplet_export_version, "null"),
+            json!({"ok": "0.1.0"})
+        );
+        assert_eq!(
+            invoke(__tapplet_export_reverse, r#"{"data": "AAEC"}"#),
+            json!({"ok": "AgEA"})
+        );
+        assert_eq!(
+            invoke(__tapplet_export_reverse, r#"{"data": [0, 255]}"#),
+            json!({"ok": "/wA="})
+        );
+
+        let invalid = invoke(__tapplet_export_greet, r#"{"name": 1}"#);
+        assert!(
+            invalid["err"]
+                .as_str()
+                .unwrap()
+                .starts_with("invalid arguments")
+        );
+        // The functions can still be called directly
+        assert_eq!(greet("Bob".to_string(), 1), "Hello, Bob");
+    }
+
+    #[test]
+    fn test_streamed_method() {
+        let next = || unsafe { take_result(tapplet_next_chunk()) };
+        assert_eq!(
+            invoke(__tapplet_export_countdown, r#"{"from": 2}"#),
+            json!({"ok": null})
+        );
+        assert_eq!(next(), json!({"ok": 2}));
+        assert_eq!(next(), json!({"ok": 1}));
+        assert_eq!(next(), json!({"done": true}));
+
+        // Unpulled chunks are dropped by the next call
+        invoke(__tapplet_export_countdown, r#"{"from": 5}"#);
+        invoke(__tapplet_export_version, "null");
+        assert_eq!(next(), json!({"done": true}));
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let args = b"null";
+        let ptr = tapplet_alloc(args.len());
+        let panicked = std::panic::catch_unwind(|| unsafe {
+            std::ptr::copy_nonoverlapping(args.as_ptr(), ptr, args.len());
+            __private::call(ptr, args.len(), |_: Value| -> Result<(), String> {
+                panic!("insufficient funds")
+            })
+        });
+        assert!(panicked.is_err());
+
+        let last_panic = unsafe { take_result(tapplet_last_panic()) };
+        let message = last_panic["ok"].as_str().unwrap();
+        assert!(message.starts_with("panicked at "), "{}", message);
+        assert!(message.ends_with("insufficient funds"), "{}", message);
+        // The message is only returned once
+        assert_eq!(
+            unsafe { take_result(tapplet_last_panic()) },
+            json!({"ok": null})
+        );
+    }
+
+    thread_local! {
+        static READY: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    }
+
+    fn setup() {
+        READY.set(true);
+    }
+
+    tapplet_init!(setup);
+
+    #[test]
+    fn test_init() {
+        assert_eq!(unsafe { take_result(tapplet_init()) }, json!({"ok": null}));
+        assert!(READY.get());
+
+        let failed = __private::init(|| Err("no word list".to_string()));
+        assert_eq!(
+            unsafe { take_result(failed) },
+            json!({"err": "no word list"})
+        );
+    }
+}
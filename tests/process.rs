@@ -0,0 +1,93 @@
+//! Runs tapplets through the `tapplet-runner` binary built alongside the tests
+#![cfg(feature = "host-core")]
+
+use std::sync::Arc;
+
+use serde_json::json;
+use tari_tapplet_lib::CallContext;
+use tari_tapplet_lib::log_sink::MemoryLogSink;
+use tari_tapplet_lib::process::ProcessTappletHost;
+use tari_tapplet_lib::reference_api::MemoryTappletApi;
+
+const MANIFEST: &str = r#"
+name = "notes"
+version = "0.1.0"
+friendly_name = "Notes"
+publisher = "test_publisher"
+public_key = "test_public_key"
+
+[api]
+methods = ["add", "fail"]
+
+[api.add]
+description = "Stores a note and returns every note."
+
+[api.add.params.note]
+type = "string"
+description = "The note to store."
+
+[api.add.returns]
+type = "array"
+description = "Every stored note."
+
+[api.fail]
+description = "Raises an error."
+
+[api.fail.returns]
+type = "string"
+description = "Never returned."
+
+[sigs]
+todo = "add sigs here"
+"#;
+
+const SCRIPT: &str = r#"
+function add(args)
+  print("adding", args.note)
+  minotari_append_data("notes", args.note)
+  return minotari_load_data_entries("notes")
+end
+
+function fail()
+  error("boom")
+end
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_run_tapplet_in_runner_process() {
+    let temp = tempfile::tempdir().unwrap();
+    std::fs::write(temp.path().join("manifest.toml"), MANIFEST).unwrap();
+    std::fs::write(temp.path().join("notes.lua"), SCRIPT).unwrap();
+
+    let api = Arc::new(MemoryTappletApi::new());
+    let logs = Arc::new(MemoryLogSink::new());
+    let mut host = ProcessTappletHost::new_shared(temp.path(), api.clone())
+        .unwrap()
+        .with_runner(env!("CARGO_BIN_EXE_tapplet-runner"))
+        .with_log_sink(logs.clone());
+    let context = CallContext::user();
+
+    // Host API calls are answered by this process
+    let notes = host
+        .run("add", json!({ "note": "milk" }), &context)
+        .await
+        .unwrap();
+    assert_eq!(notes, json!(["milk"]));
+    let notes = host
+        .run("add", json!({ "note": "eggs" }), &context)
+        .await
+        .unwrap();
+    assert_eq!(notes, json!(["milk", "eggs"]));
+    assert_eq!(
+        api.state().slots["test_publisher/notes/notes"],
+        ["milk", "eggs"]
+    );
+    assert_eq!(logs.records()[0].message, "adding\tmilk");
+
+    let err = host.run("fail", json!({}), &context).await.unwrap_err();
+    assert_eq!(err.code(), "LUA_EXECUTION_ERROR");
+    assert!(err.to_string().contains("boom"));
+    let err = host.run("missing", json!({}), &context).await.unwrap_err();
+    assert_eq!(err.code(), "METHOD_NOT_FOUND");
+    assert!(host.is_running());
+}